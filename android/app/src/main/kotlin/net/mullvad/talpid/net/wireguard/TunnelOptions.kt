@@ -4,4 +4,4 @@ import android.os.Parcelable
 import kotlinx.parcelize.Parcelize
 
 @Parcelize
-data class TunnelOptions(val mtu: Int?) : Parcelable
+data class TunnelOptions(val mtu: Int?, val persistentKeepalive: Int?) : Parcelable
@@ -0,0 +1,18 @@
+#!/usr/bin/env bash
+
+# Checks that the part of mullvad-rpc that does not depend on hyper or tokio builds for
+# wasm32-unknown-unknown, and runs its tests on the host.
+
+set -eux
+
+export RUSTFLAGS="--deny warnings"
+
+SCRIPT_DIR="$( cd "$( dirname "${BASH_SOURCE[0]}" )" && pwd )"
+cd "$SCRIPT_DIR/../mullvad-rpc"
+
+rustup target add wasm32-unknown-unknown
+
+time cargo check --locked --verbose --no-default-features --features client-core \
+    --target wasm32-unknown-unknown
+
+time cargo test --locked --verbose --no-default-features --features client-core
@@ -74,6 +74,13 @@ export default class AccountDataCache {
     }
   }
 
+  // Uses the expiry pushed by the daemon, which refreshes it in the background.
+  public handleAccountExpiry(accountData: IAccountData) {
+    if (this.currentAccount !== undefined) {
+      this.setValue(accountData);
+    }
+  }
+
   private setValue(accountData: IAccountData) {
     this.validUntil = this.getValidUntil(accountData);
     this.updateHandler(accountData);
@@ -48,6 +48,7 @@ import {
   VoucherResponse,
   TunnelProtocol,
   IDnsOptions,
+  SettingsMigrationEvent,
 } from '../shared/daemon-rpc-types';
 import log from '../shared/logging';
 
@@ -393,7 +394,11 @@ export class DaemonRpc {
   }
 
   public subscribeDaemonEventListener(listener: SubscriptionListener<DaemonEvent>) {
-    const call = this.isConnected && this.client.eventsListen(new Empty());
+    // Ask for the current state first, so that no change is missed between fetching the state
+    // and subscribing.
+    const filter = new grpcTypes.EventsFilter();
+    filter.setSnapshot(true);
+    const call = this.isConnected && this.client.eventsListen(filter);
     if (!call) {
       throw noConnectionError;
     }
@@ -462,6 +467,7 @@ export class DaemonRpc {
 
     const customOptions = new grpcTypes.CustomDnsOptions();
     customOptions.setAddressesList(dns.customOptions.addresses);
+    customOptions.setRejectOutsideTunnel(dns.customOptions.rejectOutsideTunnel);
     dnsOptions.setCustomOptions(customOptions);
 
     if (dns.state === 'custom') {
@@ -1094,6 +1100,8 @@ function convertFromTunnelOptions(tunnelOptions: grpcTypes.TunnelOptions.AsObjec
       },
       customOptions: {
         addresses: tunnelOptions.dnsOptions?.customOptions?.addressesList ?? [],
+        rejectOutsideTunnel:
+          tunnelOptions.dnsOptions?.customOptions?.rejectOutsideTunnel ?? false,
       },
     },
   };
@@ -1130,6 +1138,45 @@ function convertFromDaemonEvent(data: grpcTypes.DaemonEvent): DaemonEvent {
     };
   }
 
+  const migrationEvent = data.getMigrationEvent();
+  if (migrationEvent !== undefined) {
+    return {
+      settingsMigration: convertFromMigrationEvent(migrationEvent),
+    };
+  }
+
+  const relayDeprecated = data.getRelayDeprecated();
+  if (relayDeprecated !== undefined) {
+    return {
+      relayDeprecated: relayDeprecated.toObject(),
+    };
+  }
+
+  const relayMaintenance = data.getRelayMaintenance();
+  if (relayMaintenance !== undefined) {
+    const window = relayMaintenance.getWindow();
+    return {
+      relayMaintenance: {
+        hostname: relayMaintenance.getHostname(),
+        start: window?.getStart()?.toDate().toISOString() ?? '',
+        end: window?.getEnd()?.toDate().toISOString() ?? '',
+        reconnectAt: relayMaintenance.getReconnectAt()?.toDate().toISOString(),
+      },
+    };
+  }
+
+  const accountExpiry = data.getAccountExpiry();
+  if (accountExpiry !== undefined) {
+    return {
+      accountExpiry: { expiry: accountExpiry.getExpiry()!.toDate().toISOString() },
+    };
+  }
+
+  const eventsDropped = data.getEventsDropped();
+  if (eventsDropped !== undefined) {
+    return { eventsDropped: eventsDropped.getCount() };
+  }
+
   return {
     appVersionInfo: data.getVersionInfo()!.toObject(),
   };
@@ -1155,6 +1202,17 @@ function convertFromKeygenEvent(data: grpcTypes.KeygenEvent): KeygenEvent {
   }
 }
 
+function convertFromMigrationEvent(data: grpcTypes.MigrationEvent): SettingsMigrationEvent {
+  switch (data.getEvent()) {
+    case grpcTypes.MigrationEvent.MigrationEvent.STARTED:
+      return 'started';
+    case grpcTypes.MigrationEvent.MigrationEvent.STEP:
+      return { step: data.getVersion() };
+    case grpcTypes.MigrationEvent.MigrationEvent.COMPLETED:
+      return 'completed';
+  }
+}
+
 function convertFromOpenVpnConstraints(
   constraints: grpcTypes.OpenvpnConstraints,
 ): IOpenVpnConstraints {
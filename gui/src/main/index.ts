@@ -197,6 +197,7 @@ class ApplicationMain {
         },
         customOptions: {
           addresses: [],
+          rejectOutsideTunnel: false,
         },
       },
     },
@@ -779,6 +780,13 @@ class ApplicationMain {
           this.handleWireguardKeygenEvent(daemonEvent.wireguardKey);
         } else if ('appVersionInfo' in daemonEvent) {
           this.setLatestVersion(daemonEvent.appVersionInfo);
+        } else if ('accountExpiry' in daemonEvent) {
+          this.accountDataCache.handleAccountExpiry(daemonEvent.accountExpiry);
+        } else if ('eventsDropped' in daemonEvent) {
+          // Subscribe again to get the current state instead of the events that were missed
+          log.warn(`Missed ${daemonEvent.eventsDropped} daemon events, subscribing again`);
+          this.daemonRpc.unsubscribeDaemonEventListener(daemonEventListener);
+          this.daemonEventListener = this.subscribeEvents();
         }
       },
       (error: Error) => {
@@ -108,6 +108,7 @@ export default function CustomDnsSettings() {
             ...dns,
             state: dns.state === 'custom' || inputVisible ? 'custom' : 'default',
             customOptions: {
+              ...dns.customOptions,
               addresses: [...dns.customOptions.addresses, address],
             },
           });
@@ -150,6 +151,7 @@ export default function CustomDnsSettings() {
         await setDnsOptions({
           ...dns,
           customOptions: {
+            ...dns.customOptions,
             addresses,
           },
         });
@@ -179,6 +181,7 @@ export default function CustomDnsSettings() {
         ...dns,
         state: addresses.length > 0 && dns.state === 'custom' ? 'custom' : 'default',
         customOptions: {
+          ...dns.customOptions,
           addresses,
         },
       });
@@ -195,6 +195,7 @@ const initialState: ISettingsReduxState = {
     },
     customOptions: {
       addresses: [],
+      rejectOutsideTunnel: false,
     },
   },
   splitTunneling: false,
@@ -105,7 +105,27 @@ export type DaemonEvent =
   | { settings: ISettings }
   | { relayList: IRelayList }
   | { wireguardKey: KeygenEvent }
-  | { appVersionInfo: IAppVersionInfo };
+  | { appVersionInfo: IAppVersionInfo }
+  | { settingsMigration: SettingsMigrationEvent }
+  | { relayDeprecated: IDeprecatedRelay }
+  | { relayMaintenance: IRelayMaintenance }
+  | { accountExpiry: IAccountData }
+  | { eventsDropped: number };
+
+export type SettingsMigrationEvent = 'started' | { step: number } | 'completed';
+
+export interface IDeprecatedRelay {
+  hostname: string;
+  stillConnected: boolean;
+}
+
+export interface IRelayMaintenance {
+  hostname: string;
+  start: string;
+  end: string;
+  // Unset if the daemon stays connected to the relay.
+  reconnectAt?: string;
+}
 
 export interface ITunnelStateRelayInfo {
   endpoint: ITunnelEndpoint;
@@ -284,6 +304,7 @@ export interface IDnsOptions {
   state: 'custom' | 'default';
   customOptions: {
     addresses: string[];
+    rejectOutsideTunnel: boolean;
   };
   defaultOptions: {
     blockAds: boolean;
@@ -314,4 +314,29 @@ describe('IAccountData cache', () => {
       expect(fetchSpy).to.have.been.called.twice;
     });
   });
+
+  it('should update when the daemon pushes a new expiry', async () => {
+    const newAccountData: IAccountData = {
+      expiry: new Date('2039-01-01').toISOString(),
+    };
+    const updates: Array<IAccountData | undefined> = [];
+    const cache = new AccountDataCache(
+      (_token) => Promise.resolve(dummyAccountData),
+      (data) => updates.push(data),
+    );
+
+    // The expiry is ignored until an account is set
+    cache.handleAccountExpiry(newAccountData);
+    expect(updates).to.be.empty;
+
+    await new Promise<void>((resolve, reject) => {
+      cache.fetch(dummyAccountToken, {
+        onFinish: () => resolve(),
+        onError: (_error: Error) => reject(),
+      });
+    });
+    cache.handleAccountExpiry(newAccountData);
+
+    expect(updates[updates.length - 1]).to.deep.equal(newAccountData);
+  });
 });
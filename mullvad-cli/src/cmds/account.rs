@@ -1,11 +1,55 @@
 use crate::{new_rpc_client, Command, Error, Result};
 use itertools::Itertools;
-use mullvad_management_interface::{types::Timestamp, Code};
+use mullvad_management_interface::{
+    types::{Timestamp, VoucherSubmission},
+    Code,
+};
 use mullvad_types::account::AccountToken;
-use std::io::{self, Write};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Write},
+};
+
+/// Number of characters at the start and end of an account token that are shown when masked.
+const UNMASKED_TOKEN_CHARS: usize = 4;
 
 pub struct Account;
 
+/// An account number given to the CLI. It is masked when displayed or debug printed, so that it
+/// does not end up in terminal output or error messages by accident.
+struct AccountNumber(AccountToken);
+
+impl AccountNumber {
+    /// Parses an account number, ignoring whitespace. The error does not contain the input.
+    fn parse(input: &str) -> Result<Self> {
+        let token: String = input.split_whitespace().collect();
+        if token.is_empty() || !token.chars().all(|c| c.is_ascii_digit()) {
+            return Err(Error::InvalidCommand(
+                "The account number may only contain digits",
+            ));
+        }
+        Ok(AccountNumber(token))
+    }
+
+    /// Returns the whole account number, grouped for readability.
+    fn reveal(&self) -> String {
+        Account::group_token(&self.0)
+    }
+}
+
+impl fmt::Display for AccountNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&Account::mask_token(&self.0))
+    }
+}
+
+impl fmt::Debug for AccountNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AccountNumber({})", self)
+    }
+}
+
 #[mullvad_management_interface::async_trait]
 impl Command for Account {
     fn name(&self) -> &'static str {
@@ -25,7 +69,12 @@ impl Command for Account {
             )
             .subcommand(
                 clap::App::new("get")
-                    .about("Display information about the currently configured account"),
+                    .about("Display information about the currently configured account")
+                    .arg(
+                        clap::Arg::new("show-account-number")
+                            .long("show-account-number")
+                            .help("Display the full account number instead of a masked one"),
+                    ),
             )
             .subcommand(
                 clap::App::new("unset").about("Removes the account number from the settings"),
@@ -35,17 +84,32 @@ impl Command for Account {
                     .about("Creates a new account and sets it as the active one"),
             )
             .subcommand(
-                clap::App::new("redeem").about("Redeems a voucher").arg(
-                    clap::Arg::new("voucher")
-                        .help("The Mullvad voucher code to be submitted")
-                        .required(true),
-                ),
+                clap::App::new("redeem")
+                    .about("Redeems a voucher")
+                    .arg(clap::Arg::new("voucher").help("The Mullvad voucher code to be submitted"))
+                    .arg(
+                        clap::Arg::new("from-file")
+                            .long("from-file")
+                            .takes_value(true)
+                            .value_name("PATH")
+                            .help(
+                                "Read voucher codes from a file, one per line, or from STDIN \
+                                if the path is '-'. Codes are tried in order until one is accepted",
+                            ),
+                    )
+                    .group(
+                        clap::ArgGroup::new("voucher-input")
+                            .args(&["voucher", "from-file"])
+                            .required(true),
+                    ),
             )
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
         if let Some(set_matches) = matches.subcommand_matches("set") {
-            let mut token = match set_matches.value_of("token") {
+            // The token is validated here rather than by clap, since clap includes rejected
+            // values in its error messages.
+            let token = match set_matches.value_of("token") {
                 Some(token) => token.to_string(),
                 None => {
                     let mut token = String::new();
@@ -59,17 +123,21 @@ impl Command for Account {
                     token
                 }
             };
-            token = token.split_whitespace().join("").to_string();
-            self.set(Some(token)).await
-        } else if let Some(_matches) = matches.subcommand_matches("get") {
-            self.get().await
+            self.set(Some(AccountNumber::parse(&token)?)).await
+        } else if let Some(get_matches) = matches.subcommand_matches("get") {
+            self.get(get_matches.is_present("show-account-number"))
+                .await
         } else if let Some(_matches) = matches.subcommand_matches("unset") {
             self.set(None).await
         } else if let Some(_matches) = matches.subcommand_matches("create") {
             self.create().await
         } else if let Some(matches) = matches.subcommand_matches("redeem") {
-            let voucher = matches.value_of_t_or_exit("voucher");
-            self.redeem_voucher(voucher).await
+            if let Some(path) = matches.value_of("from-file") {
+                self.redeem_vouchers_from_file(path).await
+            } else {
+                let voucher: String = matches.value_of_t_or_exit("voucher");
+                self.redeem_voucher(Self::normalize_voucher(&voucher)).await
+            }
         } else {
             unreachable!("No account command given");
         }
@@ -77,22 +145,31 @@ impl Command for Account {
 }
 
 impl Account {
-    async fn set(&self, token: Option<AccountToken>) -> Result<()> {
+    async fn set(&self, account_number: Option<AccountNumber>) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        rpc.set_account(token.clone().unwrap_or_default()).await?;
-        if let Some(token) = token {
-            println!("Mullvad account \"{}\" set", token);
+        let token = account_number
+            .as_ref()
+            .map(|number| number.0.clone())
+            .unwrap_or_default();
+        rpc.set_account(token).await?;
+        if let Some(account_number) = account_number {
+            println!("Mullvad account \"{}\" set", account_number);
         } else {
             println!("Mullvad account removed");
         }
         Ok(())
     }
 
-    async fn get(&self) -> Result<()> {
+    async fn get(&self, show_account_number: bool) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let settings = rpc.get_settings(()).await?.into_inner();
         if settings.account_token != "" {
-            println!("Mullvad account: {}", settings.account_token);
+            let account_number = AccountNumber(settings.account_token.clone());
+            if show_account_number {
+                println!("Mullvad account: {}", account_number.reveal());
+            } else {
+                println!("Mullvad account: {}", account_number);
+            }
             let expiry = rpc
                 .get_account_data(settings.account_token)
                 .await
@@ -112,24 +189,16 @@ impl Account {
         let mut rpc = new_rpc_client().await?;
         rpc.create_new_account(()).await?;
         println!("New account created!");
-        self.get().await
+        // The account number has to be shown at least once, or the user cannot log in elsewhere.
+        self.get(true).await
     }
 
-    async fn redeem_voucher(&self, mut voucher: String) -> Result<()> {
+    async fn redeem_voucher(&self, voucher: String) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        voucher.retain(|c| c.is_alphanumeric());
 
         match rpc.submit_voucher(voucher).await {
             Ok(submission) => {
-                let submission = submission.into_inner();
-                println!(
-                    "Added {} to the account",
-                    Self::format_duration(submission.seconds_added)
-                );
-                println!(
-                    "New expiry date: {}",
-                    Self::format_expiry(&submission.new_expiry.unwrap())
-                );
+                Self::print_submission(&submission.into_inner());
                 Ok(())
             }
             Err(err) => {
@@ -144,6 +213,107 @@ impl Account {
         }
     }
 
+    async fn redeem_vouchers_from_file(&self, path: &str) -> Result<()> {
+        let mut contents = String::new();
+        let read_result = if path == "-" {
+            io::stdin().read_to_string(&mut contents)
+        } else {
+            File::open(path).and_then(|mut file| file.read_to_string(&mut contents))
+        };
+        if let Err(error) = read_result {
+            eprintln!("Failed to read vouchers from {}: {}", path, error);
+            std::process::exit(1);
+        }
+
+        let vouchers = Self::parse_vouchers(&contents);
+        if vouchers.is_empty() {
+            eprintln!("No voucher codes found in {}", path);
+            std::process::exit(1);
+        }
+
+        let mut rpc = new_rpc_client().await?;
+        for (line, voucher) in vouchers {
+            match rpc.submit_voucher(voucher.clone()).await {
+                Ok(submission) => {
+                    println!("Voucher on line {} ({}) was accepted", line, voucher);
+                    Self::print_submission(&submission.into_inner());
+                    return Ok(());
+                }
+                Err(err) => match err.code() {
+                    Code::NotFound | Code::ResourceExhausted => {
+                        eprintln!("Line {} ({}): {}", line, voucher, err.message());
+                    }
+                    _ => return Err(Error::RpcFailed(err)),
+                },
+            }
+        }
+
+        eprintln!("None of the vouchers could be redeemed");
+        std::process::exit(1);
+    }
+
+    fn print_submission(submission: &VoucherSubmission) {
+        println!(
+            "Added {} to the account",
+            Self::format_duration(submission.seconds_added)
+        );
+        println!(
+            "New expiry date: {}",
+            Self::format_expiry(submission.new_expiry.as_ref().unwrap())
+        );
+    }
+
+    /// Removes whitespace, dashes and any other separators from a voucher code, and converts it to
+    /// upper case.
+    fn normalize_voucher(voucher: &str) -> String {
+        voucher
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_uppercase)
+            .collect()
+    }
+
+    /// Returns the normalized voucher codes in `contents` along with their line numbers, skipping
+    /// empty lines. Lines may be terminated by LF, CRLF or CR.
+    fn parse_vouchers(contents: &str) -> Vec<(usize, String)> {
+        contents
+            .split("\r\n")
+            .flat_map(|line| line.split(|c| c == '\r' || c == '\n'))
+            .enumerate()
+            .map(|(index, line)| (index + 1, Self::normalize_voucher(line)))
+            .filter(|(_, voucher)| !voucher.is_empty())
+            .collect()
+    }
+
+    /// Splits the token into groups of four characters.
+    fn group_token(token: &str) -> String {
+        token
+            .chars()
+            .collect::<Vec<_>>()
+            .chunks(4)
+            .map(|chunk| chunk.iter().collect::<String>())
+            .join(" ")
+    }
+
+    /// Groups the token and replaces all but the first and last few characters with asterisks.
+    fn mask_token(token: &str) -> String {
+        let length = token.chars().count();
+        let masked: String = token
+            .chars()
+            .enumerate()
+            .map(|(index, c)| {
+                if length > 2 * UNMASKED_TOKEN_CHARS
+                    && (index < UNMASKED_TOKEN_CHARS || index >= length - UNMASKED_TOKEN_CHARS)
+                {
+                    c
+                } else {
+                    '*'
+                }
+            })
+            .collect();
+        Self::group_token(&masked)
+    }
+
     fn format_duration(seconds: u64) -> String {
         let dur = chrono::Duration::seconds(seconds as i64);
         if dur.num_days() > 0 {
@@ -163,3 +333,79 @@ impl Account {
         utc.with_timezone(&chrono::Local).to_string()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Account, AccountNumber};
+    use crate::Command;
+
+    #[test]
+    fn test_mask_token() {
+        assert_eq!(
+            Account::mask_token("1234567890123456"),
+            "1234 **** **** 3456"
+        );
+        assert_eq!(Account::mask_token("12345678"), "**** ****");
+        assert_eq!(
+            Account::group_token("1234567890123456"),
+            "1234 5678 9012 3456"
+        );
+    }
+
+    #[test]
+    fn test_account_number() {
+        let number = AccountNumber::parse(" 1234 5678\t9012 3456\n").unwrap();
+        assert_eq!(number.to_string(), "1234 **** **** 3456");
+        assert_eq!(
+            format!("{:?}", number),
+            "AccountNumber(1234 **** **** 3456)"
+        );
+        assert_eq!(number.reveal(), "1234 5678 9012 3456");
+    }
+
+    #[test]
+    fn test_malformed_account_number_is_not_printed() {
+        let malformed = "1234567890I23456";
+        // clap accepts any value, so it cannot print the token in an error of its own
+        let matches = Account
+            .clap_subcommand()
+            .try_get_matches_from(["account", "set", malformed])
+            .unwrap();
+        let token = matches
+            .subcommand_matches("set")
+            .unwrap()
+            .value_of("token")
+            .unwrap();
+
+        let error = AccountNumber::parse(token).unwrap_err();
+        let output = format!("{}\n{:?}", error, error);
+        for fragment in ["123456", "7890", "I23456"] {
+            assert!(!output.contains(fragment), "{} in {:?}", fragment, output);
+        }
+    }
+
+    #[test]
+    fn test_normalize_voucher() {
+        assert_eq!(
+            Account::normalize_voucher(" abcd-1234-EFGH-5678 "),
+            "ABCD1234EFGH5678"
+        );
+        assert_eq!(Account::normalize_voucher("ab cd\t12\r"), "ABCD12");
+        assert_eq!(Account::normalize_voucher(" - "), "");
+    }
+
+    #[test]
+    fn test_parse_vouchers() {
+        let contents = "aaaa-bbbb\r\n\r\ncccc dddd\neeee\rffff\n\n";
+        assert_eq!(
+            Account::parse_vouchers(contents),
+            vec![
+                (1, "AAAABBBB".to_owned()),
+                (3, "CCCCDDDD".to_owned()),
+                (4, "EEEE".to_owned()),
+                (5, "FFFF".to_owned()),
+            ]
+        );
+        assert!(Account::parse_vouchers("\n \r\n-\n").is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+use crate::{location, new_rpc_client, Command, Result};
+
+use mullvad_management_interface::types;
+use mullvad_types::relay_constraints::{
+    ApiBridgeMode, ApiBridgeSettings, Constraint, LocationConstraint,
+};
+
+use std::{
+    convert::TryFrom,
+    fs::{self, OpenOptions},
+    io::Write,
+};
+
+const BYTES_PER_MEGABYTE: u64 = 1_000_000;
+
+pub struct Api;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Api {
+    fn name(&self) -> &'static str {
+        "api"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name())
+            .about("Control how the app connects to the API")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::App::new("get").about("Display the current API bridge settings"))
+            .subcommand(
+                clap::App::new("traffic").about(
+                    "Display how much data has been sent and received when talking to the API",
+                ),
+            )
+            .subcommand(
+                clap::App::new("set-bridge-mode")
+                    .about("Set whether API traffic is sent through bridges")
+                    .arg(
+                        clap::Arg::new("mode")
+                            .help(
+                                "'auto' alternates between direct connections and bridges when \
+                                the API cannot be reached, 'always' never connects directly, and \
+                                'never' never uses a bridge",
+                            )
+                            .required(true)
+                            .index(1)
+                            .possible_values(&["auto", "always", "never"]),
+                    ),
+            )
+            .subcommand(
+                location::get_subcommand_with_name("set-bridge-location").about(
+                    "Set the country or city of the bridges to use for API traffic. With 'any', \
+                    the bridge location set with 'mullvad bridge set location' is used.",
+                ),
+            )
+            .subcommand(
+                clap::App::new("doh-fallback")
+                    .about(
+                        "Control whether the API host may be looked up using DNS-over-HTTPS when \
+                        no known API address works. This reveals to the DoH servers that the app \
+                        is in use.",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("set").arg(
+                            clap::Arg::new("policy")
+                                .required(true)
+                                .possible_values(&["on", "off"]),
+                        ),
+                    )
+                    .subcommand(clap::App::new("get")),
+            )
+            .subcommand(
+                clap::App::new("data-limit")
+                    .about(
+                        "Limit how much data the API may download until the daemon restarts. \
+                        Requests that are needed to connect are still sent once the limit is \
+                        reached.",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("set").arg(
+                            clap::Arg::new("megabytes")
+                                .help("The limit in megabytes, or \"unlimited\" to remove it")
+                                .required(true),
+                        ),
+                    )
+                    .subcommand(clap::App::new("get")),
+            )
+            .subcommand(
+                clap::App::new("export-bootstrap")
+                    .about(
+                        "Save the API address and the bridge used to reach the API to a file, \
+                        which can be imported on a machine that cannot reach the API",
+                    )
+                    .arg(clap::Arg::new("file").required(true).index(1)),
+            )
+            .subcommand(
+                clap::App::new("import-bootstrap")
+                    .about("Use the API address and bridge from a file created by export-bootstrap")
+                    .arg(clap::Arg::new("file").required(true).index(1)),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            Some(("get", _)) => Self::handle_get().await,
+            Some(("traffic", _)) => Self::handle_traffic().await,
+            Some(("set-bridge-mode", mode_matches)) => {
+                let mode = match mode_matches.value_of("mode").unwrap() {
+                    "auto" => ApiBridgeMode::Auto,
+                    "always" => ApiBridgeMode::Always,
+                    "never" => ApiBridgeMode::Never,
+                    _ => unreachable!("unhandled mode"),
+                };
+                Self::update_api_bridge_settings(|settings| settings.mode = mode).await
+            }
+            Some(("set-bridge-location", location_matches)) => {
+                let location = Constraint::<LocationConstraint>::from(
+                    location::get_constraint_from_args(location_matches),
+                );
+                Self::update_api_bridge_settings(|settings| settings.location = location).await
+            }
+            Some(("doh-fallback", doh_matches)) => match doh_matches.subcommand() {
+                Some(("set", set_matches)) => {
+                    let policy = set_matches.value_of("policy").expect("missing policy");
+                    Self::set_doh_fallback(policy == "on").await
+                }
+                _ => Self::get_doh_fallback().await,
+            },
+            Some(("data-limit", limit_matches)) => match limit_matches.subcommand() {
+                Some(("set", set_matches)) => {
+                    let limit = match set_matches.value_of("megabytes") {
+                        Some("unlimited") => None,
+                        _ => Some(set_matches.value_of_t_or_exit::<u64>("megabytes")),
+                    };
+                    Self::set_data_limit(limit).await
+                }
+                _ => Self::get_data_limit().await,
+            },
+            Some(("export-bootstrap", export_matches)) => {
+                Self::handle_export_bootstrap(export_matches.value_of("file").unwrap()).await
+            }
+            Some(("import-bootstrap", import_matches)) => {
+                Self::handle_import_bootstrap(import_matches.value_of("file").unwrap()).await
+            }
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+impl Api {
+    async fn get_api_bridge_settings() -> Result<ApiBridgeSettings> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        Ok(settings
+            .api_bridge_settings
+            .map(|settings| ApiBridgeSettings::try_from(settings).unwrap())
+            .unwrap_or_default())
+    }
+
+    async fn handle_get() -> Result<()> {
+        let settings = Self::get_api_bridge_settings().await?;
+        println!("API bridge mode: {}", settings.mode);
+        match settings.location {
+            Constraint::Any => println!("API bridge location: same as bridge location"),
+            Constraint::Only(location) => println!("API bridge location: {}", location),
+        }
+        Ok(())
+    }
+
+    async fn set_doh_fallback(enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_api_doh_fallback(enabled).await?;
+        println!("Changed the DoH fallback setting");
+        Ok(())
+    }
+
+    async fn get_doh_fallback() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let enabled = rpc.get_settings(()).await?.into_inner().api_doh_fallback;
+        println!("DoH fallback: {}", if enabled { "on" } else { "off" });
+        Ok(())
+    }
+
+    async fn set_data_limit(megabytes: Option<u64>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let bytes = megabytes
+            .map(|megabytes| megabytes.saturating_mul(BYTES_PER_MEGABYTE).max(1))
+            .unwrap_or(0);
+        rpc.set_api_data_limit(bytes).await?;
+        println!("Changed the API data limit");
+        Ok(())
+    }
+
+    async fn get_data_limit() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let limit = rpc.get_settings(()).await?.into_inner().api_data_limit;
+        if limit != 0 {
+            println!("API data limit: {} MB", limit / BYTES_PER_MEGABYTE);
+        } else {
+            println!("API data limit: unlimited");
+        }
+        Ok(())
+    }
+
+    async fn handle_traffic() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let stats = rpc.get_api_traffic_stats(()).await?.into_inner();
+        let print_counts = |name: &str, counts: &types::ByteCounts| {
+            println!(
+                "{:<20} sent: {:>12} bytes, received: {:>12} bytes",
+                name, counts.sent, counts.received
+            );
+        };
+        print_counts("Total", &stats.total.unwrap_or_default());
+        for (category, counts) in &stats.by_category {
+            print_counts(category, counts);
+        }
+        Ok(())
+    }
+
+    async fn handle_export_bootstrap(path: &str) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let contents = rpc.export_api_bootstrap(()).await?.into_inner();
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        if let Err(error) = options
+            .open(path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+        {
+            eprintln!("Failed to write {}: {}", path, error);
+            std::process::exit(1);
+        }
+
+        println!("Saved API bootstrap state to {}", path);
+        println!(
+            "WARNING: If a bridge is used to reach the API, the file contains its password. Only \
+             share it with machines that you trust."
+        );
+        Ok(())
+    }
+
+    async fn handle_import_bootstrap(path: &str) -> Result<()> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) => {
+                eprintln!("Failed to read {}: {}", path, error);
+                std::process::exit(1);
+            }
+        };
+
+        let mut rpc = new_rpc_client().await?;
+        if let Err(status) = rpc.import_api_bootstrap(contents).await {
+            if status.code() == mullvad_management_interface::Code::InvalidArgument {
+                eprintln!("Invalid API bootstrap file: {}", status.message());
+                std::process::exit(1);
+            }
+            return Err(status.into());
+        }
+        println!("Imported API bootstrap state");
+        Ok(())
+    }
+
+    async fn update_api_bridge_settings(update: impl FnOnce(&mut ApiBridgeSettings)) -> Result<()> {
+        let mut settings = Self::get_api_bridge_settings().await?;
+        update(&mut settings);
+
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_api_bridge_settings(types::ApiBridgeSettings::from(settings))
+            .await?;
+        println!("Updated API bridge settings");
+        Ok(())
+    }
+}
@@ -11,6 +11,7 @@ impl Command for BlockWhenDisconnected {
     fn clap_subcommand(&self) -> clap::App<'static> {
         clap::App::new(self.name())
             .about("Control if the system service should block network access when disconnected from VPN")
+            .alias("lockdown-mode")
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(
                 clap::App::new("set")
@@ -25,6 +26,22 @@ impl Command for BlockWhenDisconnected {
                 clap::App::new("get")
                     .about("Display the current always require VPN setting"),
             )
+            .subcommand(
+                clap::App::new("after-boot")
+                    .about(
+                        "Control if all network access should be blocked when the system service \
+                        starts, until the VPN is connected",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("set").arg(
+                            clap::Arg::new("policy")
+                                .required(true)
+                                .possible_values(&["on", "off"]),
+                        ),
+                    )
+                    .subcommand(clap::App::new("get")),
+            )
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
@@ -33,6 +50,13 @@ impl Command for BlockWhenDisconnected {
             self.set(block_when_disconnected == "on").await
         } else if let Some(_matches) = matches.subcommand_matches("get") {
             self.get().await
+        } else if let Some(after_boot_matches) = matches.subcommand_matches("after-boot") {
+            if let Some(set_matches) = after_boot_matches.subcommand_matches("set") {
+                let policy = set_matches.value_of("policy").expect("missing policy");
+                self.set_after_boot(policy == "on").await
+            } else {
+                self.get_after_boot().await
+            }
         } else {
             unreachable!("No block-when-disconnected command given");
         }
@@ -65,4 +89,22 @@ impl BlockWhenDisconnected {
         );
         Ok(())
     }
+
+    async fn set_after_boot(&self, lockdown_after_boot: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_lockdown_after_boot(lockdown_after_boot).await?;
+        println!("Changed lockdown after boot setting");
+        Ok(())
+    }
+
+    async fn get_after_boot(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let lockdown_after_boot = rpc.get_settings(()).await?.into_inner().lockdown_after_boot;
+        if lockdown_after_boot {
+            println!("Network traffic will be blocked after boot until the VPN is connected");
+        } else {
+            println!("Network traffic will not be blocked after boot");
+        }
+        Ok(())
+    }
 }
@@ -2,7 +2,7 @@ use crate::{location, new_rpc_client, Command, Error, Result};
 
 use mullvad_management_interface::types;
 use mullvad_types::relay_constraints::{
-    BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
+    BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint, Ownership,
 };
 use talpid_types::net::openvpn::{self, SHADOWSOCKS_CIPHERS};
 
@@ -54,6 +54,19 @@ fn create_bridge_set_subcommand() -> clap::App<'static> {
                         .required(true),
                 ),
         )
+        .subcommand(
+            clap::App::new("ownership")
+                .about(
+                    "Set whether to select bridge relays owned by Mullvad or rented from a \
+                        hosting provider.",
+                )
+                .arg(
+                    clap::Arg::new("ownership")
+                        .help("Ownership of the bridge relays to use, or 'any' for no preference.")
+                        .required(true)
+                        .possible_values(&["any", "owned", "rented"]),
+                ),
+        )
         .subcommand(location::get_subcommand().about(
             "Set country or city to select bridge relays from. Use the 'list' \
              command to show available alternatives.",
@@ -181,6 +194,9 @@ impl Bridge {
             Some(("provider", provider_matches)) => {
                 Self::handle_set_bridge_provider(provider_matches).await
             }
+            Some(("ownership", ownership_matches)) => {
+                Self::handle_set_bridge_ownership(ownership_matches).await
+            }
             Some(("custom", custom_matches)) => {
                 Self::handle_bridge_set_custom_settings(custom_matches).await
             }
@@ -215,7 +231,12 @@ impl Bridge {
     }
 
     async fn handle_set_bridge_location(matches: &clap::ArgMatches) -> Result<()> {
-        Self::update_bridge_settings(Some(location::get_constraint_from_args(matches)), None).await
+        Self::update_bridge_settings(
+            Some(location::get_constraint_from_args(matches)),
+            None,
+            None,
+        )
+        .await
     }
 
     async fn handle_set_bridge_provider(matches: &clap::ArgMatches) -> Result<()> {
@@ -226,12 +247,24 @@ impl Bridge {
             providers
         };
 
-        Self::update_bridge_settings(None, Some(providers)).await
+        Self::update_bridge_settings(None, Some(providers), None).await
+    }
+
+    async fn handle_set_bridge_ownership(matches: &clap::ArgMatches) -> Result<()> {
+        let ownership = match matches.value_of("ownership").unwrap() {
+            "any" => Constraint::Any,
+            "owned" => Constraint::Only(Ownership::MullvadOwned),
+            "rented" => Constraint::Only(Ownership::Rented),
+            _ => unreachable!(),
+        };
+
+        Self::update_bridge_settings(None, None, Some(ownership)).await
     }
 
     async fn update_bridge_settings(
         location: Option<types::RelayLocation>,
         providers: Option<Vec<String>>,
+        ownership: Option<Constraint<Ownership>>,
     ) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let settings = rpc.get_settings(()).await?.into_inner();
@@ -246,6 +279,9 @@ impl Bridge {
                     constraints.providers =
                         types::try_providers_constraint_from_proto(&new_providers).unwrap();
                 }
+                if let Some(new_ownership) = ownership {
+                    constraints.ownership = new_ownership;
+                }
                 constraints
             }
             _ => {
@@ -257,6 +293,7 @@ impl Bridge {
                 BridgeConstraints {
                     location,
                     providers,
+                    ownership: ownership.unwrap_or(Constraint::Any),
                 }
             }
         };
@@ -1,6 +1,4 @@
-use crate::{format, new_rpc_client, state, Command, Error, Result};
-use futures::StreamExt;
-use mullvad_management_interface::types::tunnel_state::State;
+use crate::{new_rpc_client, state, Command, Result};
 
 pub struct Connect;
 
@@ -19,6 +17,20 @@ impl Command for Connect {
                     .short('w')
                     .help("Wait until connected before exiting"),
             )
+            .arg(
+                clap::Arg::new("timeout")
+                    .long("timeout")
+                    .takes_value(true)
+                    .value_name("SECONDS")
+                    .requires("wait")
+                    .help("Stop waiting and fail if not connected within this many seconds"),
+            )
+            .arg(
+                clap::Arg::new("json")
+                    .long("json")
+                    .requires("wait")
+                    .help("Prints tunnel states as JSON, one object per line"),
+            )
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
@@ -31,17 +43,9 @@ impl Command for Connect {
         };
 
         if rpc.connect_tunnel(()).await?.into_inner() {
-            if let Some(mut receiver) = receiver_option {
-                while let Some(state) = receiver.next().await {
-                    let state = state?;
-                    format::print_state(&state);
-                    match state.state.unwrap() {
-                        State::Connected(_) => return Ok(()),
-                        State::Error(_) => return Err(Error::CommandFailed("connect")),
-                        _ => {}
-                    }
-                }
-                return Err(Error::StatusListenerFailed);
+            if let Some(receiver) = receiver_option {
+                let options = state::WaitOptions::from_matches(matches);
+                return state::wait_for_connected(receiver, "connect", &options).await;
             }
         }
 
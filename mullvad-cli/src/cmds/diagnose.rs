@@ -0,0 +1,28 @@
+use crate::{format, new_rpc_client, Command, Error, Result};
+
+pub struct Diagnose;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Diagnose {
+    fn name(&self) -> &'static str {
+        "diagnose"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name()).about(
+            "Check whether the API, the selected relay and DNS are reachable in the current \
+            tunnel state",
+        )
+    }
+
+    async fn run(&self, _: &clap::ArgMatches) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let report = rpc
+            .run_connectivity_check(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to run connectivity check", error))?
+            .into_inner();
+        format::print_connectivity_report(&report);
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{format, new_rpc_client, Command, Result};
 use mullvad_management_interface::types;
-use mullvad_types::settings::{DnsOptions, DnsState};
+use mullvad_types::settings::{CustomDnsOptions, DnsOptions, DnsServerReachability, DnsState};
 use std::{convert::TryInto, net::IpAddr};
 
 pub struct Dns;
@@ -16,6 +16,10 @@ impl Command for Dns {
             .about("Configure DNS servers to use when connected")
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(clap::App::new("get").about("Display the current DNS settings"))
+            .subcommand(
+                clap::App::new("status")
+                    .about("Display the DNS servers in effect and whether they respond"),
+            )
             .subcommand(
                 clap::App::new("set")
                     .about("Set DNS servers to use")
@@ -50,6 +54,14 @@ impl Command for Dns {
                                     .multiple_occurrences(true)
                                     .help("One or more IP addresses pointing to DNS resolvers.")
                                     .required(true),
+                            )
+                            .arg(
+                                clap::Arg::new("reject outside tunnel")
+                                    .long("reject-outside-tunnel")
+                                    .takes_value(false)
+                                    .help(
+                                        "Refuse servers that would be reached outside the tunnel",
+                                    ),
                             ),
                     ),
             )
@@ -74,11 +86,13 @@ impl Command for Dns {
                             _ => e.exit(),
                         },
                     };
-                    self.set_custom(servers).await
+                    self.set_custom(servers, matches.is_present("reject outside tunnel"))
+                        .await
                 }
                 _ => unreachable!("No custom-dns server command given"),
             },
             Some(("get", _)) => self.get().await,
+            Some(("status", _)) => self.status().await,
             _ => unreachable!("No custom-dns command given"),
         }
     }
@@ -107,22 +121,46 @@ impl Dns {
         Ok(())
     }
 
-    async fn set_custom(&self, servers: Option<Vec<IpAddr>>) -> Result<()> {
+    async fn set_custom(
+        &self,
+        servers: Option<Vec<IpAddr>>,
+        reject_outside_tunnel: bool,
+    ) -> Result<()> {
+        let servers = CustomDnsOptions::validate_addresses(servers.unwrap_or_default())
+            .unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            });
         let mut rpc = new_rpc_client().await?;
         let settings = rpc.get_settings(()).await?.into_inner();
         rpc.set_dns_options(types::DnsOptions {
             state: types::dns_options::DnsState::Custom as i32,
             custom_options: Some(types::CustomDnsOptions {
-                addresses: servers
-                    .unwrap_or_default()
-                    .into_iter()
-                    .map(|a| a.to_string())
-                    .collect(),
+                addresses: servers.iter().map(|a| a.to_string()).collect(),
+                reject_outside_tunnel,
             }),
             ..settings.tunnel_options.unwrap().dns_options.unwrap()
         })
         .await?;
         println!("Updated DNS settings");
+
+        let status = rpc.get_dns_state(()).await?.into_inner();
+        let tunnel_gateways: Vec<IpAddr> = status
+            .tunnel_gateways
+            .iter()
+            .filter_map(|gateway| gateway.parse().ok())
+            .collect();
+        for server in &servers {
+            println!("{}", format_server_reachability(server, &tunnel_gateways));
+        }
+        format::print_dns_outside_tunnel_warning(&status.outside_tunnel);
+        Ok(())
+    }
+
+    async fn status(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let status = rpc.get_dns_state(()).await?.into_inner();
+        format::print_dns_status(&status);
         Ok(())
     }
 
@@ -151,9 +189,26 @@ impl Dns {
                 for server in &options.custom_options.addresses {
                     println!("{}", server);
                 }
+                println!(
+                    "Reject servers outside the tunnel: {}",
+                    if options.custom_options.reject_outside_tunnel {
+                        "yes"
+                    } else {
+                        "no"
+                    }
+                );
             }
         }
 
         Ok(())
     }
 }
+
+fn format_server_reachability(server: &IpAddr, tunnel_gateways: &[IpAddr]) -> String {
+    let reachability = match CustomDnsOptions::reachability(server, tunnel_gateways) {
+        DnsServerReachability::Tunnel => "reached through the tunnel",
+        DnsServerReachability::Loopback => "runs on this device",
+        DnsServerReachability::LocalNetwork => "on the local network, reached outside the tunnel",
+    };
+    format!("{}: {}", server, reachability)
+}
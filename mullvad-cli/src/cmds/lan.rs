@@ -1,4 +1,7 @@
 use crate::{new_rpc_client, Command, Result};
+use ipnetwork::IpNetwork;
+use mullvad_management_interface::types;
+use mullvad_types::settings::validate_allowed_networks;
 
 pub struct Lan;
 
@@ -22,6 +25,35 @@ impl Command for Lan {
             .subcommand(
                 clap::App::new("get").about("Display the current local network sharing setting"),
             )
+            .subcommand(
+                clap::App::new("allow-network")
+                    .about(
+                        "Manage networks outside of the private ranges that are treated as local \
+                        networks when local network sharing is allowed",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("add")
+                            .about("Treat a network as a local network")
+                            .arg(
+                                clap::Arg::new("network")
+                                    .help("The network in CIDR notation, e.g. 100.64.0.0/10")
+                                    .required(true),
+                            ),
+                    )
+                    .subcommand(
+                        clap::App::new("remove")
+                            .about("Stop treating a network as a local network")
+                            .arg(
+                                clap::Arg::new("network")
+                                    .help("The network in CIDR notation")
+                                    .required(true),
+                            ),
+                    )
+                    .subcommand(
+                        clap::App::new("list").about("List the networks treated as local networks"),
+                    ),
+            )
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
@@ -30,6 +62,19 @@ impl Command for Lan {
             self.set(allow_lan == "allow").await
         } else if let Some(_matches) = matches.subcommand_matches("get") {
             self.get().await
+        } else if let Some(matches) = matches.subcommand_matches("allow-network") {
+            match matches.subcommand() {
+                Some(("add", matches)) => {
+                    let network = matches.value_of_t_or_exit::<IpNetwork>("network");
+                    self.add_allowed_network(network).await
+                }
+                Some(("remove", matches)) => {
+                    let network = matches.value_of_t_or_exit::<IpNetwork>("network");
+                    self.remove_allowed_network(network).await
+                }
+                Some(("list", _)) => self.list_allowed_networks().await,
+                _ => unreachable!("unhandled subcommand"),
+            }
         } else {
             unreachable!("No lan command given");
         }
@@ -53,4 +98,57 @@ impl Lan {
         );
         Ok(())
     }
+    async fn add_allowed_network(&self, network: IpNetwork) -> Result<()> {
+        let mut networks = Self::get_allowed_networks().await?;
+        networks.push(network);
+        self.set_allowed_networks(networks).await
+    }
+
+    async fn remove_allowed_network(&self, network: IpNetwork) -> Result<()> {
+        let network = normalize_network(network);
+        let mut networks = Self::get_allowed_networks().await?;
+        let previous_len = networks.len();
+        networks.retain(|allowed| *allowed != network);
+        if networks.len() == previous_len {
+            eprintln!("{} is not an allowed network", network);
+            std::process::exit(1);
+        }
+        self.set_allowed_networks(networks).await
+    }
+
+    async fn list_allowed_networks(&self) -> Result<()> {
+        for network in Self::get_allowed_networks().await? {
+            println!("{}", network);
+        }
+        Ok(())
+    }
+
+    async fn set_allowed_networks(&self, networks: Vec<IpNetwork>) -> Result<()> {
+        let networks = validate_allowed_networks(networks).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        });
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_allowed_networks(types::AllowedNetworks {
+            networks: networks.iter().map(|network| network.to_string()).collect(),
+        })
+        .await?;
+        println!("Updated allowed networks");
+        Ok(())
+    }
+
+    async fn get_allowed_networks() -> Result<Vec<IpNetwork>> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        Ok(settings
+            .allowed_networks
+            .iter()
+            .filter_map(|network| network.parse().ok())
+            .collect())
+    }
+}
+
+/// Clears the host bits of `network`, so that it matches the form the daemon stores networks in.
+fn normalize_network(network: IpNetwork) -> IpNetwork {
+    IpNetwork::new(network.network(), network.prefix()).unwrap_or(network)
 }
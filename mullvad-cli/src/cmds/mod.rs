@@ -4,6 +4,9 @@ use std::collections::HashMap;
 mod account;
 pub use self::account::Account;
 
+mod api;
+pub use self::api::Api;
+
 mod auto_connect;
 pub use self::auto_connect::AutoConnect;
 
@@ -19,6 +22,9 @@ pub use self::bridge::Bridge;
 mod connect;
 pub use self::connect::Connect;
 
+mod diagnose;
+pub use self::diagnose::Diagnose;
+
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
@@ -37,6 +43,12 @@ pub use self::relay::Relay;
 mod reset;
 pub use self::reset::Reset;
 
+mod security_preset;
+pub use self::security_preset::SecurityPreset;
+
+mod settings;
+pub use self::settings::Settings;
+
 #[cfg(any(target_os = "linux", windows))]
 mod split_tunnel;
 #[cfg(any(target_os = "linux", windows))]
@@ -55,17 +67,21 @@ pub use self::version::Version;
 pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
     let commands: Vec<Box<dyn Command>> = vec![
         Box::new(Account),
+        Box::new(Api),
         Box::new(AutoConnect),
         Box::new(BetaProgram),
         Box::new(BlockWhenDisconnected),
         Box::new(Bridge),
         Box::new(Connect),
+        Box::new(Diagnose),
         Box::new(Disconnect),
         Box::new(Dns),
         Box::new(Reconnect),
         Box::new(Lan),
         Box::new(Relay),
         Box::new(Reset),
+        Box::new(SecurityPreset),
+        Box::new(Settings),
         #[cfg(any(target_os = "linux", windows))]
         Box::new(SplitTunnel),
         Box::new(Status),
@@ -1,4 +1,4 @@
-use crate::{location, new_rpc_client, Command, Error, Result};
+use crate::{format, location, new_rpc_client, Command, Error, Result};
 use itertools::Itertools;
 use std::{
     convert::TryFrom,
@@ -131,6 +131,46 @@ impl Command for Relay {
                                 .required(true)
                             )
                     )
+                    .subcommand(
+                        clap::App::new("ownership")
+                            .about("Set whether to select relays owned by Mullvad or rented \
+                                   from a hosting provider.")
+                            .arg(
+                                clap::Arg::new("ownership")
+                                    .help("Ownership of the relays to use, or 'any' for no \
+                                           preference.")
+                                    .required(true)
+                                    .possible_values(&["any", "owned", "rented"]),
+                            )
+                    )
+                    .subcommand(
+                        clap::App::new("exclude-asn")
+                            .about("Exclude relays hosted in specific autonomous systems. This is \
+                                   best-effort: relays whose ASN is unknown are never excluded. \
+                                   'list -v' shows the ASN of each relay.")
+                            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                            .subcommand(
+                                clap::App::new("add")
+                                    .about("Add ASNs to exclude")
+                                    .arg(
+                                        clap::Arg::new("asn")
+                                            .help("ASNs to exclude, such as 'AS39351' or '39351'")
+                                            .multiple_values(true)
+                                            .required(true),
+                                    ),
+                            )
+                            .subcommand(
+                                clap::App::new("remove")
+                                    .about("Stop excluding ASNs")
+                                    .arg(
+                                        clap::Arg::new("asn")
+                                            .help("ASNs to stop excluding")
+                                            .multiple_values(true)
+                                            .required(true),
+                                    ),
+                            )
+                            .subcommand(clap::App::new("list").about("List excluded ASNs")),
+                    )
                     .subcommand(
                         clap::App::new("tunnel")
                             .about("Set tunnel protocol-specific constraints.")
@@ -200,12 +240,54 @@ impl Command for Relay {
             )
             .subcommand(clap::App::new("get"))
             .subcommand(
-                clap::App::new("list").about("List available countries and cities"),
+                clap::App::new("list")
+                    .about("List available countries and cities")
+                    .arg(
+                        clap::Arg::new("verbose")
+                            .help(
+                                "Also show the autonomous system that each relay is hosted in, \
+                                and any scheduled maintenance",
+                            )
+                            .short('v')
+                            .long("verbose"),
+                    ),
             )
             .subcommand(
                 clap::App::new("update")
                     .about("Update the list of available countries and cities"),
             )
+            .subcommand(
+                clap::App::new("maintenance-reconnect")
+                    .about(
+                        "Control whether the daemon reconnects to another relay shortly before \
+                        maintenance of the relay in use starts",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("set").arg(
+                            clap::Arg::new("policy")
+                                .required(true)
+                                .possible_values(&["on", "off"]),
+                        ),
+                    )
+                    .subcommand(clap::App::new("get")),
+            )
+            .subcommand(
+                clap::App::new("removed-relay-delay")
+                    .about(
+                        "Control how long the daemon waits before reconnecting when the relay in \
+                        use is removed from the relay list, so that ongoing downloads can finish",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::App::new("set").arg(
+                            clap::Arg::new("seconds")
+                                .help("The delay in seconds. At most one hour")
+                                .required(true),
+                        ),
+                    )
+                    .subcommand(clap::App::new("get")),
+            )
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
@@ -213,10 +295,24 @@ impl Command for Relay {
             self.set(set_matches).await
         } else if matches.subcommand_matches("get").is_some() {
             self.get().await
-        } else if matches.subcommand_matches("list").is_some() {
-            self.list().await
+        } else if let Some(list_matches) = matches.subcommand_matches("list") {
+            self.list(list_matches.is_present("verbose")).await
         } else if matches.subcommand_matches("update").is_some() {
             self.update().await
+        } else if let Some(matches) = matches.subcommand_matches("maintenance-reconnect") {
+            if let Some(set_matches) = matches.subcommand_matches("set") {
+                let policy = set_matches.value_of("policy").expect("missing policy");
+                self.set_maintenance_reconnect(policy == "on").await
+            } else {
+                self.get_maintenance_reconnect().await
+            }
+        } else if let Some(matches) = matches.subcommand_matches("removed-relay-delay") {
+            if let Some(set_matches) = matches.subcommand_matches("set") {
+                let seconds = set_matches.value_of_t_or_exit::<u64>("seconds");
+                self.set_removed_relay_delay(seconds).await
+            } else {
+                self.get_removed_relay_delay().await
+            }
         } else {
             unreachable!("No relay command given");
         }
@@ -242,6 +338,10 @@ impl Relay {
             self.set_hostname(relay_matches).await
         } else if let Some(providers_matches) = matches.subcommand_matches("provider") {
             self.set_providers(providers_matches).await
+        } else if let Some(ownership_matches) = matches.subcommand_matches("ownership") {
+            self.set_ownership(ownership_matches).await
+        } else if let Some(asn_matches) = matches.subcommand_matches("exclude-asn") {
+            self.set_excluded_asns(asn_matches).await
         } else if let Some(matches) = matches.subcommand_matches("tunnel") {
             if let Some(tunnel_matches) = matches.subcommand_matches("openvpn") {
                 self.set_openvpn_constraints(tunnel_matches).await
@@ -389,24 +489,7 @@ impl Relay {
         let hostname = matches.value_of("hostname").unwrap();
         let countries = Self::get_filtered_relays().await?;
 
-        let find_relay = || {
-            for country in countries {
-                for city in country.cities {
-                    for relay in city.relays {
-                        if relay.hostname.to_lowercase() == hostname.to_lowercase() {
-                            return Some(types::RelayLocation {
-                                country: country.code,
-                                city: city.code,
-                                hostname: relay.hostname,
-                            });
-                        }
-                    }
-                }
-            }
-            None
-        };
-
-        if let Some(location) = find_relay() {
+        if let Some(location) = find_relay_by_hostname(&countries, hostname) {
             println!(
                 "Setting location constraint to {} in {}, {}",
                 location.hostname, location.city, location.country
@@ -422,53 +505,28 @@ impl Relay {
             })
             .await
         } else {
-            clap::Error::raw(clap::ErrorKind::ValueValidation, "No matching server found").exit()
+            let suggestions = closest_hostnames(&countries, hostname);
+            let message = if suggestions.is_empty() {
+                "No matching server found".to_owned()
+            } else {
+                format!(
+                    "No matching server found. Did you mean: {}?",
+                    suggestions.join(", ")
+                )
+            };
+            clap::Error::raw(clap::ErrorKind::ValueValidation, message).exit()
         }
     }
 
     async fn set_location(&self, matches: &clap::ArgMatches) -> Result<()> {
         let location_constraint = location::get_constraint_from_args(matches);
-        let mut found = false;
 
         if !location_constraint.country.is_empty() {
             // TODO: `mullvad_types::relay_constraints::LocationConstraint::matches(&relay)`
             //       could be used to guarantee consistency with the daemon.
             let countries = Self::get_filtered_relays().await?;
-            for country in &countries {
-                if country.code != location_constraint.country {
-                    continue;
-                }
-
-                if location_constraint.city.is_empty() {
-                    found = true;
-                    break;
-                }
-
-                for city in &country.cities {
-                    if city.code != location_constraint.city {
-                        continue;
-                    }
-
-                    if location_constraint.hostname.is_empty() {
-                        found = true;
-                        break;
-                    }
-
-                    for relay in &city.relays {
-                        if relay.hostname != location_constraint.hostname {
-                            continue;
-                        }
-                        found = true;
-                        break;
-                    }
-
-                    break;
-                }
-                break;
-            }
-
-            if !found {
-                eprintln!("Warning: No matching relay was found.");
+            if let Err(mismatch) = match_location(&countries, &location_constraint) {
+                eprintln!("Warning: {}", mismatch);
             }
         }
 
@@ -502,6 +560,76 @@ impl Relay {
         .await
     }
 
+    async fn set_ownership(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let ownership = parse_ownership(matches.value_of("ownership").unwrap());
+
+        self.update_constraints(types::RelaySettingsUpdate {
+            r#type: Some(types::relay_settings_update::Type::Normal(
+                types::NormalRelaySettingsUpdate {
+                    ownership: Some(types::OwnershipUpdate {
+                        ownership: ownership as i32,
+                    }),
+                    ..Default::default()
+                },
+            )),
+        })
+        .await
+    }
+
+    async fn set_excluded_asns(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut asns = self.get_excluded_asns(&mut rpc).await?;
+
+        match matches.subcommand() {
+            Some(("add", add_matches)) => {
+                for asn in parse_asns(add_matches) {
+                    if !asns.contains(&asn) {
+                        asns.push(asn);
+                    }
+                }
+            }
+            Some(("remove", remove_matches)) => {
+                let removed = parse_asns(remove_matches);
+                asns.retain(|asn| !removed.contains(asn));
+            }
+            Some(("list", _)) => {
+                if asns.is_empty() {
+                    println!("No ASNs are excluded");
+                }
+                for asn in asns {
+                    println!("AS{}", asn);
+                }
+                return Ok(());
+            }
+            _ => unreachable!("No exclude-asn command given"),
+        }
+
+        self.update_constraints(types::RelaySettingsUpdate {
+            r#type: Some(types::relay_settings_update::Type::Normal(
+                types::NormalRelaySettingsUpdate {
+                    excluded_asns: Some(types::ExcludedAsnsUpdate { asns }),
+                    ..Default::default()
+                },
+            )),
+        })
+        .await
+    }
+
+    async fn get_excluded_asns(&self, rpc: &mut ManagementServiceClient) -> Result<Vec<u32>> {
+        match rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .relay_settings
+            .unwrap()
+            .endpoint
+            .unwrap()
+        {
+            types::relay_settings::Endpoint::Normal(settings) => Ok(settings.excluded_asns),
+            types::relay_settings::Endpoint::Custom(_settings) => Ok(vec![]),
+        }
+    }
+
     async fn set_openvpn_constraints(&self, matches: &clap::ArgMatches) -> Result<()> {
         let mut openvpn_constraints = {
             let mut rpc = new_rpc_client().await?;
@@ -636,7 +764,7 @@ impl Relay {
         Ok(())
     }
 
-    async fn list(&self) -> Result<()> {
+    async fn list(&self, verbose: bool) -> Result<()> {
         let mut countries = Self::get_filtered_relays().await?;
         countries.sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
         for mut country in countries {
@@ -665,13 +793,27 @@ impl Relay {
                     if !relay.ipv6_addr_in.is_empty() {
                         addresses.push(&relay.ipv6_addr_in);
                     }
+                    let asn_info = if verbose {
+                        format_asn(relay)
+                    } else {
+                        String::new()
+                    };
                     println!(
-                        "\t\t{} ({}) - {}, hosted by {}",
+                        "\t\t{} ({}) - {}, hosted by {}{}",
                         relay.hostname,
                         addresses.iter().join(", "),
                         support_msg,
-                        relay.provider
+                        relay.provider,
+                        asn_info
                     );
+                    if verbose {
+                        if let Some(window) = &relay.maintenance {
+                            println!(
+                                "\t\t\tScheduled maintenance: {}",
+                                format::format_maintenance_window(window)
+                            );
+                        }
+                    }
                 }
             }
             println!();
@@ -679,6 +821,53 @@ impl Relay {
         Ok(())
     }
 
+    async fn set_maintenance_reconnect(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_reconnect_before_maintenance(enabled).await?;
+        println!("Changed the maintenance reconnect setting");
+        Ok(())
+    }
+
+    async fn get_maintenance_reconnect(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let enabled = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .reconnect_before_maintenance;
+        println!(
+            "Reconnect before maintenance: {}",
+            if enabled { "on" } else { "off" }
+        );
+        Ok(())
+    }
+
+    async fn set_removed_relay_delay(&self, seconds: u64) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_relay_removed_reconnect_delay(types::Duration {
+            seconds: i64::try_from(seconds).unwrap_or(i64::MAX),
+            nanos: 0,
+        })
+        .await?;
+        println!("Changed the removed relay reconnect delay");
+        Ok(())
+    }
+
+    async fn get_removed_relay_delay(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let delay = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .relay_removed_reconnect_delay
+            .unwrap_or_default();
+        println!(
+            "Reconnect delay after the relay in use is removed: {} seconds",
+            delay.seconds
+        );
+        Ok(())
+    }
+
     async fn update(&self) -> Result<()> {
         new_rpc_client().await?.update_relay_locations(()).await?;
         println!("Updating relay list in the background...");
@@ -722,6 +911,183 @@ impl Relay {
     }
 }
 
+fn parse_asns(matches: &clap::ArgMatches) -> Vec<u32> {
+    matches
+        .values_of("asn")
+        .unwrap()
+        .map(|asn| {
+            parse_asn(asn).unwrap_or_else(|| {
+                clap::Error::raw(
+                    clap::ErrorKind::ValueValidation,
+                    format!("Invalid ASN: {}", asn),
+                )
+                .exit()
+            })
+        })
+        .collect()
+}
+
+/// Parses an ASN given either as a number or with an "AS" prefix.
+fn parse_asn(asn: &str) -> Option<u32> {
+    let number = match asn.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("as") => &asn[2..],
+        _ => asn,
+    };
+    number.parse().ok().filter(|asn| *asn != 0)
+}
+
+fn format_asn(relay: &types::Relay) -> String {
+    match (relay.asn, relay.asn_organization.as_str()) {
+        (0, _) => ", unknown ASN".to_owned(),
+        (asn, "") => format!(", AS{}", asn),
+        (asn, organization) => format!(", AS{} ({})", asn, organization),
+    }
+}
+
+/// Maximum number of hostnames suggested when no relay matches the given hostname.
+const MAX_HOSTNAME_SUGGESTIONS: usize = 3;
+
+/// Maximum edit distance for a hostname to be suggested as a correction.
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+/// Reasons for a location constraint not matching any relay in the relay list.
+#[derive(Debug, PartialEq)]
+enum LocationMismatch {
+    Country(String),
+    City(String, String),
+    Hostname(String),
+}
+
+impl std::fmt::Display for LocationMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocationMismatch::Country(country) => {
+                write!(f, "No relays found in the country '{}'", country)
+            }
+            LocationMismatch::City(country, city) => write!(
+                f,
+                "No relays found in the city '{}' in the country '{}'",
+                city, country
+            ),
+            LocationMismatch::Hostname(hostname) => {
+                write!(
+                    f,
+                    "No relay with the hostname '{}' found in this location",
+                    hostname
+                )
+            }
+        }
+    }
+}
+
+/// Returns the full location constraint of the relay with the given hostname. The comparison is
+/// case-insensitive.
+fn find_relay_by_hostname(
+    countries: &[types::RelayListCountry],
+    hostname: &str,
+) -> Option<types::RelayLocation> {
+    let hostname = hostname.to_lowercase();
+    for country in countries {
+        for city in &country.cities {
+            for relay in &city.relays {
+                if relay.hostname.to_lowercase() == hostname {
+                    return Some(types::RelayLocation {
+                        country: country.code.clone(),
+                        city: city.code.clone(),
+                        hostname: relay.hostname.clone(),
+                    });
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Checks that at least one relay in the relay list matches the location constraint.
+fn match_location(
+    countries: &[types::RelayListCountry],
+    constraint: &types::RelayLocation,
+) -> std::result::Result<(), LocationMismatch> {
+    let country = countries
+        .iter()
+        .find(|country| country.code.eq_ignore_ascii_case(&constraint.country))
+        .ok_or_else(|| LocationMismatch::Country(constraint.country.clone()))?;
+    if constraint.city.is_empty() {
+        return Ok(());
+    }
+
+    let city = country
+        .cities
+        .iter()
+        .find(|city| city.code.eq_ignore_ascii_case(&constraint.city))
+        .ok_or_else(|| {
+            LocationMismatch::City(constraint.country.clone(), constraint.city.clone())
+        })?;
+    if constraint.hostname.is_empty() {
+        return Ok(());
+    }
+
+    if city
+        .relays
+        .iter()
+        .any(|relay| relay.hostname.eq_ignore_ascii_case(&constraint.hostname))
+    {
+        Ok(())
+    } else {
+        Err(LocationMismatch::Hostname(constraint.hostname.clone()))
+    }
+}
+
+/// Returns the hostnames in the relay list that are closest to `hostname`. Hostnames that start
+/// with the given string are preferred, followed by those within a small edit distance.
+fn closest_hostnames(countries: &[types::RelayListCountry], hostname: &str) -> Vec<String> {
+    let hostname = hostname.to_lowercase();
+    let mut candidates: Vec<(bool, usize, &str)> = countries
+        .iter()
+        .flat_map(|country| country.cities.iter())
+        .flat_map(|city| city.relays.iter())
+        .filter_map(|relay| {
+            let candidate = relay.hostname.to_lowercase();
+            let is_prefix = candidate.starts_with(&hostname);
+            let distance = levenshtein_distance(&hostname, &candidate);
+            if is_prefix || distance <= MAX_SUGGESTION_DISTANCE {
+                Some((!is_prefix, distance, relay.hostname.as_str()))
+            } else {
+                None
+            }
+        })
+        .collect();
+    candidates.sort_by(|a, b| {
+        (a.0, a.1)
+            .cmp(&(b.0, b.1))
+            .then_with(|| natord::compare_ignore_case(a.2, b.2))
+    });
+    candidates
+        .into_iter()
+        .take(MAX_HOSTNAME_SUGGESTIONS)
+        .map(|(_, _, hostname)| hostname.to_owned())
+        .collect()
+}
+
+fn levenshtein_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous_row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current_row = Vec::with_capacity(b.len() + 1);
+        current_row.push(i + 1);
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution_cost = if a_char == *b_char { 0 } else { 1 };
+            current_row.push(
+                (previous_row[j] + substitution_cost)
+                    .min(previous_row[j + 1] + 1)
+                    .min(current_row[j] + 1),
+            );
+        }
+        previous_row = current_row;
+    }
+    previous_row[b.len()]
+}
+
 fn parse_port_constraint(raw_port: &str) -> Result<Constraint<u16>> {
     match raw_port.to_lowercase().as_str() {
         "any" => Ok(Constraint::Any),
@@ -740,6 +1106,15 @@ fn parse_protocol(raw_protocol: &str) -> Constraint<types::TransportProtocol> {
     }
 }
 
+fn parse_ownership(raw_ownership: &str) -> types::Ownership {
+    match raw_ownership {
+        "any" => types::Ownership::Any,
+        "owned" => types::Ownership::MullvadOwned,
+        "rented" => types::Ownership::Rented,
+        _ => unreachable!(),
+    }
+}
+
 fn parse_ip_version_constraint(raw_protocol: &str) -> Constraint<types::IpVersion> {
     match raw_protocol {
         "any" => Constraint::Any,
@@ -816,3 +1191,131 @@ fn parse_transport_port(
         )),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn relay(hostname: &str) -> types::Relay {
+        types::Relay {
+            hostname: hostname.to_owned(),
+            active: true,
+            ..Default::default()
+        }
+    }
+
+    fn relay_list() -> Vec<types::RelayListCountry> {
+        vec![
+            types::RelayListCountry {
+                name: "Sweden".to_owned(),
+                code: "se".to_owned(),
+                cities: vec![
+                    types::RelayListCity {
+                        name: "Gothenburg".to_owned(),
+                        code: "got".to_owned(),
+                        relays: vec![relay("se-got-001"), relay("se-got-002")],
+                        ..Default::default()
+                    },
+                    types::RelayListCity {
+                        name: "Stockholm".to_owned(),
+                        code: "sto".to_owned(),
+                        relays: vec![relay("se-sto-wg-001")],
+                        ..Default::default()
+                    },
+                ],
+            },
+            types::RelayListCountry {
+                name: "Norway".to_owned(),
+                code: "no".to_owned(),
+                cities: vec![types::RelayListCity {
+                    name: "Oslo".to_owned(),
+                    code: "osl".to_owned(),
+                    relays: vec![relay("no-osl-002")],
+                    ..Default::default()
+                }],
+            },
+        ]
+    }
+
+    fn location(country: &str, city: &str, hostname: &str) -> types::RelayLocation {
+        types::RelayLocation {
+            country: country.to_owned(),
+            city: city.to_owned(),
+            hostname: hostname.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_find_relay_by_hostname() {
+        let countries = relay_list();
+        assert_eq!(
+            find_relay_by_hostname(&countries, "SE-STO-WG-001"),
+            Some(location("se", "sto", "se-sto-wg-001"))
+        );
+        assert_eq!(find_relay_by_hostname(&countries, "se-sto-001"), None);
+    }
+
+    #[test]
+    fn test_match_location() {
+        let countries = relay_list();
+        assert_eq!(match_location(&countries, &location("se", "", "")), Ok(()));
+        assert_eq!(
+            match_location(&countries, &location("no", "osl", "")),
+            Ok(())
+        );
+        assert_eq!(
+            match_location(&countries, &location("se", "got", "se-got-002")),
+            Ok(())
+        );
+        assert_eq!(
+            match_location(&countries, &location("de", "", "")),
+            Err(LocationMismatch::Country("de".to_owned()))
+        );
+        assert_eq!(
+            match_location(&countries, &location("se", "osl", "")),
+            Err(LocationMismatch::City("se".to_owned(), "osl".to_owned()))
+        );
+        assert_eq!(
+            match_location(&countries, &location("se", "sto", "se-got-001")),
+            Err(LocationMismatch::Hostname("se-got-001".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_closest_hostnames() {
+        let countries = relay_list();
+        assert_eq!(
+            closest_hostnames(&countries, "se-got"),
+            vec!["se-got-001", "se-got-002"]
+        );
+        assert_eq!(
+            closest_hostnames(&countries, "se-sto-001"),
+            vec!["se-got-001", "se-sto-wg-001"]
+        );
+        assert_eq!(
+            closest_hostnames(&countries, "no-osl-02"),
+            vec!["no-osl-002"]
+        );
+        assert!(closest_hostnames(&countries, "us-nyc-001").is_empty());
+    }
+
+    #[test]
+    fn test_levenshtein_distance() {
+        assert_eq!(levenshtein_distance("", ""), 0);
+        assert_eq!(levenshtein_distance("se-got-001", "se-got-001"), 0);
+        assert_eq!(levenshtein_distance("se-got-001", "se-got-002"), 1);
+        assert_eq!(levenshtein_distance("se-sto-001", "se-sto-wg-001"), 3);
+        assert_eq!(levenshtein_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_parse_asn() {
+        assert_eq!(parse_asn("39351"), Some(39351));
+        assert_eq!(parse_asn("AS39351"), Some(39351));
+        assert_eq!(parse_asn("as39351"), Some(39351));
+        assert_eq!(parse_asn("AS"), None);
+        assert_eq!(parse_asn("0"), None);
+        assert_eq!(parse_asn("ÅS1"), None);
+        assert_eq!(parse_asn("-1"), None);
+    }
+}
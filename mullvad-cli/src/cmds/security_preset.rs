@@ -0,0 +1,72 @@
+use crate::{new_rpc_client, Command, Result};
+use mullvad_management_interface::types;
+use mullvad_types::settings::SecurityPreset as Preset;
+use std::convert::TryFrom;
+
+pub struct SecurityPreset;
+
+#[mullvad_management_interface::async_trait]
+impl Command for SecurityPreset {
+    fn name(&self) -> &'static str {
+        "security-preset"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name())
+            .about(
+                "Control always require VPN, local network sharing, allowed networks and the API \
+                 bridge mode at once",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::App::new("set")
+                    .about(
+                        "Apply a preset. strict blocks all traffic outside the tunnel and only \
+                         reaches the API through bridges, lan-friendly blocks all traffic outside the tunnel except to the local \
+                         network, and standard restores the default settings. Allowed networks \
+                         are cleared by every preset",
+                    )
+                    .arg(clap::Arg::new("preset").required(true).possible_values(&[
+                        "strict",
+                        "standard",
+                        "lan-friendly",
+                    ])),
+            )
+            .subcommand(
+                clap::App::new("get")
+                    .about("Display the preset that matches the current settings, if any"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let name = set_matches.value_of("preset").expect("missing preset");
+            let preset = Preset::from_name(name).expect("invalid preset");
+            self.set(preset).await
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get().await
+        } else {
+            unreachable!("No security-preset command given");
+        }
+    }
+}
+
+impl SecurityPreset {
+    async fn set(&self, preset: Preset) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_security_preset(types::SecurityPreset::from(Some(preset)))
+            .await?;
+        println!("Applied the {} security preset", preset);
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let preset = rpc.get_security_preset(()).await?.into_inner();
+        match Preset::try_from(preset) {
+            Ok(preset) => println!("Security preset: {}", preset),
+            Err(_) => println!("Security preset: custom"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{new_rpc_client, Command, Result};
+use std::{
+    fs,
+    io::{self, Read},
+};
+
+pub struct Settings;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Settings {
+    fn name(&self) -> &'static str {
+        "settings"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static> {
+        clap::App::new(self.name())
+            .about("Export settings to share them with other machines, or import them")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::App::new("export")
+                    .about(
+                        "Export the relay, DNS, LAN, auto-connect and split tunneling settings. \
+                        The account number, keys, custom tunnel endpoints and bridge settings \
+                        are never exported.",
+                    )
+                    .arg(
+                        clap::Arg::new("file")
+                            .help("File to write the settings to. Defaults to standard output.")
+                            .index(1),
+                    ),
+            )
+            .subcommand(
+                clap::App::new("import")
+                    .about(
+                        "Import settings created by 'mullvad settings export'. Sections that \
+                        are invalid or unsupported on this platform are skipped.",
+                    )
+                    .arg(
+                        clap::Arg::new("file")
+                            .help("File to read the settings from. Defaults to standard input.")
+                            .index(1),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            Some(("export", export_matches)) => {
+                Self::handle_export(export_matches.value_of("file")).await
+            }
+            Some(("import", import_matches)) => {
+                Self::handle_import(import_matches.value_of("file")).await
+            }
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+impl Settings {
+    async fn handle_export(path: Option<&str>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let contents = rpc.export_settings(()).await?.into_inner();
+
+        match path {
+            Some(path) => {
+                if let Err(error) = fs::write(path, contents.as_bytes()) {
+                    eprintln!("Failed to write {}: {}", path, error);
+                    std::process::exit(1);
+                }
+                println!("Saved settings to {}", path);
+            }
+            None => println!("{}", contents),
+        }
+        Ok(())
+    }
+
+    async fn handle_import(path: Option<&str>) -> Result<()> {
+        let read_result = match path {
+            Some(path) => fs::read_to_string(path),
+            None => {
+                let mut contents = String::new();
+                io::stdin().read_to_string(&mut contents).map(|_| contents)
+            }
+        };
+        let contents = match read_result {
+            Ok(contents) => contents,
+            Err(error) => {
+                eprintln!(
+                    "Failed to read {}: {}",
+                    path.unwrap_or("standard input"),
+                    error
+                );
+                std::process::exit(1);
+            }
+        };
+
+        let mut rpc = new_rpc_client().await?;
+        let report = match rpc.import_settings(contents).await {
+            Ok(report) => report.into_inner(),
+            Err(status) if status.code() == mullvad_management_interface::Code::InvalidArgument => {
+                eprintln!("Invalid settings file: {}", status.message());
+                std::process::exit(1);
+            }
+            Err(status) => return Err(status.into()),
+        };
+
+        for section in &report.applied_sections {
+            println!("Imported {}", section);
+        }
+        for skipped in &report.skipped_sections {
+            println!("Skipped {}: {}", skipped.section, skipped.reason);
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{format, format::print_keygen_event, new_rpc_client, Command, Error, Result};
 use mullvad_management_interface::{
-    types::daemon_event::Event as EventType, ManagementServiceClient,
+    types::{self, daemon_event::Event as EventType, events_filter::Category},
+    ManagementServiceClient,
 };
 
 pub struct Status;
@@ -20,71 +21,301 @@ impl Command for Status {
                     .short('l')
                     .help("Prints the current location and IP. Based on GeoIP lookups"),
             )
-            .subcommand(
-                clap::App::new("listen")
-                    .about("Listen for VPN tunnel state changes")
-                    .arg(
-                        clap::Arg::new("verbose")
-                            .short('v')
-                            .help("Enables verbose output"),
+            .arg(
+                clap::Arg::new("json")
+                    .long("json")
+                    .global(true)
+                    .conflicts_with("location")
+                    .help("Prints tunnel states as JSON, one object per line"),
+            )
+            .arg(
+                clap::Arg::new("timings")
+                    .long("timings")
+                    .conflicts_with("json")
+                    .requires("verbose")
+                    .help(
+                        "Prints how long each phase of recent connection attempts took. Must be \
+                        used with --verbose",
                     ),
             )
+            .arg(
+                clap::Arg::new("verbose")
+                    .long("verbose")
+                    .short('v')
+                    .global(true)
+                    .help("Enables verbose output"),
+            )
+            .subcommand(clap::App::new("listen").about("Listen for VPN tunnel state changes"))
     }
 
     async fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
+        let listen_matches = matches.subcommand_matches("listen");
+        let json = matches.is_present("json")
+            || listen_matches
+                .map(|listen_matches| listen_matches.is_present("json"))
+                .unwrap_or(false);
+
+        // When listening, the current state is the first event of the stream, so that no change
+        // is missed between getting the state and subscribing.
+        if let Some(listen_matches) = listen_matches {
+            return if json {
+                listen_json(&mut rpc).await
+            } else {
+                listen(&mut rpc, matches, listen_matches.is_present("verbose")).await
+            };
+        }
+
         let state = rpc.get_tunnel_state(()).await?.into_inner();
+        if json {
+            return format::print_state_json(&state);
+        }
 
         format::print_state(&state);
+        if matches.is_present("verbose") {
+            format::print_relays(&state);
+            print_rate_limit(&mut rpc).await?;
+        }
+        print_startup_state(&mut rpc, matches.is_present("verbose")).await?;
+        print_missing_relay_warning(&mut rpc).await?;
         if matches.is_present("location") {
             print_location(&mut rpc).await?;
         }
+        if matches.is_present("timings") {
+            print_connection_metrics(&mut rpc).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Prints the current tunnel state and every event after it until the daemon closes the event
+/// stream. Events other than tunnel states, removed relays and relay maintenance are only printed
+/// if `verbose` is set.
+async fn listen(
+    rpc: &mut ManagementServiceClient,
+    matches: &clap::ArgMatches,
+    verbose: bool,
+) -> Result<()> {
+    let categories = if verbose {
+        vec![]
+    } else {
+        vec![
+            Category::TunnelState,
+            Category::RelayDeprecated,
+            Category::RelayMaintenance,
+        ]
+    };
+    let mut events = rpc
+        .events_listen(types::EventsFilter {
+            categories: categories.into_iter().map(i32::from).collect(),
+            snapshot: true,
+        })
+        .await?
+        .into_inner();
 
-        if let Some(listen_matches) = matches.subcommand_matches("listen") {
-            let verbose = listen_matches.is_present("verbose");
-
-            let mut events = rpc.events_listen(()).await?.into_inner();
-
-            while let Some(event) = events.message().await? {
-                match event.event.unwrap() {
-                    EventType::TunnelState(new_state) => {
-                        format::print_state(&new_state);
-                        use mullvad_management_interface::types::tunnel_state::State::*;
-                        match new_state.state.unwrap() {
-                            Connected(..) | Disconnected(..) => {
-                                if matches.is_present("location") {
-                                    print_location(&mut rpc).await?;
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                    EventType::Settings(settings) => {
-                        if verbose {
-                            println!("New settings: {:#?}", settings);
-                        }
-                    }
-                    EventType::RelayList(relay_list) => {
-                        if verbose {
-                            println!("New relay list: {:#?}", relay_list);
-                        }
-                    }
-                    EventType::VersionInfo(app_version_info) => {
-                        if verbose {
-                            println!("New app version info: {:#?}", app_version_info);
-                        }
-                    }
-                    EventType::KeyEvent(key_event) => {
-                        if verbose {
-                            print!("Key event: ");
-                            print_keygen_event(&key_event);
-                        }
-                    }
+    let mut is_initial_state = true;
+    while let Some(event) = events.message().await? {
+        match event.event.unwrap() {
+            EventType::TunnelState(new_state) => {
+                format::print_state(&new_state);
+                if verbose {
+                    format::print_relays(&new_state);
+                }
+                use mullvad_management_interface::types::tunnel_state::State::*;
+                let print_location_now = match new_state.state.unwrap() {
+                    Connected(..) | Disconnected(..) => true,
+                    _ => is_initial_state,
+                };
+                if is_initial_state {
+                    print_missing_relay_warning(rpc).await?;
+                }
+                if print_location_now && matches.is_present("location") {
+                    print_location(rpc).await?;
+                }
+                if is_initial_state && matches.is_present("timings") {
+                    print_connection_metrics(rpc).await?;
+                }
+                is_initial_state = false;
+            }
+            EventType::Settings(settings) => {
+                println!("New settings: {:#?}", settings);
+            }
+            EventType::RelayList(relay_list) => {
+                println!("New relay list: {:#?}", relay_list);
+            }
+            EventType::VersionInfo(app_version_info) => {
+                println!("New app version info: {:#?}", app_version_info);
+            }
+            EventType::KeyEvent(key_event) => {
+                print!("Key event: ");
+                print_keygen_event(&key_event);
+            }
+            EventType::MigrationEvent(migration_event) => {
+                println!("Settings migration event: {:#?}", migration_event);
+            }
+            EventType::AccountExpiry(expiry) => {
+                println!("New account expiry: {:#?}", expiry);
+            }
+            EventType::EventsDropped(dropped) => {
+                println!(
+                    "Missed {} events because they were not received fast enough",
+                    dropped.count
+                );
+            }
+            EventType::RelayMaintenance(maintenance) => {
+                let window = maintenance
+                    .window
+                    .as_ref()
+                    .map(format::format_maintenance_window)
+                    .unwrap_or_default();
+                match &maintenance.reconnect_at {
+                    Some(reconnect_at) => println!(
+                        "Maintenance of relay {} is scheduled from {}. Reconnecting at {}",
+                        maintenance.hostname,
+                        window,
+                        format::format_timestamp(reconnect_at)
+                    ),
+                    None => println!(
+                        "Maintenance of relay {} is scheduled from {}",
+                        maintenance.hostname, window
+                    ),
+                }
+            }
+            EventType::RelayDeprecated(relay) => {
+                if relay.still_connected {
+                    println!(
+                        "Relay {} was removed from the relay list. Reconnecting shortly",
+                        relay.hostname
+                    );
+                } else {
+                    println!(
+                        "Selected relay {} was removed from the relay list",
+                        relay.hostname
+                    );
                 }
             }
         }
+    }
 
-        Ok(())
+    Ok(())
+}
+
+async fn print_connection_metrics(rpc: &mut ManagementServiceClient) -> Result<()> {
+    let metrics = rpc.get_connection_metrics(()).await?.into_inner();
+    format::print_connection_metrics(&metrics);
+    Ok(())
+}
+
+/// Prints the current tunnel state and every change after it as JSON until the daemon closes the
+/// event stream. Other events are ignored.
+async fn listen_json(rpc: &mut ManagementServiceClient) -> Result<()> {
+    let mut events = rpc
+        .events_listen(types::EventsFilter {
+            categories: vec![i32::from(Category::TunnelState)],
+            snapshot: true,
+        })
+        .await?
+        .into_inner();
+
+    loop {
+        match events.message().await {
+            Ok(Some(event)) => {
+                if let Some(EventType::TunnelState(new_state)) = event.event {
+                    format::print_state_json(&new_state)?;
+                }
+            }
+            // The stream ending or failing means that the daemon went away.
+            Ok(None) | Err(_) => break,
+        }
+    }
+
+    format::print_daemon_disconnected_json()
+}
+
+/// Prints whether traffic is blocked because of the lockdown after boot, and whether the daemon
+/// crashed the last time it ran. If `verbose` is set, also prints why the daemon started with the
+/// target state it did.
+async fn print_startup_state(rpc: &mut ManagementServiceClient, verbose: bool) -> Result<()> {
+    use types::startup_state::Reason;
+
+    let state = rpc.get_startup_state(()).await?.into_inner();
+    if state.locked_down {
+        println!("Blocking all traffic until the VPN is connected (lockdown after boot)");
+    }
+    if !state.previous_crash_report.is_empty() {
+        println!(
+            "Warning: The daemon crashed the last time it ran. A crash report was saved to {}",
+            state.previous_crash_report
+        );
+    }
+    if verbose {
+        let reason = match Reason::from_i32(state.reason) {
+            Some(Reason::NoCachedState) => "no state was saved when the service stopped",
+            Some(Reason::Restored) => "the state from before the service stopped was restored",
+            Some(Reason::UnreadableCache) => "the saved state could not be read",
+            Some(Reason::AutoConnect) => "auto-connect is turned on",
+            Some(Reason::NoAccount) => "no account is set",
+            Some(Reason::LockdownAfterBoot) => "lockdown after boot is turned on",
+            None => "unknown",
+        };
+        println!("Startup state: {}", reason);
+    }
+    Ok(())
+}
+
+/// Prints the bandwidth limit of WireGuard tunnels, if one is set.
+async fn print_rate_limit(rpc: &mut ManagementServiceClient) -> Result<()> {
+    let settings = rpc.get_settings(()).await?.into_inner();
+    let limit = settings
+        .tunnel_options
+        .and_then(|options| options.wireguard)
+        .and_then(|options| options.rate_limit);
+    if let Some(limit) = limit {
+        println!(
+            "WireGuard bandwidth limit: up {} kbit/s, down {} kbit/s",
+            limit.up_kbps, limit.down_kbps
+        );
+    }
+    Ok(())
+}
+
+async fn print_missing_relay_warning(rpc: &mut ManagementServiceClient) -> Result<()> {
+    let settings = rpc.get_settings(()).await?.into_inner();
+    let relay_list = rpc.get_relay_locations(()).await?.into_inner();
+    if let Some(hostname) = missing_selected_relay(&settings, &relay_list) {
+        println!(
+            "Warning: The selected relay {} is no longer in the relay list",
+            hostname
+        );
+    }
+    Ok(())
+}
+
+/// Returns the hostname of the relay selected in `settings` if it is missing from `relay_list`.
+fn missing_selected_relay<'a>(
+    settings: &'a types::Settings,
+    relay_list: &types::RelayList,
+) -> Option<&'a str> {
+    let hostname = match settings.relay_settings.as_ref()?.endpoint.as_ref()? {
+        types::relay_settings::Endpoint::Normal(settings) => {
+            settings.location.as_ref()?.hostname.as_str()
+        }
+        types::relay_settings::Endpoint::Custom(_) => return None,
+    };
+    if hostname.is_empty() {
+        return None;
+    }
+    let exists = relay_list
+        .countries
+        .iter()
+        .flat_map(|country| &country.cities)
+        .flat_map(|city| &city.relays)
+        .any(|relay| relay.hostname.eq_ignore_ascii_case(hostname));
+    if exists {
+        None
+    } else {
+        Some(hostname)
     }
 }
 
@@ -101,6 +332,9 @@ async fn print_location(rpc: &mut ManagementServiceClient) -> Result<()> {
             }
         }
     };
+    if !location.entry_hostname.is_empty() {
+        println!("Entry relay: {}", location.entry_hostname);
+    }
     if !location.hostname.is_empty() {
         println!("Relay: {}", location.hostname);
     }
@@ -123,3 +357,68 @@ async fn print_location(rpc: &mut ManagementServiceClient) -> Result<()> {
     );
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings_with_location(location: types::RelayLocation) -> types::Settings {
+        types::Settings {
+            relay_settings: Some(types::RelaySettings {
+                endpoint: Some(types::relay_settings::Endpoint::Normal(
+                    types::NormalRelaySettings {
+                        location: Some(location),
+                        ..Default::default()
+                    },
+                )),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_missing_selected_relay() {
+        let relay_list = types::RelayList {
+            countries: vec![types::RelayListCountry {
+                code: "se".to_string(),
+                cities: vec![types::RelayListCity {
+                    code: "got".to_string(),
+                    relays: vec![types::Relay {
+                        hostname: "se9-wireguard".to_string(),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        let location = types::RelayLocation {
+            country: "se".to_string(),
+            city: "got".to_string(),
+            hostname: "se9-wireguard".to_string(),
+        };
+        assert_eq!(
+            missing_selected_relay(&settings_with_location(location.clone()), &relay_list),
+            None
+        );
+
+        let city = types::RelayLocation {
+            hostname: String::new(),
+            ..location.clone()
+        };
+        assert_eq!(
+            missing_selected_relay(&settings_with_location(city), &relay_list),
+            None
+        );
+
+        let removed = types::RelayLocation {
+            hostname: "se10-wireguard".to_string(),
+            ..location
+        };
+        assert_eq!(
+            missing_selected_relay(&settings_with_location(removed), &relay_list),
+            Some("se10-wireguard")
+        );
+    }
+}
@@ -2,9 +2,15 @@ use crate::{format::print_keygen_event, new_rpc_client, Command, Error, Result};
 use mullvad_management_interface::types::{self, Timestamp, TunnelOptions};
 use mullvad_types::wireguard::DEFAULT_ROTATION_INTERVAL;
 use std::{convert::TryFrom, time::Duration};
+use talpid_types::net::openvpn::DataCipher;
 
 pub struct Tunnel;
 
+/// Printed after an OpenVPN option is changed, since it only affects new connections.
+const OPENVPN_RECONNECT_NOTICE: &str =
+    "The change applies the next time an OpenVPN tunnel connects. \
+    A connected OpenVPN tunnel reconnects now.";
+
 #[mullvad_management_interface::async_trait]
 impl Command for Tunnel {
     fn name(&self) -> &'static str {
@@ -37,6 +43,8 @@ fn create_wireguard_subcommand() -> clap::App<'static> {
         .about("Manage options for Wireguard tunnels")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_wireguard_mtu_subcommand())
+        .subcommand(create_wireguard_keepalive_subcommand())
+        .subcommand(create_wireguard_rate_limit_subcommand())
         .subcommand(create_wireguard_keys_subcommand());
     #[cfg(windows)]
     {
@@ -53,8 +61,64 @@ fn create_wireguard_mtu_subcommand() -> clap::App<'static> {
         .about("Configure the MTU of the wireguard tunnel")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(clap::App::new("get"))
-        .subcommand(clap::App::new("unset"))
-        .subcommand(clap::App::new("set").arg(clap::Arg::new("mtu").required(true)))
+        .subcommand(
+            clap::App::new("unset")
+                .alias("reset")
+                .about("Use the default MTU"),
+        )
+        .subcommand(
+            clap::App::new("set").arg(
+                clap::Arg::new("mtu")
+                    .help("The MTU, between 1280 and 1420")
+                    .required(true),
+            ),
+        )
+}
+
+fn create_wireguard_keepalive_subcommand() -> clap::App<'static> {
+    clap::App::new("keepalive")
+        .about("Configure the interval of persistent keepalive packets sent to the relay")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(
+            clap::App::new("reset")
+                .alias("unset")
+                .about("Do not send persistent keepalive packets"),
+        )
+        .subcommand(
+            clap::App::new("set").arg(
+                clap::Arg::new("interval")
+                    .help("The interval in seconds, between 10 and 120")
+                    .required(true),
+            ),
+        )
+}
+
+fn create_wireguard_rate_limit_subcommand() -> clap::App<'static> {
+    clap::App::new("rate-limit")
+        .about("Limit the bandwidth of the wireguard tunnel")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(
+            clap::App::new("off")
+                .alias("unset")
+                .about("Do not limit the bandwidth"),
+        )
+        .subcommand(
+            clap::App::new("set")
+                .arg(
+                    clap::Arg::new("up")
+                        .help("The limit of sent traffic in kbit/s, at least 64")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    clap::Arg::new("down")
+                        .help("The limit of received traffic in kbit/s, at least 64")
+                        .required(true)
+                        .index(2),
+                ),
+        )
 }
 
 fn create_wireguard_keys_subcommand() -> clap::App<'static> {
@@ -96,6 +160,7 @@ fn create_openvpn_subcommand() -> clap::App<'static> {
         .about("Manage options for OpenVPN tunnels")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_openvpn_mssfix_subcommand())
+        .subcommand(create_openvpn_data_cipher_subcommand())
 }
 
 fn create_openvpn_mssfix_subcommand() -> clap::App<'static> {
@@ -104,7 +169,33 @@ fn create_openvpn_mssfix_subcommand() -> clap::App<'static> {
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(clap::App::new("get"))
         .subcommand(clap::App::new("unset"))
-        .subcommand(clap::App::new("set").arg(clap::Arg::new("mssfix").required(true)))
+        .subcommand(
+            clap::App::new("set").arg(
+                clap::Arg::new("mssfix")
+                    .help("The mssfix value, or \"default\" to unset it")
+                    .required(true),
+            ),
+        )
+}
+
+fn create_openvpn_data_cipher_subcommand() -> clap::App<'static> {
+    clap::App::new("data-cipher")
+        .about("Configure which data channel cipher OpenVPN must use")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::App::new("get"))
+        .subcommand(
+            clap::App::new("set").arg(
+                clap::Arg::new("cipher")
+                    .help("The cipher to require, or \"default\" to let the server decide")
+                    .required(true)
+                    .possible_values(&[
+                        "aes-256-gcm",
+                        "aes-128-gcm",
+                        "chacha20-poly1305",
+                        "default",
+                    ]),
+            ),
+        )
 }
 
 fn create_ipv6_subcommand() -> clap::App<'static> {
@@ -127,6 +218,9 @@ impl Tunnel {
             Some(("mssfix", mssfix_matches)) => {
                 Self::handle_openvpn_mssfix_cmd(mssfix_matches).await
             }
+            Some(("data-cipher", data_cipher_matches)) => {
+                Self::handle_openvpn_data_cipher_cmd(data_cipher_matches).await
+            }
             _ => unreachable!("unhandled command"),
         }
     }
@@ -140,6 +234,14 @@ impl Tunnel {
         }
     }
 
+    async fn handle_openvpn_data_cipher_cmd(matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            Some(("get", _)) => Self::process_openvpn_data_cipher_get().await,
+            Some(("set", set_matches)) => Self::process_openvpn_data_cipher_set(set_matches).await,
+            _ => unreachable!("unhandled command"),
+        }
+    }
+
     async fn handle_wireguard_cmd(matches: &clap::ArgMatches) -> Result<()> {
         match matches.subcommand() {
             Some(("mtu", matches)) => match matches.subcommand() {
@@ -149,6 +251,20 @@ impl Tunnel {
                 _ => unreachable!("unhandled command"),
             },
 
+            Some(("keepalive", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_wireguard_keepalive_get().await,
+                Some(("set", matches)) => Self::process_wireguard_keepalive_set(matches).await,
+                Some(("reset", _)) => Self::process_wireguard_keepalive_reset().await,
+                _ => unreachable!("unhandled command"),
+            },
+
+            Some(("rate-limit", matches)) => match matches.subcommand() {
+                Some(("get", _)) => Self::process_wireguard_rate_limit_get().await,
+                Some(("set", matches)) => Self::process_wireguard_rate_limit_set(matches).await,
+                Some(("off", _)) => Self::process_wireguard_rate_limit_off().await,
+                _ => unreachable!("unhandled command"),
+            },
+
             Some(("key", matches)) => match matches.subcommand() {
                 Some(("check", _)) => Self::process_wireguard_key_check().await,
                 Some(("regenerate", _)) => Self::process_wireguard_key_generate().await,
@@ -203,6 +319,79 @@ impl Tunnel {
         Ok(())
     }
 
+    async fn process_wireguard_keepalive_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let interval = tunnel_options.wireguard.unwrap().persistent_keepalive;
+        println!(
+            "keepalive: {}",
+            if interval != 0 {
+                format!("{} seconds", interval)
+            } else {
+                "off".to_string()
+            },
+        );
+        Ok(())
+    }
+
+    async fn process_wireguard_keepalive_set(matches: &clap::ArgMatches) -> Result<()> {
+        let interval = matches.value_of_t_or_exit::<u16>("interval");
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_persistent_keepalive(u32::from(interval))
+            .await?;
+        println!("Wireguard persistent keepalive has been updated");
+        Ok(())
+    }
+
+    async fn process_wireguard_keepalive_reset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_persistent_keepalive(0).await?;
+        println!("Wireguard persistent keepalive has been turned off");
+        Ok(())
+    }
+
+    async fn process_wireguard_rate_limit_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        match tunnel_options.wireguard.unwrap().rate_limit {
+            Some(limit) => println!(
+                "rate limit: up {} kbit/s, down {} kbit/s",
+                limit.up_kbps, limit.down_kbps
+            ),
+            None => println!("rate limit: off"),
+        }
+        Ok(())
+    }
+
+    async fn process_wireguard_rate_limit_set(matches: &clap::ArgMatches) -> Result<()> {
+        let limit = types::RateLimit {
+            up_kbps: matches.value_of_t_or_exit::<u32>("up"),
+            down_kbps: matches.value_of_t_or_exit::<u32>("down"),
+        };
+        let mut rpc = new_rpc_client().await?;
+        if let Err(status) = rpc.set_wireguard_rate_limit(limit).await {
+            match status.code() {
+                mullvad_management_interface::Code::InvalidArgument
+                | mullvad_management_interface::Code::Unimplemented => {
+                    eprintln!("{}", status.message());
+                    std::process::exit(1);
+                }
+                _ => return Err(status.into()),
+            }
+        }
+        println!("Wireguard rate limit has been updated");
+        Ok(())
+    }
+
+    async fn process_wireguard_rate_limit_off() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_rate_limit(types::RateLimit {
+            up_kbps: 0,
+            down_kbps: 0,
+        })
+        .await?;
+        println!("Wireguard rate limit has been turned off");
+        Ok(())
+    }
+
     #[cfg(windows)]
     async fn process_wireguard_use_wg_nt_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options().await?;
@@ -337,14 +526,51 @@ impl Tunnel {
         let mut rpc = new_rpc_client().await?;
         rpc.set_openvpn_mssfix(0).await?;
         println!("mssfix parameter has been unset");
+        println!("{}", OPENVPN_RECONNECT_NOTICE);
         Ok(())
     }
 
     async fn process_openvpn_mssfix_set(matches: &clap::ArgMatches) -> Result<()> {
+        if matches.value_of("mssfix") == Some("default") {
+            return Self::process_openvpn_mssfix_unset().await;
+        }
         let new_value = matches.value_of_t_or_exit::<u16>("mssfix");
         let mut rpc = new_rpc_client().await?;
         rpc.set_openvpn_mssfix(new_value as u32).await?;
         println!("mssfix parameter has been updated");
+        println!("{}", OPENVPN_RECONNECT_NOTICE);
+        Ok(())
+    }
+
+    async fn process_openvpn_data_cipher_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let data_cipher = tunnel_options
+            .openvpn
+            .unwrap()
+            .data_cipher
+            .unwrap_or_default();
+        let data_cipher = Option::<DataCipher>::try_from(data_cipher)
+            .map_err(|_| Error::CommandFailed("Unknown OpenVPN data cipher"))?;
+        match data_cipher {
+            Some(data_cipher) => println!("Data cipher: {}", data_cipher),
+            None => println!("Data cipher: chosen by the server"),
+        }
+        Ok(())
+    }
+
+    async fn process_openvpn_data_cipher_set(matches: &clap::ArgMatches) -> Result<()> {
+        let data_cipher = match matches.value_of("cipher").unwrap() {
+            "aes-256-gcm" => Some(DataCipher::Aes256Gcm),
+            "aes-128-gcm" => Some(DataCipher::Aes128Gcm),
+            "chacha20-poly1305" => Some(DataCipher::Chacha20Poly1305),
+            "default" => None,
+            _ => unreachable!("invalid data cipher"),
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_openvpn_data_cipher(types::OpenvpnDataCipher::from(data_cipher))
+            .await?;
+        println!("OpenVPN data cipher has been updated");
+        println!("{}", OPENVPN_RECONNECT_NOTICE);
         Ok(())
     }
 
@@ -1,3 +1,4 @@
+use crate::{Error, Result};
 use mullvad_management_interface::types::{
     error_state::{
         firewall_policy_error::ErrorType as FirewallPolicyErrorType, Cause as ErrorStateCause,
@@ -5,10 +6,25 @@ use mullvad_management_interface::types::{
     },
     tunnel_state,
     tunnel_state::State::*,
-    ErrorState, KeygenEvent, ProxyType, TransportProtocol, TunnelEndpoint, TunnelState, TunnelType,
+    ConnectionAttemptMetrics, ConnectionMetrics, ConnectivityCheckResult, ConnectivityReport,
+    DnsServerHealth, DnsStatus, Duration, ErrorState, KeygenEvent, MaintenanceWindow, ProxyType,
+    Timestamp, TransportProtocol, TunnelEndpoint, TunnelState, TunnelType,
 };
-use mullvad_types::auth_failed::AuthFailed;
-use std::fmt::Write;
+use mullvad_types::{auth_failed::AuthFailed, states::TunnelState as MullvadTunnelState};
+use std::{
+    convert::TryFrom,
+    fmt::Write,
+    io::{self, Write as _},
+};
+
+/// Records printed by commands with machine-readable output. Each record is printed as a single
+/// line of JSON.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum JsonEvent {
+    TunnelState { tunnel_state: MullvadTunnelState },
+    DaemonDisconnected,
+}
 
 pub fn print_keygen_event(key_event: &KeygenEvent) {
     use mullvad_management_interface::types::keygen_event::KeygenEvent as EventType;
@@ -56,6 +72,214 @@ pub fn print_state(state: &TunnelState) {
     }
 }
 
+/// Prints the relays used by the tunnel, including the entry relay if multihop is used.
+pub fn print_relays(state: &TunnelState) {
+    let relay_info = match state.state.as_ref().unwrap() {
+        Connected(tunnel_state::Connected { relay_info })
+        | Connecting(tunnel_state::Connecting { relay_info }) => relay_info.as_ref(),
+        _ => None,
+    };
+    let location = match relay_info.and_then(|relay_info| relay_info.location.as_ref()) {
+        Some(location) => location,
+        None => return,
+    };
+    if !location.entry_hostname.is_empty() {
+        println!("Entry relay: {}", location.entry_hostname);
+        println!("Exit relay: {}", location.hostname);
+    } else if !location.hostname.is_empty() {
+        println!("Relay: {}", location.hostname);
+    }
+    if !location.bridge_hostname.is_empty() {
+        println!("Bridge: {}", location.bridge_hostname);
+    }
+}
+
+/// Formats a maintenance window in local time.
+pub fn format_maintenance_window(window: &MaintenanceWindow) -> String {
+    let format_time = |time: &Option<Timestamp>| {
+        time.as_ref()
+            .map(format_timestamp)
+            .unwrap_or_else(|| "unknown".to_owned())
+    };
+    format!(
+        "{} to {}",
+        format_time(&window.start),
+        format_time(&window.end)
+    )
+}
+
+pub fn format_timestamp(timestamp: &Timestamp) -> String {
+    let ndt = chrono::NaiveDateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32);
+    let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
+    utc.with_timezone(&chrono::Local).to_string()
+}
+
+pub fn print_connection_metrics(metrics: &ConnectionMetrics) {
+    if metrics.attempts.is_empty() {
+        println!("No connection attempts recorded");
+        return;
+    }
+
+    println!("Recent connection attempts:");
+    for attempt in &metrics.attempts {
+        println!("{}", format_connection_attempt(attempt));
+    }
+}
+
+fn format_connection_attempt(attempt: &ConnectionAttemptMetrics) -> String {
+    use mullvad_management_interface::types::connection_attempt_metrics::Outcome;
+
+    let outcome = match Outcome::from_i32(attempt.outcome) {
+        Some(Outcome::Connected) => "connected",
+        Some(Outcome::Failed) => "failed",
+        Some(Outcome::Aborted) => "aborted",
+        None => "unknown",
+    };
+    format!(
+        "\tAttempt {} ({}): total {}, relay selection {}, firewall {}, tunnel setup {}, \
+        connected setup {}",
+        attempt.retry_attempt + 1,
+        outcome,
+        format_phase_duration(&attempt.total),
+        format_phase_duration(&attempt.parameter_generation),
+        format_phase_duration(&attempt.firewall_policy),
+        format_phase_duration(&attempt.tunnel_setup),
+        format_phase_duration(&attempt.connected_setup),
+    )
+}
+
+pub fn print_connectivity_report(report: &ConnectivityReport) {
+    for result in &report.results {
+        println!("{}", format_connectivity_check_result(result));
+    }
+}
+
+fn format_connectivity_check_result(result: &ConnectivityCheckResult) -> String {
+    use mullvad_management_interface::types::connectivity_check_result::Step;
+
+    let step = match Step::from_i32(result.step) {
+        Some(Step::Api) => "API",
+        Some(Step::RelayReachability) => "Relay reachability",
+        Some(Step::Dns) => "DNS",
+        Some(Step::ExitIp) => "Exit IP",
+        None => "Unknown step",
+    };
+    format!(
+        "{}: {}",
+        step,
+        format_check_outcome(result.outcome, &result.latency, &result.detail)
+    )
+}
+
+pub fn print_dns_status(status: &DnsStatus) {
+    use mullvad_management_interface::types::dns_status::Source;
+
+    let source = match Source::from_i32(status.source) {
+        Some(Source::RelayDefault) => "relay default".to_owned(),
+        Some(Source::Custom) => "custom".to_owned(),
+        Some(Source::ContentBlocking) => {
+            let mut blocked = vec![];
+            if let Some(blocking) = &status.blocking {
+                if blocking.block_ads {
+                    blocked.push("ads");
+                }
+                if blocking.block_trackers {
+                    blocked.push("trackers");
+                }
+                if blocking.block_malware {
+                    blocked.push("malware");
+                }
+            }
+            format!("content blocking ({})", blocked.join(", "))
+        }
+        None => "unknown".to_owned(),
+    };
+    println!("Source: {}", source);
+
+    match &status.applied {
+        Some(applied) => {
+            println!(
+                "Applied to {} using {}",
+                applied.interface, applied.mechanism
+            );
+            for health in &status.health {
+                println!("{}", format_dns_server_health(health));
+            }
+        }
+        None => println!("The system DNS settings are not changed"),
+    }
+    print_dns_outside_tunnel_warning(&status.outside_tunnel);
+}
+
+pub fn print_dns_outside_tunnel_warning(outside_tunnel: &[String]) {
+    if !outside_tunnel.is_empty() {
+        println!(
+            "Warning: queries to {} are sent outside the tunnel",
+            outside_tunnel.join(", ")
+        );
+    }
+}
+
+fn format_dns_server_health(health: &DnsServerHealth) -> String {
+    format!(
+        "{}: {}",
+        health.server,
+        format_check_outcome(health.outcome, &health.latency, &health.detail)
+    )
+}
+
+fn format_check_outcome(outcome: i32, latency: &Option<Duration>, detail: &str) -> String {
+    use mullvad_management_interface::types::connectivity_check_result::Outcome;
+
+    let mut line = match Outcome::from_i32(outcome) {
+        Some(Outcome::Passed) => "passed",
+        Some(Outcome::Failed) => "FAILED",
+        Some(Outcome::Skipped) => "skipped",
+        None => "unknown",
+    }
+    .to_owned();
+    if latency.is_some() {
+        let _ = write!(&mut line, " ({})", format_phase_duration(latency));
+    }
+    if !detail.is_empty() {
+        let _ = write!(&mut line, " - {}", detail);
+    }
+    line
+}
+
+fn format_phase_duration(duration: &Option<Duration>) -> String {
+    match duration {
+        Some(duration) => format!(
+            "{} ms",
+            duration.seconds * 1000 + i64::from(duration.nanos / 1_000_000)
+        ),
+        None => "-".to_owned(),
+    }
+}
+
+/// Prints the tunnel state as a single line of JSON.
+pub fn print_state_json(state: &TunnelState) -> Result<()> {
+    let state = MullvadTunnelState::try_from(state.clone())
+        .map_err(|_| Error::CommandFailed("Received an invalid tunnel state"))?;
+    print_json_event(&JsonEvent::TunnelState {
+        tunnel_state: state,
+    })
+}
+
+/// Prints the record marking the end of a JSON event stream.
+pub fn print_daemon_disconnected_json() -> Result<()> {
+    print_json_event(&JsonEvent::DaemonDisconnected)
+}
+
+fn print_json_event(event: &JsonEvent) -> Result<()> {
+    let line = serde_json::to_string(event)
+        .map_err(|_| Error::CommandFailed("Failed to serialize event"))?;
+    let mut stdout = io::stdout();
+    writeln!(stdout, "{}", line)
+        .and_then(|_| stdout.flush())
+        .map_err(|_| Error::CommandFailed("Failed to write to STDOUT"))
+}
+
 fn format_endpoint(endpoint: &TunnelEndpoint) -> String {
     let tunnel_type = TunnelType::from_i32(endpoint.tunnel_type).expect("invalid tunnel protocol");
     let mut out = format!(
@@ -199,3 +423,179 @@ fn format_protocol(protocol: TransportProtocol) -> &'static str {
         TransportProtocol::Tcp => "TCP",
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{format_connection_attempt, format_connectivity_check_result, JsonEvent};
+    use mullvad_management_interface::types::{
+        connection_attempt_metrics::Outcome, connectivity_check_result, ConnectionAttemptMetrics,
+        ConnectivityCheckResult, Duration,
+    };
+    use mullvad_types::{location::GeoIpLocation, states::TunnelState};
+    use std::net::Ipv4Addr;
+    use talpid_types::{
+        net::{Endpoint, TransportProtocol, TunnelEndpoint, TunnelType},
+        tunnel::{ErrorState, ErrorStateCause},
+    };
+
+    fn to_json(tunnel_state: TunnelState) -> String {
+        serde_json::to_string(&JsonEvent::TunnelState { tunnel_state }).unwrap()
+    }
+
+    fn endpoint() -> TunnelEndpoint {
+        TunnelEndpoint {
+            endpoint: Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 51820, TransportProtocol::Udp),
+            tunnel_type: TunnelType::Wireguard,
+            proxy: None,
+            entry_endpoint: Some(Endpoint::new(
+                Ipv4Addr::new(5, 6, 7, 8),
+                443,
+                TransportProtocol::Udp,
+            )),
+        }
+    }
+
+    #[test]
+    fn test_disconnected_json() {
+        assert_eq!(
+            to_json(TunnelState::Disconnected),
+            r#"{"event":"tunnel-state","tunnel_state":{"state":"disconnected"}}"#
+        );
+    }
+
+    #[test]
+    fn test_connecting_json() {
+        assert_eq!(
+            to_json(TunnelState::Connecting {
+                endpoint: endpoint(),
+                location: None,
+            }),
+            concat!(
+                r#"{"event":"tunnel-state","tunnel_state":{"state":"connecting","details":{"#,
+                r#""endpoint":{"address":"1.2.3.4:51820","protocol":"udp","tunnel_type":"wireguard","#,
+                r#""proxy":null,"entry_endpoint":{"address":"5.6.7.8:443","protocol":"udp"}},"#,
+                r#""location":null}}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_connected_json() {
+        let location = GeoIpLocation {
+            ipv4: None,
+            ipv6: None,
+            country: "Sweden".to_owned(),
+            city: Some("Gothenburg".to_owned()),
+            latitude: 57.70887,
+            longitude: 11.97456,
+            mullvad_exit_ip: true,
+            hostname: Some("se-got-wg-001".to_owned()),
+            bridge_hostname: None,
+            entry_hostname: Some("se-sto-wg-002".to_owned()),
+        };
+        let json = to_json(TunnelState::Connected {
+            endpoint: endpoint(),
+            location: Some(location),
+        });
+        assert!(json.starts_with(
+            r#"{"event":"tunnel-state","tunnel_state":{"state":"connected","details":{"#
+        ));
+        assert!(json.contains(r#""hostname":"se-got-wg-001""#));
+        assert!(json.contains(r#""entry_hostname":"se-sto-wg-002""#));
+        assert!(json.contains(r#""entry_endpoint":{"address":"5.6.7.8:443","protocol":"udp"}"#));
+    }
+
+    #[test]
+    fn test_error_json() {
+        assert_eq!(
+            to_json(TunnelState::Error(ErrorState::new(
+                ErrorStateCause::IsOffline,
+                None
+            ))),
+            concat!(
+                r#"{"event":"tunnel-state","tunnel_state":{"state":"error","details":{"#,
+                r#""cause":{"reason":"is_offline"},"block_failure":null}}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_daemon_disconnected_json() {
+        assert_eq!(
+            serde_json::to_string(&JsonEvent::DaemonDisconnected).unwrap(),
+            r#"{"event":"daemon-disconnected"}"#
+        );
+    }
+
+    #[test]
+    fn test_format_connection_attempt() {
+        let attempt = ConnectionAttemptMetrics {
+            retry_attempt: 1,
+            parameter_generation: Some(Duration {
+                seconds: 0,
+                nanos: 2_500_000,
+            }),
+            firewall_policy: Some(Duration {
+                seconds: 0,
+                nanos: 10_000_000,
+            }),
+            tunnel_setup: None,
+            connected_setup: None,
+            total: Some(Duration {
+                seconds: 3,
+                nanos: 40_000_000,
+            }),
+            outcome: i32::from(Outcome::Failed),
+        };
+        assert_eq!(
+            format_connection_attempt(&attempt),
+            "\tAttempt 2 (failed): total 3040 ms, relay selection 2 ms, firewall 10 ms, \
+            tunnel setup -, connected setup -"
+        );
+    }
+
+    #[test]
+    fn test_format_connectivity_check_result() {
+        let passed = ConnectivityCheckResult {
+            step: i32::from(connectivity_check_result::Step::Api),
+            outcome: i32::from(connectivity_check_result::Outcome::Passed),
+            latency: Some(Duration {
+                seconds: 0,
+                nanos: 85_000_000,
+            }),
+            detail: String::new(),
+        };
+        assert_eq!(
+            format_connectivity_check_result(&passed),
+            "API: passed (85 ms)"
+        );
+
+        let skipped = ConnectivityCheckResult {
+            step: i32::from(connectivity_check_result::Step::ExitIp),
+            outcome: i32::from(connectivity_check_result::Outcome::Skipped),
+            latency: None,
+            detail: "not connected to a relay".to_owned(),
+        };
+        assert_eq!(
+            format_connectivity_check_result(&skipped),
+            "Exit IP: skipped - not connected to a relay"
+        );
+    }
+
+    #[test]
+    fn test_format_dns_server_health() {
+        let failed = DnsServerHealth {
+            server: "10.64.0.1".to_owned(),
+            outcome: i32::from(connectivity_check_result::Outcome::Failed),
+            latency: Some(Duration {
+                seconds: 2,
+                nanos: 0,
+            }),
+            detail: "No response within 2 seconds".to_owned(),
+        };
+        assert_eq!(
+            format_dns_server_health(&failed),
+            "10.64.0.1: FAILED (2000 ms) - No response within 2 seconds"
+        );
+    }
+}
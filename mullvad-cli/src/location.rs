@@ -1,7 +1,12 @@
 use mullvad_management_interface::types::RelayLocation;
 
 pub fn get_subcommand() -> clap::App<'static> {
-    clap::App::new("location")
+    get_subcommand_with_name("location")
+}
+
+/// Returns a subcommand that takes the same location arguments as [`get_subcommand`].
+pub fn get_subcommand_with_name(name: &'static str) -> clap::App<'static> {
+    clap::App::new(name)
         .arg(
             clap::Arg::new("country")
                 .help("The two letter country code, or 'any' for no preference.")
@@ -3,11 +3,15 @@
 use clap::{crate_authors, crate_description};
 #[cfg(all(unix, not(target_os = "android")))]
 use clap_complete::{generator::generate_to, Shell};
-use mullvad_management_interface::async_trait;
-use std::{collections::HashMap, io};
+use mullvad_management_interface::{async_trait, Code, ManagementServiceClient};
+use std::{
+    collections::HashMap,
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use talpid_types::ErrorExt;
 
-pub use mullvad_management_interface::{self, new_rpc_client};
+pub use mullvad_management_interface;
 
 mod cmds;
 mod format;
@@ -46,6 +50,22 @@ pub enum Error {
     #[error(display = "Failed to listen for status updates")]
     StatusListenerFailed,
 
+    #[error(display = "Timed out waiting for the tunnel to connect")]
+    Timeout,
+
+    #[error(
+        display = "The daemon (version {}) uses management interface version {}, but this \
+                   CLI requires version {}. Use a CLI from the same release as the daemon",
+        daemon_version,
+        daemon_interface,
+        expected_interface
+    )]
+    IncompatibleInterface {
+        daemon_version: String,
+        daemon_interface: u32,
+        expected_interface: u32,
+    },
+
     //#[cfg(all(unix, not(target_os = "android"))
     #[error(display = "Failed to generate shell completions")]
     CompletionsError(#[error(source, no_from)] io::Error),
@@ -74,6 +94,43 @@ async fn main() {
     std::process::exit(exit_code);
 }
 
+/// Set once the interface version of the daemon has been checked.
+static INTERFACE_CHECKED: AtomicBool = AtomicBool::new(false);
+
+/// Connects to the daemon. The first time, this also checks that the daemon uses a compatible
+/// version of the management interface.
+pub async fn new_rpc_client() -> Result<ManagementServiceClient> {
+    let mut rpc = mullvad_management_interface::new_rpc_client().await?;
+    if !INTERFACE_CHECKED.load(Ordering::SeqCst) {
+        check_interface_version(&mut rpc).await?;
+        INTERFACE_CHECKED.store(true, Ordering::SeqCst);
+    }
+    Ok(rpc)
+}
+
+async fn check_interface_version(rpc: &mut ManagementServiceClient) -> Result<()> {
+    match rpc.get_interface_version(()).await {
+        Ok(version) => {
+            let version = version.into_inner();
+            if mullvad_management_interface::is_compatible_interface(&version) {
+                Ok(())
+            } else {
+                Err(Error::IncompatibleInterface {
+                    daemon_version: version.daemon_version,
+                    daemon_interface: version.major,
+                    expected_interface: mullvad_management_interface::INTERFACE_VERSION_MAJOR,
+                })
+            }
+        }
+        // Daemons older than the interface version itself do not implement the call
+        Err(status) if status.code() == Code::Unimplemented => Ok(()),
+        Err(status) => Err(Error::RpcFailedExt(
+            "Failed to get the management interface version",
+            status,
+        )),
+    }
+}
+
 async fn run() -> Result<()> {
     env_logger::init();
 
@@ -1,19 +1,27 @@
-use crate::{Error, Result};
+use crate::{format, Error, Result};
 use futures::{
     channel::{mpsc, mpsc::Receiver},
-    SinkExt,
+    SinkExt, StreamExt,
 };
 use mullvad_management_interface::{
-    types::{daemon_event::Event as EventType, TunnelState},
+    types::{
+        self, daemon_event::Event as EventType, events_filter::Category, tunnel_state::State,
+        TunnelState,
+    },
     ManagementServiceClient,
 };
+use std::time::Duration;
 
 // Spawns a new task that listens for tunnel state changes and forwards it through the returned
 // channel. Panics if called from outside of the Tokio runtime.
 pub fn state_listen(mut rpc: ManagementServiceClient) -> Receiver<Result<TunnelState>> {
     let (mut sender, receiver) = mpsc::channel::<Result<TunnelState>>(1);
     tokio::spawn(async move {
-        match rpc.events_listen(()).await {
+        let filter = types::EventsFilter {
+            categories: vec![i32::from(Category::TunnelState)],
+            snapshot: false,
+        };
+        match rpc.events_listen(filter).await {
             Ok(events) => {
                 let mut events = events.into_inner();
                 loop {
@@ -39,3 +47,78 @@ pub fn state_listen(mut rpc: ManagementServiceClient) -> Receiver<Result<TunnelS
 
     receiver
 }
+
+/// How `wait_for_connected` reports tunnel states and how long it waits.
+pub struct WaitOptions {
+    /// Print tunnel states as JSON instead of in human readable form.
+    pub json: bool,
+    pub timeout: Option<Duration>,
+}
+
+impl WaitOptions {
+    /// Reads the options from the `json` and `timeout` arguments.
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        let timeout = if matches.is_present("timeout") {
+            Some(Duration::from_secs(
+                matches.value_of_t_or_exit::<u64>("timeout"),
+            ))
+        } else {
+            None
+        };
+        WaitOptions {
+            json: matches.is_present("json"),
+            timeout,
+        }
+    }
+}
+
+/// Prints every new tunnel state from `receiver` until the tunnel is connected. Fails if the
+/// tunnel enters the error state, or if the timeout elapses first.
+pub async fn wait_for_connected(
+    receiver: Receiver<Result<TunnelState>>,
+    command: &'static str,
+    options: &WaitOptions,
+) -> Result<()> {
+    let mut last_state = None;
+    let wait = print_until_connected(receiver, command, options.json, &mut last_state);
+    let result = match options.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, wait)
+            .await
+            .unwrap_or(Err(Error::Timeout)),
+        None => wait.await,
+    };
+    if !options.json && matches!(result, Err(Error::Timeout)) {
+        match last_state {
+            Some(state) => {
+                print!("Last observed state: ");
+                format::print_state(&state);
+            }
+            None => println!("The tunnel state did not change before the timeout"),
+        }
+    }
+    result
+}
+
+async fn print_until_connected(
+    mut receiver: Receiver<Result<TunnelState>>,
+    command: &'static str,
+    json: bool,
+    last_state: &mut Option<TunnelState>,
+) -> Result<()> {
+    while let Some(state) = receiver.next().await {
+        let state = state?;
+        if json {
+            format::print_state_json(&state)?;
+        } else {
+            format::print_state(&state);
+        }
+        let tunnel_state = state.state.clone().unwrap();
+        *last_state = Some(state);
+        match tunnel_state {
+            State::Connected(_) => return Ok(()),
+            State::Error(_) => return Err(Error::CommandFailed(command)),
+            _ => {}
+        }
+    }
+    Err(Error::StatusListenerFailed)
+}
@@ -1,70 +1,143 @@
+use crate::DaemonEventSender;
 use chrono::{DateTime, Utc};
-use futures::future::{abortable, AbortHandle};
+use futures::{channel::mpsc, future::BoxFuture, FutureExt, StreamExt};
 use mullvad_rpc::{
     availability::ApiAvailabilityHandle,
     rest::{self, Error as RestError, MullvadRestHandle},
     AccountsProxy,
 };
-use mullvad_types::account::{AccountToken, VoucherSubmission};
-use std::{future::Future, time::Duration};
-use talpid_core::future_retry::{
-    constant_interval, retry_future, retry_future_n, ExponentialBackoff, Jittered,
+use mullvad_types::account::{self, AccountExpiry, AccountToken, VoucherSubmission};
+use std::{future::Future, sync::Arc, time::Duration};
+use talpid_core::{
+    future_retry::{ExponentialBackoff, Jittered},
+    mpsc::Sender,
 };
 
-const RETRY_ACTION_INTERVAL: Duration = Duration::ZERO;
-const RETRY_ACTION_MAX_RETRIES: usize = 2;
-
 const RETRY_EXPIRY_CHECK_INTERVAL_INITIAL: Duration = Duration::from_secs(4);
 const RETRY_EXPIRY_CHECK_INTERVAL_FACTOR: u32 = 5;
 const RETRY_EXPIRY_CHECK_INTERVAL_MAX: Duration = Duration::from_secs(24 * 60 * 60);
 
+/// How often the expiry is refreshed while more than a week remains.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+/// How often the expiry is refreshed while less than a week remains.
+const REFRESH_INTERVAL_NEAR_EXPIRY: Duration = Duration::from_secs(60 * 60);
+/// How often the expiry is refreshed while less than a day remains.
+const REFRESH_INTERVAL_IMMINENT_EXPIRY: Duration = Duration::from_secs(15 * 60);
+/// How often the expiry of an expired account is refreshed, to notice when time is added.
+const REFRESH_INTERVAL_EXPIRED: Duration = Duration::from_secs(60 * 60);
+/// Shortest time between two refreshes that are not requested explicitly.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct Account(());
 
+/// The account operations of the API. This is implemented by [`AccountsProxy`], and lets the
+/// account logic be tested without the API. Retrying requests after network errors is up to the
+/// implementation, since only it knows the method of each request.
+pub(crate) trait AccountApi: Send + Sync + 'static {
+    fn create_account_detailed(
+        &self,
+    ) -> BoxFuture<'static, Result<(AccountToken, DateTime<Utc>), RestError>>;
+
+    fn get_expiry(
+        &self,
+        account: AccountToken,
+    ) -> BoxFuture<'static, Result<DateTime<Utc>, RestError>>;
+
+    fn get_www_auth_token(
+        &self,
+        account: AccountToken,
+    ) -> BoxFuture<'static, Result<String, RestError>>;
+
+    fn submit_voucher(
+        &self,
+        account: AccountToken,
+        voucher: String,
+    ) -> BoxFuture<'static, Result<VoucherSubmission, RestError>>;
+}
+
+impl AccountApi for AccountsProxy {
+    fn create_account_detailed(
+        &self,
+    ) -> BoxFuture<'static, Result<(AccountToken, DateTime<Utc>), RestError>> {
+        AccountsProxy::create_account_detailed(&mut self.clone()).boxed()
+    }
+
+    fn get_expiry(
+        &self,
+        account: AccountToken,
+    ) -> BoxFuture<'static, Result<DateTime<Utc>, RestError>> {
+        AccountsProxy::get_expiry(self, account).boxed()
+    }
+
+    fn get_www_auth_token(
+        &self,
+        account: AccountToken,
+    ) -> BoxFuture<'static, Result<String, RestError>> {
+        AccountsProxy::get_www_auth_token(self, account).boxed()
+    }
+
+    fn submit_voucher(
+        &self,
+        account: AccountToken,
+        voucher: String,
+    ) -> BoxFuture<'static, Result<VoucherSubmission, RestError>> {
+        AccountsProxy::submit_voucher(&mut self.clone(), account, voucher).boxed()
+    }
+}
+
+/// A new account expiry fetched by the background refresh.
+pub(crate) struct AccountExpiryUpdate {
+    pub account_token: AccountToken,
+    pub expiry: AccountExpiry,
+}
+
+enum ExpiryMonitorCommand {
+    SetAccount(Option<AccountToken>),
+    Refresh,
+    /// An expiry that was fetched outside of the monitor. If it belongs to an account that is not
+    /// the current one, it is used if that account is set next.
+    Update(AccountToken, DateTime<Utc>),
+}
+
 #[derive(Clone)]
 pub struct AccountHandle {
     api_availability: ApiAvailabilityHandle,
-    initial_check_abort_handle: AbortHandle,
-    proxy: AccountsProxy,
+    monitor_tx: mpsc::UnboundedSender<ExpiryMonitorCommand>,
+    api: Arc<dyn AccountApi>,
 }
 
 impl AccountHandle {
-    pub fn create_account(&self) -> impl Future<Output = Result<AccountToken, rest::Error>> {
-        let mut proxy = self.proxy.clone();
-        let api_handle = self.api_availability.clone();
-        retry_future_n(
-            move || proxy.create_account(),
-            move |result| Self::should_retry(result, &api_handle),
-            constant_interval(RETRY_ACTION_INTERVAL),
-            RETRY_ACTION_MAX_RETRIES,
-        )
+    /// Creates a new account and returns its token along with its initial expiry date. The expiry
+    /// is passed on to the expiry monitor, so it is not fetched again once the account is set.
+    pub fn create_account_detailed(
+        &self,
+    ) -> impl Future<Output = Result<(AccountToken, DateTime<Utc>), rest::Error>> {
+        let response = self.api.create_account_detailed();
+        let monitor_tx = self.monitor_tx.clone();
+        async move {
+            let (token, expiry) = response.await?;
+            if monitor_tx
+                .unbounded_send(ExpiryMonitorCommand::Update(token.clone(), expiry))
+                .is_err()
+            {
+                log::error!("The account expiry monitor is not running");
+            }
+            Ok((token, expiry))
+        }
     }
 
     pub fn get_www_auth_token(
         &self,
         account: AccountToken,
     ) -> impl Future<Output = Result<String, rest::Error>> {
-        let proxy = self.proxy.clone();
-        let api_handle = self.api_availability.clone();
-        retry_future_n(
-            move || proxy.get_www_auth_token(account.clone()),
-            move |result| Self::should_retry(result, &api_handle),
-            constant_interval(RETRY_ACTION_INTERVAL),
-            RETRY_ACTION_MAX_RETRIES,
-        )
+        self.api.get_www_auth_token(account)
     }
 
     pub async fn check_expiry(&self, token: AccountToken) -> Result<DateTime<Utc>, rest::Error> {
-        let proxy = self.proxy.clone();
-        let api_handle = self.api_availability.clone();
-        let result = retry_future_n(
-            move || proxy.get_expiry(token.clone()),
-            move |result| Self::should_retry(result, &api_handle),
-            constant_interval(RETRY_ACTION_INTERVAL),
-            RETRY_ACTION_MAX_RETRIES,
-        )
-        .await;
-        if handle_expiry_result_inner(&result, &self.api_availability) {
-            self.initial_check_abort_handle.abort();
+        let result = self.api.get_expiry(token.clone()).await;
+        handle_expiry_result_inner(&result, &self.api_availability);
+        if let Ok(expiry) = result {
+            self.send_monitor_command(ExpiryMonitorCommand::Update(token, expiry));
         }
         result
     }
@@ -74,100 +147,816 @@ impl AccountHandle {
         account_token: AccountToken,
         voucher: String,
     ) -> Result<VoucherSubmission, rest::Error> {
-        let mut proxy = self.proxy.clone();
-        let api_handle = self.api_availability.clone();
-        let result = retry_future_n(
-            move || proxy.submit_voucher(account_token.clone(), voucher.clone()),
-            move |result| Self::should_retry(result, &api_handle),
-            constant_interval(RETRY_ACTION_INTERVAL),
-            RETRY_ACTION_MAX_RETRIES,
-        )
-        .await;
-        if result.is_ok() {
-            self.initial_check_abort_handle.abort();
+        let result = self
+            .api
+            .submit_voucher(account_token.clone(), voucher)
+            .await;
+        if let Ok(ref submission) = result {
             self.api_availability.resume_background();
+            self.send_monitor_command(ExpiryMonitorCommand::Update(
+                account_token,
+                submission.new_expiry,
+            ));
         }
         result
     }
 
-    fn should_retry<T>(result: &Result<T, RestError>, api_handle: &ApiAvailabilityHandle) -> bool {
-        match result {
-            Err(error) if error.is_network_error() => !api_handle.get_state().is_offline(),
-            _ => false,
+    /// Sets the account whose expiry is refreshed in the background. Refreshing stops if `token`
+    /// is `None`.
+    pub fn set_account(&self, token: Option<AccountToken>) {
+        self.send_monitor_command(ExpiryMonitorCommand::SetAccount(token));
+    }
+
+    /// Refreshes the expiry of the current account immediately.
+    pub fn refresh_expiry(&self) {
+        self.send_monitor_command(ExpiryMonitorCommand::Refresh);
+    }
+
+    fn send_monitor_command(&self, command: ExpiryMonitorCommand) {
+        if self.monitor_tx.unbounded_send(command).is_err() {
+            log::error!("The account expiry monitor is not running");
         }
     }
 }
 
 impl Account {
-    pub fn new(
+    pub(crate) fn new(
         runtime: tokio::runtime::Handle,
         rpc_handle: MullvadRestHandle,
         token: Option<String>,
         api_availability: ApiAvailabilityHandle,
+        update_sender: DaemonEventSender<AccountExpiryUpdate>,
+    ) -> AccountHandle {
+        Self::with_api(
+            runtime,
+            Arc::new(AccountsProxy::new(rpc_handle)),
+            token,
+            api_availability,
+            update_sender,
+        )
+    }
+
+    fn with_api(
+        runtime: tokio::runtime::Handle,
+        api: Arc<dyn AccountApi>,
+        token: Option<String>,
+        api_availability: ApiAvailabilityHandle,
+        update_sender: DaemonEventSender<AccountExpiryUpdate>,
     ) -> AccountHandle {
-        let accounts_proxy = AccountsProxy::new(rpc_handle);
         api_availability.pause_background();
 
-        let api_availability_copy = api_availability.clone();
-        let accounts_proxy_copy = accounts_proxy.clone();
+        let (monitor_tx, monitor_rx) = mpsc::unbounded();
+        let monitor = ExpiryMonitor {
+            api: api.clone(),
+            api_availability: api_availability.clone(),
+            token,
+            state: ExpiryState::new(),
+            other_expiry: None,
+            update_sender,
+        };
+        runtime.spawn(monitor.run(monitor_rx));
 
-        let (future, initial_check_abort_handle) = abortable(async move {
-            let token = if let Some(token) = token {
-                token
-            } else {
-                api_availability.pause_background();
-                return;
+        AccountHandle {
+            api_availability,
+            monitor_tx,
+            api,
+        }
+    }
+}
+
+/// Refreshes the expiry of the current account in the background, and sends an update to the
+/// daemon whenever it changes.
+struct ExpiryMonitor {
+    api: Arc<dyn AccountApi>,
+    api_availability: ApiAvailabilityHandle,
+    token: Option<AccountToken>,
+    state: ExpiryState,
+    /// The most recent expiry received for an account other than the current one.
+    other_expiry: Option<(AccountToken, DateTime<Utc>)>,
+    update_sender: DaemonEventSender<AccountExpiryUpdate>,
+}
+
+impl ExpiryMonitor {
+    async fn run(mut self, commands: mpsc::UnboundedReceiver<ExpiryMonitorCommand>) {
+        let mut commands = commands.fuse();
+        let mut delay = Duration::ZERO;
+
+        loop {
+            let token = match self.token.clone() {
+                Some(token) => token,
+                None => match commands.next().await {
+                    Some(command) => {
+                        delay = self.handle_command(command).unwrap_or(delay);
+                        continue;
+                    }
+                    None => return,
+                },
             };
 
-            let retry_strategy = Jittered::jitter(
-                ExponentialBackoff::new(
-                    RETRY_EXPIRY_CHECK_INTERVAL_INITIAL,
-                    RETRY_EXPIRY_CHECK_INTERVAL_FACTOR,
-                )
-                .max_delay(RETRY_EXPIRY_CHECK_INTERVAL_MAX),
-            );
-            let future_generator = move || {
-                let wait_online = api_availability.wait_online();
-                let expiry_fut = accounts_proxy.get_expiry(token.clone());
-                let api_availability_copy = api_availability.clone();
-                async move {
+            futures::select! {
+                _ = Box::pin(tokio::time::sleep(delay)).fuse() => (),
+                command = commands.next() => match command {
+                    Some(command) => {
+                        delay = self.handle_command(command).unwrap_or(delay);
+                        continue;
+                    }
+                    None => return,
+                },
+            }
+
+            let wait_online = self.api_availability.wait_online();
+            let fetch = self.api.get_expiry(token.clone());
+            let result = futures::select! {
+                result = Box::pin(async move {
                     let _ = wait_online.await;
-                    handle_expiry_result_inner(&expiry_fut.await, &api_availability_copy)
-                }
+                    fetch.await
+                }).fuse() => result,
+                command = commands.next() => match command {
+                    Some(command) => {
+                        delay = self.handle_command(command).unwrap_or(delay);
+                        continue;
+                    }
+                    None => return,
+                },
             };
-            let should_retry = move |state_was_updated: &bool| -> bool { !*state_was_updated };
-            retry_future(future_generator, should_retry, retry_strategy).await;
+
+            if self.update_sender.is_closed() {
+                return;
+            }
+            handle_expiry_result_inner(&result, &self.api_availability);
+            let outcome = self.state.handle_result(result, Utc::now());
+            if let Some(expiry) = outcome.changed_expiry {
+                self.send_update(token.clone(), expiry);
+            }
+            match outcome.next_refresh {
+                Some(next_refresh) => delay = next_refresh,
+                None => {
+                    log::warn!("Not refreshing the account expiry since the account is invalid");
+                    self.token = None;
+                }
+            }
+        }
+    }
+
+    /// Returns the time to wait before the next refresh, or `None` if it is unchanged.
+    fn handle_command(&mut self, command: ExpiryMonitorCommand) -> Option<Duration> {
+        match command {
+            ExpiryMonitorCommand::SetAccount(token) => {
+                if token.is_none() {
+                    self.api_availability.pause_background();
+                }
+                self.state = ExpiryState::new();
+                let known_expiry = match (self.other_expiry.take(), &token) {
+                    (Some((other_token, expiry)), Some(token))
+                        if account::secure_eq(&other_token, token) =>
+                    {
+                        Some(expiry)
+                    }
+                    _ => None,
+                };
+                self.token = token;
+                match (known_expiry, self.token.clone()) {
+                    (Some(expiry), Some(token)) => self.handle_known_expiry(token, expiry),
+                    _ => Some(Duration::ZERO),
+                }
+            }
+            ExpiryMonitorCommand::Refresh => Some(Duration::ZERO),
+            ExpiryMonitorCommand::Update(token, expiry) => {
+                if !account::secure_eq_opt(self.token.as_deref(), Some(&token)) {
+                    // The expiry of some other account was checked, such as a new account that
+                    // is about to be set.
+                    self.other_expiry = Some((token, expiry));
+                    return None;
+                }
+                self.handle_known_expiry(token, expiry)
+            }
+        }
+    }
+
+    /// Uses an expiry of the current account that was fetched outside of the monitor. Returns the
+    /// time until the next refresh.
+    fn handle_known_expiry(
+        &mut self,
+        token: AccountToken,
+        expiry: DateTime<Utc>,
+    ) -> Option<Duration> {
+        let result = Ok(expiry);
+        handle_expiry_result_inner(&result, &self.api_availability);
+        let outcome = self.state.handle_result(result, Utc::now());
+        if let Some(expiry) = outcome.changed_expiry {
+            self.send_update(token, expiry);
+        }
+        outcome.next_refresh
+    }
+
+    fn send_update(&self, account_token: AccountToken, expiry: AccountExpiry) {
+        let _ = self.update_sender.send(AccountExpiryUpdate {
+            account_token,
+            expiry,
         });
-        runtime.spawn(future);
+    }
+}
 
-        AccountHandle {
-            api_availability: api_availability_copy,
-            initial_check_abort_handle,
-            proxy: accounts_proxy_copy,
+/// The last known expiry of an account, and when to fetch it again.
+struct ExpiryState {
+    last_expiry: Option<DateTime<Utc>>,
+    retry_strategy: Jittered<ExponentialBackoff>,
+}
+
+#[derive(Debug, PartialEq)]
+struct ExpiryOutcome {
+    /// Set if the expiry is different from the last known one.
+    changed_expiry: Option<AccountExpiry>,
+    /// Time until the next refresh. `None` if the account is invalid and should not be refreshed.
+    next_refresh: Option<Duration>,
+}
+
+impl ExpiryState {
+    fn new() -> Self {
+        ExpiryState {
+            last_expiry: None,
+            retry_strategy: Self::retry_strategy(),
+        }
+    }
+
+    fn retry_strategy() -> Jittered<ExponentialBackoff> {
+        Jittered::jitter(
+            ExponentialBackoff::new(
+                RETRY_EXPIRY_CHECK_INTERVAL_INITIAL,
+                RETRY_EXPIRY_CHECK_INTERVAL_FACTOR,
+            )
+            .max_delay(RETRY_EXPIRY_CHECK_INTERVAL_MAX),
+        )
+    }
+
+    fn handle_result(
+        &mut self,
+        result: Result<DateTime<Utc>, RestError>,
+        now: DateTime<Utc>,
+    ) -> ExpiryOutcome {
+        match result {
+            Ok(expiry) => {
+                self.retry_strategy = Self::retry_strategy();
+                let changed_expiry = if self.last_expiry != Some(expiry) {
+                    self.last_expiry = Some(expiry);
+                    Some(AccountExpiry {
+                        expiry,
+                        fetched_at: now,
+                    })
+                } else {
+                    None
+                };
+                ExpiryOutcome {
+                    changed_expiry,
+                    next_refresh: Some(next_refresh_interval(expiry, now)),
+                }
+            }
+            Err(RestError::ApiError(_status, ref code))
+                if code == mullvad_rpc::INVALID_ACCOUNT || code == mullvad_rpc::INVALID_AUTH =>
+            {
+                ExpiryOutcome {
+                    changed_expiry: None,
+                    next_refresh: None,
+                }
+            }
+            Err(error) => {
+                log::debug!("Failed to refresh the account expiry: {}", error);
+                ExpiryOutcome {
+                    changed_expiry: None,
+                    next_refresh: self.retry_strategy.next(),
+                }
+            }
         }
     }
 }
 
+/// Returns how long to wait before refreshing an expiry. Refreshes are more frequent the closer
+/// the account is to expiring, and one is always done shortly after it expires.
+fn next_refresh_interval(expiry: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+    let remaining = expiry.signed_duration_since(now);
+    if remaining <= chrono::Duration::zero() {
+        return REFRESH_INTERVAL_EXPIRED;
+    }
+
+    let interval = if remaining <= chrono::Duration::days(1) {
+        REFRESH_INTERVAL_IMMINENT_EXPIRY
+    } else if remaining <= chrono::Duration::days(7) {
+        REFRESH_INTERVAL_NEAR_EXPIRY
+    } else {
+        REFRESH_INTERVAL
+    };
+    let until_expiry = remaining
+        .to_std()
+        .unwrap_or(Duration::ZERO)
+        .max(MIN_REFRESH_INTERVAL);
+    interval.min(until_expiry)
+}
+
 fn handle_expiry_result_inner(
     result: &Result<chrono::DateTime<chrono::Utc>, mullvad_rpc::rest::Error>,
     api_availability: &ApiAvailabilityHandle,
-) -> bool {
+) {
     match result {
         Ok(_expiry) if *_expiry >= chrono::Utc::now() => {
             api_availability.resume_background();
-            true
         }
         Ok(_expiry) => {
             api_availability.pause_background();
-            true
         }
         Err(mullvad_rpc::rest::Error::ApiError(_status, code)) => {
             if code == mullvad_rpc::INVALID_ACCOUNT || code == mullvad_rpc::INVALID_AUTH {
                 api_availability.pause_background();
-                return true;
             }
-            false
         }
-        Err(_) => false,
+        Err(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::InternalDaemonEvent;
+    use mullvad_rpc::availability::ApiAvailability;
+    use std::{collections::VecDeque, sync::Mutex};
+
+    fn now() -> DateTime<Utc> {
+        "2022-06-01T00:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn test_refresh_interval() {
+        let now = now();
+        let in_hours = |hours| now + chrono::Duration::hours(hours);
+
+        assert_eq!(
+            next_refresh_interval(in_hours(24 * 30), now),
+            REFRESH_INTERVAL
+        );
+        assert_eq!(
+            next_refresh_interval(in_hours(24 * 3), now),
+            REFRESH_INTERVAL_NEAR_EXPIRY
+        );
+        assert_eq!(
+            next_refresh_interval(in_hours(12), now),
+            REFRESH_INTERVAL_IMMINENT_EXPIRY
+        );
+        // Refresh when the account expires
+        assert_eq!(
+            next_refresh_interval(now + chrono::Duration::minutes(5), now),
+            Duration::from_secs(5 * 60)
+        );
+        assert_eq!(
+            next_refresh_interval(now + chrono::Duration::seconds(1), now),
+            MIN_REFRESH_INTERVAL
+        );
+        assert_eq!(
+            next_refresh_interval(in_hours(-1), now),
+            REFRESH_INTERVAL_EXPIRED
+        );
+    }
+
+    #[test]
+    fn test_expiry_changes_are_reported() {
+        let now = now();
+        let expiry = now + chrono::Duration::days(30);
+        let mut state = ExpiryState::new();
+
+        let outcome = state.handle_result(Ok(expiry), now);
+        assert_eq!(
+            outcome,
+            ExpiryOutcome {
+                changed_expiry: Some(AccountExpiry {
+                    expiry,
+                    fetched_at: now,
+                }),
+                next_refresh: Some(REFRESH_INTERVAL),
+            }
+        );
+
+        // An unchanged expiry is not reported again
+        let outcome = state.handle_result(Ok(expiry), now);
+        assert_eq!(outcome.changed_expiry, None);
+
+        // Errors back off, and do not clear the last known expiry
+        let first_retry = state
+            .handle_result(Err(RestError::SendError), now)
+            .next_refresh
+            .unwrap();
+        assert!(first_retry <= RETRY_EXPIRY_CHECK_INTERVAL_INITIAL);
+        let outcome = state.handle_result(Ok(expiry), now);
+        assert_eq!(outcome.changed_expiry, None);
+
+        let new_expiry = expiry + chrono::Duration::days(30);
+        let outcome = state.handle_result(Ok(new_expiry), now);
+        assert_eq!(outcome.changed_expiry.unwrap().expiry, new_expiry);
+    }
+
+    #[test]
+    fn test_invalid_account_stops_refreshing() {
+        let mut state = ExpiryState::new();
+        let outcome = state.handle_result(
+            Err(RestError::ApiError(
+                rest::StatusCode::BAD_REQUEST,
+                mullvad_rpc::INVALID_ACCOUNT.to_string(),
+            )),
+            now(),
+        );
+        assert_eq!(outcome.next_refresh, None);
+    }
+
+    /// A call made to [`FakeAccountApi`].
+    #[derive(Debug, Clone, PartialEq)]
+    enum Call {
+        CreateAccount,
+        GetExpiry(AccountToken),
+        GetWwwAuthToken(AccountToken),
+        SubmitVoucher(AccountToken, String),
+    }
+
+    /// A programmed response of [`FakeAccountApi`].
+    enum Reply {
+        NewAccount(AccountToken, DateTime<Utc>),
+        Expiry(DateTime<Utc>),
+        AuthToken(String),
+        Voucher(VoucherSubmission),
+        /// Fails like a request that timed out.
+        NetworkError,
+        Error(RestError),
+    }
+
+    /// [`AccountApi`] that records its calls and responds with programmed replies, in order,
+    /// after a programmable delay. Calls fail with a network error once the replies run out.
+    #[derive(Clone, Default)]
+    struct FakeAccountApi {
+        state: Arc<Mutex<FakeState>>,
+    }
+
+    #[derive(Default)]
+    struct FakeState {
+        calls: Vec<Call>,
+        replies: VecDeque<Reply>,
+        latency: Duration,
+    }
+
+    impl FakeAccountApi {
+        fn with_replies(replies: impl IntoIterator<Item = Reply>) -> Self {
+            let api = Self::default();
+            api.state.lock().unwrap().replies.extend(replies);
+            api
+        }
+
+        fn set_latency(&self, latency: Duration) {
+            self.state.lock().unwrap().latency = latency;
+        }
+
+        fn push_reply(&self, reply: Reply) {
+            self.state.lock().unwrap().replies.push_back(reply);
+        }
+
+        fn calls(&self) -> Vec<Call> {
+            self.state.lock().unwrap().calls.clone()
+        }
+
+        fn respond<T: Send + 'static>(
+            &self,
+            call: Call,
+            extract: fn(Reply) -> Result<T, Reply>,
+        ) -> BoxFuture<'static, Result<T, RestError>> {
+            let (reply, latency) = {
+                let mut state = self.state.lock().unwrap();
+                state.calls.push(call.clone());
+                (
+                    state.replies.pop_front().unwrap_or(Reply::NetworkError),
+                    state.latency,
+                )
+            };
+            async move {
+                tokio::time::sleep(latency).await;
+                match extract(reply) {
+                    Ok(value) => Ok(value),
+                    Err(Reply::NetworkError) => {
+                        let elapsed =
+                            tokio::time::timeout(Duration::ZERO, futures::future::pending::<()>())
+                                .await
+                                .unwrap_err();
+                        Err(RestError::TimeoutError(elapsed))
+                    }
+                    Err(Reply::Error(error)) => Err(error),
+                    Err(_) => panic!("Unexpected reply to {:?}", call),
+                }
+            }
+            .boxed()
+        }
+    }
+
+    impl AccountApi for FakeAccountApi {
+        fn create_account_detailed(
+            &self,
+        ) -> BoxFuture<'static, Result<(AccountToken, DateTime<Utc>), RestError>> {
+            self.respond(Call::CreateAccount, |reply| match reply {
+                Reply::NewAccount(token, expiry) => Ok((token, expiry)),
+                reply => Err(reply),
+            })
+        }
+
+        fn get_expiry(
+            &self,
+            account: AccountToken,
+        ) -> BoxFuture<'static, Result<DateTime<Utc>, RestError>> {
+            self.respond(Call::GetExpiry(account), |reply| match reply {
+                Reply::Expiry(expiry) => Ok(expiry),
+                reply => Err(reply),
+            })
+        }
+
+        fn get_www_auth_token(
+            &self,
+            account: AccountToken,
+        ) -> BoxFuture<'static, Result<String, RestError>> {
+            self.respond(Call::GetWwwAuthToken(account), |reply| match reply {
+                Reply::AuthToken(token) => Ok(token),
+                reply => Err(reply),
+            })
+        }
+
+        fn submit_voucher(
+            &self,
+            account: AccountToken,
+            voucher: String,
+        ) -> BoxFuture<'static, Result<VoucherSubmission, RestError>> {
+            self.respond(Call::SubmitVoucher(account, voucher), |reply| match reply {
+                Reply::Voucher(submission) => Ok(submission),
+                reply => Err(reply),
+            })
+        }
+    }
+
+    /// An account handle that uses `api`, and the receiver of its expiry updates.
+    struct TestAccount {
+        handle: AccountHandle,
+        availability: ApiAvailability,
+        events: mpsc::UnboundedReceiver<InternalDaemonEvent>,
+        // Updates are only sent while the sender is alive
+        _event_tx: Arc<mpsc::UnboundedSender<InternalDaemonEvent>>,
+    }
+
+    impl TestAccount {
+        fn new(api: &FakeAccountApi, token: Option<&str>) -> Self {
+            let availability = ApiAvailability::new(Default::default());
+            let (event_tx, events) = mpsc::unbounded();
+            let event_tx = Arc::new(event_tx);
+            let update_sender =
+                DaemonEventSender::new(Arc::downgrade(&event_tx)).to_specialized_sender();
+            let handle = Account::with_api(
+                tokio::runtime::Handle::current(),
+                Arc::new(api.clone()),
+                token.map(str::to_owned),
+                availability.handle(),
+                update_sender,
+            );
+            TestAccount {
+                handle,
+                availability,
+                events,
+                _event_tx: event_tx,
+            }
+        }
+
+        /// Returns the next expiry update, or `None` if there is none within `timeout`.
+        async fn next_update(&mut self, timeout: Duration) -> Option<AccountExpiryUpdate> {
+            match tokio::time::timeout(timeout, self.events.next()).await {
+                Ok(Some(InternalDaemonEvent::AccountExpiry(update))) => Some(update),
+                Ok(Some(_)) => panic!("Unexpected daemon event"),
+                Ok(None) | Err(_) => None,
+            }
+        }
+    }
+
+    fn run<T>(future: impl Future<Output = T>) -> T {
+        tokio::runtime::Runtime::new()
+            .expect("Failed to initialize runtime")
+            .block_on(future)
+    }
+
+    const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_monitor_reports_expiry() {
+        let expiry = Utc::now() + chrono::Duration::days(30);
+        let api = FakeAccountApi::with_replies([Reply::Expiry(expiry)]);
+
+        run(async {
+            let mut account = TestAccount::new(&api, Some("1234"));
+            let update = account.next_update(EVENT_TIMEOUT).await.unwrap();
+            assert_eq!(update.account_token, "1234");
+            assert_eq!(update.expiry.expiry, expiry);
+            // Background requests are allowed once the account is known to be valid
+            assert!(!account.availability.get_state().is_background_paused());
+        });
+        assert_eq!(api.calls(), [Call::GetExpiry("1234".to_owned())]);
+    }
+
+    #[test]
+    fn test_monitor_detects_revoked_account() {
+        let api = FakeAccountApi::with_replies([Reply::Error(RestError::ApiError(
+            rest::StatusCode::UNAUTHORIZED,
+            mullvad_rpc::INVALID_ACCOUNT.to_owned(),
+        ))]);
+
+        run(async {
+            let mut account = TestAccount::new(&api, Some("1234"));
+            assert!(account
+                .next_update(Duration::from_millis(500))
+                .await
+                .is_none());
+            assert!(account.availability.get_state().is_background_paused());
+
+            // Refreshing does nothing until another account is set
+            account.handle.refresh_expiry();
+            assert!(account
+                .next_update(Duration::from_millis(100))
+                .await
+                .is_none());
+            assert_eq!(api.calls().len(), 1);
+
+            let expiry = Utc::now() + chrono::Duration::days(30);
+            api.push_reply(Reply::Expiry(expiry));
+            account.handle.set_account(Some("5678".to_owned()));
+            let update = account.next_update(EVENT_TIMEOUT).await.unwrap();
+            assert_eq!(update.account_token, "5678");
+        });
+        assert_eq!(
+            api.calls(),
+            [
+                Call::GetExpiry("1234".to_owned()),
+                Call::GetExpiry("5678".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_logout_cancels_pending_refresh() {
+        let api =
+            FakeAccountApi::with_replies([Reply::Expiry(Utc::now() + chrono::Duration::days(30))]);
+        api.set_latency(Duration::from_millis(200));
+
+        run(async {
+            let mut account = TestAccount::new(&api, Some("1234"));
+            while api.calls().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            account.handle.set_account(None);
+            assert!(account
+                .next_update(Duration::from_millis(500))
+                .await
+                .is_none());
+        });
+        assert_eq!(api.calls().len(), 1);
+    }
+
+    #[test]
+    fn test_checked_expiry_updates_monitor() {
+        let expiry = Utc::now() + chrono::Duration::days(30);
+        let new_expiry = expiry + chrono::Duration::days(30);
+        let api = FakeAccountApi::with_replies([Reply::Expiry(expiry)]);
+
+        run(async {
+            let mut account = TestAccount::new(&api, Some("1234"));
+            account.next_update(EVENT_TIMEOUT).await.unwrap();
+
+            // The result of an explicit check is reported by the monitor even though the next
+            // background refresh is hours away
+            api.push_reply(Reply::Expiry(new_expiry));
+            assert_eq!(
+                account
+                    .handle
+                    .check_expiry("1234".to_owned())
+                    .await
+                    .unwrap(),
+                new_expiry
+            );
+            let update = account.next_update(EVENT_TIMEOUT).await.unwrap();
+            assert_eq!(update.expiry.expiry, new_expiry);
+        });
+        assert_eq!(api.calls().len(), 2);
+    }
+
+    #[test]
+    fn test_voucher_submission_is_not_retried() {
+        let new_expiry = Utc::now() + chrono::Duration::days(30);
+        let api = FakeAccountApi::with_replies([
+            Reply::NetworkError,
+            Reply::Voucher(VoucherSubmission {
+                time_added: 30 * 24 * 60 * 60,
+                new_expiry,
+            }),
+        ]);
+
+        run(async {
+            let mut account = TestAccount::new(&api, None);
+            let result = account
+                .handle
+                .submit_voucher("1234".to_owned(), "VOUCHER".to_owned())
+                .await;
+            assert!(result.unwrap_err().is_network_error());
+            assert_eq!(api.calls().len(), 1);
+
+            let submission = account
+                .handle
+                .submit_voucher("1234".to_owned(), "VOUCHER".to_owned())
+                .await
+                .unwrap();
+            assert_eq!(submission.new_expiry, new_expiry);
+        });
+        assert_eq!(api.calls().len(), 2);
+    }
+
+    #[test]
+    fn test_account_creation_is_not_retried() {
+        let api = FakeAccountApi::with_replies([
+            Reply::NetworkError,
+            Reply::NewAccount("1234".to_owned(), now()),
+            Reply::AuthToken("auth".to_owned()),
+        ]);
+
+        run(async {
+            let account = TestAccount::new(&api, None);
+            // Account creation is a POST, so it is not retried either. Retries of idempotent
+            // requests are tested in `mullvad_rpc::rest`.
+            assert!(account.handle.create_account_detailed().await.is_err());
+            assert_eq!(
+                account.handle.create_account_detailed().await.unwrap().0,
+                "1234"
+            );
+            assert_eq!(
+                account
+                    .handle
+                    .get_www_auth_token("1234".to_owned())
+                    .await
+                    .unwrap(),
+                "auth"
+            );
+        });
+        assert_eq!(
+            api.calls(),
+            [
+                Call::CreateAccount,
+                Call::CreateAccount,
+                Call::GetWwwAuthToken("1234".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_create_account_detailed() {
+        let expiry = now() + chrono::Duration::days(1);
+        let api = FakeAccountApi::with_replies([
+            Reply::NewAccount("1234".to_owned(), expiry),
+            Reply::Error(RestError::ApiError(
+                rest::StatusCode::TOO_MANY_REQUESTS,
+                "TOO_MANY_ACCOUNTS".to_owned(),
+            )),
+            Reply::NetworkError,
+        ]);
+
+        run(async {
+            let account = TestAccount::new(&api, None);
+            assert_eq!(
+                account.handle.create_account_detailed().await.unwrap(),
+                ("1234".to_owned(), expiry)
+            );
+
+            // API errors are returned as they are
+            match account.handle.create_account_detailed().await {
+                Err(RestError::ApiError(status, code)) => {
+                    assert_eq!(status, rest::StatusCode::TOO_MANY_REQUESTS);
+                    assert_eq!(code, "TOO_MANY_ACCOUNTS");
+                }
+                result => panic!("Unexpected result: {:?}", result),
+            }
+
+            let error = account.handle.create_account_detailed().await.unwrap_err();
+            assert!(error.is_network_error());
+        });
+        assert_eq!(api.calls(), vec![Call::CreateAccount; 3]);
+    }
+
+    #[test]
+    fn test_monitor_uses_new_account_expiry() {
+        let expiry = Utc::now() + chrono::Duration::days(1);
+        let api = FakeAccountApi::with_replies([Reply::NewAccount("1234".to_owned(), expiry)]);
+
+        run(async {
+            let mut account = TestAccount::new(&api, None);
+            let (token, _) = account.handle.create_account_detailed().await.unwrap();
+            account.handle.set_account(Some(token));
+
+            let update = account.next_update(EVENT_TIMEOUT).await.unwrap();
+            assert_eq!(update.account_token, "1234");
+            assert_eq!(update.expiry.expiry, expiry);
+            assert!(!account.availability.get_state().is_background_paused());
+        });
+        // The expiry is not fetched again
+        assert_eq!(api.calls(), [Call::CreateAccount]);
     }
 }
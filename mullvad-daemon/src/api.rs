@@ -29,11 +29,13 @@ pub(crate) fn create_api_config_provider(
     struct Context {
         attempt: u32,
         daemon_sender: DaemonEventSender<ApiConnectionModeRequest>,
+        current_config: ApiConnectionMode,
     }
 
     let ctx = Context {
         attempt: 1,
         daemon_sender,
+        current_config: initial_config.clone(),
     };
 
     Box::pin(
@@ -48,14 +50,18 @@ pub(crate) fn create_api_config_provider(
                     retry_attempt: ctx.attempt,
                 });
 
-                let new_config = response_rx.await.unwrap_or_else(|error| {
-                    log::error!(
-                        "{}",
-                        error.display_chain_with_msg("Failed to receive API proxy config")
-                    );
-                    // Fall back on unbridged connection
-                    ApiConnectionMode::Direct
-                });
+                let new_config = match response_rx.await {
+                    Ok(config) => config,
+                    Err(error) => {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to receive API proxy config")
+                        );
+                        // Keep the current mode, which may not be a direct connection
+                        ctx.current_config.clone()
+                    }
+                };
+                ctx.current_config = new_config.clone();
 
                 Some((new_config, ctx))
             },
@@ -0,0 +1,207 @@
+//! Exports the state that is needed to reach the API, so that it can be imported on a machine
+//! that is unable to find a working API address or bridge by itself. The exported file is not
+//! signed, but it contains a checksum so that damaged or edited files are rejected.
+
+use chrono::{DateTime, Utc};
+use mullvad_rpc::{proxy::ApiConnectionMode, AddressCache};
+use serde::{Deserialize, Serialize};
+use std::{io, net::SocketAddr, path::Path};
+
+/// Version of the file format. Files with any other version are rejected.
+const FORMAT_VERSION: u32 = 1;
+
+/// Prefix of the checksum, which identifies the algorithm used.
+const CHECKSUM_PREFIX: &str = "crc32:";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to serialize the API bootstrap state")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to parse the API bootstrap file")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unsupported API bootstrap file version: {}", _0)]
+    UnsupportedVersion(u32),
+
+    #[error(display = "The checksum of the API bootstrap file does not match its contents")]
+    ChecksumMismatch,
+
+    #[error(display = "Failed to update the API address cache")]
+    WriteAddressCache(#[error(source)] io::Error),
+
+    #[error(display = "Failed to save the API connection mode")]
+    WriteConnectionMode(#[error(source)] io::Error),
+}
+
+/// The state that is used to reach the API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BootstrapState {
+    /// The API address from the address cache.
+    pub api_address: SocketAddr,
+    /// How the API was connected to. This may contain the password of a bridge.
+    pub connection_mode: ApiConnectionMode,
+    /// When the state was exported.
+    pub exported_at: DateTime<Utc>,
+    /// Version of the daemon that exported the state.
+    pub daemon_version: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BootstrapFile<T> {
+    version: u32,
+    checksum: String,
+    state: T,
+}
+
+impl BootstrapState {
+    /// Reads the current state from the address cache and the connection mode in use.
+    pub async fn current(address_cache: &AddressCache, connection_mode: ApiConnectionMode) -> Self {
+        BootstrapState {
+            api_address: address_cache.get_address().await,
+            connection_mode,
+            exported_at: Utc::now(),
+            daemon_version: crate::version::PRODUCT_VERSION.to_owned(),
+        }
+    }
+
+    /// Serializes the state, together with the format version and a checksum.
+    pub fn export(&self) -> Result<String, Error> {
+        let file = BootstrapFile {
+            version: FORMAT_VERSION,
+            checksum: self.checksum()?,
+            state: self,
+        };
+        serde_json::to_string_pretty(&file).map_err(Error::Serialize)
+    }
+
+    /// Parses a file created by [`BootstrapState::export`]. Files with an unknown version or a
+    /// checksum that does not match the state are rejected.
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let file: BootstrapFile<serde_json::Value> =
+            serde_json::from_str(contents).map_err(Error::Parse)?;
+        if file.version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(file.version));
+        }
+        let state: BootstrapState = serde_json::from_value(file.state).map_err(Error::Parse)?;
+        if state.checksum()? != file.checksum {
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(state)
+    }
+
+    /// Writes the state to the address cache and the connection mode cache in `cache_dir`.
+    pub async fn apply(&self, address_cache: &AddressCache, cache_dir: &Path) -> Result<(), Error> {
+        address_cache
+            .set_address(self.api_address)
+            .await
+            .map_err(Error::WriteAddressCache)?;
+        self.connection_mode
+            .save(cache_dir)
+            .await
+            .map_err(Error::WriteConnectionMode)
+    }
+
+    fn checksum(&self) -> Result<String, Error> {
+        let serialized = serde_json::to_vec(self).map_err(Error::Serialize)?;
+        Ok(format!("{}{:08x}", CHECKSUM_PREFIX, crc32(&serialized)))
+    }
+}
+
+/// Computes the CRC-32 (IEEE) checksum of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_rpc::proxy::ProxyConfig;
+    use talpid_types::net::openvpn::ShadowsocksProxySettings;
+
+    fn run<T>(future: impl std::future::Future<Output = T>) -> T {
+        tokio::runtime::Runtime::new()
+            .expect("Failed to initialize runtime")
+            .block_on(future)
+    }
+
+    fn proxied_state() -> BootstrapState {
+        BootstrapState {
+            api_address: "192.0.2.1:443".parse().unwrap(),
+            connection_mode: ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(
+                ShadowsocksProxySettings {
+                    peer: "198.51.100.1:443".parse().unwrap(),
+                    password: "mullvad".to_owned(),
+                    cipher: "aes-256-gcm".to_owned(),
+                },
+            )),
+            exported_at: "2022-01-01T12:00:00Z".parse().unwrap(),
+            daemon_version: "2022.1".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for state in [
+            proxied_state(),
+            BootstrapState {
+                connection_mode: ApiConnectionMode::Direct,
+                ..proxied_state()
+            },
+        ] {
+            let exported = state.export().unwrap();
+            assert_eq!(BootstrapState::parse(&exported).unwrap(), state);
+
+            // Formatting does not affect the checksum
+            let value: serde_json::Value = serde_json::from_str(&exported).unwrap();
+            assert_eq!(BootstrapState::parse(&value.to_string()).unwrap(), state);
+        }
+    }
+
+    #[test]
+    fn test_tampered_file_is_not_applied() {
+        let exported = proxied_state().export().unwrap();
+        let tampered = exported.replace("192.0.2.1:443", "192.0.2.2:443");
+        assert_ne!(tampered, exported);
+
+        let address_cache = AddressCache::new(None, false).unwrap();
+        let original_address = run(address_cache.get_address());
+        let result = run(async {
+            let state = BootstrapState::parse(&tampered)?;
+            state.apply(&address_cache, Path::new("/nonexistent")).await
+        });
+
+        assert!(matches!(result, Err(Error::ChecksumMismatch)));
+        assert_eq!(run(address_cache.get_address()), original_address);
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut file: serde_json::Value =
+            serde_json::from_str(&proxied_state().export().unwrap()).unwrap();
+        file["version"] = serde_json::Value::from(FORMAT_VERSION + 1);
+        assert!(matches!(
+            BootstrapState::parse(&file.to_string()),
+            Err(Error::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+        assert!(matches!(
+            BootstrapState::parse("not json"),
+            Err(Error::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,229 @@
+//! Connectivity self-check. Each step only uses connections that the firewall already permits in
+//! the current tunnel state, so running the check never affects the security of the connection.
+
+use crate::geoip;
+use mullvad_rpc::{rest::RequestServiceHandle, ApiProxy};
+use mullvad_types::connectivity_check::{
+    ConnectivityCheckOutcome, ConnectivityCheckResult, ConnectivityCheckStep, ConnectivityReport,
+};
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+use talpid_types::{
+    net::{Endpoint, TransportProtocol},
+    ErrorExt,
+};
+
+/// Maximum time that a single step may take.
+const STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Host name that is resolved by the DNS step.
+const DNS_CHECK_HOSTNAME: &str = "am.i.mullvad.net";
+
+const STEPS: [ConnectivityCheckStep; 4] = [
+    ConnectivityCheckStep::Api,
+    ConnectivityCheckStep::RelayReachability,
+    ConnectivityCheckStep::Dns,
+    ConnectivityCheckStep::ExitIp,
+];
+
+/// What the firewall lets through at the time of the check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckState {
+    /// Disconnected, and traffic is not blocked.
+    Unblocked,
+    /// Connected to a relay.
+    Connected,
+    /// Connecting, disconnecting, in the error state, or disconnected with
+    /// `block_when_disconnected` enabled. Only the API and the relay may be reachable.
+    Blocked,
+}
+
+/// Returns why `step` cannot be run in `state`, or `None` if it can be run.
+fn skip_reason(
+    step: ConnectivityCheckStep,
+    state: CheckState,
+    relay_endpoint: Option<&Endpoint>,
+) -> Option<String> {
+    match step {
+        ConnectivityCheckStep::Api => None,
+        ConnectivityCheckStep::RelayReachability => match state {
+            CheckState::Connected => Some("the tunnel is connected to the relay".to_owned()),
+            CheckState::Blocked => {
+                Some("the firewall only lets the tunnel reach the relay".to_owned())
+            }
+            CheckState::Unblocked => match relay_endpoint {
+                None => Some("no relay matches the current settings".to_owned()),
+                Some(endpoint) if endpoint.protocol == TransportProtocol::Udp => Some(format!(
+                    "{} uses UDP, which cannot be probed without a tunnel",
+                    endpoint
+                )),
+                Some(_) => None,
+            },
+        },
+        ConnectivityCheckStep::Dns => match state {
+            CheckState::Blocked => Some("DNS is blocked by the firewall".to_owned()),
+            _ => None,
+        },
+        ConnectivityCheckStep::ExitIp => match state {
+            CheckState::Connected => None,
+            _ => Some("not connected to a relay".to_owned()),
+        },
+    }
+}
+
+/// Runs every step of the connectivity check. `relay_endpoint` is the relay that would be used
+/// when connecting, and is only probed if the tunnel is disconnected.
+pub async fn run(
+    state: CheckState,
+    relay_endpoint: Option<Endpoint>,
+    api_proxy: ApiProxy,
+    request_service: RequestServiceHandle,
+) -> ConnectivityReport {
+    let mut report = ConnectivityReport::default();
+
+    for step in STEPS.iter().copied() {
+        if let Some(reason) = skip_reason(step, state, relay_endpoint.as_ref()) {
+            report.results.push(ConnectivityCheckResult {
+                step,
+                outcome: ConnectivityCheckOutcome::Skipped(reason),
+                latency: None,
+            });
+            continue;
+        }
+
+        let result = match step {
+            ConnectivityCheckStep::Api => {
+                timed(step, async {
+                    api_proxy
+                        .get_api_addrs()
+                        .await
+                        .map(|_| ())
+                        .map_err(|error| error.display_chain())
+                })
+                .await
+            }
+            ConnectivityCheckStep::RelayReachability => {
+                let address = relay_endpoint.as_ref().unwrap().address;
+                timed(step, async move {
+                    tokio::net::TcpStream::connect(address)
+                        .await
+                        .map(|_| ())
+                        .map_err(|error| format!("Failed to connect to {}: {}", address, error))
+                })
+                .await
+            }
+            ConnectivityCheckStep::Dns => {
+                timed(step, async {
+                    let mut addrs = tokio::net::lookup_host((DNS_CHECK_HOSTNAME, 443))
+                        .await
+                        .map_err(|error| {
+                            format!("Failed to resolve {}: {}", DNS_CHECK_HOSTNAME, error)
+                        })?;
+                    addrs
+                        .next()
+                        .map(|_| ())
+                        .ok_or_else(|| format!("{} has no addresses", DNS_CHECK_HOSTNAME))
+                })
+                .await
+            }
+            ConnectivityCheckStep::ExitIp => {
+                let request_service = request_service.clone();
+                timed(step, async move {
+                    let location = geoip::send_location_request(request_service)
+                        .await
+                        .map_err(|error| error.display_chain())?;
+                    if location.mullvad_exit_ip {
+                        Ok(())
+                    } else {
+                        Err("traffic does not exit through a Mullvad relay".to_owned())
+                    }
+                })
+                .await
+            }
+        };
+
+        if let ConnectivityCheckOutcome::Failed(ref reason) = result.outcome {
+            log::warn!("Connectivity check step {:?} failed: {}", step, reason);
+        }
+        report.results.push(result);
+    }
+
+    report
+}
+
+async fn timed(
+    step: ConnectivityCheckStep,
+    check: impl Future<Output = Result<(), String>>,
+) -> ConnectivityCheckResult {
+    let start = Instant::now();
+    let outcome = match tokio::time::timeout(STEP_TIMEOUT, check).await {
+        Ok(Ok(())) => ConnectivityCheckOutcome::Passed,
+        Ok(Err(reason)) => ConnectivityCheckOutcome::Failed(reason),
+        Err(_) => ConnectivityCheckOutcome::Failed(format!(
+            "Timed out after {} seconds",
+            STEP_TIMEOUT.as_secs()
+        )),
+    };
+    ConnectivityCheckResult {
+        step,
+        outcome,
+        latency: Some(start.elapsed()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn runnable_steps(
+        state: CheckState,
+        endpoint: Option<&Endpoint>,
+    ) -> Vec<ConnectivityCheckStep> {
+        STEPS
+            .iter()
+            .copied()
+            .filter(|step| skip_reason(*step, state, endpoint).is_none())
+            .collect()
+    }
+
+    #[test]
+    fn test_skipped_steps() {
+        let tcp_endpoint = Endpoint::new(Ipv4Addr::new(192, 0, 2, 1), 443, TransportProtocol::Tcp);
+        let udp_endpoint =
+            Endpoint::new(Ipv4Addr::new(192, 0, 2, 1), 51820, TransportProtocol::Udp);
+
+        assert_eq!(
+            runnable_steps(CheckState::Unblocked, Some(&tcp_endpoint)),
+            vec![
+                ConnectivityCheckStep::Api,
+                ConnectivityCheckStep::RelayReachability,
+                ConnectivityCheckStep::Dns,
+            ]
+        );
+        // WireGuard relays cannot be probed without sending a handshake.
+        assert_eq!(
+            runnable_steps(CheckState::Unblocked, Some(&udp_endpoint)),
+            vec![ConnectivityCheckStep::Api, ConnectivityCheckStep::Dns]
+        );
+        assert_eq!(
+            runnable_steps(CheckState::Unblocked, None),
+            vec![ConnectivityCheckStep::Api, ConnectivityCheckStep::Dns]
+        );
+        assert_eq!(
+            runnable_steps(CheckState::Connected, Some(&tcp_endpoint)),
+            vec![
+                ConnectivityCheckStep::Api,
+                ConnectivityCheckStep::Dns,
+                ConnectivityCheckStep::ExitIp,
+            ]
+        );
+        // Only the API is allowed through the firewall while blocking.
+        assert_eq!(
+            runnable_steps(CheckState::Blocked, Some(&tcp_endpoint)),
+            vec![ConnectivityCheckStep::Api]
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! Writes a report file when the daemon panics, so that the panic can be found after a restart.
+//! Reports use the `.log` extension so that they are included in problem reports.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, Write as _},
+    panic::{self, PanicInfo},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const CRASH_REPORT_PREFIX: &str = "daemon-crash-";
+/// Suffix of reports that were written after the last time the daemon started.
+const UNREPORTED_SUFFIX: &str = ".unreported.log";
+const REPORTED_SUFFIX: &str = ".log";
+
+/// Maximum size of a crash report. The backtrace is truncated to fit.
+const MAX_CRASH_REPORT_SIZE: usize = 64 * 1024;
+/// Number of crash reports to keep. The oldest ones are removed on startup.
+const MAX_CRASH_REPORTS: usize = 3;
+/// Number of reports that may be written within the same second before giving up.
+const MAX_REPORTS_PER_SECOND: u32 = 100;
+
+/// Set while a report is being written, so that a panic in the hook does not recurse.
+static WRITING_REPORT: AtomicBool = AtomicBool::new(false);
+
+/// Installs a panic hook that writes a crash report to `log_dir`, and then calls the previously
+/// installed hook.
+pub fn install_panic_hook(log_dir: PathBuf) {
+    let previous_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !WRITING_REPORT.swap(true, Ordering::SeqCst) {
+            let report = build_report(info);
+            if let Err(error) = write_report(&log_dir, &report, unix_timestamp()) {
+                eprintln!("Failed to write crash report: {}", error);
+            }
+            WRITING_REPORT.store(false, Ordering::SeqCst);
+        }
+        previous_hook(info);
+    }));
+}
+
+fn build_report(info: &PanicInfo<'_>) -> String {
+    let message = match info.payload().downcast_ref::<&str>() {
+        Some(message) => *message,
+        None => match info.payload().downcast_ref::<String>() {
+            Some(message) => message.as_str(),
+            None => "Box<Any>",
+        },
+    };
+    let location = info
+        .location()
+        .map(|location| format!("{}:{}", location.file(), location.line()))
+        .unwrap_or_default();
+
+    let mut report = String::new();
+    let _ = writeln!(report, "Version: {}", crate::version::PRODUCT_VERSION);
+    let _ = writeln!(
+        report,
+        "Thread: {}",
+        std::thread::current().name().unwrap_or("<unnamed>")
+    );
+    let _ = writeln!(report, "Panicked at {}: {}", location, message);
+    let _ = writeln!(report, "Backtrace:");
+    let _ = write!(report, "{:?}", backtrace::Backtrace::new());
+    truncate(&mut report, MAX_CRASH_REPORT_SIZE);
+    report
+}
+
+/// Truncates `report` to at most `max_size` bytes, on a character boundary.
+fn truncate(report: &mut String, max_size: usize) {
+    if report.len() <= max_size {
+        return;
+    }
+    let mut end = max_size;
+    while !report.is_char_boundary(end) {
+        end -= 1;
+    }
+    report.truncate(end);
+}
+
+/// Identifies a crash report. Reports written within the same second are told apart by their
+/// sequence number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct ReportId {
+    timestamp: u64,
+    sequence: u32,
+}
+
+impl ReportId {
+    fn file_name(&self, suffix: &str) -> String {
+        if self.sequence == 0 {
+            format!("{}{}{}", CRASH_REPORT_PREFIX, self.timestamp, suffix)
+        } else {
+            format!(
+                "{}{}-{}{}",
+                CRASH_REPORT_PREFIX, self.timestamp, self.sequence, suffix
+            )
+        }
+    }
+
+    fn parse(file_name: &str) -> Option<Self> {
+        let rest = file_name.strip_prefix(CRASH_REPORT_PREFIX)?;
+        let id = rest
+            .strip_suffix(UNREPORTED_SUFFIX)
+            .or_else(|| rest.strip_suffix(REPORTED_SUFFIX))?;
+        let (timestamp, sequence) = match id.split_once('-') {
+            Some((timestamp, sequence)) => (timestamp, sequence.parse().ok()?),
+            None => (id, 0),
+        };
+        Some(Self {
+            timestamp: timestamp.parse().ok()?,
+            sequence,
+        })
+    }
+}
+
+/// Writes `report` to a new file in `log_dir`. An existing report is never overwritten, even if
+/// it was written within the same second.
+fn write_report(log_dir: &Path, report: &str, timestamp: u64) -> io::Result<PathBuf> {
+    for sequence in 0..MAX_REPORTS_PER_SECOND {
+        let id = ReportId {
+            timestamp,
+            sequence,
+        };
+        // The report keeps its ID when it is marked as seen, so that name must be free too.
+        if log_dir.join(id.file_name(REPORTED_SUFFIX)).exists() {
+            continue;
+        }
+        let path = log_dir.join(id.file_name(UNREPORTED_SUFFIX));
+        let mut file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        };
+        writeln!(file, "Timestamp: {}", timestamp)?;
+        file.write_all(report.as_bytes())?;
+        file.sync_all()?;
+        return Ok(path);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "too many crash reports were written within the same second",
+    ))
+}
+
+/// Returns the most recent crash report written since the daemon last started, if any, and
+/// marks all crash reports as seen. Only the most recent reports are kept.
+pub fn take_unreported_crash(log_dir: &Path) -> Option<PathBuf> {
+    let mut reports = list_reports(log_dir);
+    reports.sort_by_key(|(id, _)| *id);
+
+    let mut latest_unreported = None;
+    for (id, path) in &mut *reports {
+        let is_unreported = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.ends_with(UNREPORTED_SUFFIX))
+            .unwrap_or(false);
+        if is_unreported {
+            let reported_path = log_dir.join(id.file_name(REPORTED_SUFFIX));
+            match fs::rename(&*path, &reported_path) {
+                Ok(()) => *path = reported_path,
+                Err(error) => log::error!("Failed to mark crash report as seen: {}", error),
+            }
+            latest_unreported = Some(path.clone());
+        }
+    }
+
+    let excess = reports.len().saturating_sub(MAX_CRASH_REPORTS);
+    for (_, path) in reports.drain(..excess) {
+        if let Err(error) = fs::remove_file(&path) {
+            log::error!(
+                "Failed to remove old crash report {}: {}",
+                path.display(),
+                error
+            );
+        }
+    }
+
+    latest_unreported.filter(|path| path.exists())
+}
+
+/// Returns all crash reports in `log_dir` with their IDs.
+fn list_reports(log_dir: &Path) -> Vec<(ReportId, PathBuf)> {
+    let entries = match fs::read_dir(log_dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name();
+            let id = ReportId::parse(file_name.to_str()?)?;
+            Some((id, entry.path()))
+        })
+        .collect()
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::new_temp_dir;
+
+    #[test]
+    fn test_panic_writes_report() {
+        let dir = new_temp_dir();
+
+        let default_hook = panic::take_hook();
+        install_panic_hook(dir.clone());
+        let result = std::thread::Builder::new()
+            .name("crash-report-test".to_owned())
+            .spawn(|| panic!("controlled panic {}", 42))
+            .unwrap()
+            .join();
+        panic::set_hook(default_hook);
+        assert!(result.is_err());
+
+        // Other tests may panic while the hook is installed, so look for this test's report.
+        let reports: Vec<String> = list_reports(&dir)
+            .into_iter()
+            .map(|(_, path)| fs::read_to_string(path).unwrap())
+            .collect();
+        let report = reports
+            .iter()
+            .find(|report| report.contains("Thread: crash-report-test"))
+            .expect("no crash report was written");
+        assert!(report.contains("controlled panic 42"));
+        assert!(report.contains(crate::version::PRODUCT_VERSION));
+        assert!(report.contains("Backtrace:"));
+        assert!(report.len() <= MAX_CRASH_REPORT_SIZE + 32);
+
+        // The next start finds the report and marks it as seen
+        let unreported = take_unreported_crash(&dir).expect("crash was not detected");
+        assert!(fs::read_to_string(unreported)
+            .unwrap()
+            .contains("controlled panic 42"));
+        assert_eq!(take_unreported_crash(&dir), None);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_old_reports_are_removed() {
+        let dir = new_temp_dir();
+        for timestamp in 0..MAX_CRASH_REPORTS as u64 + 2 {
+            write_report(&dir, "report", 1000 + timestamp).unwrap();
+        }
+        fs::write(dir.join("daemon.log"), "log").unwrap();
+
+        let latest = take_unreported_crash(&dir).unwrap();
+        assert_eq!(latest, dir.join("daemon-crash-1004.log"));
+
+        let mut timestamps: Vec<u64> = list_reports(&dir)
+            .into_iter()
+            .map(|(id, _)| id.timestamp)
+            .collect();
+        timestamps.sort_unstable();
+        assert_eq!(timestamps, vec![1002, 1003, 1004]);
+        assert!(dir.join("daemon.log").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_reports_within_same_second() {
+        let dir = new_temp_dir();
+        let first = write_report(&dir, "first", 1000).unwrap();
+        let second = write_report(&dir, "second", 1000).unwrap();
+        assert_eq!(first, dir.join("daemon-crash-1000.unreported.log"));
+        assert_eq!(second, dir.join("daemon-crash-1000-1.unreported.log"));
+
+        // Seen reports are not overwritten either
+        assert_eq!(
+            take_unreported_crash(&dir),
+            Some(dir.join("daemon-crash-1000-1.log"))
+        );
+        let third = write_report(&dir, "third", 1000).unwrap();
+        assert_eq!(third, dir.join("daemon-crash-1000-2.unreported.log"));
+        assert_eq!(
+            take_unreported_crash(&dir),
+            Some(dir.join("daemon-crash-1000-2.log"))
+        );
+
+        let contents: Vec<String> = [
+            "daemon-crash-1000.log",
+            "daemon-crash-1000-1.log",
+            "daemon-crash-1000-2.log",
+        ]
+        .iter()
+        .map(|name| fs::read_to_string(dir.join(name)).unwrap())
+        .collect();
+        assert!(contents[0].ends_with("first"));
+        assert!(contents[1].ends_with("second"));
+        assert!(contents[2].ends_with("third"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut report = "ab\u{e5}c".to_owned();
+        truncate(&mut report, 3);
+        assert_eq!(report, "ab");
+        let mut report = "abc".to_owned();
+        truncate(&mut report, 10);
+        assert_eq!(report, "abc");
+    }
+}
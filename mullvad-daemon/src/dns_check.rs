@@ -0,0 +1,335 @@
+//! Checks whether the DNS servers applied by the tunnel state machine respond. Queries are only
+//! sent to servers that are reached through the tunnel, and only while connected, so the check
+//! never sends DNS traffic outside the tunnel.
+
+use mullvad_types::{
+    connectivity_check::ConnectivityCheckOutcome,
+    dns::{DnsServerHealth, DnsSource},
+    settings::{CustomDnsOptions, DnsServerReachability},
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use talpid_types::net::dns::AppliedDnsConfig;
+use tokio::net::UdpSocket;
+
+/// Maximum time to wait for a response from a single server.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Results are reused for requests made within this long of the previous check.
+const MIN_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Host name that is looked up on each server.
+const CHECK_HOSTNAME: &str = "am.i.mullvad.net";
+
+const DNS_PORT: u16 = 53;
+
+/// Query type and class of an IPv4 address (A) lookup.
+const QUERY_TYPE_A: u16 = 1;
+const QUERY_CLASS_IN: u16 = 1;
+
+/// Size of the header of a DNS message.
+const HEADER_LEN: usize = 12;
+
+/// Runs health checks and remembers the latest results, so that frequent requests do not cause
+/// a query for each request.
+#[derive(Clone, Default)]
+pub struct HealthChecker {
+    last_check: Arc<Mutex<Option<LastCheck>>>,
+}
+
+struct LastCheck {
+    time: Instant,
+    servers: Vec<IpAddr>,
+    results: Vec<DnsServerHealth>,
+}
+
+impl HealthChecker {
+    /// Returns one result for each applied server. Servers are skipped unless `connected` is set
+    /// and the queries would be sent through the tunnel.
+    pub async fn check(
+        &self,
+        connected: bool,
+        source: &DnsSource,
+        applied: Option<&AppliedDnsConfig>,
+    ) -> Vec<DnsServerHealth> {
+        let servers = match applied {
+            Some(applied) => applied.servers.clone(),
+            None => return vec![],
+        };
+        if !connected {
+            return skip_all(&servers, "not connected");
+        }
+
+        if let Some(last_check) = &*self.last_check.lock().unwrap() {
+            if last_check.servers == servers && last_check.time.elapsed() < MIN_CHECK_INTERVAL {
+                return last_check.results.clone();
+            }
+        }
+
+        // The relay default is the tunnel gateway, which has a private address
+        let tunnel_gateways = match source {
+            DnsSource::RelayDefault => servers.clone(),
+            _ => vec![],
+        };
+        let checks = servers.iter().map(|server| {
+            let in_tunnel = CustomDnsOptions::reachability(server, &tunnel_gateways)
+                == DnsServerReachability::Tunnel;
+            check_server(SocketAddr::new(*server, DNS_PORT), in_tunnel)
+        });
+        let results = futures::future::join_all(checks).await;
+
+        *self.last_check.lock().unwrap() = Some(LastCheck {
+            time: Instant::now(),
+            servers,
+            results: results.clone(),
+        });
+        results
+    }
+}
+
+fn skip_all(servers: &[IpAddr], reason: &str) -> Vec<DnsServerHealth> {
+    servers
+        .iter()
+        .map(|server| DnsServerHealth {
+            server: *server,
+            outcome: ConnectivityCheckOutcome::Skipped(reason.to_owned()),
+            latency: None,
+        })
+        .collect()
+}
+
+async fn check_server(address: SocketAddr, in_tunnel: bool) -> DnsServerHealth {
+    if !in_tunnel {
+        return DnsServerHealth {
+            server: address.ip(),
+            outcome: ConnectivityCheckOutcome::Skipped(
+                "queries to this server are not sent through the tunnel".to_owned(),
+            ),
+            latency: None,
+        };
+    }
+
+    let start = Instant::now();
+    let outcome = match tokio::time::timeout(QUERY_TIMEOUT, query(address)).await {
+        Ok(Ok(())) => ConnectivityCheckOutcome::Passed,
+        Ok(Err(reason)) => ConnectivityCheckOutcome::Failed(reason),
+        Err(_) => ConnectivityCheckOutcome::Failed(format!(
+            "No response within {} seconds",
+            QUERY_TIMEOUT.as_secs()
+        )),
+    };
+    if let ConnectivityCheckOutcome::Failed(reason) = &outcome {
+        log::warn!("DNS server {} did not respond: {}", address.ip(), reason);
+    }
+    DnsServerHealth {
+        server: address.ip(),
+        outcome,
+        latency: Some(start.elapsed()),
+    }
+}
+
+/// Looks up [`CHECK_HOSTNAME`] on the server at `address`, and waits for the response.
+async fn query(address: SocketAddr) -> Result<(), String> {
+    let bind_address: SocketAddr = if address.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(bind_address)
+        .await
+        .map_err(|error| format!("Failed to open socket: {}", error))?;
+    socket
+        .connect(address)
+        .await
+        .map_err(|error| format!("Failed to connect to {}: {}", address, error))?;
+
+    let id = rand::random();
+    socket
+        .send(&build_query(id, CHECK_HOSTNAME))
+        .await
+        .map_err(|error| format!("Failed to send query: {}", error))?;
+
+    let mut buffer = [0u8; 512];
+    loop {
+        let len = socket
+            .recv(&mut buffer)
+            .await
+            .map_err(|error| format!("Failed to receive response: {}", error))?;
+        // Ignore responses to other queries
+        if let Some(result) = check_response(id, &buffer[..len]) {
+            return result;
+        }
+    }
+}
+
+/// Encodes a recursive A query for `hostname`.
+fn build_query(id: u16, hostname: &str) -> Vec<u8> {
+    let mut query = Vec::with_capacity(HEADER_LEN + hostname.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Standard query with recursion desired
+    query.extend_from_slice(&0x0100u16.to_be_bytes());
+    // One question, no answer, authority or additional records
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in hostname.split('.').filter(|label| !label.is_empty()) {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&QUERY_TYPE_A.to_be_bytes());
+    query.extend_from_slice(&QUERY_CLASS_IN.to_be_bytes());
+    query
+}
+
+/// Returns `None` if `response` is not a response to the query with `id`. Otherwise, returns
+/// whether the server answered the query successfully.
+fn check_response(id: u16, response: &[u8]) -> Option<Result<(), String>> {
+    if response.len() < HEADER_LEN || response[..2] != id.to_be_bytes() {
+        return None;
+    }
+    let is_response = response[2] & 0x80 != 0;
+    if !is_response {
+        return None;
+    }
+    Some(match response[3] & 0x0f {
+        0 => Ok(()),
+        code => Err(format!("The server responded with error code {}", code)),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn run<T>(future: impl std::future::Future<Output = T>) -> T {
+        tokio::runtime::Runtime::new()
+            .expect("Failed to initialize runtime")
+            .block_on(future)
+    }
+
+    /// Answers the first query it receives with `response_code`.
+    async fn spawn_responder(response_code: u8) -> SocketAddr {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 512];
+            let (len, peer) = socket.recv_from(&mut buffer).await.unwrap();
+            let mut response = buffer[..len].to_vec();
+            response[2] |= 0x80;
+            response[3] = (response[3] & 0xf0) | response_code;
+            // A response to some other query is ignored
+            let mut other = response.clone();
+            other[0] = other[0].wrapping_add(1);
+            socket.send_to(&other, peer).await.unwrap();
+            socket.send_to(&response, peer).await.unwrap();
+        });
+        address
+    }
+
+    #[test]
+    fn test_query_encoding() {
+        let query = build_query(0x1234, "am.i.mullvad.net");
+        assert_eq!(&query[..4], &[0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(
+            &query[HEADER_LEN..],
+            b"\x02am\x01i\x07mullvad\x03net\x00\x00\x01\x00\x01"
+        );
+    }
+
+    #[test]
+    fn test_responding_server() {
+        run(async {
+            let address = spawn_responder(0).await;
+            let health = check_server(address, true).await;
+            assert_eq!(health.outcome, ConnectivityCheckOutcome::Passed);
+            assert!(health.latency.is_some());
+        });
+    }
+
+    #[test]
+    fn test_server_error() {
+        run(async {
+            // SERVFAIL
+            let address = spawn_responder(2).await;
+            let health = check_server(address, true).await;
+            assert!(matches!(
+                health.outcome,
+                ConnectivityCheckOutcome::Failed(_)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_blackholed_server() {
+        run(async {
+            // Receives queries but never responds
+            let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let health = check_server(socket.local_addr().unwrap(), true).await;
+            assert_eq!(
+                health.outcome,
+                ConnectivityCheckOutcome::Failed("No response within 2 seconds".to_owned())
+            );
+        });
+    }
+
+    #[test]
+    fn test_servers_outside_tunnel_are_skipped() {
+        run(async {
+            // Would respond, but must not be queried
+            let address = spawn_responder(0).await;
+            let health = check_server(address, false).await;
+            assert!(matches!(
+                health.outcome,
+                ConnectivityCheckOutcome::Skipped(_)
+            ));
+            assert_eq!(health.latency, None);
+        });
+    }
+
+    #[test]
+    fn test_check_is_skipped_when_disconnected() {
+        let applied = AppliedDnsConfig {
+            interface: "wg-mullvad".to_owned(),
+            servers: vec!["10.64.0.1".parse().unwrap()],
+            mechanism: talpid_types::net::dns::DnsMechanism::Resolvconf,
+        };
+        let results =
+            run(HealthChecker::default().check(false, &DnsSource::RelayDefault, Some(&applied)));
+        assert_eq!(results.len(), 1);
+        assert!(matches!(
+            results[0].outcome,
+            ConnectivityCheckOutcome::Skipped(_)
+        ));
+
+        assert!(
+            run(HealthChecker::default().check(true, &DnsSource::RelayDefault, None)).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_recent_results_are_reused() {
+        let server: IpAddr = "10.64.0.1".parse().unwrap();
+        let applied = AppliedDnsConfig {
+            interface: "wg-mullvad".to_owned(),
+            servers: vec![server],
+            mechanism: talpid_types::net::dns::DnsMechanism::Resolvconf,
+        };
+        let cached = vec![DnsServerHealth {
+            server,
+            outcome: ConnectivityCheckOutcome::Passed,
+            latency: Some(Duration::from_millis(20)),
+        }];
+        let checker = HealthChecker::default();
+        *checker.last_check.lock().unwrap() = Some(LastCheck {
+            time: Instant::now(),
+            servers: vec![server],
+            results: cached.clone(),
+        });
+
+        let results = run(checker.check(true, &DnsSource::RelayDefault, Some(&applied)));
+        assert_eq!(results, cached);
+    }
+}
@@ -0,0 +1,268 @@
+//! Retries file system operations that fail with errors that are likely to go away by
+//! themselves, such as interrupted system calls, timeouts on network file systems, or files that
+//! are briefly locked by antivirus software on Windows.
+
+use std::{
+    future::Future,
+    io,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// How many times an operation is retried before its error is returned.
+const MAX_RETRIES: usize = 3;
+/// Delay before the first retry. The delay is doubled for every retry.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Total number of retries since the daemon started. It is included in the log so that
+/// recurring problems can be spotted in problem reports.
+static RETRY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns whether `error` is likely to be transient, so that the operation may succeed if it is
+/// retried.
+pub fn is_transient(error: &io::Error) -> bool {
+    if matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    ) {
+        return true;
+    }
+    match error.raw_os_error() {
+        Some(code) => is_transient_os_error(code),
+        None => false,
+    }
+}
+
+#[cfg(unix)]
+fn is_transient_os_error(code: i32) -> bool {
+    code == libc::EINTR
+        || code == libc::EAGAIN
+        || code == libc::EWOULDBLOCK
+        || code == libc::ETIMEDOUT
+}
+
+#[cfg(windows)]
+fn is_transient_os_error(code: i32) -> bool {
+    use winapi::shared::winerror::{ERROR_LOCK_VIOLATION, ERROR_SHARING_VIOLATION};
+    code == ERROR_SHARING_VIOLATION as i32 || code == ERROR_LOCK_VIOLATION as i32
+}
+
+/// Runs `operation`, and runs it again after a short delay if it fails with a transient error.
+/// Other errors are returned immediately. The operation must be safe to repeat.
+pub async fn retry<T, F, Fut>(operation: F) -> io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    retry_with_counter(&RETRY_COUNT, operation).await
+}
+
+/// Like [`retry`], but for blocking operations. The current thread sleeps between attempts.
+pub fn retry_blocking<T>(operation: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    retry_blocking_with_counter(&RETRY_COUNT, operation)
+}
+
+async fn retry_with_counter<T, F, Fut>(counter: &AtomicUsize, mut operation: F) -> io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    let mut attempts = Attempts::new(counter);
+    loop {
+        match operation().await {
+            Err(error) => match attempts.next_delay(&error) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Err(error),
+            },
+            result => return result,
+        }
+    }
+}
+
+fn retry_blocking_with_counter<T>(
+    counter: &AtomicUsize,
+    mut operation: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut attempts = Attempts::new(counter);
+    loop {
+        match operation() {
+            Err(error) => match attempts.next_delay(&error) {
+                Some(delay) => std::thread::sleep(delay),
+                None => return Err(error),
+            },
+            result => return result,
+        }
+    }
+}
+
+/// Keeps track of the retries of a single operation.
+struct Attempts<'a> {
+    counter: &'a AtomicUsize,
+    retries: usize,
+    delay: Duration,
+}
+
+impl<'a> Attempts<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        Attempts {
+            counter,
+            retries: 0,
+            delay: INITIAL_RETRY_DELAY,
+        }
+    }
+
+    /// Returns how long to wait before retrying after `error`, or `None` if the error should be
+    /// returned.
+    fn next_delay(&mut self, error: &io::Error) -> Option<Duration> {
+        if self.retries >= MAX_RETRIES || !is_transient(error) {
+            return None;
+        }
+        self.retries += 1;
+        let total_retries = self.counter.fetch_add(1, Ordering::Relaxed) + 1;
+        log::debug!(
+            "File system operation failed with a transient error: {}. Retrying ({}/{}, {} \
+             retries since startup)",
+            error,
+            self.retries,
+            MAX_RETRIES,
+            total_retries
+        );
+
+        let delay = self.delay;
+        self.delay *= 2;
+        Some(delay)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{collections::VecDeque, sync::Mutex};
+
+    /// File system operation that returns the scripted results in order.
+    struct ScriptedFs {
+        results: Mutex<VecDeque<io::Result<u32>>>,
+        calls: AtomicUsize,
+    }
+
+    impl ScriptedFs {
+        fn new(results: Vec<io::Result<u32>>) -> Self {
+            ScriptedFs {
+                results: Mutex::new(results.into()),
+                calls: AtomicUsize::new(0),
+            }
+        }
+
+        async fn operation(&self) -> io::Result<u32> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.results
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("operation was called too many times")
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    fn run<T>(future: impl Future<Output = T>) -> T {
+        tokio::runtime::Runtime::new()
+            .expect("Failed to initialize runtime")
+            .block_on(future)
+    }
+
+    fn interrupted() -> io::Result<u32> {
+        Err(io::Error::from(io::ErrorKind::Interrupted))
+    }
+
+    #[test]
+    fn test_transient_errors() {
+        assert!(is_transient(&io::Error::from(io::ErrorKind::Interrupted)));
+        assert!(is_transient(&io::Error::from(io::ErrorKind::WouldBlock)));
+        assert!(is_transient(&io::Error::from(io::ErrorKind::TimedOut)));
+        assert!(!is_transient(&io::Error::from(io::ErrorKind::NotFound)));
+        assert!(!is_transient(&io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+        assert!(!is_transient(&io::Error::new(
+            io::ErrorKind::Other,
+            "not an os error"
+        )));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_transient_os_errors() {
+        for code in &[libc::EINTR, libc::EAGAIN, libc::ETIMEDOUT] {
+            assert!(is_transient(&io::Error::from_raw_os_error(*code)));
+        }
+        for code in &[libc::ENOENT, libc::EACCES, libc::ENOSPC, libc::EROFS] {
+            assert!(!is_transient(&io::Error::from_raw_os_error(*code)));
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_transient_os_errors() {
+        use winapi::shared::winerror::{
+            ERROR_ACCESS_DENIED, ERROR_DISK_FULL, ERROR_FILE_NOT_FOUND, ERROR_LOCK_VIOLATION,
+            ERROR_SHARING_VIOLATION,
+        };
+        for code in &[ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION] {
+            assert!(is_transient(&io::Error::from_raw_os_error(*code as i32)));
+        }
+        for code in &[ERROR_FILE_NOT_FOUND, ERROR_ACCESS_DENIED, ERROR_DISK_FULL] {
+            assert!(!is_transient(&io::Error::from_raw_os_error(*code as i32)));
+        }
+    }
+
+    #[test]
+    fn test_retry_until_success() {
+        let counter = AtomicUsize::new(0);
+        let fs = ScriptedFs::new(vec![interrupted(), interrupted(), Ok(42)]);
+
+        let result = run(retry_with_counter(&counter, || fs.operation()));
+
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(fs.calls(), 3);
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_non_transient_error_is_not_retried() {
+        let counter = AtomicUsize::new(0);
+        let fs = ScriptedFs::new(vec![Err(io::Error::from(io::ErrorKind::NotFound))]);
+
+        let result = run(retry_with_counter(&counter, || fs.operation()));
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(fs.calls(), 1);
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_retries_are_bounded() {
+        let counter = AtomicUsize::new(0);
+        let fs = ScriptedFs::new((0..=MAX_RETRIES).map(|_| interrupted()).collect());
+
+        let result = run(retry_with_counter(&counter, || fs.operation()));
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert_eq!(fs.calls(), MAX_RETRIES + 1);
+        assert_eq!(counter.load(Ordering::SeqCst), MAX_RETRIES);
+    }
+
+    #[test]
+    fn test_retry_blocking() {
+        let counter = AtomicUsize::new(0);
+        let mut results = VecDeque::from(vec![interrupted(), Ok(42)]);
+
+        let result = retry_blocking_with_counter(&counter, || results.pop_front().unwrap());
+
+        assert_eq!(result.unwrap(), 42);
+        assert!(results.is_empty());
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+}
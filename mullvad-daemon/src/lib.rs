@@ -7,43 +7,59 @@ extern crate serde;
 mod account;
 pub mod account_history;
 mod api;
+mod api_bootstrap;
+mod connectivity_check;
+pub mod crash_report;
+mod dns_check;
 pub mod exception_logging;
 #[cfg(target_os = "macos")]
 pub mod exclusion_gid;
+mod fs_retry;
 mod geoip;
 pub mod logging;
 #[cfg(not(target_os = "android"))]
 pub mod management_interface;
 mod migrations;
+mod preserving;
 mod relays;
 #[cfg(not(target_os = "android"))]
 pub mod rpc_uniqueness_check;
 pub mod runtime;
 pub mod settings;
+mod settings_transfer;
 mod target_state;
 pub mod version;
 mod version_check;
 
-use crate::target_state::PersistentTargetState;
+pub use migrations::{
+    migrate_all_dry_run, redact_settings, MigrationEvent, MigrationReport, SettingsChange,
+};
+
+use crate::target_state::{PersistentTargetState, StartupAction, StartupReason, StartupState};
 use futures::{
     channel::{mpsc, oneshot},
     future::{abortable, AbortHandle, Future},
     StreamExt,
 };
+use ipnetwork::IpNetwork;
 use mullvad_rpc::{
     availability::ApiAvailabilityHandle,
     proxy::{ApiConnectionMode, ProxyConfig},
 };
 use mullvad_types::{
-    account::{AccountData, AccountToken, VoucherSubmission},
+    account::{AccountData, AccountExpiry, AccountToken, VoucherSubmission},
+    connectivity_check::ConnectivityReport,
+    dns::{DnsSource, DnsStatus},
     endpoint::MullvadEndpoint,
     location::{Coordinates, GeoIpLocation},
     relay_constraints::{
-        BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, RelaySettings,
-        RelaySettingsUpdate,
+        ApiBridgeMode, ApiBridgeSettings, BridgeSettings, BridgeState, Constraint,
+        InternalBridgeConstraints, LocationConstraint, RelaySettings, RelaySettingsUpdate,
     },
-    relay_list::{Relay, RelayList},
-    settings::{DnsOptions, DnsState, Settings},
+    relay_list::{
+        DeprecatedRelay, FailedRelay, MaintenanceWindow, Relay, RelayList, RelayMaintenance,
+    },
+    settings::{CustomDnsError, CustomDnsOptions, DnsOptions, DnsState, SecurityPreset, Settings},
     states::{TargetState, TunnelState},
     version::{AppVersion, AppVersionInfo},
     wireguard::{KeygenEvent, RotationInterval},
@@ -58,7 +74,7 @@ use std::{collections::HashSet, ffi::OsString};
 use std::{
     marker::PhantomData,
     mem,
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     path::PathBuf,
     pin::Pin,
     sync::{mpsc as sync_mpsc, Arc, Weak},
@@ -67,17 +83,22 @@ use std::{
 #[cfg(any(target_os = "linux", windows))]
 use talpid_core::split_tunnel;
 use talpid_core::{
+    dns::AppliedDns,
     mpsc::Sender,
-    tunnel_state_machine::{self, TunnelCommand, TunnelParametersGenerator},
+    tunnel_state_machine::{self, ConnectionMetrics, TunnelCommand, TunnelParametersGenerator},
 };
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
 use talpid_types::{
     net::{
         openvpn::{self, ProxySettings},
+        wireguard::RateLimit,
         TransportProtocol, TunnelEndpoint, TunnelParameters, TunnelType,
     },
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    tunnel::{
+        format_connection_attempts, ConnectionAttemptMetrics, ErrorStateCause,
+        ParameterGenerationError, TunnelStateTransition, CONNECTION_METRICS_FILENAME,
+    },
     ErrorExt,
 };
 #[cfg(not(target_os = "android"))]
@@ -153,6 +174,9 @@ pub enum Error {
     #[error(display = "Settings error")]
     SettingsError(#[error(source)] settings::Error),
 
+    #[error(display = "Invalid custom DNS servers")]
+    InvalidCustomDns(#[error(source)] CustomDnsError),
+
     #[error(display = "Account history error")]
     AccountHistory(#[error(source)] account_history::Error),
 
@@ -205,6 +229,8 @@ pub enum DaemonCommand {
     Reconnect(oneshot::Sender<bool>),
     /// Request the current state.
     GetState(oneshot::Sender<TunnelState>),
+    /// Get the reason for the target state the daemon started with
+    GetStartupState(oneshot::Sender<StartupState>),
     /// Get the current geographical location.
     GetCurrentLocation(oneshot::Sender<Option<GeoIpLocation>>),
     CreateNewAccount(ResponseTx<String, Error>),
@@ -217,6 +243,9 @@ pub enum DaemonCommand {
     GetWwwAuthToken(ResponseTx<String, Error>),
     /// Submit voucher to add time to the current account. Returns time added in seconds
     SubmitVoucher(ResponseTx<VoucherSubmission, Error>, String),
+    /// Refresh the expiry of the current account now, rather than waiting for the next
+    /// background refresh
+    RefreshAccountExpiry(ResponseTx<(), Error>),
     /// Request account history
     GetAccountHistory(oneshot::Sender<Option<AccountToken>>),
     /// Remove the last used account, if there is one
@@ -232,25 +261,49 @@ pub enum DaemonCommand {
     UpdateRelaySettings(ResponseTx<(), settings::Error>, RelaySettingsUpdate),
     /// Set the allow LAN setting.
     SetAllowLan(ResponseTx<(), settings::Error>, bool),
+    /// Set the networks that are treated as local networks in addition to the private ranges.
+    SetAllowedNetworks(ResponseTx<(), settings::Error>, Vec<IpNetwork>),
+    /// Set lockdown, allow LAN and the allowed networks to the values of a preset.
+    SetSecurityPreset(ResponseTx<(), settings::Error>, SecurityPreset),
     /// Set the beta program setting.
     SetShowBetaReleases(ResponseTx<(), settings::Error>, bool),
     /// Set the block_when_disconnected setting.
     SetBlockWhenDisconnected(ResponseTx<(), settings::Error>, bool),
     /// Set the auto-connect setting.
     SetAutoConnect(ResponseTx<(), settings::Error>, bool),
+    /// Set if the daemon should block all traffic on start until a connect request is received
+    SetLockdownAfterBoot(ResponseTx<(), settings::Error>, bool),
+    /// Set if the daemon should move away from relays that are about to go into maintenance
+    SetReconnectBeforeMaintenance(ResponseTx<(), settings::Error>, bool),
+    /// Set how long to wait before reconnecting when the relay in use is removed from the relay
+    /// list
+    SetRelayRemovedReconnectDelay(ResponseTx<(), settings::Error>, Duration),
+    /// Set if the API host may be looked up using DNS-over-HTTPS when no API address works
+    SetApiDohFallback(ResponseTx<(), settings::Error>, bool),
+    /// Set how many bytes the API may download per session before non-essential requests are
+    /// refused
+    SetApiDataLimit(ResponseTx<(), settings::Error>, Option<u64>),
     /// Set the mssfix argument for OpenVPN
     SetOpenVpnMssfix(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Set the data channel cipher for OpenVPN
+    SetOpenVpnDataCipher(ResponseTx<(), settings::Error>, Option<openvpn::DataCipher>),
     /// Set proxy details for OpenVPN
     SetBridgeSettings(ResponseTx<(), settings::Error>, BridgeSettings),
     /// Set proxy state
     SetBridgeState(ResponseTx<(), settings::Error>, BridgeState),
+    /// Set if and through which bridges API traffic is sent
+    SetApiBridgeSettings(ResponseTx<(), settings::Error>, ApiBridgeSettings),
     /// Set if IPv6 should be enabled in the tunnel
     SetEnableIpv6(ResponseTx<(), settings::Error>, bool),
     /// Set DNS options or servers to use
-    SetDnsOptions(ResponseTx<(), settings::Error>, DnsOptions),
+    SetDnsOptions(ResponseTx<(), Error>, DnsOptions),
     /// Toggle macOS network check leak
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Set the persistent keepalive interval for wireguard tunnels, in seconds
+    SetWireguardPersistentKeepalive(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Set the bandwidth limit for wireguard tunnels
+    SetWireguardRateLimit(ResponseTx<(), settings::Error>, Option<RateLimit>),
     /// Set automatic key rotation interval for wireguard tunnels
     SetWireguardRotationInterval(ResponseTx<(), settings::Error>, Option<RotationInterval>),
     /// Get the daemon settings
@@ -265,9 +318,37 @@ pub enum DaemonCommand {
     GetVersionInfo(oneshot::Sender<Option<AppVersionInfo>>),
     /// Get current version of the app
     GetCurrentVersion(oneshot::Sender<AppVersion>),
+    /// Get timing metrics for the most recent connection attempts
+    GetConnectionMetrics(oneshot::Sender<Vec<ConnectionAttemptMetrics>>),
+    /// Check that the API, the relay and DNS are reachable in the current tunnel state
+    RunConnectivityCheck(oneshot::Sender<ConnectivityReport>),
+    /// Get the DNS servers in effect, and check whether they respond
+    GetDnsStatus(oneshot::Sender<DnsStatus>),
+    /// Get the relays that are avoided because they recently failed to connect
+    GetFailedRelays(oneshot::Sender<Vec<FailedRelay>>),
+    /// Call the function with a snapshot of the current state. No events are emitted between
+    /// taking the snapshot and calling the function, so it can be used to subscribe to events
+    /// without missing any changes
+    Subscribe(oneshot::Sender<()>, SubscribeCallback),
     /// Remove settings and clear the cache
     #[cfg(not(target_os = "android"))]
     FactoryReset(ResponseTx<(), Error>),
+    /// Override the API endpoint. Only supported by builds with the api-override feature
+    SetApiEndpoint(ResponseTx<(), mullvad_rpc::Error>, String, SocketAddr),
+    /// Return the state used to reach the API, in a format that can be imported elsewhere
+    ExportApiBootstrap(ResponseTx<String, api_bootstrap::Error>),
+    /// Apply a state that was exported with `ExportApiBootstrap`
+    ImportApiBootstrap(ResponseTx<(), api_bootstrap::Error>, String),
+    /// Return the settings that can be shared with other machines, in a format that can be
+    /// imported
+    ExportSettings(ResponseTx<String, settings_transfer::Error>),
+    /// Apply the valid sections of settings that were exported with `ExportSettings`
+    ImportSettings(
+        ResponseTx<settings_transfer::ImportReport, settings_transfer::Error>,
+        String,
+    ),
+    /// Get the number of bytes sent and received when talking to the API
+    GetApiTrafficStats(oneshot::Sender<mullvad_rpc::traffic_stats::TrafficSnapshot>),
     /// Request list of processes excluded from the tunnel
     #[cfg(target_os = "linux")]
     GetSplitTunnelProcesses(ResponseTx<Vec<i32>, split_tunnel::Error>),
@@ -307,6 +388,17 @@ pub enum DaemonCommand {
     BypassSocket(RawFd, oneshot::Sender<()>),
 }
 
+/// Receives the state of the daemon when a client subscribes to events.
+pub type SubscribeCallback = Box<dyn FnOnce(EventSnapshot) + Send>;
+
+/// The state of the daemon that a new event subscriber has not been notified about.
+pub struct EventSnapshot {
+    pub tunnel_state: TunnelState,
+    pub settings: Settings,
+    pub relay_list: RelayList,
+    pub app_version_info: Option<AppVersionInfo>,
+}
+
 /// All events that can happen in the daemon. Sent from various threads and exposed interfaces.
 pub(crate) enum InternalDaemonEvent {
     /// Tunnel has changed state.
@@ -316,6 +408,9 @@ pub(crate) enum InternalDaemonEvent {
         sync_mpsc::Sender<Result<TunnelParameters, ParameterGenerationError>>,
         u32,
     ),
+    /// Report from the `MullvadTunnelParametersGenerator` that the last generated relay could
+    /// not be connected to.
+    RelayConnectionFailed,
     /// A command sent to the daemon.
     Command(DaemonCommand),
     /// Daemon shutdown triggered by a signal, ctrl-c or similar.
@@ -333,6 +428,12 @@ pub(crate) enum InternalDaemonEvent {
     NewAppVersionInfo(AppVersionInfo),
     /// Request from REST client to use a different API endpoint.
     GenerateApiConnectionMode(api::ApiConnectionModeRequest),
+    /// A new relay list was downloaded.
+    NewRelayList(RelayList),
+    /// The background refresh fetched a new account expiry.
+    AccountExpiry(account::AccountExpiryUpdate),
+    /// Maintenance of a relay in use is approaching.
+    RelayMaintenance(relays::maintenance::MaintenanceEvent),
     /// The split tunnel paths or state were updated.
     #[cfg(target_os = "windows")]
     ExcludedPathsEvent(ExcludedPathsUpdate, oneshot::Sender<Result<(), Error>>),
@@ -368,6 +469,24 @@ impl From<api::ApiConnectionModeRequest> for InternalDaemonEvent {
     }
 }
 
+impl From<RelayList> for InternalDaemonEvent {
+    fn from(relay_list: RelayList) -> Self {
+        InternalDaemonEvent::NewRelayList(relay_list)
+    }
+}
+
+impl From<account::AccountExpiryUpdate> for InternalDaemonEvent {
+    fn from(update: account::AccountExpiryUpdate) -> Self {
+        InternalDaemonEvent::AccountExpiry(update)
+    }
+}
+
+impl From<relays::maintenance::MaintenanceEvent> for InternalDaemonEvent {
+    fn from(event: relays::maintenance::MaintenanceEvent) -> Self {
+        InternalDaemonEvent::RelayMaintenance(event)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum DaemonExecutionState {
     Running,
@@ -531,18 +650,39 @@ pub trait EventListener {
 
     /// Notify clients of a key generation event.
     fn notify_key_event(&self, key_event: KeygenEvent);
+
+    /// Notify clients of the progress of the settings migration.
+    fn notify_migration_event(&self, migration_event: MigrationEvent);
+
+    /// Notify clients that a relay in use, or the selected relay, was removed from the relay list.
+    fn notify_relay_deprecated(&self, relay: DeprecatedRelay);
+
+    /// Notify that the expiry of the current account changed.
+    fn notify_account_expiry(&self, expiry: AccountExpiry);
+
+    /// Notify clients that maintenance of a relay in use is about to start.
+    fn notify_relay_maintenance(&self, maintenance: RelayMaintenance);
 }
 
 pub struct Daemon<L: EventListener> {
     tunnel_command_tx: Arc<mpsc::UnboundedSender<TunnelCommand>>,
     tunnel_state: TunnelState,
     target_state: PersistentTargetState,
+    /// Whether traffic is blocked until the next connect request, because
+    /// `lockdown_after_boot` was enabled when the daemon started.
+    boot_lockdown: bool,
+    /// Report of a panic during the previous run of the daemon, if it crashed.
+    previous_crash_report: Option<PathBuf>,
     state: DaemonExecutionState,
     #[cfg(target_os = "linux")]
     exclude_pids: split_tunnel::PidManager,
     rx: mpsc::UnboundedReceiver<InternalDaemonEvent>,
     tx: DaemonEventSender,
     reconnection_job: Option<AbortHandle>,
+    maintenance_job: Option<AbortHandle>,
+    /// The last maintenance that clients were notified about, so that planning again does not
+    /// repeat the notification.
+    notified_maintenance: Option<(String, MaintenanceWindow)>,
     event_listener: L,
     settings: SettingsPersister,
     account_history: account_history::AccountHistory,
@@ -558,11 +698,26 @@ pub struct Daemon<L: EventListener> {
     app_version_info: Option<AppVersionInfo>,
     shutdown_tasks: Vec<Pin<Box<dyn Future<Output = ()>>>>,
     tunnel_state_machine_handle: tunnel_state_machine::JoinHandle,
+    connection_metrics: ConnectionMetrics,
+    /// The connection attempts that were last written to the cache directory.
+    saved_connection_metrics: Vec<ConnectionAttemptMetrics>,
+    applied_dns: AppliedDns,
+    dns_health_checker: dns_check::HealthChecker,
     cache_dir: PathBuf,
     #[cfg(target_os = "windows")]
     volume_update_tx: mpsc::UnboundedSender<()>,
 }
 
+/// Sends the progress of the settings migration straight to the event listener.
+struct MigrationEventForwarder<'a, L>(&'a L);
+
+impl<L: EventListener> Sender<MigrationEvent> for MigrationEventForwarder<'_, L> {
+    fn send(&self, event: MigrationEvent) -> Result<(), ()> {
+        self.0.notify_migration_event(event);
+        Ok(())
+    }
+}
+
 impl<L> Daemon<L>
 where
     L: EventListener + Clone + Send + 'static,
@@ -588,7 +743,12 @@ where
 
         let (internal_event_tx, internal_event_rx) = command_channel.destructure();
 
-        if let Err(error) = migrations::migrate_all(&cache_dir, &settings_dir).await {
+        // The event loop only starts after the migration, so progress is sent to clients
+        // directly.
+        let migration_progress_tx = MigrationEventForwarder(&event_listener);
+        if let Err(error) =
+            migrations::migrate_all(&cache_dir, &settings_dir, &migration_progress_tx).await
+        {
             log::error!(
                 "{}",
                 error.display_chain_with_msg("Failed to migrate settings or cache")
@@ -596,14 +756,32 @@ where
         }
         let settings = SettingsPersister::load(&settings_dir).await;
 
-        let target_state = if settings.get_account_token().is_none() {
-            PersistentTargetState::force(&cache_dir, TargetState::Unsecured).await
-        } else if settings.auto_connect {
-            log::info!("Automatically connecting since auto-connect is turned on");
-            PersistentTargetState::force(&cache_dir, TargetState::Secured).await
-        } else {
-            PersistentTargetState::new(&cache_dir).await
-        };
+        let startup_action = target_state::startup_action(
+            settings.get_account_token().is_some(),
+            settings.auto_connect,
+            settings.lockdown_after_boot,
+        );
+        match startup_action {
+            StartupAction::Force(_, StartupReason::AutoConnect) => {
+                log::info!("Automatically connecting since auto-connect is turned on")
+            }
+            StartupAction::Force(_, StartupReason::LockdownAfterBoot) => log::info!(
+                "Blocking all traffic until a connect request is received since lockdown after \
+                 boot is turned on"
+            ),
+            _ => (),
+        }
+        let boot_lockdown = settings.lockdown_after_boot;
+        let previous_crash_report = log_dir
+            .as_deref()
+            .and_then(crash_report::take_unreported_crash);
+        if let Some(report) = &previous_crash_report {
+            log::warn!(
+                "The daemon crashed the last time it ran. See {} for details",
+                report.display()
+            );
+        }
+        let target_state = PersistentTargetState::from_action(&cache_dir, startup_action).await;
 
         let tunnel_parameters_generator = MullvadTunnelParametersGenerator {
             tx: internal_event_tx.clone(),
@@ -621,14 +799,27 @@ where
             vec![]
         };
 
-        let rpc_runtime = mullvad_rpc::MullvadRpcRuntime::with_cache(
+        let mut rpc_runtime = mullvad_rpc::MullvadRpcRuntime::with_cache(
             &cache_dir,
             true,
+            settings.api_doh_fallback,
             #[cfg(target_os = "android")]
             Self::create_bypass_tx(&internal_event_tx),
         )
         .await
         .map_err(Error::InitRpcFactory)?;
+        rpc_runtime.set_connection_listener(Arc::new(|info: mullvad_rpc::ConnectionInfo| {
+            log::debug!(
+                "Connected to API {} at {} in {}ms",
+                if info.proxied {
+                    "via Shadowsocks"
+                } else {
+                    "directly"
+                },
+                info.peer,
+                info.connect_time.as_millis()
+            );
+        }));
 
         let api_availability = rpc_runtime.availability_handle();
         api_availability.suspend();
@@ -637,15 +828,18 @@ where
             api::get_allowed_endpoint(rpc_runtime.address_cache.get_address().await);
 
         let (offline_state_tx, offline_state_rx) = mpsc::unbounded();
+        let connection_metrics = ConnectionMetrics::default();
+        let applied_dns = AppliedDns::default();
         #[cfg(target_os = "windows")]
         let (volume_update_tx, volume_update_rx) = mpsc::unbounded();
         let (tunnel_command_tx, tunnel_state_machine_handle) = tunnel_state_machine::spawn(
             tunnel_state_machine::InitialTunnelState {
                 allow_lan: settings.allow_lan,
-                block_when_disconnected: settings.block_when_disconnected,
+                allowed_networks: settings.allowed_networks.clone(),
+                block_when_disconnected: settings.block_when_disconnected || boot_lockdown,
                 dns_servers: Self::get_dns_resolvers(&settings.tunnel_options.dns_options),
                 allowed_endpoint: initial_api_endpoint,
-                reset_firewall: *target_state != TargetState::Secured,
+                reset_firewall: *target_state != TargetState::Secured && !boot_lockdown,
                 #[cfg(windows)]
                 exclude_paths,
             },
@@ -654,6 +848,8 @@ where
             resource_dir.clone(),
             internal_event_tx.to_specialized_sender(),
             offline_state_tx,
+            connection_metrics.clone(),
+            applied_dns.clone(),
             #[cfg(target_os = "windows")]
             volume_update_rx,
             #[cfg(target_os = "macos")]
@@ -674,15 +870,21 @@ where
         let rpc_handle = rpc_runtime
             .mullvad_rest_handle(proxy_provider, endpoint_updater.callback())
             .await;
+        rpc_handle
+            .service()
+            .data_usage()
+            .set_max_session_bytes(settings.api_data_limit);
 
         Self::forward_offline_state(api_availability.clone(), offline_state_rx).await;
 
         let relay_list_listener = event_listener.clone();
+        let relay_list_tx: DaemonEventSender<RelayList> = internal_event_tx.to_specialized_sender();
         let on_relay_list_update = move |relay_list: &RelayList| {
             relay_list_listener.notify_relay_list(relay_list.clone());
+            let _ = relay_list_tx.send(relay_list.clone());
         };
 
-        let relay_selector = relays::RelaySelector::new(
+        let mut relay_selector = relays::RelaySelector::new(
             rpc_handle.clone(),
             on_relay_list_update,
             &resource_dir,
@@ -690,6 +892,12 @@ where
             api_availability.clone(),
         );
 
+        // The initial API connection mode is direct. Replace it before any request is sent if
+        // the API must never be reached directly.
+        if settings.api_bridge_settings.mode == ApiBridgeMode::Always {
+            Self::use_initial_api_bridge(&mut relay_selector, &settings, &rpc_handle).await;
+        }
+
         let app_version_info = version_check::load_cache(&cache_dir).await;
         let (version_updater, version_updater_handle) = version_check::VersionUpdater::new(
             rpc_handle.clone(),
@@ -716,6 +924,7 @@ where
             rpc_handle.clone(),
             settings.get_account_token(),
             api_availability.clone(),
+            internal_event_tx.to_specialized_sender(),
         );
 
         // Attempt to download a fresh relay list
@@ -725,12 +934,16 @@ where
             tunnel_command_tx,
             tunnel_state: TunnelState::Disconnected,
             target_state,
+            boot_lockdown,
+            previous_crash_report,
             state: DaemonExecutionState::Running,
             #[cfg(target_os = "linux")]
             exclude_pids: split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?,
             rx: internal_event_rx,
             tx: internal_event_tx,
             reconnection_job: None,
+            maintenance_job: None,
+            notified_maintenance: None,
             event_listener,
             settings,
             account_history,
@@ -746,6 +959,10 @@ where
             app_version_info,
             shutdown_tasks: vec![],
             tunnel_state_machine_handle,
+            connection_metrics,
+            saved_connection_metrics: Vec::new(),
+            applied_dns,
+            dns_health_checker: dns_check::HealthChecker::default(),
             cache_dir,
             #[cfg(target_os = "windows")]
             volume_update_tx,
@@ -812,7 +1029,7 @@ where
         // If auto-connect is enabled, block all traffic before shutting down to ensure
         // that no traffic can leak during boot.
         #[cfg(windows)]
-        if self.settings.auto_connect {
+        if self.settings.auto_connect || self.settings.lockdown_after_boot {
             self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(true));
         }
 
@@ -879,6 +1096,7 @@ where
                 self.handle_generate_tunnel_parameters(&tunnel_parameters_tx, retry_attempt)
                     .await
             }
+            RelayConnectionFailed => self.handle_relay_connection_failed(),
             Command(command) => self.handle_command(command).await,
             TriggerShutdown => self.trigger_shutdown_event(),
             WgKeyEvent(key_event) => self.handle_wireguard_key_event(key_event).await,
@@ -891,6 +1109,9 @@ where
             GenerateApiConnectionMode(request) => {
                 self.handle_generate_api_connection_mode(request).await
             }
+            NewRelayList(relay_list) => self.handle_new_relay_list(relay_list).await,
+            AccountExpiry(update) => self.handle_account_expiry(update),
+            RelayMaintenance(event) => self.handle_relay_maintenance(event),
             #[cfg(windows)]
             ExcludedPathsEvent(update, tx) => self.handle_new_excluded_paths(update, tx).await,
         }
@@ -919,6 +1140,7 @@ where
         };
 
         self.unschedule_reconnect();
+        self.save_connection_metrics().await;
 
         log::debug!("New tunnel state: {:?}", tunnel_state);
         match tunnel_state {
@@ -945,6 +1167,7 @@ where
 
         self.tunnel_state = tunnel_state.clone();
         self.event_listener.notify_new_state(tunnel_state);
+        self.schedule_maintenance_job();
     }
 
     async fn reset_rpc_sockets_on_tunnel_state_transition(
@@ -960,6 +1183,17 @@ where
         };
     }
 
+    fn handle_relay_connection_failed(&mut self) {
+        // Avoid the relay that the tunnel connected to. For multihop, that is the entry relay.
+        if let Some(relay) = self
+            .last_generated_entry_relay
+            .as_ref()
+            .or(self.last_generated_relay.as_ref())
+        {
+            self.relay_selector.report_failure(&relay.hostname);
+        }
+    }
+
     async fn handle_generate_tunnel_parameters(
         &mut self,
         tunnel_parameters_tx: &sync_mpsc::Sender<
@@ -989,6 +1223,15 @@ where
                             retry_attempt,
                             self.settings.get_wireguard().is_some(),
                         )
+                        .map_err(|error| {
+                            if matches!(
+                                error,
+                                relays::Error::NoRelayWithProvidersAndOwnership
+                                    | relays::Error::SameEntryAndExitRelay
+                            ) {
+                                log::error!("{}", error);
+                            }
+                        })
                         .ok();
                     if let Some(relays::RelaySelectorResult {
                         exit_relay,
@@ -1054,6 +1297,7 @@ where
                         let bridge_constraints = InternalBridgeConstraints {
                             location: settings.location.clone(),
                             providers: settings.providers.clone(),
+                            ownership: settings.ownership,
                             // FIXME: This is temporary while talpid-core only supports TCP proxies
                             transport_protocol: Constraint::Only(TransportProtocol::Tcp),
                         };
@@ -1137,19 +1381,10 @@ where
 
     async fn schedule_reconnect(&mut self, delay: Duration) {
         self.unschedule_reconnect();
-
-        let tunnel_command_tx = self.tx.to_specialized_sender();
-        let (future, abort_handle) = abortable(Box::pin(async move {
-            tokio::time::sleep(delay).await;
-            log::debug!("Attempting to reconnect");
-            let (tx, rx) = oneshot::channel();
-            let _ = tunnel_command_tx.send(DaemonCommand::Reconnect(tx));
-            // suppress "unable to send" warning:
-            let _ = rx.await;
-        }));
-
-        tokio::spawn(future);
-        self.reconnection_job = Some(abort_handle);
+        self.reconnection_job = Some(spawn_delayed_reconnect(
+            self.tx.to_specialized_sender(),
+            delay,
+        ));
     }
 
     fn unschedule_reconnect(&mut self) {
@@ -1158,6 +1393,50 @@ where
         }
     }
 
+    /// Plans for the upcoming maintenance of the relays that the tunnel is connected to,
+    /// replacing any previous plan. Nothing is planned unless the tunnel is connected.
+    fn schedule_maintenance_job(&mut self) {
+        use relays::maintenance::{self, MaintenanceEvent};
+
+        if let Some(job) = self.maintenance_job.take() {
+            job.abort();
+        }
+        if !matches!(self.tunnel_state, TunnelState::Connected { .. }) {
+            return;
+        }
+
+        let hostnames: Vec<&str> = self
+            .last_generated_relay
+            .iter()
+            .chain(self.last_generated_entry_relay.iter())
+            .map(|relay| relay.hostname.as_str())
+            .collect();
+        let relays_in_use = self.relay_selector.find_relays(&hostnames);
+        let plan = match maintenance::plan(&relays_in_use, mullvad_rpc::server_time::now()) {
+            Some(plan) => plan,
+            None => return,
+        };
+
+        let event_tx = self.tx.to_specialized_sender();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            tokio::time::sleep(plan.notify_in).await;
+            let hostname = plan.hostname.clone();
+            let reconnect_in = plan
+                .reconnect_in
+                .map(|reconnect_in| reconnect_in.saturating_sub(plan.notify_in));
+            if event_tx.send(MaintenanceEvent::Approaching(plan)).is_err() {
+                return;
+            }
+            if let Some(reconnect_in) = reconnect_in {
+                tokio::time::sleep(reconnect_in).await;
+                let _ = event_tx.send(MaintenanceEvent::Reconnect(hostname));
+            }
+        }));
+
+        tokio::spawn(future);
+        self.maintenance_job = Some(abort_handle);
+    }
+
     async fn handle_command(&mut self, command: DaemonCommand) {
         use self::DaemonCommand::*;
         if !self.state.is_running() {
@@ -1168,11 +1447,13 @@ where
             SetTargetState(tx, state) => self.on_set_target_state(tx, state).await,
             Reconnect(tx) => self.on_reconnect(tx),
             GetState(tx) => self.on_get_state(tx),
+            GetStartupState(tx) => self.on_get_startup_state(tx),
             GetCurrentLocation(tx) => self.on_get_current_location(tx).await,
             CreateNewAccount(tx) => self.on_create_new_account(tx).await,
             GetAccountData(tx, account_token) => self.on_get_account_data(tx, account_token).await,
             GetWwwAuthToken(tx) => self.on_get_www_auth_token(tx).await,
             SubmitVoucher(tx, voucher) => self.on_submit_voucher(tx, voucher).await,
+            RefreshAccountExpiry(tx) => self.on_refresh_account_expiry(tx),
             GetRelayLocations(tx) => self.on_get_relay_locations(tx),
             UpdateRelayLocations => self.on_update_relay_locations().await,
             SetAccount(tx, account_token) => self.on_set_account(tx, account_token).await,
@@ -1180,20 +1461,48 @@ where
             ClearAccountHistory(tx) => self.on_clear_account_history(tx).await,
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update).await,
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan).await,
+            SetAllowedNetworks(tx, allowed_networks) => {
+                self.on_set_allowed_networks(tx, allowed_networks).await
+            }
+            SetSecurityPreset(tx, preset) => self.on_set_security_preset(tx, preset).await,
             SetShowBetaReleases(tx, enabled) => self.on_set_show_beta_releases(tx, enabled).await,
             SetBlockWhenDisconnected(tx, block_when_disconnected) => {
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
                     .await
             }
             SetAutoConnect(tx, auto_connect) => self.on_set_auto_connect(tx, auto_connect).await,
+            SetLockdownAfterBoot(tx, lockdown_after_boot) => {
+                self.on_set_lockdown_after_boot(tx, lockdown_after_boot)
+                    .await
+            }
+            SetReconnectBeforeMaintenance(tx, enabled) => {
+                self.on_set_reconnect_before_maintenance(tx, enabled).await
+            }
+            SetRelayRemovedReconnectDelay(tx, delay) => {
+                self.on_set_relay_removed_reconnect_delay(tx, delay).await
+            }
+            SetApiDohFallback(tx, enabled) => self.on_set_api_doh_fallback(tx, enabled).await,
+            SetApiDataLimit(tx, limit) => self.on_set_api_data_limit(tx, limit).await,
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg).await,
+            SetOpenVpnDataCipher(tx, data_cipher) => {
+                self.on_set_openvpn_data_cipher(tx, data_cipher).await
+            }
             SetBridgeSettings(tx, bridge_settings) => {
                 self.on_set_bridge_settings(tx, bridge_settings).await
             }
             SetBridgeState(tx, bridge_state) => self.on_set_bridge_state(tx, bridge_state).await,
+            SetApiBridgeSettings(tx, api_bridge_settings) => {
+                self.on_set_api_bridge_settings(tx, api_bridge_settings)
+                    .await
+            }
             SetEnableIpv6(tx, enable_ipv6) => self.on_set_enable_ipv6(tx, enable_ipv6).await,
             SetDnsOptions(tx, dns_servers) => self.on_set_dns_options(tx, dns_servers).await,
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu).await,
+            SetWireguardPersistentKeepalive(tx, interval) => {
+                self.on_set_wireguard_persistent_keepalive(tx, interval)
+                    .await
+            }
+            SetWireguardRateLimit(tx, limit) => self.on_set_wireguard_rate_limit(tx, limit).await,
             SetWireguardRotationInterval(tx, interval) => {
                 self.on_set_wireguard_rotation_interval(tx, interval).await
             }
@@ -1203,6 +1512,17 @@ where
             VerifyWireguardKey(tx) => self.on_verify_wireguard_key(tx).await,
             GetVersionInfo(tx) => self.on_get_version_info(tx).await,
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
+            GetConnectionMetrics(tx) => self.on_get_connection_metrics(tx),
+            RunConnectivityCheck(tx) => self.on_run_connectivity_check(tx).await,
+            GetDnsStatus(tx) => self.on_get_dns_status(tx),
+            GetFailedRelays(tx) => self.on_get_failed_relays(tx),
+            Subscribe(tx, callback) => self.on_subscribe(tx, callback),
+            SetApiEndpoint(tx, host, address) => self.on_set_api_endpoint(tx, host, address).await,
+            ExportApiBootstrap(tx) => self.on_export_api_bootstrap(tx).await,
+            ImportApiBootstrap(tx, contents) => self.on_import_api_bootstrap(tx, contents).await,
+            ExportSettings(tx) => self.on_export_settings(tx),
+            ImportSettings(tx, contents) => self.on_import_settings(tx, contents).await,
+            GetApiTrafficStats(tx) => self.on_get_api_traffic_stats(tx),
             #[cfg(not(target_os = "android"))]
             FactoryReset(tx) => self.on_factory_reset(tx).await,
             #[cfg(target_os = "linux")]
@@ -1337,18 +1657,139 @@ where
         self.event_listener.notify_app_version(app_version_info);
     }
 
+    fn handle_account_expiry(&mut self, update: account::AccountExpiryUpdate) {
+        if !mullvad_types::account::secure_eq_opt(
+            self.settings.get_account_token().as_deref(),
+            Some(&update.account_token),
+        ) {
+            // The account was changed while the expiry was being fetched.
+            return;
+        }
+        self.event_listener.notify_account_expiry(update.expiry);
+    }
+
+    /// Checks whether the relays in use, or the relay selected by hostname, were removed from
+    /// the new relay list. If the tunnel still uses a removed relay, a reconnect is scheduled.
+    /// The maintenance of the relays in use is planned again, since it may have changed.
+    async fn handle_new_relay_list(&mut self, relay_list: RelayList) {
+        self.schedule_maintenance_job();
+
+        let relays_in_use: Vec<&str> = match self.tunnel_state {
+            TunnelState::Connected { .. } | TunnelState::Connecting { .. } => self
+                .last_generated_relay
+                .iter()
+                .chain(self.last_generated_entry_relay.iter())
+                .map(|relay| relay.hostname.as_str())
+                .collect(),
+            _ => vec![],
+        };
+        let pinned_hostname = match self.settings.get_relay_settings() {
+            RelaySettings::Normal(constraints) => match constraints.location {
+                Constraint::Only(LocationConstraint::Hostname(_, _, hostname)) => Some(hostname),
+                _ => None,
+            },
+            RelaySettings::CustomTunnelEndpoint(_) => None,
+        };
+
+        let deprecated_relays =
+            relays::find_deprecated_relays(&relay_list, &relays_in_use, pinned_hostname.as_deref());
+        let reconnect_delay = self.settings.relay_removed_reconnect_delay;
+        let reconnect = deprecated_relays.iter().any(|relay| relay.still_connected);
+        for relay in deprecated_relays {
+            if relay.still_connected {
+                log::warn!(
+                    "Relay {} is in use but was removed from the relay list. Reconnecting in {} seconds",
+                    relay.hostname,
+                    reconnect_delay.as_secs()
+                );
+            } else {
+                log::warn!(
+                    "Selected relay {} was removed from the relay list",
+                    relay.hostname
+                );
+            }
+            self.event_listener.notify_relay_deprecated(relay);
+        }
+        if reconnect {
+            self.schedule_reconnect(reconnect_delay).await;
+        }
+    }
+
+    fn handle_relay_maintenance(&mut self, event: relays::maintenance::MaintenanceEvent) {
+        use relays::maintenance::MaintenanceEvent;
+
+        let reconnect = self.settings.reconnect_before_maintenance;
+        match event {
+            MaintenanceEvent::Approaching(plan) => {
+                let notice = (plan.hostname.clone(), plan.window);
+                if self.notified_maintenance.as_ref() == Some(&notice) {
+                    return;
+                }
+                self.notified_maintenance = Some(notice);
+
+                let reconnect_at = if reconnect {
+                    plan.reconnect_at(mullvad_rpc::server_time::now())
+                } else {
+                    None
+                };
+                log::info!(
+                    "Maintenance of relay {} starts at {}{}",
+                    plan.hostname,
+                    plan.window.start,
+                    reconnect_at
+                        .map(|time| format!(". Reconnecting to another relay at {}", time))
+                        .unwrap_or_default()
+                );
+                self.event_listener
+                    .notify_relay_maintenance(RelayMaintenance {
+                        hostname: plan.hostname,
+                        window: plan.window,
+                        reconnect_at,
+                    });
+            }
+            MaintenanceEvent::Reconnect(hostname) => {
+                let still_in_use = self
+                    .last_generated_relay
+                    .iter()
+                    .chain(self.last_generated_entry_relay.iter())
+                    .any(|relay| relay.hostname.eq_ignore_ascii_case(&hostname));
+                if !still_in_use || !matches!(self.tunnel_state, TunnelState::Connected { .. }) {
+                    return;
+                }
+                if !reconnect {
+                    log::debug!(
+                        "Staying connected to {} since reconnecting before maintenance is disabled",
+                        hostname
+                    );
+                    return;
+                }
+                log::info!(
+                    "Reconnecting since maintenance of relay {} is about to start",
+                    hostname
+                );
+                self.reconnect_tunnel();
+            }
+        }
+    }
+
     /// Returns the next API connection mode to use for reaching the API.
     ///
     /// When `mullvad-rpc` fails to contact the API, it requests a new connection mode
     /// from this function, which will be used for future requests. The API can be
     /// connected to either directly (i.e., [`ApiConnectionMode::Direct`]) or from
-    /// a bridge ([`ApiConnectionMode::Proxied`]).
+    /// a bridge ([`ApiConnectionMode::Proxied`]), or not at all ([`ApiConnectionMode::Blocked`]).
     ///
-    /// * Every 3rd attempt returns [`ApiConnectionMode::Direct`] (i.e., no bridge).
-    /// * For any other attempt, this function returns a configuration for the bridge that is
-    ///   closest to the selected relay location[^note] and matches all bridge constraints.
+    /// * In the automatic API bridge mode, every 3rd attempt returns
+    ///   [`ApiConnectionMode::Direct`] (i.e., no bridge). The other modes always or never use a
+    ///   bridge.
+    /// * When a bridge is used, this function returns a configuration for the bridge that is
+    ///   closest to the selected relay location[^note] and matches the API bridge location, or
+    ///   the bridge location if none is set, as well as all other bridge constraints.
     /// * When no matching bridge is found, e.g. if the selected hosting providers don't match any
-    ///   bridge, [`ApiConnectionMode::Direct`] is returned.
+    ///   bridge, [`ApiConnectionMode::Direct`] is returned in the automatic mode. In the `Always`
+    ///   mode, [`ApiConnectionMode::Blocked`] is returned instead, so that the API is never
+    ///   reached directly or through a bridge outside of the selected location. Requests fail
+    ///   until a later attempt finds a matching bridge.
     ///
     /// [^note]: The "selected relay location" is the location of the last relay that
     ///    the daemon connected to, or, if no relay was connected to, the "midpoint" of
@@ -1357,6 +1798,18 @@ where
         &mut self,
         request: api::ApiConnectionModeRequest,
     ) {
+        let config = self.next_api_connection_mode(request.retry_attempt);
+        if let Err(error) = config.save(&self.cache_dir).await {
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("Failed to save API endpoint")
+            );
+        }
+        let _ = request.response_tx.send(config);
+    }
+
+    /// Returns the API connection mode to use for `retry_attempt`.
+    fn next_api_connection_mode(&mut self, retry_attempt: u32) -> ApiConnectionMode {
         let location = self
             .last_generated_entry_relay
             .as_ref()
@@ -1369,44 +1822,35 @@ where
                     None
                 }
             });
-        let bridge = if request.retry_attempt % 3 > 0 {
-            let constraints = match &self.settings.bridge_settings {
-                BridgeSettings::Normal(settings) => InternalBridgeConstraints {
-                    location: settings.location.clone(),
-                    providers: settings.providers.clone(),
-                    transport_protocol: Constraint::Only(TransportProtocol::Tcp),
-                },
-                _ => InternalBridgeConstraints {
-                    location: Constraint::Any,
-                    providers: Constraint::Any,
-                    transport_protocol: Constraint::Only(TransportProtocol::Tcp),
-                },
-            };
-            self.relay_selector
-                .get_proxy_settings(&constraints, location)
-        } else {
-            None
-        };
-        let config = match bridge {
-            Some((settings, _relay)) => match settings {
-                ProxySettings::Shadowsocks(ss_settings) => {
-                    ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss_settings))
-                }
-                _ => {
-                    log::error!("Received unexpected proxy settings type");
-                    ApiConnectionMode::Direct
-                }
-            },
-            None => ApiConnectionMode::Direct,
-        };
+        let api_bridge_settings = &self.settings.api_bridge_settings;
+        let bridge = self.relay_selector.get_api_bridge(
+            api_bridge_settings,
+            &self.settings.bridge_settings,
+            location,
+            retry_attempt,
+        );
+        match bridge {
+            Some((ProxySettings::Shadowsocks(ss_settings), _relay)) => {
+                ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss_settings))
+            }
+            Some(_) => {
+                log::error!("Received unexpected proxy settings type");
+                Self::fallback_api_connection_mode(api_bridge_settings.mode)
+            }
+            None => Self::fallback_api_connection_mode(api_bridge_settings.mode),
+        }
+    }
 
-        if let Err(error) = config.save(&self.cache_dir).await {
-            log::debug!(
-                "{}",
-                error.display_chain_with_msg("Failed to save API endpoint")
+    /// Returns the API connection mode to use when there is no bridge to use for API traffic.
+    fn fallback_api_connection_mode(mode: ApiBridgeMode) -> ApiConnectionMode {
+        if mode == ApiBridgeMode::Always {
+            log::error!(
+                "No bridge matches the API bridge settings. Blocking API traffic until one does"
             );
+            ApiConnectionMode::Blocked
+        } else {
+            ApiConnectionMode::Direct
         }
-        let _ = request.response_tx.send(config);
     }
 
     #[cfg(windows)]
@@ -1441,6 +1885,9 @@ where
         new_target_state: TargetState,
     ) {
         if self.state.is_running() {
+            if new_target_state == TargetState::Secured && self.boot_lockdown {
+                self.lift_boot_lockdown();
+            }
             let state_change_initated = self.set_target_state(new_target_state).await;
             Self::oneshot_send(tx, state_change_initated, "state change initiated");
         } else {
@@ -1458,10 +1905,28 @@ where
         }
     }
 
+    /// Stops blocking traffic when disconnected, unless `block_when_disconnected` is enabled.
+    fn lift_boot_lockdown(&mut self) {
+        log::info!("Lifting the lockdown after boot since a connect request was received");
+        self.boot_lockdown = false;
+        if !self.settings.block_when_disconnected {
+            self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(false));
+        }
+    }
+
     fn on_get_state(&self, tx: oneshot::Sender<TunnelState>) {
         Self::oneshot_send(tx, self.tunnel_state.clone(), "current state");
     }
 
+    fn on_get_startup_state(&self, tx: oneshot::Sender<StartupState>) {
+        let state = StartupState {
+            reason: self.target_state.startup_reason(),
+            locked_down: self.boot_lockdown,
+            previous_crash_report: self.previous_crash_report.clone(),
+        };
+        Self::oneshot_send(tx, state, "startup state");
+    }
+
     async fn on_get_current_location(&mut self, tx: oneshot::Sender<Option<GeoIpLocation>>) {
         use self::TunnelState::*;
 
@@ -1541,10 +2006,11 @@ where
 
     async fn on_create_new_account(&mut self, tx: ResponseTx<String, Error>) {
         let daemon_tx = self.tx.clone();
-        let future = self.account.create_account();
+        // The expiry is handed to the expiry monitor, so it is known as soon as the account is set
+        let future = self.account.create_account_detailed();
         tokio::spawn(async move {
             match future.await {
-                Ok(account_token) => {
+                Ok((account_token, _expiry)) => {
                     let _ = daemon_tx.send(InternalDaemonEvent::NewAccountEvent(account_token, tx));
                 }
                 Err(err) => {
@@ -1612,6 +2078,16 @@ where
         }
     }
 
+    fn on_refresh_account_expiry(&mut self, tx: ResponseTx<(), Error>) {
+        let result = if self.settings.get_account_token().is_some() {
+            self.account.refresh_expiry();
+            Ok(())
+        } else {
+            Err(Error::NoAccountToken)
+        };
+        Self::oneshot_send(tx, result, "refresh_account_expiry response");
+    }
+
     fn on_get_relay_locations(&mut self, tx: oneshot::Sender<RelayList>) {
         Self::oneshot_send(tx, self.relay_selector.get_locations(), "relay locations");
     }
@@ -1662,6 +2138,7 @@ where
         if account_changed {
             self.event_listener
                 .notify_settings(self.settings.to_settings());
+            self.account.set_account(account_token.clone());
 
             let history_token = match account_token {
                 Some(token) => token,
@@ -1791,90 +2268,399 @@ where
         );
     }
 
-    #[cfg(not(target_os = "android"))]
-    async fn on_factory_reset(&mut self, tx: ResponseTx<(), Error>) {
-        let mut last_error = Ok(());
-
-        let remove_key = self.remove_current_key_rpc();
-        tokio::spawn(async move {
-            if let Err(error) = remove_key.await {
-                log::error!(
-                    "{}",
-                    error.display_chain_with_msg(
-                        "Failed to remove WireGuard key for previous account"
-                    )
-                );
-            }
-        });
-
-        if let Err(error) = self.account_history.clear().await {
+    /// Writes the most recent connection attempts to the cache directory, where problem reports
+    /// pick them up.
+    async fn save_connection_metrics(&mut self) {
+        let attempts = self.connection_metrics.attempts();
+        if attempts == self.saved_connection_metrics {
+            return;
+        }
+        let path = self.cache_dir.join(CONNECTION_METRICS_FILENAME);
+        if let Err(error) = fs::write(&path, format_connection_attempts(&attempts)).await {
             log::error!(
                 "{}",
-                error.display_chain_with_msg("Failed to clear account history")
+                error.display_chain_with_msg("Failed to save the recent connection attempts")
             );
-            last_error = Err(Error::ClearAccountHistoryError(error));
-        }
-
-        if let Err(e) = self.settings.reset().await {
-            log::error!("Failed to reset settings: {}", e);
-            last_error = Err(Error::ClearSettingsError(e));
         }
+        self.saved_connection_metrics = attempts;
+    }
 
-        // Shut the daemon down.
-        self.trigger_shutdown_event();
-
-        self.shutdown_tasks.push(Box::pin(async move {
-            if let Err(e) = Self::clear_cache_directory().await {
-                log::error!(
-                    "{}",
-                    e.display_chain_with_msg("Failed to clear cache directory")
-                );
-                last_error = Err(Error::ClearCacheError);
-            }
-
-            if let Err(e) = Self::clear_log_directory().await {
-                log::error!(
-                    "{}",
-                    e.display_chain_with_msg("Failed to clear log directory")
-                );
-                last_error = Err(Error::ClearLogsError);
-            }
-            Self::oneshot_send(tx, last_error, "factory_reset response");
-        }));
+    fn on_get_connection_metrics(&mut self, tx: oneshot::Sender<Vec<ConnectionAttemptMetrics>>) {
+        Self::oneshot_send(
+            tx,
+            self.connection_metrics.attempts(),
+            "get_connection_metrics response",
+        );
     }
 
-    #[cfg(target_os = "linux")]
-    fn on_get_split_tunnel_processes(&mut self, tx: ResponseTx<Vec<i32>, split_tunnel::Error>) {
-        let result = self.exclude_pids.list().map_err(|error| {
-            log::error!("{}", error.display_chain_with_msg("Unable to obtain PIDs"));
-            error
-        });
-        Self::oneshot_send(tx, result, "get_split_tunnel_processes response");
+    fn on_get_failed_relays(&mut self, tx: oneshot::Sender<Vec<FailedRelay>>) {
+        Self::oneshot_send(
+            tx,
+            self.relay_selector.get_failed_relays(),
+            "get_failed_relays response",
+        );
     }
 
-    #[cfg(target_os = "linux")]
-    fn on_add_split_tunnel_process(&mut self, tx: ResponseTx<(), split_tunnel::Error>, pid: i32) {
-        let result = self.exclude_pids.add(pid).map_err(|error| {
-            log::error!("{}", error.display_chain_with_msg("Unable to add PID"));
-            error
-        });
-        Self::oneshot_send(tx, result, "add_split_tunnel_process response");
+    fn on_export_settings(&self, tx: ResponseTx<String, settings_transfer::Error>) {
+        let result = settings_transfer::export(&self.settings);
+        if let Err(error) = &result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to export settings")
+            );
+        }
+        Self::oneshot_send(tx, result, "export_settings response");
     }
 
-    #[cfg(target_os = "linux")]
-    fn on_remove_split_tunnel_process(
+    async fn on_import_settings(
         &mut self,
-        tx: ResponseTx<(), split_tunnel::Error>,
-        pid: i32,
+        tx: ResponseTx<settings_transfer::ImportReport, settings_transfer::Error>,
+        contents: String,
     ) {
-        let result = self.exclude_pids.remove(pid).map_err(|error| {
-            log::error!("{}", error.display_chain_with_msg("Unable to remove PID"));
-            error
-        });
-        Self::oneshot_send(tx, result, "remove_split_tunnel_process response");
+        let result = self.import_settings(&contents).await;
+        if let Err(error) = &result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to import settings")
+            );
+        }
+        Self::oneshot_send(tx, result, "import_settings response");
     }
 
-    #[cfg(target_os = "linux")]
+    async fn import_settings(
+        &mut self,
+        contents: &str,
+    ) -> Result<settings_transfer::ImportReport, settings_transfer::Error> {
+        let import = settings_transfer::SettingsImport::parse(contents)?;
+        let old_settings = self.settings.to_settings();
+        let settings_changed = self
+            .settings
+            .import_settings(&import)
+            .await
+            .map_err(settings_transfer::Error::WriteSettings)?;
+        if !settings_changed {
+            return Ok(import.report().clone());
+        }
+
+        let settings = self.settings.to_settings();
+        if settings.allow_lan != old_settings.allow_lan {
+            self.send_tunnel_command(TunnelCommand::AllowLan(settings.allow_lan));
+        }
+        if settings.allowed_networks != old_settings.allowed_networks {
+            self.send_tunnel_command(TunnelCommand::AllowedNetworks(
+                settings.allowed_networks.clone(),
+            ));
+        }
+        if settings.tunnel_options.dns_options != old_settings.tunnel_options.dns_options {
+            self.send_tunnel_command(TunnelCommand::Dns(Self::get_dns_resolvers(
+                &settings.tunnel_options.dns_options,
+            )));
+        }
+        #[cfg(windows)]
+        if settings.split_tunnel != old_settings.split_tunnel {
+            let excluded_apps = if settings.split_tunnel.enable_exclusions {
+                settings
+                    .split_tunnel
+                    .apps
+                    .iter()
+                    .map(OsString::from)
+                    .collect()
+            } else {
+                vec![]
+            };
+            let (result_tx, result_rx) = oneshot::channel();
+            self.send_tunnel_command(TunnelCommand::SetExcludedApps(result_tx, excluded_apps));
+            tokio::spawn(async move {
+                match result_rx.await {
+                    Ok(Ok(_)) => (),
+                    Ok(Err(error)) => log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to set excluded apps list")
+                    ),
+                    Err(_) => log::error!("The tunnel failed to return a result"),
+                }
+            });
+        }
+        let relay_settings_changed =
+            settings.get_relay_settings() != old_settings.get_relay_settings();
+        self.event_listener.notify_settings(settings);
+        if relay_settings_changed {
+            self.relay_selector.clear_failures();
+            log::info!("Initiating tunnel restart because the relay settings changed");
+            self.reconnect_tunnel();
+        }
+
+        Ok(import.report().clone())
+    }
+
+    fn on_get_api_traffic_stats(
+        &mut self,
+        tx: oneshot::Sender<mullvad_rpc::traffic_stats::TrafficSnapshot>,
+    ) {
+        Self::oneshot_send(
+            tx,
+            self.rpc_runtime.api_traffic_stats(),
+            "get_api_traffic_stats response",
+        );
+    }
+
+    fn on_subscribe(&self, tx: oneshot::Sender<()>, callback: SubscribeCallback) {
+        // Events are only emitted by the daemon loop, and commands are handled in the order they
+        // were queued together with other events, so the snapshot includes every earlier change.
+        callback(EventSnapshot {
+            tunnel_state: self.tunnel_state.clone(),
+            settings: self.settings.to_settings(),
+            relay_list: self.relay_selector.get_locations(),
+            app_version_info: self.app_version_info.clone(),
+        });
+        Self::oneshot_send(tx, (), "subscribe response");
+    }
+
+    async fn on_run_connectivity_check(&mut self, tx: oneshot::Sender<ConnectivityReport>) {
+        let state = match self.tunnel_state {
+            TunnelState::Disconnected if !self.settings.block_when_disconnected => {
+                connectivity_check::CheckState::Unblocked
+            }
+            TunnelState::Connected { .. } => connectivity_check::CheckState::Connected,
+            _ => connectivity_check::CheckState::Blocked,
+        };
+
+        // Only probe the relay that would be used if the tunnel was connected now.
+        let relay_endpoint = match (state, self.settings.get_relay_settings()) {
+            (connectivity_check::CheckState::Unblocked, RelaySettings::Normal(constraints)) => self
+                .relay_selector
+                .get_tunnel_endpoint(
+                    &constraints,
+                    self.settings.get_bridge_state(),
+                    0,
+                    self.settings.get_wireguard().is_some(),
+                )
+                .ok()
+                .map(|result| result.endpoint.to_endpoint()),
+            _ => None,
+        };
+
+        let api_proxy = mullvad_rpc::ApiProxy::new(self.rpc_handle.clone());
+        let request_service = self.rpc_runtime.rest_handle().await;
+        tokio::spawn(async move {
+            let report =
+                connectivity_check::run(state, relay_endpoint, api_proxy, request_service).await;
+            Self::oneshot_send(tx, report, "run_connectivity_check response");
+        });
+    }
+
+    fn on_get_dns_status(&self, tx: oneshot::Sender<DnsStatus>) {
+        let dns_options = &self.settings.tunnel_options.dns_options;
+        let source = DnsSource::from_options(dns_options);
+        let tunnel_gateways = self.relay_selector.get_tunnel_gateways();
+        let outside_tunnel = if source == DnsSource::Custom {
+            CustomDnsOptions::servers_outside_tunnel(
+                &dns_options.custom_options.addresses,
+                &tunnel_gateways,
+            )
+        } else {
+            vec![]
+        };
+        let applied = self.applied_dns.get();
+        let connected = matches!(self.tunnel_state, TunnelState::Connected { .. });
+        let health_checker = self.dns_health_checker.clone();
+        tokio::spawn(async move {
+            let health = health_checker
+                .check(connected, &source, applied.as_ref())
+                .await;
+            let status = DnsStatus {
+                source,
+                applied,
+                health,
+                tunnel_gateways,
+                outside_tunnel,
+            };
+            Self::oneshot_send(tx, status, "get_dns_status response");
+        });
+    }
+
+    async fn on_set_api_endpoint(
+        &mut self,
+        tx: ResponseTx<(), mullvad_rpc::Error>,
+        host: String,
+        address: SocketAddr,
+    ) {
+        if let Err(error) = mullvad_rpc::set_api_endpoint(host, address) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set API endpoint")
+            );
+            Self::oneshot_send(tx, Err(error), "set_api_endpoint response");
+            return;
+        }
+
+        // Proxied connection modes are allowed through the firewall by their own endpoint.
+        let is_direct = match self.rpc_handle.mode_selection() {
+            Some(selection) => selection.mode == ApiConnectionMode::Direct,
+            None => true,
+        };
+        if is_direct {
+            let (result_tx, result_rx) = oneshot::channel();
+            self.send_tunnel_command(TunnelCommand::AllowEndpoint(
+                api::get_allowed_endpoint(address),
+                result_tx,
+            ));
+            if result_rx.await.is_err() {
+                log::error!("Failed to update allowed endpoint");
+            }
+        }
+        // Drop connections to the old endpoint.
+        self.rpc_handle.service().reset().await;
+
+        Self::oneshot_send(tx, Ok(()), "set_api_endpoint response");
+    }
+
+    async fn on_export_api_bootstrap(&self, tx: ResponseTx<String, api_bootstrap::Error>) {
+        let connection_mode = self
+            .rpc_handle
+            .mode_selection()
+            .map(|selection| selection.mode)
+            .unwrap_or(ApiConnectionMode::Direct);
+        if connection_mode.is_proxy() {
+            log::warn!("Exporting API bootstrap state, which contains the password of a bridge");
+        }
+        let state = api_bootstrap::BootstrapState::current(
+            &self.rpc_runtime.address_cache,
+            connection_mode,
+        )
+        .await;
+        Self::oneshot_send(tx, state.export(), "export_api_bootstrap response");
+    }
+
+    async fn on_import_api_bootstrap(
+        &mut self,
+        tx: ResponseTx<(), api_bootstrap::Error>,
+        contents: String,
+    ) {
+        let result = self.import_api_bootstrap(&contents).await;
+        if let Err(error) = &result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to import API bootstrap state")
+            );
+        }
+        Self::oneshot_send(tx, result, "import_api_bootstrap response");
+    }
+
+    async fn import_api_bootstrap(&self, contents: &str) -> Result<(), api_bootstrap::Error> {
+        // Nothing is applied unless the whole file is valid
+        let state = api_bootstrap::BootstrapState::parse(contents)?;
+        log::info!(
+            "Importing API bootstrap state exported by version {} at {}: API address {}, {}",
+            state.daemon_version,
+            state.exported_at,
+            state.api_address,
+            state.connection_mode
+        );
+        state
+            .apply(&self.rpc_runtime.address_cache, &self.cache_dir)
+            .await?;
+
+        match self
+            .rpc_handle
+            .service()
+            .set_connection_mode(state.connection_mode)
+            .await
+        {
+            Ok(true) => (),
+            Ok(false) => log::error!("The imported API connection mode was rejected"),
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to switch API connection mode")
+            ),
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn on_factory_reset(&mut self, tx: ResponseTx<(), Error>) {
+        let mut last_error = Ok(());
+
+        let remove_key = self.remove_current_key_rpc();
+        tokio::spawn(async move {
+            if let Err(error) = remove_key.await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to remove WireGuard key for previous account"
+                    )
+                );
+            }
+        });
+
+        if let Err(error) = self.account_history.clear().await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to clear account history")
+            );
+            last_error = Err(Error::ClearAccountHistoryError(error));
+        }
+
+        if let Err(e) = self.settings.reset().await {
+            log::error!("Failed to reset settings: {}", e);
+            last_error = Err(Error::ClearSettingsError(e));
+        }
+
+        // Shut the daemon down.
+        self.trigger_shutdown_event();
+
+        self.shutdown_tasks.push(Box::pin(async move {
+            if let Err(e) = Self::clear_cache_directory().await {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Failed to clear cache directory")
+                );
+                last_error = Err(Error::ClearCacheError);
+            }
+
+            if let Err(e) = Self::clear_log_directory().await {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Failed to clear log directory")
+                );
+                last_error = Err(Error::ClearLogsError);
+            }
+            Self::oneshot_send(tx, last_error, "factory_reset response");
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_split_tunnel_processes(&mut self, tx: ResponseTx<Vec<i32>, split_tunnel::Error>) {
+        let result = self.exclude_pids.list().map_err(|error| {
+            log::error!("{}", error.display_chain_with_msg("Unable to obtain PIDs"));
+            error
+        });
+        Self::oneshot_send(tx, result, "get_split_tunnel_processes response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_add_split_tunnel_process(&mut self, tx: ResponseTx<(), split_tunnel::Error>, pid: i32) {
+        let result = self.exclude_pids.add(pid).map_err(|error| {
+            log::error!("{}", error.display_chain_with_msg("Unable to add PID"));
+            error
+        });
+        Self::oneshot_send(tx, result, "add_split_tunnel_process response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_remove_split_tunnel_process(
+        &mut self,
+        tx: ResponseTx<(), split_tunnel::Error>,
+        pid: i32,
+    ) {
+        let result = self.exclude_pids.remove(pid).map_err(|error| {
+            log::error!("{}", error.display_chain_with_msg("Unable to remove PID"));
+            error
+        });
+        Self::oneshot_send(tx, result, "remove_split_tunnel_process response");
+    }
+
+    #[cfg(target_os = "linux")]
     fn on_clear_split_tunnel_processes(&mut self, tx: ResponseTx<(), split_tunnel::Error>) {
         let result = self.exclude_pids.clear().map_err(|error| {
             log::error!("{}", error.display_chain_with_msg("Unable to clear PIDs"));
@@ -2055,6 +2841,7 @@ where
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
+                    self.relay_selector.clear_failures();
                     log::info!("Initiating tunnel restart because the relay settings changed");
                     self.reconnect_tunnel();
                 }
@@ -2084,6 +2871,31 @@ where
         }
     }
 
+    async fn on_set_allowed_networks(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        allowed_networks: Vec<IpNetwork>,
+    ) {
+        let save_result = self
+            .settings
+            .set_allowed_networks(allowed_networks.clone())
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_allowed_networks response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::AllowedNetworks(allowed_networks));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allowed_networks response");
+            }
+        }
+    }
+
     async fn on_set_show_beta_releases(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2107,6 +2919,46 @@ where
         }
     }
 
+    async fn on_set_security_preset(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        preset: SecurityPreset,
+    ) {
+        let old_allowed_networks = self.settings.allowed_networks.clone();
+        let old_allow_lan = self.settings.allow_lan;
+        let old_block_when_disconnected = self.settings.block_when_disconnected;
+
+        let save_result = self.settings.set_security_preset(preset).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_security_preset response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if self.settings.allowed_networks != old_allowed_networks {
+                        self.send_tunnel_command(TunnelCommand::AllowedNetworks(
+                            self.settings.allowed_networks.clone(),
+                        ));
+                    }
+                    if self.settings.allow_lan != old_allow_lan {
+                        self.send_tunnel_command(TunnelCommand::AllowLan(self.settings.allow_lan));
+                    }
+                    if self.settings.block_when_disconnected != old_block_when_disconnected {
+                        self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+                            self.settings.block_when_disconnected || self.boot_lockdown,
+                        ));
+                    }
+                    // A changed API bridge mode is used the next time the API connection mode
+                    // rotates.
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_security_preset response");
+            }
+        }
+    }
+
     async fn on_set_block_when_disconnected(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2122,8 +2974,9 @@ where
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
+                    // The lockdown after boot is only lifted by a connect request
                     self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
-                        block_when_disconnected,
+                        block_when_disconnected || self.boot_lockdown,
                     ));
                 }
             }
@@ -2155,6 +3008,125 @@ where
         }
     }
 
+    async fn on_set_lockdown_after_boot(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        lockdown_after_boot: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_lockdown_after_boot(lockdown_after_boot)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_lockdown_after_boot response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_lockdown_after_boot response");
+            }
+        }
+    }
+
+    async fn on_set_reconnect_before_maintenance(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_reconnect_before_maintenance(enabled)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_reconnect_before_maintenance response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_reconnect_before_maintenance response");
+            }
+        }
+    }
+
+    async fn on_set_relay_removed_reconnect_delay(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        delay: Duration,
+    ) {
+        let save_result = self.settings.set_relay_removed_reconnect_delay(delay).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_relay_removed_reconnect_delay response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_relay_removed_reconnect_delay response");
+            }
+        }
+    }
+
+    async fn on_set_api_doh_fallback(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_api_doh_fallback(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                self.rpc_runtime
+                    .address_cache
+                    .doh_fallback()
+                    .set_enabled(enabled);
+                Self::oneshot_send(tx, Ok(()), "set_api_doh_fallback response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_api_doh_fallback response");
+            }
+        }
+    }
+
+    async fn on_set_api_data_limit(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        limit: Option<u64>,
+    ) {
+        let save_result = self.settings.set_api_data_limit(limit).await;
+        match save_result {
+            Ok(settings_changed) => {
+                self.rpc_handle
+                    .service()
+                    .data_usage()
+                    .set_max_session_bytes(limit);
+                Self::oneshot_send(tx, Ok(()), "set_api_data_limit response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_api_data_limit response");
+            }
+        }
+    }
+
     async fn on_set_openvpn_mssfix(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2182,6 +3154,33 @@ where
         }
     }
 
+    async fn on_set_openvpn_data_cipher(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        data_cipher: Option<openvpn::DataCipher>,
+    ) {
+        let save_result = self.settings.set_openvpn_data_cipher(data_cipher).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_openvpn_data_cipher response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::OpenVpn) = self.get_connected_tunnel_type() {
+                        log::info!(
+                            "Initiating tunnel restart because the OpenVPN data cipher changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_openvpn_data_cipher response");
+            }
+        }
+    }
+
     async fn on_set_bridge_settings(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2207,6 +3206,34 @@ where
         }
     }
 
+    async fn on_set_api_bridge_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        api_bridge_settings: ApiBridgeSettings,
+    ) {
+        match self
+            .settings
+            .set_api_bridge_settings(api_bridge_settings)
+            .await
+        {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    // The new settings are used the next time the API connection mode rotates.
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+                Self::oneshot_send(tx, Ok(()), "set_api_bridge_settings response");
+            }
+            Err(e) => {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Failed to set API bridge settings")
+                );
+                Self::oneshot_send(tx, Err(e), "set_api_bridge_settings response");
+            }
+        }
+    }
+
     async fn on_set_bridge_state(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2252,11 +3279,19 @@ where
         }
     }
 
-    async fn on_set_dns_options(
-        &mut self,
-        tx: ResponseTx<(), settings::Error>,
-        dns_options: DnsOptions,
-    ) {
+    async fn on_set_dns_options(&mut self, tx: ResponseTx<(), Error>, dns_options: DnsOptions) {
+        if dns_options.state == DnsState::Custom {
+            let gateways = self.relay_selector.get_tunnel_gateways();
+            if let Err(error) = dns_options.custom_options.check_outside_tunnel(&gateways) {
+                log::error!("{}", error);
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::InvalidCustomDns(error)),
+                    "set_dns_options response",
+                );
+                return;
+            }
+        }
         let save_result = self.settings.set_dns_options(dns_options.clone()).await;
         match save_result {
             Ok(settings_changed) => {
@@ -2270,7 +3305,7 @@ where
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_dns_options response");
+                Self::oneshot_send(tx, Err(Error::SettingsError(e)), "set_dns_options response");
             }
         }
     }
@@ -2302,6 +3337,65 @@ where
         }
     }
 
+    async fn on_set_wireguard_persistent_keepalive(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        interval: Option<u16>,
+    ) {
+        let save_result = self
+            .settings
+            .set_wireguard_persistent_keepalive(interval)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_persistent_keepalive response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        log::info!(
+                            "Initiating tunnel restart because the WireGuard persistent \
+                             keepalive setting changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_persistent_keepalive response");
+            }
+        }
+    }
+
+    async fn on_set_wireguard_rate_limit(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        limit: Option<RateLimit>,
+    ) {
+        let save_result = self.settings.set_wireguard_rate_limit(limit).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_rate_limit response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        log::info!(
+                            "Initiating tunnel restart because the WireGuard bandwidth limit \
+                             changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_rate_limit response");
+            }
+        }
+    }
+
     async fn on_set_wireguard_rotation_interval(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
@@ -2502,6 +3596,31 @@ where
         Some(bypass_tx)
     }
 
+    async fn use_initial_api_bridge(
+        relay_selector: &mut relays::RelaySelector,
+        settings: &Settings,
+        rpc_handle: &mullvad_rpc::rest::MullvadRestHandle,
+    ) {
+        let bridge = relay_selector.get_api_bridge(
+            &settings.api_bridge_settings,
+            &settings.bridge_settings,
+            None::<Coordinates>,
+            0,
+        );
+        let mode = match bridge {
+            Some((ProxySettings::Shadowsocks(ss_settings), _relay)) => {
+                ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss_settings))
+            }
+            _ => Self::fallback_api_connection_mode(settings.api_bridge_settings.mode),
+        };
+        if let Err(error) = rpc_handle.service().set_connection_mode(mode).await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set the initial API connection mode")
+            );
+        }
+    }
+
     async fn forward_offline_state(
         api_availability: ApiAvailabilityHandle,
         mut offline_state_rx: mpsc::UnboundedReceiver<bool>,
@@ -2653,6 +3772,24 @@ impl DaemonShutdownHandle {
     }
 }
 
+/// Spawns a job that sends a reconnect command after `delay`, unless it is aborted first.
+fn spawn_delayed_reconnect(
+    tunnel_command_tx: DaemonEventSender<DaemonCommand>,
+    delay: Duration,
+) -> AbortHandle {
+    let (future, abort_handle) = abortable(Box::pin(async move {
+        tokio::time::sleep(delay).await;
+        log::debug!("Attempting to reconnect");
+        let (tx, rx) = oneshot::channel();
+        let _ = tunnel_command_tx.send(DaemonCommand::Reconnect(tx));
+        // suppress "unable to send" warning:
+        let _ = rx.await;
+    }));
+
+    tokio::spawn(future);
+    abort_handle
+}
+
 struct MullvadTunnelParametersGenerator {
     tx: DaemonEventSender,
 }
@@ -2683,6 +3820,16 @@ impl TunnelParametersGenerator for MullvadTunnelParametersGenerator {
             }
         }
     }
+
+    fn report_connection_failure(&mut self) {
+        if self
+            .tx
+            .send(InternalDaemonEvent::RelayConnectionFailed)
+            .is_err()
+        {
+            log::error!("Failed to report relay connection failure to the daemon");
+        }
+    }
 }
 
 /// Bump filehandle limit
@@ -2721,3 +3868,64 @@ pub fn bump_filehandle_limit() {
         );
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{distributions::Alphanumeric, Rng};
+
+    const SHORT_DELAY: Duration = Duration::from_millis(100);
+    const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Creates an empty directory with a random name in the temporary directory.
+    pub(crate) fn new_temp_dir() -> PathBuf {
+        let name: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(10)
+            .map(char::from)
+            .collect();
+        let dir = std::env::temp_dir().join(format!("mullvad-daemon-test-{}", name));
+        std::fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    fn run<T>(future: impl Future<Output = T>) -> T {
+        tokio::runtime::Runtime::new()
+            .expect("Failed to initialize runtime")
+            .block_on(future)
+    }
+
+    #[test]
+    fn test_delayed_reconnect() {
+        run(async {
+            let (event_tx, mut events) = mpsc::unbounded();
+            let event_tx = Arc::new(event_tx);
+            let command_tx =
+                DaemonEventSender::new(Arc::downgrade(&event_tx)).to_specialized_sender();
+
+            let start = std::time::Instant::now();
+            let _job = spawn_delayed_reconnect(command_tx, SHORT_DELAY);
+            match tokio::time::timeout(EVENT_TIMEOUT, events.next()).await {
+                Ok(Some(InternalDaemonEvent::Command(DaemonCommand::Reconnect(_)))) => (),
+                _ => panic!("Expected a reconnect command"),
+            }
+            assert!(start.elapsed() >= SHORT_DELAY);
+        });
+    }
+
+    #[test]
+    fn test_delayed_reconnect_abort() {
+        run(async {
+            let (event_tx, mut events) = mpsc::unbounded();
+            let event_tx = Arc::new(event_tx);
+            let command_tx =
+                DaemonEventSender::new(Arc::downgrade(&event_tx)).to_specialized_sender();
+
+            let job = spawn_delayed_reconnect(command_tx, SHORT_DELAY);
+            job.abort();
+            assert!(tokio::time::timeout(4 * SHORT_DELAY, events.next())
+                .await
+                .is_err());
+        });
+    }
+}
@@ -1,7 +1,7 @@
 #![deny(rust_2018_idioms)]
 
 use mullvad_daemon::{
-    logging,
+    crash_report, logging,
     management_interface::{ManagementInterfaceEventBroadcaster, ManagementInterfaceServer},
     rpc_uniqueness_check,
     runtime::new_runtime_builder,
@@ -54,6 +54,9 @@ fn init_logging(config: &cli::Config) -> Result<Option<PathBuf>, String> {
     )
     .map_err(|e| e.display_chain_with_msg("Unable to initialize logger"))?;
     log_panics::init();
+    if let Some(ref log_dir) = log_dir {
+        crash_report::install_panic_hook(log_dir.clone());
+    }
     exception_logging::enable();
     version::log_version();
     if let Some(ref log_dir) = log_dir {
@@ -1,21 +1,33 @@
-use crate::{account_history, settings, DaemonCommand, DaemonCommandSender, EventListener};
+use crate::{
+    account_history, api_bootstrap, settings, settings_transfer,
+    target_state::{StartupReason, StartupState},
+    DaemonCommand, DaemonCommandSender, EventListener, EventSnapshot, MigrationEvent,
+};
 use futures::{
     channel::{mpsc, oneshot},
-    StreamExt,
+    Stream, StreamExt,
 };
+use ipnetwork::IpNetwork;
 use mullvad_management_interface::{
-    types::{self, daemon_event, management_service_server::ManagementService},
+    types::{
+        self, daemon_event, events_filter::Category as EventCategory,
+        management_service_server::ManagementService,
+    },
     Code, Request, Response, Status,
 };
 use mullvad_paths;
-use mullvad_rpc::{rest::Error as RestError, StatusCode};
+use mullvad_rpc::{
+    rest::Error as RestError,
+    traffic_stats::{ByteCounts, TrafficSnapshot},
+    StatusCode,
+};
 #[cfg(not(target_os = "android"))]
-use mullvad_types::settings::DnsOptions;
+use mullvad_types::settings::{CustomDnsOptions, DnsOptions, MAX_RELAY_REMOVED_RECONNECT_DELAY};
 use mullvad_types::{
-    account::AccountToken,
-    relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
-    relay_list::RelayList,
-    settings::Settings,
+    account::{AccountExpiry, AccountToken},
+    relay_constraints::{ApiBridgeSettings, BridgeSettings, BridgeState, RelaySettingsUpdate},
+    relay_list::{DeprecatedRelay, RelayList, RelayMaintenance},
+    settings::{validate_allowed_networks, AllowedNetworksError, SecurityPreset, Settings},
     states::{TargetState, TunnelState},
     version,
     wireguard::{RotationInterval, RotationIntervalError},
@@ -26,10 +38,20 @@ use std::path::PathBuf;
 use std::{
     cmp,
     convert::{TryFrom, TryInto},
-    sync::Arc,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
     time::Duration,
 };
-use talpid_types::ErrorExt;
+use talpid_core::tunnel::wireguard::rate_limit;
+use talpid_types::{
+    net::{openvpn, wireguard},
+    ErrorExt,
+};
+use tokio::sync::mpsc::error::TrySendError;
 use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
 
 #[derive(err_derive::Error, Debug)]
@@ -42,12 +64,138 @@ pub enum Error {
 
 struct ManagementServiceImpl {
     daemon_tx: DaemonCommandSender,
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<Vec<EventsListener>>>,
 }
 
 pub type ServiceResult<T> = std::result::Result<Response<T>, Status>;
-type EventsListenerReceiver = UnboundedReceiverStream<Result<types::DaemonEvent, Status>>;
-type EventsListenerSender = tokio::sync::mpsc::UnboundedSender<Result<types::DaemonEvent, Status>>;
+
+/// Maximum number of events that are queued for a subscriber. Events are dropped for subscribers
+/// that fall further behind than this, so that they cannot hold up the daemon.
+const EVENT_QUEUE_SIZE: usize = 128;
+
+/// A subscriber of daemon events, and the categories of events that it wants. Events are shared
+/// between all subscribers, so that each event is only converted once.
+struct EventsListener {
+    tx: tokio::sync::mpsc::Sender<Arc<types::DaemonEvent>>,
+    categories: Vec<EventCategory>,
+    /// Number of events that have been dropped since the last event that was queued.
+    dropped: AtomicU64,
+}
+
+/// The stream of events sent to a subscriber.
+struct EventsListenerReceiver {
+    rx: tokio::sync::mpsc::Receiver<Arc<types::DaemonEvent>>,
+}
+
+impl Stream for EventsListenerReceiver {
+    type Item = Result<types::DaemonEvent, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx
+            .poll_recv(cx)
+            .map(|event| event.map(|event| Ok((*event).clone())))
+    }
+}
+
+impl EventsListener {
+    fn new(categories: Vec<EventCategory>, queue_size: usize) -> (Self, EventsListenerReceiver) {
+        let (tx, rx) = tokio::sync::mpsc::channel(queue_size);
+        let listener = EventsListener {
+            tx,
+            categories,
+            dropped: AtomicU64::new(0),
+        };
+        (listener, EventsListenerReceiver { rx })
+    }
+
+    /// Queues `event` if the listener wants it. If events were dropped before it, an
+    /// `EventsDropped` event is queued first. Returns `false` if the listener has gone away.
+    fn send(&self, event: &Arc<types::DaemonEvent>) -> bool {
+        if let Some(category) = event.event.as_ref().and_then(event_category) {
+            if !self.categories.is_empty() && !self.categories.contains(&category) {
+                return true;
+            }
+        }
+
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            let gap_event = types::DaemonEvent {
+                event: Some(daemon_event::Event::EventsDropped(types::EventsDropped {
+                    count: dropped,
+                })),
+            };
+            match self.tx.try_send(Arc::new(gap_event)) {
+                Ok(()) => self.dropped.store(0, Ordering::Relaxed),
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+                Err(TrySendError::Closed(_)) => return false,
+            }
+        }
+
+        match self.tx.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                    log::warn!("Dropping events for a subscriber that is not keeping up");
+                }
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Sends the snapshot and then adds the listener to `subscriptions`. Must be called from the
+    /// daemon loop so that no events are broadcast in between.
+    fn subscribe_with_snapshot(
+        self,
+        subscriptions: &RwLock<Vec<EventsListener>>,
+        snapshot: EventSnapshot,
+    ) {
+        for event in snapshot_events(snapshot) {
+            if !self.send(&Arc::new(event)) {
+                return;
+            }
+        }
+        subscriptions.write().push(self);
+    }
+}
+
+/// Returns the category of `event`, or `None` if it is sent to every subscriber.
+fn event_category(event: &daemon_event::Event) -> Option<EventCategory> {
+    use daemon_event::Event;
+    Some(match event {
+        Event::TunnelState(_) => EventCategory::TunnelState,
+        Event::Settings(_) => EventCategory::Settings,
+        Event::RelayList(_) => EventCategory::RelayList,
+        Event::VersionInfo(_) => EventCategory::VersionInfo,
+        Event::KeyEvent(_) => EventCategory::KeyEvent,
+        Event::MigrationEvent(_) => EventCategory::MigrationEvent,
+        Event::RelayDeprecated(_) => EventCategory::RelayDeprecated,
+        Event::AccountExpiry(_) => EventCategory::AccountExpiry,
+        Event::RelayMaintenance(_) => EventCategory::RelayMaintenance,
+        Event::EventsDropped(_) => return None,
+    })
+}
+
+/// Converts a snapshot into the events that are sent to a new subscriber, in the documented order.
+fn snapshot_events(snapshot: EventSnapshot) -> Vec<types::DaemonEvent> {
+    let mut events = vec![
+        daemon_event::Event::TunnelState(types::TunnelState::from(snapshot.tunnel_state)),
+        daemon_event::Event::Settings(types::Settings::from(&snapshot.settings)),
+        daemon_event::Event::RelayList(convert_relay_list(snapshot.relay_list)),
+    ];
+    if let Some(app_version_info) = snapshot.app_version_info {
+        events.push(daemon_event::Event::VersionInfo(
+            types::AppVersionInfo::from(app_version_info),
+        ));
+    }
+    events
+        .into_iter()
+        .map(|event| types::DaemonEvent { event: Some(event) })
+        .collect()
+}
 
 const INVALID_VOUCHER_MESSAGE: &str = "This voucher code is invalid";
 const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
@@ -95,16 +243,79 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(types::TunnelState::from(state)))
     }
 
+    async fn get_startup_state(&self, _: Request<()>) -> ServiceResult<types::StartupState> {
+        log::debug!("get_startup_state");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetStartupState(tx))?;
+        let state = self.wait_for_result(rx).await?;
+        Ok(Response::new(convert_startup_state(state)))
+    }
+
+    async fn get_connection_metrics(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ConnectionMetrics> {
+        log::debug!("get_connection_metrics");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetConnectionMetrics(tx))?;
+        let attempts = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ConnectionMetrics::from(attempts)))
+    }
+
+    async fn run_connectivity_check(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ConnectivityReport> {
+        log::debug!("run_connectivity_check");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RunConnectivityCheck(tx))?;
+        let report = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ConnectivityReport::from(report)))
+    }
+
+    async fn get_dns_state(&self, _: Request<()>) -> ServiceResult<types::DnsStatus> {
+        log::debug!("get_dns_state");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetDnsStatus(tx))?;
+        let status = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::DnsStatus::from(status)))
+    }
+
     // Control the daemon and receive events
     //
 
-    async fn events_listen(&self, _: Request<()>) -> ServiceResult<Self::EventsListenStream> {
-        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-
-        let mut subscriptions = self.subscriptions.write();
-        subscriptions.push(tx);
+    async fn events_listen(
+        &self,
+        request: Request<types::EventsFilter>,
+    ) -> ServiceResult<Self::EventsListenStream> {
+        let filter = request.into_inner();
+        let categories = filter
+            .categories
+            .into_iter()
+            .map(|category| {
+                EventCategory::from_i32(category)
+                    .ok_or_else(|| Status::invalid_argument("unknown event category"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (listener, rx) = EventsListener::new(categories, EVENT_QUEUE_SIZE);
+
+        if filter.snapshot {
+            let subscriptions = self.subscriptions.clone();
+            let (result_tx, result_rx) = oneshot::channel();
+            self.send_command_to_daemon(DaemonCommand::Subscribe(
+                result_tx,
+                Box::new(move |snapshot| {
+                    listener.subscribe_with_snapshot(&subscriptions, snapshot)
+                }),
+            ))?;
+            self.wait_for_result(result_rx).await?;
+        } else {
+            let mut subscriptions = self.subscriptions.write();
+            subscriptions.push(listener);
+        }
 
-        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+        Ok(Response::new(rx))
     }
 
     async fn prepare_restart(&self, _: Request<()>) -> ServiceResult<()> {
@@ -136,6 +347,72 @@ impl ManagementService for ManagementServiceImpl {
         }
     }
 
+    async fn set_api_endpoint(&self, request: Request<types::ApiEndpoint>) -> ServiceResult<()> {
+        let endpoint = request.into_inner();
+        log::debug!("set_api_endpoint({}, {})", endpoint.host, endpoint.address);
+        let address = endpoint
+            .address
+            .parse()
+            .map_err(|_| Status::invalid_argument("invalid API address"))?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetApiEndpoint(tx, endpoint.host, address))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(|error| Status::failed_precondition(error.to_string()))
+    }
+
+    async fn export_api_bootstrap(&self, _: Request<()>) -> ServiceResult<String> {
+        log::debug!("export_api_bootstrap");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ExportApiBootstrap(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_api_bootstrap_error)
+    }
+
+    async fn import_api_bootstrap(&self, request: Request<String>) -> ServiceResult<()> {
+        log::debug!("import_api_bootstrap");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ImportApiBootstrap(tx, request.into_inner()))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_api_bootstrap_error)
+    }
+
+    async fn export_settings(&self, _: Request<()>) -> ServiceResult<String> {
+        log::debug!("export_settings");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ExportSettings(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_transfer_error)
+    }
+
+    async fn import_settings(
+        &self,
+        request: Request<String>,
+    ) -> ServiceResult<types::SettingsImportReport> {
+        log::debug!("import_settings");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ImportSettings(tx, request.into_inner()))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|report| Response::new(convert_settings_import_report(report)))
+            .map_err(map_settings_transfer_error)
+    }
+
+    async fn get_api_traffic_stats(&self, _: Request<()>) -> ServiceResult<types::ApiTrafficStats> {
+        log::debug!("get_api_traffic_stats");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetApiTrafficStats(tx))?;
+        let stats = self.wait_for_result(rx).await?;
+        Ok(Response::new(convert_api_traffic_stats(stats)))
+    }
+
     async fn get_current_version(&self, _: Request<()>) -> ServiceResult<String> {
         log::debug!("get_current_version");
         let (tx, rx) = oneshot::channel();
@@ -144,6 +421,18 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(version))
     }
 
+    async fn get_interface_version(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::InterfaceVersion> {
+        log::debug!("get_interface_version");
+        Ok(Response::new(types::InterfaceVersion {
+            major: mullvad_management_interface::INTERFACE_VERSION_MAJOR,
+            minor: mullvad_management_interface::INTERFACE_VERSION_MINOR,
+            daemon_version: crate::version::PRODUCT_VERSION.to_owned(),
+        }))
+    }
+
     async fn get_version_info(&self, _: Request<()>) -> ServiceResult<types::AppVersionInfo> {
         log::debug!("get_version_info");
 
@@ -250,6 +539,29 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_api_bridge_settings(
+        &self,
+        request: Request<types::ApiBridgeSettings>,
+    ) -> ServiceResult<()> {
+        let api_bridge_settings = ApiBridgeSettings::try_from(request.into_inner())?;
+
+        log::debug!("set_api_bridge_settings({:?})", api_bridge_settings);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetApiBridgeSettings(tx, api_bridge_settings))?;
+        let settings_result = self.wait_for_result(rx).await?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn get_failed_relays(&self, _: Request<()>) -> ServiceResult<types::FailedRelayList> {
+        log::debug!("get_failed_relays");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetFailedRelays(tx))?;
+        let relays = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::FailedRelayList::from(relays)))
+    }
+
     // Settings
     //
 
@@ -273,6 +585,54 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_security_preset(
+        &self,
+        request: Request<types::SecurityPreset>,
+    ) -> ServiceResult<()> {
+        let preset = SecurityPreset::try_from(request.into_inner())?;
+        log::debug!("set_security_preset({})", preset);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSecurityPreset(tx, preset))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn get_security_preset(&self, _: Request<()>) -> ServiceResult<types::SecurityPreset> {
+        log::debug!("get_security_preset");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetSettings(tx))?;
+        self.wait_for_result(rx)
+            .await
+            .map(|settings| Response::new(SecurityPreset::matching(&settings).into()))
+    }
+
+    async fn set_allowed_networks(
+        &self,
+        request: Request<types::AllowedNetworks>,
+    ) -> ServiceResult<()> {
+        let networks = request
+            .into_inner()
+            .networks
+            .iter()
+            .map(|network| {
+                network
+                    .parse::<IpNetwork>()
+                    .map_err(|_| Status::invalid_argument(format!("Invalid network: {}", network)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let networks = validate_allowed_networks(networks)
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        log::debug!("set_allowed_networks({:?})", networks);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAllowedNetworks(tx, networks))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_show_beta_releases(&self, request: Request<bool>) -> ServiceResult<()> {
         let enabled = request.into_inner();
         log::debug!("set_show_beta_releases({})", enabled);
@@ -309,10 +669,86 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_lockdown_after_boot(&self, request: Request<bool>) -> ServiceResult<()> {
+        let lockdown_after_boot = request.into_inner();
+        log::debug!("set_lockdown_after_boot({})", lockdown_after_boot);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetLockdownAfterBoot(tx, lockdown_after_boot))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_reconnect_before_maintenance(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_reconnect_before_maintenance({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetReconnectBeforeMaintenance(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_relay_removed_reconnect_delay(
+        &self,
+        request: Request<types::Duration>,
+    ) -> ServiceResult<()> {
+        let delay = Duration::try_from(request.into_inner())
+            .map_err(|_| Status::invalid_argument("unexpected negative reconnect delay"))?;
+        if delay > MAX_RELAY_REMOVED_RECONNECT_DELAY {
+            return Err(Status::invalid_argument(format!(
+                "The reconnect delay may be at most {} seconds",
+                MAX_RELAY_REMOVED_RECONNECT_DELAY.as_secs()
+            )));
+        }
+        log::debug!("set_relay_removed_reconnect_delay({:?})", delay);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetRelayRemovedReconnectDelay(tx, delay))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_api_doh_fallback(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_api_doh_fallback({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetApiDohFallback(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_api_data_limit(&self, request: Request<u64>) -> ServiceResult<()> {
+        let limit = request.into_inner();
+        let limit = if limit != 0 { Some(limit) } else { None };
+        log::debug!("set_api_data_limit({:?})", limit);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetApiDataLimit(tx, limit))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_openvpn_mssfix(&self, request: Request<u32>) -> ServiceResult<()> {
         let mssfix = request.into_inner();
         let mssfix = if mssfix != 0 {
-            Some(mssfix as u16)
+            let mssfix = u16::try_from(mssfix)
+                .ok()
+                .filter(|mssfix| openvpn::TunnelOptions::is_valid_mssfix(*mssfix))
+                .ok_or_else(|| {
+                    Status::invalid_argument(format!(
+                        "mssfix must be between {} and {}",
+                        openvpn::MIN_MSSFIX,
+                        openvpn::MAX_MSSFIX
+                    ))
+                })?;
+            Some(mssfix)
         } else {
             None
         };
@@ -325,9 +761,22 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_openvpn_data_cipher(
+        &self,
+        request: Request<types::OpenvpnDataCipher>,
+    ) -> ServiceResult<()> {
+        let data_cipher = Option::<openvpn::DataCipher>::try_from(request.into_inner())?;
+        log::debug!("set_openvpn_data_cipher({:?})", data_cipher);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetOpenVpnDataCipher(tx, data_cipher))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_wireguard_mtu(&self, request: Request<u32>) -> ServiceResult<()> {
-        let mtu = request.into_inner();
-        let mtu = if mtu != 0 { Some(mtu as u16) } else { None };
+        let mtu = parse_wireguard_mtu(request.into_inner())?;
         log::debug!("set_wireguard_mtu({:?})", mtu);
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::SetWireguardMtu(tx, mtu))?;
@@ -337,6 +786,31 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_wireguard_persistent_keepalive(&self, request: Request<u32>) -> ServiceResult<()> {
+        let interval = parse_wireguard_persistent_keepalive(request.into_inner())?;
+        log::debug!("set_wireguard_persistent_keepalive({:?})", interval);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardPersistentKeepalive(tx, interval))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_wireguard_rate_limit(
+        &self,
+        request: Request<types::RateLimit>,
+    ) -> ServiceResult<()> {
+        let limit = parse_wireguard_rate_limit(request.into_inner())?;
+        log::debug!("set_wireguard_rate_limit({:?})", limit);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardRateLimit(tx, limit))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_enable_ipv6(&self, request: Request<bool>) -> ServiceResult<()> {
         let enable_ipv6 = request.into_inner();
         log::debug!("set_enable_ipv6({})", enable_ipv6);
@@ -350,7 +824,10 @@ impl ManagementService for ManagementServiceImpl {
 
     #[cfg(not(target_os = "android"))]
     async fn set_dns_options(&self, request: Request<types::DnsOptions>) -> ServiceResult<()> {
-        let options = DnsOptions::try_from(request.into_inner())?;
+        let mut options = DnsOptions::try_from(request.into_inner())?;
+        options.custom_options.addresses =
+            CustomDnsOptions::validate_addresses(options.custom_options.addresses)
+                .map_err(|error| Status::invalid_argument(error.to_string()))?;
         log::debug!("set_dns_options({:?})", options);
 
         let (tx, rx) = oneshot::channel();
@@ -358,7 +835,7 @@ impl ManagementService for ManagementServiceImpl {
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
-            .map_err(map_settings_error)
+            .map_err(map_daemon_error)
     }
 
     #[cfg(target_os = "android")]
@@ -479,6 +956,16 @@ impl ManagementService for ManagementServiceImpl {
             })
     }
 
+    async fn refresh_account_expiry(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("refresh_account_expiry");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RefreshAccountExpiry(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
     // WireGuard key management
     //
 
@@ -745,7 +1232,7 @@ impl ManagementInterfaceServer {
     pub async fn start(
         tunnel_tx: DaemonCommandSender,
     ) -> Result<(String, ManagementInterfaceEventBroadcaster), Error> {
-        let subscriptions = Arc::<RwLock<Vec<EventsListenerSender>>>::default();
+        let subscriptions = Arc::<RwLock<Vec<EventsListener>>>::default();
 
         let socket_path = mullvad_paths::get_rpc_socket_path()
             .to_string_lossy()
@@ -782,7 +1269,7 @@ impl ManagementInterfaceServer {
 /// A handle that allows broadcasting messages to all subscribers of the management interface.
 #[derive(Clone)]
 pub struct ManagementInterfaceEventBroadcaster {
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<Vec<EventsListener>>>,
     _close_handle: mpsc::Sender<()>,
 }
 
@@ -809,17 +1296,10 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
     /// Sends relays to all subscribers of the management interface.
     fn notify_relay_list(&self, relay_list: RelayList) {
         log::debug!("Broadcasting new relay list");
-        let mut new_list = types::RelayList {
-            countries: Vec::new(),
-        };
-        new_list.countries.reserve(relay_list.countries.len());
-        for country in relay_list.countries.into_iter() {
-            new_list
-                .countries
-                .push(types::RelayListCountry::from(country));
-        }
         self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::RelayList(new_list)),
+            event: Some(daemon_event::Event::RelayList(convert_relay_list(
+                relay_list,
+            ))),
         })
     }
 
@@ -840,13 +1320,127 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
             ))),
         })
     }
+
+    fn notify_migration_event(&self, migration_event: MigrationEvent) {
+        log::debug!(
+            "Broadcasting settings migration event: {:?}",
+            migration_event
+        );
+        use types::migration_event::MigrationEvent as Event;
+        let (event, version) = match migration_event {
+            MigrationEvent::Started => (Event::Started, 0),
+            MigrationEvent::Step { version } => (Event::Step, version as u32),
+            MigrationEvent::Completed => (Event::Completed, 0),
+        };
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::MigrationEvent(types::MigrationEvent {
+                event: i32::from(event),
+                version,
+            })),
+        })
+    }
+
+    fn notify_relay_deprecated(&self, relay: DeprecatedRelay) {
+        log::debug!("Broadcasting deprecated relay: {:?}", relay);
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::RelayDeprecated(
+                types::RelayDeprecated {
+                    hostname: relay.hostname,
+                    still_connected: relay.still_connected,
+                },
+            )),
+        })
+    }
+
+    fn notify_account_expiry(&self, expiry: AccountExpiry) {
+        log::debug!("Broadcasting account expiry: {}", expiry.expiry);
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::AccountExpiry(types::AccountExpiry {
+                expiry: Some(types::Timestamp {
+                    seconds: expiry.expiry.timestamp(),
+                    nanos: 0,
+                }),
+                fetched_at: Some(types::Timestamp {
+                    seconds: expiry.fetched_at.timestamp(),
+                    nanos: 0,
+                }),
+            })),
+        })
+    }
+
+    fn notify_relay_maintenance(&self, maintenance: RelayMaintenance) {
+        log::debug!("Broadcasting relay maintenance: {:?}", maintenance);
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::RelayMaintenance(
+                types::RelayMaintenance {
+                    hostname: maintenance.hostname,
+                    window: Some(types::MaintenanceWindow::from(maintenance.window)),
+                    reconnect_at: maintenance.reconnect_at.map(|time| types::Timestamp {
+                        seconds: time.timestamp(),
+                        nanos: 0,
+                    }),
+                },
+            )),
+        })
+    }
 }
 
 impl ManagementInterfaceEventBroadcaster {
     fn notify(&self, value: types::DaemonEvent) {
+        let value = Arc::new(value);
         let mut subscriptions = self.subscriptions.write();
         // TODO: using write-lock everywhere. use a mutex instead?
-        subscriptions.retain(|tx| tx.send(Ok(value.clone())).is_ok());
+        subscriptions.retain(|listener| listener.send(&value));
+    }
+}
+
+fn convert_relay_list(relay_list: RelayList) -> types::RelayList {
+    let mut new_list = types::RelayList {
+        countries: Vec::new(),
+    };
+    new_list.countries.reserve(relay_list.countries.len());
+    for country in relay_list.countries.into_iter() {
+        new_list
+            .countries
+            .push(types::RelayListCountry::from(country));
+    }
+    new_list
+}
+
+fn convert_api_traffic_stats(stats: TrafficSnapshot) -> types::ApiTrafficStats {
+    let convert_counts = |counts: ByteCounts| types::ByteCounts {
+        sent: counts.sent,
+        received: counts.received,
+    };
+    types::ApiTrafficStats {
+        total: Some(convert_counts(stats.total)),
+        by_category: stats
+            .by_category
+            .into_iter()
+            .map(|(category, counts)| (category, convert_counts(counts)))
+            .collect(),
+    }
+}
+
+fn convert_settings_import_report(
+    report: settings_transfer::ImportReport,
+) -> types::SettingsImportReport {
+    types::SettingsImportReport {
+        applied_sections: report
+            .applied
+            .into_iter()
+            .map(|section| section.name().to_owned())
+            .collect(),
+        skipped_sections: report
+            .skipped
+            .into_iter()
+            .map(
+                |(section, reason)| types::settings_import_report::SkippedSection {
+                    section: section.name().to_owned(),
+                    reason: reason.to_string(),
+                },
+            )
+            .collect(),
     }
 }
 
@@ -857,6 +1451,7 @@ fn map_daemon_error(error: crate::Error) -> Status {
     match error {
         DaemonError::RestError(error) => map_rest_error(error),
         DaemonError::SettingsError(error) => map_settings_error(error),
+        DaemonError::InvalidCustomDns(error) => Status::invalid_argument(error.to_string()),
         #[cfg(windows)]
         DaemonError::SplitTunnelError(error) => map_split_tunnel_error(error),
         DaemonError::AccountHistory(error) => map_account_history_error(error),
@@ -914,6 +1509,84 @@ fn map_rest_error(error: RestError) -> Status {
     }
 }
 
+fn convert_startup_state(state: StartupState) -> types::StartupState {
+    use types::startup_state::Reason;
+
+    let reason = match state.reason {
+        StartupReason::NoCachedState => Reason::NoCachedState,
+        StartupReason::Restored => Reason::Restored,
+        StartupReason::UnreadableCache => Reason::UnreadableCache,
+        StartupReason::AutoConnect => Reason::AutoConnect,
+        StartupReason::NoAccount => Reason::NoAccount,
+        StartupReason::LockdownAfterBoot => Reason::LockdownAfterBoot,
+    };
+    types::StartupState {
+        reason: i32::from(reason),
+        locked_down: state.locked_down,
+        previous_crash_report: state
+            .previous_crash_report
+            .map(|path| path.display().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// Converts an MTU received over gRPC, where `0` means that no MTU is set.
+fn parse_wireguard_mtu(mtu: u32) -> Result<Option<u16>, Status> {
+    if mtu == 0 {
+        return Ok(None);
+    }
+    u16::try_from(mtu)
+        .ok()
+        .filter(|mtu| wireguard::TunnelOptions::is_valid_mtu(*mtu))
+        .map(Some)
+        .ok_or_else(|| {
+            Status::invalid_argument(format!(
+                "mtu must be between {} and {}",
+                wireguard::MIN_MTU,
+                wireguard::MAX_MTU
+            ))
+        })
+}
+
+/// Converts a persistent keepalive interval received over gRPC, where `0` means that keepalive
+/// packets are not sent.
+fn parse_wireguard_persistent_keepalive(interval: u32) -> Result<Option<u16>, Status> {
+    if interval == 0 {
+        return Ok(None);
+    }
+    u16::try_from(interval)
+        .ok()
+        .filter(|interval| wireguard::TunnelOptions::is_valid_persistent_keepalive(*interval))
+        .map(Some)
+        .ok_or_else(|| {
+            Status::invalid_argument(format!(
+                "persistent keepalive must be between {} and {} seconds",
+                wireguard::MIN_PERSISTENT_KEEPALIVE,
+                wireguard::MAX_PERSISTENT_KEEPALIVE
+            ))
+        })
+}
+
+/// Converts a bandwidth limit received over gRPC, where both limits set to `0` means that the
+/// bandwidth is not limited. Limits that cannot be enforced on this platform are rejected.
+fn parse_wireguard_rate_limit(
+    limit: types::RateLimit,
+) -> Result<Option<wireguard::RateLimit>, Status> {
+    if limit.up_kbps == 0 && limit.down_kbps == 0 {
+        return Ok(None);
+    }
+    let limit = wireguard::RateLimit::from(limit);
+    rate_limit::check_supported(limit).map_err(|error| match error {
+        rate_limit::Error::InvalidLimit(_) => Status::invalid_argument(format!(
+            "bandwidth limits must be at least {} kbit/s",
+            wireguard::MIN_RATE_LIMIT_KBPS
+        )),
+        // Only fails with `Unsupported` otherwise
+        error => Status::unimplemented(error.to_string()),
+    })?;
+    Ok(Some(limit))
+}
+
 /// Converts an instance of [`mullvad_daemon::settings::Error`] into a tonic status.
 fn map_settings_error(error: settings::Error) -> Status {
     match error {
@@ -926,6 +1599,37 @@ fn map_settings_error(error: settings::Error) -> Status {
         settings::Error::SerializeError(..) | settings::Error::ParseError(..) => {
             Status::new(Code::Internal, error.to_string())
         }
+        settings::Error::InvalidAllowedNetworks(AllowedNetworksError::Unsupported) => {
+            Status::unimplemented(error.display_chain())
+        }
+        settings::Error::InvalidAllowedNetworks(..) => {
+            Status::invalid_argument(error.display_chain())
+        }
+    }
+}
+
+/// Converts an error from exporting or importing the API bootstrap state into a tonic status.
+fn map_api_bootstrap_error(error: api_bootstrap::Error) -> Status {
+    match error {
+        api_bootstrap::Error::Parse(..)
+        | api_bootstrap::Error::UnsupportedVersion(..)
+        | api_bootstrap::Error::ChecksumMismatch => Status::invalid_argument(error.display_chain()),
+        api_bootstrap::Error::WriteAddressCache(..)
+        | api_bootstrap::Error::WriteConnectionMode(..) => {
+            Status::failed_precondition(error.display_chain())
+        }
+        api_bootstrap::Error::Serialize(..) => Status::internal(error.display_chain()),
+    }
+}
+
+fn map_settings_transfer_error(error: settings_transfer::Error) -> Status {
+    match error {
+        settings_transfer::Error::Parse(..)
+        | settings_transfer::Error::UnsupportedVersion(..)
+        | settings_transfer::Error::NewerVersion(..)
+        | settings_transfer::Error::Migrate(..) => Status::invalid_argument(error.display_chain()),
+        settings_transfer::Error::WriteSettings(error) => map_settings_error(error),
+        settings_transfer::Error::Serialize(..) => Status::internal(error.display_chain()),
     }
 }
 
@@ -940,3 +1644,267 @@ fn map_account_history_error(error: account_history::Error) -> Status {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_wireguard_mtu() {
+        assert_eq!(parse_wireguard_mtu(0).unwrap(), None);
+        assert_eq!(parse_wireguard_mtu(1280).unwrap(), Some(1280));
+        assert_eq!(parse_wireguard_mtu(1420).unwrap(), Some(1420));
+
+        for mtu in [1279, 1421, u32::from(u16::MAX) + 1380] {
+            let status = parse_wireguard_mtu(mtu).unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn test_parse_wireguard_rate_limit() {
+        let off = types::RateLimit {
+            up_kbps: 0,
+            down_kbps: 0,
+        };
+        assert_eq!(parse_wireguard_rate_limit(off).unwrap(), None);
+
+        let too_low = types::RateLimit {
+            up_kbps: 5000,
+            down_kbps: 0,
+        };
+        let status = parse_wireguard_rate_limit(too_low).unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let limit = types::RateLimit {
+            up_kbps: 5000,
+            down_kbps: 20000,
+        };
+        if cfg!(target_os = "linux") {
+            assert_eq!(
+                parse_wireguard_rate_limit(limit).unwrap(),
+                Some(wireguard::RateLimit {
+                    up_kbps: 5000,
+                    down_kbps: 20000,
+                })
+            );
+        } else {
+            let status = parse_wireguard_rate_limit(limit).unwrap_err();
+            assert_eq!(status.code(), Code::Unimplemented);
+        }
+    }
+
+    #[test]
+    fn test_parse_wireguard_persistent_keepalive() {
+        assert_eq!(parse_wireguard_persistent_keepalive(0).unwrap(), None);
+        assert_eq!(parse_wireguard_persistent_keepalive(10).unwrap(), Some(10));
+        assert_eq!(
+            parse_wireguard_persistent_keepalive(120).unwrap(),
+            Some(120)
+        );
+
+        for interval in [9, 121, u32::from(u16::MAX) + 26] {
+            let status = parse_wireguard_persistent_keepalive(interval).unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+        }
+    }
+
+    /// Changes the settings from one thread while another thread subscribes, and checks that
+    /// the subscriber receives the settings at the time it subscribed followed by every change.
+    #[test]
+    fn test_snapshot_subscription_has_no_gap() {
+        enum Command {
+            ToggleAllowLan,
+            Subscribe(crate::SubscribeCallback),
+        }
+
+        const TOGGLES: usize = 200;
+        let subscriptions = Arc::<RwLock<Vec<EventsListener>>>::default();
+        let (close_handle, _close_rx) = mpsc::channel(0);
+        let broadcaster = ManagementInterfaceEventBroadcaster {
+            subscriptions: subscriptions.clone(),
+            _close_handle: close_handle,
+        };
+
+        // Stands in for the daemon loop, which handles commands and emits events in order
+        let (command_tx, command_rx) = std::sync::mpsc::channel();
+        let daemon = std::thread::spawn(move || {
+            let mut settings = Settings::default();
+            for command in command_rx {
+                match command {
+                    Command::ToggleAllowLan => {
+                        settings.allow_lan = !settings.allow_lan;
+                        broadcaster.notify_settings(settings.clone());
+                    }
+                    Command::Subscribe(callback) => callback(EventSnapshot {
+                        tunnel_state: TunnelState::Disconnected,
+                        settings: settings.clone(),
+                        relay_list: RelayList::empty(),
+                        app_version_info: None,
+                    }),
+                }
+            }
+            settings.allow_lan
+        });
+
+        let toggle_tx = command_tx.clone();
+        let toggler = std::thread::spawn(move || {
+            for _ in 0..TOGGLES {
+                toggle_tx.send(Command::ToggleAllowLan).unwrap();
+            }
+        });
+
+        let (listener, mut rx) = EventsListener::new(vec![EventCategory::Settings], TOGGLES + 1);
+        let listener_subscriptions = subscriptions.clone();
+        command_tx
+            .send(Command::Subscribe(Box::new(move |snapshot| {
+                listener.subscribe_with_snapshot(&listener_subscriptions, snapshot)
+            })))
+            .unwrap();
+
+        toggler.join().unwrap();
+        drop(command_tx);
+        let final_allow_lan = daemon.join().unwrap();
+        drop(subscriptions);
+
+        let received: Vec<bool> = tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut received = vec![];
+            while let Some(event) = rx.next().await {
+                match event.unwrap().event {
+                    Some(daemon_event::Event::Settings(settings)) => {
+                        received.push(settings.allow_lan)
+                    }
+                    other => panic!("Unexpected event: {:?}", other),
+                }
+            }
+            received
+        });
+
+        assert!(!received.is_empty() && received.len() <= TOGGLES + 1);
+        for pair in received.windows(2) {
+            assert_ne!(pair[0], pair[1], "a settings change was missed");
+        }
+        assert_eq!(received.last(), Some(&final_allow_lan));
+    }
+
+    fn new_broadcaster() -> (
+        ManagementInterfaceEventBroadcaster,
+        Arc<RwLock<Vec<EventsListener>>>,
+    ) {
+        let subscriptions = Arc::<RwLock<Vec<EventsListener>>>::default();
+        let (close_handle, _close_rx) = mpsc::channel(0);
+        let broadcaster = ManagementInterfaceEventBroadcaster {
+            subscriptions: subscriptions.clone(),
+            _close_handle: close_handle,
+        };
+        (broadcaster, subscriptions)
+    }
+
+    /// Returns the events that are queued for `rx`, without waiting for more.
+    fn queued_events(rx: &mut EventsListenerReceiver) -> Vec<Arc<types::DaemonEvent>> {
+        let mut events = vec![];
+        while let Ok(event) = rx.rx.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+
+    fn settings_with_allow_lan(allow_lan: bool) -> Settings {
+        Settings {
+            allow_lan,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn test_events_are_filtered_and_shared() {
+        let (broadcaster, subscriptions) = new_broadcaster();
+        let (all_listener, mut all_rx) = EventsListener::new(vec![], EVENT_QUEUE_SIZE);
+        let (state_listener, mut state_rx) =
+            EventsListener::new(vec![EventCategory::TunnelState], EVENT_QUEUE_SIZE);
+        let (settings_listener, mut settings_rx) =
+            EventsListener::new(vec![EventCategory::Settings], EVENT_QUEUE_SIZE);
+        subscriptions
+            .write()
+            .extend(vec![all_listener, state_listener, settings_listener]);
+
+        broadcaster.notify_new_state(TunnelState::Disconnected);
+        broadcaster.notify_settings(settings_with_allow_lan(true));
+
+        let all_events = queued_events(&mut all_rx);
+        let state_events = queued_events(&mut state_rx);
+        let settings_events = queued_events(&mut settings_rx);
+        assert_eq!(all_events.len(), 2);
+        assert_eq!(state_events.len(), 1);
+        assert_eq!(settings_events.len(), 1);
+        assert!(matches!(
+            state_events[0].event,
+            Some(daemon_event::Event::TunnelState(_))
+        ));
+        assert!(matches!(
+            settings_events[0].event,
+            Some(daemon_event::Event::Settings(_))
+        ));
+
+        // Each event is converted once, and shared by the subscribers that receive it
+        assert!(Arc::ptr_eq(&all_events[0], &state_events[0]));
+        assert!(Arc::ptr_eq(&all_events[1], &settings_events[0]));
+    }
+
+    #[test]
+    fn test_stalled_subscriber_gets_gap_event() {
+        const QUEUE_SIZE: usize = 2;
+        let (broadcaster, subscriptions) = new_broadcaster();
+        let (stalled_listener, mut stalled_rx) =
+            EventsListener::new(vec![EventCategory::Settings], QUEUE_SIZE);
+        let (listener, mut rx) = EventsListener::new(vec![EventCategory::Settings], 16);
+        subscriptions
+            .write()
+            .extend(vec![stalled_listener, listener]);
+
+        let mut allow_lan = false;
+        for _ in 0..5 {
+            allow_lan = !allow_lan;
+            broadcaster.notify_settings(settings_with_allow_lan(allow_lan));
+        }
+        // The stalled subscriber is kept, and the other subscriber is not affected
+        assert_eq!(subscriptions.read().len(), 2);
+        assert_eq!(queued_events(&mut rx).len(), 5);
+        assert_eq!(queued_events(&mut stalled_rx).len(), QUEUE_SIZE);
+
+        // The gap is reported before the next event
+        broadcaster.notify_settings(settings_with_allow_lan(!allow_lan));
+        let events = queued_events(&mut stalled_rx);
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0].event,
+            Some(daemon_event::Event::EventsDropped(types::EventsDropped {
+                count: 3
+            }))
+        );
+        assert!(matches!(
+            events[1].event,
+            Some(daemon_event::Event::Settings(_))
+        ));
+
+        // No gap is reported once the subscriber keeps up
+        broadcaster.notify_settings(settings_with_allow_lan(allow_lan));
+        let events = queued_events(&mut stalled_rx);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            events[0].event,
+            Some(daemon_event::Event::Settings(_))
+        ));
+    }
+
+    #[test]
+    fn test_closed_subscriber_is_removed() {
+        let (broadcaster, subscriptions) = new_broadcaster();
+        let (listener, rx) = EventsListener::new(vec![], EVENT_QUEUE_SIZE);
+        subscriptions.write().push(listener);
+        drop(rx);
+
+        broadcaster.notify_new_state(TunnelState::Disconnected);
+        assert!(subscriptions.read().is_empty());
+    }
+}
@@ -31,7 +31,16 @@
 //! 1. Implement the migration and add adequate tests.
 //! 1. Add to the changelog: "Settings format updated to `vY`"
 
-use std::path::Path;
+use crate::fs_retry;
+use mullvad_types::settings::Settings;
+use rand::{distributions::Alphanumeric, Rng};
+use std::{
+    cmp,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use talpid_core::mpsc::Sender;
+use talpid_types::ErrorExt;
 use tokio::{
     fs,
     io::{self, AsyncWriteExt},
@@ -47,6 +56,23 @@ mod v5;
 
 const SETTINGS_FILE: &str = "settings.json";
 
+/// Number of settings backups to keep. The oldest ones are removed when a new one is written.
+const MAX_SETTINGS_BACKUPS: usize = 3;
+const BACKUP_FILE_SUFFIX: &str = ".bak";
+
+/// All settings migrations, in the order they must be applied.
+const MIGRATIONS: [fn(&mut serde_json::Value) -> Result<()>; 5] = [
+    v1::migrate,
+    v2::migrate,
+    v3::migrate,
+    v4::migrate,
+    v5::migrate,
+];
+
+/// Keys whose values are replaced by [`redact_settings`], wherever they appear in the settings.
+const SENSITIVE_KEYS: [&str; 3] = ["account_token", "private_key", "access_token"];
+const REDACTED_VALUE: &str = "[REDACTED]";
+
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
 pub enum Error {
@@ -71,6 +97,12 @@ pub enum Error {
     #[error(display = "Unable to sync settings to disk")]
     SyncError(#[error(source)] io::Error),
 
+    #[error(display = "Unable to replace the settings file")]
+    RenameError(#[error(source)] io::Error),
+
+    #[error(display = "Unable to back up the settings before migrating them")]
+    BackupError(#[error(source)] io::Error),
+
     #[error(display = "Failed to read the account history")]
     ReadHistoryError(#[error(source)] io::Error),
 
@@ -83,15 +115,78 @@ pub enum Error {
     #[cfg(windows)]
     #[error(display = "Failed to restore Windows update backup")]
     WinMigrationError(#[error(source)] windows::Error),
+
+    #[cfg(target_os = "macos")]
+    #[error(display = "Failed to restore settings from before a macOS migration")]
+    MacOsMigrationError(#[error(source)] macos::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub async fn migrate_all(cache_dir: &Path, settings_dir: &Path) -> Result<()> {
+/// Reports the progress of [`migrate_all`], so that frontends can tell the user that the settings
+/// are being upgraded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationEvent {
+    /// The migration has started.
+    Started,
+    /// The migration from settings version `version` is running.
+    Step { version: u64 },
+    /// The migration has finished. This is always the last event, even if the migration failed
+    /// or nothing had to be migrated.
+    Completed,
+}
+
+/// A value in the settings that a migration adds, removes or changes. Each change holds the path
+/// to the value, such as `relay_settings.normal.location`. Values are left out, since they may
+/// contain account tokens or keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingsChange {
+    Added(String),
+    Removed(String),
+    Changed(String),
+}
+
+/// What [`migrate_all_dry_run`] would do to the settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub from_version: Option<u64>,
+    pub to_version: Option<u64>,
+    pub changes: Vec<SettingsChange>,
+}
+
+/// Ignores progress events, for migrations that are not shown to the user.
+struct IgnoreProgress;
+
+impl Sender<MigrationEvent> for IgnoreProgress {
+    fn send(&self, _: MigrationEvent) -> std::result::Result<(), ()> {
+        Ok(())
+    }
+}
+
+pub async fn migrate_all(
+    cache_dir: &Path,
+    settings_dir: &Path,
+    progress_tx: &impl Sender<MigrationEvent>,
+) -> Result<()> {
+    let _ = progress_tx.send(MigrationEvent::Started);
+    let result = migrate_all_inner(cache_dir, settings_dir, progress_tx).await;
+    let _ = progress_tx.send(MigrationEvent::Completed);
+    result
+}
+
+async fn migrate_all_inner(
+    cache_dir: &Path,
+    settings_dir: &Path,
+    progress_tx: &impl Sender<MigrationEvent>,
+) -> Result<()> {
     #[cfg(windows)]
     windows::migrate_after_windows_update(settings_dir)
         .await
         .map_err(Error::WinMigrationError)?;
+    #[cfg(target_os = "macos")]
+    macos::migrate_after_macos_migration(settings_dir)
+        .await
+        .map_err(Error::MacOsMigrationError)?;
 
     let path = settings_dir.join(SETTINGS_FILE);
 
@@ -99,22 +194,14 @@ pub async fn migrate_all(cache_dir: &Path, settings_dir: &Path) -> Result<()> {
         return Ok(());
     }
 
-    let settings_bytes = fs::read(&path).await.map_err(Error::ReadError)?;
-
-    let mut settings: serde_json::Value =
-        serde_json::from_reader(&settings_bytes[..]).map_err(Error::ParseError)?;
-
-    if !settings.is_object() {
-        return Err(Error::NoMatchingVersion);
-    }
+    let settings_bytes = fs_retry::retry(|| fs::read(&path))
+        .await
+        .map_err(Error::ReadError)?;
 
+    let mut settings = parse_settings(&settings_bytes)?;
     let old_settings = settings.clone();
 
-    v1::migrate(&mut settings)?;
-    v2::migrate(&mut settings)?;
-    v3::migrate(&mut settings)?;
-    v4::migrate(&mut settings)?;
-    v5::migrate(&mut settings)?;
+    migrate_settings(&mut settings, progress_tx)?;
 
     account_history::migrate_location(cache_dir, settings_dir).await;
     account_history::migrate_formats(settings_dir, &mut settings).await?;
@@ -124,8 +211,113 @@ pub async fn migrate_all(cache_dir: &Path, settings_dir: &Path) -> Result<()> {
         return Ok(());
     }
 
-    let buffer = serde_json::to_string_pretty(&settings).map_err(Error::SerializeError)?;
+    let old_version = settings_version(&old_settings);
+    if old_version != settings_version(&settings) {
+        let backup_path = write_backup(settings_dir, &settings_bytes, old_version).await?;
+        log::info!("Backed up old settings to {}", backup_path.display());
+    }
+
+    let buffer = serialize_settings(&settings)?;
+
+    // Write to a temporary file first, so that the settings are never left partially written
+    let mut temp_extension = String::from("temp");
+    temp_extension.extend(
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(5)
+            .map(char::from),
+    );
+    let temp_path = path.with_extension(temp_extension);
+    if let Err(error) = write_settings_file(&temp_path, buffer.as_bytes()).await {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(error);
+    }
+    fs_retry::retry(|| fs::rename(&temp_path, &path))
+        .await
+        .map_err(Error::RenameError)?;
+
+    log::debug!("Migrated settings. Wrote settings to {}", path.display());
 
+    Ok(())
+}
+
+/// Runs all settings migrations on the settings file in `settings_dir` without writing anything
+/// to disk, and reports what they would change. The platform specific restoration of settings
+/// from before an OS upgrade and the account history migration are not run, since they only
+/// apply to files on disk.
+pub async fn migrate_all_dry_run(settings_dir: &Path) -> Result<MigrationReport> {
+    let settings_bytes = fs::read(settings_dir.join(SETTINGS_FILE))
+        .await
+        .map_err(Error::ReadError)?;
+    migrate_bytes_dry_run(&settings_bytes)
+}
+
+fn migrate_bytes_dry_run(settings_bytes: &[u8]) -> Result<MigrationReport> {
+    let old_settings = parse_settings(settings_bytes)?;
+    let mut settings = old_settings.clone();
+    migrate_settings(&mut settings, &IgnoreProgress)?;
+    serialize_settings(&settings)?;
+
+    let mut changes = vec![];
+    diff_settings("", &old_settings, &settings, &mut changes);
+    Ok(MigrationReport {
+        from_version: settings_version(&old_settings),
+        to_version: settings_version(&settings),
+        changes,
+    })
+}
+
+fn parse_settings(settings_bytes: &[u8]) -> Result<serde_json::Value> {
+    let settings: serde_json::Value =
+        serde_json::from_reader(settings_bytes).map_err(Error::ParseError)?;
+    if !settings.is_object() {
+        return Err(Error::NoMatchingVersion);
+    }
+    Ok(settings)
+}
+
+/// Adds the paths of all values that differ between `old` and `new` to `changes`. Objects are
+/// compared key by key. Any other values, including arrays, are compared as a whole.
+fn diff_settings(
+    path: &str,
+    old: &serde_json::Value,
+    new: &serde_json::Value,
+    changes: &mut Vec<SettingsChange>,
+) {
+    let join = |key: &str| {
+        if path.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+
+    match (old, new) {
+        (serde_json::Value::Object(old), serde_json::Value::Object(new)) => {
+            for (key, old_value) in old {
+                match new.get(key) {
+                    Some(new_value) => diff_settings(&join(key), old_value, new_value, changes),
+                    None => changes.push(SettingsChange::Removed(join(key))),
+                }
+            }
+            for key in new.keys().filter(|key| !old.contains_key(*key)) {
+                changes.push(SettingsChange::Added(join(key)));
+            }
+        }
+        (old, new) if old != new => changes.push(SettingsChange::Changed(path.to_owned())),
+        _ => (),
+    }
+}
+
+/// Serializes the migrated settings, and checks that the result can be loaded as the current
+/// settings format.
+fn serialize_settings(settings: &serde_json::Value) -> Result<String> {
+    let buffer = serde_json::to_string_pretty(settings).map_err(Error::SerializeError)?;
+    serde_json::from_str::<Settings>(&buffer).map_err(Error::SerializeError)?;
+    Ok(buffer)
+}
+
+async fn write_settings_file(path: &Path, buffer: &[u8]) -> Result<()> {
     let mut options = fs::OpenOptions::new();
     #[cfg(unix)]
     {
@@ -135,19 +327,170 @@ pub async fn migrate_all(cache_dir: &Path, settings_dir: &Path) -> Result<()> {
         .create(true)
         .write(true)
         .truncate(true)
-        .open(&path)
+        .open(path)
         .await
         .map_err(Error::OpenError)?;
-    file.write_all(&buffer.into_bytes())
-        .await
-        .map_err(Error::WriteError)?;
-    file.sync_data().await.map_err(Error::SyncError)?;
+    file.write_all(buffer).await.map_err(Error::WriteError)?;
+    file.sync_data().await.map_err(Error::SyncError)
+}
 
-    log::debug!("Migrated settings. Wrote settings to {}", path.display());
+/// Migrates settings that may only contain some of the keys of the settings file, such as
+/// exported settings, to the current format. Missing keys are left out.
+pub fn migrate_partial(settings: &mut serde_json::Value) -> Result<()> {
+    migrate_settings(settings, &IgnoreProgress)
+}
 
+fn migrate_settings(
+    settings: &mut serde_json::Value,
+    progress_tx: &impl Sender<MigrationEvent>,
+) -> Result<()> {
+    for (index, migrate) in MIGRATIONS.iter().enumerate() {
+        let _ = progress_tx.send(MigrationEvent::Step {
+            version: index as u64 + 1,
+        });
+        let version_before = settings_version(settings);
+        migrate(settings)?;
+        let version_after = settings_version(settings);
+        debug_assert!(
+            is_valid_version_step(version_before, version_after),
+            "Settings migration went from version {:?} to {:?}",
+            version_before,
+            version_after,
+        );
+    }
     Ok(())
 }
 
+/// Returns the version of the settings. Settings without a version are in the V1 format.
+fn settings_version(settings: &serde_json::Value) -> Option<u64> {
+    match settings.get("settings_version") {
+        Some(version) => version.as_u64(),
+        None => Some(1),
+    }
+}
+
+/// A migration must either leave the version alone or bump it to the next version.
+fn is_valid_version_step(before: Option<u64>, after: Option<u64>) -> bool {
+    before == after || before.map(|version| version + 1) == after
+}
+
+/// Writes `settings_bytes`, the settings as they were before migrating, to a new backup file in
+/// `settings_dir`, and removes all but the most recent backups.
+async fn write_backup(
+    settings_dir: &Path,
+    settings_bytes: &[u8],
+    version: Option<u64>,
+) -> Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or(0);
+    let path = settings_dir.join(backup_file_name(version, timestamp));
+
+    let mut options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    {
+        options.mode(0o600);
+    }
+    let mut file = options
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&path)
+        .await
+        .map_err(Error::BackupError)?;
+    file.write_all(settings_bytes)
+        .await
+        .map_err(Error::BackupError)?;
+    file.sync_data().await.map_err(Error::BackupError)?;
+
+    remove_old_backups(settings_dir).await;
+
+    Ok(path)
+}
+
+fn backup_file_name(version: Option<u64>, timestamp: u64) -> String {
+    let version = version
+        .map(|version| version.to_string())
+        .unwrap_or_else(|| "unknown".to_owned());
+    format!(
+        "{}.v{}.{}{}",
+        SETTINGS_FILE, version, timestamp, BACKUP_FILE_SUFFIX
+    )
+}
+
+/// Returns the timestamp of a backup written by [`write_backup`], or `None` if `file_name` is not
+/// a settings backup.
+fn backup_timestamp(file_name: &str) -> Option<u64> {
+    let version_and_timestamp = file_name
+        .strip_prefix(SETTINGS_FILE)?
+        .strip_prefix(".v")?
+        .strip_suffix(BACKUP_FILE_SUFFIX)?;
+    let (_version, timestamp) = version_and_timestamp.rsplit_once('.')?;
+    timestamp.parse().ok()
+}
+
+/// Returns the backups among `file_names` that must be removed so that only the
+/// `MAX_SETTINGS_BACKUPS` most recent ones remain.
+fn backups_to_remove(mut file_names: Vec<String>) -> Vec<String> {
+    file_names.retain(|file_name| backup_timestamp(file_name).is_some());
+    file_names.sort_by_key(|file_name| cmp::Reverse(backup_timestamp(file_name)));
+    file_names.split_off(cmp::min(MAX_SETTINGS_BACKUPS, file_names.len()))
+}
+
+async fn remove_old_backups(settings_dir: &Path) {
+    let mut file_names = vec![];
+    let mut entries = match fs::read_dir(settings_dir).await {
+        Ok(entries) => entries,
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to list old settings backups")
+            );
+            return;
+        }
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if let Some(file_name) = entry.file_name().to_str() {
+            file_names.push(file_name.to_owned());
+        }
+    }
+
+    for file_name in backups_to_remove(file_names) {
+        if let Err(error) = fs::remove_file(settings_dir.join(&file_name)).await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg(&format!("Failed to remove {}", file_name))
+            );
+        }
+    }
+}
+
+/// Returns a copy of the given settings with account tokens, access tokens and WireGuard private
+/// keys masked, so that the result can be attached to problem reports. The structure of the
+/// settings is preserved, and keys that have no value set are left as they are.
+pub fn redact_settings(value: &serde_json::Value) -> serde_json::Value {
+    let mut redacted = value.clone();
+    redact_value(&mut redacted);
+    redacted
+}
+
+fn redact_value(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if SENSITIVE_KEYS.contains(&key.as_str()) && !value.is_null() {
+                    *value = serde_json::Value::String(REDACTED_VALUE.to_owned());
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_value),
+        _ => (),
+    }
+}
+
 #[cfg(windows)]
 mod windows {
     use std::{ffi::OsStr, io, os::windows::ffi::OsStrExt, path::Path, ptr};
@@ -341,3 +684,426 @@ mod windows {
         unsafe { IsWellKnownSid(sid as *const SID as *mut _, well_known_sid_type) == TRUE }
     }
 }
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use std::{io, os::unix::fs::MetadataExt, path::Path};
+    use talpid_types::ErrorExt;
+    use tokio::fs;
+
+    /// Directories that hold the system files of the previous installation after an upgrade or
+    /// a transfer with Migration Assistant.
+    const MIGRATION_DIRS: [&str; 2] =
+        ["/Previous System", "/Users/Shared/Relocated Items/Security"];
+    const MIGRATE_FILES: [(&str, bool); 2] =
+        [("settings.json", true), ("account-history.json", false)];
+    const ROOT_UID: u32 = 0;
+
+    #[derive(err_derive::Error, Debug)]
+    #[error(no_from)]
+    pub enum Error {
+        #[error(display = "Could not read the owner of the backup directory")]
+        Metadata(#[error(source)] io::Error),
+
+        #[error(display = "Backup directory is not owned by root")]
+        WrongOwner,
+
+        #[error(display = "Failed to copy files during migration")]
+        IoError(#[error(source)] io::Error),
+    }
+
+    /// Attempts to restore the Mullvad settings from the system files that macOS keeps after an
+    /// upgrade or a migration. Upon success, it returns `Ok(true)` if the migration succeeded,
+    /// and `Ok(false)` if no migration was needed.
+    pub async fn migrate_after_macos_migration(
+        destination_settings_dir: &Path,
+    ) -> Result<bool, Error> {
+        if destination_settings_dir.join(super::SETTINGS_FILE).exists() {
+            return Ok(false);
+        }
+        let relative_settings_dir = match destination_settings_dir.strip_prefix("/") {
+            Ok(relative_dir) => relative_dir,
+            Err(_) => return Ok(false),
+        };
+
+        let source_settings_dir = MIGRATION_DIRS
+            .iter()
+            .map(|dir| Path::new(dir).join(relative_settings_dir))
+            .find(|dir| dir.join(super::SETTINGS_FILE).exists());
+        match source_settings_dir {
+            Some(source_settings_dir) => {
+                migrate_from_dir(&source_settings_dir, destination_settings_dir, ROOT_UID).await
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn migrate_from_dir(
+        source_settings_dir: &Path,
+        destination_settings_dir: &Path,
+        expected_owner: u32,
+    ) -> Result<bool, Error> {
+        let metadata = fs::metadata(source_settings_dir)
+            .await
+            .map_err(Error::Metadata)?;
+        if metadata.uid() != expected_owner {
+            return Err(Error::WrongOwner);
+        }
+
+        if !destination_settings_dir.exists() {
+            fs::create_dir_all(destination_settings_dir)
+                .await
+                .map_err(Error::IoError)?;
+        }
+
+        let mut result = Ok(true);
+
+        for (file, required) in &MIGRATE_FILES {
+            let from = source_settings_dir.join(file);
+            let to = destination_settings_dir.join(file);
+
+            match fs::metadata(&from).await {
+                Ok(metadata) if metadata.uid() != expected_owner => {
+                    log::error!(
+                        "Not migrating {} since it is not owned by root",
+                        from.display()
+                    );
+                    if *required {
+                        result = Err(Error::WrongOwner);
+                    }
+                    continue;
+                }
+                Ok(_) => (),
+                Err(error) => {
+                    if *required {
+                        result = Err(Error::IoError(error));
+                    }
+                    continue;
+                }
+            }
+
+            log::debug!("Migrating {} to {}", from.display(), to.display());
+
+            match fs::copy(&from, &to).await {
+                Ok(_) => {
+                    let _ = fs::remove_file(from).await;
+                }
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "Failed to copy {} to {}",
+                            from.display(),
+                            to.display()
+                        ))
+                    );
+                    if *required {
+                        result = Err(Error::IoError(error));
+                    }
+                }
+            }
+        }
+
+        if let Err(error) = fs::remove_dir(source_settings_dir).await {
+            log::trace!(
+                "{}",
+                error.display_chain_with_msg("Failed to delete backup directory")
+            );
+        }
+
+        result
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn test_migrate_from_dir() {
+            let source_dir = crate::test::new_temp_dir();
+            let destination_dir = crate::test::new_temp_dir().join("settings");
+            std::fs::write(source_dir.join("settings.json"), b"{}").unwrap();
+            let owner = std::fs::metadata(&source_dir).unwrap().uid();
+
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+
+            // Another owner than the expected one is rejected
+            assert!(matches!(
+                runtime.block_on(migrate_from_dir(
+                    &source_dir,
+                    &destination_dir,
+                    owner.wrapping_add(1)
+                )),
+                Err(Error::WrongOwner)
+            ));
+            assert!(!destination_dir.exists());
+
+            // The account history is optional
+            assert!(runtime
+                .block_on(migrate_from_dir(&source_dir, &destination_dir, owner))
+                .unwrap());
+            assert!(destination_dir.join("settings.json").exists());
+            assert!(!source_dir.exists());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        backup_file_name, backup_timestamp, backups_to_remove, diff_settings,
+        is_valid_version_step, migrate_all, migrate_all_dry_run, migrate_bytes_dry_run,
+        migrate_settings, redact_settings, serialize_settings, settings_version, Error,
+        MigrationEvent, Settings, SettingsChange, MAX_SETTINGS_BACKUPS, MIGRATIONS, SETTINGS_FILE,
+    };
+    use crate::test::new_temp_dir;
+    use mullvad_types::settings::CURRENT_SETTINGS_VERSION;
+    use std::sync::Mutex;
+    use talpid_core::mpsc::Sender;
+
+    #[derive(Default)]
+    struct EventRecorder(Mutex<Vec<MigrationEvent>>);
+
+    impl Sender<MigrationEvent> for EventRecorder {
+        fn send(&self, event: MigrationEvent) -> Result<(), ()> {
+            self.0.lock().unwrap().push(event);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_migrations_are_monotonic() {
+        let mut settings = serde_json::json!({});
+        let mut version = settings_version(&settings).unwrap();
+        assert_eq!(version, 1);
+
+        for migrate in &MIGRATIONS {
+            migrate(&mut settings).unwrap();
+            let new_version = settings_version(&settings).expect("Invalid settings version");
+            assert!(
+                new_version == version || new_version == version + 1,
+                "Migration went from version {} to {}",
+                version,
+                new_version
+            );
+            version = new_version;
+        }
+
+        assert_eq!(version, CURRENT_SETTINGS_VERSION as u64);
+    }
+
+    #[test]
+    fn test_migrate_settings_ends_at_current_version() {
+        for start_version in 2..=CURRENT_SETTINGS_VERSION as u64 {
+            let mut settings = serde_json::json!({ "settings_version": start_version });
+            migrate_settings(&mut settings, &EventRecorder::default()).unwrap();
+            assert_eq!(
+                settings_version(&settings),
+                Some(CURRENT_SETTINGS_VERSION as u64)
+            );
+        }
+    }
+
+    #[test]
+    fn test_version_steps() {
+        assert!(is_valid_version_step(Some(1), Some(1)));
+        assert!(is_valid_version_step(Some(1), Some(2)));
+        assert!(!is_valid_version_step(Some(2), Some(4)));
+        assert!(!is_valid_version_step(Some(3), Some(2)));
+        assert!(!is_valid_version_step(Some(3), None));
+    }
+
+    const SETTINGS: &str = r#"
+{
+  "account_token": "1234567890123456",
+  "wireguard": {
+    "private_key": "mAdSb0P5ttloOwK7Jp0PC2u6meUT+BPE3sNihrwdg3g=",
+    "addresses": {
+      "ipv4_address": "10.64.10.1/32",
+      "ipv6_address": "fc00:bbbb:bbbb:bb01::1:a01/128"
+    },
+    "created": "2021-11-23T12:30:00Z"
+  },
+  "access_token": null,
+  "allow_lan": true,
+  "settings_version": 5
+}
+"#;
+
+    #[test]
+    fn test_redact_settings() {
+        let settings: serde_json::Value = serde_json::from_str(SETTINGS).unwrap();
+        let redacted = redact_settings(&settings);
+
+        assert_eq!(redacted["account_token"], "[REDACTED]");
+        assert_eq!(redacted["wireguard"]["private_key"], "[REDACTED]");
+        assert!(redacted["access_token"].is_null());
+
+        assert_eq!(
+            redacted["wireguard"]["addresses"],
+            settings["wireguard"]["addresses"]
+        );
+        assert_eq!(
+            redacted["wireguard"]["created"],
+            settings["wireguard"]["created"]
+        );
+        assert_eq!(redacted["allow_lan"], settings["allow_lan"]);
+        assert_eq!(redacted["settings_version"], settings["settings_version"]);
+        assert_eq!(
+            redacted.as_object().unwrap().len(),
+            settings.as_object().unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_backup_file_names() {
+        let file_name = backup_file_name(Some(5), 1650000000);
+        assert_eq!(file_name, "settings.json.v5.1650000000.bak");
+        assert_eq!(backup_timestamp(&file_name), Some(1650000000));
+        assert_eq!(
+            backup_timestamp(&backup_file_name(None, 10)),
+            Some(10),
+            "backups of unversioned settings must be recognized"
+        );
+
+        assert_eq!(backup_timestamp("settings.json"), None);
+        assert_eq!(backup_timestamp("account-history.json"), None);
+        assert_eq!(backup_timestamp("settings.json.v5.bak"), None);
+    }
+
+    #[test]
+    fn test_backups_to_remove() {
+        let mut file_names: Vec<String> = (0..MAX_SETTINGS_BACKUPS as u64 + 2)
+            .map(|timestamp| backup_file_name(Some(4), 1000 + timestamp))
+            .collect();
+        file_names.push("settings.json".to_owned());
+        file_names.reverse();
+
+        let mut removed = backups_to_remove(file_names);
+        removed.sort();
+        assert_eq!(
+            removed,
+            vec![
+                backup_file_name(Some(4), 1000),
+                backup_file_name(Some(4), 1001)
+            ]
+        );
+
+        assert!(backups_to_remove(vec![backup_file_name(Some(4), 1000)]).is_empty());
+    }
+
+    #[test]
+    fn test_serialized_settings_must_load() {
+        let settings = serde_json::to_value(Settings::default()).unwrap();
+        assert!(serialize_settings(&settings).is_ok());
+
+        let mut invalid_settings = settings;
+        invalid_settings["settings_version"] = serde_json::json!("five");
+        assert!(matches!(
+            serialize_settings(&invalid_settings),
+            Err(Error::SerializeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let v4_settings = r#"{
+  "account_token": "1234567890123456",
+  "allow_lan": true,
+  "show_beta_releases": false,
+  "settings_version": 4
+}"#;
+        let report = migrate_bytes_dry_run(v4_settings.as_bytes()).unwrap();
+        assert_eq!(report.from_version, Some(4));
+        assert_eq!(report.to_version, Some(CURRENT_SETTINGS_VERSION as u64));
+        assert!(report
+            .changes
+            .contains(&SettingsChange::Changed("settings_version".to_owned())));
+        assert!(!report
+            .changes
+            .iter()
+            .any(|change| *change == SettingsChange::Changed("allow_lan".to_owned())));
+
+        assert!(matches!(
+            migrate_bytes_dry_run(b"[]"),
+            Err(Error::NoMatchingVersion)
+        ));
+    }
+
+    #[test]
+    fn test_dry_run_does_not_write() {
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        let dir = new_temp_dir();
+        let v4_settings = r#"{"account_token": null, "settings_version": 4}"#;
+        std::fs::write(dir.join(SETTINGS_FILE), v4_settings).unwrap();
+
+        let report = runtime.block_on(migrate_all_dry_run(&dir)).unwrap();
+        assert_eq!(report.from_version, Some(4));
+        assert_eq!(
+            std::fs::read_to_string(dir.join(SETTINGS_FILE)).unwrap(),
+            v4_settings
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_diff_settings() {
+        let old = serde_json::json!({
+            "a": 1,
+            "b": { "c": true, "d": [1, 2] },
+            "e": "removed",
+        });
+        let new = serde_json::json!({
+            "a": 1,
+            "b": { "c": false, "d": [1, 2], "f": null },
+            "g": "added",
+        });
+        let mut changes = vec![];
+        diff_settings("", &old, &new, &mut changes);
+        assert_eq!(
+            changes,
+            vec![
+                SettingsChange::Changed("b.c".to_owned()),
+                SettingsChange::Added("b.f".to_owned()),
+                SettingsChange::Removed("e".to_owned()),
+                SettingsChange::Added("g".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_migration_events() {
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        let dir = new_temp_dir();
+
+        // Without a settings file, there is nothing to migrate
+        let events = EventRecorder::default();
+        runtime.block_on(migrate_all(&dir, &dir, &events)).unwrap();
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            vec![MigrationEvent::Started, MigrationEvent::Completed]
+        );
+
+        std::fs::write(dir.join(SETTINGS_FILE), SETTINGS).unwrap();
+        let events = EventRecorder::default();
+        runtime.block_on(migrate_all(&dir, &dir, &events)).unwrap();
+        let mut expected_events = vec![MigrationEvent::Started];
+        expected_events
+            .extend((1..=MIGRATIONS.len() as u64).map(|version| MigrationEvent::Step { version }));
+        expected_events.push(MigrationEvent::Completed);
+        assert_eq!(*events.0.lock().unwrap(), expected_events);
+
+        // Invalid settings still end with a `Completed` event
+        std::fs::write(dir.join(SETTINGS_FILE), "[]").unwrap();
+        let events = EventRecorder::default();
+        assert!(runtime.block_on(migrate_all(&dir, &dir, &events)).is_err());
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            vec![MigrationEvent::Started, MigrationEvent::Completed]
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
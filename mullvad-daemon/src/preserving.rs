@@ -0,0 +1,213 @@
+//! Keeps the fields of a persisted JSON file that the current version does not know about, so
+//! that they are written back when the file is saved. Otherwise, settings added by a newer
+//! version would be lost after downgrading and upgrading again.
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+use std::ops::{Deref, DerefMut};
+
+/// A value read from a JSON file, together with the fields in the file that `T` does not have.
+#[derive(Debug, Clone)]
+pub struct Preserving<T> {
+    value: T,
+    unknown_fields: Vec<UnknownField>,
+}
+
+/// A field that is not part of the deserialized type, and the path of the object it is in.
+#[derive(Debug, Clone, PartialEq)]
+struct UnknownField {
+    parents: Vec<String>,
+    key: String,
+    value: Value,
+}
+
+impl<T: Serialize + DeserializeOwned> Preserving<T> {
+    /// Wraps a value that was not read from a file.
+    pub fn new(value: T) -> Self {
+        Preserving {
+            value,
+            unknown_fields: vec![],
+        }
+    }
+
+    /// Deserializes `bytes` and remembers the fields that `T` does not have. `name` is used to
+    /// log that unknown fields were found.
+    pub fn from_slice(bytes: &[u8], name: &str) -> serde_json::Result<Self> {
+        let original: Value = serde_json::from_slice(bytes)?;
+        let value = T::deserialize(&original)?;
+        let known = serde_json::to_value(&value)?;
+
+        let mut unknown_fields = vec![];
+        if let (Value::Object(original), Value::Object(known)) = (original, &known) {
+            find_unknown_fields(original, known, &mut vec![], &mut unknown_fields);
+        }
+        if !unknown_fields.is_empty() {
+            log::info!(
+                "Preserving unknown fields in {}: {}",
+                name,
+                unknown_fields
+                    .iter()
+                    .map(UnknownField::path)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        Ok(Preserving {
+            value,
+            unknown_fields,
+        })
+    }
+
+    /// Serializes the value, including the unknown fields it was read with.
+    pub fn to_string_pretty(&self) -> serde_json::Result<String> {
+        let mut output = serde_json::to_value(&self.value)?;
+        if let Value::Object(output) = &mut output {
+            for field in &self.unknown_fields {
+                field.insert_into(output);
+            }
+        }
+        serde_json::to_string_pretty(&output)
+    }
+}
+
+impl UnknownField {
+    fn path(&self) -> String {
+        let mut path = self.parents.clone();
+        path.push(self.key.clone());
+        path.join(".")
+    }
+
+    /// Adds the field to `object`, unless the field is known now or its parent no longer exists.
+    fn insert_into(&self, object: &mut Map<String, Value>) {
+        let mut parent = object;
+        for key in &self.parents {
+            parent = match parent.get_mut(key) {
+                Some(Value::Object(child)) => child,
+                _ => return,
+            };
+        }
+        if !parent.contains_key(&self.key) {
+            parent.insert(self.key.clone(), self.value.clone());
+        }
+    }
+}
+
+/// Collects the fields in `original` that are missing from `known`, recursing into objects that
+/// are in both.
+fn find_unknown_fields(
+    original: Map<String, Value>,
+    known: &Map<String, Value>,
+    parents: &mut Vec<String>,
+    unknown_fields: &mut Vec<UnknownField>,
+) {
+    for (key, value) in original {
+        match (value, known.get(&key)) {
+            (value, None) => unknown_fields.push(UnknownField {
+                parents: parents.clone(),
+                key,
+                value,
+            }),
+            (Value::Object(original), Some(Value::Object(known))) => {
+                parents.push(key);
+                find_unknown_fields(original, known, parents, unknown_fields);
+                parents.pop();
+            }
+            _ => (),
+        }
+    }
+}
+
+impl<T> Deref for Preserving<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Preserving<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct State {
+        name: String,
+        inner: Inner,
+        choice: Choice,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Inner {
+        enabled: bool,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    enum Choice {
+        First { value: u32 },
+        Second,
+    }
+
+    const NEWER_FILE: &str = r#"{
+        "name": "old",
+        "added_later": [1, 2, 3],
+        "inner": { "enabled": false, "also_added": { "nested": true } },
+        "choice": { "first": { "value": 1, "extra": "x" } }
+    }"#;
+
+    #[test]
+    fn test_unknown_fields_survive_save() {
+        let mut state: Preserving<State> =
+            Preserving::from_slice(NEWER_FILE.as_bytes(), "test state").unwrap();
+        assert_eq!(state.name, "old");
+        state.name = "new".to_owned();
+        state.inner.enabled = true;
+
+        let saved: Value = serde_json::from_str(&state.to_string_pretty().unwrap()).unwrap();
+        let expected: Value = serde_json::from_str(
+            r#"{
+                "name": "new",
+                "added_later": [1, 2, 3],
+                "inner": { "enabled": true, "also_added": { "nested": true } },
+                "choice": { "first": { "value": 1, "extra": "x" } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(saved, expected);
+
+        // Loading the saved file again finds the same unknown fields
+        let reloaded: Preserving<State> =
+            Preserving::from_slice(saved.to_string().as_bytes(), "test state").unwrap();
+        assert_eq!(reloaded.unknown_fields, state.unknown_fields);
+    }
+
+    #[test]
+    fn test_unknown_fields_of_replaced_object_are_dropped() {
+        let mut state: Preserving<State> =
+            Preserving::from_slice(NEWER_FILE.as_bytes(), "test state").unwrap();
+        state.choice = Choice::Second;
+
+        let saved: Value = serde_json::from_str(&state.to_string_pretty().unwrap()).unwrap();
+        assert_eq!(saved["choice"], Value::from("second"));
+        assert_eq!(saved["added_later"], serde_json::json!([1, 2, 3]));
+    }
+
+    #[test]
+    fn test_new_value_has_no_unknown_fields() {
+        let state = Preserving::new(State {
+            name: "name".to_owned(),
+            inner: Inner { enabled: true },
+            choice: Choice::Second,
+        });
+        let saved: Value = serde_json::from_str(&state.to_string_pretty().unwrap()).unwrap();
+        assert_eq!(saved, serde_json::to_value(&*state).unwrap());
+    }
+}
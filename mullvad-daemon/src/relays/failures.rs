@@ -0,0 +1,157 @@
+//! Keeps track of relays that recently failed to connect, so that the relay selector can avoid
+//! them for a while. Nothing is persisted: failures are forgotten when the daemon restarts.
+
+use mullvad_types::relay_list::{FailedRelay, Relay};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// How long a relay is avoided after it failed to connect.
+const FAILURE_PENALTY: Duration = Duration::from_secs(15 * 60);
+
+pub struct RelayFailures {
+    /// When the penalty of each failed relay expires.
+    penalties: HashMap<String, Instant>,
+    penalty: Duration,
+}
+
+impl RelayFailures {
+    pub fn new() -> Self {
+        Self::with_penalty(FAILURE_PENALTY)
+    }
+
+    fn with_penalty(penalty: Duration) -> Self {
+        RelayFailures {
+            penalties: HashMap::new(),
+            penalty,
+        }
+    }
+
+    /// Records that connecting to `hostname` failed. Any previous penalty is extended.
+    pub fn report(&mut self, hostname: &str) {
+        self.penalties
+            .insert(hostname.to_owned(), Instant::now() + self.penalty);
+    }
+
+    pub fn clear(&mut self) {
+        self.penalties.clear();
+    }
+
+    /// Removes the relays that recently failed from `relays`, unless no other relays remain.
+    pub fn filter(&mut self, relays: Vec<Relay>) -> Vec<Relay> {
+        self.remove_expired(Instant::now());
+        if self.penalties.is_empty() {
+            return relays;
+        }
+
+        let (penalized, healthy): (Vec<Relay>, Vec<Relay>) = relays
+            .into_iter()
+            .partition(|relay| self.penalties.contains_key(&relay.hostname));
+        if healthy.is_empty() {
+            return penalized;
+        }
+        if !penalized.is_empty() {
+            log::debug!(
+                "Avoiding relays that recently failed: {}",
+                penalized
+                    .iter()
+                    .map(|relay| relay.hostname.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        healthy
+    }
+
+    /// Returns the relays that are currently avoided and how long until they are selected again.
+    pub fn failed_relays(&mut self) -> Vec<FailedRelay> {
+        let now = Instant::now();
+        self.remove_expired(now);
+
+        let mut failed_relays: Vec<FailedRelay> = self
+            .penalties
+            .iter()
+            .map(|(hostname, expiry)| FailedRelay {
+                hostname: hostname.clone(),
+                penalty_remaining: expiry.saturating_duration_since(now),
+            })
+            .collect();
+        failed_relays.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        failed_relays
+    }
+
+    fn remove_expired(&mut self, now: Instant) {
+        self.penalties.retain(|_, expiry| *expiry > now);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::relay_list::{RelayBridges, RelayTunnels};
+
+    fn relay(hostname: &str) -> Relay {
+        Relay {
+            hostname: hostname.to_string(),
+            ipv4_addr_in: "192.0.2.1".parse().unwrap(),
+            ipv6_addr_in: None,
+            include_in_country: true,
+            active: true,
+            owned: true,
+            provider: "31173".to_string(),
+            asn: None,
+            asn_organization: None,
+            weight: 1,
+            tunnels: RelayTunnels::default(),
+            bridges: RelayBridges::default(),
+            location: None,
+            maintenance: None,
+        }
+    }
+
+    fn hostnames(relays: &[Relay]) -> Vec<&str> {
+        relays.iter().map(|relay| relay.hostname.as_str()).collect()
+    }
+
+    #[test]
+    fn test_failed_relays_are_avoided() {
+        let mut failures = RelayFailures::new();
+        let relays = vec![relay("se9-wireguard"), relay("se10-wireguard")];
+
+        failures.report("se9-wireguard");
+        assert_eq!(
+            hostnames(&failures.filter(relays.clone())),
+            vec!["se10-wireguard"]
+        );
+        assert_eq!(
+            failures
+                .failed_relays()
+                .into_iter()
+                .map(|failed| failed.hostname)
+                .collect::<Vec<_>>(),
+            vec!["se9-wireguard".to_string()]
+        );
+
+        // If every matching relay failed, they are all still candidates.
+        failures.report("se10-wireguard");
+        assert_eq!(
+            hostnames(&failures.filter(relays.clone())),
+            vec!["se9-wireguard", "se10-wireguard"]
+        );
+
+        failures.clear();
+        assert!(failures.failed_relays().is_empty());
+    }
+
+    #[test]
+    fn test_penalty_expires() {
+        let mut failures = RelayFailures::with_penalty(Duration::from_millis(0));
+        failures.report("se9-wireguard");
+        assert!(failures.failed_relays().is_empty());
+        assert_eq!(
+            hostnames(&failures.filter(vec![relay("se9-wireguard"), relay("se10-wireguard")])),
+            vec!["se9-wireguard", "se10-wireguard"]
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! Avoids relays whose maintenance is about to start, and plans when the daemon should move away
+//! from a relay that it is connected to. Maintenance windows are provided by the API, so they are
+//! compared against [`mullvad_rpc::server_time::now`] rather than the local clock.
+
+use chrono::{DateTime, Utc};
+use mullvad_types::relay_list::{MaintenanceWindow, Relay};
+use std::time::Duration;
+
+/// Relays whose maintenance starts within this long are only selected if no other relays match.
+/// This is also how long in advance a connected tunnel is warned about maintenance of its relays.
+pub const AVOID_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Relays whose maintenance starts within this long, or is ongoing, are only selected if every
+/// matching relay is this close to maintenance.
+const EXCLUDE_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// How long before the start of a maintenance window the daemon reconnects to another relay.
+const RECONNECT_LEAD: Duration = Duration::from_secs(2 * 60);
+
+/// How strongly a relay is avoided due to maintenance. Lower is better.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Priority {
+    Available,
+    Avoided,
+    Excluded,
+}
+
+fn priority(relay: &Relay, now: DateTime<Utc>) -> Priority {
+    match relay
+        .maintenance
+        .and_then(|window| window.time_until_start(now))
+    {
+        Some(time_until_start) if time_until_start < EXCLUDE_WINDOW => Priority::Excluded,
+        Some(time_until_start) if time_until_start < AVOID_WINDOW => Priority::Avoided,
+        _ => Priority::Available,
+    }
+}
+
+/// Keeps only the relays in `relays` that are least affected by upcoming maintenance, so that
+/// relays close to maintenance are only selected if no other relays remain.
+pub fn filter(relays: Vec<Relay>, now: DateTime<Utc>) -> Vec<Relay> {
+    let best = match relays.iter().map(|relay| priority(relay, now)).min() {
+        Some(best) => best,
+        None => return relays,
+    };
+
+    let (selectable, avoided): (Vec<Relay>, Vec<Relay>) = relays
+        .into_iter()
+        .partition(|relay| priority(relay, now) == best);
+    if !avoided.is_empty() {
+        log::debug!(
+            "Avoiding relays with upcoming maintenance: {}",
+            avoided
+                .iter()
+                .map(|relay| relay.hostname.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    selectable
+}
+
+/// What the daemon does about the maintenance of a relay that the tunnel is connected to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenancePlan {
+    pub hostname: String,
+    pub window: MaintenanceWindow,
+    /// Time until the tunnel is warned about the maintenance.
+    pub notify_in: Duration,
+    /// Time until the daemon reconnects to another relay. `None` if the window is too close to
+    /// move away from, which means the relay was selected because no other relay matched.
+    pub reconnect_in: Option<Duration>,
+}
+
+impl MaintenancePlan {
+    /// Returns when the daemon reconnects to another relay, if it does.
+    pub fn reconnect_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let reconnect_in = chrono::Duration::from_std(self.reconnect_in?).ok()?;
+        Some(now + reconnect_in)
+    }
+}
+
+/// Events sent to the daemon by the job that carries out a [`MaintenancePlan`].
+#[derive(Debug)]
+pub enum MaintenanceEvent {
+    /// The maintenance window of a relay in use starts within [`AVOID_WINDOW`].
+    Approaching(MaintenancePlan),
+    /// The maintenance window of a relay in use is about to start.
+    Reconnect(String),
+}
+
+/// Returns a plan for the relay among `relays_in_use` whose maintenance starts first. Relays
+/// without upcoming maintenance are ignored.
+pub fn plan(relays_in_use: &[Relay], now: DateTime<Utc>) -> Option<MaintenancePlan> {
+    relays_in_use
+        .iter()
+        .filter_map(|relay| {
+            let window = relay.maintenance?;
+            let time_until_start = window.time_until_start(now)?;
+            let reconnect_in = if time_until_start > RECONNECT_LEAD {
+                Some(time_until_start - RECONNECT_LEAD)
+            } else {
+                None
+            };
+            Some(MaintenancePlan {
+                hostname: relay.hostname.clone(),
+                window,
+                notify_in: time_until_start.saturating_sub(AVOID_WINDOW),
+                reconnect_in,
+            })
+        })
+        .min_by_key(|plan| plan.window.start)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::relay_list::{RelayBridges, RelayTunnels};
+
+    fn now() -> DateTime<Utc> {
+        "2022-03-01T12:00:00Z".parse().unwrap()
+    }
+
+    /// Returns a relay with a one hour maintenance window that starts `minutes` from [`now`].
+    fn relay(hostname: &str, minutes: Option<i64>) -> Relay {
+        Relay {
+            hostname: hostname.to_string(),
+            ipv4_addr_in: "192.0.2.1".parse().unwrap(),
+            ipv6_addr_in: None,
+            include_in_country: true,
+            active: true,
+            owned: true,
+            provider: "31173".to_string(),
+            asn: None,
+            asn_organization: None,
+            weight: 1,
+            tunnels: RelayTunnels::default(),
+            bridges: RelayBridges::default(),
+            location: None,
+            maintenance: minutes.map(|minutes| {
+                let start = now() + chrono::Duration::minutes(minutes);
+                MaintenanceWindow {
+                    start,
+                    end: start + chrono::Duration::hours(1),
+                }
+            }),
+        }
+    }
+
+    fn hostnames(relays: &[Relay]) -> Vec<&str> {
+        relays.iter().map(|relay| relay.hostname.as_str()).collect()
+    }
+
+    #[test]
+    fn test_relays_near_maintenance_are_avoided() {
+        let relays = vec![
+            relay("none", None),
+            relay("in-61-min", Some(61)),
+            relay("finished", Some(-61)),
+            relay("in-59-min", Some(59)),
+            relay("in-9-min", Some(9)),
+            relay("ongoing", Some(-30)),
+        ];
+        assert_eq!(
+            hostnames(&filter(relays.clone(), now())),
+            vec!["none", "in-61-min", "finished"]
+        );
+
+        // Relays whose maintenance starts within an hour are used if no other relays match
+        assert_eq!(
+            hostnames(&filter(relays[3..].to_vec(), now())),
+            vec!["in-59-min"]
+        );
+        assert_eq!(
+            hostnames(&filter(vec![relay("in-11-min", Some(11))], now())),
+            vec!["in-11-min"]
+        );
+
+        // Relays whose maintenance starts within ten minutes are used only as a last resort
+        assert_eq!(
+            hostnames(&filter(relays[4..].to_vec(), now())),
+            vec!["in-9-min", "ongoing"]
+        );
+        assert!(filter(vec![], now()).is_empty());
+    }
+
+    #[test]
+    fn test_plan() {
+        assert_eq!(plan(&[relay("none", None)], now()), None);
+        assert_eq!(plan(&[relay("finished", Some(-61))], now()), None);
+
+        // The relay whose maintenance starts first is planned for
+        let relays = [relay("in-90-min", Some(90)), relay("in-30-min", Some(30))];
+        let planned = plan(&relays, now()).unwrap();
+        assert_eq!(planned.hostname, "in-30-min");
+        assert_eq!(planned.notify_in, Duration::ZERO);
+        assert_eq!(planned.reconnect_in, Some(Duration::from_secs(28 * 60)));
+        assert_eq!(
+            planned.reconnect_at(now()),
+            Some("2022-03-01T12:28:00Z".parse().unwrap())
+        );
+
+        let planned = plan(&relays[..1], now()).unwrap();
+        assert_eq!(planned.notify_in, Duration::from_secs(30 * 60));
+        assert_eq!(planned.reconnect_in, Some(Duration::from_secs(88 * 60)));
+
+        // Relays that are already about to go into maintenance are not moved away from
+        for in_use in [relay("in-1-min", Some(1)), relay("ongoing", Some(-30))] {
+            let planned = plan(&[in_use], now()).unwrap();
+            assert_eq!(planned.notify_in, Duration::ZERO);
+            assert_eq!(planned.reconnect_in, None);
+            assert_eq!(planned.reconnect_at(now()), None);
+        }
+    }
+
+    #[test]
+    fn test_reconnect_avoids_relay() {
+        // When the daemon reconnects, the relay is close enough to maintenance to be excluded
+        let relays = [relay("in-30-min", Some(30)), relay("none", None)];
+        let planned = plan(&relays[..1], now()).unwrap();
+        let reconnect_at = planned.reconnect_at(now()).unwrap();
+        assert_eq!(
+            hostnames(&filter(relays.to_vec(), reconnect_at)),
+            vec!["none"]
+        );
+    }
+}
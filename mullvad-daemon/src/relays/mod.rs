@@ -1,6 +1,7 @@
 //! When changing relay selection, please verify if `docs/relay-selector.md` needs to be
 //! updated as well.
 
+use crate::fs_retry;
 use chrono::{DateTime, Local};
 use ipnetwork::IpNetwork;
 use mullvad_rpc::{availability::ApiAvailabilityHandle, rest::MullvadRestHandle};
@@ -8,10 +9,11 @@ use mullvad_types::{
     endpoint::{MullvadEndpoint, MullvadWireguardEndpoint},
     location::{Coordinates, Location},
     relay_constraints::{
-        BridgeState, Constraint, InternalBridgeConstraints, LocationConstraint, Match,
-        OpenVpnConstraints, Providers, RelayConstraints, Set, TransportPort, WireguardConstraints,
+        ApiBridgeMode, ApiBridgeSettings, BridgeSettings, BridgeState, Constraint,
+        InternalBridgeConstraints, LocationConstraint, Match, OpenVpnConstraints, Ownership,
+        Providers, RelayConstraints, Set, TransportPort, WireguardConstraints,
     },
-    relay_list::{Relay, RelayList, WireguardEndpointData},
+    relay_list::{DeprecatedRelay, FailedRelay, Relay, RelayList, WireguardEndpointData},
 };
 use parking_lot::Mutex;
 use rand::{self, seq::SliceRandom, Rng};
@@ -30,10 +32,13 @@ use talpid_types::{
 use crate::relays::updater::RelayListUpdater;
 
 use self::{
+    failures::RelayFailures,
     matcher::{RelayMatcher, TunnelMatcher, WireguardMatcher},
     updater::RelayListUpdaterHandle,
 };
 
+mod failures;
+pub mod maintenance;
 mod matcher;
 mod updater;
 
@@ -63,6 +68,15 @@ pub enum Error {
     #[error(display = "No relays matching current constraints")]
     NoRelay,
 
+    #[error(display = "No relays match both the provider and the ownership constraints")]
+    NoRelayWithProvidersAndOwnership,
+
+    #[error(
+        display = "The multihop entry and exit locations only match the same relay, but the \
+                   entry and exit relays must differ"
+    )]
+    SameEntryAndExitRelay,
+
     #[error(display = "Failure in serialization of the relay list")]
     Serialize(#[error(source)] serde_json::Error),
 
@@ -171,8 +185,8 @@ impl ParsedRelays {
 
     pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
         log::debug!("Reading relays from {}", path.as_ref().display());
-        let (last_modified, file) =
-            Self::open_file(path.as_ref()).map_err(Error::OpenRelayCache)?;
+        let (last_modified, file) = fs_retry::retry_blocking(|| Self::open_file(path.as_ref()))
+            .map_err(Error::OpenRelayCache)?;
         let relay_list =
             serde_json::from_reader(io::BufReader::new(file)).map_err(Error::Serialize)?;
 
@@ -204,6 +218,7 @@ impl ParsedRelays {
 
 pub struct RelaySelector {
     parsed_relays: Arc<Mutex<ParsedRelays>>,
+    failures: Mutex<RelayFailures>,
     updater: Option<RelayListUpdaterHandle>,
 }
 
@@ -245,6 +260,7 @@ impl RelaySelector {
 
         RelaySelector {
             parsed_relays,
+            failures: Mutex::new(RelayFailures::new()),
             updater: Some(updater),
         }
     }
@@ -263,6 +279,57 @@ impl RelaySelector {
         }
     }
 
+    /// Records that connecting to `hostname` failed. The relay is avoided for a while, unless it
+    /// is the only relay that matches the constraints.
+    pub fn report_failure(&self, hostname: &str) {
+        log::debug!(
+            "Avoiding relay {} after a failed connection attempt",
+            hostname
+        );
+        self.failures.lock().report(hostname);
+    }
+
+    /// Forgets all relays that recently failed to connect.
+    pub fn clear_failures(&self) {
+        self.failures.lock().clear();
+    }
+
+    /// Returns the relays that are avoided because they recently failed to connect.
+    pub fn get_failed_relays(&self) -> Vec<FailedRelay> {
+        self.failures.lock().failed_relays()
+    }
+
+    /// Returns the current information about the relays with the given hostnames.
+    pub fn find_relays(&self, hostnames: &[&str]) -> Vec<Relay> {
+        self.parsed_relays
+            .lock()
+            .relays()
+            .iter()
+            .filter(|relay| {
+                hostnames
+                    .iter()
+                    .any(|hostname| relay.hostname.eq_ignore_ascii_case(hostname))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the unique WireGuard tunnel gateways of all relays. DNS servers at these addresses
+    /// are reached through the tunnel.
+    pub fn get_tunnel_gateways(&self) -> Vec<IpAddr> {
+        let mut gateways = Vec::new();
+        for relay in self.parsed_relays.lock().relays() {
+            for data in &relay.tunnels.wireguard {
+                for gateway in [IpAddr::V4(data.ipv4_gateway), IpAddr::V6(data.ipv6_gateway)] {
+                    if !gateways.contains(&gateway) {
+                        gateways.push(gateway);
+                    }
+                }
+            }
+        }
+        gateways
+    }
+
     /// Returns all countries and cities. The cities in the object returned does not have any
     /// relays in them.
     pub fn get_locations(&mut self) -> RelayList {
@@ -278,10 +345,12 @@ impl RelaySelector {
         retry_attempt: u32,
         wg_key_exists: bool,
     ) -> Result<RelaySelectorResult, Error> {
-        match relay_constraints.tunnel_protocol {
+        let result = match relay_constraints.tunnel_protocol {
             Constraint::Only(TunnelType::OpenVpn) => self.get_openvpn_endpoint(
                 &relay_constraints.location,
                 &relay_constraints.providers,
+                relay_constraints.ownership,
+                &relay_constraints.excluded_asns,
                 relay_constraints.openvpn_constraints.clone(),
                 bridge_state,
                 retry_attempt,
@@ -290,6 +359,8 @@ impl RelaySelector {
             Constraint::Only(TunnelType::Wireguard) => self.get_wireguard_endpoint(
                 &relay_constraints.location,
                 &relay_constraints.providers,
+                relay_constraints.ownership,
+                &relay_constraints.excluded_asns,
                 &relay_constraints.wireguard_constraints,
                 retry_attempt,
             ),
@@ -299,9 +370,38 @@ impl RelaySelector {
                 retry_attempt,
                 wg_key_exists,
             ),
+        };
+
+        match result {
+            Err(Error::NoRelay)
+                if !self.any_relay_matches(
+                    &relay_constraints.providers,
+                    relay_constraints.ownership,
+                ) =>
+            {
+                Err(Error::NoRelayWithProvidersAndOwnership)
+            }
+            result => result,
         }
     }
 
+    /// Returns whether any active relay is hosted by one of `providers` and has the given
+    /// ownership. This is always true if neither is constrained.
+    fn any_relay_matches(
+        &self,
+        providers: &Constraint<Providers>,
+        ownership: Constraint<Ownership>,
+    ) -> bool {
+        if providers.is_any() && ownership.is_any() {
+            return true;
+        }
+        self.parsed_relays
+            .lock()
+            .relays()
+            .iter()
+            .any(|relay| relay.active && providers.matches(relay) && ownership.matches(relay))
+    }
+
     /// Returns the average location of relays that match the given constraints.
     /// This returns none if the location is `any` or if no relays match the constraints.
     pub fn get_relay_midpoint(&self, relay_constraints: &RelayConstraints) -> Option<Coordinates> {
@@ -336,6 +436,8 @@ impl RelaySelector {
         &self,
         location: &Constraint<LocationConstraint>,
         providers: &Constraint<Providers>,
+        ownership: Constraint<Ownership>,
+        excluded_asns: &[u32],
         openvpn_constraints: OpenVpnConstraints,
         bridge_state: BridgeState,
         retry_attempt: u32,
@@ -343,6 +445,8 @@ impl RelaySelector {
         let mut relay_matcher = RelayMatcher {
             location: location.clone(),
             providers: providers.clone(),
+            ownership,
+            excluded_asns: excluded_asns.to_vec(),
             tunnel: openvpn_constraints,
         };
 
@@ -387,15 +491,50 @@ impl RelaySelector {
 
     fn get_wireguard_multi_hop_endpoint(
         &self,
-        mut entry_matcher: RelayMatcher<WireguardMatcher>,
+        entry_matcher: RelayMatcher<WireguardMatcher>,
         exit_location: Constraint<LocationConstraint>,
     ) -> Result<RelaySelectorResult, Error> {
-        let mut exit_matcher = RelayMatcher {
+        let exit_matcher = RelayMatcher {
             location: exit_location,
             tunnel: WIREGUARD_EXIT_CONSTRAINTS.clone().into(),
             ..entry_matcher.clone()
         };
 
+        self.select_wireguard_multi_hop_endpoint(entry_matcher.clone(), exit_matcher.clone())
+            .map_err(|error| match error {
+                Error::NoRelay if self.is_same_single_relay(&entry_matcher, &exit_matcher) => {
+                    Error::SameEntryAndExitRelay
+                }
+                error => error,
+            })
+    }
+
+    /// Returns whether exactly one relay matches both `entry_matcher` and `exit_matcher`, and no
+    /// other relay matches either of them. Then the entry and exit relay cannot differ.
+    fn is_same_single_relay(
+        &self,
+        entry_matcher: &RelayMatcher<WireguardMatcher>,
+        exit_matcher: &RelayMatcher<WireguardMatcher>,
+    ) -> bool {
+        let parsed_relays = self.parsed_relays.lock();
+        let matching_hostnames = |matcher: &RelayMatcher<WireguardMatcher>| -> Vec<String> {
+            parsed_relays
+                .relays()
+                .iter()
+                .filter(|relay| relay.active)
+                .filter_map(|relay| matcher.filter_matching_relay(relay))
+                .map(|relay| relay.hostname)
+                .collect()
+        };
+        let entry_hostnames = matching_hostnames(entry_matcher);
+        entry_hostnames.len() == 1 && matching_hostnames(exit_matcher) == entry_hostnames
+    }
+
+    fn select_wireguard_multi_hop_endpoint(
+        &self,
+        mut entry_matcher: RelayMatcher<WireguardMatcher>,
+        mut exit_matcher: RelayMatcher<WireguardMatcher>,
+    ) -> Result<RelaySelectorResult, Error> {
         let (exit_relay, entry_relay, exit_endpoint, mut entry_endpoint) =
             if entry_matcher.location.is_subset(&exit_matcher.location) {
                 let (entry_relay, entry_endpoint) = self.get_entry_endpoint(&entry_matcher)?;
@@ -443,12 +582,16 @@ impl RelaySelector {
         &self,
         location: &Constraint<LocationConstraint>,
         providers: &Constraint<Providers>,
+        ownership: Constraint<Ownership>,
+        excluded_asns: &[u32],
         wireguard_constraints: &WireguardConstraints,
         retry_attempt: u32,
     ) -> Result<RelaySelectorResult, Error> {
         let mut entry_relay_matcher = RelayMatcher {
             location: location.clone(),
             providers: providers.clone(),
+            ownership,
+            excluded_asns: excluded_asns.to_vec(),
             tunnel: wireguard_constraints.clone().into(),
         };
 
@@ -550,6 +693,8 @@ impl RelaySelector {
                 retry_attempt,
                 &original_constraints.location,
                 &original_constraints.providers,
+                original_constraints.ownership,
+                &original_constraints.excluded_asns,
                 wg_key_exists,
             );
 
@@ -641,6 +786,8 @@ impl RelaySelector {
             .filter(|relay| relay.active)
             .filter_map(|relay| matcher.filter_matching_relay(relay))
             .collect();
+        let matching_relays = self.failures.lock().filter(matching_relays);
+        let matching_relays = maintenance::filter(matching_relays, mullvad_rpc::server_time::now());
 
         let relay = self
             .pick_random_relay(&matching_relays)
@@ -691,6 +838,46 @@ impl RelaySelector {
             (retry_attempt % 4) < 2
     }
 
+    /// Returns the bridge to send API traffic through, or `None` if there is no matching bridge
+    /// or if the API should be reached directly. In the automatic mode, every third attempt is
+    /// made without a bridge. The caller decides what `None` means in the `Always` mode.
+    pub fn get_api_bridge<T: Into<Coordinates>>(
+        &mut self,
+        api_bridge_settings: &ApiBridgeSettings,
+        bridge_settings: &BridgeSettings,
+        location: Option<T>,
+        retry_attempt: u32,
+    ) -> Option<(ProxySettings, Relay)> {
+        let use_bridge = match api_bridge_settings.mode {
+            ApiBridgeMode::Auto => retry_attempt % 3 > 0,
+            ApiBridgeMode::Always => true,
+            ApiBridgeMode::Never => false,
+        };
+        if !use_bridge {
+            return None;
+        }
+
+        let (bridge_location, providers, ownership) = match bridge_settings {
+            BridgeSettings::Normal(settings) => (
+                settings.location.clone(),
+                settings.providers.clone(),
+                settings.ownership,
+            ),
+            BridgeSettings::Custom(_) => (Constraint::Any, Constraint::Any, Constraint::Any),
+        };
+        let location_constraint = match &api_bridge_settings.location {
+            Constraint::Only(location) => Constraint::Only(location.clone()),
+            Constraint::Any => bridge_location,
+        };
+        let constraints = InternalBridgeConstraints {
+            location: location_constraint,
+            providers,
+            ownership,
+            transport_protocol: Constraint::Only(TransportProtocol::Tcp),
+        };
+        self.get_proxy_settings(&constraints, location)
+    }
+
     pub fn get_proxy_settings<T: Into<Coordinates>>(
         &mut self,
         constraints: &InternalBridgeConstraints,
@@ -731,6 +918,8 @@ impl RelaySelector {
         retry_attempt: u32,
         location_constraint: &Constraint<LocationConstraint>,
         providers_constraint: &Constraint<Providers>,
+        ownership_constraint: Constraint<Ownership>,
+        excluded_asns: &[u32],
         wg_key_exists: bool,
     ) -> (Constraint<u16>, TransportProtocol, TunnelType) {
         #[cfg(target_os = "windows")]
@@ -741,6 +930,8 @@ impl RelaySelector {
                         && !relay.tunnels.openvpn.is_empty()
                         && location_constraint.matches(relay)
                         && providers_constraint.matches(relay)
+                        && ownership_constraint.matches(relay)
+                        && !relay.is_hosted_in_any(excluded_asns)
                 });
             if location_supports_openvpn {
                 let (preferred_port, preferred_protocol) =
@@ -754,6 +945,8 @@ impl RelaySelector {
                 && !relay.tunnels.wireguard.is_empty()
                 && location_constraint.matches(relay)
                 && providers_constraint.matches(relay)
+                && ownership_constraint.matches(relay)
+                && !relay.is_hosted_in_any(excluded_asns)
         });
         // If location does not support WireGuard, defer to preferred OpenVPN tunnel
         // constraints
@@ -829,6 +1022,8 @@ impl RelaySelector {
             .filter(|relay| relay.active)
             .filter_map(|relay| matcher.filter_matching_relay(relay))
             .collect();
+        let matching_relays = self.failures.lock().filter(matching_relays);
+        let matching_relays = maintenance::filter(matching_relays, mullvad_rpc::server_time::now());
 
         self.pick_random_relay(&matching_relays)
             .and_then(|selected_relay| {
@@ -853,6 +1048,9 @@ impl RelaySelector {
         if !constraints.providers.matches(relay) {
             return None;
         }
+        if !constraints.ownership.matches(relay) {
+            return None;
+        }
 
         let mut filtered_relay = relay.clone();
         filtered_relay
@@ -968,14 +1166,52 @@ impl RelaySelectorResult {
     }
 }
 
+/// Returns the relays in `relays_in_use`, and the relay selected by `pinned_hostname`, that are
+/// missing from `relay_list`.
+pub fn find_deprecated_relays(
+    relay_list: &RelayList,
+    relays_in_use: &[&str],
+    pinned_hostname: Option<&str>,
+) -> Vec<DeprecatedRelay> {
+    let exists = |hostname: &str| {
+        relay_list
+            .countries
+            .iter()
+            .flat_map(|country| &country.cities)
+            .flat_map(|city| &city.relays)
+            .any(|relay| relay.hostname.eq_ignore_ascii_case(hostname))
+    };
+
+    let mut deprecated_relays: Vec<DeprecatedRelay> = relays_in_use
+        .iter()
+        .filter(|hostname| !exists(hostname))
+        .map(|hostname| DeprecatedRelay {
+            hostname: hostname.to_string(),
+            still_connected: true,
+        })
+        .collect();
+    if let Some(pinned_hostname) = pinned_hostname {
+        let already_found = deprecated_relays
+            .iter()
+            .any(|relay| relay.hostname.eq_ignore_ascii_case(pinned_hostname));
+        if !already_found && !exists(pinned_hostname) {
+            deprecated_relays.push(DeprecatedRelay {
+                hostname: pinned_hostname.to_owned(),
+                still_connected: false,
+            });
+        }
+    }
+    deprecated_relays
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use mullvad_types::{
-        relay_constraints::RelayConstraints,
+        relay_constraints::{BridgeConstraints, RelayConstraints},
         relay_list::{
             OpenVpnEndpointData, Relay, RelayBridges, RelayListCity, RelayListCountry,
-            RelayTunnels, WireguardEndpointData,
+            RelayTunnels, ShadowsocksEndpointData, WireguardEndpointData,
         },
     };
     use talpid_types::net::wireguard::PublicKey;
@@ -1002,6 +1238,8 @@ mod test {
                                     active: true,
                                     owned: true,
                                     provider: "31173".to_string(),
+                                    asn: Some(39351),
+                                    asn_organization: Some("31173 Services AB".to_string()),
                                     weight: 1,
                                     tunnels: RelayTunnels {
                                         openvpn: vec![],
@@ -1019,6 +1257,7 @@ mod test {
                                         shadowsocks: vec![],
                                     },
                                     location: None,
+                                    maintenance: None,
                                 },
                                 Relay {
                                     hostname: "se10-wireguard".to_string(),
@@ -1028,6 +1267,8 @@ mod test {
                                     active: true,
                                     owned: true,
                                     provider: "31173".to_string(),
+                                    asn: Some(42708),
+                                    asn_organization: Some("GleSYS AB".to_string()),
                                     weight: 1,
                                     tunnels: RelayTunnels {
                                         openvpn: vec![],
@@ -1045,6 +1286,7 @@ mod test {
                                         shadowsocks: vec![],
                                     },
                                     location: None,
+                                    maintenance: None,
                                 },
                                 Relay {
                                     hostname: "se-got-001".to_string(),
@@ -1054,6 +1296,8 @@ mod test {
                                     active: true,
                                     owned: true,
                                     provider: "31173".to_string(),
+                                    asn: None,
+                                    asn_organization: None,
                                     weight: 1,
                                     tunnels: RelayTunnels {
                                         openvpn: vec![
@@ -1076,6 +1320,7 @@ mod test {
                                         shadowsocks: vec![],
                                     },
                                     location: None,
+                                    maintenance: None,
                                 },
                                 Relay {
                                     hostname: "se11-wireguard-filtered".to_string(),
@@ -1085,6 +1330,8 @@ mod test {
                                     active: true,
                                     owned: true,
                                     provider: "31173".to_string(),
+                                    asn: None,
+                                    asn_organization: None,
                                     weight: 1,
                                     tunnels: RelayTunnels {
                                         openvpn: vec![],
@@ -1102,6 +1349,7 @@ mod test {
                                         shadowsocks: vec![],
                                     },
                                     location: None,
+                                    maintenance: None,
                                 },
                                 Relay {
                                     hostname: "se-got-010-filtered".to_string(),
@@ -1111,6 +1359,8 @@ mod test {
                                     active: true,
                                     owned: true,
                                     provider: "31173".to_string(),
+                                    asn: None,
+                                    asn_organization: None,
                                     weight: 1,
                                     tunnels: RelayTunnels {
                                         openvpn: vec![OpenVpnEndpointData{
@@ -1123,6 +1373,7 @@ mod test {
                                         shadowsocks: vec![],
                                     },
                                     location: None,
+                                    maintenance: None,
                                 }
                             ],
                         },
@@ -1133,11 +1384,16 @@ mod test {
     }
 
     fn new_relay_selector() -> RelaySelector {
+        new_relay_selector_with_relays(RELAYS.clone())
+    }
+
+    fn new_relay_selector_with_relays(relays: RelayList) -> RelaySelector {
         RelaySelector {
             parsed_relays: Arc::new(Mutex::new(ParsedRelays::from_relay_list(
-                RELAYS.clone(),
+                relays,
                 SystemTime::now(),
             ))),
+            failures: Mutex::new(RelayFailures::new()),
             updater: None,
         }
     }
@@ -1249,9 +1505,10 @@ mod test {
         relay_constraints.wireguard_constraints.entry_location = Constraint::Only(location1);
 
         // The same host cannot be used for entry and exit
-        assert!(relay_selector
-            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
-            .is_err());
+        assert!(matches!(
+            relay_selector.get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true),
+            Err(Error::SameEntryAndExitRelay)
+        ));
 
         relay_constraints.wireguard_constraints.entry_location = Constraint::Only(location2);
 
@@ -1261,6 +1518,36 @@ mod test {
             .is_ok());
     }
 
+    #[test]
+    fn test_wg_entry_without_matching_relay() {
+        let relay_selector = new_relay_selector();
+
+        let mut relay_constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".to_string())),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+        relay_constraints.wireguard_constraints.use_multihop = true;
+        relay_constraints.wireguard_constraints.entry_location =
+            Constraint::Only(LocationConstraint::Country("de".to_string()));
+
+        // No relay matches the entry location
+        assert!(matches!(
+            relay_selector.get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true),
+            Err(Error::NoRelay)
+        ));
+
+        // No relay matches the exit location
+        relay_constraints.location =
+            Constraint::Only(LocationConstraint::Country("de".to_string()));
+        relay_constraints.wireguard_constraints.entry_location =
+            Constraint::Only(LocationConstraint::Country("se".to_string()));
+        assert!(matches!(
+            relay_selector.get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true),
+            Err(Error::NoRelay)
+        ));
+    }
+
     #[test]
     fn test_wg_entry_filter() -> Result<(), String> {
         let relay_selector = new_relay_selector();
@@ -1486,6 +1773,7 @@ mod test {
     const WIREGUARD_MULTIHOP_CONSTRAINTS: RelayConstraints = RelayConstraints {
         location: Constraint::Any,
         providers: Constraint::Any,
+        ownership: Constraint::Any,
         wireguard_constraints: WireguardConstraints {
             use_multihop: true,
             port: Constraint::Any,
@@ -1496,6 +1784,7 @@ mod test {
         openvpn_constraints: OpenVpnConstraints {
             port: Constraint::Any,
         },
+        excluded_asns: Vec::new(),
     };
 
     #[test]
@@ -1622,4 +1911,382 @@ mod test {
             .get_tunnel_endpoint(&constraints, BridgeState::Off, 0, true)
             .expect_err("Successfully selected a relay that should be filtered");
     }
+
+    #[test]
+    fn test_excluded_asns() {
+        let relay_selector = new_relay_selector();
+        let mut constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".to_string())),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            excluded_asns: vec![39351],
+            ..RelayConstraints::default()
+        };
+
+        for attempt in 0..10 {
+            let result = relay_selector
+                .get_tunnel_endpoint(&constraints, BridgeState::Off, attempt, true)
+                .expect("Failed to select a relay outside of the excluded ASN");
+            assert_eq!(result.exit_relay.hostname, "se10-wireguard");
+        }
+
+        constraints.excluded_asns = vec![39351, 42708];
+        relay_selector
+            .get_tunnel_endpoint(&constraints, BridgeState::Off, 0, true)
+            .expect_err("Successfully selected a relay in an excluded ASN");
+
+        // Relays without ASN information cannot be excluded.
+        constraints.tunnel_protocol = Constraint::Only(TunnelType::OpenVpn);
+        let result = relay_selector
+            .get_tunnel_endpoint(&constraints, BridgeState::Off, 0, true)
+            .expect("Failed to select a relay without ASN information");
+        assert_eq!(result.exit_relay.hostname, "se-got-001");
+
+        // The preferred tunnel protocol only considers relays outside of the excluded ASNs.
+        constraints.tunnel_protocol = Constraint::Any;
+        let preferred =
+            relay_selector.preferred_constraints(&constraints, BridgeState::Off, 0, true);
+        assert_eq!(
+            preferred.tunnel_protocol,
+            Constraint::Only(TunnelType::OpenVpn)
+        );
+    }
+
+    #[test]
+    fn test_providers_and_excluded_asns() {
+        let mut relays = RELAYS.clone();
+        relays.countries[0].cities[0].relays[1].provider = "M247".to_string();
+        let relay_selector = new_relay_selector_with_relays(relays);
+
+        let providers = |providers: &[&str]| {
+            Constraint::Only(
+                Providers::new(providers.iter().map(|provider| provider.to_string())).unwrap(),
+            )
+        };
+        let mut constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".to_string())),
+            providers: providers(&["31173"]),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            excluded_asns: vec![39351],
+            ..RelayConstraints::default()
+        };
+
+        // The only relay with a matching provider is in an excluded ASN.
+        relay_selector
+            .get_tunnel_endpoint(&constraints, BridgeState::Off, 0, true)
+            .expect_err("Successfully selected a relay in an excluded ASN");
+
+        constraints.providers = providers(&["31173", "M247"]);
+        for attempt in 0..10 {
+            let result = relay_selector
+                .get_tunnel_endpoint(&constraints, BridgeState::Off, attempt, true)
+                .expect("Failed to select a relay matching both filters");
+            assert_eq!(result.exit_relay.hostname, "se10-wireguard");
+        }
+
+        constraints.providers = providers(&["M247"]);
+        constraints.excluded_asns = vec![42708];
+        relay_selector
+            .get_tunnel_endpoint(&constraints, BridgeState::Off, 0, true)
+            .expect_err("Successfully selected a relay in an excluded ASN");
+    }
+
+    #[test]
+    fn test_ownership() {
+        let mut relays = RELAYS.clone();
+        relays.countries[0].cities[0].relays[1].owned = false;
+        relays.countries[0].cities[0].relays[1].provider = "M247".to_string();
+        let relay_selector = new_relay_selector_with_relays(relays);
+
+        let mut constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".to_string())),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ownership: Constraint::Only(Ownership::Rented),
+            ..RelayConstraints::default()
+        };
+        for attempt in 0..10 {
+            let result = relay_selector
+                .get_tunnel_endpoint(&constraints, BridgeState::Off, attempt, true)
+                .expect("Failed to select a rented relay");
+            assert_eq!(result.exit_relay.hostname, "se10-wireguard");
+        }
+
+        constraints.ownership = Constraint::Only(Ownership::MullvadOwned);
+        for attempt in 0..10 {
+            let result = relay_selector
+                .get_tunnel_endpoint(&constraints, BridgeState::Off, attempt, true)
+                .expect("Failed to select a Mullvad-owned relay");
+            assert_ne!(result.exit_relay.hostname, "se10-wireguard");
+        }
+
+        // The only rented relay is not hosted by the selected provider.
+        constraints.ownership = Constraint::Only(Ownership::Rented);
+        constraints.providers =
+            Constraint::Only(Providers::new(vec!["31173".to_string()].into_iter()).unwrap());
+        assert!(matches!(
+            relay_selector.get_tunnel_endpoint(&constraints, BridgeState::Off, 0, true),
+            Err(Error::NoRelayWithProvidersAndOwnership)
+        ));
+    }
+
+    #[test]
+    fn test_failed_relays_are_avoided() {
+        let relay_selector = new_relay_selector();
+        let mut constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".to_string())),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+
+        relay_selector.report_failure("se9-wireguard");
+        for attempt in 0..10 {
+            let result = relay_selector
+                .get_tunnel_endpoint(&constraints, BridgeState::Off, attempt, true)
+                .expect("Failed to select a relay");
+            assert_eq!(result.exit_relay.hostname, "se10-wireguard");
+        }
+
+        // A failed relay is still selected if nothing else matches.
+        constraints.location = Constraint::Only(LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            "se9-wireguard".to_string(),
+        ));
+        let result = relay_selector
+            .get_tunnel_endpoint(&constraints, BridgeState::Off, 0, true)
+            .expect("Failed to select the only matching relay");
+        assert_eq!(result.exit_relay.hostname, "se9-wireguard");
+
+        relay_selector.clear_failures();
+        assert!(relay_selector.get_failed_relays().is_empty());
+    }
+
+    #[test]
+    fn test_deprecated_relays() {
+        assert!(find_deprecated_relays(
+            &RELAYS,
+            &["se-got-001", "se9-wireguard"],
+            Some("se10-wireguard")
+        )
+        .is_empty());
+
+        // The connected relay was removed
+        let mut relays = RELAYS.clone();
+        relays.countries[0].cities[0]
+            .relays
+            .retain(|relay| relay.hostname != "se9-wireguard");
+        assert_eq!(
+            find_deprecated_relays(&relays, &["se-got-001", "se9-wireguard"], None),
+            vec![DeprecatedRelay {
+                hostname: "se9-wireguard".to_string(),
+                still_connected: true,
+            }]
+        );
+
+        // The pinned relay was removed while connected to another relay
+        assert_eq!(
+            find_deprecated_relays(&relays, &["se10-wireguard"], Some("se9-wireguard")),
+            vec![DeprecatedRelay {
+                hostname: "se9-wireguard".to_string(),
+                still_connected: false,
+            }]
+        );
+
+        // The pinned relay is also the connected one
+        assert_eq!(
+            find_deprecated_relays(&relays, &["se9-wireguard"], Some("se9-wireguard")),
+            vec![DeprecatedRelay {
+                hostname: "se9-wireguard".to_string(),
+                still_connected: true,
+            }]
+        );
+    }
+
+    /// Returns a relay list with one Shadowsocks bridge in Gothenburg and one in Berlin.
+    fn bridge_relays() -> RelayList {
+        let bridge = |hostname: &str, ip: &str| Relay {
+            hostname: hostname.to_string(),
+            ipv4_addr_in: ip.parse().unwrap(),
+            ipv6_addr_in: None,
+            include_in_country: true,
+            active: true,
+            owned: true,
+            provider: "31173".to_string(),
+            asn: None,
+            asn_organization: None,
+            weight: 1,
+            tunnels: RelayTunnels {
+                openvpn: vec![],
+                wireguard: vec![],
+            },
+            bridges: RelayBridges {
+                shadowsocks: vec![ShadowsocksEndpointData {
+                    port: 443,
+                    cipher: "aes-256-gcm".to_string(),
+                    password: "mullvad".to_string(),
+                    protocol: TransportProtocol::Tcp,
+                }],
+            },
+            location: None,
+            maintenance: None,
+        };
+        let city = |name: &str, code: &str, latitude, longitude, relay| RelayListCity {
+            name: name.to_string(),
+            code: code.to_string(),
+            latitude,
+            longitude,
+            relays: vec![relay],
+        };
+
+        RelayList {
+            etag: None,
+            countries: vec![
+                RelayListCountry {
+                    name: "Sweden".to_string(),
+                    code: "se".to_string(),
+                    cities: vec![city(
+                        "Gothenburg",
+                        "got",
+                        57.70887,
+                        11.97456,
+                        bridge("se-got-br-001", "185.213.154.117"),
+                    )],
+                },
+                RelayListCountry {
+                    name: "Germany".to_string(),
+                    code: "de".to_string(),
+                    cities: vec![city(
+                        "Berlin",
+                        "ber",
+                        52.520008,
+                        13.404954,
+                        bridge("de-ber-br-001", "193.138.218.71"),
+                    )],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_api_bridge_selection() {
+        let mut relay_selector = new_relay_selector_with_relays(bridge_relays());
+        let gothenburg = Coordinates {
+            latitude: 57.70887,
+            longitude: 11.97456,
+        };
+        let bridge_settings = BridgeSettings::Normal(Default::default());
+        let mut select = |api_bridge_settings: &ApiBridgeSettings, retry_attempt| {
+            relay_selector
+                .get_api_bridge(
+                    api_bridge_settings,
+                    &bridge_settings,
+                    Some(gothenburg.clone()),
+                    retry_attempt,
+                )
+                .map(|(_, relay)| relay.hostname)
+        };
+
+        let auto = ApiBridgeSettings::default();
+        assert_eq!(select(&auto, 0), None);
+        assert_eq!(select(&auto, 1), Some("se-got-br-001".to_string()));
+        assert_eq!(select(&auto, 3), None);
+
+        let always = ApiBridgeSettings {
+            mode: ApiBridgeMode::Always,
+            location: Constraint::Any,
+        };
+        for retry_attempt in 0..4 {
+            assert_eq!(
+                select(&always, retry_attempt),
+                Some("se-got-br-001".to_string())
+            );
+        }
+
+        let never = ApiBridgeSettings {
+            mode: ApiBridgeMode::Never,
+            location: Constraint::Any,
+        };
+        for retry_attempt in 0..4 {
+            assert_eq!(select(&never, retry_attempt), None);
+        }
+
+        // The API bridge location overrides the closest bridge
+        let always_germany = ApiBridgeSettings {
+            mode: ApiBridgeMode::Always,
+            location: Constraint::Only(LocationConstraint::Country("de".to_string())),
+        };
+        assert_eq!(
+            select(&always_germany, 0),
+            Some("de-ber-br-001".to_string())
+        );
+
+        let always_nowhere = ApiBridgeSettings {
+            mode: ApiBridgeMode::Always,
+            location: Constraint::Only(LocationConstraint::Country("fi".to_string())),
+        };
+        // The location is never traded for another bridge
+        for retry_attempt in 0..4 {
+            assert_eq!(select(&always_nowhere, retry_attempt), None);
+        }
+    }
+
+    #[test]
+    fn test_api_bridge_without_bridges() {
+        let mut relay_selector = new_relay_selector_with_relays(RelayList::empty());
+        let always = ApiBridgeSettings {
+            mode: ApiBridgeMode::Always,
+            location: Constraint::Any,
+        };
+        let bridge = relay_selector.get_api_bridge(
+            &always,
+            &BridgeSettings::Normal(Default::default()),
+            None::<Coordinates>,
+            0,
+        );
+        assert!(bridge.is_none());
+    }
+
+    #[test]
+    fn test_api_bridge_falls_back_on_bridge_location() {
+        let mut relay_selector = new_relay_selector_with_relays(bridge_relays());
+        let bridge_settings = BridgeSettings::Normal(BridgeConstraints {
+            location: Constraint::Only(LocationConstraint::City(
+                "de".to_string(),
+                "ber".to_string(),
+            )),
+            providers: Constraint::Any,
+            ownership: Constraint::Any,
+        });
+        let always = ApiBridgeSettings {
+            mode: ApiBridgeMode::Always,
+            location: Constraint::Any,
+        };
+        let (_, relay) = relay_selector
+            .get_api_bridge(&always, &bridge_settings, None::<Coordinates>, 0)
+            .unwrap();
+        assert_eq!(relay.hostname, "de-ber-br-001");
+
+        let unmatched_bridge_settings = BridgeSettings::Normal(BridgeConstraints {
+            location: Constraint::Only(LocationConstraint::Country("fi".to_string())),
+            providers: Constraint::Any,
+            ownership: Constraint::Any,
+        });
+        assert!(relay_selector
+            .get_api_bridge(&always, &unmatched_bridge_settings, None::<Coordinates>, 0)
+            .is_none());
+    }
+
+    #[test]
+    fn test_tunnel_gateways() {
+        let relay_selector = new_relay_selector();
+        assert_eq!(
+            relay_selector.get_tunnel_gateways(),
+            vec![
+                "10.64.0.1".parse::<IpAddr>().unwrap(),
+                "fc00:bbbb:bbbb:bb01::1".parse::<IpAddr>().unwrap(),
+            ]
+        );
+
+        let relay_selector = new_relay_selector_with_relays(RelayList::empty());
+        assert!(relay_selector.get_tunnel_gateways().is_empty());
+    }
 }
@@ -1,4 +1,5 @@
 use super::{Error, ParsedRelays};
+use crate::fs_retry;
 use futures::{
     channel::mpsc,
     future::{Fuse, FusedFuture},
@@ -8,6 +9,7 @@ use mullvad_rpc::{availability::ApiAvailabilityHandle, rest::MullvadRestHandle,
 use mullvad_types::relay_list::RelayList;
 use parking_lot::Mutex;
 use std::{
+    io,
     path::{Path, PathBuf},
     sync::Arc,
     time::{Duration, Instant, SystemTime},
@@ -196,14 +198,15 @@ impl RelayListUpdater {
     /// Write a `RelayList` to the cache file.
     async fn cache_relays(cache_path: &Path, relays: &RelayList) -> Result<(), Error> {
         log::debug!("Writing relays cache to {}", cache_path.display());
-        let mut file = File::create(cache_path)
-            .await
-            .map_err(Error::OpenRelayCache)?;
         let bytes = serde_json::to_vec_pretty(relays).map_err(Error::Serialize)?;
-        let mut slice: &[u8] = bytes.as_slice();
-        let _ = tokio::io::copy(&mut slice, &mut file)
+        fs_retry::retry(|| Self::write_cache_file(cache_path, &bytes))
             .await
-            .map_err(Error::WriteRelayCache)?;
+            .map_err(Error::WriteRelayCache)
+    }
+
+    async fn write_cache_file(cache_path: &Path, mut bytes: &[u8]) -> io::Result<()> {
+        let mut file = File::create(cache_path).await?;
+        tokio::io::copy(&mut bytes, &mut file).await?;
         Ok(())
     }
 }
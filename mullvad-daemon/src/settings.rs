@@ -1,8 +1,13 @@
+use crate::{fs_retry, preserving::Preserving, settings_transfer::SettingsImport};
 #[cfg(not(target_os = "android"))]
 use futures::TryFutureExt;
+use ipnetwork::IpNetwork;
 use mullvad_types::{
-    relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
-    settings::{DnsOptions, Settings},
+    relay_constraints::{ApiBridgeSettings, BridgeSettings, BridgeState, RelaySettingsUpdate},
+    settings::{
+        check_allowed_networks_supported, AllowedNetworksError, DnsOptions, SecurityPreset,
+        Settings,
+    },
     wireguard::{RotationInterval, WireguardData},
 };
 #[cfg(target_os = "windows")]
@@ -10,8 +15,12 @@ use std::collections::HashSet;
 use std::{
     ops::Deref,
     path::{Path, PathBuf},
+    time::Duration,
+};
+use talpid_types::{
+    net::{openvpn, wireguard::RateLimit},
+    ErrorExt,
 };
-use talpid_types::ErrorExt;
 use tokio::{
     fs,
     io::{self, AsyncWriteExt},
@@ -40,11 +49,14 @@ pub enum Error {
 
     #[error(display = "Unable to set settings file permissions")]
     SetPermissions(#[error(source)] io::Error),
+
+    #[error(display = "Invalid allowed networks")]
+    InvalidAllowedNetworks(#[error(source)] AllowedNetworksError),
 }
 
 #[derive(Debug)]
 pub struct SettingsPersister {
-    settings: Settings,
+    settings: Preserving<Settings>,
     path: PathBuf,
 }
 
@@ -59,7 +71,7 @@ impl SettingsPersister {
                     "{}",
                     error.display_chain_with_msg("Failed to load settings. Using defaults.")
                 );
-                let mut settings = Self::default_settings();
+                let mut settings = Preserving::new(Self::default_settings());
 
                 // Protect the user by blocking the internet by default. Previous settings may
                 // not have caused the daemon to enter the non-blocking disconnected state.
@@ -92,15 +104,15 @@ impl SettingsPersister {
         persister
     }
 
-    async fn load_from_file(path: &Path) -> Result<(Settings, bool), Error> {
+    async fn load_from_file(path: &Path) -> Result<(Preserving<Settings>, bool), Error> {
         log::info!("Loading settings from {}", path.display());
 
-        let settings_bytes = match fs::read(path).await {
+        let settings_bytes = match fs_retry::retry(|| fs::read(path)).await {
             Ok(bytes) => bytes,
             Err(error) => {
                 if error.kind() == io::ErrorKind::NotFound {
                     log::info!("No settings were found. Using defaults.");
-                    return Ok((Self::default_settings(), true));
+                    return Ok((Preserving::new(Self::default_settings()), true));
                 } else {
                     return Err(Error::ReadError(path.display().to_string(), error));
                 }
@@ -109,59 +121,63 @@ impl SettingsPersister {
         Ok((Self::load_from_bytes(&settings_bytes)?, false))
     }
 
-    fn load_from_bytes(bytes: &[u8]) -> Result<Settings, Error> {
-        serde_json::from_slice(bytes).map_err(Error::ParseError)
+    fn load_from_bytes(bytes: &[u8]) -> Result<Preserving<Settings>, Error> {
+        Preserving::from_slice(bytes, SETTINGS_FILE).map_err(Error::ParseError)
     }
 
     /// Serializes the settings and saves them to the file it was loaded from.
     async fn save(&mut self) -> Result<(), Error> {
         log::debug!("Writing settings to {}", self.path.display());
 
-        let buffer = serde_json::to_string_pretty(&self.settings).map_err(Error::SerializeError)?;
-        let mut options = fs::OpenOptions::new();
-        #[cfg(unix)]
-        {
-            options.mode(0o600);
-        }
-        let mut file = options
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&self.path)
-            .await
-            .map_err(|e| Error::WriteError(self.path.display().to_string(), e))?;
-        file.write_all(&buffer.into_bytes())
+        let buffer = self
+            .settings
+            .to_string_pretty()
+            .map_err(Error::SerializeError)?;
+        fs_retry::retry(|| Self::write_file(&self.path, buffer.as_bytes()))
             .await
             .map_err(|e| Error::WriteError(self.path.display().to_string(), e))?;
 
         #[cfg(unix)]
         {
             use std::os::unix::fs::PermissionsExt;
-            let mut permissions = file
-                .metadata()
+            let mut permissions = fs::metadata(&self.path)
                 .await
                 .map_err(Error::SetPermissions)?
                 .permissions();
             if permissions.mode() & 0o777 != 0o600 {
                 log::debug!("Updating file permissions");
                 permissions.set_mode(0o600);
-                file.set_permissions(permissions)
+                fs::set_permissions(&self.path, permissions)
                     .await
                     .map_err(Error::SetPermissions)?;
             }
         }
 
-        file.sync_all()
-            .await
-            .map_err(|e| Error::WriteError(self.path.display().to_string(), e))?;
-
         Ok(())
     }
 
+    /// Replaces the contents of the file at `path` with `buffer`. This can be safely repeated if
+    /// it fails.
+    async fn write_file(path: &Path, buffer: &[u8]) -> io::Result<()> {
+        let mut options = fs::OpenOptions::new();
+        #[cfg(unix)]
+        {
+            options.mode(0o600);
+        }
+        let mut file = options
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .await?;
+        file.write_all(buffer).await?;
+        file.sync_all().await
+    }
+
     /// Resets default settings
     #[cfg(not(target_os = "android"))]
     pub async fn reset(&mut self) -> Result<(), Error> {
-        self.settings = Self::default_settings();
+        self.settings = Preserving::new(Self::default_settings());
         let path = self.path.clone();
         self.save()
             .or_else(|e| async move {
@@ -178,7 +194,7 @@ impl SettingsPersister {
     }
 
     pub fn to_settings(&self) -> Settings {
-        self.settings.clone()
+        (*self.settings).clone()
     }
 
     /// Modifies `Settings::default()` somewhat, e.g. depending on whether a beta version
@@ -219,6 +235,28 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_allowed_networks(
+        &mut self,
+        allowed_networks: Vec<IpNetwork>,
+    ) -> Result<bool, Error> {
+        check_allowed_networks_supported(&allowed_networks)
+            .map_err(Error::InvalidAllowedNetworks)?;
+        let should_save = Self::update_field(&mut self.settings.allowed_networks, allowed_networks);
+        self.update(should_save).await
+    }
+
+    /// Changes all settings that make up `preset`, and saves them at once.
+    pub async fn set_security_preset(&mut self, preset: SecurityPreset) -> Result<bool, Error> {
+        let should_save = preset.apply(&mut self.settings);
+        self.update(should_save).await
+    }
+
+    /// Applies the valid sections of imported settings, and saves them at once.
+    pub async fn import_settings(&mut self, import: &SettingsImport) -> Result<bool, Error> {
+        let should_save = import.apply(&mut self.settings);
+        self.update(should_save).await
+    }
+
     pub async fn set_block_when_disconnected(
         &mut self,
         block_when_disconnected: bool,
@@ -235,6 +273,40 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_lockdown_after_boot(
+        &mut self,
+        lockdown_after_boot: bool,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.lockdown_after_boot, lockdown_after_boot);
+        self.update(should_save).await
+    }
+
+    pub async fn set_reconnect_before_maintenance(&mut self, enabled: bool) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.reconnect_before_maintenance, enabled);
+        self.update(should_save).await
+    }
+
+    pub async fn set_relay_removed_reconnect_delay(
+        &mut self,
+        delay: Duration,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.relay_removed_reconnect_delay, delay);
+        self.update(should_save).await
+    }
+
+    pub async fn set_api_doh_fallback(&mut self, enabled: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.api_doh_fallback, enabled);
+        self.update(should_save).await
+    }
+
+    pub async fn set_api_data_limit(&mut self, limit: Option<u64>) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.api_data_limit, limit);
+        self.update(should_save).await
+    }
+
     pub async fn set_openvpn_mssfix(&mut self, openvpn_mssfix: Option<u16>) -> Result<bool, Error> {
         let should_save = Self::update_field(
             &mut self.settings.tunnel_options.openvpn.mssfix,
@@ -243,6 +315,17 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_openvpn_data_cipher(
+        &mut self,
+        data_cipher: Option<openvpn::DataCipher>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.openvpn.data_cipher,
+            data_cipher,
+        );
+        self.update(should_save).await
+    }
+
     pub async fn set_enable_ipv6(&mut self, enable_ipv6: bool) -> Result<bool, Error> {
         let should_save = Self::update_field(
             &mut self.settings.tunnel_options.generic.enable_ipv6,
@@ -263,6 +346,33 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_wireguard_persistent_keepalive(
+        &mut self,
+        interval: Option<u16>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self
+                .settings
+                .tunnel_options
+                .wireguard
+                .options
+                .persistent_keepalive,
+            interval,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_wireguard_rate_limit(
+        &mut self,
+        limit: Option<RateLimit>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.wireguard.options.rate_limit,
+            limit,
+        );
+        self.update(should_save).await
+    }
+
     pub async fn set_wireguard_rotation_interval(
         &mut self,
         interval: Option<RotationInterval>,
@@ -291,6 +401,15 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_api_bridge_settings(
+        &mut self,
+        api_bridge_settings: ApiBridgeSettings,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.api_bridge_settings, api_bridge_settings);
+        self.update(should_save).await
+    }
+
     pub async fn set_bridge_state(&mut self, bridge_state: BridgeState) -> Result<bool, Error> {
         let should_save = self.settings.set_bridge_state(bridge_state);
         self.update(should_save).await
@@ -355,7 +474,7 @@ impl Deref for SettingsPersister {
 #[cfg(test)]
 mod test {
     use super::SettingsPersister;
-    use mullvad_types::settings::SettingsVersion;
+    use mullvad_types::settings::{SettingsVersion, DEFAULT_RELAY_REMOVED_RECONNECT_DELAY};
     use serde_json;
 
     #[test]
@@ -431,6 +550,34 @@ mod test {
               "show_beta_releases": false
         }"#;
 
-        let _ = SettingsPersister::load_from_bytes(settings).unwrap();
+        let settings = SettingsPersister::load_from_bytes(settings).unwrap();
+        // Settings that were added later are set to their defaults
+        assert_eq!(
+            settings.relay_removed_reconnect_delay,
+            DEFAULT_RELAY_REMOVED_RECONNECT_DELAY
+        );
+    }
+
+    #[test]
+    fn test_unknown_fields_are_preserved() {
+        let mut settings = SettingsPersister::load_from_bytes(
+            br#"{
+                "allow_lan": false,
+                "tunnel_options": { "generic": { "enable_ipv6": false, "added_later": 1 } },
+                "settings_version": 5,
+                "future_setting": { "enabled": true }
+            }"#,
+        )
+        .unwrap();
+        settings.allow_lan = true;
+
+        let saved: serde_json::Value =
+            serde_json::from_str(&settings.to_string_pretty().unwrap()).unwrap();
+        assert_eq!(saved["allow_lan"], true);
+        assert_eq!(
+            saved["future_setting"],
+            serde_json::json!({ "enabled": true })
+        );
+        assert_eq!(saved["tunnel_options"]["generic"]["added_later"], 1);
     }
 }
@@ -0,0 +1,506 @@
+//! Exports the settings that can be carried over to another machine, and imports them again. The
+//! account token, WireGuard keys, custom tunnel endpoints and bridge settings are never exported,
+//! since they may contain credentials.
+//!
+//! The exported settings use the same keys as the settings file, so that exports from older
+//! versions of the app can be brought up to date by the settings migrations.
+
+use crate::{migrations, settings, version};
+use chrono::{DateTime, Utc};
+use ipnetwork::IpNetwork;
+use mullvad_types::{
+    relay_constraints::{
+        RelayConstraints, RelayConstraintsUpdate, RelaySettings, RelaySettingsUpdate,
+    },
+    settings::{
+        check_allowed_networks_supported, validate_allowed_networks, DnsOptions, Settings,
+        CURRENT_SETTINGS_VERSION,
+    },
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt;
+#[cfg(windows)]
+use std::{collections::HashSet, path::PathBuf};
+
+/// The oldest settings version that can be imported. Older versions have no version field.
+const MIN_SETTINGS_VERSION: u64 = 2;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to serialize the settings")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to parse the settings file")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unsupported settings version: {:?}", _0)]
+    UnsupportedVersion(Option<u64>),
+
+    #[error(
+        display = "The settings were exported by a newer version of the app (settings version {})",
+        _0
+    )]
+    NewerVersion(u64),
+
+    #[error(display = "Failed to migrate the settings")]
+    Migrate(#[error(source)] migrations::Error),
+
+    #[error(display = "Failed to save the imported settings")]
+    WriteSettings(#[error(source)] settings::Error),
+}
+
+/// A group of settings that is either imported as a whole, or not at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    /// Relay location, tunnel protocol and the other relay constraints.
+    RelayConstraints,
+    Dns,
+    /// Local network sharing and the allowed networks.
+    Lan,
+    AutoConnect,
+    /// Applications excluded from the tunnel.
+    SplitTunnel,
+}
+
+impl Section {
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::RelayConstraints => "relay_constraints",
+            Section::Dns => "dns",
+            Section::Lan => "lan",
+            Section::AutoConnect => "auto_connect",
+            Section::SplitTunnel => "split_tunnel",
+        }
+    }
+}
+
+/// Why a section was not imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The file does not contain the section.
+    Missing,
+    /// The section cannot be used on this platform.
+    Unsupported,
+    /// The section could not be parsed or contains invalid values.
+    Invalid(String),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Missing => f.write_str("not present in the file"),
+            SkipReason::Unsupported => f.write_str("not supported on this platform"),
+            SkipReason::Invalid(reason) => write!(f, "invalid: {}", reason),
+        }
+    }
+}
+
+/// Which sections of an imported file were applied, and why the others were skipped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub applied: Vec<Section>,
+    pub skipped: Vec<(Section, SkipReason)>,
+}
+
+impl ImportReport {
+    /// Parses `value` and checks the result with `validate`. The outcome is added to the report.
+    fn parse_section<T: DeserializeOwned, U>(
+        &mut self,
+        section: Section,
+        value: Option<serde_json::Value>,
+        validate: impl FnOnce(T) -> Result<U, SkipReason>,
+    ) -> Option<U> {
+        let result = match value {
+            Some(value) => serde_json::from_value(value)
+                .map_err(|error| SkipReason::Invalid(error.to_string()))
+                .and_then(validate),
+            None => Err(SkipReason::Missing),
+        };
+        match result {
+            Ok(parsed) => {
+                self.applied.push(section);
+                Some(parsed)
+            }
+            Err(reason) => {
+                log::warn!("Not importing {} settings: {}", section.name(), reason);
+                self.skipped.push((section, reason));
+                None
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportFile {
+    /// Version of the daemon that exported the settings.
+    daemon_version: String,
+    exported_at: DateTime<Utc>,
+    /// The exported settings, in the settings file format given by `settings_version`.
+    settings: serde_json::Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct LanSettings {
+    allow_lan: bool,
+    #[serde(default)]
+    allowed_networks: Vec<IpNetwork>,
+}
+
+#[cfg(windows)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct SplitTunnelSettings {
+    enable_exclusions: bool,
+    #[serde(default)]
+    apps: HashSet<PathBuf>,
+}
+
+/// The valid sections of an imported file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingsImport {
+    relay_constraints: Option<RelayConstraints>,
+    dns_options: Option<DnsOptions>,
+    lan: Option<LanSettings>,
+    auto_connect: Option<bool>,
+    #[cfg(windows)]
+    split_tunnel: Option<SplitTunnelSettings>,
+    report: ImportReport,
+}
+
+/// Serializes the settings that can be shared with other machines.
+pub fn export(settings: &Settings) -> Result<String, Error> {
+    let mut exported = serde_json::json!({
+        "settings_version": CURRENT_SETTINGS_VERSION as u32,
+        "tunnel_options": {
+            "dns_options": settings.tunnel_options.dns_options,
+        },
+        "allow_lan": settings.allow_lan,
+        "allowed_networks": settings.allowed_networks,
+        "auto_connect": settings.auto_connect,
+    });
+    // Custom tunnel endpoints contain the credentials of the tunnel
+    if let relay_settings @ RelaySettings::Normal(_) = settings.get_relay_settings() {
+        exported["relay_settings"] =
+            serde_json::to_value(relay_settings).map_err(Error::Serialize)?;
+    }
+    #[cfg(windows)]
+    {
+        exported["split_tunnel"] =
+            serde_json::to_value(&settings.split_tunnel).map_err(Error::Serialize)?;
+    }
+
+    let file = ExportFile {
+        daemon_version: version::PRODUCT_VERSION.to_owned(),
+        exported_at: Utc::now(),
+        settings: exported,
+    };
+    serde_json::to_string_pretty(&file).map_err(Error::Serialize)
+}
+
+impl SettingsImport {
+    /// Parses a file created by [`export`], migrating it to the current settings format first.
+    /// Files exported by newer versions of the app are rejected. Invalid sections are skipped.
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let file: ExportFile = serde_json::from_str(contents).map_err(Error::Parse)?;
+        let mut settings = file.settings;
+
+        let version = settings
+            .get("settings_version")
+            .and_then(serde_json::Value::as_u64);
+        match version {
+            Some(version) if version > CURRENT_SETTINGS_VERSION as u64 => {
+                return Err(Error::NewerVersion(version));
+            }
+            Some(version) if version >= MIN_SETTINGS_VERSION => (),
+            _ => return Err(Error::UnsupportedVersion(version)),
+        }
+        migrations::migrate_partial(&mut settings).map_err(Error::Migrate)?;
+
+        let mut report = ImportReport::default();
+        let relay_constraints = report.parse_section(
+            Section::RelayConstraints,
+            settings.get("relay_settings").cloned(),
+            |relay_settings| match relay_settings {
+                RelaySettings::Normal(constraints) => Ok(constraints),
+                RelaySettings::CustomTunnelEndpoint(_) => Err(SkipReason::Invalid(
+                    "custom tunnel endpoints cannot be imported".to_owned(),
+                )),
+            },
+        );
+        let dns_options = report.parse_section(
+            Section::Dns,
+            settings
+                .get("tunnel_options")
+                .and_then(|options| options.get("dns_options"))
+                .cloned(),
+            Ok,
+        );
+        let lan_settings = settings.get("allow_lan").map(|allow_lan| {
+            let mut lan_settings = serde_json::json!({ "allow_lan": allow_lan });
+            if let Some(allowed_networks) = settings.get("allowed_networks") {
+                lan_settings["allowed_networks"] = allowed_networks.clone();
+            }
+            lan_settings
+        });
+        let lan = report.parse_section(Section::Lan, lan_settings, |lan: LanSettings| {
+            let allowed_networks = validate_allowed_networks(lan.allowed_networks)
+                .and_then(|networks| {
+                    check_allowed_networks_supported(&networks)?;
+                    Ok(networks)
+                })
+                .map_err(|error| SkipReason::Invalid(error.to_string()))?;
+            Ok(LanSettings {
+                allowed_networks,
+                ..lan
+            })
+        });
+        let auto_connect = report.parse_section(
+            Section::AutoConnect,
+            settings.get("auto_connect").cloned(),
+            Ok,
+        );
+
+        #[cfg(windows)]
+        let split_tunnel = report.parse_section(
+            Section::SplitTunnel,
+            settings.get("split_tunnel").cloned(),
+            Ok,
+        );
+        #[cfg(not(windows))]
+        if settings.get("split_tunnel").is_some() {
+            report
+                .skipped
+                .push((Section::SplitTunnel, SkipReason::Unsupported));
+        }
+
+        Ok(SettingsImport {
+            relay_constraints,
+            dns_options,
+            lan,
+            auto_connect,
+            #[cfg(windows)]
+            split_tunnel,
+            report,
+        })
+    }
+
+    pub fn report(&self) -> &ImportReport {
+        &self.report
+    }
+
+    /// Applies the valid sections to `settings`. Returns whether any setting changed.
+    pub fn apply(&self, settings: &mut Settings) -> bool {
+        let mut changed = false;
+        if let Some(constraints) = self.relay_constraints.clone() {
+            changed |= settings.update_relay_settings(RelaySettingsUpdate::Normal(
+                RelayConstraintsUpdate {
+                    location: Some(constraints.location),
+                    providers: Some(constraints.providers),
+                    ownership: Some(constraints.ownership),
+                    tunnel_protocol: Some(constraints.tunnel_protocol),
+                    wireguard_constraints: Some(constraints.wireguard_constraints),
+                    openvpn_constraints: Some(constraints.openvpn_constraints),
+                    excluded_asns: Some(constraints.excluded_asns),
+                },
+            ));
+        }
+        if let Some(dns_options) = &self.dns_options {
+            changed |= replace(&mut settings.tunnel_options.dns_options, dns_options);
+        }
+        if let Some(lan) = &self.lan {
+            changed |= replace(&mut settings.allow_lan, &lan.allow_lan);
+            changed |= replace(&mut settings.allowed_networks, &lan.allowed_networks);
+        }
+        if let Some(auto_connect) = &self.auto_connect {
+            changed |= replace(&mut settings.auto_connect, auto_connect);
+        }
+        #[cfg(windows)]
+        if let Some(split_tunnel) = &self.split_tunnel {
+            changed |= replace(
+                &mut settings.split_tunnel.enable_exclusions,
+                &split_tunnel.enable_exclusions,
+            );
+            changed |= replace(&mut settings.split_tunnel.apps, &split_tunnel.apps);
+        }
+        changed
+    }
+}
+
+/// Sets `field` to `value`. Returns whether the value changed.
+fn replace<T: PartialEq + Clone>(field: &mut T, value: &T) -> bool {
+    if field == value {
+        return false;
+    }
+    *field = value.clone();
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::{
+        relay_constraints::{Constraint, LocationConstraint},
+        settings::{DnsState, ALLOWED_NETWORKS_SUPPORTED},
+        wireguard::{AssociatedAddresses, WireguardData},
+    };
+    use talpid_types::net::{wireguard::PrivateKey, TunnelType};
+
+    fn custom_settings() -> Settings {
+        let mut settings = Settings::default();
+        settings.update_relay_settings(RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+            location: Some(Constraint::Only(LocationConstraint::Country(
+                "se".to_owned(),
+            ))),
+            tunnel_protocol: Some(Constraint::Only(TunnelType::Wireguard)),
+            excluded_asns: Some(vec![64500]),
+            ..Default::default()
+        }));
+        settings.tunnel_options.dns_options.state = DnsState::Custom;
+        settings.tunnel_options.dns_options.custom_options.addresses =
+            vec!["10.0.0.1".parse().unwrap()];
+        settings.allow_lan = true;
+        if ALLOWED_NETWORKS_SUPPORTED {
+            settings.allowed_networks = vec!["100.64.0.0/10".parse().unwrap()];
+        }
+        settings.auto_connect = true;
+        settings
+    }
+
+    /// Exports `custom_settings()` and lets `edit` change the exported settings.
+    fn export_with(edit: impl FnOnce(&mut serde_json::Value)) -> String {
+        let mut file: serde_json::Value =
+            serde_json::from_str(&export(&custom_settings()).unwrap()).unwrap();
+        edit(&mut file["settings"]);
+        file.to_string()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let exported = export(&custom_settings()).unwrap();
+        let import = SettingsImport::parse(&exported).unwrap();
+
+        let mut settings = Settings::default();
+        assert!(import.apply(&mut settings));
+        assert_eq!(settings, custom_settings());
+        assert!(!import.apply(&mut settings));
+
+        #[cfg(not(windows))]
+        assert_eq!(
+            import.report().applied,
+            vec![
+                Section::RelayConstraints,
+                Section::Dns,
+                Section::Lan,
+                Section::AutoConnect
+            ]
+        );
+    }
+
+    #[test]
+    fn test_secrets_are_not_exported() {
+        let mut settings = custom_settings();
+        settings.set_account_token(Some("1234123412341234".to_owned()));
+        let private_key = PrivateKey::new_from_random();
+        settings.set_wireguard(Some(WireguardData {
+            private_key: private_key.clone(),
+            addresses: AssociatedAddresses {
+                ipv4_address: "10.64.0.2/32".parse().unwrap(),
+                ipv6_address: "fc00::2/128".parse().unwrap(),
+            },
+            created: Utc::now(),
+        }));
+
+        let exported = export(&settings).unwrap();
+        assert!(!exported.contains("1234123412341234"));
+        assert!(!exported.contains(&private_key.to_base64()));
+        for key in [
+            "account_token",
+            "\"wireguard\"",
+            "private_key",
+            "bridge_settings",
+        ] {
+            assert!(!exported.contains(key), "{} was exported", key);
+        }
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let newer = CURRENT_SETTINGS_VERSION as u64 + 1;
+        let exported = export_with(|settings| {
+            settings["settings_version"] = serde_json::json!(newer);
+        });
+        assert!(matches!(
+            SettingsImport::parse(&exported),
+            Err(Error::NewerVersion(version)) if version == newer
+        ));
+
+        let unversioned = export_with(|settings| {
+            settings.as_object_mut().unwrap().remove("settings_version");
+        });
+        assert!(matches!(
+            SettingsImport::parse(&unversioned),
+            Err(Error::UnsupportedVersion(None))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_section_is_skipped() {
+        let exported = export_with(|settings| {
+            settings["allowed_networks"] = serde_json::json!(["0.0.0.0/0"]);
+            settings.as_object_mut().unwrap().remove("auto_connect");
+        });
+        let import = SettingsImport::parse(&exported).unwrap();
+
+        assert_eq!(
+            import.report().applied,
+            vec![Section::RelayConstraints, Section::Dns]
+        );
+        let skipped = &import.report().skipped;
+        assert_eq!(skipped[0].0, Section::Lan);
+        assert!(matches!(skipped[0].1, SkipReason::Invalid(_)));
+        assert_eq!(skipped[1], (Section::AutoConnect, SkipReason::Missing));
+
+        let mut settings = Settings::default();
+        import.apply(&mut settings);
+        assert_eq!(
+            settings.get_relay_settings(),
+            custom_settings().get_relay_settings()
+        );
+        assert!(!settings.allow_lan);
+        assert!(settings.allowed_networks.is_empty());
+    }
+
+    #[test]
+    fn test_import_is_migrated() {
+        // Multihop used to be enabled by setting an entry location
+        let exported = export_with(|settings| {
+            let constraints = &mut settings["relay_settings"]["normal"]["wireguard_constraints"];
+            constraints.as_object_mut().unwrap().remove("use_multihop");
+            constraints["entry_location"] = serde_json::json!({ "only": { "country": "de" } });
+        });
+        let import = SettingsImport::parse(&exported).unwrap();
+
+        let mut settings = Settings::default();
+        import.apply(&mut settings);
+        match settings.get_relay_settings() {
+            RelaySettings::Normal(constraints) => {
+                assert!(constraints.wireguard_constraints.use_multihop)
+            }
+            RelaySettings::CustomTunnelEndpoint(_) => unreachable!(),
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_split_tunnel_is_unsupported() {
+        let exported = export_with(|settings| {
+            settings["split_tunnel"] =
+                serde_json::json!({ "enable_exclusions": true, "apps": ["C:\\app.exe"] });
+        });
+        let import = SettingsImport::parse(&exported).unwrap();
+        assert!(import
+            .report()
+            .skipped
+            .contains(&(Section::SplitTunnel, SkipReason::Unsupported)));
+    }
+}
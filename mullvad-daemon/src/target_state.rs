@@ -10,19 +10,84 @@ use tokio::{fs, io};
 const DEFAULT_TARGET_STATE: TargetState = TargetState::Unsecured;
 const TARGET_START_STATE_FILE: &str = "target-start-state.json";
 
+/// Why the daemon started with the target state it did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupReason {
+    /// There was no cached target state, so the default was used.
+    NoCachedState,
+    /// The target state from before the daemon was stopped was restored.
+    Restored,
+    /// The cached target state could not be read, so the secured state is used to avoid leaks.
+    UnreadableCache,
+    /// Auto-connect is enabled.
+    AutoConnect,
+    /// There is no account to connect with.
+    NoAccount,
+    /// `lockdown_after_boot` is enabled, so traffic is blocked until a connect request arrives.
+    LockdownAfterBoot,
+}
+
+/// The target state the daemon started with, and whether it is still locked down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartupState {
+    pub reason: StartupReason,
+    /// Whether traffic is blocked until the next connect request.
+    pub locked_down: bool,
+    /// Report of a panic during the previous run of the daemon, if it crashed.
+    pub previous_crash_report: Option<PathBuf>,
+}
+
+/// How the target state is selected when the daemon starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupAction {
+    /// Use this state, regardless of the cached one.
+    Force(TargetState, StartupReason),
+    /// Restore the cached state.
+    Restore,
+}
+
+/// Selects the target state to start with. `lockdown_after_boot` takes precedence over
+/// everything else, so that neither auto-connect nor a restored state connects before the user
+/// asks to. A missing account takes precedence over auto-connect, since there is no way to
+/// connect without one.
+pub fn startup_action(
+    has_account: bool,
+    auto_connect: bool,
+    lockdown_after_boot: bool,
+) -> StartupAction {
+    if lockdown_after_boot {
+        StartupAction::Force(TargetState::Unsecured, StartupReason::LockdownAfterBoot)
+    } else if !has_account {
+        StartupAction::Force(TargetState::Unsecured, StartupReason::NoAccount)
+    } else if auto_connect {
+        StartupAction::Force(TargetState::Secured, StartupReason::AutoConnect)
+    } else {
+        StartupAction::Restore
+    }
+}
+
 /// Persists the target state to a file, which is only removed if the instance is dropped cleanly.
 pub struct PersistentTargetState {
     state: TargetState,
+    reason: StartupReason,
     cache_path: PathBuf,
     locked: bool,
 }
 
 impl PersistentTargetState {
+    /// Initialize using the selected startup action.
+    pub async fn from_action(cache_dir: &Path, action: StartupAction) -> Self {
+        match action {
+            StartupAction::Force(state, reason) => Self::force(cache_dir, state, reason).await,
+            StartupAction::Restore => Self::new(cache_dir).await,
+        }
+    }
+
     /// Initialize using the current target state (if there is one)
     pub async fn new(cache_dir: &Path) -> Self {
         let cache_path = cache_dir.join(TARGET_START_STATE_FILE);
         let mut update_cache = false;
-        let state = match fs::read_to_string(&cache_path).await {
+        let (state, reason) = match fs::read_to_string(&cache_path).await {
             Ok(content) => serde_json::from_str(&content)
                 .map(|state| {
                     log::info!(
@@ -30,7 +95,7 @@ impl PersistentTargetState {
                         state,
                         cache_path.display()
                     );
-                    state
+                    (state, StartupReason::Restored)
                 })
                 .unwrap_or_else(|error| {
                     log::error!(
@@ -38,24 +103,25 @@ impl PersistentTargetState {
                         error.display_chain_with_msg("Failed to parse cached target tunnel state")
                     );
                     update_cache = true;
-                    TargetState::Secured
+                    (TargetState::Secured, StartupReason::UnreadableCache)
                 }),
             Err(error) => {
                 if error.kind() == io::ErrorKind::NotFound {
                     log::debug!("No cached target state to load");
-                    DEFAULT_TARGET_STATE
+                    (DEFAULT_TARGET_STATE, StartupReason::NoCachedState)
                 } else {
                     log::error!(
                         "{}",
                         error.display_chain_with_msg("Failed to read cached target tunnel state")
                     );
                     update_cache = true;
-                    TargetState::Secured
+                    (TargetState::Secured, StartupReason::UnreadableCache)
                 }
             }
         };
         let state = PersistentTargetState {
             state,
+            reason,
             cache_path,
             locked: false,
         };
@@ -66,10 +132,11 @@ impl PersistentTargetState {
     }
 
     /// Override the current target state, if there is one
-    pub async fn force(cache_dir: &Path, state: TargetState) -> Self {
+    pub async fn force(cache_dir: &Path, state: TargetState, reason: StartupReason) -> Self {
         let cache_path = cache_dir.join(TARGET_START_STATE_FILE);
         let state = PersistentTargetState {
             state,
+            reason,
             cache_path,
             locked: false,
         };
@@ -84,6 +151,11 @@ impl PersistentTargetState {
         }
     }
 
+    /// Returns why the daemon started with the target state it did.
+    pub fn startup_reason(&self) -> StartupReason {
+        self.reason
+    }
+
     /// Prevent the file from being removed when the instance is dropped.
     pub fn lock(&mut self) {
         self.locked = true;
@@ -106,6 +178,8 @@ impl PersistentTargetState {
         self.locked = true;
     }
 
+    /// Saves the state to a temporary file first, so that the cache is never left with partial
+    /// content if the machine shuts down while the state is being written.
     async fn save(&self) {
         log::trace!(
             "Saving tunnel target state to {}",
@@ -113,7 +187,12 @@ impl PersistentTargetState {
         );
         match serde_json::to_string(&self.state) {
             Ok(data) => {
-                if let Err(error) = fs::write(&self.cache_path, data).await {
+                let temp_path = self.cache_path.with_extension("temp");
+                let result = async {
+                    fs::write(&temp_path, data).await?;
+                    fs::rename(&temp_path, &self.cache_path).await
+                };
+                if let Err(error) = result.await {
                     log::error!(
                         "{}",
                         error.display_chain_with_msg("Failed to write cache target state")
@@ -153,3 +232,94 @@ impl Deref for PersistentTargetState {
         &self.state
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_startup_action() {
+        use StartupAction::*;
+
+        // (has_account, auto_connect, lockdown_after_boot) => action
+        let cases = [
+            ((true, false, false), Restore),
+            (
+                (true, true, false),
+                Force(TargetState::Secured, StartupReason::AutoConnect),
+            ),
+            (
+                (false, true, false),
+                Force(TargetState::Unsecured, StartupReason::NoAccount),
+            ),
+            (
+                (false, false, false),
+                Force(TargetState::Unsecured, StartupReason::NoAccount),
+            ),
+            (
+                (true, false, true),
+                Force(TargetState::Unsecured, StartupReason::LockdownAfterBoot),
+            ),
+            (
+                (true, true, true),
+                Force(TargetState::Unsecured, StartupReason::LockdownAfterBoot),
+            ),
+            (
+                (false, true, true),
+                Force(TargetState::Unsecured, StartupReason::LockdownAfterBoot),
+            ),
+        ];
+        for ((has_account, auto_connect, lockdown_after_boot), expected) in cases {
+            assert_eq!(
+                startup_action(has_account, auto_connect, lockdown_after_boot),
+                expected,
+                "has_account: {}, auto_connect: {}, lockdown_after_boot: {}",
+                has_account,
+                auto_connect,
+                lockdown_after_boot
+            );
+        }
+    }
+
+    #[test]
+    fn test_restore_saved_state() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("mullvad-target-state-test-{}", std::process::id()));
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+
+        runtime.block_on(async {
+            let restored = PersistentTargetState::new(&cache_dir).await;
+            assert_eq!(*restored, DEFAULT_TARGET_STATE);
+            assert_eq!(restored.startup_reason(), StartupReason::NoCachedState);
+            drop(restored);
+
+            let mut state = PersistentTargetState::force(
+                &cache_dir,
+                TargetState::Unsecured,
+                StartupReason::AutoConnect,
+            )
+            .await;
+            state.set(TargetState::Secured).await;
+            // The file is kept if the daemon does not shut down cleanly
+            state.lock();
+            drop(state);
+
+            let restored =
+                PersistentTargetState::from_action(&cache_dir, StartupAction::Restore).await;
+            assert_eq!(*restored, TargetState::Secured);
+            assert_eq!(restored.startup_reason(), StartupReason::Restored);
+            assert!(!cache_dir
+                .join(TARGET_START_STATE_FILE)
+                .with_extension("temp")
+                .exists());
+
+            // A clean shutdown removes the file
+            restored.finalize().await;
+            let restored = PersistentTargetState::new(&cache_dir).await;
+            assert_eq!(restored.startup_reason(), StartupReason::NoCachedState);
+        });
+
+        let _ = std::fs::remove_dir_all(&cache_dir);
+    }
+}
@@ -1,7 +1,9 @@
 use crate::{
+    fs_retry,
     version::{is_beta_version, PRODUCT_VERSION},
     DaemonEventSender,
 };
+use chrono::{DateTime, Utc};
 use futures::{
     channel::{mpsc, oneshot},
     stream::FusedStream,
@@ -28,6 +30,9 @@ lazy_static::lazy_static! {
 }
 
 const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(15);
+/// The running version is reported as unsupported if an API endpoint that it uses stops working
+/// within this many days, and there is a version to upgrade to.
+const SUNSET_WARNING_DAYS: i64 = 30;
 /// How often the updater should wake up to check the in-memory cache.
 /// This exist to prevent problems around sleeping. If you set it to sleep
 /// for `UPDATE_INTERVAL` directly and the computer is suspended, that clock
@@ -262,17 +267,12 @@ impl VersionUpdater {
             "Writing version check cache to {}",
             self.cache_path.display()
         );
-        let mut file = File::create(&self.cache_path)
-            .await
-            .map_err(Error::WriteVersionCache)?;
         let cached_app_version = CachedAppVersionInfo::from(last_app_version_info.clone());
-        let mut buf = serde_json::to_vec_pretty(&cached_app_version).map_err(Error::Serialize)?;
-        let mut read_buf: &[u8] = buf.as_mut();
+        let buf = serde_json::to_vec_pretty(&cached_app_version).map_err(Error::Serialize)?;
 
-        let _ = tokio::io::copy(&mut read_buf, &mut file)
+        fs_retry::retry(|| write_cache_file(&self.cache_path, &buf))
             .await
-            .map_err(Error::WriteVersionCache)?;
-        Ok(())
+            .map_err(Error::WriteVersionCache)
     }
 
     fn response_to_version_info(
@@ -286,8 +286,17 @@ impl VersionUpdater {
             self.show_beta_releases || is_beta_version(),
         );
 
+        let sunset_is_near =
+            is_sunset_near(mullvad_rpc::deprecation::earliest_sunset(), Utc::now());
+        let supported = if response.supported && sunset_is_near && suggested_upgrade.is_some() {
+            log::warn!("This version uses API endpoints that will stop working soon");
+            false
+        } else {
+            response.supported
+        };
+
         AppVersionInfo {
-            supported: response.supported,
+            supported,
             latest_stable: response.latest_stable.unwrap_or_else(|| "".to_owned()),
             latest_beta: response.latest_beta,
             suggested_upgrade,
@@ -435,10 +444,16 @@ impl VersionUpdater {
     }
 }
 
+async fn write_cache_file(path: &Path, mut buffer: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path).await?;
+    tokio::io::copy(&mut buffer, &mut file).await?;
+    Ok(())
+}
+
 async fn try_load_cache(cache_dir: &Path) -> Result<AppVersionInfo, Error> {
     let path = cache_dir.join(VERSION_INFO_FILENAME);
     log::debug!("Loading version check cache from {}", path.display());
-    let content = fs::read_to_string(&path)
+    let content = fs_retry::retry(|| fs::read_to_string(&path))
         .map_err(Error::ReadVersionCache)
         .await?;
     let version_info: CachedAppVersionInfo =
@@ -464,10 +479,29 @@ pub async fn load_cache(cache_dir: &Path) -> Option<AppVersionInfo> {
     }
 }
 
+/// Returns whether `sunset` is less than `SUNSET_WARNING_DAYS` days after `now`.
+fn is_sunset_near(sunset: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    sunset
+        .map(|sunset| sunset - now < chrono::Duration::days(SUNSET_WARNING_DAYS))
+        .unwrap_or(false)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_sunset_is_near() {
+        let now = "2022-06-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let in_days = |days| Some(now + chrono::Duration::days(days));
+
+        assert!(!is_sunset_near(None, now));
+        assert!(!is_sunset_near(in_days(SUNSET_WARNING_DAYS), now));
+        assert!(is_sunset_near(in_days(SUNSET_WARNING_DAYS - 1), now));
+        // A sunset that has passed is also near
+        assert!(is_sunset_near(in_days(-1), now));
+    }
+
     #[test]
     fn test_version_upgrade_suggestions() {
         let latest_stable = Some("2020.4".to_string());
@@ -357,9 +357,7 @@ impl KeyManager {
         interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
         loop {
             interval.tick().await;
-            if (Utc::now().signed_duration_since(key.created)).num_seconds() as u64
-                >= rotation_interval_secs
-            {
+            if key.needs_rotation(Duration::from_secs(rotation_interval_secs)) {
                 return;
             }
         }
@@ -5,9 +5,13 @@ use jnix::{
     },
     IntoJava, JnixEnv,
 };
-use mullvad_daemon::EventListener;
+use mullvad_daemon::{EventListener, MigrationEvent};
 use mullvad_types::{
-    relay_list::RelayList, settings::Settings, states::TunnelState, version::AppVersionInfo,
+    account::AccountExpiry,
+    relay_list::{DeprecatedRelay, RelayList, RelayMaintenance},
+    settings::Settings,
+    states::TunnelState,
+    version::AppVersionInfo,
     wireguard::KeygenEvent,
 };
 use std::{sync::mpsc, thread};
@@ -63,6 +67,14 @@ impl EventListener for JniEventListener {
     fn notify_app_version(&self, app_version_info: AppVersionInfo) {
         let _ = self.0.send(Event::AppVersionInfo(app_version_info));
     }
+
+    fn notify_migration_event(&self, _migration_event: MigrationEvent) {}
+
+    fn notify_relay_deprecated(&self, _relay: DeprecatedRelay) {}
+
+    fn notify_account_expiry(&self, _expiry: AccountExpiry) {}
+
+    fn notify_relay_maintenance(&self, _maintenance: RelayMaintenance) {}
 }
 
 struct JniEventHandler<'env> {
@@ -1,5 +1,12 @@
+use std::{env, path::PathBuf};
+
 fn main() {
     const PROTO_FILE: &str = "proto/management_interface.proto";
-    tonic_build::compile_protos(PROTO_FILE).unwrap();
+    let descriptor_path =
+        PathBuf::from(env::var("OUT_DIR").unwrap()).join("management_interface_descriptor.bin");
+    tonic_build::configure()
+        .file_descriptor_set_path(descriptor_path)
+        .compile(&[PROTO_FILE], &["proto"])
+        .unwrap();
     println!("cargo:rerun-if-changed={}", PROTO_FILE);
 }
@@ -19,6 +19,23 @@ pub type ManagementServiceClient =
     types::management_service_client::ManagementServiceClient<Channel>;
 pub use types::management_service_server::{ManagementService, ManagementServiceServer};
 
+/// Major version of the management interface. Must be bumped when a change breaks existing
+/// clients, such as when a call or field is removed or the meaning of a field changes.
+pub const INTERFACE_VERSION_MAJOR: u32 = 1;
+/// Minor version of the management interface. Should be bumped when calls or fields are added.
+pub const INTERFACE_VERSION_MINOR: u32 = 16;
+
+/// Encoded descriptors of the management interface, which the reflection service is built from.
+#[cfg(feature = "reflection")]
+const FILE_DESCRIPTOR_SET: &[u8] =
+    tonic::include_file_descriptor_set!("management_interface_descriptor");
+
+/// Returns whether a client built against this interface can talk to a daemon that reports
+/// `version`.
+pub fn is_compatible_interface(version: &types::InterfaceVersion) -> bool {
+    version.major == INTERFACE_VERSION_MAJOR
+}
+
 #[cfg(unix)]
 lazy_static::lazy_static! {
     static ref MULLVAD_MANAGEMENT_SOCKET_GROUP: Option<String> = env::var("MULLVAD_MANAGEMENT_SOCKET_GROUP")
@@ -40,6 +57,10 @@ pub enum Error {
     #[error(display = "Unable to set permissions for IPC endpoint")]
     PermissionsError(#[error(source)] io::Error),
 
+    #[cfg(feature = "reflection")]
+    #[error(display = "Failed to create the reflection service")]
+    ReflectionError(#[error(source)] tonic_reflection::server::Error),
+
     #[cfg(unix)]
     #[error(display = "Group not found")]
     NoGidError,
@@ -97,9 +118,17 @@ pub async fn spawn_rpc_server<T: ManagementService, F: Future<Output = ()> + Sen
             .map_err(Error::PermissionsError)?;
     }
 
+    #[cfg(feature = "reflection")]
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .build()
+        .map_err(Error::ReflectionError)?;
+
     Ok(tokio::spawn(async move {
-        Server::builder()
-            .add_service(ManagementServiceServer::new(service))
+        let router = Server::builder().add_service(ManagementServiceServer::new(service));
+        #[cfg(feature = "reflection")]
+        let router = router.add_service(reflection_service);
+        router
             .serve_with_incoming_shutdown(incoming.map_ok(StreamBox), abort_rx)
             .await
             .map_err(Error::GrpcTransportError)
@@ -141,3 +170,75 @@ impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for StreamBox<T> {
         Pin::new(&mut self.0).poll_shutdown(cx)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use prost::Message;
+
+    #[test]
+    fn test_interface_compatibility() {
+        let version = |major, minor| types::InterfaceVersion {
+            major,
+            minor,
+            daemon_version: "2022.1".to_owned(),
+        };
+        assert!(is_compatible_interface(&version(
+            INTERFACE_VERSION_MAJOR,
+            INTERFACE_VERSION_MINOR
+        )));
+        assert!(is_compatible_interface(&version(
+            INTERFACE_VERSION_MAJOR,
+            INTERFACE_VERSION_MINOR + 1
+        )));
+        assert!(!is_compatible_interface(&version(
+            INTERFACE_VERSION_MAJOR + 1,
+            0
+        )));
+    }
+
+    #[test]
+    fn test_interface_version_decoding() {
+        let version = types::InterfaceVersion {
+            major: INTERFACE_VERSION_MAJOR,
+            minor: INTERFACE_VERSION_MINOR,
+            daemon_version: "2022.1".to_owned(),
+        };
+
+        // Fields added to the message by a newer daemon are ignored by older clients
+        let mut encoded = version.encode_to_vec();
+        encoded.extend_from_slice(&[(15 << 3), 1]);
+        assert_eq!(
+            types::InterfaceVersion::decode(encoded.as_slice()).unwrap(),
+            version
+        );
+
+        // A daemon that only sets the major version still decodes
+        let major_only = types::InterfaceVersion {
+            major: 2,
+            ..Default::default()
+        };
+        let decoded = types::InterfaceVersion::decode(major_only.encode_to_vec().as_slice());
+        assert_eq!(decoded.unwrap().major, 2);
+    }
+
+    #[cfg(feature = "reflection")]
+    #[test]
+    fn test_reflection_descriptors() {
+        let descriptors = prost_types::FileDescriptorSet::decode(FILE_DESCRIPTOR_SET).unwrap();
+        let file = descriptors
+            .file
+            .iter()
+            .find(|file| file.package() == "mullvad_daemon.management_interface")
+            .expect("Missing descriptor for the management interface");
+        assert!(file
+            .service
+            .iter()
+            .any(|service| service.name() == "ManagementService"));
+
+        assert!(tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+            .build()
+            .is_ok());
+    }
+}
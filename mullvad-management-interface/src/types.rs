@@ -240,6 +240,160 @@ impl From<mullvad_types::version::AppVersionInfo> for AppVersionInfo {
     }
 }
 
+impl From<talpid_types::tunnel::ConnectionAttemptMetrics> for ConnectionAttemptMetrics {
+    fn from(attempt: talpid_types::tunnel::ConnectionAttemptMetrics) -> Self {
+        use talpid_types::tunnel::ConnectionAttemptOutcome;
+
+        let outcome = match attempt.outcome {
+            ConnectionAttemptOutcome::Connected => connection_attempt_metrics::Outcome::Connected,
+            ConnectionAttemptOutcome::Failed => connection_attempt_metrics::Outcome::Failed,
+            ConnectionAttemptOutcome::Aborted => connection_attempt_metrics::Outcome::Aborted,
+        };
+
+        Self {
+            retry_attempt: attempt.retry_attempt,
+            parameter_generation: attempt.parameter_generation.map(Duration::from),
+            firewall_policy: attempt.firewall_policy.map(Duration::from),
+            tunnel_setup: attempt.tunnel_setup.map(Duration::from),
+            connected_setup: attempt.connected_setup.map(Duration::from),
+            total: Some(Duration::from(attempt.total)),
+            outcome: i32::from(outcome),
+        }
+    }
+}
+
+impl From<Vec<talpid_types::tunnel::ConnectionAttemptMetrics>> for ConnectionMetrics {
+    fn from(attempts: Vec<talpid_types::tunnel::ConnectionAttemptMetrics>) -> Self {
+        Self {
+            attempts: attempts
+                .into_iter()
+                .map(ConnectionAttemptMetrics::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<Vec<mullvad_types::relay_list::FailedRelay>> for FailedRelayList {
+    fn from(relays: Vec<mullvad_types::relay_list::FailedRelay>) -> Self {
+        Self {
+            relays: relays
+                .into_iter()
+                .map(|relay| FailedRelay {
+                    hostname: relay.hostname,
+                    penalty_remaining: Some(Duration::from(relay.penalty_remaining)),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<mullvad_types::connectivity_check::ConnectivityCheckResult> for ConnectivityCheckResult {
+    fn from(result: mullvad_types::connectivity_check::ConnectivityCheckResult) -> Self {
+        use mullvad_types::connectivity_check::{
+            ConnectivityCheckOutcome as Outcome, ConnectivityCheckStep as Step,
+        };
+
+        let step = match result.step {
+            Step::Api => connectivity_check_result::Step::Api,
+            Step::RelayReachability => connectivity_check_result::Step::RelayReachability,
+            Step::Dns => connectivity_check_result::Step::Dns,
+            Step::ExitIp => connectivity_check_result::Step::ExitIp,
+        };
+        let (outcome, detail) = match result.outcome {
+            Outcome::Passed => (connectivity_check_result::Outcome::Passed, String::new()),
+            Outcome::Failed(detail) => (connectivity_check_result::Outcome::Failed, detail),
+            Outcome::Skipped(detail) => (connectivity_check_result::Outcome::Skipped, detail),
+        };
+
+        Self {
+            step: i32::from(step),
+            outcome: i32::from(outcome),
+            latency: result.latency.map(Duration::from),
+            detail,
+        }
+    }
+}
+
+impl From<mullvad_types::connectivity_check::ConnectivityReport> for ConnectivityReport {
+    fn from(report: mullvad_types::connectivity_check::ConnectivityReport) -> Self {
+        Self {
+            results: report
+                .results
+                .into_iter()
+                .map(ConnectivityCheckResult::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<mullvad_types::dns::DnsStatus> for DnsStatus {
+    fn from(status: mullvad_types::dns::DnsStatus) -> Self {
+        use mullvad_types::{
+            connectivity_check::ConnectivityCheckOutcome as Outcome, dns::DnsSource,
+        };
+
+        let (source, blocking) = match status.source {
+            DnsSource::RelayDefault => (dns_status::Source::RelayDefault, None),
+            DnsSource::Custom => (dns_status::Source::Custom, None),
+            DnsSource::ContentBlocking(options) => (
+                dns_status::Source::ContentBlocking,
+                Some(DefaultDnsOptions {
+                    block_ads: options.block_ads,
+                    block_trackers: options.block_trackers,
+                    block_malware: options.block_malware,
+                }),
+            ),
+        };
+
+        Self {
+            source: i32::from(source),
+            blocking,
+            applied: status.applied.map(|applied| AppliedDnsConfig {
+                interface: applied.interface,
+                servers: applied
+                    .servers
+                    .iter()
+                    .map(|server| server.to_string())
+                    .collect(),
+                mechanism: applied.mechanism.to_string(),
+            }),
+            health: status
+                .health
+                .into_iter()
+                .map(|health| {
+                    let (outcome, detail) = match health.outcome {
+                        Outcome::Passed => {
+                            (connectivity_check_result::Outcome::Passed, String::new())
+                        }
+                        Outcome::Failed(detail) => {
+                            (connectivity_check_result::Outcome::Failed, detail)
+                        }
+                        Outcome::Skipped(detail) => {
+                            (connectivity_check_result::Outcome::Skipped, detail)
+                        }
+                    };
+                    DnsServerHealth {
+                        server: health.server.to_string(),
+                        outcome: i32::from(outcome),
+                        latency: health.latency.map(Duration::from),
+                        detail,
+                    }
+                })
+                .collect(),
+            tunnel_gateways: status
+                .tunnel_gateways
+                .iter()
+                .map(|gateway| gateway.to_string())
+                .collect(),
+            outside_tunnel: status
+                .outside_tunnel
+                .iter()
+                .map(|server| server.to_string())
+                .collect(),
+        }
+    }
+}
+
 impl From<mullvad_types::ConnectionConfig> for ConnectionConfig {
     fn from(config: mullvad_types::ConnectionConfig) -> Self {
         Self {
@@ -392,8 +546,23 @@ impl From<&mullvad_types::settings::Settings> for Settings {
             bridge_settings: Some(BridgeSettings::from(settings.bridge_settings.clone())),
             bridge_state: Some(BridgeState::from(settings.get_bridge_state())),
             allow_lan: settings.allow_lan,
+            allowed_networks: settings
+                .allowed_networks
+                .iter()
+                .map(|network| network.to_string())
+                .collect(),
+            api_bridge_settings: Some(ApiBridgeSettings::from(
+                settings.api_bridge_settings.clone(),
+            )),
+            api_doh_fallback: settings.api_doh_fallback,
+            api_data_limit: settings.api_data_limit.unwrap_or(0),
             block_when_disconnected: settings.block_when_disconnected,
             auto_connect: settings.auto_connect,
+            lockdown_after_boot: settings.lockdown_after_boot,
+            reconnect_before_maintenance: settings.reconnect_before_maintenance,
+            relay_removed_reconnect_delay: Some(Duration::from(
+                settings.relay_removed_reconnect_delay,
+            )),
             tunnel_options: Some(TunnelOptions::from(&settings.tunnel_options)),
             show_beta_releases: settings.show_beta_releases,
             split_tunnel,
@@ -414,6 +583,48 @@ impl From<mullvad_types::relay_constraints::BridgeState> for BridgeState {
     }
 }
 
+impl From<Option<mullvad_types::settings::SecurityPreset>> for SecurityPreset {
+    fn from(preset: Option<mullvad_types::settings::SecurityPreset>) -> Self {
+        use mullvad_types::settings::SecurityPreset;
+        Self {
+            preset: i32::from(match preset {
+                Some(SecurityPreset::Strict) => security_preset::Preset::Strict,
+                Some(SecurityPreset::Standard) => security_preset::Preset::Standard,
+                Some(SecurityPreset::LanFriendly) => security_preset::Preset::LanFriendly,
+                None => security_preset::Preset::Custom,
+            }),
+        }
+    }
+}
+
+impl From<Option<talpid_types::net::openvpn::DataCipher>> for OpenvpnDataCipher {
+    fn from(data_cipher: Option<talpid_types::net::openvpn::DataCipher>) -> Self {
+        use talpid_types::net::openvpn::DataCipher;
+        Self {
+            cipher: i32::from(match data_cipher {
+                Some(DataCipher::Aes256Gcm) => openvpn_data_cipher::Cipher::Aes256Gcm,
+                Some(DataCipher::Aes128Gcm) => openvpn_data_cipher::Cipher::Aes128Gcm,
+                Some(DataCipher::Chacha20Poly1305) => openvpn_data_cipher::Cipher::Chacha20Poly1305,
+                None => openvpn_data_cipher::Cipher::Default,
+            }),
+        }
+    }
+}
+
+impl From<mullvad_types::relay_constraints::ApiBridgeSettings> for ApiBridgeSettings {
+    fn from(settings: mullvad_types::relay_constraints::ApiBridgeSettings) -> Self {
+        use mullvad_types::relay_constraints::ApiBridgeMode;
+        Self {
+            mode: i32::from(match settings.mode {
+                ApiBridgeMode::Auto => api_bridge_settings::Mode::Auto,
+                ApiBridgeMode::Always => api_bridge_settings::Mode::Always,
+                ApiBridgeMode::Never => api_bridge_settings::Mode::Never,
+            }),
+            location: settings.location.option().map(RelayLocation::from),
+        }
+    }
+}
+
 impl From<mullvad_types::relay_constraints::BridgeSettings> for BridgeSettings {
     fn from(settings: mullvad_types::relay_constraints::BridgeSettings) -> Self {
         use mullvad_types::relay_constraints::BridgeSettings as MullvadBridgeSettings;
@@ -428,6 +639,7 @@ impl From<mullvad_types::relay_constraints::BridgeSettings> for BridgeSettings {
                         .option()
                         .map(RelayLocation::from),
                     providers: convert_providers_constraint(&constraints.providers),
+                    ownership: convert_ownership_constraint(constraints.ownership),
                 })
             }
             MullvadBridgeSettings::Custom(proxy_settings) => match proxy_settings {
@@ -480,6 +692,8 @@ impl From<mullvad_types::relay_constraints::RelaySettings> for RelaySettings {
                 relay_settings::Endpoint::Normal(NormalRelaySettings {
                     location: constraints.location.option().map(RelayLocation::from),
                     providers: convert_providers_constraint(&constraints.providers),
+                    excluded_asns: constraints.excluded_asns,
+                    ownership: convert_ownership_constraint(constraints.ownership),
                     tunnel_type: match constraints.tunnel_protocol {
                         Constraint::Any => None,
                         Constraint::Only(talpid_net::TunnelType::Wireguard) => {
@@ -549,6 +763,7 @@ impl From<&mullvad_types::settings::DnsOptions> for DnsOptions {
                     .iter()
                     .map(|addr| addr.to_string())
                     .collect(),
+                reject_outside_tunnel: options.custom_options.reject_outside_tunnel,
             }),
         }
     }
@@ -559,6 +774,7 @@ impl From<&mullvad_types::settings::TunnelOptions> for TunnelOptions {
         Self {
             openvpn: Some(tunnel_options::OpenvpnOptions {
                 mssfix: u32::from(options.openvpn.mssfix.unwrap_or_default()),
+                data_cipher: Some(OpenvpnDataCipher::from(options.openvpn.data_cipher)),
             }),
             wireguard: Some(tunnel_options::WireguardOptions {
                 mtu: u32::from(options.wireguard.options.mtu.unwrap_or_default()),
@@ -570,6 +786,14 @@ impl From<&mullvad_types::settings::TunnelOptions> for TunnelOptions {
                 use_wireguard_nt: options.wireguard.options.use_wireguard_nt,
                 #[cfg(not(windows))]
                 use_wireguard_nt: false,
+                persistent_keepalive: u32::from(
+                    options
+                        .wireguard
+                        .options
+                        .persistent_keepalive
+                        .unwrap_or_default(),
+                ),
+                rate_limit: options.wireguard.options.rate_limit.map(RateLimit::from),
             }),
             generic: Some(tunnel_options::GenericOptions {
                 enable_ipv6: options.generic.enable_ipv6,
@@ -582,6 +806,15 @@ impl From<&mullvad_types::settings::TunnelOptions> for TunnelOptions {
     }
 }
 
+impl From<talpid_types::net::wireguard::RateLimit> for RateLimit {
+    fn from(limit: talpid_types::net::wireguard::RateLimit) -> Self {
+        Self {
+            up_kbps: limit.up_kbps,
+            down_kbps: limit.down_kbps,
+        }
+    }
+}
+
 impl From<mullvad_types::relay_list::RelayListCountry> for RelayListCountry {
     fn from(country: mullvad_types::relay_list::RelayListCountry) -> Self {
         let mut proto_country = RelayListCountry {
@@ -617,6 +850,8 @@ impl From<mullvad_types::relay_list::Relay> for Relay {
             active: relay.active,
             owned: relay.owned,
             provider: relay.provider,
+            asn: relay.asn.unwrap_or_default(),
+            asn_organization: relay.asn_organization.unwrap_or_default(),
             weight: relay.weight,
             tunnels: Some(RelayTunnels {
                 openvpn: relay
@@ -671,6 +906,31 @@ impl From<mullvad_types::relay_list::Relay> for Relay {
                 latitude: location.latitude,
                 longitude: location.longitude,
             }),
+            maintenance: relay.maintenance.map(MaintenanceWindow::from),
+        }
+    }
+}
+
+impl From<mullvad_types::relay_list::MaintenanceWindow> for MaintenanceWindow {
+    fn from(window: mullvad_types::relay_list::MaintenanceWindow) -> Self {
+        Self {
+            start: Some(Timestamp {
+                seconds: window.start.timestamp(),
+                nanos: 0,
+            }),
+            end: Some(Timestamp {
+                seconds: window.end.timestamp(),
+                nanos: 0,
+            }),
+        }
+    }
+}
+
+impl From<RateLimit> for talpid_types::net::wireguard::RateLimit {
+    fn from(limit: RateLimit) -> Self {
+        Self {
+            up_kbps: limit.up_kbps,
+            down_kbps: limit.down_kbps,
         }
     }
 }
@@ -784,6 +1044,7 @@ impl TryFrom<RelaySettings> for mullvad_types::relay_constraints::RelaySettings
                     .map(Constraint::<mullvad_types::relay_constraints::LocationConstraint>::from)
                     .unwrap_or(Constraint::Any);
                 let providers = try_providers_constraint_from_proto(&settings.providers)?;
+                let ownership = try_ownership_constraint_from_i32(settings.ownership)?;
                 let tunnel_protocol = settings
                     .tunnel_type
                     .map(Constraint::<net::TunnelType>::try_from)
@@ -808,6 +1069,8 @@ impl TryFrom<RelaySettings> for mullvad_types::relay_constraints::RelaySettings
                         tunnel_protocol,
                         wireguard_constraints,
                         openvpn_constraints,
+                        excluded_asns: settings.excluded_asns,
+                        ownership,
                     },
                 ))
             }
@@ -864,6 +1127,10 @@ impl TryFrom<RelaySettingsUpdate> for mullvad_types::relay_constraints::RelaySet
                 } else {
                     None
                 };
+                let ownership = settings
+                    .ownership
+                    .map(|update| try_ownership_constraint_from_i32(update.ownership))
+                    .transpose()?;
                 let tunnel_protocol = if let Some(update) = settings.tunnel_type {
                     Some(
                         update
@@ -898,6 +1165,8 @@ impl TryFrom<RelaySettingsUpdate> for mullvad_types::relay_constraints::RelaySet
                         tunnel_protocol,
                         wireguard_constraints,
                         openvpn_constraints,
+                        excluded_asns: settings.excluded_asns.map(|update| update.asns),
+                        ownership,
                     },
                 ))
             }
@@ -1052,6 +1321,259 @@ impl TryFrom<ConnectionConfig> for mullvad_types::ConnectionConfig {
     }
 }
 
+impl TryFrom<GeoIpLocation> for mullvad_types::location::GeoIpLocation {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(geoip: GeoIpLocation) -> Result<Self, Self::Error> {
+        let ipv4 = if !geoip.ipv4.is_empty() {
+            Some(
+                geoip
+                    .ipv4
+                    .parse()
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid IPv4 address"))?,
+            )
+        } else {
+            None
+        };
+        let ipv6 = if !geoip.ipv6.is_empty() {
+            Some(
+                geoip
+                    .ipv6
+                    .parse()
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid IPv6 address"))?,
+            )
+        } else {
+            None
+        };
+
+        Ok(mullvad_types::location::GeoIpLocation {
+            ipv4,
+            ipv6,
+            country: geoip.country,
+            city: option_from_proto_string(geoip.city),
+            latitude: geoip.latitude,
+            longitude: geoip.longitude,
+            mullvad_exit_ip: geoip.mullvad_exit_ip,
+            hostname: option_from_proto_string(geoip.hostname),
+            bridge_hostname: option_from_proto_string(geoip.bridge_hostname),
+            entry_hostname: option_from_proto_string(geoip.entry_hostname),
+        })
+    }
+}
+
+impl TryFrom<Endpoint> for talpid_types::net::Endpoint {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(endpoint: Endpoint) -> Result<Self, Self::Error> {
+        Ok(talpid_types::net::Endpoint {
+            address: endpoint
+                .address
+                .parse()
+                .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid endpoint address"))?,
+            protocol: try_transport_protocol_from_i32(endpoint.protocol)?,
+        })
+    }
+}
+
+impl TryFrom<TunnelEndpoint> for talpid_types::net::TunnelEndpoint {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(endpoint: TunnelEndpoint) -> Result<Self, Self::Error> {
+        use talpid_types::net;
+
+        let tunnel_type = match TunnelType::from_i32(endpoint.tunnel_type) {
+            Some(TunnelType::Wireguard) => net::TunnelType::Wireguard,
+            Some(TunnelType::Openvpn) => net::TunnelType::OpenVpn,
+            None => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid tunnel type",
+                ))
+            }
+        };
+
+        let proxy = match endpoint.proxy {
+            Some(proxy_ep) => Some(net::proxy::ProxyEndpoint {
+                endpoint: net::Endpoint::try_from(Endpoint {
+                    address: proxy_ep.address,
+                    protocol: proxy_ep.protocol,
+                })?,
+                proxy_type: match ProxyType::from_i32(proxy_ep.proxy_type) {
+                    Some(ProxyType::Shadowsocks) => net::proxy::ProxyType::Shadowsocks,
+                    Some(ProxyType::Custom) => net::proxy::ProxyType::Custom,
+                    None => {
+                        return Err(FromProtobufTypeError::InvalidArgument("invalid proxy type"))
+                    }
+                },
+            }),
+            None => None,
+        };
+
+        Ok(net::TunnelEndpoint {
+            endpoint: net::Endpoint::try_from(Endpoint {
+                address: endpoint.address,
+                protocol: endpoint.protocol,
+            })?,
+            tunnel_type,
+            proxy,
+            entry_endpoint: endpoint
+                .entry_endpoint
+                .map(net::Endpoint::try_from)
+                .transpose()?,
+        })
+    }
+}
+
+impl TryFrom<TunnelState> for mullvad_types::states::TunnelState {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(state: TunnelState) -> Result<Self, Self::Error> {
+        use error_state::{
+            firewall_policy_error::ErrorType as PolicyErrorType, Cause, GenerationError,
+        };
+        use mullvad_types::{location, states::TunnelState as MullvadTunnelState};
+        use talpid_types::{net, tunnel as talpid_tunnel};
+
+        let try_relay_info = |relay_info: Option<TunnelStateRelayInfo>| {
+            let relay_info =
+                relay_info.ok_or(FromProtobufTypeError::InvalidArgument("missing relay info"))?;
+            let endpoint =
+                relay_info
+                    .tunnel_endpoint
+                    .ok_or(FromProtobufTypeError::InvalidArgument(
+                        "missing tunnel endpoint",
+                    ))?;
+            Ok::<_, FromProtobufTypeError>((
+                net::TunnelEndpoint::try_from(endpoint)?,
+                relay_info
+                    .location
+                    .map(location::GeoIpLocation::try_from)
+                    .transpose()?,
+            ))
+        };
+
+        let try_firewall_error =
+            |firewall_error: error_state::FirewallPolicyError| match PolicyErrorType::from_i32(
+                firewall_error.r#type,
+            ) {
+                Some(PolicyErrorType::Generic) => Ok(talpid_tunnel::FirewallPolicyError::Generic),
+                #[cfg(windows)]
+                Some(PolicyErrorType::Locked) => {
+                    let blocking_app =
+                        if firewall_error.lock_pid != 0 || !firewall_error.lock_name.is_empty() {
+                            Some(talpid_tunnel::BlockingApplication {
+                                name: firewall_error.lock_name,
+                                pid: firewall_error.lock_pid,
+                            })
+                        } else {
+                            None
+                        };
+                    Ok(talpid_tunnel::FirewallPolicyError::Locked(blocking_app))
+                }
+                _ => Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid firewall policy error",
+                )),
+            };
+
+        let state = state.state.ok_or(FromProtobufTypeError::InvalidArgument(
+            "missing tunnel state",
+        ))?;
+
+        Ok(match state {
+            tunnel_state::State::Disconnected(_) => MullvadTunnelState::Disconnected,
+            tunnel_state::State::Connecting(tunnel_state::Connecting { relay_info }) => {
+                let (endpoint, location) = try_relay_info(relay_info)?;
+                MullvadTunnelState::Connecting { endpoint, location }
+            }
+            tunnel_state::State::Connected(tunnel_state::Connected { relay_info }) => {
+                let (endpoint, location) = try_relay_info(relay_info)?;
+                MullvadTunnelState::Connected { endpoint, location }
+            }
+            tunnel_state::State::Disconnecting(tunnel_state::Disconnecting {
+                after_disconnect,
+            }) => MullvadTunnelState::Disconnecting(
+                match AfterDisconnect::from_i32(after_disconnect) {
+                    Some(AfterDisconnect::Nothing) => talpid_tunnel::ActionAfterDisconnect::Nothing,
+                    Some(AfterDisconnect::Block) => talpid_tunnel::ActionAfterDisconnect::Block,
+                    Some(AfterDisconnect::Reconnect) => {
+                        talpid_tunnel::ActionAfterDisconnect::Reconnect
+                    }
+                    None => {
+                        return Err(FromProtobufTypeError::InvalidArgument(
+                            "invalid action after disconnect",
+                        ))
+                    }
+                },
+            ),
+            tunnel_state::State::Error(tunnel_state::Error { error_state }) => {
+                let error_state = error_state.ok_or(FromProtobufTypeError::InvalidArgument(
+                    "missing error state",
+                ))?;
+
+                let cause = match Cause::from_i32(error_state.cause) {
+                    Some(Cause::AuthFailed) => talpid_tunnel::ErrorStateCause::AuthFailed(
+                        option_from_proto_string(error_state.auth_fail_reason),
+                    ),
+                    Some(Cause::Ipv6Unavailable) => talpid_tunnel::ErrorStateCause::Ipv6Unavailable,
+                    Some(Cause::SetFirewallPolicyError) => {
+                        let policy_error = error_state.policy_error.ok_or(
+                            FromProtobufTypeError::InvalidArgument("missing firewall policy error"),
+                        )?;
+                        talpid_tunnel::ErrorStateCause::SetFirewallPolicyError(try_firewall_error(
+                            policy_error,
+                        )?)
+                    }
+                    Some(Cause::SetDnsError) => talpid_tunnel::ErrorStateCause::SetDnsError,
+                    Some(Cause::StartTunnelError) => {
+                        talpid_tunnel::ErrorStateCause::StartTunnelError
+                    }
+                    Some(Cause::TunnelParameterError) => {
+                        talpid_tunnel::ErrorStateCause::TunnelParameterError(
+                            match GenerationError::from_i32(error_state.parameter_error) {
+                                Some(GenerationError::NoMatchingRelay) => {
+                                    talpid_tunnel::ParameterGenerationError::NoMatchingRelay
+                                }
+                                Some(GenerationError::NoMatchingBridgeRelay) => {
+                                    talpid_tunnel::ParameterGenerationError::NoMatchingBridgeRelay
+                                }
+                                Some(GenerationError::NoWireguardKey) => {
+                                    talpid_tunnel::ParameterGenerationError::NoWireguardKey
+                                }
+                                Some(GenerationError::CustomTunnelHostResolutionError) => {
+                                    talpid_tunnel::ParameterGenerationError::CustomTunnelHostResultionError
+                                }
+                                None => {
+                                    return Err(FromProtobufTypeError::InvalidArgument(
+                                        "invalid parameter error",
+                                    ))
+                                }
+                            },
+                        )
+                    }
+                    Some(Cause::IsOffline) => talpid_tunnel::ErrorStateCause::IsOffline,
+                    #[cfg(target_os = "android")]
+                    Some(Cause::VpnPermissionDenied) => {
+                        talpid_tunnel::ErrorStateCause::VpnPermissionDenied
+                    }
+                    #[cfg(target_os = "windows")]
+                    Some(Cause::SplitTunnelError) => talpid_tunnel::ErrorStateCause::SplitTunnelError,
+                    _ => {
+                        return Err(FromProtobufTypeError::InvalidArgument(
+                            "invalid error state cause",
+                        ))
+                    }
+                };
+
+                let block_failure = error_state
+                    .blocking_error
+                    .map(try_firewall_error)
+                    .transpose()?;
+
+                MullvadTunnelState::Error(talpid_tunnel::ErrorState::new(cause, block_failure))
+            }
+        })
+    }
+}
+
 impl From<RelayLocation> for Constraint<mullvad_types::relay_constraints::LocationConstraint> {
     fn from(location: RelayLocation) -> Self {
         use mullvad_types::relay_constraints::LocationConstraint;
@@ -1092,11 +1614,13 @@ impl TryFrom<BridgeSettings> for mullvad_types::relay_constraints::BridgeSetting
                     }
                 };
                 let providers = try_providers_constraint_from_proto(&constraints.providers)?;
+                let ownership = try_ownership_constraint_from_i32(constraints.ownership)?;
 
                 Ok(mullvad_constraints::BridgeSettings::Normal(
                     mullvad_constraints::BridgeConstraints {
                         location,
                         providers,
+                        ownership,
                     },
                 ))
             }
@@ -1144,6 +1668,31 @@ impl TryFrom<BridgeSettings> for mullvad_types::relay_constraints::BridgeSetting
     }
 }
 
+impl TryFrom<ApiBridgeSettings> for mullvad_types::relay_constraints::ApiBridgeSettings {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: ApiBridgeSettings) -> Result<Self, Self::Error> {
+        use mullvad_types::relay_constraints::ApiBridgeMode;
+
+        let mode = match api_bridge_settings::Mode::from_i32(settings.mode) {
+            Some(api_bridge_settings::Mode::Auto) => ApiBridgeMode::Auto,
+            Some(api_bridge_settings::Mode::Always) => ApiBridgeMode::Always,
+            Some(api_bridge_settings::Mode::Never) => ApiBridgeMode::Never,
+            None => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid API bridge mode",
+                ))
+            }
+        };
+        let location = settings
+            .location
+            .map(Constraint::<mullvad_types::relay_constraints::LocationConstraint>::from)
+            .unwrap_or(Constraint::Any);
+
+        Ok(mullvad_types::relay_constraints::ApiBridgeSettings { mode, location })
+    }
+}
+
 impl TryFrom<BridgeState> for mullvad_types::relay_constraints::BridgeState {
     type Error = FromProtobufTypeError;
 
@@ -1163,6 +1712,41 @@ impl TryFrom<BridgeState> for mullvad_types::relay_constraints::BridgeState {
     }
 }
 
+impl TryFrom<SecurityPreset> for mullvad_types::settings::SecurityPreset {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(preset: SecurityPreset) -> Result<Self, Self::Error> {
+        use mullvad_types::settings::SecurityPreset;
+        match security_preset::Preset::from_i32(preset.preset) {
+            Some(security_preset::Preset::Strict) => Ok(SecurityPreset::Strict),
+            Some(security_preset::Preset::Standard) => Ok(SecurityPreset::Standard),
+            Some(security_preset::Preset::LanFriendly) => Ok(SecurityPreset::LanFriendly),
+            Some(security_preset::Preset::Custom) | None => Err(
+                FromProtobufTypeError::InvalidArgument("invalid security preset"),
+            ),
+        }
+    }
+}
+
+impl TryFrom<OpenvpnDataCipher> for Option<talpid_types::net::openvpn::DataCipher> {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(data_cipher: OpenvpnDataCipher) -> Result<Self, Self::Error> {
+        use talpid_types::net::openvpn::DataCipher;
+        match openvpn_data_cipher::Cipher::from_i32(data_cipher.cipher) {
+            Some(openvpn_data_cipher::Cipher::Aes256Gcm) => Ok(Some(DataCipher::Aes256Gcm)),
+            Some(openvpn_data_cipher::Cipher::Aes128Gcm) => Ok(Some(DataCipher::Aes128Gcm)),
+            Some(openvpn_data_cipher::Cipher::Chacha20Poly1305) => {
+                Ok(Some(DataCipher::Chacha20Poly1305))
+            }
+            Some(openvpn_data_cipher::Cipher::Default) => Ok(None),
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid OpenVPN data cipher",
+            )),
+        }
+    }
+}
+
 impl TryFrom<TunnelOptions> for mullvad_types::settings::TunnelOptions {
     type Error = FromProtobufTypeError;
 
@@ -1197,6 +1781,10 @@ impl TryFrom<TunnelOptions> for mullvad_types::settings::TunnelOptions {
                 } else {
                     None
                 },
+                data_cipher: match openvpn_options.data_cipher {
+                    Some(data_cipher) => Option::<net::openvpn::DataCipher>::try_from(data_cipher)?,
+                    None => None,
+                },
             },
             wireguard: mullvad_types::wireguard::TunnelOptions {
                 options: net::wireguard::TunnelOptions {
@@ -1205,6 +1793,14 @@ impl TryFrom<TunnelOptions> for mullvad_types::settings::TunnelOptions {
                     } else {
                         None
                     },
+                    persistent_keepalive: if wireguard_options.persistent_keepalive != 0 {
+                        Some(wireguard_options.persistent_keepalive as u16)
+                    } else {
+                        None
+                    },
+                    rate_limit: wireguard_options
+                        .rate_limit
+                        .map(net::wireguard::RateLimit::from),
                     #[cfg(windows)]
                     use_wireguard_nt: wireguard_options.use_wireguard_nt,
                 },
@@ -1282,6 +1878,7 @@ impl TryFrom<DnsOptions> for mullvad_types::settings::DnsOptions {
                         })
                     })
                     .collect::<Result<Vec<_>, _>>()?,
+                reject_outside_tunnel: custom_options.reject_outside_tunnel,
             },
         })
     }
@@ -1302,6 +1899,14 @@ impl TryFrom<TransportPort> for mullvad_types::relay_constraints::TransportPort
     }
 }
 
+fn option_from_proto_string(s: String) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
 fn try_transport_protocol_from_i32(
     protocol: i32,
 ) -> Result<talpid_types::net::TransportProtocol, FromProtobufTypeError> {
@@ -1335,6 +1940,32 @@ fn convert_providers_constraint(
     }
 }
 
+pub fn try_ownership_constraint_from_i32(
+    ownership: i32,
+) -> Result<Constraint<mullvad_types::relay_constraints::Ownership>, FromProtobufTypeError> {
+    use mullvad_types::relay_constraints::Ownership as MullvadOwnership;
+
+    match Ownership::from_i32(ownership) {
+        Some(Ownership::Any) => Ok(Constraint::Any),
+        Some(Ownership::MullvadOwned) => Ok(Constraint::Only(MullvadOwnership::MullvadOwned)),
+        Some(Ownership::Rented) => Ok(Constraint::Only(MullvadOwnership::Rented)),
+        None => Err(FromProtobufTypeError::InvalidArgument("invalid ownership")),
+    }
+}
+
+fn convert_ownership_constraint(
+    ownership: Constraint<mullvad_types::relay_constraints::Ownership>,
+) -> i32 {
+    use mullvad_types::relay_constraints::Ownership as MullvadOwnership;
+
+    let ownership = match ownership {
+        Constraint::Any => Ownership::Any,
+        Constraint::Only(MullvadOwnership::MullvadOwned) => Ownership::MullvadOwned,
+        Constraint::Only(MullvadOwnership::Rented) => Ownership::Rented,
+    };
+    i32::from(ownership)
+}
+
 impl From<FromProtobufTypeError> for crate::Status {
     fn from(err: FromProtobufTypeError) -> Self {
         match err {
@@ -1342,3 +1973,234 @@ impl From<FromProtobufTypeError> for crate::Status {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::connectivity_check::{
+        ConnectivityCheckOutcome, ConnectivityCheckResult as CheckResult, ConnectivityCheckStep,
+    };
+    use talpid_types::tunnel::ConnectionAttemptOutcome;
+
+    #[test]
+    fn test_api_bridge_settings_conversion() {
+        use mullvad_types::relay_constraints::{
+            ApiBridgeMode, ApiBridgeSettings as MullvadApiBridgeSettings, LocationConstraint,
+        };
+
+        let settings = MullvadApiBridgeSettings {
+            mode: ApiBridgeMode::Always,
+            location: Constraint::Only(LocationConstraint::City(
+                "de".to_string(),
+                "ber".to_string(),
+            )),
+        };
+        let proto_settings = ApiBridgeSettings::from(settings.clone());
+        assert_eq!(
+            proto_settings.mode,
+            i32::from(api_bridge_settings::Mode::Always)
+        );
+        assert_eq!(
+            MullvadApiBridgeSettings::try_from(proto_settings).unwrap(),
+            settings
+        );
+
+        // An unset location means that the bridge location is used
+        let proto_settings = ApiBridgeSettings::from(MullvadApiBridgeSettings::default());
+        assert_eq!(proto_settings.location, None);
+        assert_eq!(
+            MullvadApiBridgeSettings::try_from(proto_settings).unwrap(),
+            MullvadApiBridgeSettings::default()
+        );
+
+        assert!(MullvadApiBridgeSettings::try_from(ApiBridgeSettings {
+            mode: 10,
+            location: None,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_dns_status_conversion() {
+        use mullvad_types::{
+            dns::{
+                DnsServerHealth as MullvadDnsServerHealth, DnsSource, DnsStatus as MullvadDnsStatus,
+            },
+            settings::DefaultDnsOptions as MullvadDefaultDnsOptions,
+        };
+        use talpid_types::net::dns::{AppliedDnsConfig as TalpidAppliedDnsConfig, DnsMechanism};
+
+        let status = DnsStatus::from(MullvadDnsStatus {
+            source: DnsSource::ContentBlocking(MullvadDefaultDnsOptions {
+                block_ads: true,
+                block_trackers: false,
+                block_malware: false,
+            }),
+            applied: Some(TalpidAppliedDnsConfig {
+                interface: "wg-mullvad".to_string(),
+                servers: vec!["100.64.0.1".parse().unwrap()],
+                mechanism: DnsMechanism::SystemdResolved,
+            }),
+            health: vec![MullvadDnsServerHealth {
+                server: "100.64.0.1".parse().unwrap(),
+                outcome: ConnectivityCheckOutcome::Failed("timed out".to_string()),
+                latency: Some(std::time::Duration::from_secs(2)),
+            }],
+            tunnel_gateways: vec!["10.64.0.1".parse().unwrap()],
+            outside_tunnel: vec![],
+        });
+
+        assert_eq!(
+            status.source,
+            i32::from(dns_status::Source::ContentBlocking)
+        );
+        assert!(status.blocking.unwrap().block_ads);
+        let applied = status.applied.unwrap();
+        assert_eq!(applied.servers, vec!["100.64.0.1".to_string()]);
+        assert_eq!(applied.mechanism, "systemd-resolved");
+        assert_eq!(
+            status.health[0].outcome,
+            i32::from(connectivity_check_result::Outcome::Failed)
+        );
+        assert_eq!(status.health[0].detail, "timed out");
+        assert_eq!(status.tunnel_gateways, vec!["10.64.0.1".to_string()]);
+        assert!(status.outside_tunnel.is_empty());
+    }
+
+    #[test]
+    fn test_connectivity_report_conversion() {
+        let report = mullvad_types::connectivity_check::ConnectivityReport {
+            results: vec![
+                CheckResult {
+                    step: ConnectivityCheckStep::Api,
+                    outcome: ConnectivityCheckOutcome::Passed,
+                    latency: Some(std::time::Duration::from_millis(120)),
+                },
+                CheckResult {
+                    step: ConnectivityCheckStep::Dns,
+                    outcome: ConnectivityCheckOutcome::Failed("timed out".to_string()),
+                    latency: Some(std::time::Duration::from_secs(5)),
+                },
+                CheckResult {
+                    step: ConnectivityCheckStep::ExitIp,
+                    outcome: ConnectivityCheckOutcome::Skipped("not connected".to_string()),
+                    latency: None,
+                },
+            ],
+        };
+
+        let report = ConnectivityReport::from(report);
+        assert_eq!(report.results.len(), 3);
+
+        let api = &report.results[0];
+        assert_eq!(api.step, i32::from(connectivity_check_result::Step::Api));
+        assert_eq!(
+            api.outcome,
+            i32::from(connectivity_check_result::Outcome::Passed)
+        );
+        assert_eq!(
+            api.latency,
+            Some(Duration {
+                seconds: 0,
+                nanos: 120_000_000
+            })
+        );
+        assert!(api.detail.is_empty());
+
+        let dns = &report.results[1];
+        assert_eq!(
+            dns.outcome,
+            i32::from(connectivity_check_result::Outcome::Failed)
+        );
+        assert_eq!(dns.detail, "timed out");
+
+        let exit_ip = &report.results[2];
+        assert_eq!(
+            exit_ip.step,
+            i32::from(connectivity_check_result::Step::ExitIp)
+        );
+        assert_eq!(
+            exit_ip.outcome,
+            i32::from(connectivity_check_result::Outcome::Skipped)
+        );
+        assert_eq!(exit_ip.latency, None);
+        assert_eq!(exit_ip.detail, "not connected");
+    }
+
+    #[test]
+    fn test_connection_metrics_conversion() {
+        let attempts = vec![
+            talpid_types::tunnel::ConnectionAttemptMetrics {
+                retry_attempt: 0,
+                parameter_generation: Some(std::time::Duration::from_millis(3)),
+                firewall_policy: Some(std::time::Duration::from_millis(12)),
+                tunnel_setup: None,
+                connected_setup: None,
+                total: std::time::Duration::from_millis(1500),
+                outcome: ConnectionAttemptOutcome::Aborted,
+            },
+            talpid_types::tunnel::ConnectionAttemptMetrics {
+                retry_attempt: 1,
+                parameter_generation: Some(std::time::Duration::from_millis(2)),
+                firewall_policy: Some(std::time::Duration::from_millis(10)),
+                tunnel_setup: Some(std::time::Duration::from_secs(2)),
+                connected_setup: Some(std::time::Duration::from_millis(40)),
+                total: std::time::Duration::from_millis(2052),
+                outcome: ConnectionAttemptOutcome::Connected,
+            },
+        ];
+
+        let metrics = ConnectionMetrics::from(attempts);
+        assert_eq!(metrics.attempts.len(), 2);
+
+        let aborted = &metrics.attempts[0];
+        assert_eq!(
+            aborted.outcome,
+            i32::from(connection_attempt_metrics::Outcome::Aborted)
+        );
+        assert_eq!(
+            aborted.firewall_policy,
+            Some(Duration {
+                seconds: 0,
+                nanos: 12_000_000
+            })
+        );
+        assert_eq!(aborted.tunnel_setup, None);
+
+        let connected = &metrics.attempts[1];
+        assert_eq!(connected.retry_attempt, 1);
+        assert_eq!(
+            connected.outcome,
+            i32::from(connection_attempt_metrics::Outcome::Connected)
+        );
+        assert_eq!(
+            connected.tunnel_setup,
+            Some(Duration {
+                seconds: 2,
+                nanos: 0
+            })
+        );
+        assert_eq!(
+            connected.total,
+            Some(Duration {
+                seconds: 2,
+                nanos: 52_000_000
+            })
+        );
+    }
+
+    #[test]
+    fn test_security_preset_conversion() {
+        use mullvad_types::settings::SecurityPreset as MullvadSecurityPreset;
+
+        for preset in MullvadSecurityPreset::ALL {
+            let converted = SecurityPreset::from(Some(preset));
+            assert_eq!(MullvadSecurityPreset::try_from(converted).unwrap(), preset);
+        }
+
+        // Custom settings cannot be applied, and neither can presets added by newer clients
+        let custom = SecurityPreset::from(None);
+        assert!(MullvadSecurityPreset::try_from(custom).is_err());
+        assert!(MullvadSecurityPreset::try_from(SecurityPreset { preset: 100 }).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 #![deny(rust_2018_idioms)]
 
 use lazy_static::lazy_static;
-use mullvad_rpc::proxy::ApiConnectionMode;
+use mullvad_rpc::{proxy::ApiConnectionMode, UploadProgress};
 use regex::Regex;
 use std::{
     borrow::Cow,
@@ -11,16 +11,26 @@ use std::{
     fs::{self, File},
     io::{self, BufWriter, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    time::Duration,
 };
 use talpid_types::ErrorExt;
 
+mod log_filter;
 pub mod metadata;
 
-/// Maximum number of bytes to read from each log file
+pub use log_filter::{parse_duration, LogFilter};
+
+/// Maximum number of bytes to include from each log file
 const LOG_MAX_READ_BYTES: usize = 128 * 1024;
+/// Maximum number of bytes to read from the end of each log file before it is filtered
+const LOG_MAX_SCAN_BYTES: usize = 8 * LOG_MAX_READ_BYTES;
+/// Maximum number of bytes to include in the timeline of the daemon and frontend logs
+const TIMELINE_MAX_BYTES: usize = LOG_MAX_READ_BYTES;
 const EXTRA_BYTES: usize = 32 * 1024;
-/// Fit five logs plus some system information in the report.
-const REPORT_MAX_SIZE: usize = (5 * LOG_MAX_READ_BYTES) + EXTRA_BYTES;
+/// Fit five logs and the timeline plus some system information in the report.
+const REPORT_MAX_SIZE: usize = (5 * LOG_MAX_READ_BYTES) + TIMELINE_MAX_BYTES + EXTRA_BYTES;
+/// How often the progress of sending a report is checked.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Field delimeter in generated problem report
 const LOG_DELIMITER: &str = "====================";
@@ -110,9 +120,15 @@ pub fn collect_report(
     extra_logs: &[&Path],
     output_path: &Path,
     redact_custom_strings: Vec<String>,
+    log_filter: LogFilter,
     #[cfg(target_os = "android")] android_log_dir: &Path,
 ) -> Result<(), Error> {
-    let mut problem_report = ProblemReport::new(redact_custom_strings);
+    let mut problem_report = ProblemReport::new(redact_custom_strings, log_filter);
+
+    match mullvad_paths::get_cache_dir() {
+        Ok(cache_dir) => problem_report.add_connection_attempts(&cache_dir),
+        Err(error) => problem_report.add_error("Failed to find the cache directory", &error),
+    }
 
     let daemon_logs_dir = {
         #[cfg(target_os = "android")]
@@ -134,6 +150,8 @@ pub fn collect_report(
                     Ok(path) => {
                         if is_tunnel_log(&path) {
                             problem_report.add_log(&path);
+                        } else if is_daemon_log(&path) {
+                            problem_report.add_timeline_log(&path);
                         } else {
                             other_logs.push(path);
                         }
@@ -153,7 +171,7 @@ pub fn collect_report(
         Some(Ok(frontend_logs)) => {
             for log in frontend_logs {
                 match log {
-                    Ok(path) => problem_report.add_log(&path),
+                    Ok(path) => problem_report.add_timeline_log(&path),
                     Err(error) => problem_report.add_error("Unable to get log path", &error),
                 }
             }
@@ -170,6 +188,7 @@ pub fn collect_report(
     }
 
     problem_report.add_logs(extra_logs);
+    problem_report.add_timeline();
 
     write_problem_report(&output_path, &problem_report).map_err(|source| Error::WriteReportError {
         path: output_path.display().to_string(),
@@ -246,6 +265,14 @@ fn is_tunnel_log(path: &Path) -> bool {
     }
 }
 
+/// Returns whether `path` is the current or rotated log of the daemon.
+fn is_daemon_log(path: &Path) -> bool {
+    match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy().starts_with("daemon."),
+        None => false,
+    }
+}
+
 #[cfg(target_os = "android")]
 fn write_logcat_to_file(log_dir: &Path) -> Result<PathBuf, io::Error> {
     let logcat_path = log_dir.join("logcat.txt");
@@ -262,6 +289,18 @@ pub fn send_problem_report(
     user_message: &str,
     report_path: &Path,
     cache_dir: &Path,
+) -> Result<(), Error> {
+    send_problem_report_with_progress(user_email, user_message, report_path, cache_dir, |_| ())
+}
+
+/// Like `send_problem_report`, but calls `on_progress` with the percentage of the report that has
+/// been sent whenever it changes. The percentage starts over if sending is retried.
+pub fn send_problem_report_with_progress(
+    user_email: &str,
+    user_message: &str,
+    report_path: &Path,
+    cache_dir: &Path,
+    on_progress: impl Fn(u8) + Send + 'static,
 ) -> Result<(), Error> {
     let report_content = normalize_newlines(
         read_file_lossy(report_path, REPORT_MAX_SIZE).map_err(|source| {
@@ -282,6 +321,7 @@ pub fn send_problem_report(
         user_message,
         &report_content,
         cache_dir,
+        on_progress,
     ))
 }
 
@@ -290,12 +330,14 @@ async fn send_problem_report_inner(
     user_message: &str,
     report_content: &str,
     cache_dir: &Path,
+    on_progress: impl Fn(u8) + Send + 'static,
 ) -> Result<(), Error> {
     let metadata =
         ProblemReport::parse_metadata(&report_content).unwrap_or_else(|| metadata::collect());
     let rpc_runtime = mullvad_rpc::MullvadRpcRuntime::with_cache(
         cache_dir,
         false,
+        false,
         #[cfg(target_os = "android")]
         None,
     )
@@ -313,17 +355,31 @@ async fn send_problem_report_inner(
             .await,
     );
 
+    // The report is only serialized once, and the same body is sent on every attempt
+    let body = mullvad_rpc::ProblemReportProxy::serialize_problem_report(
+        user_email,
+        user_message,
+        report_content,
+        &metadata,
+    )
+    .map_err(Error::SendProblemReportError)?;
+    let progress = UploadProgress::new();
+    let progress_reporter = tokio::spawn(report_progress(progress.clone(), on_progress));
+
+    let mut result = Err(Error::SendFailedTooManyTimes);
     for _attempt in 0..MAX_SEND_ATTEMPTS {
         match rpc_client
-            .problem_report(user_email, user_message, &report_content, &metadata)
+            .send_problem_report(body.clone(), progress.clone())
             .await
         {
             Ok(()) => {
-                return Ok(());
+                result = Ok(());
+                break;
             }
             Err(error) => {
                 if !error.is_network_error() {
-                    return Err(Error::SendProblemReportError(error));
+                    result = Err(Error::SendProblemReportError(error));
+                    break;
                 }
                 log::error!(
                     "{}",
@@ -334,7 +390,22 @@ async fn send_problem_report_inner(
             }
         }
     }
-    Err(Error::SendFailedTooManyTimes)
+    progress_reporter.abort();
+    result
+}
+
+/// Calls `on_progress` whenever the percentage of the report that has been sent changes.
+async fn report_progress(progress: UploadProgress, on_progress: impl Fn(u8)) {
+    let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+    let mut last_percentage = None;
+    loop {
+        interval.tick().await;
+        let percentage = progress.percentage();
+        if progress.total_bytes() > 0 && last_percentage != Some(percentage) {
+            on_progress(percentage);
+            last_percentage = Some(percentage);
+        }
+    }
 }
 
 fn write_problem_report(path: &Path, problem_report: &ProblemReport) -> io::Result<()> {
@@ -351,20 +422,25 @@ struct ProblemReport {
     metadata: BTreeMap<String, String>,
     logs: Vec<(String, String)>,
     log_paths: HashSet<PathBuf>,
+    timeline_logs: Vec<(String, String)>,
     redact_custom_strings: Vec<String>,
+    log_filter: LogFilter,
 }
 
 impl ProblemReport {
     /// Creates a new problem report with system information. Logs can be added with `add_log`.
-    /// Logs will have all strings in `redact_custom_strings` removed from them.
-    pub fn new(mut redact_custom_strings: Vec<String>) -> Self {
+    /// Logs will have the entries rejected by `log_filter` and all strings in
+    /// `redact_custom_strings` removed from them.
+    pub fn new(mut redact_custom_strings: Vec<String>, log_filter: LogFilter) -> Self {
         redact_custom_strings.retain(|redact| !redact.is_empty());
 
         ProblemReport {
             metadata: metadata::collect(),
             logs: Vec::new(),
             log_paths: HashSet::new(),
+            timeline_logs: Vec::new(),
             redact_custom_strings,
+            log_filter,
         }
     }
 
@@ -383,22 +459,75 @@ impl ProblemReport {
     /// Attach a file log to this report. This method adds the error chain instead of the log
     /// contents if an error occurs while reading the log file.
     pub fn add_log(&mut self, path: &Path) {
+        self.add_log_inner(path, false);
+    }
+
+    /// Like `add_log`, but also includes the log in the timeline added by `add_timeline`.
+    pub fn add_timeline_log(&mut self, path: &Path) {
+        self.add_log_inner(path, true);
+    }
+
+    fn add_log_inner(&mut self, path: &Path, in_timeline: bool) {
         let expanded_path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
         if self.log_paths.insert(expanded_path.clone()) {
             let redacted_path = self.redact(&expanded_path.to_string_lossy());
-            let content = self.redact(&read_file_lossy(path, LOG_MAX_READ_BYTES).unwrap_or_else(
-                |error| {
-                    error.display_chain_with_msg(&format!(
-                        "Error reading the contents of log file: {}",
-                        expanded_path.display()
-                    ))
-                },
-            ));
+            let content = match read_file_lossy(path, LOG_MAX_SCAN_BYTES) {
+                Ok(content) => {
+                    let filtered = self.log_filter.apply(&content, LOG_MAX_READ_BYTES);
+                    if in_timeline {
+                        let source = path
+                            .file_name()
+                            .map(|name| name.to_string_lossy().into_owned())
+                            .unwrap_or_else(|| redacted_path.clone());
+                        self.timeline_logs.push((source, content));
+                    }
+                    filtered
+                }
+                Err(error) => error.display_chain_with_msg(&format!(
+                    "Error reading the contents of log file: {}",
+                    expanded_path.display()
+                )),
+            };
+            // Redact after filtering, so that filtering sees the original entries
+            let content = self.redact(&content);
             self.logs.push((redacted_path, content));
             log::info!("Adding {}", expanded_path.display());
         }
     }
 
+    /// Attach a timeline that interleaves the entries of the logs added with `add_timeline_log`
+    /// by their timestamps. The timeline is only added if the log filter has a time window, and
+    /// it is placed before the other logs.
+    pub fn add_timeline(&mut self) {
+        if self.log_filter.since.is_none() || self.timeline_logs.is_empty() {
+            return;
+        }
+        let logs: Vec<(&str, &str)> = self
+            .timeline_logs
+            .iter()
+            .map(|(source, content)| (source.as_str(), content.as_str()))
+            .collect();
+        let timeline = self.log_filter.timeline(&logs, TIMELINE_MAX_BYTES);
+        let timeline = self.redact(&timeline);
+        self.timeline_logs.clear();
+        self.logs.insert(0, ("Timeline".to_owned(), timeline));
+    }
+
+    /// Attach the most recent connection attempts that the daemon recorded in `cache_dir`. Nothing
+    /// is added if no attempts have been recorded.
+    pub fn add_connection_attempts(&mut self, cache_dir: &Path) {
+        let path = cache_dir.join(talpid_types::tunnel::CONNECTION_METRICS_FILENAME);
+        match fs::read_to_string(&path) {
+            Ok(attempts) => {
+                let attempts = self.redact(&attempts);
+                self.logs
+                    .push(("Recent connection attempts".to_owned(), attempts));
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => self.add_error("Failed to read the recent connection attempts", &error),
+        }
+    }
+
     /// Attach an error to the report.
     pub fn add_error(&mut self, message: &'static str, error: &impl ErrorExt) {
         let redacted_error = self.redact(&error.display_chain());
@@ -663,20 +792,49 @@ mod tests {
     }
 
     fn assert_redacts(input: &str) {
-        let report = ProblemReport::new(vec![]);
+        let report = ProblemReport::new(vec![], LogFilter::default());
         let actual = report.redact(&format!("pre {} post", input));
         assert_eq!("pre [REDACTED] post", actual);
     }
 
     fn assert_does_not_redact(input: &str) {
-        let report = ProblemReport::new(vec![]);
+        let report = ProblemReport::new(vec![], LogFilter::default());
         let res = report.redact(input);
         assert_eq!(input, res);
     }
 
+    #[test]
+    fn adds_connection_attempts() {
+        let cache_dir = std::env::temp_dir().join(format!(
+            "mullvad-problem-report-test-{}",
+            uuid::Uuid::new_v4()
+        ));
+        fs::create_dir(&cache_dir).unwrap();
+
+        let mut report = ProblemReport::new(vec![], LogFilter::default());
+        report.add_connection_attempts(&cache_dir);
+        assert!(report.logs.is_empty());
+
+        let attempts =
+            "Attempt 1 (connected): total 1200 ms, relay selection 3 ms, firewall 20 ms, \
+            tunnel setup 1100 ms, connected setup 77 ms\n";
+        fs::write(
+            cache_dir.join(talpid_types::tunnel::CONNECTION_METRICS_FILENAME),
+            attempts,
+        )
+        .unwrap();
+        report.add_connection_attempts(&cache_dir);
+        assert_eq!(
+            report.logs,
+            vec![("Recent connection attempts".to_owned(), attempts.to_owned())]
+        );
+
+        let _ = fs::remove_dir_all(&cache_dir);
+    }
+
     #[test]
     fn parse_metadata() {
-        let report = ProblemReport::new(Vec::new());
+        let report = ProblemReport::new(Vec::new(), LogFilter::default());
         let mut report_data = Vec::new();
         report
             .write_to(&mut report_data)
@@ -0,0 +1,482 @@
+//! Filters and truncates logs before they are added to a problem report. Entries are parsed from
+//! the format written by the daemon, `[<timestamp>][<target>][<level>] <message>`, and the format
+//! written by the frontend, `[<timestamp>][<level>] <message>`, where the message may span several
+//! lines. Lines in other formats are kept as they are.
+
+use chrono::{DateTime, Duration as ChronoDuration, Local, NaiveDateTime};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::time::Duration;
+
+/// Formats of timestamps in local time. The fractional seconds are optional.
+const LOCAL_TIMESTAMP_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"];
+
+/// Formats of timestamps with an offset from UTC, as written by older versions.
+const OFFSET_TIMESTAMP_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f%z",
+    "%Y-%m-%d %H:%M:%S%.f %z",
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+];
+
+/// Controls which log entries are included in a problem report.
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    /// Entries less severe than this are removed. Errors are always kept.
+    pub min_level: Option<log::Level>,
+    /// Entries older than this are removed. Errors are always kept.
+    pub since: Option<Duration>,
+}
+
+/// Parses a duration such as `90s`, `30m`, `24h` or `7d`.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split_at = value.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = value.split_at(split_at);
+    let amount: u64 = amount.parse().ok()?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(amount.checked_mul(unit_secs)?))
+}
+
+struct Entry<'a> {
+    text: &'a str,
+    level: Option<log::Level>,
+    timestamp: Option<NaiveDateTime>,
+}
+
+impl Entry<'_> {
+    /// Errors and panics are never filtered out, and are the last entries to be truncated.
+    fn is_important(&self) -> bool {
+        self.level == Some(log::Level::Error) || self.text.contains("panicked at")
+    }
+}
+
+impl LogFilter {
+    /// Removes the entries in `log` that do not pass the filter, and then removes the oldest
+    /// entries until at most `max_bytes` remain. Entries are never split, and errors are only
+    /// removed if the errors alone do not fit.
+    pub(crate) fn apply(&self, log: &str, max_bytes: usize) -> String {
+        self.apply_at(log, max_bytes, Local::now().naive_local())
+    }
+
+    fn apply_at(&self, log: &str, max_bytes: usize, now: NaiveDateTime) -> String {
+        let cutoff = self.cutoff(now);
+
+        let entries: Vec<Entry<'_>> = parse_entries(log)
+            .into_iter()
+            .filter(|entry| entry.is_important() || self.includes(entry, cutoff))
+            .collect();
+        truncate(&entries, max_bytes)
+    }
+
+    /// Interleaves the entries of several logs by their timestamps. Each log is given together
+    /// with the name of its source, which is prepended to its entries. Only entries that pass the
+    /// filter and have a timestamp are included, and the oldest entries are removed until at most
+    /// `max_bytes` remain. Entries with equal timestamps keep the order they are given in.
+    pub(crate) fn timeline(&self, logs: &[(&str, &str)], max_bytes: usize) -> String {
+        self.timeline_at(logs, max_bytes, Local::now().naive_local())
+    }
+
+    fn timeline_at(&self, logs: &[(&str, &str)], max_bytes: usize, now: NaiveDateTime) -> String {
+        let cutoff = self.cutoff(now);
+
+        let mut entries: Vec<(&str, NaiveDateTime, Entry<'_>)> = Vec::new();
+        for &(source, log) in logs {
+            for entry in parse_entries(log) {
+                if let Some(timestamp) = entry.timestamp {
+                    if self.includes(&entry, cutoff) {
+                        entries.push((source, timestamp, entry));
+                    }
+                }
+            }
+        }
+        entries.sort_by_key(|(_, timestamp, _)| *timestamp);
+
+        let lines: Vec<String> = entries
+            .iter()
+            .map(|(source, _, entry)| format!("[{}] {}", source, entry.text))
+            .collect();
+        let keep = select_newest(
+            lines
+                .iter()
+                .zip(&entries)
+                .map(|(line, (_, _, entry))| (line.len(), entry.is_important())),
+            max_bytes,
+        );
+        lines
+            .iter()
+            .zip(keep)
+            .filter(|(_, keep)| *keep)
+            .map(|(line, _)| line.as_str())
+            .collect()
+    }
+
+    fn cutoff(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        self.since
+            .and_then(|since| ChronoDuration::from_std(since).ok())
+            .and_then(|since| now.checked_sub_signed(since))
+    }
+
+    fn includes(&self, entry: &Entry<'_>, cutoff: Option<NaiveDateTime>) -> bool {
+        let level_included = match (self.min_level, entry.level) {
+            (Some(min_level), Some(level)) => level <= min_level,
+            _ => true,
+        };
+        let time_included = match (cutoff, entry.timestamp) {
+            (Some(cutoff), Some(timestamp)) => timestamp >= cutoff,
+            _ => true,
+        };
+        level_included && time_included
+    }
+}
+
+/// Parses a timestamp in local time, or with an offset from UTC. Timestamps with an offset are
+/// converted to local time, so that they can be compared with the other timestamps.
+fn parse_timestamp(timestamp: &str) -> Option<NaiveDateTime> {
+    for format in LOCAL_TIMESTAMP_FORMATS {
+        if let Ok(timestamp) = NaiveDateTime::parse_from_str(timestamp, format) {
+            return Some(timestamp);
+        }
+    }
+    OFFSET_TIMESTAMP_FORMATS
+        .iter()
+        .find_map(|format| DateTime::parse_from_str(timestamp, format).ok())
+        .or_else(|| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map(|timestamp| timestamp.with_timezone(&Local).naive_local())
+}
+
+/// Parses the level of an entry. The frontend uses some names of its own.
+fn parse_level(level: &str) -> Option<log::Level> {
+    match level.to_ascii_lowercase().as_str() {
+        "warning" => Some(log::Level::Warn),
+        "verbose" => Some(log::Level::Debug),
+        level => level.parse().ok(),
+    }
+}
+
+/// Splits `log` into entries. Lines that do not start a new entry belong to the previous entry,
+/// or are entries of their own if no entry has started yet. Entries with a malformed timestamp
+/// have no timestamp, and are not filtered by time.
+fn parse_entries(log: &str) -> Vec<Entry<'_>> {
+    lazy_static! {
+        static ref HEADER: Regex =
+            Regex::new(r"^\[(?P<timestamp>[^\]]*)\](?:\[[^\]]*\])?\[(?P<level>[A-Za-z]+)\]")
+                .unwrap();
+    }
+
+    let mut entries: Vec<Entry<'_>> = Vec::new();
+    let mut start = 0;
+    let mut in_entry = false;
+    for line in log.split_inclusive('\n') {
+        let end = start + line.len();
+        match HEADER.captures(line) {
+            Some(captures) => {
+                entries.push(Entry {
+                    text: &log[start..end],
+                    level: parse_level(&captures["level"]),
+                    timestamp: parse_timestamp(&captures["timestamp"]),
+                });
+                in_entry = true;
+            }
+            None => match entries.last_mut() {
+                Some(entry) if in_entry => {
+                    let entry_start = end - line.len() - entry.text.len();
+                    entry.text = &log[entry_start..end];
+                }
+                _ => entries.push(Entry {
+                    text: line,
+                    level: None,
+                    timestamp: None,
+                }),
+            },
+        }
+        start = end;
+    }
+    entries
+}
+
+/// Keeps as many of the newest entries as fit in `max_bytes`, but fits important entries first.
+fn truncate(entries: &[Entry<'_>], max_bytes: usize) -> String {
+    let keep = select_newest(
+        entries
+            .iter()
+            .map(|entry| (entry.text.len(), entry.is_important())),
+        max_bytes,
+    );
+
+    let mut output = String::new();
+    for (entry, keep) in entries.iter().zip(keep) {
+        if keep {
+            output.push_str(entry.text);
+        }
+    }
+    output
+}
+
+/// Selects as many of the newest entries as fit in `max_bytes`, but selects important entries
+/// first. Entries are given as their size and whether they are important.
+fn select_newest(entries: impl Iterator<Item = (usize, bool)>, max_bytes: usize) -> Vec<bool> {
+    let entries: Vec<(usize, bool)> = entries.collect();
+    let mut keep = vec![false; entries.len()];
+    let mut size = 0;
+
+    for (index, (len, important)) in entries.iter().enumerate().rev() {
+        if *important && size + len <= max_bytes {
+            keep[index] = true;
+            size += len;
+        }
+    }
+    for (index, (len, important)) in entries.iter().enumerate().rev() {
+        if *important {
+            continue;
+        }
+        if size + len > max_bytes {
+            break;
+        }
+        keep[index] = true;
+        size += len;
+    }
+    keep
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{FixedOffset, TimeZone};
+
+    const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+    const LOG: &str = "\
+rotated log fragment
+[2022-03-01 10:00:00.000][mullvad_daemon][DEBUG] Old debug message
+[2022-03-01 10:00:01.000][mullvad_daemon][ERROR] Old error
+Caused by: something
+[2022-03-02 09:00:00.000][mullvad_daemon::version_check][INFO] Version check
+[2022-03-02 09:30:00.000][talpid_core::tunnel][TRACE] Multi-line
+  trace message
+[2022-03-02 09:59:00.000][mullvad_daemon][WARN] Recent warning
+";
+
+    fn now() -> NaiveDateTime {
+        NaiveDateTime::parse_from_str("2022-03-02 10:00:00.000", TIMESTAMP_FORMAT).unwrap()
+    }
+
+    #[test]
+    fn test_no_filter() {
+        assert_eq!(LogFilter::default().apply_at(LOG, 1024, now()), LOG);
+    }
+
+    #[test]
+    fn test_filter_by_level() {
+        let filter = LogFilter {
+            min_level: Some(log::Level::Info),
+            since: None,
+        };
+        assert_eq!(
+            filter.apply_at(LOG, 1024, now()),
+            "\
+rotated log fragment
+[2022-03-01 10:00:01.000][mullvad_daemon][ERROR] Old error
+Caused by: something
+[2022-03-02 09:00:00.000][mullvad_daemon::version_check][INFO] Version check
+[2022-03-02 09:59:00.000][mullvad_daemon][WARN] Recent warning
+"
+        );
+    }
+
+    #[test]
+    fn test_filter_by_time() {
+        let filter = LogFilter {
+            min_level: None,
+            since: Some(Duration::from_secs(59 * 60)),
+        };
+        assert_eq!(
+            filter.apply_at(LOG, 1024, now()),
+            "\
+rotated log fragment
+[2022-03-01 10:00:01.000][mullvad_daemon][ERROR] Old error
+Caused by: something
+[2022-03-02 09:30:00.000][talpid_core::tunnel][TRACE] Multi-line
+  trace message
+[2022-03-02 09:59:00.000][mullvad_daemon][WARN] Recent warning
+"
+        );
+    }
+
+    #[test]
+    fn test_malformed_timestamp_is_kept() {
+        let log = "[yesterday][mullvad_daemon][INFO] Message\n";
+        let filter = LogFilter {
+            min_level: Some(log::Level::Info),
+            since: Some(Duration::from_secs(60)),
+        };
+        assert_eq!(filter.apply_at(log, 1024, now()), log);
+    }
+
+    #[test]
+    fn test_truncation_keeps_errors() {
+        let log = "\
+[2022-03-02 09:00:00.000][mullvad_daemon][ERROR] Error
+thread 'main' panicked at 'boom'
+[2022-03-02 09:01:00.000][mullvad_daemon][INFO] First
+[2022-03-02 09:02:00.000][mullvad_daemon][INFO] Second
+";
+        let error_block = "\
+[2022-03-02 09:00:00.000][mullvad_daemon][ERROR] Error
+thread 'main' panicked at 'boom'
+";
+        let second = "[2022-03-02 09:02:00.000][mullvad_daemon][INFO] Second\n";
+        let max_bytes = error_block.len() + second.len() + 10;
+        assert_eq!(
+            LogFilter::default().apply_at(log, max_bytes, now()),
+            format!("{}{}", error_block, second)
+        );
+    }
+
+    #[test]
+    fn test_frontend_format() {
+        let log = "\
+[2022-03-02 09:00:00.000][verbose] Old frontend message
+[2022-03-02 09:30:00.000][warning] Recent frontend warning
+[2022-03-02 09:31:00.000][debug] Recent debug message
+";
+        let filter = LogFilter {
+            min_level: Some(log::Level::Info),
+            since: Some(Duration::from_secs(59 * 60)),
+        };
+        assert_eq!(
+            filter.apply_at(log, 1024, now()),
+            "[2022-03-02 09:30:00.000][warning] Recent frontend warning\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamps() {
+        let expected = NaiveDateTime::parse_from_str("2022-03-02 09:30:00.000", TIMESTAMP_FORMAT);
+        assert_eq!(parse_timestamp("2022-03-02 09:30:00.000"), expected.ok());
+        assert_eq!(parse_timestamp("2022-03-02 09:30:00"), expected.ok());
+        assert_eq!(parse_timestamp("2022-03-02T09:30:00.000"), expected.ok());
+        assert_eq!(parse_timestamp("yesterday"), None);
+
+        let with_offset = FixedOffset::east(2 * 60 * 60)
+            .ymd(2022, 3, 2)
+            .and_hms(9, 30, 0)
+            .with_timezone(&Local)
+            .naive_local();
+        assert_eq!(
+            parse_timestamp("2022-03-02 09:30:00.000+02:00"),
+            Some(with_offset)
+        );
+        assert_eq!(
+            parse_timestamp("2022-03-02 09:30:00 +0200"),
+            Some(with_offset)
+        );
+        assert_eq!(
+            parse_timestamp("2022-03-02T07:30:00.000Z"),
+            Some(with_offset)
+        );
+    }
+
+    #[test]
+    fn test_unparsable_log_is_kept() {
+        let log = "Mon Mar  2 09:00:00 2022 OpenVPN 2.5.1\nMon Mar  2 09:00:01 2022 Connected\n";
+        let filter = LogFilter {
+            min_level: Some(log::Level::Info),
+            since: Some(Duration::from_secs(60)),
+        };
+        assert_eq!(filter.apply_at(log, 1024, now()), log);
+        assert_eq!(filter.timeline_at(&[("openvpn.log", log)], 1024, now()), "");
+    }
+
+    #[test]
+    fn test_timeline_order() {
+        let daemon_log = "\
+[2022-03-02 09:00:00.000][mullvad_daemon][INFO] Connecting
+[2022-03-02 09:00:02.000][mullvad_daemon][INFO] Connected
+  to relay
+";
+        let frontend_log = "\
+[2022-03-02 09:00:00.000][info] Connect button pressed
+[2022-03-02 09:00:01.000][info] Connecting
+[2022-03-02 09:00:03.000][info] Connected
+";
+        let timeline = LogFilter::default().timeline_at(
+            &[("daemon.log", daemon_log), ("frontend.log", frontend_log)],
+            1024,
+            now(),
+        );
+        assert_eq!(
+            timeline,
+            "\
+[daemon.log] [2022-03-02 09:00:00.000][mullvad_daemon][INFO] Connecting
+[frontend.log] [2022-03-02 09:00:00.000][info] Connect button pressed
+[frontend.log] [2022-03-02 09:00:01.000][info] Connecting
+[daemon.log] [2022-03-02 09:00:02.000][mullvad_daemon][INFO] Connected
+  to relay
+[frontend.log] [2022-03-02 09:00:03.000][info] Connected
+"
+        );
+    }
+
+    #[test]
+    fn test_timeline_across_rotation() {
+        // The window starts in the rotated log and ends in the current log
+        let old_log = "\
+[2022-03-02 08:00:00.000][mullvad_daemon][INFO] Too old
+[2022-03-02 09:40:00.000][mullvad_daemon][INFO] Before rotation
+";
+        let log = "\
+[2022-03-02 09:50:00.000][mullvad_daemon][INFO] After rotation
+";
+        let filter = LogFilter {
+            min_level: None,
+            since: Some(Duration::from_secs(30 * 60)),
+        };
+        let timeline = filter.timeline_at(
+            &[("daemon.log", log), ("daemon.old.log", old_log)],
+            1024,
+            now(),
+        );
+        assert_eq!(
+            timeline,
+            "\
+[daemon.old.log] [2022-03-02 09:40:00.000][mullvad_daemon][INFO] Before rotation
+[daemon.log] [2022-03-02 09:50:00.000][mullvad_daemon][INFO] After rotation
+"
+        );
+
+        // The newest entries are kept when the timeline does not fit
+        let newest =
+            "[daemon.log] [2022-03-02 09:50:00.000][mullvad_daemon][INFO] After rotation\n";
+        assert_eq!(
+            filter.timeline_at(
+                &[("daemon.log", log), ("daemon.old.log", old_log)],
+                newest.len(),
+                now()
+            ),
+            newest
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(
+            parse_duration("24h"),
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_duration("2d"),
+            Some(Duration::from_secs(2 * 24 * 60 * 60))
+        );
+        assert_eq!(parse_duration("24"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("1w"), None);
+    }
+}
@@ -1,8 +1,13 @@
 #![deny(rust_2018_idioms)]
 
 use clap::{crate_authors, crate_name};
-use mullvad_problem_report::{collect_report, metadata, Error};
-use std::{env, path::Path, process};
+use mullvad_problem_report::{collect_report, metadata, parse_duration, Error, LogFilter};
+use std::{
+    env,
+    io::{self, Write},
+    path::Path,
+    process,
+};
 use talpid_types::ErrorExt;
 
 fn main() {
@@ -55,6 +60,40 @@ fn run() -> Result<(), Error> {
                         .multiple_occurrences(true)
                         .multiple_values(true)
                         .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::new("since")
+                        .help(
+                            "Only include log entries from this long ago, such as 30m, 24h or 7d. \
+                             Errors are always included. A timeline of the daemon and frontend \
+                             logs within this window is added to the report.",
+                        )
+                        .long("since")
+                        .visible_alias("last")
+                        .value_name("DURATION")
+                        .takes_value(true)
+                        .validator(|value| {
+                            parse_duration(value)
+                                .map(|_| ())
+                                .ok_or("expected a number followed by s, m, h or d")
+                        }),
+                )
+                .arg(
+                    clap::Arg::new("min_level")
+                        .help(
+                            "Only include log entries at least this severe. Defaults to info, or \
+                             to trace with --verbose.",
+                        )
+                        .long("min-level")
+                        .value_name("LEVEL")
+                        .takes_value(true)
+                        .possible_values(&["error", "warn", "info", "debug", "trace"]),
+                )
+                .arg(
+                    clap::Arg::new("verbose")
+                        .help("Include debug and trace log entries.")
+                        .long("verbose")
+                        .short('v'),
                 ),
         )
         .subcommand(
@@ -98,7 +137,18 @@ fn run() -> Result<(), Error> {
             .map(|os_values| os_values.map(Path::new).collect())
             .unwrap_or_else(Vec::new);
         let output_path = Path::new(collect_matches.value_of_os("output").unwrap());
-        collect_report(&extra_logs, output_path, redact_custom_strings)?;
+        let min_level = match collect_matches.value_of("min_level") {
+            Some(level) => level.parse().expect("invalid log level"),
+            None if collect_matches.is_present("verbose") => log::Level::Trace,
+            None => log::Level::Info,
+        };
+        let log_filter = LogFilter {
+            min_level: Some(min_level),
+            since: collect_matches
+                .value_of("since")
+                .map(|since| parse_duration(since).expect("invalid duration")),
+        };
+        collect_report(&extra_logs, output_path, redact_custom_strings, log_filter)?;
 
         let expanded_output_path = output_path
             .canonicalize()
@@ -127,12 +177,18 @@ fn send_problem_report(
     report_path: &Path,
 ) -> Result<(), Error> {
     let cache_dir = mullvad_paths::get_cache_dir().map_err(Error::ObtainCacheDirectory)?;
-    match mullvad_problem_report::send_problem_report(
+    let result = mullvad_problem_report::send_problem_report_with_progress(
         user_email,
         user_message,
         report_path,
         &cache_dir,
-    ) {
+        |percentage| {
+            print!("\rSending problem report: {}%", percentage);
+            let _ = io::stdout().flush();
+        },
+    );
+    println!();
+    match result {
         Ok(()) => println!("Problem report sent"),
         Err(e) => eprintln!("{}", e.display_chain()),
     }
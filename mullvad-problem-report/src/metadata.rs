@@ -11,5 +11,15 @@ pub fn collect() -> BTreeMap<String, String> {
     );
     metadata.insert("os".to_owned(), talpid_platform_metadata::version());
     metadata.extend(talpid_platform_metadata::extra_metadata());
+    if let Some(prefix) = api_nat64_prefix() {
+        metadata.insert("api-nat64-prefix".to_owned(), prefix);
+    }
     metadata
 }
+
+/// Returns the NAT64 prefix that the daemon used to reach the API, if any.
+fn api_nat64_prefix() -> Option<String> {
+    let cache_dir = mullvad_paths::get_cache_dir().ok()?;
+    let prefix = std::fs::read_to_string(cache_dir.join(mullvad_rpc::API_NAT64_FILENAME)).ok()?;
+    Some(prefix.trim().to_owned())
+}
@@ -1,5 +1,28 @@
-use super::API;
-use std::{io, net::SocketAddr, path::Path, sync::Arc};
+#[cfg(target_os = "android")]
+use super::https_client_with_sni::SocketBypassRequest;
+use super::{
+    api_endpoint,
+    doh::{self, DohFallback},
+    nat64::{self, Nat64Prefix},
+    API_NAT64_FILENAME,
+};
+use chrono::{DateTime, Utc};
+#[cfg(target_os = "android")]
+use futures::channel::mpsc;
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    Rng,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use talpid_types::ErrorExt;
 use tokio::{
     fs,
     io::{AsyncReadExt, AsyncWriteExt},
@@ -25,59 +48,414 @@ pub enum Error {
     EmptyAddressCache,
 }
 
+/// How long the result of looking for an IPv6 route to the API is reused for, when none of the
+/// cached addresses are routable.
+const IPV6_FALLBACK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Number of recent connection attempts per address that are used to weigh the addresses.
+const CONNECT_HISTORY_LENGTH: usize = 10;
+
+/// Marks addresses found using DNS-over-HTTPS in the cache file.
+const DOH_SOURCE_TAG: &str = "doh";
+
 #[derive(Clone)]
 pub struct AddressCache {
     inner: Arc<Mutex<AddressCacheInner>>,
     write_path: Option<Arc<Path>>,
+    doh_fallback: Arc<DohFallback>,
+    pinned_host: Arc<std::sync::Mutex<Option<PinnedHost>>>,
+}
+
+/// An address that is used for a host instead of looking it up.
+#[derive(Clone)]
+struct PinnedHost {
+    hostname: String,
+    address: IpAddr,
 }
 
 impl AddressCache {
-    /// Initialize cache using the hardcoded address, and write changes to `write_path`.
-    pub fn new(write_path: Option<Box<Path>>) -> Result<Self, Error> {
-        Self::new_inner(API.addr, write_path)
+    /// Initialize cache using the hardcoded address, and write changes to `write_path`. If
+    /// `doh_fallback` is set, the API host may be looked up using DNS-over-HTTPS when the cached
+    /// addresses stop working. This can be changed later using [`AddressCache::doh_fallback`].
+    pub fn new(write_path: Option<Box<Path>>, doh_fallback: bool) -> Result<Self, Error> {
+        let bundled_address = CachedAddress {
+            address: api_endpoint().addr,
+            validated_at: None,
+            source: AddressSource::Default,
+        };
+        Self::new_inner(vec![bundled_address], write_path, doh_fallback)
     }
 
-    /// Initialize cache using `read_path`, and write changes to `write_path`.
-    pub async fn from_file(read_path: &Path, write_path: Option<Box<Path>>) -> Result<Self, Error> {
+    /// Initialize cache using `read_path`, and write changes to `write_path`. See
+    /// [`AddressCache::new`] for the meaning of `doh_fallback`.
+    pub async fn from_file(
+        read_path: &Path,
+        write_path: Option<Box<Path>>,
+        doh_fallback: bool,
+    ) -> Result<Self, Error> {
         log::debug!("Loading API addresses from {}", read_path.display());
-        Self::new_inner(read_address_file(read_path).await?, write_path)
+        Self::new_inner(
+            read_address_file(read_path).await?,
+            write_path,
+            doh_fallback,
+        )
     }
 
-    fn new_inner(address: SocketAddr, write_path: Option<Box<Path>>) -> Result<Self, Error> {
-        let cache = AddressCacheInner::from_address(address);
-        log::debug!("Using API address: {}", cache.address);
+    fn new_inner(
+        addresses: Vec<CachedAddress>,
+        write_path: Option<Box<Path>>,
+        doh_fallback: bool,
+    ) -> Result<Self, Error> {
+        let cache = AddressCacheInner::from_addresses(addresses).ok_or(Error::EmptyAddressCache)?;
+        log::debug!("Using API address: {}", cache.addresses[0].address);
 
         let address_cache = Self {
             inner: Arc::new(Mutex::new(cache)),
             write_path: write_path.map(|cache| Arc::from(cache)),
+            doh_fallback: Arc::new(DohFallback::new(doh_fallback)),
+            pinned_host: Arc::new(std::sync::Mutex::new(None)),
         };
         Ok(address_cache)
     }
 
-    /// Returns the address if the hostname equals `API.host`. Otherwise, returns `None`.
+    /// Returns the settings for looking up the API host using DNS-over-HTTPS.
+    pub fn doh_fallback(&self) -> &DohFallback {
+        &self.doh_fallback
+    }
+
+    /// Makes `hostname` resolve to `address` until [`AddressCache::unpin_host`] is called.
+    /// Nothing is looked up for the host while it is pinned, neither using getaddrinfo nor to
+    /// find an IPv6 address of the API.
+    pub(crate) fn pin_host(&self, hostname: String, address: IpAddr) {
+        log::debug!("Pinning {} to {}", hostname, address);
+        *self.pinned_host.lock().unwrap() = Some(PinnedHost { hostname, address });
+    }
+
+    /// Stops using the address set by [`AddressCache::pin_host`].
+    pub(crate) fn unpin_host(&self) {
+        *self.pinned_host.lock().unwrap() = None;
+    }
+
+    /// Returns the address that `hostname` is pinned to, if any.
+    pub(crate) fn pinned_address(&self, hostname: &str) -> Option<IpAddr> {
+        self.pinned_host
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|pinned_host| pinned_host.hostname.eq_ignore_ascii_case(hostname))
+            .map(|pinned_host| pinned_host.address)
+    }
+
+    /// Returns the address if the hostname equals the API host. Otherwise, returns `None`.
     pub async fn resolve_hostname(&self, hostname: &str) -> Option<SocketAddr> {
-        if hostname.eq_ignore_ascii_case(&API.host) {
+        if hostname.eq_ignore_ascii_case(&api_endpoint().host) {
             Some(self.get_address().await)
         } else {
             None
         }
     }
 
-    /// Returns the currently selected address.
+    /// Returns the currently selected address, or the overridden API address if the API endpoint
+    /// has been overridden.
+    ///
+    /// The most recently set address is preferred. If the system has no route to it, such as when
+    /// the address is IPv4 and the network is IPv6-only, another routable address is returned. If
+    /// no cached address is routable, an IPv6 address is synthesized using the NAT64 prefix of the
+    /// network, or the API host is resolved over IPv6. If the API host is pinned, the pinned
+    /// address is returned instead and nothing is looked up.
     pub async fn get_address(&self) -> SocketAddr {
-        self.inner.lock().await.address
+        let api = api_endpoint();
+        if api.disable_address_cache {
+            return api.addr;
+        }
+        let mut inner = self.inner.lock().await;
+        let primary = inner.addresses[0].address;
+
+        if let Some(address) = self.pinned_address(&api.host) {
+            return SocketAddr::new(address, primary.port());
+        }
+
+        if let Some(address) = inner
+            .addresses
+            .iter()
+            .map(|cached| cached.address)
+            .find(|address| nat64::is_routable(address.ip()))
+        {
+            self.clear_ipv6_fallback(&mut inner).await;
+            return address;
+        }
+
+        if let Some(fallback) = &inner.ipv6_fallback {
+            if fallback.checked_at.elapsed() < IPV6_FALLBACK_INTERVAL {
+                return fallback.address.unwrap_or(primary);
+            }
+        }
+        let previous_prefix = inner
+            .ipv6_fallback
+            .as_ref()
+            .and_then(|fallback| fallback.prefix);
+        let addresses: Vec<SocketAddr> = inner
+            .addresses
+            .iter()
+            .map(|cached| cached.address)
+            .collect();
+        let fallback = Ipv6Fallback::find(&api.host, &addresses).await;
+        if fallback.prefix != previous_prefix {
+            self.save_nat64_marker(fallback.prefix).await;
+        }
+        inner.ipv6_fallback = Some(fallback.clone());
+        fallback.address.unwrap_or(primary)
     }
 
+    /// Prefers `address`, replacing any cached address of the same family. Unless `address` is
+    /// already cached, it is not considered validated by the API.
     pub async fn set_address(&self, address: SocketAddr) -> io::Result<()> {
+        self.set_address_from(address, AddressSource::Default).await
+    }
+
+    async fn set_address_from(&self, address: SocketAddr, source: AddressSource) -> io::Result<()> {
+        let mut inner = self.inner.lock().await;
+        let new_address = inner
+            .addresses
+            .iter()
+            .find(|cached| cached.address == address)
+            .copied()
+            .unwrap_or(CachedAddress {
+                address,
+                validated_at: None,
+                source,
+            });
+        self.replace_addresses(&mut inner, vec![new_address]).await
+    }
+
+    /// Replaces the cached addresses with the first IPv4 and IPv6 address in `addresses`, which
+    /// were just returned by the API, preferring the first address. Cached addresses of a family
+    /// missing from `addresses` are kept.
+    pub async fn set_addresses(&self, addresses: &[SocketAddr]) -> io::Result<()> {
+        let mut inner = self.inner.lock().await;
+        let now = Utc::now();
+        let new_addresses = addresses
+            .iter()
+            .map(|address| CachedAddress {
+                address: *address,
+                validated_at: Some(now),
+                source: AddressSource::Default,
+            })
+            .collect();
+        self.replace_addresses(&mut inner, new_addresses).await
+    }
+
+    /// Returns whether none of the cached addresses have been returned by the API within
+    /// `max_age`.
+    pub async fn is_stale(&self, max_age: Duration) -> bool {
+        let now = Utc::now();
+        self.inner
+            .lock()
+            .await
+            .addresses
+            .iter()
+            .all(|cached| cached.is_stale(max_age, now))
+    }
+
+    /// Removes the addresses that have not been returned by the API within `max_age`. If every
+    /// address is that old, none are removed, but the most recently validated address is
+    /// preferred.
+    pub async fn prune_stale(&self, max_age: Duration) -> io::Result<()> {
         let mut inner = self.inner.lock().await;
-        if address != inner.address {
-            self.save_to_disk(&address).await?;
-            inner.address = address;
+        let now = Utc::now();
+        let mut new_addresses: Vec<CachedAddress> = inner
+            .addresses
+            .iter()
+            .filter(|cached| !cached.is_stale(max_age, now))
+            .copied()
+            .collect();
+        if new_addresses.is_empty() {
+            new_addresses = inner.addresses.clone();
+            // `None` is ordered first, so it ends up last
+            new_addresses.sort_by(|a, b| b.validated_at.cmp(&a.validated_at));
+        }
+        for cached in &inner.addresses {
+            if !new_addresses.contains(cached) {
+                log::debug!(
+                    "Removing API address {}, which the API has not returned since {}",
+                    cached.address,
+                    cached
+                        .validated_at
+                        .map(|validated_at| validated_at.to_rfc3339())
+                        .unwrap_or_else(|| "ever".to_owned())
+                );
+            }
+        }
+        self.save_addresses(&mut inner, new_addresses).await
+    }
+
+    /// Records that a connection to `address` was established. Addresses that are not cached are
+    /// ignored.
+    pub async fn record_success(&self, address: SocketAddr) {
+        self.inner.lock().await.record_outcome(address, true);
+    }
+
+    /// Records that connecting to `address` failed. Addresses that are not cached are ignored.
+    pub async fn record_failure(&self, address: SocketAddr) {
+        self.inner.lock().await.record_outcome(address, false);
+    }
+
+    /// Returns whether the most recent connection attempts to every cached address and to the
+    /// bundled address failed.
+    pub async fn all_addresses_failed(&self) -> bool {
+        let inner = self.inner.lock().await;
+        inner
+            .addresses
+            .iter()
+            .all(|cached| inner.has_failed(&cached.address))
+            && inner.has_failed(&api_endpoint().addr)
+    }
+
+    /// Picks the preferred address at random. Addresses are picked in proportion to how many of
+    /// the recent connections to them succeeded. Addresses without any recorded connections are
+    /// weighted as if half of them had succeeded, so every address is equally likely until
+    /// outcomes have been recorded.
+    ///
+    /// If every cached address failed and the bundled address has not been tried since, the
+    /// bundled address is preferred instead.
+    pub async fn randomize(&self) -> io::Result<()> {
+        let mut inner = self.inner.lock().await;
+        let bundled_address = api_endpoint().addr;
+        if !inner.has_failed(&bundled_address)
+            && inner.addresses.iter().all(|cached| {
+                cached.address != bundled_address && inner.has_failed(&cached.address)
+            })
+        {
+            log::debug!(
+                "Every cached API address failed. Falling back on the bundled address {}",
+                bundled_address
+            );
+            let new_addresses = vec![CachedAddress {
+                address: bundled_address,
+                validated_at: None,
+                source: AddressSource::Default,
+            }];
+            return self.replace_addresses(&mut inner, new_addresses).await;
+        }
+        let index = inner.pick_weighted(&mut rand::thread_rng());
+        if index == 0 {
+            return Ok(());
+        }
+        let mut new_addresses = inner.addresses.clone();
+        let preferred = new_addresses.remove(index);
+        log::debug!("Preferring API address {}", preferred.address);
+        new_addresses.insert(0, preferred);
+        self.save_addresses(&mut inner, new_addresses).await
+    }
+
+    /// Prefers `new_addresses`, keeping cached addresses of other families.
+    async fn replace_addresses(
+        &self,
+        inner: &mut AddressCacheInner,
+        new_addresses: Vec<CachedAddress>,
+    ) -> io::Result<()> {
+        let new_addresses =
+            one_per_family(new_addresses.into_iter().chain(inner.addresses.clone()));
+        self.save_addresses(inner, new_addresses).await
+    }
+
+    async fn save_addresses(
+        &self,
+        inner: &mut AddressCacheInner,
+        new_addresses: Vec<CachedAddress>,
+    ) -> io::Result<()> {
+        if new_addresses != inner.addresses {
+            self.save_to_disk(&new_addresses).await?;
+            let addresses_changed = !new_addresses
+                .iter()
+                .map(|cached| cached.address)
+                .eq(inner.addresses.iter().map(|cached| cached.address));
+            inner.addresses = new_addresses;
+            let addresses = &inner.addresses;
+            let bundled_address = api_endpoint().addr;
+            inner.connect_history.retain(|address, _| {
+                *address == bundled_address
+                    || addresses.iter().any(|cached| cached.address == *address)
+            });
+            if addresses_changed {
+                self.clear_ipv6_fallback(inner).await;
+            }
         }
         Ok(())
     }
 
-    async fn save_to_disk(&self, address: &SocketAddr) -> io::Result<()> {
+    /// Looks up the API host using DNS-over-HTTPS and replaces the cached address if it is not
+    /// among the results. Nothing is done unless every cached address and the bundled address
+    /// failed, or if the fallback is disabled or a lookup was started recently.
+    pub(crate) async fn refresh_using_doh(
+        &self,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) {
+        let api = api_endpoint();
+        if api.disable_address_cache
+            || !self.all_addresses_failed().await
+            || !self.doh_fallback.begin_lookup()
+        {
+            return;
+        }
+
+        let addresses = match doh::resolve(
+            &api.host,
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
+        )
+        .await
+        {
+            Ok(addresses) => addresses,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to look up the API host using DoH")
+                );
+                return;
+            }
+        };
+        let current_address = self.get_address().await;
+        if addresses.contains(&current_address.ip()) {
+            return;
+        }
+        let new_address = SocketAddr::new(addresses[0], current_address.port());
+        log::info!("Using API address {} obtained using DoH", new_address);
+        if let Err(error) = self.set_address_from(new_address, AddressSource::Doh).await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to save the API address obtained using DoH")
+            );
+        }
+    }
+
+    async fn clear_ipv6_fallback(&self, inner: &mut AddressCacheInner) {
+        let fallback = inner.ipv6_fallback.take();
+        if fallback.and_then(|fallback| fallback.prefix).is_some() {
+            self.save_nat64_marker(None).await;
+        }
+    }
+
+    /// Records the NAT64 prefix that is used to reach the API, if any, so that it can be included
+    /// in problem reports.
+    async fn save_nat64_marker(&self, prefix: Option<Nat64Prefix>) {
+        let marker_path = match self.write_path.as_ref() {
+            Some(write_path) => write_path.with_file_name(API_NAT64_FILENAME),
+            None => return,
+        };
+        let result = match prefix {
+            Some(prefix) => fs::write(&marker_path, format!("{}\n", prefix)).await,
+            None => match fs::remove_file(&marker_path).await {
+                Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            },
+        };
+        if let Err(error) = result {
+            log::error!("Failed to update the NAT64 marker file: {}", error);
+        }
+    }
+
+    async fn save_to_disk(&self, addresses: &[CachedAddress]) -> io::Result<()> {
         let write_path = match self.write_path.as_ref() {
             Some(write_path) => write_path,
             None => return Ok(()),
@@ -86,8 +464,11 @@ impl AddressCache {
         let temp_path = write_path.with_file_name("api-cache.temp");
 
         let mut file = fs::File::create(&temp_path).await?;
-        let mut contents = address.to_string();
-        contents += "\n";
+        let mut contents = String::new();
+        for cached in addresses {
+            contents += &cached.to_string();
+            contents += "\n";
+        }
         file.write_all(contents.as_bytes()).await?;
         file.sync_data().await?;
 
@@ -95,24 +476,531 @@ impl AddressCache {
     }
 }
 
+/// Where a cached address was obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AddressSource {
+    /// The bundled address, an address returned by the API, or an address that was set
+    /// explicitly.
+    Default,
+    /// An address of the API host that was found using DNS-over-HTTPS.
+    Doh,
+}
+
+/// An API address, when the API last returned it, and where it was obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CachedAddress {
+    address: SocketAddr,
+    /// `None` for the bundled address, for addresses from other sources such as DoH, and for
+    /// addresses read from files written by older versions.
+    validated_at: Option<DateTime<Utc>>,
+    source: AddressSource,
+}
+
+impl CachedAddress {
+    fn is_stale(&self, max_age: Duration, now: DateTime<Utc>) -> bool {
+        match (self.validated_at, chrono::Duration::from_std(max_age)) {
+            (Some(validated_at), Ok(max_age)) => now - validated_at > max_age,
+            (Some(_), Err(_)) => false,
+            (None, _) => true,
+        }
+    }
+}
+
+impl fmt::Display for CachedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.address)?;
+        if let Some(validated_at) = self.validated_at {
+            write!(f, " {}", validated_at.to_rfc3339())?;
+        }
+        if self.source == AddressSource::Doh {
+            write!(f, " {}", DOH_SOURCE_TAG)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CachedAddress {
+    type Err = Error;
+
+    /// Parses a line of the cache file: the address, an optional timestamp and an optional
+    /// source tag. The timestamp is missing in files written by older versions.
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut parts = line.split_whitespace().peekable();
+        let address = parts
+            .next()
+            .and_then(|address| address.parse().ok())
+            .ok_or(Error::ParseAddressCache)?;
+        let validated_at = match parts.next_if(|part| *part != DOH_SOURCE_TAG) {
+            Some(validated_at) => Some(
+                DateTime::parse_from_rfc3339(validated_at)
+                    .map_err(|_| Error::ParseAddressCache)?
+                    .with_timezone(&Utc),
+            ),
+            None => None,
+        };
+        let source = match parts.next_if_eq(&DOH_SOURCE_TAG) {
+            Some(_) => AddressSource::Doh,
+            None => AddressSource::Default,
+        };
+        if parts.next().is_some() {
+            return Err(Error::ParseAddressCache);
+        }
+        Ok(CachedAddress {
+            address,
+            validated_at,
+            source,
+        })
+    }
+}
+
 #[derive(Clone, PartialEq, Eq)]
 struct AddressCacheInner {
-    address: SocketAddr,
+    /// At most one address per family. The first address is preferred.
+    addresses: Vec<CachedAddress>,
+    ipv6_fallback: Option<Ipv6Fallback>,
+    /// Outcomes of the most recent connection attempts to the cached addresses and the bundled
+    /// address. This is not saved to disk.
+    connect_history: HashMap<SocketAddr, ConnectHistory>,
 }
 
 impl AddressCacheInner {
-    fn from_address(address: SocketAddr) -> Self {
-        Self { address }
+    fn from_addresses(addresses: Vec<CachedAddress>) -> Option<Self> {
+        let addresses = one_per_family(addresses);
+        if addresses.is_empty() {
+            return None;
+        }
+        Some(Self {
+            addresses,
+            ipv6_fallback: None,
+            connect_history: HashMap::new(),
+        })
+    }
+
+    /// Records an outcome for `address` if it is cached or the bundled address.
+    fn record_outcome(&mut self, address: SocketAddr, success: bool) {
+        if address == api_endpoint().addr
+            || self
+                .addresses
+                .iter()
+                .any(|cached| cached.address == address)
+        {
+            self.connect_history
+                .entry(address)
+                .or_default()
+                .record(success);
+        }
+    }
+
+    /// Returns whether the most recent connection attempt to `address` failed.
+    fn has_failed(&self, address: &SocketAddr) -> bool {
+        self.connect_history
+            .get(address)
+            .and_then(|history| history.outcomes.back())
+            .map(|success| !success)
+            .unwrap_or(false)
+    }
+
+    /// Returns the index of an address, picked at random in proportion to its weight.
+    fn pick_weighted(&self, rng: &mut impl Rng) -> usize {
+        let weights = self.addresses.iter().map(|cached| {
+            self.connect_history
+                .get(&cached.address)
+                .map(ConnectHistory::weight)
+                .unwrap_or(ConnectHistory::DEFAULT_WEIGHT)
+        });
+        match WeightedIndex::new(weights) {
+            Ok(distribution) => distribution.sample(rng),
+            Err(_) => 0,
+        }
+    }
+}
+
+/// Whether the most recent connection attempts to an address succeeded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ConnectHistory {
+    outcomes: VecDeque<bool>,
+}
+
+impl ConnectHistory {
+    /// Weight of an address without any recorded attempts.
+    const DEFAULT_WEIGHT: f64 = 0.5;
+
+    fn record(&mut self, success: bool) {
+        if self.outcomes.len() == CONNECT_HISTORY_LENGTH {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(success);
+    }
+
+    /// Returns the share of successful attempts, counting one extra success and one extra
+    /// failure so that an address is never ruled out completely.
+    fn weight(&self) -> f64 {
+        let successes = self.outcomes.iter().filter(|success| **success).count();
+        (successes + 1) as f64 / (self.outcomes.len() + 2) as f64
     }
 }
 
-async fn read_address_file(path: &Path) -> Result<SocketAddr, Error> {
+/// Returns the first IPv4 and IPv6 address in `addresses`, in their original order.
+fn one_per_family(addresses: impl IntoIterator<Item = CachedAddress>) -> Vec<CachedAddress> {
+    let mut unique_addresses: Vec<CachedAddress> = vec![];
+    for address in addresses {
+        if !unique_addresses
+            .iter()
+            .any(|unique| unique.address.is_ipv4() == address.address.is_ipv4())
+        {
+            unique_addresses.push(address);
+        }
+    }
+    unique_addresses
+}
+
+/// IPv6 address of the API that is used when none of the cached addresses are routable.
+#[derive(Clone, PartialEq, Eq)]
+struct Ipv6Fallback {
+    checked_at: Instant,
+    address: Option<SocketAddr>,
+    /// The NAT64 prefix that `address` was synthesized with, if any.
+    prefix: Option<Nat64Prefix>,
+}
+
+impl Ipv6Fallback {
+    async fn find(hostname: &str, addresses: &[SocketAddr]) -> Self {
+        let checked_at = Instant::now();
+        let ipv4_address = addresses.iter().find(|address| address.is_ipv4());
+
+        if let Some(ipv4_address) = ipv4_address {
+            if let Some(prefix) = nat64::discover_prefix().await {
+                if let IpAddr::V4(ipv4) = ipv4_address.ip() {
+                    let address =
+                        SocketAddr::new(prefix.synthesize(ipv4).into(), ipv4_address.port());
+                    if nat64::is_routable(address.ip()) {
+                        log::info!("Using API address {} synthesized using NAT64", address);
+                        return Self {
+                            checked_at,
+                            address: Some(address),
+                            prefix: Some(prefix),
+                        };
+                    }
+                }
+            }
+        }
+
+        let port = addresses[0].port();
+        let address = nat64::lookup_ipv6(hostname, port)
+            .await
+            .into_iter()
+            .find(|address| nat64::is_routable(address.ip()));
+        match address {
+            Some(address) => log::info!("Using API address {} resolved over IPv6", address),
+            None => log::warn!("No route to the API was found"),
+        }
+        Self {
+            checked_at,
+            address,
+            prefix: None,
+        }
+    }
+}
+
+async fn read_address_file(path: &Path) -> Result<Vec<CachedAddress>, Error> {
     let mut file = fs::File::open(path)
         .await
         .map_err(|error| Error::OpenAddressCache(error))?;
-    let mut address = String::new();
-    file.read_to_string(&mut address)
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
         .await
         .map_err(Error::ReadAddressCache)?;
-    address.trim().parse().map_err(|_| Error::ParseAddressCache)
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addresses(addresses: &[&str]) -> Vec<SocketAddr> {
+        addresses
+            .iter()
+            .map(|address| address.parse().unwrap())
+            .collect()
+    }
+
+    fn cached(addresses: &[&str]) -> Vec<CachedAddress> {
+        addresses.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    fn cached_addresses(
+        runtime: &tokio::runtime::Runtime,
+        cache: &AddressCache,
+    ) -> Vec<SocketAddr> {
+        runtime
+            .block_on(cache.inner.lock())
+            .addresses
+            .iter()
+            .map(|cached| cached.address)
+            .collect()
+    }
+
+    #[test]
+    fn test_one_address_per_family() {
+        let inner = AddressCacheInner::from_addresses(cached(&[
+            "192.0.2.1:443",
+            "192.0.2.2:443",
+            "[2001:db8::1]:443",
+        ]))
+        .unwrap();
+        assert_eq!(
+            inner.addresses,
+            cached(&["192.0.2.1:443", "[2001:db8::1]:443"])
+        );
+        assert!(AddressCacheInner::from_addresses(vec![]).is_none());
+    }
+
+    #[test]
+    fn test_doh_fallback_is_opt_in() {
+        assert!(!AddressCache::new(None, false)
+            .unwrap()
+            .doh_fallback()
+            .is_enabled());
+        let cache = AddressCache::new(None, true).unwrap();
+        assert!(cache.doh_fallback().is_enabled());
+    }
+
+    #[test]
+    fn test_set_addresses() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let cache =
+            AddressCache::new_inner(cached(&["192.0.2.1:443", "[2001:db8::1]:443"]), None, false)
+                .unwrap();
+
+        // The new address replaces the cached address of the same family
+        runtime
+            .block_on(cache.set_address("192.0.2.2:443".parse().unwrap()))
+            .unwrap();
+        assert_eq!(
+            cached_addresses(&runtime, &cache),
+            addresses(&["192.0.2.2:443", "[2001:db8::1]:443"])
+        );
+
+        runtime
+            .block_on(cache.set_addresses(&addresses(&["[2001:db8::2]:443", "[2001:db8::3]:443"])))
+            .unwrap();
+        assert_eq!(
+            cached_addresses(&runtime, &cache),
+            addresses(&["[2001:db8::2]:443", "192.0.2.2:443"])
+        );
+    }
+
+    #[test]
+    fn test_cache_file_format() {
+        let with_timestamp: CachedAddress =
+            "192.0.2.1:443 2022-03-01T12:00:00+00:00".parse().unwrap();
+        assert_eq!(
+            with_timestamp.validated_at,
+            Some("2022-03-01T12:00:00Z".parse().unwrap())
+        );
+        assert_eq!(
+            with_timestamp.to_string(),
+            "192.0.2.1:443 2022-03-01T12:00:00+00:00"
+        );
+
+        // Files written by older versions contain no timestamps
+        let without_timestamp: CachedAddress = "[2001:db8::1]:443".parse().unwrap();
+        assert_eq!(without_timestamp.validated_at, None);
+        assert_eq!(without_timestamp.to_string(), "[2001:db8::1]:443");
+
+        // Addresses found using DoH are tagged
+        let from_doh: CachedAddress = "192.0.2.1:443 doh".parse().unwrap();
+        assert_eq!(from_doh.source, AddressSource::Doh);
+        assert_eq!(from_doh.validated_at, None);
+        assert_eq!(from_doh.to_string(), "192.0.2.1:443 doh");
+        let validated_from_doh: CachedAddress = "192.0.2.1:443 2022-03-01T12:00:00+00:00 doh"
+            .parse()
+            .unwrap();
+        assert_eq!(validated_from_doh.source, AddressSource::Doh);
+        assert!(validated_from_doh.validated_at.is_some());
+        assert_eq!(with_timestamp.source, AddressSource::Default);
+
+        assert!("192.0.2.1:443 yesterday".parse::<CachedAddress>().is_err());
+        assert!("192.0.2.1:443 doh doh".parse::<CachedAddress>().is_err());
+        assert!("192.0.2.1:443 doh 2022-03-01T12:00:00+00:00"
+            .parse::<CachedAddress>()
+            .is_err());
+        assert!("192.0.2.1".parse::<CachedAddress>().is_err());
+    }
+
+    #[test]
+    fn test_doh_address_source() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let cache = AddressCache::new_inner(cached(&["192.0.2.1:443"]), None, false).unwrap();
+        let doh_address: SocketAddr = "192.0.2.2:443".parse().unwrap();
+        runtime
+            .block_on(cache.set_address_from(doh_address, AddressSource::Doh))
+            .unwrap();
+        let inner = runtime.block_on(cache.inner.lock());
+        assert_eq!(inner.addresses[0].address, doh_address);
+        assert_eq!(inner.addresses[0].source, AddressSource::Doh);
+        drop(inner);
+
+        // The API validating the address does not keep the tag
+        runtime
+            .block_on(cache.set_addresses(&[doh_address]))
+            .unwrap();
+        let inner = runtime.block_on(cache.inner.lock());
+        assert_eq!(inner.addresses[0].source, AddressSource::Default);
+    }
+
+    #[test]
+    fn test_bundled_address_fallback() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let bundled_address = api_endpoint().addr;
+        let cached_address: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        let cache = AddressCache::new_inner(cached(&["192.0.2.1:443"]), None, false).unwrap();
+
+        runtime.block_on(async {
+            assert!(!cache.all_addresses_failed().await);
+
+            // Once the cached address fails, the bundled address is tried
+            cache.record_failure(cached_address).await;
+            assert!(!cache.all_addresses_failed().await);
+            cache.randomize().await.unwrap();
+        });
+        assert_eq!(cached_addresses(&runtime, &cache), vec![bundled_address]);
+
+        runtime.block_on(async {
+            cache.record_failure(bundled_address).await;
+            assert!(cache.all_addresses_failed().await);
+
+            cache.record_success(bundled_address).await;
+            assert!(!cache.all_addresses_failed().await);
+        });
+    }
+
+    #[test]
+    fn test_connect_history() {
+        let mut history = ConnectHistory::default();
+        assert_eq!(history.weight(), ConnectHistory::DEFAULT_WEIGHT);
+        for _ in 0..CONNECT_HISTORY_LENGTH {
+            history.record(false);
+        }
+        assert!(history.weight() > 0.0);
+
+        // Only the most recent attempts are counted
+        for _ in 0..CONNECT_HISTORY_LENGTH {
+            history.record(true);
+        }
+        assert_eq!(history.outcomes.len(), CONNECT_HISTORY_LENGTH);
+        assert_eq!(
+            history.weight(),
+            (CONNECT_HISTORY_LENGTH + 1) as f64 / (CONNECT_HISTORY_LENGTH + 2) as f64
+        );
+    }
+
+    #[test]
+    fn test_weighted_pick() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        const PICKS: usize = 1000;
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut inner =
+            AddressCacheInner::from_addresses(cached(&["192.0.2.1:443", "[2001:db8::1]:443"]))
+                .unwrap();
+        let mut count_second = |inner: &AddressCacheInner| {
+            (0..PICKS)
+                .filter(|_| inner.pick_weighted(&mut rng) == 1)
+                .count()
+        };
+
+        // Without history, both addresses are equally likely
+        let second = count_second(&inner);
+        assert!((400..600).contains(&second), "picked {} times", second);
+
+        for _ in 0..CONNECT_HISTORY_LENGTH {
+            inner.record_outcome("192.0.2.1:443".parse().unwrap(), false);
+            inner.record_outcome("[2001:db8::1]:443".parse().unwrap(), true);
+        }
+        let second = count_second(&inner);
+        assert!(second > 800, "picked {} times", second);
+
+        // Addresses that are not cached are not tracked
+        inner.record_outcome("192.0.2.2:443".parse().unwrap(), true);
+        assert_eq!(inner.connect_history.len(), 2);
+    }
+
+    #[test]
+    fn test_randomize() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let cache =
+            AddressCache::new_inner(cached(&["192.0.2.1:443", "[2001:db8::1]:443"]), None, false)
+                .unwrap();
+        let failing: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        runtime.block_on(async {
+            for _ in 0..CONNECT_HISTORY_LENGTH {
+                cache.record_failure(failing).await;
+                cache
+                    .record_success("[2001:db8::1]:443".parse().unwrap())
+                    .await;
+            }
+        });
+        while cached_addresses(&runtime, &cache)[0] == failing {
+            runtime.block_on(cache.randomize()).unwrap();
+        }
+        assert_eq!(
+            cached_addresses(&runtime, &cache),
+            addresses(&["[2001:db8::1]:443", "192.0.2.1:443"])
+        );
+
+        // The history is dropped with the address
+        runtime
+            .block_on(cache.set_address("192.0.2.2:443".parse().unwrap()))
+            .unwrap();
+        let inner = runtime.block_on(cache.inner.lock());
+        assert!(!inner.connect_history.contains_key(&failing));
+    }
+
+    #[test]
+    fn test_prune_stale() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let ttl = Duration::from_secs(7 * 24 * 60 * 60);
+        let old = (Utc::now() - chrono::Duration::days(8)).to_rfc3339();
+        let older = (Utc::now() - chrono::Duration::days(9)).to_rfc3339();
+
+        // Stale addresses are removed if there are validated addresses
+        let cache = AddressCache::new_inner(
+            cached(&[&format!("192.0.2.1:443 {}", old), "[2001:db8::1]:443"]),
+            None,
+            false,
+        )
+        .unwrap();
+        assert!(runtime.block_on(cache.is_stale(ttl)));
+        runtime
+            .block_on(cache.set_addresses(&addresses(&["[2001:db8::1]:443"])))
+            .unwrap();
+        assert!(!runtime.block_on(cache.is_stale(ttl)));
+        runtime.block_on(cache.prune_stale(ttl)).unwrap();
+        assert_eq!(
+            cached_addresses(&runtime, &cache),
+            addresses(&["[2001:db8::1]:443"])
+        );
+
+        // If every address is stale, the most recently validated one is preferred
+        let cache = AddressCache::new_inner(
+            cached(&[
+                &format!("192.0.2.1:443 {}", older),
+                &format!("[2001:db8::1]:443 {}", old),
+            ]),
+            None,
+            false,
+        )
+        .unwrap();
+        runtime.block_on(cache.prune_stale(ttl)).unwrap();
+        assert_eq!(
+            cached_addresses(&runtime, &cache),
+            addresses(&["[2001:db8::1]:443", "192.0.2.1:443"])
+        );
+    }
 }
@@ -0,0 +1,275 @@
+//! Keeps track of API endpoints that the API has marked as deprecated, using the `Deprecation`
+//! and `Sunset` response headers.
+
+use chrono::{offset::Utc, DateTime, TimeZone};
+use hyper::header::{HeaderMap, HeaderName};
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const DEPRECATION: &str = "deprecation";
+const SUNSET: &str = "sunset";
+
+/// How often a warning is logged for an endpoint whose deprecation has not changed.
+const LOG_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: DeprecationRegistry = DeprecationRegistry::default();
+}
+
+/// An API endpoint that is deprecated or will stop working.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeprecatedEndpoint {
+    /// Path of the endpoint.
+    pub endpoint: String,
+    /// When the endpoint was or will be deprecated, if the API says so.
+    pub deprecated_at: Option<DateTime<Utc>>,
+    /// When the endpoint will stop working.
+    pub sunset: Option<DateTime<Utc>>,
+    /// Link to documentation about the deprecation.
+    pub link: Option<String>,
+}
+
+/// Returns all endpoints that have been marked as deprecated since the daemon started.
+pub fn deprecated_endpoints() -> Vec<DeprecatedEndpoint> {
+    REGISTRY.endpoints()
+}
+
+/// Returns the earliest sunset of any deprecated endpoint.
+pub fn earliest_sunset() -> Option<DateTime<Utc>> {
+    REGISTRY.earliest_sunset()
+}
+
+pub(crate) fn record_response(endpoint: &str, headers: &HeaderMap) {
+    REGISTRY.record(endpoint, headers);
+}
+
+#[derive(Default)]
+struct DeprecationRegistry {
+    endpoints: Mutex<BTreeMap<String, Entry>>,
+}
+
+struct Entry {
+    endpoint: DeprecatedEndpoint,
+    last_logged: Instant,
+}
+
+impl DeprecationRegistry {
+    /// Records `endpoint` if `headers` contain deprecation headers. Nothing is done otherwise.
+    fn record(&self, endpoint: &str, headers: &HeaderMap) {
+        let deprecated_endpoint = match parse_headers(endpoint, headers) {
+            Some(deprecated_endpoint) => deprecated_endpoint,
+            None => return,
+        };
+
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let should_log = match endpoints.get(endpoint) {
+            Some(entry) => {
+                entry.endpoint != deprecated_endpoint || entry.last_logged.elapsed() >= LOG_INTERVAL
+            }
+            None => true,
+        };
+        if !should_log {
+            return;
+        }
+
+        log::warn!(
+            "API endpoint {} is deprecated{}{}",
+            endpoint,
+            deprecated_endpoint
+                .sunset
+                .map(|sunset| format!(" and stops working on {}", sunset.format("%Y-%m-%d")))
+                .unwrap_or_default(),
+            deprecated_endpoint
+                .link
+                .as_ref()
+                .map(|link| format!(". See {}", link))
+                .unwrap_or_default(),
+        );
+        endpoints.insert(
+            endpoint.to_owned(),
+            Entry {
+                endpoint: deprecated_endpoint,
+                last_logged: Instant::now(),
+            },
+        );
+    }
+
+    fn endpoints(&self) -> Vec<DeprecatedEndpoint> {
+        self.endpoints
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| entry.endpoint.clone())
+            .collect()
+    }
+
+    fn earliest_sunset(&self) -> Option<DateTime<Utc>> {
+        self.endpoints
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|entry| entry.endpoint.sunset)
+            .min()
+    }
+}
+
+/// Returns the deprecation of `endpoint` if either a `Deprecation` or a `Sunset` header is
+/// present.
+fn parse_headers(endpoint: &str, headers: &HeaderMap) -> Option<DeprecatedEndpoint> {
+    let deprecation = header_str(headers, DEPRECATION);
+    let sunset = header_str(headers, SUNSET);
+    if deprecation.is_none() && sunset.is_none() {
+        return None;
+    }
+
+    Some(DeprecatedEndpoint {
+        endpoint: endpoint.to_owned(),
+        deprecated_at: deprecation.and_then(parse_deprecation_date),
+        sunset: sunset.and_then(parse_http_date),
+        link: parse_link(headers),
+    })
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &'static str) -> Option<&'a str> {
+    headers
+        .get(HeaderName::from_static(name))
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Parses the value of a `Deprecation` header, which is either `true`, a Unix timestamp
+/// prefixed with `@`, or an HTTP date.
+fn parse_deprecation_date(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    match value.strip_prefix('@') {
+        Some(timestamp) => Utc.timestamp_opt(timestamp.parse().ok()?, 0).single(),
+        None => parse_http_date(value),
+    }
+}
+
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// Returns the target of the first link with the relation type `deprecation` or `sunset`.
+fn parse_link(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(hyper::header::LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|link| {
+            let mut parts = link.split(';').map(str::trim);
+            let target = parts.next()?.strip_prefix('<')?.strip_suffix('>')?;
+            let has_relation = parts.any(|param| {
+                let relation = param.strip_prefix("rel=").unwrap_or("");
+                let relation = relation.trim_matches('"');
+                relation.split_whitespace().any(|relation| {
+                    relation.eq_ignore_ascii_case(DEPRECATION)
+                        || relation.eq_ignore_ascii_case(SUNSET)
+                })
+            });
+            if has_relation {
+                Some(target.to_owned())
+            } else {
+                None
+            }
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn headers(values: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn test_record_deprecation_headers() {
+        let registry = DeprecationRegistry::default();
+
+        registry.record("/app/v1/relays", &headers(&[]));
+        assert!(registry.endpoints().is_empty());
+
+        registry.record(
+            "/app/v1/wireguard-keys",
+            &headers(&[
+                (DEPRECATION, "@1654041600"),
+                (SUNSET, "Fri, 01 Jul 2022 00:00:00 GMT"),
+                ("link", "<https://api.mullvad.net/>; rel=\"alternate\""),
+                (
+                    "link",
+                    "<https://mullvad.net/deprecations>; rel=\"deprecation\"",
+                ),
+            ]),
+        );
+        registry.record("/app/v1/me", &headers(&[(DEPRECATION, "true")]));
+
+        let june = Utc.ymd(2022, 6, 1).and_hms(0, 0, 0);
+        let july = Utc.ymd(2022, 7, 1).and_hms(0, 0, 0);
+        assert_eq!(
+            registry.endpoints(),
+            vec![
+                DeprecatedEndpoint {
+                    endpoint: "/app/v1/me".to_owned(),
+                    deprecated_at: None,
+                    sunset: None,
+                    link: None,
+                },
+                DeprecatedEndpoint {
+                    endpoint: "/app/v1/wireguard-keys".to_owned(),
+                    deprecated_at: Some(june),
+                    sunset: Some(july),
+                    link: Some("https://mullvad.net/deprecations".to_owned()),
+                },
+            ]
+        );
+        assert_eq!(registry.earliest_sunset(), Some(july));
+    }
+
+    #[test]
+    fn test_changed_deprecation_replaces_entry() {
+        let registry = DeprecationRegistry::default();
+        registry.record(
+            "/app/v1/me",
+            &headers(&[(SUNSET, "Fri, 01 Jul 2022 00:00:00 GMT")]),
+        );
+        registry.record(
+            "/app/v1/me",
+            &headers(&[(SUNSET, "Mon, 01 Aug 2022 00:00:00 GMT")]),
+        );
+
+        let endpoints = registry.endpoints();
+        assert_eq!(endpoints.len(), 1);
+        assert_eq!(
+            endpoints[0].sunset,
+            Some(Utc.ymd(2022, 8, 1).and_hms(0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn test_parse_deprecation_date() {
+        assert_eq!(parse_deprecation_date("true"), None);
+        assert_eq!(
+            parse_deprecation_date("@0"),
+            Some(Utc.ymd(1970, 1, 1).and_hms(0, 0, 0))
+        );
+        assert_eq!(
+            parse_deprecation_date("Wed, 01 Jun 2022 00:00:00 GMT"),
+            Some(Utc.ymd(2022, 6, 1).and_hms(0, 0, 0))
+        );
+    }
+}
@@ -0,0 +1,641 @@
+//! Minimal DNS-over-HTTPS (RFC 8484) client, used as a last resort for finding an address for the
+//! API host when neither the system resolver nor the cached address work.
+
+#[cfg(target_os = "android")]
+use crate::https_client_with_sni::SocketBypassRequest;
+use crate::{https_client_with_sni::HttpsConnectorWithSni, tls_stream::TlsStream};
+#[cfg(target_os = "android")]
+use futures::channel::mpsc;
+use hyper::{body::HttpBody, header, StatusCode};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use talpid_types::ErrorExt;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// DoH servers that are tried, in order. They are addressed by IP so that no resolver is needed to
+/// reach them, and their certificates are issued by the root that `TlsStream` trusts.
+const SERVERS: &[DohServer] = &[
+    DohServer {
+        hostname: "dns.mullvad.net",
+        ip: Ipv4Addr::new(194, 242, 2, 2),
+    },
+    DohServer {
+        hostname: "adblock.dns.mullvad.net",
+        ip: Ipv4Addr::new(194, 242, 2, 3),
+    },
+];
+
+const DOH_PORT: u16 = 443;
+
+/// Timeout for a single DoH query, including connecting to the server.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Minimum time between two DoH lookups.
+const MIN_LOOKUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Responses larger than this are rejected.
+const MAX_RESPONSE_SIZE: usize = 65535;
+
+const DNS_MESSAGE_CONTENT_TYPE: &str = "application/dns-message";
+
+const HEADER_SIZE: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const CLASS_IN: u16 = 1;
+const MAX_NAME_LENGTH: usize = 255;
+const MAX_LABEL_LENGTH: usize = 63;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Invalid hostname")]
+    InvalidHostname,
+
+    #[error(display = "Failed to connect to the DoH server")]
+    Io(#[error(source)] std::io::Error),
+
+    #[error(display = "HTTP request to the DoH server failed")]
+    Http(#[error(source)] hyper::Error),
+
+    #[error(display = "The DoH server responded with status {}", _0)]
+    Status(StatusCode),
+
+    #[error(display = "The DoH query timed out")]
+    Timeout,
+
+    #[error(display = "The DoH response is too large")]
+    ResponseTooLarge,
+
+    #[error(display = "Malformed DNS response")]
+    MalformedResponse,
+
+    #[error(display = "DNS query failed with response code {}", _0)]
+    ResponseCode(u8),
+
+    #[error(display = "DNS response contains no usable addresses")]
+    NoAddresses,
+}
+
+struct DohServer {
+    hostname: &'static str,
+    ip: Ipv4Addr,
+}
+
+/// DNS record types that may be queried for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordType {
+    A,
+    Aaaa,
+}
+
+impl RecordType {
+    fn code(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Aaaa => 28,
+        }
+    }
+}
+
+/// Controls whether DNS-over-HTTPS may be used to look up the API host, and how often.
+#[derive(Debug)]
+pub struct DohFallback {
+    enabled: AtomicBool,
+    last_lookup: Mutex<Option<Instant>>,
+}
+
+impl DohFallback {
+    /// Creates the fallback settings. The fallback can only be used once `enabled` is set, here
+    /// or later using [`DohFallback::set_enabled`].
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            last_lookup: Mutex::new(None),
+        }
+    }
+
+    /// Returns whether the fallback may be used.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Allows or prevents the fallback from being used. When disabled, no DoH servers are ever
+    /// contacted.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns whether a lookup may be started now, and if so, records that one was started.
+    pub(crate) fn begin_lookup(&self) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let mut last_lookup = self.last_lookup.lock().unwrap();
+        let now = Instant::now();
+        if let Some(last_lookup) = *last_lookup {
+            if now.saturating_duration_since(last_lookup) < MIN_LOOKUP_INTERVAL {
+                return false;
+            }
+        }
+        *last_lookup = Some(now);
+        true
+    }
+}
+
+/// Looks up the addresses of `hostname`, trying each DoH server until one succeeds. IPv6 addresses
+/// are only returned if there are no IPv4 addresses.
+pub(crate) async fn resolve(
+    hostname: &str,
+    #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+) -> Result<Vec<IpAddr>, Error> {
+    let mut last_error = Error::NoAddresses;
+    for server in SERVERS {
+        for record_type in [RecordType::A, RecordType::Aaaa] {
+            let result = tokio::time::timeout(QUERY_TIMEOUT, async {
+                let socket = HttpsConnectorWithSni::open_socket(
+                    SocketAddr::new(server.ip.into(), DOH_PORT),
+                    #[cfg(target_os = "android")]
+                    socket_bypass_tx.clone(),
+                )
+                .await
+                .map_err(Error::Io)?;
+                query(socket, server, hostname, record_type).await
+            })
+            .await
+            .unwrap_or(Err(Error::Timeout));
+
+            match result {
+                Ok(addresses) => return Ok(addresses),
+                Err(error) => {
+                    log::debug!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "DoH query to {} failed",
+                            server.hostname
+                        ))
+                    );
+                    last_error = error;
+                }
+            }
+        }
+    }
+    Err(last_error)
+}
+
+async fn query<S>(
+    stream: S,
+    server: &DohServer,
+    hostname: &str,
+    record_type: RecordType,
+) -> Result<Vec<IpAddr>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let tls_stream = TlsStream::connect_https(stream, server.hostname)
+        .await
+        .map_err(Error::Io)?;
+    send_query(tls_stream, server.hostname, hostname, record_type).await
+}
+
+/// Sends a query for `hostname` over `stream`, which must be connected to the DoH server
+/// `server_hostname`.
+async fn send_query<S>(
+    stream: S,
+    server_hostname: &str,
+    hostname: &str,
+    record_type: RecordType,
+) -> Result<Vec<IpAddr>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let message = encode_query(hostname, record_type)?;
+
+    let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .map_err(Error::Http)?;
+    tokio::spawn(async move {
+        if let Err(error) = connection.await {
+            log::trace!("{}", error.display_chain_with_msg("DoH connection failed"));
+        }
+    });
+
+    let request = hyper::Request::get(format!("/dns-query?dns={}", base64url_encode(&message)))
+        .header(header::HOST, server_hostname)
+        .header(header::ACCEPT, DNS_MESSAGE_CONTENT_TYPE)
+        .body(hyper::Body::empty())
+        .expect("DoH request is valid");
+    let response = sender.send_request(request).await.map_err(Error::Http)?;
+    if response.status() != StatusCode::OK {
+        return Err(Error::Status(response.status()));
+    }
+    // The body is read in chunks so that an oversized response is never buffered in full
+    let mut body = response.into_body();
+    let mut response_message = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(Error::Http)?;
+        if response_message.len() + chunk.len() > MAX_RESPONSE_SIZE {
+            return Err(Error::ResponseTooLarge);
+        }
+        response_message.extend_from_slice(&chunk);
+    }
+
+    let addresses: Vec<_> = decode_response(&response_message, record_type)?
+        .into_iter()
+        .filter(is_plausible_address)
+        .collect();
+    if addresses.is_empty() {
+        return Err(Error::NoAddresses);
+    }
+    Ok(addresses)
+}
+
+/// Rejects addresses that a poisoned or misconfigured resolver may return instead of real ones.
+fn is_plausible_address(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            !(address.is_unspecified()
+                || address.is_loopback()
+                || address.is_private()
+                || address.is_link_local()
+                || address.is_broadcast()
+                || address.is_multicast())
+        }
+        IpAddr::V6(address) => {
+            !(address.is_unspecified() || address.is_loopback() || address.is_multicast())
+        }
+    }
+}
+
+/// Encodes a recursive query for `hostname` in DNS wire format. The ID is always 0, as
+/// recommended by RFC 8484.
+pub fn encode_query(hostname: &str, record_type: RecordType) -> Result<Vec<u8>, Error> {
+    let mut message = Vec::with_capacity(HEADER_SIZE + hostname.len() + 6);
+    // ID, flags, QDCOUNT, ANCOUNT, NSCOUNT, ARCOUNT
+    for value in [0, FLAG_RECURSION_DESIRED, 1, 0, 0, 0] {
+        message.extend_from_slice(&u16::to_be_bytes(value));
+    }
+
+    let name_start = message.len();
+    for label in hostname.strip_suffix('.').unwrap_or(hostname).split('.') {
+        if label.is_empty() || label.len() > MAX_LABEL_LENGTH || !label.is_ascii() {
+            return Err(Error::InvalidHostname);
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    if message.len() - name_start > MAX_NAME_LENGTH {
+        return Err(Error::InvalidHostname);
+    }
+
+    message.extend_from_slice(&record_type.code().to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(message)
+}
+
+/// Returns the addresses in the answer section of a DNS response that match `record_type`.
+/// Other records, such as CNAMEs, are ignored.
+pub fn decode_response(message: &[u8], record_type: RecordType) -> Result<Vec<IpAddr>, Error> {
+    let flags = read_u16(message, 2)?;
+    if flags & FLAG_RESPONSE == 0 {
+        return Err(Error::MalformedResponse);
+    }
+    let response_code = (flags & 0x000f) as u8;
+    if response_code != 0 {
+        return Err(Error::ResponseCode(response_code));
+    }
+    let question_count = read_u16(message, 4)?;
+    let answer_count = read_u16(message, 6)?;
+
+    let mut offset = HEADER_SIZE;
+    for _ in 0..question_count {
+        // Skip the name, type and class
+        offset = skip_name(message, offset)? + 4;
+    }
+
+    let mut addresses = vec![];
+    for _ in 0..answer_count {
+        offset = skip_name(message, offset)?;
+        let answer_type = read_u16(message, offset)?;
+        let answer_class = read_u16(message, offset + 2)?;
+        // The TTL is ignored
+        let data_length = usize::from(read_u16(message, offset + 8)?);
+        let data_start = offset + 10;
+        let data = message
+            .get(data_start..data_start + data_length)
+            .ok_or(Error::MalformedResponse)?;
+        offset = data_start + data_length;
+
+        if answer_type != record_type.code() || answer_class != CLASS_IN {
+            continue;
+        }
+        let address: Result<IpAddr, _> = match record_type {
+            RecordType::A => <[u8; 4]>::try_from(data).map(|octets| Ipv4Addr::from(octets).into()),
+            RecordType::Aaaa => {
+                <[u8; 16]>::try_from(data).map(|octets| Ipv6Addr::from(octets).into())
+            }
+        };
+        addresses.push(address.map_err(|_| Error::MalformedResponse)?);
+    }
+    Ok(addresses)
+}
+
+/// Returns the offset following the (possibly compressed) name at `offset`.
+fn skip_name(message: &[u8], mut offset: usize) -> Result<usize, Error> {
+    loop {
+        let length = *message.get(offset).ok_or(Error::MalformedResponse)?;
+        match length & 0xc0 {
+            // A pointer ends the name
+            0xc0 => {
+                if offset + 1 >= message.len() {
+                    return Err(Error::MalformedResponse);
+                }
+                return Ok(offset + 2);
+            }
+            0x00 if length == 0 => return Ok(offset + 1),
+            0x00 => offset += 1 + usize::from(length),
+            _ => return Err(Error::MalformedResponse),
+        }
+    }
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Result<u16, Error> {
+    message
+        .get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or(Error::MalformedResponse)
+}
+
+/// Encodes `data` as unpadded base64url, as required for DoH GET requests.
+fn base64url_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    let mut encoded = String::with_capacity((data.len() * 4 + 2) / 3);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let group = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..=chunk.len() {
+            let index = (group >> (18 - 6 * i)) & 0x3f;
+            encoded.push(char::from(ALPHABET[index as usize]));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Response to an A query for api.mullvad.net, where the answer is a CNAME followed by an
+    /// A record. Both answer names are compressed.
+    fn cname_response() -> Vec<u8> {
+        let mut response = vec![
+            0x00, 0x00, // ID
+            0x81, 0x80, // Flags: response, RD, RA
+            0x00, 0x01, // QDCOUNT
+            0x00, 0x02, // ANCOUNT
+            0x00, 0x00, // NSCOUNT
+            0x00, 0x00, // ARCOUNT
+        ];
+        // Question
+        response.extend_from_slice(b"\x03api\x07mullvad\x03net\x00");
+        response.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        // CNAME answer pointing to the question name
+        response.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x05, 0x00, 0x01]);
+        response.extend_from_slice(&[0x00, 0x00, 0x01, 0x2c, 0x00, 0x07]);
+        response.extend_from_slice(b"\x04edge\xc0\x10");
+        // A answer
+        response.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01]);
+        response.extend_from_slice(&[0x00, 0x00, 0x01, 0x2c, 0x00, 0x04]);
+        response.extend_from_slice(&[45, 83, 223, 196]);
+        response
+    }
+
+    #[test]
+    fn test_encode_query() {
+        let query = encode_query("api.mullvad.net", RecordType::A).unwrap();
+        let mut expected = vec![0, 0, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        expected.extend_from_slice(b"\x03api\x07mullvad\x03net\x00");
+        expected.extend_from_slice(&[0, 1, 0, 1]);
+        assert_eq!(query, expected);
+
+        // A trailing dot is allowed
+        assert_eq!(
+            encode_query("api.mullvad.net.", RecordType::A).unwrap(),
+            expected
+        );
+
+        let query = encode_query("api.mullvad.net", RecordType::Aaaa).unwrap();
+        assert_eq!(&query[query.len() - 4..], &[0, 28, 0, 1]);
+    }
+
+    #[test]
+    fn test_encode_invalid_hostname() {
+        let long_label = "a".repeat(64);
+        let long_name = "a.".repeat(128);
+        for hostname in ["", "api..mullvad.net", &long_label, &long_name] {
+            assert!(matches!(
+                encode_query(hostname, RecordType::A),
+                Err(Error::InvalidHostname)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decode_response() {
+        let addresses = decode_response(&cname_response(), RecordType::A).unwrap();
+        assert_eq!(addresses, vec![IpAddr::from([45, 83, 223, 196])]);
+
+        // Records of other types are ignored
+        let addresses = decode_response(&cname_response(), RecordType::Aaaa).unwrap();
+        assert!(addresses.is_empty());
+    }
+
+    #[test]
+    fn test_decode_aaaa_response() {
+        let mut response = vec![0, 0, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        response.extend_from_slice(b"\x03api\x07mullvad\x03net\x00");
+        response.extend_from_slice(&[0x00, 0x1c, 0x00, 0x01]);
+        response.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x1c, 0x00, 0x01]);
+        response.extend_from_slice(&[0x00, 0x00, 0x01, 0x2c, 0x00, 0x10]);
+        let address: Ipv6Addr = "2a03:1b20:3:f011::a01f".parse().unwrap();
+        response.extend_from_slice(&address.octets());
+
+        let addresses = decode_response(&response, RecordType::Aaaa).unwrap();
+        assert_eq!(addresses, vec![IpAddr::V6(address)]);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        // Name error
+        let mut response = cname_response();
+        response[3] = 0x83;
+        assert!(matches!(
+            decode_response(&response, RecordType::A),
+            Err(Error::ResponseCode(3))
+        ));
+
+        // Not a response
+        let mut response = cname_response();
+        response[2] = 0x01;
+        assert!(matches!(
+            decode_response(&response, RecordType::A),
+            Err(Error::MalformedResponse)
+        ));
+
+        // Truncated at every possible position
+        let response = cname_response();
+        for length in 0..response.len() {
+            assert!(matches!(
+                decode_response(&response[..length], RecordType::A),
+                Err(Error::MalformedResponse)
+            ));
+        }
+
+        // A record with the wrong length
+        let mut response = cname_response();
+        let length = response.len();
+        response[length - 5] = 0x03;
+        response.pop();
+        assert!(matches!(
+            decode_response(&response, RecordType::A),
+            Err(Error::MalformedResponse)
+        ));
+    }
+
+    #[test]
+    fn test_plausible_addresses() {
+        assert!(is_plausible_address(&IpAddr::from([45, 83, 223, 196])));
+        assert!(!is_plausible_address(&IpAddr::from([0, 0, 0, 0])));
+        assert!(!is_plausible_address(&IpAddr::from([127, 0, 0, 1])));
+        assert!(!is_plausible_address(&IpAddr::from([10, 0, 0, 1])));
+        assert!(!is_plausible_address(&IpAddr::V6(Ipv6Addr::LOCALHOST)));
+    }
+
+    #[test]
+    fn test_base64url_encode() {
+        assert_eq!(base64url_encode(b""), "");
+        assert_eq!(base64url_encode(b"f"), "Zg");
+        assert_eq!(base64url_encode(b"fo"), "Zm8");
+        assert_eq!(base64url_encode(b"foo"), "Zm9v");
+        assert_eq!(base64url_encode(b"foob"), "Zm9vYg");
+        assert_eq!(base64url_encode(&[0xfb, 0xff]), "-_8");
+    }
+
+    /// Serves a single DoH request received on `stream`, responding with `status` and `body`.
+    /// Returns the head of the request.
+    async fn serve_mock_doh_request(
+        mut stream: tokio::io::DuplexStream,
+        status: &'static str,
+        body: Vec<u8>,
+    ) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let read = stream.read(&mut buffer).await.unwrap();
+            assert!(
+                read > 0,
+                "the connection was closed before the request was received"
+            );
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let head = format!(
+            "HTTP/1.1 {}\r\ncontent-type: {}\r\ncontent-length: {}\r\n\r\n",
+            status,
+            DNS_MESSAGE_CONTENT_TYPE,
+            body.len()
+        );
+        // The client stops reading oversized responses, so writing may fail
+        if stream.write_all(head.as_bytes()).await.is_ok() {
+            let _ = stream.write_all(&body).await;
+        }
+        String::from_utf8(request).unwrap()
+    }
+
+    #[test]
+    fn test_mock_doh_server() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (client, server) = tokio::io::duplex(4096);
+            let server = tokio::spawn(serve_mock_doh_request(server, "200 OK", cname_response()));
+
+            let addresses = send_query(client, "doh.test", "api.mullvad.net", RecordType::A)
+                .await
+                .unwrap();
+            assert_eq!(addresses, vec![IpAddr::from([45, 83, 223, 196])]);
+
+            let request = server.await.unwrap();
+            let query = encode_query("api.mullvad.net", RecordType::A).unwrap();
+            assert!(request.starts_with(&format!(
+                "GET /dns-query?dns={} HTTP/1.1\r\n",
+                base64url_encode(&query)
+            )));
+            let request = request.to_ascii_lowercase();
+            assert!(request.contains("host: doh.test\r\n"));
+            assert!(request.contains("accept: application/dns-message\r\n"));
+        });
+    }
+
+    #[test]
+    fn test_mock_doh_server_errors() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (client, server) = tokio::io::duplex(4096);
+            tokio::spawn(serve_mock_doh_request(
+                server,
+                "503 Service Unavailable",
+                vec![],
+            ));
+            assert!(matches!(
+                send_query(client, "doh.test", "api.mullvad.net", RecordType::A).await,
+                Err(Error::Status(StatusCode::SERVICE_UNAVAILABLE))
+            ));
+
+            let (client, server) = tokio::io::duplex(4096);
+            tokio::spawn(serve_mock_doh_request(
+                server,
+                "200 OK",
+                vec![0; MAX_RESPONSE_SIZE + 1],
+            ));
+            assert!(matches!(
+                send_query(client, "doh.test", "api.mullvad.net", RecordType::A).await,
+                Err(Error::ResponseTooLarge)
+            ));
+
+            // Implausible addresses are discarded
+            let mut response = cname_response();
+            let length = response.len();
+            response[length - 4..].copy_from_slice(&[127, 0, 0, 1]);
+            let (client, server) = tokio::io::duplex(4096);
+            tokio::spawn(serve_mock_doh_request(server, "200 OK", response));
+            assert!(matches!(
+                send_query(client, "doh.test", "api.mullvad.net", RecordType::A).await,
+                Err(Error::NoAddresses)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_lookup_rate_limit() {
+        let fallback = DohFallback::new(true);
+        assert!(fallback.begin_lookup());
+        assert!(!fallback.begin_lookup());
+
+        let fallback = DohFallback::new(false);
+        assert!(!fallback.begin_lookup());
+        fallback.set_enabled(true);
+        assert!(fallback.begin_lookup());
+    }
+}
@@ -0,0 +1,225 @@
+//! Descriptions of the requests that clients send to the Mullvad API. These only say what to send
+//! and which responses to expect, so they can be used with any transport.
+
+use crate::models;
+use http::{Method, StatusCode};
+use mullvad_types::{account::AccountToken, version::AppVersion};
+use talpid_types::net::wireguard;
+
+/// Response bodies of version checks are small, so anything larger than this is not legitimate.
+pub const VERSION_CHECK_MAX_SIZE: usize = 64 * 1024;
+
+/// A request to the API, independent of how it is sent.
+#[derive(Debug, Clone)]
+pub struct RequestDescriptor {
+    pub method: Method,
+    /// Path of the endpoint, relative to the root of the API.
+    pub path: String,
+    /// Account token to authenticate the request with, if any.
+    pub auth: Option<AccountToken>,
+    /// JSON body of the request, if any.
+    pub body: Option<Vec<u8>>,
+    /// Headers to add to the request, in addition to the ones every API request has.
+    pub headers: Vec<(&'static str, String)>,
+    /// Status codes of successful responses. Any other status is an error.
+    pub expected_statuses: &'static [StatusCode],
+    /// Largest response body, in bytes, that may be deserialized. `None` if the transport decides.
+    pub max_response_size: Option<usize>,
+}
+
+impl RequestDescriptor {
+    fn new(method: Method, path: String, expected_statuses: &'static [StatusCode]) -> Self {
+        Self {
+            method,
+            path,
+            auth: None,
+            body: None,
+            headers: vec![],
+            expected_statuses,
+            max_response_size: None,
+        }
+    }
+
+    fn with_auth(mut self, account: AccountToken) -> Self {
+        self.auth = Some(account);
+        self
+    }
+
+    fn with_json_body<S: serde::Serialize>(mut self, body: &S) -> serde_json::Result<Self> {
+        self.body = Some(serde_json::to_vec(body)?);
+        Ok(self)
+    }
+}
+
+/// Returns the details of an account. The response is a [`models::AccountResponse`].
+pub fn get_expiry(account: AccountToken) -> RequestDescriptor {
+    RequestDescriptor::new(Method::GET, "/v1/me".to_owned(), &[StatusCode::OK]).with_auth(account)
+}
+
+/// Creates a new account. The response is a [`models::AccountResponse`].
+pub fn create_account() -> RequestDescriptor {
+    RequestDescriptor::new(
+        Method::POST,
+        "/v1/accounts".to_owned(),
+        &[StatusCode::CREATED],
+    )
+}
+
+/// Adds the time of a voucher to an account. The response is a
+/// [`mullvad_types::account::VoucherSubmission`].
+pub fn submit_voucher(
+    account: AccountToken,
+    voucher_code: String,
+) -> serde_json::Result<RequestDescriptor> {
+    RequestDescriptor::new(
+        Method::POST,
+        "/v1/submit-voucher".to_owned(),
+        &[StatusCode::OK],
+    )
+    .with_auth(account)
+    .with_json_body(&models::VoucherSubmissionRequest { voucher_code })
+}
+
+/// Returns a token for logging in to the website. The response is a
+/// [`models::AuthTokenResponse`].
+pub fn www_auth_token(account: AccountToken) -> RequestDescriptor {
+    RequestDescriptor::new(
+        Method::POST,
+        "/v1/www-auth-token".to_owned(),
+        &[StatusCode::OK],
+    )
+    .with_auth(account)
+}
+
+/// Checks whether an app version is supported. The response is a
+/// [`models::AppVersionResponse`].
+pub fn version_check(
+    app_version: &AppVersion,
+    platform: &str,
+    platform_version: String,
+) -> RequestDescriptor {
+    let mut descriptor = RequestDescriptor::new(
+        Method::GET,
+        format!("/v1/releases/{}/{}", platform, app_version),
+        &[StatusCode::OK],
+    );
+    descriptor
+        .headers
+        .push(("M-Platform-Version", platform_version));
+    descriptor.max_response_size = Some(VERSION_CHECK_MAX_SIZE);
+    descriptor
+}
+
+/// Adds a WireGuard key to an account. The response is a
+/// [`mullvad_types::wireguard::AssociatedAddresses`].
+pub fn push_wg_key(
+    account: AccountToken,
+    pubkey: wireguard::PublicKey,
+) -> serde_json::Result<RequestDescriptor> {
+    RequestDescriptor::new(
+        Method::POST,
+        "/v1/wireguard-keys".to_owned(),
+        &[StatusCode::CREATED],
+    )
+    .with_auth(account)
+    .with_json_body(&models::PublishKeyRequest { pubkey })
+}
+
+/// Replaces a WireGuard key of an account. The response is a
+/// [`mullvad_types::wireguard::AssociatedAddresses`].
+pub fn replace_wg_key(
+    account: AccountToken,
+    old: wireguard::PublicKey,
+    new: wireguard::PublicKey,
+) -> serde_json::Result<RequestDescriptor> {
+    RequestDescriptor::new(
+        Method::POST,
+        "/v1/replace-wireguard-key".to_owned(),
+        &[StatusCode::CREATED, StatusCode::OK],
+    )
+    .with_auth(account)
+    .with_json_body(&models::ReplaceKeyRequest { old, new })
+}
+
+/// Returns the addresses associated with a WireGuard key of an account. The response is a
+/// [`mullvad_types::wireguard::AssociatedAddresses`].
+pub fn get_wg_key(account: AccountToken, key: &wireguard::PublicKey) -> RequestDescriptor {
+    RequestDescriptor::new(Method::GET, wg_key_path(key), &[StatusCode::OK]).with_auth(account)
+}
+
+/// Removes a WireGuard key from an account. The response has no body.
+pub fn remove_wg_key(account: AccountToken, key: &wireguard::PublicKey) -> RequestDescriptor {
+    RequestDescriptor::new(Method::DELETE, wg_key_path(key), &[StatusCode::NO_CONTENT])
+        .with_auth(account)
+}
+
+/// Returns the addresses that the API can be reached on. The response is a list of socket
+/// addresses.
+pub fn api_addrs() -> RequestDescriptor {
+    RequestDescriptor::new(Method::GET, "/v1/api-addrs".to_owned(), &[StatusCode::OK])
+}
+
+fn wg_key_path(key: &wireguard::PublicKey) -> String {
+    format!(
+        "/v1/wireguard-keys/{}",
+        urlencoding::encode(&key.to_base64())
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_account_requests() {
+        let account = "1234123412341234".to_owned();
+
+        let descriptor = get_expiry(account.clone());
+        assert_eq!(descriptor.method, Method::GET);
+        assert_eq!(descriptor.path, "/v1/me");
+        assert_eq!(descriptor.auth.as_ref(), Some(&account));
+        assert!(descriptor.body.is_none());
+
+        let descriptor = create_account();
+        assert_eq!(descriptor.method, Method::POST);
+        assert_eq!(descriptor.expected_statuses, &[StatusCode::CREATED]);
+        assert!(descriptor.auth.is_none());
+
+        let descriptor = submit_voucher(account.clone(), "VOUCHER".to_owned()).unwrap();
+        assert_eq!(descriptor.path, "/v1/submit-voucher");
+        assert_eq!(descriptor.auth, Some(account));
+        let body: serde_json::Value = serde_json::from_slice(&descriptor.body.unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({ "voucher_code": "VOUCHER" }));
+    }
+
+    #[test]
+    fn test_version_check() {
+        let version: AppVersion = "2022.1".to_owned();
+        let descriptor = version_check(&version, "linux", "Ubuntu 22.04".to_owned());
+        assert_eq!(descriptor.path, "/v1/releases/linux/2022.1");
+        assert_eq!(
+            descriptor.headers,
+            vec![("M-Platform-Version", "Ubuntu 22.04".to_owned())]
+        );
+        assert_eq!(descriptor.max_response_size, Some(VERSION_CHECK_MAX_SIZE));
+        assert!(descriptor.auth.is_none());
+    }
+
+    #[test]
+    fn test_wg_key_paths_are_encoded() {
+        // Base64 keys contain characters that must be escaped in paths
+        let key = wireguard::PublicKey::from([0xfb; 32]);
+        assert!(key.to_base64().contains('+') || key.to_base64().contains('/'));
+
+        let account = "1234123412341234".to_owned();
+        let get = get_wg_key(account.clone(), &key);
+        let remove = remove_wg_key(account, &key);
+        assert_eq!(get.path, remove.path);
+        assert!(get.path.starts_with("/v1/wireguard-keys/"));
+        assert!(!get.path["/v1/wireguard-keys/".len()..].contains('/'));
+        assert!(!get.path.contains('+'));
+        assert_eq!(get.method, Method::GET);
+        assert_eq!(remove.method, Method::DELETE);
+        assert_eq!(remove.expected_statuses, &[StatusCode::NO_CONTENT]);
+    }
+}
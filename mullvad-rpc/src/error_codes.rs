@@ -0,0 +1,16 @@
+//! Error codes returned by the Mullvad API in [`crate::models::ErrorResponse`].
+
+/// Error code returned by the Mullvad API if the voucher has alreaby been used.
+pub const VOUCHER_USED: &str = "VOUCHER_USED";
+
+/// Error code returned by the Mullvad API if the voucher code is invalid.
+pub const INVALID_VOUCHER: &str = "INVALID_VOUCHER";
+
+/// Error code returned by the Mullvad API if the account token is invalid.
+pub const INVALID_ACCOUNT: &str = "INVALID_ACCOUNT";
+
+/// Error code returned by the Mullvad API if the account token is missing or invalid.
+pub const INVALID_AUTH: &str = "INVALID_AUTH";
+
+/// Error code for when an account has too many keys. Returned when trying to push a new key.
+pub const KEY_LIMIT_REACHED: &str = "KEY_LIMIT_REACHED";
@@ -1,7 +1,10 @@
+#[cfg(any(test, feature = "test-util"))]
+use crate::test_util::FaultInjector;
 use crate::{
     abortable_stream::{AbortableStream, AbortableStreamHandle},
     proxy::{ApiConnection, ApiConnectionMode, ProxyConfig},
     tls_stream::TlsStream,
+    traffic_stats::{ApiTrafficStats, CountingStream},
     AddressCache,
 };
 use futures::{channel::mpsc, future, StreamExt};
@@ -31,7 +34,7 @@ use std::{
     str::{self, FromStr},
     sync::{Arc, Mutex},
     task::{Context, Poll},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use talpid_types::ErrorExt;
 #[cfg(target_os = "android")]
@@ -41,6 +44,20 @@ use tokio::{net::TcpStream, time::timeout};
 
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// Details about a successfully established API connection.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionInfo {
+    /// Address of the remote end of the socket. This is the proxy server if a proxy was used.
+    pub peer: SocketAddr,
+    /// Whether the connection goes through a proxy.
+    pub proxied: bool,
+    /// Time it took to open the socket and complete the TLS handshake.
+    pub connect_time: Duration,
+}
+
+/// Callback that is invoked whenever the connector has established a new stream.
+pub type ConnectionListener = Arc<dyn Fn(ConnectionInfo) + Send + Sync>;
+
 #[derive(Clone)]
 pub struct HttpsConnectorWithSniHandle {
     tx: mpsc::UnboundedSender<HttpsConnectorRequest>,
@@ -71,6 +88,8 @@ enum InnerConnectionMode {
     Direct,
     /// Connect to the destination via a proxy.
     Proxied(ParsedShadowsocksConfig),
+    /// Fail every connection attempt.
+    Blocked,
 }
 
 #[derive(Clone)]
@@ -106,6 +125,7 @@ impl TryFrom<ApiConnectionMode> for InnerConnectionMode {
                         .map_err(|_| ProxyConfigError::InvalidCipher(config.cipher))?,
                 })
             }
+            ApiConnectionMode::Blocked => InnerConnectionMode::Blocked,
         })
     }
 }
@@ -118,6 +138,8 @@ pub struct HttpsConnectorWithSni {
     address_cache: AddressCache,
     abort_notify: Arc<tokio::sync::Notify>,
     proxy_context: SharedContext,
+    connection_listener: Option<ConnectionListener>,
+    traffic_stats: ApiTrafficStats,
     #[cfg(target_os = "android")]
     socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
 }
@@ -125,6 +147,8 @@ pub struct HttpsConnectorWithSni {
 struct HttpsConnectorWithSniInner {
     stream_handles: Vec<AbortableStreamHandle>,
     proxy_config: InnerConnectionMode,
+    #[cfg(any(test, feature = "test-util"))]
+    faults: Option<FaultInjector>,
 }
 
 #[cfg(target_os = "android")]
@@ -134,6 +158,8 @@ impl HttpsConnectorWithSni {
     pub fn new(
         sni_hostname: Option<String>,
         address_cache: AddressCache,
+        connection_listener: Option<ConnectionListener>,
+        traffic_stats: ApiTrafficStats,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> (Self, HttpsConnectorWithSniHandle) {
         let (tx, mut rx) = mpsc::unbounded();
@@ -141,6 +167,8 @@ impl HttpsConnectorWithSni {
         let inner = Arc::new(Mutex::new(HttpsConnectorWithSniInner {
             stream_handles: vec![],
             proxy_config: InnerConnectionMode::Direct,
+            #[cfg(any(test, feature = "test-util"))]
+            faults: None,
         }));
 
         let inner_copy = inner.clone();
@@ -183,6 +211,8 @@ impl HttpsConnectorWithSni {
                 address_cache,
                 abort_notify,
                 proxy_context: SsContext::new_shared(ServerType::Local),
+                connection_listener,
+                traffic_stats,
                 #[cfg(target_os = "android")]
                 socket_bypass_tx,
             },
@@ -191,14 +221,14 @@ impl HttpsConnectorWithSni {
     }
 
     #[cfg(not(target_os = "android"))]
-    async fn open_socket(addr: SocketAddr) -> std::io::Result<TcpStream> {
+    pub(crate) async fn open_socket(addr: SocketAddr) -> std::io::Result<TcpStream> {
         timeout(CONNECT_TIMEOUT, TcpStream::connect(addr))
             .await
             .map_err(|err| io::Error::new(io::ErrorKind::TimedOut, err))?
     }
 
     #[cfg(target_os = "android")]
-    async fn open_socket(
+    pub(crate) async fn open_socket(
         addr: SocketAddr,
         socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> std::io::Result<TcpStream> {
@@ -220,6 +250,31 @@ impl HttpsConnectorWithSni {
             .map_err(|err| io::Error::new(io::ErrorKind::TimedOut, err))?
     }
 
+    /// Injects `faults` into the connections that are opened from now on.
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn inject_faults(&self, faults: FaultInjector) {
+        self.inner.lock().unwrap().faults = Some(faults);
+    }
+
+    /// Resolves the configured hostname now, and uses the result for all subsequent connections
+    /// to that host until [`Self::unpin_host`] is called. Nothing is looked up for the host while
+    /// it is pinned, so this can be used to do the lookup while traffic outside the tunnel is
+    /// still allowed.
+    pub async fn prefetch_and_pin_host(&self) -> io::Result<IpAddr> {
+        let hostname = self.sni_hostname.clone().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no hostname is configured")
+        })?;
+        self.address_cache.unpin_host();
+        let address = Self::resolve_hostname(&self.address_cache, &hostname).await?;
+        self.address_cache.pin_host(hostname, address);
+        Ok(address)
+    }
+
+    /// Stops using the address stored by [`Self::prefetch_and_pin_host`].
+    pub fn unpin_host(&self) {
+        self.address_cache.unpin_host();
+    }
+
     async fn resolve_address(address_cache: AddressCache, uri: Uri) -> io::Result<SocketAddr> {
         let hostname = uri.host().ok_or(io::Error::new(
             io::ErrorKind::InvalidInput,
@@ -230,10 +285,37 @@ impl HttpsConnectorWithSni {
             return Ok(SocketAddr::new(addr, port));
         }
 
+        let addr = Self::resolve_hostname(&address_cache, hostname).await?;
+        Ok(SocketAddr::new(addr, port))
+    }
+
+    /// Feeds the outcome of connecting directly to `addr` back to the address cache. When the
+    /// connection failed, the preferred address is picked again.
+    async fn record_connect_outcome(address_cache: &AddressCache, addr: SocketAddr, success: bool) {
+        if success {
+            address_cache.record_success(addr).await;
+            return;
+        }
+        address_cache.record_failure(addr).await;
+        if let Err(error) = address_cache.randomize().await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to save the preferred API address")
+            );
+        }
+    }
+
+    async fn resolve_hostname(address_cache: &AddressCache, hostname: &str) -> io::Result<IpAddr> {
+        // A pinned address is never looked up again.
+        //
+        if let Some(addr) = address_cache.pinned_address(hostname) {
+            return Ok(addr);
+        }
+
         // Preferentially, use cached address.
         //
         if let Some(addr) = address_cache.resolve_hostname(hostname).await {
-            return Ok(SocketAddr::new(addr.ip(), port));
+            return Ok(addr.ip());
         }
 
         // Use getaddrinfo as a fallback
@@ -248,7 +330,7 @@ impl HttpsConnectorWithSni {
         let addr = addrs
             .next()
             .ok_or(io::Error::new(io::ErrorKind::Other, "Empty DNS response"))?;
-        Ok(SocketAddr::new(addr.ip(), port))
+        Ok(addr.ip())
     }
 }
 
@@ -259,7 +341,7 @@ impl fmt::Debug for HttpsConnectorWithSni {
 }
 
 impl Service<Uri> for HttpsConnectorWithSni {
-    type Response = AbortableStream<ApiConnection>;
+    type Response = AbortableStream<CountingStream<ApiConnection>>;
     type Error = io::Error;
     type Future =
         Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
@@ -282,6 +364,8 @@ impl Service<Uri> for HttpsConnectorWithSni {
         #[cfg(target_os = "android")]
         let socket_bypass_tx = self.socket_bypass_tx.clone();
         let address_cache = self.address_cache.clone();
+        let connection_listener = self.connection_listener.clone();
+        let traffic_stats = self.traffic_stats.clone();
 
         let fut = async move {
             if uri.scheme() != Some(&Scheme::HTTPS) {
@@ -292,12 +376,15 @@ impl Service<Uri> for HttpsConnectorWithSni {
             }
 
             let hostname = sni_hostname?;
-            let addr = Self::resolve_address(address_cache, uri).await?;
+            let addr = Self::resolve_address(address_cache.clone(), uri).await?;
 
             // Loop until we have established a connection. This starts over if a new endpoint
             // is selected while connecting.
-            let stream = loop {
+            let (stream, info) = loop {
                 let config = { inner.lock().unwrap().proxy_config.clone() };
+                #[cfg(any(test, feature = "test-util"))]
+                let faults = { inner.lock().unwrap().faults.clone() };
+                let is_direct = matches!(config, InnerConnectionMode::Direct);
                 let hostname_copy = hostname.clone();
                 let addr_copy = addr.clone();
                 let context = proxy_context.clone();
@@ -305,10 +392,18 @@ impl Service<Uri> for HttpsConnectorWithSni {
                 let socket_bypass_tx_copy = socket_bypass_tx.clone();
 
                 let stream_fut: Pin<
-                    Box<dyn Future<Output = Result<ApiConnection, io::Error>> + Send>,
+                    Box<
+                        dyn Future<Output = Result<(ApiConnection, ConnectionInfo), io::Error>>
+                            + Send,
+                    >,
                 > = Box::pin(async move {
+                    let start = Instant::now();
                     match config {
                         InnerConnectionMode::Direct => {
+                            #[cfg(any(test, feature = "test-util"))]
+                            if let Some(faults) = &faults {
+                                faults.apply(addr_copy.ip()).await?;
+                            }
                             let socket = Self::open_socket(
                                 addr_copy,
                                 #[cfg(target_os = "android")]
@@ -317,9 +412,19 @@ impl Service<Uri> for HttpsConnectorWithSni {
                             .await?;
                             let tls_stream =
                                 TlsStream::connect_https(socket, &hostname_copy).await?;
-                            Ok(ApiConnection::Direct(tls_stream))
+                            let info = ConnectionInfo {
+                                peer: addr_copy,
+                                proxied: false,
+                                connect_time: start.elapsed(),
+                            };
+                            Ok((ApiConnection::Direct(tls_stream), info))
                         }
                         InnerConnectionMode::Proxied(proxy_config) => {
+                            let proxy_peer = proxy_config.peer;
+                            #[cfg(any(test, feature = "test-util"))]
+                            if let Some(faults) = &faults {
+                                faults.apply(proxy_peer.ip()).await?;
+                            }
                             let socket = Self::open_socket(
                                 proxy_config.peer,
                                 #[cfg(target_os = "android")]
@@ -334,8 +439,17 @@ impl Service<Uri> for HttpsConnectorWithSni {
                             );
                             let tls_stream =
                                 TlsStream::connect_https(proxy, &hostname_copy).await?;
-                            Ok(ApiConnection::Proxied(tls_stream))
+                            let info = ConnectionInfo {
+                                peer: proxy_peer,
+                                proxied: true,
+                                connect_time: start.elapsed(),
+                            };
+                            Ok((ApiConnection::Proxied(tls_stream), info))
                         }
+                        InnerConnectionMode::Blocked => Err(io::Error::new(
+                            io::ErrorKind::Other,
+                            "API connections are blocked by the connection mode",
+                        )),
                     }
                 });
 
@@ -343,10 +457,20 @@ impl Service<Uri> for HttpsConnectorWithSni {
                 if let future::Either::Left((stream, _)) =
                     future::select(stream_fut, Box::pin(abort_notify.notified())).await
                 {
+                    // Only direct connections say anything about the API address
+                    if is_direct {
+                        Self::record_connect_outcome(&address_cache, addr, stream.is_ok()).await;
+                    }
                     break stream?;
                 }
             };
 
+            if let Some(listener) = connection_listener {
+                // Notify the listener without holding up the connection.
+                tokio::spawn(async move { listener(info) });
+            }
+
+            let stream = CountingStream::new(stream, traffic_stats);
             let (stream, socket_handle) = AbortableStream::new(stream);
 
             {
@@ -361,3 +485,85 @@ impl Service<Uri> for HttpsConnectorWithSni {
         Box::pin(fut)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api_endpoint;
+
+    #[test]
+    fn test_pinned_host() {
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            let api = api_endpoint();
+            let address_cache = AddressCache::new(None, false).unwrap();
+            let (connector, _handle) = HttpsConnectorWithSni::new(
+                Some(api.host.clone()),
+                address_cache.clone(),
+                None,
+                ApiTrafficStats::default(),
+                #[cfg(target_os = "android")]
+                None,
+            );
+            let uri: Uri = format!("https://{}/app/v1/api-addrs", api.host)
+                .parse()
+                .unwrap();
+            let resolve =
+                || HttpsConnectorWithSni::resolve_address(address_cache.clone(), uri.clone());
+
+            let pinned_address = connector.prefetch_and_pin_host().await.unwrap();
+            assert_eq!(pinned_address, api.addr.ip());
+
+            // Changing the address that the host resolves to must not affect the pinned address.
+            let new_address: SocketAddr = "192.0.2.1:443".parse().unwrap();
+            address_cache.set_address(new_address).await.unwrap();
+            assert_eq!(
+                resolve().await.unwrap(),
+                SocketAddr::new(pinned_address, 443)
+            );
+            // The NAT64 lookup in the address cache is skipped as well
+            assert_eq!(
+                address_cache.get_address().await,
+                SocketAddr::new(pinned_address, 443)
+            );
+            // Pinning again resolves the host again
+            assert_eq!(
+                connector.prefetch_and_pin_host().await.unwrap(),
+                new_address.ip()
+            );
+
+            connector.unpin_host();
+            let newer_address: SocketAddr = "192.0.2.2:443".parse().unwrap();
+            address_cache.set_address(newer_address).await.unwrap();
+            assert_eq!(resolve().await.unwrap(), newer_address);
+        });
+    }
+
+    #[test]
+    fn test_blocked_mode() {
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            let api = api_endpoint();
+            let connected = Arc::new(Mutex::new(false));
+            let connected_copy = connected.clone();
+            let listener: ConnectionListener =
+                Arc::new(move |_| *connected_copy.lock().unwrap() = true);
+            let (mut connector, _handle) = HttpsConnectorWithSni::new(
+                Some(api.host.clone()),
+                AddressCache::new(None, false).unwrap(),
+                Some(listener),
+                ApiTrafficStats::default(),
+                #[cfg(target_os = "android")]
+                None,
+            );
+            connector.inner.lock().unwrap().proxy_config =
+                InnerConnectionMode::try_from(ApiConnectionMode::Blocked).unwrap();
+
+            let uri: Uri = format!("https://{}/app/v1/api-addrs", api.host)
+                .parse()
+                .unwrap();
+            assert!(connector.call(uri).await.is_err());
+            assert!(!*connected.lock().unwrap());
+        });
+    }
+}
@@ -0,0 +1,174 @@
+//! Bodies of the requests to and responses from the Mullvad API. These do not depend on how the
+//! requests are sent.
+
+use chrono::{offset::Utc, DateTime};
+use mullvad_types::{account::AccountToken, version::AppVersion};
+use std::collections::BTreeMap;
+use talpid_types::net::wireguard;
+
+/// Account details returned by the API.
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct AccountResponse {
+    pub token: AccountToken,
+    pub expires: DateTime<Utc>,
+}
+
+/// Body of a voucher submission. The response is a
+/// [`mullvad_types::account::VoucherSubmission`].
+#[derive(serde::Serialize, Debug)]
+pub struct VoucherSubmissionRequest {
+    pub voucher_code: String,
+}
+
+/// Token for logging in to the website without entering the account number.
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct AuthTokenResponse {
+    pub auth_token: String,
+}
+
+/// Body of a problem report.
+#[derive(serde::Serialize, Debug)]
+pub struct ProblemReport<'a> {
+    /// Email address to reply to. May be empty.
+    pub address: &'a str,
+    pub message: &'a str,
+    pub log: &'a str,
+    pub metadata: &'a BTreeMap<String, String>,
+}
+
+/// Body of a request that adds a WireGuard key to an account.
+#[derive(serde::Serialize, Debug)]
+pub struct PublishKeyRequest {
+    pub pubkey: wireguard::PublicKey,
+}
+
+/// Body of a request that replaces a WireGuard key of an account.
+#[derive(serde::Serialize, Debug)]
+pub struct ReplaceKeyRequest {
+    pub old: wireguard::PublicKey,
+    pub new: wireguard::PublicKey,
+}
+
+/// Body of the error responses of the API. `code` is one of the constants in
+/// [`crate::error_codes`] for errors that clients are expected to handle.
+#[derive(serde::Deserialize, Debug)]
+pub struct ErrorResponse {
+    pub code: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct AppVersionResponse {
+    pub supported: bool,
+    pub latest: AppVersion,
+    pub latest_stable: Option<AppVersion>,
+    pub latest_beta: AppVersion,
+    /// Whether the app refuses to function until it is upgraded. Only relevant when the version
+    /// is unsupported.
+    #[serde(default)]
+    pub upgrade_required: Option<bool>,
+    /// Point in time after which an unsupported version is blocked.
+    #[serde(default)]
+    pub block_date: Option<DateTime<Utc>>,
+}
+
+/// How strictly an upgrade is enforced for an unsupported app version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeEnforcement {
+    /// The version is supported.
+    None,
+    /// The user should be asked to upgrade, but the app keeps working.
+    Nag,
+    /// The app refuses to function, immediately or once `after` has passed.
+    Block { after: Option<DateTime<Utc>> },
+}
+
+impl AppVersionResponse {
+    /// Returns how an upgrade should be enforced. An unsupported version is blocked if the API
+    /// requires an upgrade or sets a block date, unless it explicitly says that no upgrade is
+    /// required.
+    pub fn enforcement(&self) -> UpgradeEnforcement {
+        if self.supported {
+            return UpgradeEnforcement::None;
+        }
+        match (self.upgrade_required, self.block_date) {
+            (Some(true), after) | (None, after @ Some(_)) => UpgradeEnforcement::Block { after },
+            _ => UpgradeEnforcement::Nag,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_version_response(extra_fields: &str) -> AppVersionResponse {
+        let body = format!(
+            r#"{{"supported": false, "latest": "2022.1", "latest_stable": "2022.1", "latest_beta": "2022.2-beta1"{}}}"#,
+            extra_fields
+        );
+        serde_json::from_str(&body).unwrap()
+    }
+
+    #[test]
+    fn test_upgrade_enforcement() {
+        let mut response = parse_version_response("");
+        assert_eq!(response.enforcement(), UpgradeEnforcement::Nag);
+        response.supported = true;
+        assert_eq!(response.enforcement(), UpgradeEnforcement::None);
+
+        let response = parse_version_response(r#", "upgrade_required": false"#);
+        assert_eq!(response.enforcement(), UpgradeEnforcement::Nag);
+
+        let response = parse_version_response(r#", "upgrade_required": true"#);
+        assert_eq!(
+            response.enforcement(),
+            UpgradeEnforcement::Block { after: None }
+        );
+
+        let block_date = "2022-06-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let response = parse_version_response(
+            r#", "upgrade_required": true, "block_date": "2022-06-01T00:00:00Z""#,
+        );
+        assert_eq!(
+            response.enforcement(),
+            UpgradeEnforcement::Block {
+                after: Some(block_date)
+            }
+        );
+
+        let response = parse_version_response(r#", "block_date": "2022-06-01T00:00:00Z""#);
+        assert_eq!(
+            response.enforcement(),
+            UpgradeEnforcement::Block {
+                after: Some(block_date)
+            }
+        );
+
+        let response = parse_version_response(
+            r#", "upgrade_required": false, "block_date": "2022-06-01T00:00:00Z""#,
+        );
+        assert_eq!(response.enforcement(), UpgradeEnforcement::Nag);
+    }
+
+    #[test]
+    fn test_key_request_bodies() {
+        let old = wireguard::PublicKey::from([1u8; 32]);
+        let new = wireguard::PublicKey::from([2u8; 32]);
+
+        let body = serde_json::to_value(&PublishKeyRequest {
+            pubkey: old.clone(),
+        })
+        .unwrap();
+        assert_eq!(body, serde_json::json!({ "pubkey": old.to_base64() }));
+
+        let body = serde_json::to_value(&ReplaceKeyRequest {
+            old: old.clone(),
+            new: new.clone(),
+        })
+        .unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "old": old.to_base64(), "new": new.to_base64() })
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! Support for reaching IPv4 addresses from networks that only provide IPv6 connectivity, such as
+//! mobile networks that use 464XLAT. On these networks, IPv4 hosts are reached through a NAT64
+//! gateway, using IPv6 addresses synthesized from a prefix that is discovered as described in
+//! RFC 7050.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+/// Name that only has A records. Its AAAA records are synthesized by DNS64 resolvers.
+const DISCOVERY_HOSTNAME: &str = "ipv4only.arpa";
+
+/// The A records of [`DISCOVERY_HOSTNAME`].
+const DISCOVERY_ADDRESSES: [Ipv4Addr; 2] =
+    [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+
+/// Maximum time to wait for the discovery lookup.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Prefix lengths allowed by RFC 6052, in the order that they are tried during discovery.
+const PREFIX_LENGTHS: [u8; 6] = [96, 64, 56, 48, 40, 32];
+
+/// Octet of the IPv6 address that is always zero in synthesized addresses (the "u" octet).
+const RESERVED_OCTET: usize = 8;
+
+/// A prefix that IPv4 addresses are embedded in to be reached through NAT64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nat64Prefix {
+    address: Ipv6Addr,
+    length: u8,
+}
+
+impl Nat64Prefix {
+    /// The Well-Known Prefix, 64:ff9b::/96, defined in RFC 6052.
+    pub const WELL_KNOWN: Nat64Prefix = Nat64Prefix {
+        address: Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0),
+        length: 96,
+    };
+
+    /// Returns a prefix of `length` bits of `address`, or `None` if RFC 6052 does not allow
+    /// `length`. Bits after the prefix are ignored.
+    pub fn new(address: Ipv6Addr, length: u8) -> Option<Self> {
+        if !PREFIX_LENGTHS.contains(&length) {
+            return None;
+        }
+        let mut octets = address.octets();
+        for octet in &mut octets[usize::from(length / 8)..] {
+            *octet = 0;
+        }
+        Some(Nat64Prefix {
+            address: Ipv6Addr::from(octets),
+            length,
+        })
+    }
+
+    /// Embeds `ipv4` in the prefix, as described in section 2.2 of RFC 6052.
+    pub fn synthesize(&self, ipv4: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = self.address.octets();
+        for (position, octet) in self.embedded_positions().zip(ipv4.octets()) {
+            octets[position] = octet;
+        }
+        Ipv6Addr::from(octets)
+    }
+
+    /// Returns the IPv4 address embedded in `address`, or `None` if `address` was not synthesized
+    /// from this prefix.
+    pub fn extract(&self, address: Ipv6Addr) -> Option<Ipv4Addr> {
+        let octets = address.octets();
+        let prefix_length = usize::from(self.length / 8);
+        if octets[..prefix_length] != self.address.octets()[..prefix_length] {
+            return None;
+        }
+        if prefix_length <= RESERVED_OCTET && octets[RESERVED_OCTET] != 0 {
+            return None;
+        }
+        let mut ipv4 = [0u8; 4];
+        for (octet, position) in ipv4.iter_mut().zip(self.embedded_positions()) {
+            *octet = octets[position];
+        }
+        Some(Ipv4Addr::from(ipv4))
+    }
+
+    /// Returns the positions of the octets of the embedded IPv4 address.
+    fn embedded_positions(&self) -> impl Iterator<Item = usize> {
+        (usize::from(self.length / 8)..16)
+            .filter(|position| *position != RESERVED_OCTET)
+            .take(4)
+    }
+}
+
+impl fmt::Display for Nat64Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.length)
+    }
+}
+
+/// Returns the prefix that the AAAA records of [`DISCOVERY_HOSTNAME`] were synthesized with.
+fn prefix_from_discovery(addresses: &[Ipv6Addr]) -> Option<Nat64Prefix> {
+    addresses.iter().find_map(|address| {
+        PREFIX_LENGTHS.iter().find_map(|length| {
+            let prefix = Nat64Prefix::new(*address, *length)?;
+            let embedded = prefix.extract(*address)?;
+            if DISCOVERY_ADDRESSES.contains(&embedded) {
+                Some(prefix)
+            } else {
+                None
+            }
+        })
+    })
+}
+
+/// Discovers the NAT64 prefix of the current network by looking up the AAAA records of
+/// [`DISCOVERY_HOSTNAME`] using the system resolver. Returns `None` if the network has no DNS64
+/// resolver.
+pub async fn discover_prefix() -> Option<Nat64Prefix> {
+    let addresses: Vec<Ipv6Addr> = lookup_ipv6(DISCOVERY_HOSTNAME, 0)
+        .await
+        .into_iter()
+        .filter_map(|address| match address.ip() {
+            IpAddr::V6(address) => Some(address),
+            IpAddr::V4(_) => None,
+        })
+        .collect();
+    let prefix = prefix_from_discovery(&addresses);
+    match prefix {
+        Some(prefix) => log::debug!("Discovered NAT64 prefix {}", prefix),
+        None => log::debug!("No NAT64 prefix was discovered"),
+    }
+    prefix
+}
+
+/// Looks up the IPv6 addresses of `hostname` using the system resolver.
+pub async fn lookup_ipv6(hostname: &str, port: u16) -> Vec<SocketAddr> {
+    match tokio::time::timeout(DISCOVERY_TIMEOUT, tokio::net::lookup_host((hostname, port))).await {
+        Ok(Ok(addresses)) => addresses.filter(SocketAddr::is_ipv6).collect(),
+        Ok(Err(error)) => {
+            log::debug!("Failed to look up {}: {}", hostname, error);
+            vec![]
+        }
+        Err(_) => {
+            log::debug!("Timed out looking up {}", hostname);
+            vec![]
+        }
+    }
+}
+
+/// Returns whether the system has a route to `address`. No traffic is sent.
+pub fn is_routable(address: IpAddr) -> bool {
+    let bind_address: IpAddr = match address {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    UdpSocket::bind((bind_address, 0))
+        .and_then(|socket| socket.connect((address, 443)))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_synthesize() {
+        // Examples from section 2.4 of RFC 6052
+        let ipv4 = Ipv4Addr::new(192, 0, 2, 33);
+        let cases = [
+            ("2001:db8::", 32, "2001:db8:c000:221::"),
+            ("2001:db8:100::", 40, "2001:db8:1c0:2:21::"),
+            ("2001:db8:122::", 48, "2001:db8:122:c000:2:2100::"),
+            ("2001:db8:122:300::", 56, "2001:db8:122:3c0:0:221::"),
+            ("2001:db8:122:344::", 64, "2001:db8:122:344:c0:2:2100:0"),
+            ("2001:db8:122:344::", 96, "2001:db8:122:344::192.0.2.33"),
+        ];
+        for (prefix, length, expected) in cases {
+            let prefix = Nat64Prefix::new(prefix.parse().unwrap(), length).unwrap();
+            let expected: Ipv6Addr = expected.parse().unwrap();
+            assert_eq!(prefix.synthesize(ipv4), expected, "prefix {}", prefix);
+            assert_eq!(prefix.extract(expected), Some(ipv4), "prefix {}", prefix);
+        }
+
+        assert_eq!(
+            Nat64Prefix::WELL_KNOWN.synthesize(ipv4),
+            "64:ff9b::192.0.2.33".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_prefix() {
+        assert_eq!(Nat64Prefix::new(Ipv6Addr::UNSPECIFIED, 33), None);
+
+        // Bits after the prefix are ignored
+        let prefix = Nat64Prefix::new("2001:db8:ffff::1".parse().unwrap(), 32).unwrap();
+        assert_eq!(prefix.to_string(), "2001:db8::/32");
+
+        // Addresses with another prefix, or with the reserved octet set, have no IPv4 address
+        assert_eq!(prefix.extract("2001:db9:c000:221::".parse().unwrap()), None);
+        assert_eq!(
+            prefix.extract("2001:db8:c000:221:100::".parse().unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_prefix_from_discovery() {
+        assert_eq!(
+            prefix_from_discovery(&["64:ff9b::192.0.0.170".parse().unwrap()]),
+            Some(Nat64Prefix::WELL_KNOWN)
+        );
+        assert_eq!(
+            prefix_from_discovery(&["2001:db8:122:344:c0:0:aa00:0".parse().unwrap()]),
+            Nat64Prefix::new("2001:db8:122:344::".parse().unwrap(), 64)
+        );
+        assert_eq!(
+            prefix_from_discovery(&[
+                "2001:db8::1".parse().unwrap(),
+                "2001:db8:c000:ab::".parse().unwrap(),
+            ]),
+            Nat64Prefix::new("2001:db8::".parse().unwrap(), 32)
+        );
+        assert_eq!(
+            prefix_from_discovery(&["2001:db8::1".parse().unwrap()]),
+            None
+        );
+        assert_eq!(prefix_from_discovery(&[]), None);
+    }
+}
@@ -0,0 +1,656 @@
+//! The API client that connects to the API itself, using hyper and tokio.
+
+#[cfg(target_os = "android")]
+use crate::SocketBypassRequest;
+use crate::{
+    address_cache,
+    availability::{self, ApiAvailability, ApiAvailabilityHandle},
+    endpoints, models,
+    proxy::ApiConnectionMode,
+    rest,
+    traffic_stats::{ApiTrafficStats, TrafficSnapshot},
+    AddressCache, AppVersionResponse, ConnectionListener, UploadProgress,
+};
+use bytes::Bytes;
+use chrono::{offset::Utc, DateTime};
+#[cfg(target_os = "android")]
+use futures::channel::mpsc;
+use futures::Stream;
+use hyper::{header, StatusCode};
+use mullvad_types::{
+    account::{AccountToken, VoucherSubmission},
+    version::AppVersion,
+};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::Path,
+    sync::RwLock,
+    time::Duration,
+};
+use talpid_types::{net::wireguard, ErrorExt};
+
+pub const API_IP_CACHE_FILENAME: &str = "api-ip-address.txt";
+
+/// File in the cache directory that contains the NAT64 prefix used to reach the API, if any.
+pub const API_NAT64_FILENAME: &str = "api-nat64.txt";
+
+/// File in the cache directory that contains the API traffic counters.
+pub const API_TRAFFIC_STATS_FILENAME: &str = "api-traffic.json";
+
+lazy_static::lazy_static! {
+    static ref API: RwLock<ApiEndpoint> = RwLock::new(ApiEndpoint::get());
+}
+
+/// Returns the endpoint that the API is currently reached at.
+pub(crate) fn api_endpoint() -> ApiEndpoint {
+    API.read().unwrap().clone()
+}
+
+/// Overrides the API endpoint for requests created after this call. Requests built by a
+/// `MullvadRestHandle` follow the new endpoint, but existing connections are kept until the
+/// request service is reset. The address cache is bypassed while the endpoint is overridden.
+///
+/// This fails unless the crate is built with the `api-override` feature.
+pub fn set_api_endpoint(host: String, addr: SocketAddr) -> Result<(), Error> {
+    if !cfg!(feature = "api-override") {
+        return Err(Error::ApiOverrideNotAllowed);
+    }
+    API.write().unwrap().set_override(host, addr);
+    Ok(())
+}
+
+/// A hostname and socketaddr to reach the Mullvad REST API over.
+#[derive(Clone)]
+struct ApiEndpoint {
+    host: String,
+    addr: SocketAddr,
+    disable_address_cache: bool,
+}
+
+impl ApiEndpoint {
+    /// Returns the endpoint to connect to the API over.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `MULLVAD_API_ADDR` has invalid contents or if only one of
+    /// `MULLVAD_API_ADDR` or `MULLVAD_API_HOST` has been set but not the other.
+    fn get() -> ApiEndpoint {
+        const API_HOST_DEFAULT: &str = "api.mullvad.net";
+        const API_IP_DEFAULT: IpAddr = IpAddr::V4(Ipv4Addr::new(193, 138, 218, 78));
+        const API_PORT_DEFAULT: u16 = 443;
+
+        fn read_var(key: &'static str) -> Option<String> {
+            use std::env;
+            match env::var(key) {
+                Ok(v) => Some(v),
+                Err(env::VarError::NotPresent) => None,
+                Err(env::VarError::NotUnicode(_)) => panic!("{} does not contain valid UTF-8", key),
+            }
+        }
+
+        let host_var = read_var("MULLVAD_API_HOST");
+        let address_var = read_var("MULLVAD_API_ADDR");
+
+        let mut api = ApiEndpoint {
+            host: API_HOST_DEFAULT.to_owned(),
+            addr: SocketAddr::new(API_IP_DEFAULT, API_PORT_DEFAULT),
+            disable_address_cache: false,
+        };
+
+        if cfg!(feature = "api-override") {
+            match (host_var, address_var) {
+                (None, None) => (),
+                (Some(_), None) => panic!("MULLVAD_API_HOST is set, but not MULLVAD_API_ADDR"),
+                (None, Some(_)) => panic!("MULLVAD_API_ADDR is set, but not MULLVAD_API_HOST"),
+                (Some(user_host), Some(user_addr)) => {
+                    let user_addr = user_addr
+                        .parse()
+                        .expect("MULLVAD_API_ADDR is not a valid socketaddr");
+                    api.set_override(user_host, user_addr);
+                }
+            }
+        } else {
+            if host_var.is_some() || address_var.is_some() {
+                log::warn!(
+                    "MULLVAD_API_HOST and MULLVAD_API_ADDR are ignored in production builds"
+                );
+            }
+        }
+        api
+    }
+
+    fn set_override(&mut self, host: String, addr: SocketAddr) {
+        log::debug!("Overriding API. Using {} at {}", host, addr);
+        self.host = host;
+        self.addr = addr;
+        self.disable_address_cache = true;
+    }
+}
+
+/// A type that helps with the creation of RPC connections.
+pub struct MullvadRpcRuntime {
+    handle: tokio::runtime::Handle,
+    pub address_cache: AddressCache,
+    api_availability: availability::ApiAvailability,
+    connection_listener: Option<ConnectionListener>,
+    traffic_stats: ApiTrafficStats,
+    #[cfg(target_os = "android")]
+    socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+}
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to construct a rest client")]
+    RestError(#[error(source)] rest::Error),
+
+    #[error(display = "Failed to load address cache")]
+    AddressCacheError(#[error(source)] address_cache::Error),
+
+    #[error(display = "API availability check failed")]
+    ApiCheckError(#[error(source)] availability::Error),
+
+    #[error(display = "The API endpoint can only be overridden in builds with api-override")]
+    ApiOverrideNotAllowed,
+}
+
+/// Closure that receives the next API (real or proxy) endpoint to use for `api.mullvad.net`.
+/// It should return a future that determines whether to reject the new endpoint or not.
+pub trait ApiEndpointUpdateCallback: Fn(SocketAddr) -> Self::AcceptedNewEndpoint {
+    type AcceptedNewEndpoint: Future<Output = bool> + Send;
+}
+
+impl<U, T: Future<Output = bool> + Send> ApiEndpointUpdateCallback for U
+where
+    U: Fn(SocketAddr) -> T,
+{
+    type AcceptedNewEndpoint = T;
+}
+
+impl MullvadRpcRuntime {
+    /// Create a new `MullvadRpcRuntime`.
+    pub fn new(handle: tokio::runtime::Handle) -> Result<Self, Error> {
+        Self::new_inner(
+            handle,
+            ApiTrafficStats::default(),
+            #[cfg(target_os = "android")]
+            None,
+        )
+    }
+
+    fn new_inner(
+        handle: tokio::runtime::Handle,
+        traffic_stats: ApiTrafficStats,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> Result<Self, Error> {
+        Ok(MullvadRpcRuntime {
+            handle,
+            address_cache: AddressCache::new(None, false)?,
+            api_availability: ApiAvailability::new(availability::State::default()),
+            connection_listener: None,
+            traffic_stats,
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
+        })
+    }
+
+    /// Create a new `MullvadRpcRuntime` using the specified directories.
+    /// Try to use the cache directory first, and fall back on the bundled address otherwise.
+    /// If `doh_fallback` is set, the API host may be looked up using DNS-over-HTTPS when the
+    /// cached addresses stop working.
+    pub async fn with_cache(
+        cache_dir: &Path,
+        write_changes: bool,
+        doh_fallback: bool,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> Result<Self, Error> {
+        let handle = tokio::runtime::Handle::current();
+
+        let traffic_stats_file = cache_dir.join(API_TRAFFIC_STATS_FILENAME);
+        let traffic_stats = ApiTrafficStats::load(&traffic_stats_file).await;
+        if write_changes {
+            traffic_stats.spawn_persist_task(traffic_stats_file);
+        }
+
+        if api_endpoint().disable_address_cache {
+            return Self::new_inner(
+                handle,
+                traffic_stats,
+                #[cfg(target_os = "android")]
+                socket_bypass_tx,
+            );
+        }
+
+        let cache_file = cache_dir.join(API_IP_CACHE_FILENAME);
+        let write_file = if write_changes {
+            Some(cache_file.clone().into_boxed_path())
+        } else {
+            None
+        };
+
+        let cached = AddressCache::from_file(&cache_file, write_file.clone(), doh_fallback).await;
+        let address_cache = match cached {
+            Ok(cache) => cache,
+            Err(error) => {
+                if cache_file.exists() {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(
+                            "Failed to load cached API addresses. Falling back on bundled address"
+                        )
+                    );
+                }
+                AddressCache::new(write_file, doh_fallback)?
+            }
+        };
+
+        Ok(MullvadRpcRuntime {
+            handle,
+            address_cache,
+            api_availability: ApiAvailability::new(availability::State::default()),
+            connection_listener: None,
+            traffic_stats,
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
+        })
+    }
+
+    /// Creates a new request service and returns a handle to it.
+    async fn new_request_service<T: Stream<Item = ApiConnectionMode> + Unpin + Send + 'static>(
+        &self,
+        sni_hostname: Option<String>,
+        proxy_provider: T,
+        new_address_callback: impl ApiEndpointUpdateCallback + Send + Sync + 'static,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> rest::RequestServiceHandle {
+        let service_handle = rest::RequestService::new(
+            sni_hostname,
+            self.api_availability.handle(),
+            self.address_cache.clone(),
+            proxy_provider,
+            new_address_callback,
+            self.connection_listener.clone(),
+            self.traffic_stats.clone(),
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
+        )
+        .await;
+        service_handle
+    }
+
+    /// Returns a request factory initialized to create requests for the master API
+    pub async fn mullvad_rest_handle<
+        T: Stream<Item = ApiConnectionMode> + Unpin + Send + 'static,
+    >(
+        &self,
+        proxy_provider: T,
+        new_address_callback: impl ApiEndpointUpdateCallback + Send + Sync + 'static,
+    ) -> rest::MullvadRestHandle {
+        // The SNI hostname is taken from the URI of each request, so that it follows the API
+        // endpoint if it is overridden.
+        let service = self
+            .new_request_service(
+                None,
+                proxy_provider,
+                new_address_callback,
+                #[cfg(target_os = "android")]
+                self.socket_bypass_tx.clone(),
+            )
+            .await;
+        let factory = rest::RequestFactory::for_api(Some("app".to_owned()));
+
+        rest::MullvadRestHandle::new(
+            service,
+            factory,
+            self.address_cache.clone(),
+            self.availability_handle(),
+        )
+    }
+
+    /// Returns a new request service handle
+    pub async fn rest_handle(&mut self) -> rest::RequestServiceHandle {
+        self.new_request_service(
+            None,
+            ApiConnectionMode::Direct.into_repeat(),
+            |_| async { true },
+            #[cfg(target_os = "android")]
+            None,
+        )
+        .await
+    }
+
+    /// Sets a callback that is invoked whenever a request service created after this call
+    /// establishes a new connection. The callback is invoked on a separate task.
+    pub fn set_connection_listener(&mut self, listener: ConnectionListener) {
+        self.connection_listener = Some(listener);
+    }
+
+    pub fn handle(&mut self) -> &mut tokio::runtime::Handle {
+        &mut self.handle
+    }
+
+    pub fn availability_handle(&self) -> ApiAvailabilityHandle {
+        self.api_availability.handle()
+    }
+
+    /// Returns the number of bytes that request services created by this runtime have sent and
+    /// received, including traffic counted by previous runs if the runtime uses a cache directory.
+    pub fn api_traffic_stats(&self) -> TrafficSnapshot {
+        self.traffic_stats.snapshot()
+    }
+}
+
+#[derive(Clone)]
+pub struct AccountsProxy {
+    handle: rest::MullvadRestHandle,
+}
+
+/// How many times an account request that failed with a network error is sent again. Only
+/// requests with idempotent methods are retried.
+const ACCOUNT_REQUEST_RETRIES: usize = 2;
+
+impl AccountsProxy {
+    pub fn new(handle: rest::MullvadRestHandle) -> Self {
+        Self { handle }
+    }
+
+    pub fn get_expiry(
+        &self,
+        account: AccountToken,
+    ) -> impl Future<Output = Result<DateTime<Utc>, rest::Error>> {
+        let response = self.send_request(endpoints::get_expiry(account));
+        async move {
+            let account: models::AccountResponse =
+                rest::deserialize_checked_body(response.await?).await?;
+            Ok(account.expires)
+        }
+    }
+
+    pub fn create_account(&mut self) -> impl Future<Output = Result<AccountToken, rest::Error>> {
+        let response = self.create_account_detailed();
+        async move { Ok(response.await?.0) }
+    }
+
+    /// Creates a new account and returns its token along with its initial expiry date.
+    pub fn create_account_detailed(
+        &mut self,
+    ) -> impl Future<Output = Result<(AccountToken, DateTime<Utc>), rest::Error>> {
+        let response = self.send_request(endpoints::create_account());
+
+        async move {
+            let account: models::AccountResponse =
+                rest::deserialize_checked_body(response.await?).await?;
+            Ok((account.token, account.expires))
+        }
+    }
+
+    pub fn submit_voucher(
+        &mut self,
+        account_token: AccountToken,
+        voucher_code: String,
+    ) -> impl Future<Output = Result<VoucherSubmission, rest::Error>> {
+        let response = endpoints::submit_voucher(account_token, voucher_code)
+            .map(|descriptor| self.send_request(descriptor));
+
+        async move { rest::deserialize_checked_body(response?.await?).await }
+    }
+
+    pub fn get_www_auth_token(
+        &self,
+        account: AccountToken,
+    ) -> impl Future<Output = Result<String, rest::Error>> {
+        let response = self.send_request(endpoints::www_auth_token(account));
+
+        async move {
+            let response: models::AuthTokenResponse =
+                rest::deserialize_checked_body(response.await?).await?;
+            Ok(response.auth_token)
+        }
+    }
+
+    /// Sends the request described by `descriptor`, and sends it again after network errors if
+    /// its method is idempotent.
+    fn send_request(
+        &self,
+        descriptor: endpoints::RequestDescriptor,
+    ) -> impl Future<Output = Result<rest::Response, rest::Error>> {
+        let handle = self.handle.clone();
+        async move {
+            let response = handle
+                .request_with_retries(
+                    |factory| factory.build_request(&descriptor),
+                    ACCOUNT_REQUEST_RETRIES,
+                )
+                .await?;
+            rest::parse_rest_response(response, descriptor.expected_statuses).await
+        }
+    }
+}
+
+pub struct ProblemReportProxy {
+    handle: rest::MullvadRestHandle,
+}
+
+/// Deadline for sending a problem report. Large reports take a long time to send over slow links.
+const PROBLEM_REPORT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Problem report uploads that make no progress for this long are aborted, so that they can be
+/// retried long before `PROBLEM_REPORT_TIMEOUT`.
+const PROBLEM_REPORT_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl ProblemReportProxy {
+    pub fn new(handle: rest::MullvadRestHandle) -> Self {
+        Self { handle }
+    }
+
+    pub fn problem_report(
+        &self,
+        email: &str,
+        message: &str,
+        log: &str,
+        metadata: &BTreeMap<String, String>,
+    ) -> impl Future<Output = Result<(), rest::Error>> {
+        let request = Self::serialize_problem_report(email, message, log, metadata)
+            .map(|body| self.send_problem_report(body, UploadProgress::new()));
+        async move { request?.await }
+    }
+
+    /// Serializes a problem report, so that it can be sent several times without being
+    /// serialized again.
+    pub fn serialize_problem_report(
+        email: &str,
+        message: &str,
+        log: &str,
+        metadata: &BTreeMap<String, String>,
+    ) -> Result<Bytes, rest::Error> {
+        let report = models::ProblemReport {
+            address: email,
+            message,
+            log,
+            metadata,
+        };
+        Ok(Bytes::from(serde_json::to_vec(&report)?))
+    }
+
+    /// Sends a problem report serialized by `serialize_problem_report`, and reports how much of
+    /// it has been sent to `progress`. Fails with `rest::Error::Stalled` if the upload stops
+    /// making progress.
+    pub fn send_problem_report(
+        &self,
+        body: Bytes,
+        progress: UploadProgress,
+    ) -> impl Future<Output = Result<(), rest::Error>> {
+        let service = self.handle.service.clone();
+        let request = self.handle.factory.post("/v1/problem-report");
+
+        async move {
+            let mut request = request?;
+            request.add_header(header::CONTENT_TYPE, "application/json")?;
+            request.set_tracked_body(body, progress, PROBLEM_REPORT_STALL_TIMEOUT);
+            request.set_timeout(PROBLEM_REPORT_TIMEOUT);
+            let response = service.request(request).await?;
+            rest::parse_rest_response(response, &[StatusCode::NO_CONTENT]).await?;
+            Ok(())
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AppVersionProxy {
+    handle: rest::MullvadRestHandle,
+}
+
+impl AppVersionProxy {
+    pub fn new(handle: rest::MullvadRestHandle) -> Self {
+        Self { handle }
+    }
+
+    pub fn version_check(
+        &self,
+        app_version: AppVersion,
+        platform: &str,
+        platform_version: String,
+    ) -> impl Future<Output = Result<AppVersionResponse, rest::Error>> {
+        let response = rest::send_descriptor(
+            &self.handle.factory,
+            self.handle.service.clone(),
+            endpoints::version_check(&app_version, platform, platform_version),
+        );
+
+        async move { rest::deserialize_checked_body(response.await?).await }
+    }
+}
+
+#[derive(Clone)]
+pub struct WireguardKeyProxy {
+    handle: rest::MullvadRestHandle,
+}
+
+impl WireguardKeyProxy {
+    pub fn new(handle: rest::MullvadRestHandle) -> Self {
+        Self { handle }
+    }
+
+    pub fn push_wg_key(
+        &mut self,
+        account_token: AccountToken,
+        public_key: wireguard::PublicKey,
+        timeout: Option<std::time::Duration>,
+    ) -> impl Future<Output = Result<mullvad_types::wireguard::AssociatedAddresses, rest::Error>> + 'static
+    {
+        let service = self.handle.service.clone();
+        let descriptor = endpoints::push_wg_key(account_token, public_key);
+
+        let request = descriptor
+            .map_err(rest::Error::from)
+            .and_then(|descriptor| {
+                let request = self.handle.factory.build_request(&descriptor)?;
+                Ok((request, descriptor.expected_statuses))
+            });
+        async move {
+            let (mut request, expected_statuses) = request?;
+            if let Some(timeout) = timeout {
+                request.set_timeout(timeout);
+            }
+            // The key is required to connect to WireGuard relays.
+            request.set_essential(true);
+            let response = service.request(request).await?;
+            rest::deserialize_body(rest::parse_rest_response(response, expected_statuses).await?)
+                .await
+        }
+    }
+
+    pub async fn replace_wg_key(
+        &mut self,
+        account_token: AccountToken,
+        old: wireguard::PublicKey,
+        new: wireguard::PublicKey,
+    ) -> Result<mullvad_types::wireguard::AssociatedAddresses, rest::Error> {
+        let response = rest::send_descriptor(
+            &self.handle.factory,
+            self.handle.service.clone(),
+            endpoints::replace_wg_key(account_token, old, new)?,
+        )
+        .await?;
+
+        rest::deserialize_body(response).await
+    }
+
+    pub async fn get_wireguard_key(
+        &mut self,
+        account_token: AccountToken,
+        key: &wireguard::PublicKey,
+    ) -> Result<mullvad_types::wireguard::AssociatedAddresses, rest::Error> {
+        let response = rest::send_descriptor(
+            &self.handle.factory,
+            self.handle.service.clone(),
+            endpoints::get_wg_key(account_token, key),
+        )
+        .await?;
+
+        rest::deserialize_body(response).await
+    }
+
+    pub fn remove_wireguard_key(
+        &mut self,
+        account_token: AccountToken,
+        key: wireguard::PublicKey,
+    ) -> impl Future<Output = Result<(), rest::Error>> {
+        let future = rest::send_descriptor(
+            &self.handle.factory,
+            self.handle.service.clone(),
+            endpoints::remove_wg_key(account_token, &key),
+        );
+        async move {
+            let _ = future.await?;
+            Ok(())
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ApiProxy {
+    handle: rest::MullvadRestHandle,
+}
+
+impl ApiProxy {
+    pub fn new(handle: rest::MullvadRestHandle) -> Self {
+        Self { handle }
+    }
+
+    pub async fn get_api_addrs(&self) -> Result<Vec<SocketAddr>, rest::Error> {
+        let response = rest::send_descriptor(
+            &self.handle.factory,
+            self.handle.service.clone(),
+            endpoints::api_addrs(),
+        )
+        .await?;
+
+        rest::deserialize_body(response).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_api_endpoint_override() {
+        let mut api = api_endpoint();
+        let addr: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        api.set_override("api.example.com".to_owned(), addr);
+        assert_eq!(api.host, "api.example.com");
+        assert_eq!(api.addr, addr);
+        assert!(api.disable_address_cache);
+    }
+
+    #[cfg(not(feature = "api-override"))]
+    #[test]
+    fn test_api_override_rejected() {
+        let original = api_endpoint();
+        let result = set_api_endpoint("api.example.com".to_owned(), original.addr);
+        assert!(matches!(result, Err(Error::ApiOverrideNotAllowed)));
+        assert_eq!(api_endpoint().host, original.host);
+    }
+}
@@ -26,6 +26,8 @@ pub enum ApiConnectionMode {
     Direct,
     /// Connect to the destination via a proxy.
     Proxied(ProxyConfig),
+    /// Do not connect to the destination at all. Every connection attempt fails.
+    Blocked,
 }
 
 impl fmt::Display for ApiConnectionMode {
@@ -33,6 +35,7 @@ impl fmt::Display for ApiConnectionMode {
         match self {
             ApiConnectionMode::Direct => write!(f, "unproxied"),
             ApiConnectionMode::Proxied(settings) => settings.fmt(f),
+            ApiConnectionMode::Blocked => write!(f, "blocked"),
         }
     }
 }
@@ -129,16 +132,16 @@ impl ApiConnectionMode {
         }
     }
 
-    /// Returns the remote address, or `None` for `ApiConnectionMode::Direct`.
+    /// Returns the remote address, or `None` if the mode is not `ApiConnectionMode::Proxied`.
     pub fn get_endpoint(&self) -> Option<SocketAddr> {
         match self {
             ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss)) => Some(ss.peer),
-            ApiConnectionMode::Direct => None,
+            ApiConnectionMode::Direct | ApiConnectionMode::Blocked => None,
         }
     }
 
     pub fn is_proxy(&self) -> bool {
-        *self != ApiConnectionMode::Direct
+        matches!(self, ApiConnectionMode::Proxied(_))
     }
 
     /// Convenience function that returns a stream that repeats
@@ -20,6 +20,7 @@ pub struct RelayListProxy {
 }
 
 const RELAY_LIST_TIMEOUT: Duration = Duration::from_secs(15);
+const RELAY_LIST_MAX_SIZE: usize = 16 * 1024 * 1024;
 
 impl RelayListProxy {
     /// Construct a new relay list rest client
@@ -38,6 +39,7 @@ impl RelayListProxy {
         let future = async move {
             let mut request = request?;
             request.set_timeout(RELAY_LIST_TIMEOUT);
+            request.set_max_response_size(RELAY_LIST_MAX_SIZE);
 
             if let Some(ref tag) = etag {
                 request.add_header(header::IF_NONE_MATCH, tag)?;
@@ -312,10 +314,13 @@ fn relay(relay: Relay, location: location::Location) -> relay_list::Relay {
         active: relay.active,
         owned: relay.owned,
         provider: relay.provider,
+        asn: relay.asn,
+        asn_organization: relay.asn_organization,
         weight: relay.weight,
         tunnels: Default::default(),
         bridges: Default::default(),
         location: Some(location),
+        maintenance: relay.maintenance,
     }
 }
 
@@ -343,6 +348,12 @@ struct Relay {
     ipv4_addr_in: Ipv4Addr,
     weight: u64,
     include_in_country: bool,
+    #[serde(default)]
+    asn: Option<u32>,
+    #[serde(default)]
+    asn_organization: Option<String>,
+    #[serde(default)]
+    maintenance: Option<relay_list::MaintenanceWindow>,
 }
 
 impl Relay {
@@ -1,30 +1,48 @@
 #[cfg(target_os = "android")]
 pub use crate::https_client_with_sni::SocketBypassRequest;
+pub use crate::models::ErrorResponse;
+#[cfg(any(test, feature = "test-util"))]
+use crate::test_util::FaultInjector;
 use crate::{
     address_cache::AddressCache,
     availability::ApiAvailabilityHandle,
-    https_client_with_sni::{HttpsConnectorWithSni, HttpsConnectorWithSniHandle},
+    deprecation,
+    endpoints::RequestDescriptor,
+    https_client_with_sni::{
+        ConnectionListener, HttpsConnectorWithSni, HttpsConnectorWithSniHandle,
+    },
     proxy::ApiConnectionMode,
+    server_time,
+    traffic_stats::{ApiTrafficStats, Attribution, ConnectionCounter},
+    upload_progress::{self, UploadProgress},
 };
+use bytes::Bytes;
 use futures::{
     channel::{mpsc, oneshot},
     sink::SinkExt,
-    stream::StreamExt,
+    stream::{StreamExt, TryStreamExt},
     Stream, TryFutureExt,
 };
 use hyper::{
     client::Client,
-    header::{self, HeaderValue},
-    Method, Uri,
+    header::{self, HeaderMap, HeaderValue},
+    Uri,
 };
 use std::{
+    borrow::Cow,
     future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
     str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 use talpid_types::ErrorExt;
 
-pub use hyper::StatusCode;
+pub use hyper::{Method, StatusCode};
 
 pub type Request = hyper::Request<hyper::Body>;
 pub type Response = hyper::Response<hyper::Body>;
@@ -33,9 +51,15 @@ const TIMER_CHECK_INTERVAL: Duration = Duration::from_secs(60);
 const API_IP_CHECK_DELAY: Duration = Duration::from_secs(15 * 60);
 const API_IP_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
 const API_IP_CHECK_ERROR_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Cached API addresses that the API has not returned for this long are removed.
+const API_IP_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
 
 pub type Result<T> = std::result::Result<T, Error>;
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default limit for the size of response bodies that are deserialized.
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+/// Number of bytes of an unexpected response body to include in errors.
+const UNEXPECTED_BODY_PREVIEW_SIZE: usize = 200;
 
 /// Describes all the ways a REST request can fail
 #[derive(err_derive::Error, Debug)]
@@ -55,6 +79,10 @@ pub enum Error {
     #[error(display = "Request timed out")]
     TimeoutError(#[error(source)] tokio::time::error::Elapsed),
 
+    /// No part of the request body was sent for longer than the stall timeout of the request.
+    #[error(display = "Request body upload stalled")]
+    Stalled,
+
     #[error(display = "Failed to deserialize data")]
     DeserializeError(#[error(source)] serde_json::Error),
 
@@ -68,15 +96,36 @@ pub enum Error {
     #[error(display = "Unexpected response status code {} - {}", _0, _1)]
     ApiError(StatusCode, String),
 
+    /// The API host could not be looked up.
+    #[error(display = "Failed to resolve the API host")]
+    ResolveError(#[error(source)] io::Error),
+
     /// The string given was not a valid URI.
     #[error(display = "Not a valid URI")]
     UriError(#[error(source)] http::uri::InvalidUri),
+
+    /// The response body is larger than the limit set for the request.
+    #[error(display = "Response body exceeds the limit of {} bytes", _0)]
+    ResponseTooLarge(usize),
+
+    /// The response does not contain JSON.
+    #[error(display = "Unexpected content type in response: {}", _0)]
+    UnexpectedContentType(String),
+
+    /// The amount of data downloaded during this session exceeds the configured limit.
+    #[error(display = "The data limit for this session has been exceeded")]
+    DataCapExceeded,
+
+    /// The response contains fields that the response type does not handle. Only returned by
+    /// debug builds when strict schema checking is enabled.
+    #[error(display = "API response does not match the expected schema: {}", _0)]
+    SchemaMismatch(String),
 }
 
 impl Error {
     pub fn is_network_error(&self) -> bool {
         match self {
-            Error::HyperError(_) | Error::TimeoutError(_) => true,
+            Error::HyperError(_) | Error::TimeoutError(_) | Error::Stalled => true,
             _ => false,
         }
     }
@@ -104,6 +153,74 @@ impl Error {
 
 use super::ApiEndpointUpdateCallback;
 
+/// Keeps track of how much data a `RequestService` has downloaded, and optionally limits it.
+#[derive(Debug)]
+pub struct DataUsage {
+    downloaded_bytes: AtomicU64,
+    /// `u64::MAX` means that there is no limit.
+    max_session_bytes: AtomicU64,
+}
+
+impl Default for DataUsage {
+    fn default() -> Self {
+        Self {
+            downloaded_bytes: AtomicU64::new(0),
+            max_session_bytes: AtomicU64::new(u64::MAX),
+        }
+    }
+}
+
+impl DataUsage {
+    /// Returns the number of response body bytes received so far.
+    pub fn downloaded_bytes(&self) -> u64 {
+        self.downloaded_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the data limit for the session, if there is one.
+    pub fn max_session_bytes(&self) -> Option<u64> {
+        match self.max_session_bytes.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Sets the number of bytes after which non-essential requests are rejected.
+    pub fn set_max_session_bytes(&self, max_session_bytes: Option<u64>) {
+        self.max_session_bytes
+            .store(max_session_bytes.unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    fn add_downloaded_bytes(&self, bytes: u64) {
+        self.downloaded_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn allows_request(&self, essential: bool) -> bool {
+        essential
+            || self
+                .max_session_bytes()
+                .map(|limit| self.downloaded_bytes() < limit)
+                .unwrap_or(true)
+    }
+}
+
+/// Explains why a `RequestService` is using its current connection mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModeSelectionReason {
+    /// The mode is the first one returned by the connection mode provider.
+    Initial,
+    /// A request failed with a network error using the previous mode.
+    Fallback,
+    /// The mode was set using [`RequestServiceHandle::set_connection_mode`].
+    Explicit,
+}
+
+/// The connection mode that a `RequestService` is using, and why it was selected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeSelection {
+    pub mode: ApiConnectionMode,
+    pub reason: ModeSelectionReason,
+}
+
 /// A service that executes HTTP requests, allowing for on-demand termination of all in-flight
 /// requests
 pub(crate) struct RequestService<
@@ -112,12 +229,18 @@ pub(crate) struct RequestService<
 > {
     command_tx: mpsc::Sender<RequestCommand>,
     command_rx: mpsc::Receiver<RequestCommand>,
+    connector: HttpsConnectorWithSni,
     connector_handle: HttpsConnectorWithSniHandle,
     client: hyper::Client<HttpsConnectorWithSni, hyper::Body>,
     proxy_config_provider: T,
     new_address_callback: F,
     address_cache: AddressCache,
     api_availability: ApiAvailabilityHandle,
+    data_usage: Arc<DataUsage>,
+    traffic_stats: ApiTrafficStats,
+    mode_selection: Arc<Mutex<Option<ModeSelection>>>,
+    #[cfg(target_os = "android")]
+    socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
 }
 
 impl<
@@ -132,32 +255,45 @@ impl<
         address_cache: AddressCache,
         mut proxy_config_provider: T,
         new_address_callback: F,
+        connection_listener: Option<ConnectionListener>,
+        traffic_stats: ApiTrafficStats,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> RequestServiceHandle {
         let (connector, connector_handle) = HttpsConnectorWithSni::new(
             sni_hostname,
             address_cache.clone(),
+            connection_listener,
+            traffic_stats.clone(),
             #[cfg(target_os = "android")]
             socket_bypass_tx.clone(),
         );
 
-        proxy_config_provider
-            .next()
-            .await
-            .map(|config| connector_handle.set_connection_mode(config));
+        let mode_selection = proxy_config_provider.next().await.map(|config| {
+            connector_handle.set_connection_mode(config.clone());
+            ModeSelection {
+                mode: config,
+                reason: ModeSelectionReason::Initial,
+            }
+        });
 
         let (command_tx, command_rx) = mpsc::channel(1);
-        let client = Client::builder().build(connector);
+        let client = Client::builder().build(connector.clone());
 
         let service = Self {
             command_tx,
             command_rx,
+            connector,
             connector_handle,
             client,
             proxy_config_provider,
             new_address_callback,
             address_cache,
             api_availability,
+            data_usage: Arc::new(DataUsage::default()),
+            traffic_stats,
+            mode_selection: Arc::new(Mutex::new(mode_selection)),
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
         };
         let handle = service.handle();
         tokio::spawn(service.into_future());
@@ -167,16 +303,32 @@ impl<
     fn handle(&self) -> RequestServiceHandle {
         RequestServiceHandle {
             tx: self.command_tx.clone(),
+            data_usage: self.data_usage.clone(),
+            mode_selection: self.mode_selection.clone(),
         }
     }
 
     async fn process_command(&mut self, command: RequestCommand) {
         match command {
             RequestCommand::NewRequest(request, completion_tx) => {
+                if !self.data_usage.allows_request(request.is_essential()) {
+                    log::warn!(
+                        "Rejecting request to {} due to the data limit",
+                        request.uri()
+                    );
+                    let _ = completion_tx.send(Err(Error::DataCapExceeded));
+                    return;
+                }
+
                 let mut tx = self.command_tx.clone();
+                let data_usage = self.data_usage.clone();
+                let traffic_stats = self.traffic_stats.clone();
                 let timeout = request.timeout();
+                let max_response_size = request.max_response_size();
+                let stall_detection = request.stall_detection.clone();
 
                 let hyper_request = request.into_request();
+                let request_path = RequestPath(hyper_request.uri().path().to_owned());
 
                 let api_availability = self.api_availability.clone();
                 let suspend_fut = api_availability.wait_for_unsuspend();
@@ -188,11 +340,41 @@ impl<
                 };
 
                 let future = async move {
-                    let response = tokio::time::timeout(timeout, request_future)
-                        .await
-                        .map_err(Error::TimeoutError);
+                    let stalled = async move {
+                        match stall_detection {
+                            Some((progress, stall_timeout)) => {
+                                upload_progress::stalled(progress, stall_timeout).await
+                            }
+                            None => futures::future::pending().await,
+                        }
+                    };
+                    let response = tokio::select! {
+                        response = tokio::time::timeout(timeout, request_future) => {
+                            flatten_result(response.map_err(Error::TimeoutError))
+                        }
+                        () = stalled => Err(Error::Stalled),
+                    };
 
-                    let response = flatten_result(response).map_err(|error| error.map_aborted());
+                    let response = response
+                        .map(|response| {
+                            server_time::record_response(response.headers());
+                            let attribution = Attribution::new(
+                                traffic_stats,
+                                response.extensions().get::<ConnectionCounter>().cloned(),
+                                request_path.0.clone(),
+                            );
+                            let (parts, body) = response.into_parts();
+                            let body = hyper::Body::wrap_stream(body.inspect_ok(move |chunk| {
+                                let _ = &attribution;
+                                data_usage.add_downloaded_bytes(chunk.len() as u64)
+                            }));
+                            let mut response = Response::from_parts(parts, body);
+                            let extensions = response.extensions_mut();
+                            extensions.insert(MaxResponseSize(max_response_size));
+                            extensions.insert(request_path);
+                            response
+                        })
+                        .map_err(|error| error.map_aborted());
 
                     if let Err(err) = &response {
                         if err.is_network_error() && !api_availability.get_state().is_offline() {
@@ -212,19 +394,81 @@ impl<
             RequestCommand::Reset => {
                 self.connector_handle.reset();
             }
+            RequestCommand::PinHost(result_tx) => {
+                let connector = self.connector.clone();
+                tokio::spawn(async move {
+                    let _ = result_tx.send(connector.prefetch_and_pin_host().await);
+                });
+            }
+            RequestCommand::UnpinHost => {
+                self.connector.unpin_host();
+            }
+            #[cfg(any(test, feature = "test-util"))]
+            RequestCommand::InjectFaults(faults) => {
+                self.connector.inject_faults(faults);
+            }
             RequestCommand::NextApiConfig => {
+                self.spawn_doh_lookup();
                 if let Some(new_config) = self.proxy_config_provider.next().await {
-                    let endpoint = match new_config.get_endpoint() {
-                        Some(endpoint) => endpoint,
-                        None => self.address_cache.get_address().await,
-                    };
-                    // Switch to new connection mode unless rejected by address change callback
-                    if (self.new_address_callback)(endpoint).await {
-                        self.connector_handle.set_connection_mode(new_config);
-                    }
+                    self.switch_connection_mode(new_config, ModeSelectionReason::Fallback)
+                        .await;
                 }
             }
+            RequestCommand::SetApiConfig(new_config, result_tx) => {
+                let switched = self
+                    .switch_connection_mode(new_config, ModeSelectionReason::Explicit)
+                    .await;
+                if switched {
+                    // Drop connections that use the previous mode or address
+                    self.connector_handle.reset();
+                }
+                let _ = result_tx.send(switched);
+            }
+        }
+    }
+
+    /// Switches to `new_config` unless it is rejected by the address change callback. Returns
+    /// whether the mode was switched.
+    async fn switch_connection_mode(
+        &mut self,
+        new_config: ApiConnectionMode,
+        reason: ModeSelectionReason,
+    ) -> bool {
+        let endpoint = match new_config.get_endpoint() {
+            Some(endpoint) => endpoint,
+            None => self.address_cache.get_address().await,
+        };
+        if !(self.new_address_callback)(endpoint).await {
+            return false;
         }
+        self.connector_handle
+            .set_connection_mode(new_config.clone());
+        *self.mode_selection.lock().unwrap() = Some(ModeSelection {
+            mode: new_config,
+            reason,
+        });
+        true
+    }
+
+    /// Looks up the API host using DNS-over-HTTPS in the background, if every known API address
+    /// failed and the address cache allows it. The new address is only used once the connection
+    /// mode is `Direct` again.
+    fn spawn_doh_lookup(&self) {
+        if !self.address_cache.doh_fallback().is_enabled() {
+            return;
+        }
+        let address_cache = self.address_cache.clone();
+        #[cfg(target_os = "android")]
+        let socket_bypass_tx = self.socket_bypass_tx.clone();
+
+        tokio::spawn(async move {
+            address_cache
+                .refresh_using_doh(
+                    #[cfg(target_os = "android")]
+                    socket_bypass_tx,
+                )
+                .await;
+        });
     }
 
     async fn into_future(mut self) {
@@ -239,15 +483,69 @@ impl<
 /// A handle to interact with a spawned `RequestService`.
 pub struct RequestServiceHandle {
     tx: mpsc::Sender<RequestCommand>,
+    data_usage: Arc<DataUsage>,
+    mode_selection: Arc<Mutex<Option<ModeSelection>>>,
 }
 
 impl RequestServiceHandle {
+    /// Returns the data usage counters of the corresponding RequestService.
+    pub fn data_usage(&self) -> &DataUsage {
+        &self.data_usage
+    }
+
+    /// Returns the connection mode currently used by the corresponding RequestService, and why it
+    /// was selected. This is `None` if the connection mode provider never returned a mode.
+    pub fn mode_selection(&self) -> Option<ModeSelection> {
+        self.mode_selection.lock().unwrap().clone()
+    }
+
     /// Resets the corresponding RequestService, dropping all in-flight requests.
     pub async fn reset(&self) {
         let mut tx = self.tx.clone();
         let _ = tx.send(RequestCommand::Reset).await;
     }
 
+    /// Makes the corresponding RequestService use `mode` until a request fails and the next mode
+    /// is requested from the connection mode provider. In-flight requests are dropped. Returns
+    /// whether the mode was accepted by the address change callback.
+    pub async fn set_connection_mode(&self, mode: ApiConnectionMode) -> Result<bool> {
+        let (result_tx, result_rx) = oneshot::channel();
+        let mut tx = self.tx.clone();
+        tx.send(RequestCommand::SetApiConfig(mode, result_tx))
+            .await
+            .map_err(|_| Error::SendError)?;
+        result_rx.await.map_err(|_| Error::ReceiveError)
+    }
+
+    /// Looks up the API host now, and connects to the resulting address until
+    /// [`RequestServiceHandle::unpin_host`] is called. The host is not looked up again while it is
+    /// pinned.
+    pub async fn prefetch_and_pin_host(&self) -> Result<IpAddr> {
+        let (result_tx, result_rx) = oneshot::channel();
+        let mut tx = self.tx.clone();
+        tx.send(RequestCommand::PinHost(result_tx))
+            .await
+            .map_err(|_| Error::SendError)?;
+        result_rx
+            .await
+            .map_err(|_| Error::ReceiveError)?
+            .map_err(Error::ResolveError)
+    }
+
+    /// Stops using the address pinned by [`RequestServiceHandle::prefetch_and_pin_host`].
+    pub async fn unpin_host(&self) {
+        let mut tx = self.tx.clone();
+        let _ = tx.send(RequestCommand::UnpinHost).await;
+    }
+
+    /// Injects `faults` into the connections that the corresponding RequestService opens for
+    /// requests submitted after this returns.
+    #[cfg(any(test, feature = "test-util"))]
+    pub async fn inject_faults(&self, faults: FaultInjector) {
+        let mut tx = self.tx.clone();
+        let _ = tx.send(RequestCommand::InjectFaults(faults)).await;
+    }
+
     /// Submits a `RestRequest` for exectuion to the request service.
     pub async fn request(&self, request: RestRequest) -> Result<Response> {
         let (completion_tx, completion_rx) = oneshot::channel();
@@ -268,6 +566,11 @@ pub(crate) enum RequestCommand {
     ),
     Reset,
     NextApiConfig,
+    SetApiConfig(ApiConnectionMode, oneshot::Sender<bool>),
+    PinHost(oneshot::Sender<io::Result<IpAddr>>),
+    UnpinHost,
+    #[cfg(any(test, feature = "test-util"))]
+    InjectFaults(FaultInjector),
 }
 
 /// A REST request that is sent to the RequestService to be executed.
@@ -275,9 +578,21 @@ pub(crate) enum RequestCommand {
 pub struct RestRequest {
     request: Request,
     timeout: Duration,
+    max_response_size: usize,
+    essential: bool,
     auth: Option<HeaderValue>,
+    stall_detection: Option<(UploadProgress, Duration)>,
 }
 
+/// Limit for the response body size, attached to responses by the `RequestService`.
+#[derive(Debug, Clone, Copy)]
+struct MaxResponseSize(usize);
+
+/// Path of the request that a response belongs to, attached to responses by the
+/// `RequestService`.
+#[derive(Debug, Clone)]
+struct RequestPath(String);
+
 impl RestRequest {
     /// Constructs a GET request with the given URI. Returns an error if the URI is not valid.
     pub fn get(uri: &str) -> Result<Self> {
@@ -297,7 +612,10 @@ impl RestRequest {
 
         Ok(RestRequest {
             timeout: DEFAULT_TIMEOUT,
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            essential: false,
             auth: None,
+            stall_detection: None,
             request,
         })
     }
@@ -326,6 +644,43 @@ impl RestRequest {
         self.timeout
     }
 
+    /// Sets the largest response body, in bytes, that may be deserialized.
+    pub fn set_max_response_size(&mut self, max_response_size: usize) {
+        self.max_response_size = max_response_size;
+    }
+
+    /// Retrieves the response body size limit
+    pub fn max_response_size(&self) -> usize {
+        self.max_response_size
+    }
+
+    /// Marks the request as essential. Essential requests are sent even if the data limit for
+    /// the session has been exceeded.
+    pub fn set_essential(&mut self, essential: bool) {
+        self.essential = essential;
+    }
+
+    /// Returns whether the request bypasses the data limit
+    pub fn is_essential(&self) -> bool {
+        self.essential
+    }
+
+    /// Sets the body of the request, and reports how much of it has been sent to `progress`. The
+    /// request fails with `Error::Stalled` if no part of the body is sent for `stall_timeout`
+    /// after the upload has started. The timeout of the request still applies.
+    pub fn set_tracked_body(
+        &mut self,
+        body: Bytes,
+        progress: UploadProgress,
+        stall_timeout: Duration,
+    ) {
+        self.request
+            .headers_mut()
+            .insert(header::CONTENT_LENGTH, HeaderValue::from(body.len() as u64));
+        *self.request.body_mut() = upload_progress::counting_body(body, progress.clone());
+        self.stall_detection = Some((progress, stall_timeout));
+    }
+
     pub fn add_header<T: header::IntoHeaderName>(&mut self, key: T, value: &str) -> Result<()> {
         let header_value = http::HeaderValue::from_str(value).map_err(Error::InvalidHeaderError)?;
         self.request.headers_mut().insert(key, header_value);
@@ -347,6 +702,16 @@ impl RestRequest {
     pub fn uri(&self) -> &Uri {
         self.request.uri()
     }
+
+    /// Returns the method of the request
+    pub fn method(&self) -> &Method {
+        self.request.method()
+    }
+
+    /// Returns the headers of the request
+    pub fn headers(&self) -> &HeaderMap {
+        self.request.headers()
+    }
 }
 
 impl From<Request> for RestRequest {
@@ -354,32 +719,70 @@ impl From<Request> for RestRequest {
         Self {
             request,
             timeout: DEFAULT_TIMEOUT,
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            essential: false,
             auth: None,
+            stall_detection: None,
         }
     }
 }
 
-#[derive(serde::Deserialize)]
-pub struct ErrorResponse {
-    pub code: String,
-}
-
 #[derive(Clone)]
 pub struct RequestFactory {
-    hostname: String,
+    host: RequestHost,
     path_prefix: Option<String>,
     pub timeout: Duration,
+    pub max_response_size: usize,
+}
+
+#[derive(Clone)]
+enum RequestHost {
+    Fixed {
+        hostname: String,
+        /// Headers added to every request. `None` if the hostname is not a valid header value, in
+        /// which case building requests fails.
+        default_headers: Option<HeaderMap>,
+    },
+    /// The current API host, which may be overridden at runtime.
+    Api,
 }
 
 impl RequestFactory {
     pub fn new(hostname: String, path_prefix: Option<String>) -> Self {
+        let default_headers = Self::default_headers(&hostname);
+        Self::with_host(
+            RequestHost::Fixed {
+                hostname,
+                default_headers,
+            },
+            path_prefix,
+        )
+    }
+
+    /// Returns a factory that creates requests for the API. Requests created after the API
+    /// endpoint has been overridden are sent to the new host.
+    pub fn for_api(path_prefix: Option<String>) -> Self {
+        Self::with_host(RequestHost::Api, path_prefix)
+    }
+
+    fn with_host(host: RequestHost, path_prefix: Option<String>) -> Self {
         Self {
-            hostname,
+            host,
             path_prefix,
             timeout: DEFAULT_TIMEOUT,
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
         }
     }
 
+    fn default_headers(hostname: &str) -> Option<HeaderMap> {
+        HeaderValue::from_str(hostname).ok().map(|host| {
+            let mut headers = HeaderMap::with_capacity(4);
+            headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+            headers.insert(header::HOST, host);
+            headers
+        })
+    }
+
     pub fn request(&self, path: &str, method: Method) -> Result<RestRequest> {
         self.hyper_request(path, method)
             .map(RestRequest::from)
@@ -399,17 +802,43 @@ impl RequestFactory {
     }
 
     pub fn post_json<S: serde::Serialize>(&self, path: &str, body: &S) -> Result<RestRequest> {
-        let mut request = self.hyper_request(path, Method::POST)?;
+        let json_body = serde_json::to_vec(&body)?;
+        self.json_request(path, Method::POST, json_body)
+    }
+
+    pub fn delete(&self, path: &str) -> Result<RestRequest> {
+        self.hyper_request(path, Method::DELETE)
+            .map(RestRequest::from)
+            .map(|req| self.set_request_timeout(req))
+    }
+
+    /// Creates the request described by `descriptor`.
+    pub fn build_request(&self, descriptor: &RequestDescriptor) -> Result<RestRequest> {
+        let mut request = match &descriptor.body {
+            Some(body) => {
+                self.json_request(&descriptor.path, descriptor.method.clone(), body.clone())?
+            }
+            None => self.request(&descriptor.path, descriptor.method.clone())?,
+        };
+        for (name, value) in &descriptor.headers {
+            request.add_header(*name, value)?;
+        }
+        if let Some(max_response_size) = descriptor.max_response_size {
+            request.set_max_response_size(max_response_size);
+        }
+        request.set_auth(descriptor.auth.clone())?;
+        Ok(request)
+    }
 
-        let json_body = serde_json::to_string(&body)?;
-        let body_length = json_body.as_bytes().len() as u64;
-        *request.body_mut() = json_body.into_bytes().into();
+    fn json_request(&self, path: &str, method: Method, json_body: Vec<u8>) -> Result<RestRequest> {
+        let mut request = self.hyper_request(path, method)?;
+
+        let body_length = json_body.len() as u64;
+        // Converting a `Vec` into a body does not copy it.
+        *request.body_mut() = hyper::Body::from(json_body);
 
         let headers = request.headers_mut();
-        headers.insert(
-            header::CONTENT_LENGTH,
-            HeaderValue::from_str(&body_length.to_string()).map_err(Error::InvalidHeaderError)?,
-        );
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body_length));
         headers.insert(
             header::CONTENT_TYPE,
             HeaderValue::from_static("application/json"),
@@ -418,31 +847,50 @@ impl RequestFactory {
         Ok(self.set_request_timeout(RestRequest::from(request)))
     }
 
-    pub fn delete(&self, path: &str) -> Result<RestRequest> {
-        self.hyper_request(path, Method::DELETE)
-            .map(RestRequest::from)
-            .map(|req| self.set_request_timeout(req))
-    }
-
     fn hyper_request(&self, path: &str, method: Method) -> Result<Request> {
-        let uri = self.get_uri(path)?;
-        let request = http::request::Builder::new()
+        let (hostname, default_headers) = match &self.host {
+            RequestHost::Fixed {
+                hostname,
+                default_headers,
+            } => (Cow::Borrowed(hostname.as_str()), default_headers.clone()),
+            RequestHost::Api => {
+                let hostname = super::api_endpoint().host;
+                let default_headers = Self::default_headers(&hostname);
+                (Cow::Owned(hostname), default_headers)
+            }
+        };
+        let uri = self.get_uri(&hostname, path)?;
+        let default_headers = match default_headers {
+            Some(headers) => headers,
+            None => {
+                // Let the builder produce the error for the invalid hostname.
+                return http::request::Builder::new()
+                    .method(method)
+                    .uri(uri)
+                    .header(header::HOST, &*hostname)
+                    .body(hyper::Body::empty())
+                    .map_err(Error::HttpError);
+            }
+        };
+
+        let mut request = http::request::Builder::new()
             .method(method)
             .uri(uri)
-            .header(header::ACCEPT, HeaderValue::from_static("application/json"))
-            .header(header::HOST, self.hostname.clone());
-
-        request.body(hyper::Body::empty()).map_err(Error::HttpError)
+            .body(hyper::Body::empty())
+            .map_err(Error::HttpError)?;
+        *request.headers_mut() = default_headers;
+        Ok(request)
     }
 
-    fn get_uri(&self, path: &str) -> Result<Uri> {
+    fn get_uri(&self, hostname: &str, path: &str) -> Result<Uri> {
         let prefix = self.path_prefix.as_ref().map(AsRef::as_ref).unwrap_or("");
-        let uri = format!("https://{}/{}{}", self.hostname, prefix, path);
+        let uri = format!("https://{}/{}{}", hostname, prefix, path);
         hyper::Uri::from_str(&uri).map_err(Error::UriError)
     }
 
     fn set_request_timeout(&self, mut request: RestRequest) -> RestRequest {
         request.timeout = self.timeout;
+        request.max_response_size = self.max_response_size;
         request
     }
 }
@@ -498,26 +946,106 @@ pub fn post_request_with_json<B: serde::Serialize>(
     }
 }
 
-pub async fn deserialize_body<T: serde::de::DeserializeOwned>(mut response: Response) -> Result<T> {
+/// Sends the request described by `descriptor`, and fails if the response status is not one of
+/// the expected ones.
+pub fn send_descriptor(
+    factory: &RequestFactory,
+    service: RequestServiceHandle,
+    descriptor: RequestDescriptor,
+) -> impl Future<Output = Result<Response>> {
+    let request = factory.build_request(&descriptor);
+    async move {
+        let response = service.request(request?).await?;
+        parse_rest_response(response, descriptor.expected_statuses).await
+    }
+}
+
+/// Deserializes a JSON response body. Fails if the body is larger than the limit set for the
+/// request, or if the response does not contain JSON.
+pub async fn deserialize_body<T: serde::de::DeserializeOwned>(response: Response) -> Result<T> {
+    let body = read_json_body(response).await?;
+    serde_json::from_slice(&body).map_err(Error::DeserializeError)
+}
+
+/// Like `deserialize_body`, but debug builds also compare the response to the type it is parsed
+/// into, and warn about fields that the type does not handle.
+pub async fn deserialize_checked_body<T>(response: Response) -> Result<T>
+where
+    T: serde::de::DeserializeOwned + serde::Serialize,
+{
+    let body = read_json_body(response).await?;
+    let value = serde_json::from_slice(&body).map_err(Error::DeserializeError)?;
+    #[cfg(any(debug_assertions, feature = "api-override"))]
+    crate::schema_check::check(&body, &value).map_err(Error::SchemaMismatch)?;
+    Ok(value)
+}
+
+async fn read_json_body(mut response: Response) -> Result<Vec<u8>> {
+    let max_response_size = response
+        .extensions()
+        .get::<MaxResponseSize>()
+        .map(|limit| limit.0)
+        .unwrap_or(DEFAULT_MAX_RESPONSE_SIZE);
+
     let body_length: usize = response
         .headers()
         .get(header::CONTENT_LENGTH)
         .and_then(|header_value| header_value.to_str().ok())
         .and_then(|length| length.parse::<usize>().ok())
         .unwrap_or(0);
+    if body_length > max_response_size {
+        return Err(Error::ResponseTooLarge(max_response_size));
+    }
 
     let mut body: Vec<u8> = Vec::with_capacity(body_length);
     while let Some(chunk) = response.body_mut().next().await {
         body.extend(&chunk?);
+        if body.len() > max_response_size {
+            return Err(Error::ResponseTooLarge(max_response_size));
+        }
     }
 
-    serde_json::from_slice(&body).map_err(Error::DeserializeError)
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|header_value| header_value.to_str().ok())
+        .unwrap_or("");
+    if !is_json_content_type(content_type) {
+        return Err(Error::UnexpectedContentType(format!(
+            "\"{}\", body: \"{}\"",
+            content_type,
+            body_preview(&body)
+        )));
+    }
+
+    Ok(body)
+}
+
+fn is_json_content_type(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .map(|mime_type| mime_type.trim().eq_ignore_ascii_case("application/json"))
+        .unwrap_or(false)
+}
+
+/// Returns the beginning of the body as a string, with control characters removed.
+fn body_preview(body: &[u8]) -> String {
+    let preview = &body[..std::cmp::min(body.len(), UNEXPECTED_BODY_PREVIEW_SIZE)];
+    String::from_utf8_lossy(preview)
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect()
 }
 
 pub async fn parse_rest_response(
     response: Response,
     expected_statuses: &'static [hyper::StatusCode],
 ) -> Result<Response> {
+    if let Some(RequestPath(path)) = response.extensions().get::<RequestPath>() {
+        deprecation::record_response(path, response.headers());
+    }
+
     if !expected_statuses.contains(&response.status()) {
         log::error!(
             "Unexpected HTTP status code {}, expected codes [{}]",
@@ -568,7 +1096,7 @@ impl MullvadRestHandle {
             factory,
             availability,
         };
-        if !super::API.disable_address_cache {
+        if !super::api_endpoint().disable_address_cache {
             handle.spawn_api_address_fetcher(address_cache);
         }
         handle
@@ -591,6 +1119,11 @@ impl MullvadRestHandle {
             loop {
                 interval.tick().await;
                 if next_check < Instant::now() {
+                    if super::api_endpoint().disable_address_cache {
+                        // The endpoint was overridden after the fetcher was started.
+                        next_check = next_regular_check();
+                        continue;
+                    }
                     if let Err(error) = availability.wait_background().await {
                         log::error!("Failed while waiting for API: {}", error);
                         next_check = next_error_check();
@@ -598,18 +1131,21 @@ impl MullvadRestHandle {
                     }
                     match api_proxy.clone().get_api_addrs().await {
                         Ok(new_addrs) => {
-                            if let Some(addr) = new_addrs.get(0) {
+                            if !new_addrs.is_empty() {
                                 log::debug!(
-                                    "Fetched new API address {:?}. Fetching again in {} hours",
-                                    addr,
+                                    "Fetched new API addresses {:?}. Fetching again in {} hours",
+                                    new_addrs,
                                     API_IP_CHECK_INTERVAL.as_secs() / (60 * 60)
                                 );
-                                if let Err(err) = address_cache.set_address(*addr).await {
+                                if let Err(err) = address_cache.set_addresses(&new_addrs).await {
                                     log::error!(
                                         "Failed to save newly updated API address: {}",
                                         err
                                     );
                                 }
+                                if let Err(err) = address_cache.prune_stale(API_IP_TTL).await {
+                                    log::error!("Failed to remove stale API addresses: {}", err);
+                                }
                             } else {
                                 log::error!("API returned no API addresses");
                             }
@@ -636,6 +1172,48 @@ impl MullvadRestHandle {
     pub fn factory(&self) -> &RequestFactory {
         &self.factory
     }
+
+    /// Returns the connection mode currently used for API requests, and why it was selected.
+    pub fn mode_selection(&self) -> Option<ModeSelection> {
+        self.service.mode_selection()
+    }
+
+    /// Sends the request created by `make_request`. If it fails with a network error while the
+    /// API is not known to be offline, a new request is created and sent, at most `max_retries`
+    /// times. Requests whose method is not idempotent are never sent again, since the first
+    /// attempt may have reached the API even though no response was received.
+    pub async fn request_with_retries(
+        &self,
+        mut make_request: impl FnMut(&RequestFactory) -> Result<RestRequest>,
+        max_retries: usize,
+    ) -> Result<Response> {
+        let mut retries = 0;
+        loop {
+            let request = make_request(&self.factory)?;
+            let is_idempotent = is_idempotent_method(request.method());
+            match self.service.request(request).await {
+                Err(error)
+                    if error.is_network_error()
+                        && is_idempotent
+                        && retries < max_retries
+                        && !self.availability.get_state().is_offline() =>
+                {
+                    log::debug!("{}", error.display_chain_with_msg("Retrying API request"));
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Returns whether sending a request using `method` more than once has the same effect as sending
+/// it once. Only such requests may be retried automatically.
+pub fn is_idempotent_method(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    )
 }
 
 fn flatten_result<T, E>(
@@ -646,3 +1224,548 @@ fn flatten_result<T, E>(
         Err(err) => Err(err),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response_with_body(content_type: &str, body: Vec<u8>, limit: usize) -> Response {
+        let mut response = hyper::Response::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .body(hyper::Body::from(body))
+            .unwrap();
+        response.extensions_mut().insert(MaxResponseSize(limit));
+        response
+    }
+
+    #[test]
+    fn test_deserialize_json_body() {
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        let response = response_with_body(
+            "application/json; charset=utf-8",
+            br#"{"code": "INVALID_ACCOUNT"}"#.to_vec(),
+            DEFAULT_MAX_RESPONSE_SIZE,
+        );
+        let body: ErrorResponse = runtime.block_on(deserialize_body(response)).unwrap();
+        assert_eq!(body.code, "INVALID_ACCOUNT");
+    }
+
+    #[test]
+    fn test_oversized_body() {
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        let mut body = br#"{"code": ""#.to_vec();
+        body.extend(std::iter::repeat(b'a').take(2048));
+        body.extend(br#""}"#);
+        let response = response_with_body("application/json", body, 1024);
+        let result: Result<ErrorResponse> = runtime.block_on(deserialize_body(response));
+        assert!(matches!(result, Err(Error::ResponseTooLarge(1024))));
+    }
+
+    #[test]
+    fn test_html_block_page() {
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        let mut body = b"<html>\r\n<body>\tBlocked</body>\r\n".to_vec();
+        body.extend(std::iter::repeat(b'x').take(500));
+        let response = response_with_body("text/html", body, DEFAULT_MAX_RESPONSE_SIZE);
+        let result: Result<ErrorResponse> = runtime.block_on(deserialize_body(response));
+        match result {
+            Err(Error::UnexpectedContentType(message)) => {
+                assert!(message.contains("text/html"));
+                assert!(message.contains("<html><body>Blocked</body>"));
+                assert!(!message.chars().any(|c| c.is_control()));
+                assert!(message.len() < 250);
+            }
+            _ => panic!("expected an unexpected content type error"),
+        }
+    }
+
+    #[test]
+    fn test_request_headers() {
+        #[derive(serde::Serialize)]
+        struct Body {
+            account: &'static str,
+        }
+
+        let factory = RequestFactory::new("api.example.com".to_owned(), Some("app/".to_owned()));
+
+        let request = factory.get("v1/accounts").unwrap().into_request();
+        assert_eq!(request.method(), Method::GET);
+        assert_eq!(
+            request.uri().to_string(),
+            "https://api.example.com/app/v1/accounts"
+        );
+        assert_eq!(request.headers().len(), 2);
+        assert_eq!(request.headers()[header::ACCEPT], "application/json");
+        assert_eq!(request.headers()[header::HOST], "api.example.com");
+
+        let mut request = factory
+            .post_json("v1/accounts", &Body { account: "1234" })
+            .unwrap();
+        request.set_auth(Some("token".to_owned())).unwrap();
+        let request = request.into_request();
+        assert_eq!(request.headers().len(), 5);
+        assert_eq!(request.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(request.headers()[header::CONTENT_LENGTH], "18");
+        assert_eq!(request.headers()[header::AUTHORIZATION], "Token token");
+
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        let body = runtime
+            .block_on(hyper::body::to_bytes(request.into_body()))
+            .unwrap();
+        assert_eq!(&body[..], br#"{"account":"1234"}"#);
+
+        // Headers added to one request do not leak into the defaults.
+        let request = factory.delete("v1/accounts").unwrap().into_request();
+        assert_eq!(request.headers().len(), 2);
+    }
+
+    #[test]
+    fn test_build_request_from_descriptor() {
+        let factory = RequestFactory::new("api.example.com".to_owned(), Some("app".to_owned()));
+
+        let descriptor =
+            crate::endpoints::version_check(&"2022.1".to_owned(), "linux", "Arch".to_owned());
+        let request = factory.build_request(&descriptor).unwrap();
+        assert_eq!(
+            request.max_response_size(),
+            crate::endpoints::VERSION_CHECK_MAX_SIZE
+        );
+        let request = request.into_request();
+        assert_eq!(request.method(), Method::GET);
+        assert_eq!(
+            request.uri().to_string(),
+            "https://api.example.com/app/v1/releases/linux/2022.1"
+        );
+        assert_eq!(request.headers()["M-Platform-Version"], "Arch");
+        assert!(request.headers().get(header::AUTHORIZATION).is_none());
+
+        let descriptor =
+            crate::endpoints::submit_voucher("token".to_owned(), "VOUCHER".to_owned()).unwrap();
+        let request = factory.build_request(&descriptor).unwrap().into_request();
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.headers()[header::AUTHORIZATION], "Token token");
+        assert_eq!(request.headers()[header::CONTENT_TYPE], "application/json");
+
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        let body = runtime
+            .block_on(hyper::body::to_bytes(request.into_body()))
+            .unwrap();
+        assert_eq!(&body[..], br#"{"voucher_code":"VOUCHER"}"#);
+
+        let mut descriptor = crate::endpoints::api_addrs();
+        descriptor
+            .headers
+            .push(("M-Invalid", "line\nbreak".to_owned()));
+        assert!(matches!(
+            factory.build_request(&descriptor),
+            Err(Error::InvalidHeaderError(_))
+        ));
+    }
+
+    #[test]
+    fn test_deprecation_headers_are_recorded() {
+        let mut response = hyper::Response::builder()
+            .header("sunset", "Fri, 01 Jul 2022 00:00:00 GMT")
+            .body(hyper::Body::empty())
+            .unwrap();
+        response
+            .extensions_mut()
+            .insert(RequestPath("/app/v1/deprecation-test".to_owned()));
+
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime
+            .block_on(parse_rest_response(response, &[StatusCode::OK]))
+            .unwrap();
+
+        assert!(deprecation::deprecated_endpoints()
+            .iter()
+            .any(|endpoint| endpoint.endpoint == "/app/v1/deprecation-test"
+                && endpoint.sunset.is_some()));
+    }
+
+    #[test]
+    fn test_api_request_host() {
+        let factory = RequestFactory::for_api(Some("app/".to_owned()));
+        let host = crate::api_endpoint().host;
+
+        let request = factory.get("v1/relays").unwrap().into_request();
+        assert_eq!(
+            request.uri().to_string(),
+            format!("https://{}/app/v1/relays", host)
+        );
+        assert_eq!(request.headers()[header::HOST], host.as_str());
+    }
+
+    #[test]
+    fn test_data_cap() {
+        let usage = DataUsage::default();
+        assert!(usage.allows_request(false));
+
+        usage.set_max_session_bytes(Some(1000));
+        usage.add_downloaded_bytes(600);
+        assert!(usage.allows_request(false));
+
+        usage.add_downloaded_bytes(600);
+        assert_eq!(usage.downloaded_bytes(), 1200);
+        assert!(!usage.allows_request(false));
+        assert!(usage.allows_request(true));
+
+        usage.set_max_session_bytes(None);
+        assert!(usage.allows_request(false));
+    }
+
+    #[test]
+    fn test_request_service_data_cap() {
+        use crate::availability::ApiAvailability;
+
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            // Nothing listens on this address, so requests that are sent fail immediately.
+            let address_cache = AddressCache::new(None, false).unwrap();
+            address_cache
+                .set_address("127.0.0.1:1".parse().unwrap())
+                .await
+                .unwrap();
+            let availability = ApiAvailability::new(Default::default());
+            let service = RequestService::new(
+                None,
+                availability.handle(),
+                address_cache,
+                ApiConnectionMode::Direct.into_repeat(),
+                |_| async { true },
+                None,
+                ApiTrafficStats::default(),
+                #[cfg(target_os = "android")]
+                None,
+            )
+            .await;
+            service.data_usage().set_max_session_bytes(Some(0));
+
+            let factory = RequestFactory::new(crate::api_endpoint().host, None);
+            let result = service.request(factory.get("/").unwrap()).await;
+            assert!(matches!(result, Err(Error::DataCapExceeded)));
+
+            // Essential requests are still sent
+            let mut request = factory.get("/").unwrap();
+            request.set_essential(true);
+            let error = service.request(request).await.unwrap_err();
+            assert!(error.is_network_error());
+
+            service.data_usage().set_max_session_bytes(None);
+            let error = service
+                .request(factory.get("/").unwrap())
+                .await
+                .unwrap_err();
+            assert!(error.is_network_error());
+        });
+    }
+
+    #[test]
+    fn test_request_retries() {
+        use crate::availability::ApiAvailability;
+
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            // Nothing listens on this address, so every request fails with a network error.
+            let address_cache = AddressCache::new(None, false).unwrap();
+            address_cache
+                .set_address("127.0.0.1:1".parse().unwrap())
+                .await
+                .unwrap();
+            let availability = ApiAvailability::new(Default::default());
+            let service = RequestService::new(
+                None,
+                availability.handle(),
+                address_cache.clone(),
+                ApiConnectionMode::Direct.into_repeat(),
+                |_| async { true },
+                None,
+                ApiTrafficStats::default(),
+                #[cfg(target_os = "android")]
+                None,
+            )
+            .await;
+            let handle = MullvadRestHandle::new(
+                service,
+                RequestFactory::new(crate::api_endpoint().host, None),
+                address_cache,
+                availability.handle(),
+            );
+
+            let send = |method: Method| {
+                let handle = handle.clone();
+                async move {
+                    let mut attempts = 0;
+                    let result = handle
+                        .request_with_retries(
+                            |factory| {
+                                attempts += 1;
+                                factory.request("/", method.clone())
+                            },
+                            2,
+                        )
+                        .await;
+                    assert!(result.unwrap_err().is_network_error());
+                    attempts
+                }
+            };
+
+            assert_eq!(send(Method::GET).await, 3);
+            assert_eq!(send(Method::POST).await, 1);
+
+            availability.handle().set_offline(true);
+            assert_eq!(send(Method::GET).await, 1);
+        });
+    }
+
+    #[test]
+    fn test_idempotent_methods() {
+        assert!(is_idempotent_method(&Method::GET));
+        assert!(is_idempotent_method(&Method::PUT));
+        assert!(is_idempotent_method(&Method::DELETE));
+        assert!(!is_idempotent_method(&Method::POST));
+        assert!(!is_idempotent_method(&Method::PATCH));
+    }
+
+    #[test]
+    fn test_mode_selection_after_fallback() {
+        use crate::{availability::ApiAvailability, proxy::ProxyConfig};
+        use talpid_types::net::openvpn::ShadowsocksProxySettings;
+
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            // Nothing listens on this address, so direct connections fail immediately.
+            let unreachable_address: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let address_cache = AddressCache::new(None, false).unwrap();
+            address_cache
+                .set_address(unreachable_address)
+                .await
+                .unwrap();
+
+            let proxied =
+                ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ShadowsocksProxySettings {
+                    peer: unreachable_address,
+                    password: "mullvad".to_owned(),
+                    cipher: "aes-256-gcm".to_owned(),
+                }));
+            let availability = ApiAvailability::new(Default::default());
+            let service = RequestService::new(
+                None,
+                availability.handle(),
+                address_cache,
+                futures::stream::iter(vec![ApiConnectionMode::Direct, proxied.clone()]),
+                |_| async { true },
+                None,
+                ApiTrafficStats::default(),
+                #[cfg(target_os = "android")]
+                None,
+            )
+            .await;
+
+            assert_eq!(
+                service.mode_selection(),
+                Some(ModeSelection {
+                    mode: ApiConnectionMode::Direct,
+                    reason: ModeSelectionReason::Initial,
+                })
+            );
+
+            let factory = RequestFactory::new(crate::api_endpoint().host, None);
+            assert!(service
+                .request(factory.get("/").unwrap())
+                .await
+                .unwrap_err()
+                .is_network_error());
+
+            let expected = Some(ModeSelection {
+                mode: proxied,
+                reason: ModeSelectionReason::Fallback,
+            });
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while service.mode_selection() != expected {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("Connection mode did not fall back after a network error");
+        });
+    }
+
+    #[test]
+    fn test_address_rotation_on_faults() {
+        use crate::{
+            availability::ApiAvailability,
+            test_util::{Fault, Schedule},
+        };
+
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            let cached_address: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let bundled_address = crate::api_endpoint().addr;
+            let address_cache = AddressCache::new(None, false).unwrap();
+            address_cache.set_address(cached_address).await.unwrap();
+
+            let availability = ApiAvailability::new(Default::default());
+            let service = RequestService::new(
+                None,
+                availability.handle(),
+                address_cache,
+                ApiConnectionMode::Direct.into_repeat(),
+                |_| async { true },
+                None,
+                ApiTrafficStats::default(),
+                #[cfg(target_os = "android")]
+                None,
+            )
+            .await;
+            let faults = FaultInjector::new();
+            faults.add_fault(cached_address.ip(), Fault::Reset, Schedule::Always);
+            faults.add_fault(bundled_address.ip(), Fault::Reset, Schedule::Always);
+            service.inject_faults(faults.clone()).await;
+
+            let factory = RequestFactory::new(crate::api_endpoint().host, None);
+            assert!(service
+                .request(factory.get("/").unwrap())
+                .await
+                .unwrap_err()
+                .is_network_error());
+            wait_for_fallback(&service).await;
+
+            // The only cached address failed, so the bundled address is tried next
+            assert!(service
+                .request(factory.get("/").unwrap())
+                .await
+                .unwrap_err()
+                .is_network_error());
+            assert_eq!(
+                faults.attempted_addresses(),
+                vec![cached_address.ip(), bundled_address.ip()]
+            );
+        });
+    }
+
+    #[test]
+    fn test_mode_fallback_on_dropped_connection() {
+        use crate::{
+            availability::ApiAvailability,
+            proxy::ProxyConfig,
+            test_util::{Fault, Schedule},
+        };
+        use talpid_types::net::openvpn::ShadowsocksProxySettings;
+
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            let direct_address: SocketAddr = "127.0.0.1:1".parse().unwrap();
+            let proxy_address: SocketAddr = "127.0.0.2:1".parse().unwrap();
+            let address_cache = AddressCache::new(None, false).unwrap();
+            address_cache.set_address(direct_address).await.unwrap();
+
+            let proxied =
+                ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ShadowsocksProxySettings {
+                    peer: proxy_address,
+                    password: "mullvad".to_owned(),
+                    cipher: "aes-256-gcm".to_owned(),
+                }));
+            let availability = ApiAvailability::new(Default::default());
+            let service = RequestService::new(
+                None,
+                availability.handle(),
+                address_cache,
+                futures::stream::iter(vec![ApiConnectionMode::Direct, proxied]),
+                |_| async { true },
+                None,
+                ApiTrafficStats::default(),
+                #[cfg(target_os = "android")]
+                None,
+            )
+            .await;
+            let faults = FaultInjector::new();
+            faults.add_fault(direct_address.ip(), Fault::Drop, Schedule::Always);
+            faults.add_fault(proxy_address.ip(), Fault::Reset, Schedule::Always);
+            service.inject_faults(faults.clone()).await;
+
+            let factory = RequestFactory::new(crate::api_endpoint().host, None);
+            let mut request = factory.get("/").unwrap();
+            request.set_timeout(Duration::from_millis(100));
+            assert!(matches!(
+                service.request(request).await,
+                Err(Error::TimeoutError(_))
+            ));
+            wait_for_fallback(&service).await;
+
+            assert!(service
+                .request(factory.get("/").unwrap())
+                .await
+                .unwrap_err()
+                .is_network_error());
+            // The dropped attempt may be retried with the new mode when the mode is switched
+            let mut attempts = faults.attempted_addresses();
+            attempts.dedup();
+            assert_eq!(attempts, vec![direct_address.ip(), proxy_address.ip()]);
+        });
+    }
+
+    /// Waits until `service` has switched connection modes after a failed request.
+    async fn wait_for_fallback(service: &RequestServiceHandle) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while service.mode_selection().map(|selection| selection.reason)
+                != Some(ModeSelectionReason::Fallback)
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Connection mode did not fall back after a network error");
+    }
+
+    #[test]
+    fn test_explicit_mode_selection() {
+        use crate::{availability::ApiAvailability, proxy::ProxyConfig};
+        use talpid_types::net::openvpn::ShadowsocksProxySettings;
+
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            let proxied =
+                ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ShadowsocksProxySettings {
+                    peer: "127.0.0.1:1".parse().unwrap(),
+                    password: "mullvad".to_owned(),
+                    cipher: "aes-256-gcm".to_owned(),
+                }));
+            let availability = ApiAvailability::new(Default::default());
+            let service = RequestService::new(
+                None,
+                availability.handle(),
+                AddressCache::new(None, false).unwrap(),
+                ApiConnectionMode::Direct.into_repeat(),
+                |address: SocketAddr| async move { address.port() == 1 },
+                None,
+                ApiTrafficStats::default(),
+                #[cfg(target_os = "android")]
+                None,
+            )
+            .await;
+
+            assert!(service.set_connection_mode(proxied.clone()).await.unwrap());
+            assert_eq!(
+                service.mode_selection(),
+                Some(ModeSelection {
+                    mode: proxied.clone(),
+                    reason: ModeSelectionReason::Explicit,
+                })
+            );
+
+            // Modes that are rejected by the callback are not used
+            let rejected =
+                ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ShadowsocksProxySettings {
+                    peer: "127.0.0.1:2".parse().unwrap(),
+                    password: "mullvad".to_owned(),
+                    cipher: "aes-256-gcm".to_owned(),
+                }));
+            assert!(!service.set_connection_mode(rejected).await.unwrap());
+            assert_eq!(
+                service.mode_selection().map(|selection| selection.mode),
+                Some(proxied)
+            );
+        });
+    }
+}
@@ -0,0 +1,236 @@
+//! Detects drift between API responses and the types they are deserialized into. This is only
+//! compiled into debug builds and builds with the `api-override` feature.
+
+use serde_json::Value;
+use std::{collections::HashSet, fmt, sync::Mutex};
+
+/// If this environment variable is set, schema mismatches are returned as errors instead of being
+/// logged. Intended for API integration test runs.
+const STRICT_SCHEMA_ENV_VAR: &str = "MULLVAD_API_STRICT_SCHEMA";
+
+lazy_static::lazy_static! {
+    /// Response types for which a mismatch has already been logged.
+    static ref REPORTED_TYPES: Mutex<HashSet<&'static str>> = Mutex::new(HashSet::new());
+}
+
+/// A difference between a response body and the value it was deserialized into.
+#[derive(Debug, PartialEq)]
+pub enum Mismatch {
+    /// The response contains a field that the response type does not know about.
+    UnknownField(String),
+    /// The field was parsed into a different JSON type than the one the response contained.
+    Coerced {
+        path: String,
+        received: &'static str,
+        parsed: &'static str,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::UnknownField(path) => write!(f, "unknown field \"{}\"", path),
+            Mismatch::Coerced {
+                path,
+                received,
+                parsed,
+            } => write!(
+                f,
+                "field \"{}\" received as {} but parsed as {}",
+                path, received, parsed
+            ),
+        }
+    }
+}
+
+/// Compares a response body to the serialized form of the value it was deserialized into.
+/// Mismatches are logged once per response type, or returned as an error if
+/// `MULLVAD_API_STRICT_SCHEMA` is set.
+pub fn check<T: serde::Serialize>(body: &[u8], parsed: &T) -> Result<(), String> {
+    let strict = std::env::var_os(STRICT_SCHEMA_ENV_VAR).is_some();
+    check_inner(body, parsed, strict)
+}
+
+fn check_inner<T: serde::Serialize>(body: &[u8], parsed: &T, strict: bool) -> Result<(), String> {
+    let (received, parsed) = match (
+        serde_json::from_slice::<Value>(body),
+        serde_json::to_value(parsed),
+    ) {
+        (Ok(received), Ok(parsed)) => (received, parsed),
+        _ => return Ok(()),
+    };
+
+    let mismatches = find_mismatches(&received, &parsed);
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+
+    let type_name = std::any::type_name::<T>();
+    let description = format!(
+        "{}: {}",
+        type_name,
+        mismatches
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    if strict {
+        return Err(description);
+    }
+    if should_report(type_name) {
+        log::warn!(
+            "API response does not match the expected schema. {}",
+            description
+        );
+    }
+    Ok(())
+}
+
+fn should_report(type_name: &'static str) -> bool {
+    REPORTED_TYPES
+        .lock()
+        .map(|mut reported| reported.insert(type_name))
+        .unwrap_or(false)
+}
+
+/// Returns every field in `received` that is missing from `parsed` or has a different JSON type.
+/// Fields only present in `parsed`, such as defaulted fields, are not reported.
+pub fn find_mismatches(received: &Value, parsed: &Value) -> Vec<Mismatch> {
+    let mut mismatches = vec![];
+    compare("", received, parsed, &mut mismatches);
+    mismatches
+}
+
+fn compare(path: &str, received: &Value, parsed: &Value, mismatches: &mut Vec<Mismatch>) {
+    match (received, parsed) {
+        (Value::Object(received), Value::Object(parsed)) => {
+            for (key, received_value) in received {
+                let field_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                match parsed.get(key) {
+                    Some(parsed_value) => {
+                        compare(&field_path, received_value, parsed_value, mismatches)
+                    }
+                    None => mismatches.push(Mismatch::UnknownField(field_path)),
+                }
+            }
+        }
+        (Value::Array(received), Value::Array(parsed)) => {
+            for (index, (received_value, parsed_value)) in
+                received.iter().zip(parsed.iter()).enumerate()
+            {
+                let element_path = format!("{}[{}]", path, index);
+                compare(&element_path, received_value, parsed_value, mismatches);
+            }
+        }
+        (received, parsed) => {
+            let received_kind = json_kind(received);
+            let parsed_kind = json_kind(parsed);
+            if received_kind != parsed_kind {
+                mismatches.push(Mismatch::Coerced {
+                    path: path.to_owned(),
+                    received: received_kind,
+                    parsed: parsed_kind,
+                });
+            }
+        }
+    }
+}
+
+fn json_kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(serde::Serialize)]
+    struct Response {
+        token: String,
+        expires: u64,
+    }
+
+    #[test]
+    fn test_matching_response() {
+        let received = json!({"token": "1234", "expires": 10, "devices": [{"id": 1}]});
+        let parsed = json!({"token": "1234", "expires": 10, "devices": [{"id": 1}]});
+        assert!(find_mismatches(&received, &parsed).is_empty());
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let received =
+            json!({"token": "1234", "new_field": true, "devices": [{"id": 1, "name": "a"}]});
+        let parsed = json!({"token": "1234", "devices": [{"id": 1}]});
+        assert_eq!(
+            find_mismatches(&received, &parsed),
+            vec![
+                Mismatch::UnknownField("devices[0].name".to_owned()),
+                Mismatch::UnknownField("new_field".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_retyped_fields() {
+        let received = json!({"token": 1234, "limits": {"max": "5"}});
+        let parsed = json!({"token": "1234", "limits": {"max": 5}});
+        assert_eq!(
+            find_mismatches(&received, &parsed),
+            vec![
+                Mismatch::Coerced {
+                    path: "limits.max".to_owned(),
+                    received: "string",
+                    parsed: "number",
+                },
+                Mismatch::Coerced {
+                    path: "token".to_owned(),
+                    received: "number",
+                    parsed: "string",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_defaulted_fields_are_ignored() {
+        let received = json!({"token": "1234"});
+        let parsed = json!({"token": "1234", "expires": null});
+        assert!(find_mismatches(&received, &parsed).is_empty());
+    }
+
+    #[test]
+    fn test_modes() {
+        let parsed = Response {
+            token: "1234".to_owned(),
+            expires: 10,
+        };
+        let body = br#"{"token": "1234", "expires": 10, "extra": 1}"#;
+
+        assert!(check_inner(body, &parsed, false).is_ok());
+        let error = check_inner(body, &parsed, true).unwrap_err();
+        assert!(error.contains("unknown field \"extra\""));
+
+        let body = br#"{"token": "1234", "expires": 10}"#;
+        assert!(check_inner(body, &parsed, true).is_ok());
+    }
+
+    #[test]
+    fn test_reported_once_per_type() {
+        assert!(should_report("schema_check::test::Once"));
+        assert!(!should_report("schema_check::test::Once"));
+    }
+}
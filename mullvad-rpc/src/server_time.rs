@@ -0,0 +1,69 @@
+//! Estimates the clock of the API from the `Date` header of its responses, so that times
+//! provided by the API can be compared against it even if the local clock is wrong.
+
+use chrono::{offset::Utc, DateTime, Duration};
+use hyper::header::{HeaderMap, DATE};
+use std::sync::atomic::{AtomicI64, Ordering};
+
+/// Changes of the offset by at least this many seconds are logged.
+const LOG_THRESHOLD_SECS: i64 = 60;
+
+/// Difference between the clock of the API and the local clock, in seconds.
+static OFFSET_SECS: AtomicI64 = AtomicI64::new(0);
+
+/// Returns the current time according to the API. The local time is returned until a response
+/// with a valid `Date` header has been received.
+pub fn now() -> DateTime<Utc> {
+    Utc::now() + offset()
+}
+
+/// Returns how far ahead of the local clock the clock of the API is.
+pub fn offset() -> Duration {
+    Duration::seconds(OFFSET_SECS.load(Ordering::Relaxed))
+}
+
+pub(crate) fn record_response(headers: &HeaderMap) {
+    if let Some(offset) = parse_offset(headers, Utc::now()) {
+        let previous = OFFSET_SECS.swap(offset.num_seconds(), Ordering::Relaxed);
+        if (previous - offset.num_seconds()).abs() >= LOG_THRESHOLD_SECS {
+            log::debug!(
+                "The local clock differs from the API by {} seconds",
+                offset.num_seconds()
+            );
+        }
+    }
+}
+
+/// Returns the difference between the `Date` header and `received_at`.
+fn parse_offset(headers: &HeaderMap, received_at: DateTime<Utc>) -> Option<Duration> {
+    let date = headers.get(DATE)?.to_str().ok()?;
+    let date = DateTime::parse_from_rfc2822(date.trim()).ok()?;
+    Some(date.with_timezone(&Utc) - received_at)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn headers(date: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(DATE, HeaderValue::from_static(date));
+        headers
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        let received_at: DateTime<Utc> = "2022-03-01T12:00:00Z".parse().unwrap();
+        assert_eq!(
+            parse_offset(&headers("Tue, 01 Mar 2022 12:05:00 GMT"), received_at),
+            Some(Duration::minutes(5))
+        );
+        assert_eq!(
+            parse_offset(&headers("Tue, 01 Mar 2022 11:59:30 GMT"), received_at),
+            Some(Duration::seconds(-30))
+        );
+        assert_eq!(parse_offset(&headers("yesterday"), received_at), None);
+        assert_eq!(parse_offset(&HeaderMap::new(), received_at), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Utilities for testing how API clients behave on unreliable networks.
+
+use futures::future;
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A fault that can be injected into connection attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Wait before connecting.
+    Delay(Duration),
+    /// Never complete the attempt, as if all packets were dropped.
+    Drop,
+    /// Fail the attempt immediately with `ConnectionReset`.
+    Reset,
+}
+
+/// Selects the connection attempts that a fault applies to. Attempts are counted per address,
+/// starting from zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every attempt.
+    Always,
+    /// The first `n` attempts.
+    First(usize),
+    /// Every attempt after the first `n`.
+    After(usize),
+}
+
+impl Schedule {
+    fn applies_to(&self, attempt: usize) -> bool {
+        match *self {
+            Schedule::Always => true,
+            Schedule::First(n) => attempt < n,
+            Schedule::After(n) => attempt >= n,
+        }
+    }
+}
+
+struct FaultRule {
+    address: IpAddr,
+    fault: Fault,
+    schedule: Schedule,
+}
+
+#[derive(Default)]
+struct FaultState {
+    rules: Vec<FaultRule>,
+    attempts_per_address: HashMap<IpAddr, usize>,
+    attempted_addresses: Vec<IpAddr>,
+}
+
+impl FaultState {
+    fn register_attempt(&mut self, address: IpAddr) -> Option<Fault> {
+        let attempts = self.attempts_per_address.entry(address).or_insert(0);
+        let attempt = *attempts;
+        *attempts += 1;
+        self.attempted_addresses.push(address);
+
+        self.rules
+            .iter()
+            .find(|rule| rule.address == address && rule.schedule.applies_to(attempt))
+            .map(|rule| rule.fault)
+    }
+}
+
+/// Injects faults into the connections that a `RequestService` opens to specific addresses. This
+/// is the API address when connecting directly, and the proxy address otherwise. Use
+/// [`crate::rest::RequestServiceHandle::inject_faults`] to install it. Clones share the same
+/// rules and attempt counters.
+#[derive(Clone, Default)]
+pub struct FaultInjector {
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Injects `fault` into the attempts to connect to `address` that match `schedule`. If
+    /// several rules match an attempt, the one that was added first is used.
+    pub fn add_fault(&self, address: IpAddr, fault: Fault, schedule: Schedule) {
+        self.state.lock().unwrap().rules.push(FaultRule {
+            address,
+            fault,
+            schedule,
+        });
+    }
+
+    /// Removes all faults. Attempt counters are not reset.
+    pub fn clear_faults(&self) {
+        self.state.lock().unwrap().rules.clear();
+    }
+
+    /// Returns the addresses of all connection attempts made so far, in order.
+    pub fn attempted_addresses(&self) -> Vec<IpAddr> {
+        self.state.lock().unwrap().attempted_addresses.clone()
+    }
+
+    /// Registers an attempt to connect to `address`, and applies the fault scheduled for it, if
+    /// any. The connection should only be opened if this returns `Ok`.
+    pub(crate) async fn apply(&self, address: IpAddr) -> io::Result<()> {
+        let fault = self.state.lock().unwrap().register_attempt(address);
+        match fault {
+            Some(Fault::Delay(delay)) => tokio::time::sleep(delay).await,
+            Some(Fault::Drop) => future::pending::<()>().await,
+            Some(Fault::Reset) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    format!("connection to {} reset by FaultInjector", address),
+                ))
+            }
+            None => (),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FaultInjector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultInjector").finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_schedules() {
+        assert!(Schedule::Always.applies_to(0));
+        assert!(Schedule::First(2).applies_to(1));
+        assert!(!Schedule::First(2).applies_to(2));
+        assert!(!Schedule::After(2).applies_to(1));
+        assert!(Schedule::After(2).applies_to(2));
+    }
+
+    #[test]
+    fn test_faults_per_address() {
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+        runtime.block_on(async {
+            let faulty: IpAddr = "192.0.2.1".parse().unwrap();
+            let healthy: IpAddr = "192.0.2.2".parse().unwrap();
+            let faults = FaultInjector::new();
+            faults.add_fault(faulty, Fault::Reset, Schedule::First(1));
+
+            assert_eq!(
+                faults.apply(faulty).await.unwrap_err().kind(),
+                io::ErrorKind::ConnectionReset
+            );
+            assert!(faults.apply(healthy).await.is_ok());
+            // The fault only applied to the first attempt
+            assert!(faults.apply(faulty).await.is_ok());
+            assert_eq!(faults.attempted_addresses(), vec![faulty, healthy, faulty]);
+        });
+    }
+}
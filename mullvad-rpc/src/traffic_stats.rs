@@ -0,0 +1,488 @@
+//! Counts the bytes that API connections send and receive, in total and per kind of request, so
+//! that users on metered connections can see how much data is spent on the API outside the
+//! tunnel.
+
+use hyper::client::connect::{Connected, Connection};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The counters are written to disk at most this often.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of bytes sent and received.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteCounts {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl ByteCounts {
+    fn add(&mut self, other: ByteCounts) {
+        self.sent += other.sent;
+        self.received += other.received;
+    }
+}
+
+/// The traffic counters at some point in time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficSnapshot {
+    /// Bytes sent and received by all API connections, including HTTP and TLS overhead.
+    pub total: ByteCounts,
+    /// The same bytes, by the category of the request that used the connection. See
+    /// [`path_category`]. Requests that fail before a response is received are only counted in
+    /// `total`.
+    pub by_category: BTreeMap<String, ByteCounts>,
+}
+
+/// Traffic counters shared by all request services of a `MullvadRpcRuntime`. Clones refer to the
+/// same counters.
+#[derive(Debug, Clone, Default)]
+pub struct ApiTrafficStats {
+    inner: Arc<StatsInner>,
+}
+
+#[derive(Debug, Default)]
+struct StatsInner {
+    sent: AtomicU64,
+    received: AtomicU64,
+    by_category: Mutex<BTreeMap<String, ByteCounts>>,
+    /// Whether the counters changed since they were last written to disk.
+    changed: AtomicBool,
+}
+
+impl ApiTrafficStats {
+    fn from_snapshot(snapshot: TrafficSnapshot) -> Self {
+        Self {
+            inner: Arc::new(StatsInner {
+                sent: AtomicU64::new(snapshot.total.sent),
+                received: AtomicU64::new(snapshot.total.received),
+                by_category: Mutex::new(snapshot.by_category),
+                changed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Returns the current counters.
+    pub fn snapshot(&self) -> TrafficSnapshot {
+        TrafficSnapshot {
+            total: ByteCounts {
+                sent: self.inner.sent.load(Ordering::Relaxed),
+                received: self.inner.received.load(Ordering::Relaxed),
+            },
+            by_category: self.inner.by_category.lock().unwrap().clone(),
+        }
+    }
+
+    /// Loads counters saved by a previous run. Starts from zero if there are none.
+    pub(crate) async fn load(path: &Path) -> Self {
+        match tokio::fs::read(path).await {
+            Ok(contents) => match serde_json::from_slice(&contents) {
+                Ok(snapshot) => Self::from_snapshot(snapshot),
+                Err(error) => {
+                    log::error!("Failed to parse API traffic counters: {}", error);
+                    Self::default()
+                }
+            },
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    log::error!("Failed to read API traffic counters: {}", error);
+                }
+                Self::default()
+            }
+        }
+    }
+
+    /// Writes the counters to `path` whenever they have changed, at most once every
+    /// [`PERSIST_INTERVAL`].
+    pub(crate) fn spawn_persist_task(&self, path: PathBuf) {
+        let stats = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PERSIST_INTERVAL);
+            loop {
+                interval.tick().await;
+                if stats.inner.changed.swap(false, Ordering::Relaxed) {
+                    if let Err(error) = stats.save(&path).await {
+                        log::error!("Failed to save API traffic counters: {}", error);
+                    }
+                }
+            }
+        });
+    }
+
+    async fn save(&self, path: &Path) -> io::Result<()> {
+        let contents = serde_json::to_vec(&self.snapshot())?;
+        let temp_path = path.with_extension("temp");
+        tokio::fs::write(&temp_path, contents).await?;
+        tokio::fs::rename(&temp_path, path).await
+    }
+
+    /// Counts bytes that were attributed to a request to `path`.
+    pub(crate) fn attribute(&self, path: &str, counts: ByteCounts) {
+        if counts == ByteCounts::default() {
+            return;
+        }
+        let mut by_category = self.inner.by_category.lock().unwrap();
+        by_category
+            .entry(path_category(path))
+            .or_default()
+            .add(counts);
+        self.inner.changed.store(true, Ordering::Relaxed);
+    }
+
+    fn add_sent(&self, bytes: u64) {
+        self.inner.sent.fetch_add(bytes, Ordering::Relaxed);
+        self.inner.changed.store(true, Ordering::Relaxed);
+    }
+
+    fn add_received(&self, bytes: u64) {
+        self.inner.received.fetch_add(bytes, Ordering::Relaxed);
+        self.inner.changed.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Returns the category that requests to `path` are counted under. This is the first segment of
+/// the path after the API prefix and version, such as `relays` for `/app/v1/relays`.
+pub fn path_category(path: &str) -> String {
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    let mut segment = segments.next();
+    if segment == Some("app") {
+        segment = segments.next();
+    }
+    if let Some(version) = segment {
+        if version.len() > 1
+            && version.starts_with('v')
+            && version[1..].bytes().all(|byte| byte.is_ascii_digit())
+        {
+            segment = segments.next();
+        }
+    }
+    segment.unwrap_or("other").to_owned()
+}
+
+/// Bytes that a single connection has sent and received. Attached to every response that is
+/// received over the connection, so that the bytes can be attributed to the request.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionCounter {
+    inner: Arc<ConnectionCounterInner>,
+}
+
+#[derive(Debug, Default)]
+struct ConnectionCounterInner {
+    sent: AtomicU64,
+    received: AtomicU64,
+    attributed_sent: AtomicU64,
+    attributed_received: AtomicU64,
+}
+
+impl ConnectionCounter {
+    /// Returns the bytes that have not been attributed to a request yet, and marks them as
+    /// attributed. Connections are used by one request at a time, so these belong to the request
+    /// that is currently using the connection.
+    pub(crate) fn take_unattributed(&self) -> ByteCounts {
+        let sent = self.inner.sent.load(Ordering::Relaxed);
+        let received = self.inner.received.load(Ordering::Relaxed);
+        let attributed_sent = self.inner.attributed_sent.swap(sent, Ordering::Relaxed);
+        let attributed_received = self
+            .inner
+            .attributed_received
+            .swap(received, Ordering::Relaxed);
+        ByteCounts {
+            sent: sent - attributed_sent,
+            received: received - attributed_received,
+        }
+    }
+}
+
+/// Attributes the unattributed bytes of a connection to a request when dropped. This is kept
+/// alive by the response body, so that bytes received while reading the body are included.
+pub(crate) struct Attribution {
+    stats: ApiTrafficStats,
+    counter: Option<ConnectionCounter>,
+    path: String,
+}
+
+impl Attribution {
+    pub(crate) fn new(
+        stats: ApiTrafficStats,
+        counter: Option<ConnectionCounter>,
+        path: String,
+    ) -> Self {
+        Self {
+            stats,
+            counter,
+            path,
+        }
+    }
+}
+
+impl Drop for Attribution {
+    fn drop(&mut self) {
+        if let Some(counter) = &self.counter {
+            self.stats
+                .attribute(&self.path, counter.take_unattributed());
+        }
+    }
+}
+
+/// Stream that counts the bytes that pass through it, both per connection and in total.
+pub(crate) struct CountingStream<S> {
+    stream: S,
+    stats: ApiTrafficStats,
+    counter: ConnectionCounter,
+}
+
+impl<S> CountingStream<S> {
+    pub(crate) fn new(stream: S, stats: ApiTrafficStats) -> Self {
+        Self {
+            stream,
+            stats,
+            counter: ConnectionCounter::default(),
+        }
+    }
+}
+
+impl<S> AsyncRead for CountingStream<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled_before = buf.filled().len();
+        let result = Pin::new(&mut self.stream).poll_read(cx, buf);
+        let bytes = (buf.filled().len() - filled_before) as u64;
+        if bytes > 0 {
+            self.counter
+                .inner
+                .received
+                .fetch_add(bytes, Ordering::Relaxed);
+            self.stats.add_received(bytes);
+        }
+        result
+    }
+}
+
+impl<S> AsyncWrite for CountingStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(bytes)) = result {
+            self.counter
+                .inner
+                .sent
+                .fetch_add(bytes as u64, Ordering::Relaxed);
+            self.stats.add_sent(bytes as u64);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+impl<S> Connection for CountingStream<S>
+where
+    S: Connection,
+{
+    fn connected(&self) -> Connected {
+        self.stream.connected().extra(self.counter.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Upper bound for the size of the request and status lines and headers in the tests.
+    const HEADER_OVERHEAD: u64 = 256;
+
+    fn run<T>(future: impl std::future::Future<Output = T>) -> T {
+        tokio::runtime::Runtime::new()
+            .expect("Failed to initialize runtime")
+            .block_on(future)
+    }
+
+    #[test]
+    fn test_path_category() {
+        assert_eq!(path_category("/app/v1/relays"), "relays");
+        assert_eq!(path_category("/app/v1/problem-report"), "problem-report");
+        assert_eq!(path_category("/accounts/v1/accounts/me"), "accounts");
+        assert_eq!(path_category("/app/v1/"), "other");
+        assert_eq!(path_category("/"), "other");
+        assert_eq!(path_category("/app/version"), "version");
+    }
+
+    #[test]
+    fn test_counting_stream() {
+        let stats = ApiTrafficStats::default();
+        let (client, mut server) = tokio::io::duplex(1024);
+        let mut stream = CountingStream::new(client, stats.clone());
+        let counter = stream.counter.clone();
+
+        run(async {
+            stream.write_all(&[0u8; 100]).await.unwrap();
+            server.read_exact(&mut [0u8; 100]).await.unwrap();
+            server.write_all(&[0u8; 300]).await.unwrap();
+            stream.read_exact(&mut [0u8; 300]).await.unwrap();
+        });
+
+        let expected = ByteCounts {
+            sent: 100,
+            received: 300,
+        };
+        assert_eq!(counter.take_unattributed(), expected);
+        assert_eq!(counter.take_unattributed(), ByteCounts::default());
+        assert_eq!(stats.snapshot().total, expected);
+    }
+
+    /// Reads an HTTP/1.1 request with a `Content-Length` body from `server`, and responds with
+    /// `payload`. Returns the size of the request body.
+    async fn respond(mut server: tokio::io::DuplexStream, payload: &[u8]) -> usize {
+        let mut request = vec![];
+        let mut buf = [0u8; 1024];
+        let (head_length, body_length) = loop {
+            let read = server.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..read]);
+            if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&request[..end]).to_lowercase();
+                let body_length = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .map(|length| length.trim().parse().unwrap())
+                    .unwrap_or(0);
+                break (end + 4, body_length);
+            }
+        };
+        while request.len() < head_length + body_length {
+            let read = server.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..read]);
+        }
+
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n",
+            payload.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(payload);
+        server.write_all(&response).await.unwrap();
+        body_length
+    }
+
+    /// Sends requests over a mock connection, and checks that the bytes attributed to each
+    /// request match its payloads within the header overhead.
+    #[test]
+    fn test_request_attribution() {
+        let stats = ApiTrafficStats::default();
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let stream = CountingStream::new(client, stats.clone());
+        let counter = stream.counter.clone();
+        let relay_list = vec![b'r'; 20_000];
+        let report = vec![b'p'; 5_000];
+
+        run(async {
+            let (mut sender, connection) = hyper::client::conn::handshake(stream).await.unwrap();
+            tokio::spawn(connection);
+            let server_task = tokio::spawn({
+                let relay_list = relay_list.clone();
+                async move {
+                    respond(server, &relay_list).await;
+                }
+            });
+
+            let request = hyper::Request::get("/app/v1/relays")
+                .body(hyper::Body::empty())
+                .unwrap();
+            let response = sender.send_request(request).await.unwrap();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body.len(), relay_list.len());
+            stats.attribute("/app/v1/relays", counter.take_unattributed());
+            server_task.await.unwrap();
+        });
+
+        // A second connection for the upload
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let stream = CountingStream::new(client, stats.clone());
+        let counter = stream.counter.clone();
+        run(async {
+            let (mut sender, connection) = hyper::client::conn::handshake(stream).await.unwrap();
+            tokio::spawn(connection);
+            let server_task = tokio::spawn(async move { respond(server, b"").await });
+
+            let request = hyper::Request::post("/app/v1/problem-report")
+                .header(hyper::header::CONTENT_LENGTH, report.len())
+                .body(hyper::Body::from(report.clone()))
+                .unwrap();
+            let response = sender.send_request(request).await.unwrap();
+            hyper::body::to_bytes(response.into_body()).await.unwrap();
+            stats.attribute("/app/v1/problem-report", counter.take_unattributed());
+            assert_eq!(server_task.await.unwrap(), report.len());
+        });
+
+        let snapshot = stats.snapshot();
+        let relays = snapshot.by_category["relays"];
+        assert!(relays.received >= relay_list.len() as u64);
+        assert!(relays.received <= relay_list.len() as u64 + HEADER_OVERHEAD);
+        assert!(relays.sent <= HEADER_OVERHEAD);
+
+        let problem_report = snapshot.by_category["problem-report"];
+        assert!(problem_report.sent >= report.len() as u64);
+        assert!(problem_report.sent <= report.len() as u64 + HEADER_OVERHEAD);
+        assert!(problem_report.received <= HEADER_OVERHEAD);
+
+        let mut attributed = relays;
+        attributed.add(problem_report);
+        assert_eq!(snapshot.total, attributed);
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("api-traffic-test-{}.json", std::process::id()));
+        let stats = ApiTrafficStats::default();
+        stats.add_sent(10);
+        stats.attribute(
+            "/app/v1/relays",
+            ByteCounts {
+                sent: 10,
+                received: 0,
+            },
+        );
+
+        let loaded = run(async {
+            stats.save(&path).await.unwrap();
+            ApiTrafficStats::load(&path).await
+        });
+        assert_eq!(loaded.snapshot(), stats.snapshot());
+
+        std::fs::remove_file(&path).unwrap();
+
+        let missing = run(ApiTrafficStats::load(&path));
+        assert_eq!(missing.snapshot(), TrafficSnapshot::default());
+    }
+}
@@ -0,0 +1,206 @@
+//! Tracks how much of a request body has been taken by the connection, so that uploads that stop
+//! making progress can be aborted long before the deadline of the request.
+
+use bytes::Bytes;
+use futures::{stream, StreamExt};
+use std::{
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Tracked bodies are split into chunks of this size, so that progress is reported while the body
+/// is being sent.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Progress of sending a request body. Clones refer to the same counters.
+#[derive(Debug, Clone, Default)]
+pub struct UploadProgress {
+    inner: Arc<ProgressInner>,
+}
+
+#[derive(Debug, Default)]
+struct ProgressInner {
+    sent_bytes: AtomicU64,
+    total_bytes: AtomicU64,
+    /// When a chunk was last taken by the connection. `None` until the upload has started.
+    last_progress: Mutex<Option<Instant>>,
+}
+
+impl UploadProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of body bytes taken by the connection so far.
+    pub fn sent_bytes(&self) -> u64 {
+        self.inner.sent_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the size of the body.
+    pub fn total_bytes(&self) -> u64 {
+        self.inner.total_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns how much of the body has been sent, in percent.
+    pub fn percentage(&self) -> u8 {
+        match self.total_bytes() {
+            0 => 0,
+            total => (self.sent_bytes().min(total) * 100 / total) as u8,
+        }
+    }
+
+    /// Resets the counters for a new attempt at sending a body of `total_bytes` bytes.
+    fn start(&self, total_bytes: u64) {
+        self.inner.sent_bytes.store(0, Ordering::Relaxed);
+        self.inner.total_bytes.store(total_bytes, Ordering::Relaxed);
+        *self.inner.last_progress.lock().unwrap() = None;
+    }
+
+    fn add_sent_bytes(&self, bytes: u64) {
+        self.inner.sent_bytes.fetch_add(bytes, Ordering::Relaxed);
+        *self.inner.last_progress.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn is_complete(&self) -> bool {
+        self.sent_bytes() >= self.total_bytes()
+    }
+
+    /// Returns how long ago progress was last made, or `None` if the upload has not started.
+    fn time_since_progress(&self) -> Option<Duration> {
+        self.inner
+            .last_progress
+            .lock()
+            .unwrap()
+            .map(|last_progress| last_progress.elapsed())
+    }
+}
+
+/// Returns a body that yields `body` in chunks, and records each chunk in `progress` when the
+/// connection takes it.
+pub(crate) fn counting_body(body: Bytes, progress: UploadProgress) -> hyper::Body {
+    progress.start(body.len() as u64);
+    let chunks: Vec<Bytes> = (0..body.len())
+        .step_by(CHUNK_SIZE)
+        .map(|start| body.slice(start..(start + CHUNK_SIZE).min(body.len())))
+        .collect();
+    hyper::Body::wrap_stream(stream::iter(chunks).map(move |chunk| {
+        progress.add_sent_bytes(chunk.len() as u64);
+        Ok::<_, Infallible>(chunk)
+    }))
+}
+
+/// Resolves once the upload has started and no part of the body has been taken for
+/// `stall_timeout`. Never resolves once the whole body has been taken, since the server may take
+/// any amount of time to respond. Time spent connecting is only limited by the request deadline.
+pub(crate) async fn stalled(progress: UploadProgress, stall_timeout: Duration) {
+    loop {
+        if progress.is_complete() {
+            return futures::future::pending().await;
+        }
+        match progress.time_since_progress() {
+            Some(idle) if idle >= stall_timeout => return,
+            Some(idle) => tokio::time::sleep(stall_timeout - idle).await,
+            None => tokio::time::sleep(stall_timeout).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hyper::body::HttpBody;
+
+    const STALL_TIMEOUT: Duration = Duration::from_millis(200);
+
+    fn run<T>(future: impl std::future::Future<Output = T>) -> T {
+        tokio::runtime::Runtime::new()
+            .expect("Failed to initialize runtime")
+            .block_on(future)
+    }
+
+    /// Reads `chunks` chunks from `body`, waiting `delay` before each one, like a throttled
+    /// connection would.
+    async fn read_throttled(body: &mut hyper::Body, chunks: usize, delay: Duration) {
+        for _ in 0..chunks {
+            tokio::time::sleep(delay).await;
+            body.data().await.unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_steady_progress() {
+        let progress = UploadProgress::new();
+        let mut body = counting_body(Bytes::from(vec![0u8; 4 * CHUNK_SIZE]), progress.clone());
+        assert_eq!(progress.total_bytes(), 4 * CHUNK_SIZE as u64);
+        assert_eq!(progress.percentage(), 0);
+
+        run(async {
+            tokio::select! {
+                () = read_throttled(&mut body, 4, STALL_TIMEOUT / 4) => (),
+                () = stalled(progress.clone(), STALL_TIMEOUT) => panic!("the upload stalled"),
+            }
+            assert!(body.data().await.is_none());
+
+            // Waiting for the response is not a stall
+            let waiting =
+                tokio::time::timeout(STALL_TIMEOUT * 2, stalled(progress.clone(), STALL_TIMEOUT));
+            assert!(waiting.await.is_err());
+        });
+        assert_eq!(progress.percentage(), 100);
+    }
+
+    #[test]
+    fn test_mid_body_stall() {
+        let progress = UploadProgress::new();
+        let mut body = counting_body(Bytes::from(vec![0u8; 4 * CHUNK_SIZE]), progress.clone());
+
+        run(async {
+            read_throttled(&mut body, 2, Duration::ZERO).await;
+            let started = Instant::now();
+            tokio::select! {
+                () = read_throttled(&mut body, 2, STALL_TIMEOUT * 10) => {
+                    panic!("the stall was not detected")
+                }
+                () = stalled(progress.clone(), STALL_TIMEOUT) => (),
+            }
+            assert!(started.elapsed() < STALL_TIMEOUT * 5);
+        });
+        assert_eq!(progress.percentage(), 50);
+    }
+
+    #[test]
+    fn test_deadline_applies_without_stall() {
+        let progress = UploadProgress::new();
+        let mut body = counting_body(Bytes::from(vec![0u8; 64 * CHUNK_SIZE]), progress.clone());
+
+        // Progress is steady, but too slow to finish before the deadline
+        let result = run(async {
+            let upload = tokio::time::timeout(
+                STALL_TIMEOUT * 2,
+                read_throttled(&mut body, 64, STALL_TIMEOUT / 4),
+            );
+            tokio::select! {
+                result = upload => result,
+                () = stalled(progress.clone(), STALL_TIMEOUT) => panic!("the upload stalled"),
+            }
+        });
+        assert!(result.is_err());
+        assert!(progress.percentage() < 100);
+    }
+
+    #[test]
+    fn test_restart_resets_progress() {
+        let progress = UploadProgress::new();
+        let mut body = counting_body(Bytes::from_static(b"report"), progress.clone());
+        run(async { body.data().await.unwrap().unwrap() });
+        assert_eq!(progress.percentage(), 100);
+
+        let _body = counting_body(Bytes::from_static(b"report"), progress.clone());
+        assert_eq!(progress.sent_bytes(), 0);
+        assert_eq!(progress.time_since_progress(), None);
+    }
+}
@@ -0,0 +1,229 @@
+//! Sends the requests in [`crate::endpoints`] through a transport that is provided by the
+//! embedder, such as `fetch` in a browser. Nothing here depends on hyper or tokio, and futures do
+//! not have to be `Send`, since `wasm32-unknown-unknown` has no threads.
+
+use crate::{endpoints::RequestDescriptor, models::ErrorResponse};
+use http::StatusCode;
+use std::{future::Future, pin::Pin};
+
+/// Future returned by [`WasmTransport::send`].
+pub type TransportFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<TransportResponse, TransportError>> + 'a>>;
+
+/// A response received by a transport.
+#[derive(Debug, Clone)]
+pub struct TransportResponse {
+    pub status: StatusCode,
+    pub body: Vec<u8>,
+}
+
+/// The transport failed to send a request or to receive its response.
+#[derive(err_derive::Error, Debug)]
+#[error(display = "Transport error: {}", _0)]
+pub struct TransportError(pub String);
+
+/// Sends requests to the API.
+pub trait WasmTransport {
+    /// Sends the request described by `request` to the API. The transport adds the host and any
+    /// path prefix to `request.path`, and sets the `Authorization` header to `Token <auth>` if
+    /// `request.auth` is set. Responses with any status are returned as they are.
+    fn send<'a>(&'a self, request: &'a RequestDescriptor) -> TransportFuture<'a>;
+}
+
+/// Describes all the ways a request sent by a [`WasmClient`] can fail.
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to send request")]
+    TransportError(#[error(source)] TransportError),
+
+    #[error(display = "Failed to deserialize data")]
+    DeserializeError(#[error(source)] serde_json::Error),
+
+    /// Unexpected response code
+    #[error(display = "Unexpected response status code {} - {}", _0, _1)]
+    ApiError(StatusCode, String),
+
+    /// The response body is larger than the limit set for the request.
+    #[error(display = "Response body exceeds the limit of {} bytes", _0)]
+    ResponseTooLarge(usize),
+}
+
+/// Sends API requests using a [`WasmTransport`], and handles their responses the same way as
+/// the native client.
+pub struct WasmClient<T> {
+    transport: T,
+}
+
+impl<T: WasmTransport> WasmClient<T> {
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    /// Sends a request and deserializes the JSON body of the response.
+    pub async fn execute<R: serde::de::DeserializeOwned>(
+        &self,
+        request: &RequestDescriptor,
+    ) -> Result<R, Error> {
+        let response = self.send(request).await?;
+        serde_json::from_slice(&response.body).map_err(Error::DeserializeError)
+    }
+
+    /// Sends a request whose response has no body.
+    pub async fn execute_empty(&self, request: &RequestDescriptor) -> Result<(), Error> {
+        self.send(request).await?;
+        Ok(())
+    }
+
+    async fn send(&self, request: &RequestDescriptor) -> Result<TransportResponse, Error> {
+        let response = self.transport.send(request).await?;
+
+        if let Some(max_response_size) = request.max_response_size {
+            if response.body.len() > max_response_size {
+                return Err(Error::ResponseTooLarge(max_response_size));
+            }
+        }
+
+        // Like the native client, only unsuccessful statuses are errors.
+        if !request.expected_statuses.contains(&response.status) {
+            log::error!(
+                "Unexpected HTTP status code {}, expected codes [{}]",
+                response.status,
+                request
+                    .expected_statuses
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            );
+            if !response.status.is_success() {
+                return Err(error_from_response(response));
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+fn error_from_response(response: TransportResponse) -> Error {
+    let message = match response.status {
+        StatusCode::NOT_FOUND => "Not found",
+        StatusCode::METHOD_NOT_ALLOWED => "Method not allowed",
+        status => {
+            return match serde_json::from_slice::<ErrorResponse>(&response.body) {
+                Ok(error) => Error::ApiError(status, error.code),
+                Err(error) => Error::DeserializeError(error),
+            }
+        }
+    };
+    Error::ApiError(response.status, message.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{endpoints, error_codes, models};
+    use futures::executor::block_on;
+    use std::{cell::RefCell, collections::VecDeque};
+
+    /// Returns the queued responses in order, and records the requests that it is asked to send.
+    #[derive(Default)]
+    struct MockTransport {
+        responses: RefCell<VecDeque<Result<TransportResponse, TransportError>>>,
+        requests: RefCell<Vec<RequestDescriptor>>,
+    }
+
+    impl MockTransport {
+        fn respond(&self, status: StatusCode, body: &str) {
+            self.responses.borrow_mut().push_back(Ok(TransportResponse {
+                status,
+                body: body.as_bytes().to_vec(),
+            }));
+        }
+    }
+
+    impl WasmTransport for MockTransport {
+        fn send<'a>(&'a self, request: &'a RequestDescriptor) -> TransportFuture<'a> {
+            self.requests.borrow_mut().push(request.clone());
+            let response = self
+                .responses
+                .borrow_mut()
+                .pop_front()
+                .expect("No response queued");
+            Box::pin(async move { response })
+        }
+    }
+
+    #[test]
+    fn test_successful_request() {
+        let client = WasmClient::new(MockTransport::default());
+        client.transport.respond(
+            StatusCode::OK,
+            r#"{"token": "1234123412341234", "expires": "2022-06-01T00:00:00Z"}"#,
+        );
+
+        let request = endpoints::get_expiry("1234123412341234".to_owned());
+        let account: models::AccountResponse = block_on(client.execute(&request)).unwrap();
+        assert_eq!(account.token, "1234123412341234");
+
+        let requests = client.transport.requests.borrow();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/v1/me");
+        assert_eq!(requests[0].auth.as_deref(), Some("1234123412341234"));
+    }
+
+    #[test]
+    fn test_error_responses() {
+        let client = WasmClient::new(MockTransport::default());
+        let request = endpoints::get_expiry("1234123412341234".to_owned());
+
+        client.transport.respond(
+            StatusCode::UNAUTHORIZED,
+            r#"{"code": "INVALID_ACCOUNT", "error": "Invalid account"}"#,
+        );
+        let result = block_on(client.execute::<models::AccountResponse>(&request));
+        match result {
+            Err(Error::ApiError(status, code)) => {
+                assert_eq!(status, StatusCode::UNAUTHORIZED);
+                assert_eq!(code, error_codes::INVALID_ACCOUNT);
+            }
+            _ => panic!("Unexpected result: {:?}", result),
+        }
+
+        client.transport.respond(StatusCode::NOT_FOUND, "");
+        let result = block_on(client.execute::<models::AccountResponse>(&request));
+        assert!(matches!(
+            result,
+            Err(Error::ApiError(StatusCode::NOT_FOUND, message)) if message == "Not found"
+        ));
+
+        client
+            .transport
+            .responses
+            .borrow_mut()
+            .push_back(Err(TransportError("offline".to_owned())));
+        let result = block_on(client.execute::<models::AccountResponse>(&request));
+        assert!(matches!(result, Err(Error::TransportError(_))));
+    }
+
+    #[test]
+    fn test_empty_and_oversized_responses() {
+        let client = WasmClient::new(MockTransport::default());
+        let key = talpid_types::net::wireguard::PublicKey::from([1u8; 32]);
+
+        client.transport.respond(StatusCode::NO_CONTENT, "");
+        let request = endpoints::remove_wg_key("1234123412341234".to_owned(), &key);
+        block_on(client.execute_empty(&request)).unwrap();
+
+        let body = format!(
+            r#"{{"supported": true, "latest": "2022.1", "padding": "{}"}}"#,
+            "x".repeat(endpoints::VERSION_CHECK_MAX_SIZE)
+        );
+        client.transport.respond(StatusCode::OK, &body);
+        let request = endpoints::version_check(&"2022.1".to_owned(), "linux", "Arch".to_owned());
+        let result = block_on(client.execute::<models::AppVersionResponse>(&request));
+        assert!(matches!(
+            result,
+            Err(Error::ResponseTooLarge(endpoints::VERSION_CHECK_MAX_SIZE))
+        ));
+    }
+}
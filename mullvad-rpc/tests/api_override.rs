@@ -0,0 +1,27 @@
+//! Overriding the API endpoint affects the whole process, so this is kept out of the unit tests,
+//! which expect the default endpoint.
+#![cfg(feature = "api-override")]
+
+use hyper::header;
+use mullvad_rpc::{rest::RequestFactory, AddressCache};
+use std::net::SocketAddr;
+
+#[test]
+fn test_set_api_endpoint() {
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
+    // Both are created before the override, and must follow it anyway
+    let factory = RequestFactory::for_api(Some("app/".to_owned()));
+    let address_cache = AddressCache::new(None, false).unwrap();
+
+    let addr: SocketAddr = "192.0.2.1:4443".parse().unwrap();
+    mullvad_rpc::set_api_endpoint("api.example.com".to_owned(), addr).unwrap();
+
+    let request = factory.get("v1/api-addrs").unwrap();
+    assert_eq!(
+        request.uri().to_string(),
+        "https://api.example.com/app/v1/api-addrs"
+    );
+    assert_eq!(request.headers()[header::HOST], "api.example.com");
+
+    assert_eq!(runtime.block_on(address_cache.get_address()), addr);
+}
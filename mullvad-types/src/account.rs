@@ -6,6 +6,28 @@ use serde::{Deserialize, Serialize};
 /// Identifier used to authenticate or identify a Mullvad account.
 pub type AccountToken = String;
 
+/// Compares two tokens without returning early at the first differing byte, so that the time it
+/// takes does not reveal how much of a token is correct. Only the lengths are compared in
+/// variable time. Use this when one of the tokens comes from an untrusted source.
+pub fn secure_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.bytes()
+        .zip(b.bytes())
+        .fold(0u8, |difference, (a, b)| difference | (a ^ b))
+        == 0
+}
+
+/// Like [`secure_eq`], but for optional tokens. Two missing tokens are equal.
+pub fn secure_eq_opt(a: Option<&str>, b: Option<&str>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => secure_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
 /// Account expiration info returned by the API via `/v1/me`.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 #[cfg_attr(target_os = "android", derive(IntoJava))]
@@ -22,6 +44,14 @@ impl AccountData {
     }
 }
 
+/// The expiry of the current account, as last fetched by the daemon.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AccountExpiry {
+    pub expiry: DateTime<Utc>,
+    /// When the expiry was fetched from the API.
+    pub fetched_at: DateTime<Utc>,
+}
+
 /// Data structure that's returned from successful invocation of the mullvad API's
 /// `/v1/submit-voucher` RPC.
 #[derive(Deserialize, Serialize, Debug)]
@@ -35,3 +65,22 @@ pub struct VoucherSubmission {
     #[cfg_attr(target_os = "android", jnix(map = "|expiry| expiry.to_string()"))]
     pub new_expiry: DateTime<Utc>,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_secure_eq() {
+        assert!(secure_eq("1234567890123456", "1234567890123456"));
+        assert!(!secure_eq("1234567890123456", "1234567890123457"));
+        assert!(!secure_eq("1234567890123456", "0234567890123456"));
+        assert!(!secure_eq("1234567890123456", "123456789012345"));
+        assert!(secure_eq("", ""));
+
+        assert!(secure_eq_opt(None, None));
+        assert!(secure_eq_opt(Some("1234"), Some("1234")));
+        assert!(!secure_eq_opt(Some("1234"), None));
+        assert!(!secure_eq_opt(None, Some("1234")));
+    }
+}
@@ -0,0 +1,39 @@
+//! Results of the connectivity self-check run by the daemon.
+
+use std::time::Duration;
+
+/// A step of the connectivity check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectivityCheckStep {
+    /// The API is reachable through the current API connection mode.
+    Api,
+    /// A TCP connection can be made to the selected relay, without establishing a tunnel.
+    RelayReachability,
+    /// Host names can be resolved using the configured DNS servers.
+    Dns,
+    /// Traffic leaves through a Mullvad relay, according to am.i.mullvad.net.
+    ExitIp,
+}
+
+/// The outcome of a single step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectivityCheckOutcome {
+    Passed,
+    Failed(String),
+    /// The step was not run. The reason is included.
+    Skipped(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectivityCheckResult {
+    pub step: ConnectivityCheckStep,
+    pub outcome: ConnectivityCheckOutcome,
+    /// How long the step took. Not set for skipped steps.
+    pub latency: Option<Duration>,
+}
+
+/// The results of all steps of the connectivity check, in the order they were run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectivityReport {
+    pub results: Vec<ConnectivityCheckResult>,
+}
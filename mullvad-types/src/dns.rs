@@ -0,0 +1,91 @@
+//! The DNS configuration in effect, as reported by the daemon.
+
+use crate::{
+    connectivity_check::ConnectivityCheckOutcome,
+    settings::{DefaultDnsOptions, DnsOptions, DnsState},
+};
+use std::{net::IpAddr, time::Duration};
+use talpid_types::net::dns::AppliedDnsConfig;
+
+/// Where the DNS servers that are used while connected come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsSource {
+    /// The DNS server in the tunnel gateway of the relay.
+    RelayDefault,
+    /// Custom DNS servers from the settings.
+    Custom,
+    /// The content blocking DNS server that blocks the given categories.
+    ContentBlocking(DefaultDnsOptions),
+}
+
+impl DnsSource {
+    /// Returns the source of the DNS servers that `options` result in.
+    pub fn from_options(options: &DnsOptions) -> Self {
+        match options.state {
+            DnsState::Default => {
+                let blocking = &options.default_options;
+                if blocking.block_ads || blocking.block_trackers || blocking.block_malware {
+                    DnsSource::ContentBlocking(blocking.clone())
+                } else {
+                    DnsSource::RelayDefault
+                }
+            }
+            // Without any addresses, the relay default is used.
+            DnsState::Custom if options.custom_options.addresses.is_empty() => {
+                DnsSource::RelayDefault
+            }
+            DnsState::Custom => DnsSource::Custom,
+        }
+    }
+}
+
+/// The result of sending a query to one of the applied DNS servers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsServerHealth {
+    pub server: IpAddr,
+    pub outcome: ConnectivityCheckOutcome,
+    /// How long the server took to respond. Not set for skipped servers.
+    pub latency: Option<Duration>,
+}
+
+/// The DNS servers in effect, and whether they respond.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsStatus {
+    pub source: DnsSource,
+    /// The configuration that the system DNS settings were last changed to, or `None` if they
+    /// are not changed.
+    pub applied: Option<AppliedDnsConfig>,
+    /// One result for each applied server, in the same order.
+    pub health: Vec<DnsServerHealth>,
+    /// The gateway addresses of the relays, which are reached through the tunnel.
+    pub tunnel_gateways: Vec<IpAddr>,
+    /// The custom DNS servers that queries are sent to outside the tunnel.
+    pub outside_tunnel: Vec<IpAddr>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::settings::CustomDnsOptions;
+
+    #[test]
+    fn test_dns_source() {
+        let mut options = DnsOptions::default();
+        assert_eq!(DnsSource::from_options(&options), DnsSource::RelayDefault);
+
+        options.default_options.block_ads = true;
+        assert_eq!(
+            DnsSource::from_options(&options),
+            DnsSource::ContentBlocking(options.default_options.clone())
+        );
+
+        options.state = DnsState::Custom;
+        assert_eq!(DnsSource::from_options(&options), DnsSource::RelayDefault);
+
+        options.custom_options = CustomDnsOptions {
+            addresses: vec!["192.0.2.53".parse().unwrap()],
+            reject_outside_tunnel: false,
+        };
+        assert_eq!(DnsSource::from_options(&options), DnsSource::Custom);
+    }
+}
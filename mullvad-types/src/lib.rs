@@ -2,6 +2,8 @@
 
 pub mod account;
 pub mod auth_failed;
+pub mod connectivity_check;
+pub mod dns;
 pub mod endpoint;
 pub mod location;
 pub mod relay_constraints;
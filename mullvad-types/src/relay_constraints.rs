@@ -185,11 +185,17 @@ pub struct RelayConstraints {
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub providers: Constraint<Providers>,
     #[cfg_attr(target_os = "android", jnix(skip))]
+    pub ownership: Constraint<Ownership>,
+    #[cfg_attr(target_os = "android", jnix(skip))]
     pub tunnel_protocol: Constraint<TunnelType>,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub wireguard_constraints: WireguardConstraints,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub openvpn_constraints: OpenVpnConstraints,
+    /// Relays hosted in any of these autonomous systems are never selected. This is best-effort:
+    /// relays whose ASN is unknown are not excluded.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub excluded_asns: Vec<u32>,
 }
 
 #[cfg(target_os = "android")]
@@ -199,8 +205,10 @@ impl Default for RelayConstraints {
             tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
             location: Constraint::default(),
             providers: Constraint::default(),
+            ownership: Constraint::default(),
             wireguard_constraints: WireguardConstraints::default(),
             openvpn_constraints: OpenVpnConstraints::default(),
+            excluded_asns: Vec::new(),
         }
     }
 }
@@ -210,6 +218,7 @@ impl RelayConstraints {
         RelayConstraints {
             location: update.location.unwrap_or_else(|| self.location.clone()),
             providers: update.providers.unwrap_or_else(|| self.providers.clone()),
+            ownership: update.ownership.unwrap_or(self.ownership),
             tunnel_protocol: update
                 .tunnel_protocol
                 .unwrap_or_else(|| self.tunnel_protocol.clone()),
@@ -219,6 +228,9 @@ impl RelayConstraints {
             openvpn_constraints: update
                 .openvpn_constraints
                 .unwrap_or_else(|| self.openvpn_constraints.clone()),
+            excluded_asns: update
+                .excluded_asns
+                .unwrap_or_else(|| self.excluded_asns.clone()),
         }
     }
 }
@@ -250,9 +262,21 @@ impl fmt::Display for RelayConstraints {
         }
         write!(f, " using ")?;
         match self.providers {
-            Constraint::Any => write!(f, "any provider"),
-            Constraint::Only(ref constraint) => constraint.fmt(f),
+            Constraint::Any => write!(f, "any provider")?,
+            Constraint::Only(ref constraint) => constraint.fmt(f)?,
+        }
+        if let Constraint::Only(ref ownership) = self.ownership {
+            write!(f, " ({} servers)", ownership)?;
+        }
+        if !self.excluded_asns.is_empty() {
+            let asns: Vec<String> = self
+                .excluded_asns
+                .iter()
+                .map(|asn| format!("AS{}", asn))
+                .collect();
+            write!(f, " excluding {}", asns.join(", "))?;
         }
+        Ok(())
     }
 }
 
@@ -368,6 +392,33 @@ impl fmt::Display for Providers {
     }
 }
 
+/// Limits the set of [`crate::relay_list::Relay`]s used by a `RelaySelector` based on whether
+/// the servers are owned by Mullvad.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ownership {
+    MullvadOwned,
+    Rented,
+}
+
+impl Match<Relay> for Ownership {
+    fn matches(&self, relay: &Relay) -> bool {
+        match self {
+            Ownership::MullvadOwned => relay.owned,
+            Ownership::Rented => !relay.owned,
+        }
+    }
+}
+
+impl fmt::Display for Ownership {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Ownership::MullvadOwned => write!(f, "Mullvad-owned"),
+            Ownership::Rented => write!(f, "rented"),
+        }
+    }
+}
+
 impl fmt::Display for LocationConstraint {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self {
@@ -477,6 +528,7 @@ pub enum BridgeSettings {
 pub struct BridgeConstraints {
     pub location: Constraint<LocationConstraint>,
     pub providers: Constraint<Providers>,
+    pub ownership: Constraint<Ownership>,
 }
 
 impl fmt::Display for BridgeConstraints {
@@ -487,9 +539,13 @@ impl fmt::Display for BridgeConstraints {
         }
         write!(f, " using ")?;
         match self.providers {
-            Constraint::Any => write!(f, "any provider"),
-            Constraint::Only(ref constraint) => constraint.fmt(f),
+            Constraint::Any => write!(f, "any provider")?,
+            Constraint::Only(ref constraint) => constraint.fmt(f)?,
+        }
+        if let Constraint::Only(ref ownership) = self.ownership {
+            write!(f, " ({} servers)", ownership)?;
         }
+        Ok(())
     }
 }
 
@@ -516,10 +572,54 @@ impl fmt::Display for BridgeState {
     }
 }
 
+/// Controls whether API traffic is sent through a bridge.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiBridgeMode {
+    /// Alternate between direct connections and bridges when the API cannot be reached.
+    Auto,
+    /// Never connect to the API directly.
+    Always,
+    /// Never use a bridge to connect to the API.
+    Never,
+}
+
+impl Default for ApiBridgeMode {
+    fn default() -> Self {
+        ApiBridgeMode::Auto
+    }
+}
+
+impl fmt::Display for ApiBridgeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                ApiBridgeMode::Auto => "auto",
+                ApiBridgeMode::Always => "always",
+                ApiBridgeMode::Never => "never",
+            }
+        )
+    }
+}
+
+/// Selects the bridges that API traffic may be sent through.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(rename_all = "snake_case")]
+pub struct ApiBridgeSettings {
+    pub mode: ApiBridgeMode,
+    /// Location of the bridges to use for API traffic. If this is `Any`, the location in the
+    /// bridge settings is used.
+    pub location: Constraint<LocationConstraint>,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub struct InternalBridgeConstraints {
     pub location: Constraint<LocationConstraint>,
     pub providers: Constraint<Providers>,
+    pub ownership: Constraint<Ownership>,
     pub transport_protocol: Constraint<TransportProtocol>,
 }
 
@@ -571,9 +671,13 @@ pub struct RelayConstraintsUpdate {
     #[cfg_attr(target_os = "android", jnix(default))]
     pub providers: Option<Constraint<Providers>>,
     #[cfg_attr(target_os = "android", jnix(default))]
+    pub ownership: Option<Constraint<Ownership>>,
+    #[cfg_attr(target_os = "android", jnix(default))]
     pub tunnel_protocol: Option<Constraint<TunnelType>>,
     #[cfg_attr(target_os = "android", jnix(default))]
     pub wireguard_constraints: Option<WireguardConstraints>,
     #[cfg_attr(target_os = "android", jnix(default))]
     pub openvpn_constraints: Option<OpenVpnConstraints>,
+    #[cfg_attr(target_os = "android", jnix(default))]
+    pub excluded_asns: Option<Vec<u32>>,
 }
@@ -2,12 +2,14 @@ use crate::{
     endpoint::MullvadEndpoint,
     location::{CityCode, CountryCode, Location},
 };
+use chrono::{DateTime, Utc};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
 use std::{
     fmt,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
 };
 use talpid_types::net::{
     openvpn::{ProxySettings, ShadowsocksProxySettings},
@@ -76,6 +78,14 @@ pub struct Relay {
     pub owned: bool,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub provider: String,
+    /// Autonomous system that the relay is hosted in, if known.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub asn: Option<u32>,
+    /// Organization that owns [`Relay::asn`], if known.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub asn_organization: Option<String>,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub weight: u64,
     #[serde(skip_serializing_if = "RelayTunnels::is_empty", default)]
@@ -85,6 +95,63 @@ pub struct Relay {
     pub bridges: RelayBridges,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub location: Option<Location>,
+    /// Upcoming or ongoing maintenance of the relay, if any is scheduled.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub maintenance: Option<MaintenanceWindow>,
+}
+
+/// A period during which a relay is taken down for maintenance. The times are provided by the
+/// API, and should be compared against the server time rather than the local clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MaintenanceWindow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl MaintenanceWindow {
+    /// Returns the time until the window starts, or zero if it is ongoing. Returns `None` if the
+    /// window has ended.
+    pub fn time_until_start(&self, now: DateTime<Utc>) -> Option<Duration> {
+        if now >= self.end {
+            return None;
+        }
+        Some((self.start - now).to_std().unwrap_or(Duration::ZERO))
+    }
+}
+
+/// A maintenance window that is about to start on a relay that the tunnel uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayMaintenance {
+    pub hostname: String,
+    pub window: MaintenanceWindow,
+    /// When the daemon will reconnect to another relay, or `None` if it will stay connected.
+    pub reconnect_at: Option<DateTime<Utc>>,
+}
+
+/// A relay that the tunnel uses, or that the relay constraints select by hostname, but that is
+/// missing from a new relay list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeprecatedRelay {
+    pub hostname: String,
+    /// Whether the tunnel is still using the relay.
+    pub still_connected: bool,
+}
+
+/// A relay that recently failed to connect, and that the relay selector avoids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedRelay {
+    pub hostname: String,
+    /// Time until the relay may be selected again.
+    pub penalty_remaining: Duration,
+}
+
+impl Relay {
+    /// Returns whether the relay is known to be hosted in one of `asns`. Relays without ASN
+    /// information never match.
+    pub fn is_hosted_in_any(&self, asns: &[u32]) -> bool {
+        self.asn.map(|asn| asns.contains(&asn)).unwrap_or(false)
+    }
 }
 
 /// Provides protocol-specific information about a [`Relay`].
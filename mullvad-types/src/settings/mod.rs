@@ -1,16 +1,18 @@
 use crate::{
+    account,
     relay_constraints::{
-        BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
-        RelayConstraints, RelaySettings, RelaySettingsUpdate,
+        ApiBridgeMode, ApiBridgeSettings, BridgeConstraints, BridgeSettings, BridgeState,
+        Constraint, LocationConstraint, RelayConstraints, RelaySettings, RelaySettingsUpdate,
     },
     wireguard,
 };
+use ipnetwork::IpNetwork;
 #[cfg(target_os = "android")]
 use jnix::{jni::objects::JObject, FromJava, IntoJava, JnixEnv};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::net::IpAddr;
 #[cfg(target_os = "windows")]
-use std::{collections::HashSet, path::PathBuf};
+use std::{collections::HashSet, path::PathBuf, time::Duration};
+use std::{fmt, net::IpAddr};
 use talpid_types::net::{self, openvpn, GenericTunnelOptions};
 
 /// The version used by the current version of the code. Should always be the
@@ -55,6 +57,12 @@ impl Serialize for SettingsVersion {
     }
 }
 
+/// Default time to wait before reconnecting when the relay in use is removed from the relay list.
+pub const DEFAULT_RELAY_REMOVED_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+/// Longest time that the daemon may wait before reconnecting when the relay in use is removed
+/// from the relay list.
+pub const MAX_RELAY_REMOVED_RECONNECT_DELAY: Duration = Duration::from_secs(60 * 60);
+
 /// Mullvad daemon settings.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[serde(default)]
@@ -69,14 +77,42 @@ pub struct Settings {
     pub bridge_settings: BridgeSettings,
     #[cfg_attr(target_os = "android", jnix(skip))]
     bridge_state: BridgeState,
+    /// Controls if and through which bridges API traffic is sent.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub api_bridge_settings: ApiBridgeSettings,
+    /// Whether the API host may be looked up using DNS-over-HTTPS when none of the known API
+    /// addresses work. This reveals to the DoH servers that the app is in use.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub api_doh_fallback: bool,
+    /// Number of bytes that API responses may contain per daemon session before requests that
+    /// are not essential are refused. `None` means that there is no limit.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub api_data_limit: Option<u64>,
     /// If the daemon should allow communication with private (LAN) networks.
     pub allow_lan: bool,
+    /// Networks outside of the private ranges that are also treated as local networks when
+    /// `allow_lan` is enabled.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub allowed_networks: Vec<IpNetwork>,
     /// Extra level of kill switch. When this setting is on, the disconnected state will block
     /// the firewall to not allow any traffic in or out.
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub block_when_disconnected: bool,
     /// If the daemon should connect the VPN tunnel directly on start or not.
     pub auto_connect: bool,
+    /// When this setting is on, the daemon blocks all traffic when it starts, until it is asked
+    /// to connect. This takes precedence over `auto_connect` and the target state from before the
+    /// daemon was stopped.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub lockdown_after_boot: bool,
+    /// Whether to reconnect to another relay shortly before maintenance of the relay in use
+    /// starts.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub reconnect_before_maintenance: bool,
+    /// How long to wait before reconnecting when the relay in use is removed from the relay list,
+    /// so that ongoing downloads have a chance to finish.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub relay_removed_reconnect_delay: Duration,
     /// Options that should be applied to tunnels of a specific type regardless of where the relays
     /// might be located.
     pub tunnel_options: TunnelOptions,
@@ -110,9 +146,16 @@ impl Default for Settings {
             }),
             bridge_settings: BridgeSettings::Normal(BridgeConstraints::default()),
             bridge_state: BridgeState::Auto,
+            api_bridge_settings: ApiBridgeSettings::default(),
+            api_doh_fallback: false,
+            api_data_limit: None,
             allow_lan: false,
+            allowed_networks: Vec::new(),
             block_when_disconnected: false,
             auto_connect: false,
+            lockdown_after_boot: false,
+            reconnect_before_maintenance: true,
+            relay_removed_reconnect_delay: DEFAULT_RELAY_REMOVED_RECONNECT_DELAY,
             tunnel_options: TunnelOptions::default(),
             show_beta_releases: false,
             #[cfg(windows)]
@@ -134,7 +177,7 @@ impl Settings {
             log::debug!("Setting empty account token is treated as unsetting it");
             account_token = None;
         }
-        if account_token != self.account_token {
+        if !account::secure_eq_opt(account_token.as_deref(), self.account_token.as_deref()) {
             if account_token.is_none() {
                 log::info!("Unsetting account token");
             } else if self.account_token.is_none() {
@@ -273,6 +316,7 @@ impl From<AndroidDnsOptions> for DnsOptions {
             default_options: DefaultDnsOptions::default(),
             custom_options: CustomDnsOptions {
                 addresses: options.addresses,
+                reject_outside_tunnel: false,
             },
         }
     }
@@ -303,6 +347,266 @@ pub struct DefaultDnsOptions {
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
 pub struct CustomDnsOptions {
     pub addresses: Vec<IpAddr>,
+    /// Refuse custom DNS servers that would be reached outside the tunnel.
+    #[serde(default)]
+    pub reject_outside_tunnel: bool,
+}
+
+/// Maximum number of custom DNS servers.
+pub const MAX_CUSTOM_DNS_SERVERS: usize = 8;
+
+/// Error for an unusable list of custom DNS servers.
+#[derive(err_derive::Error, Debug, Clone, PartialEq, Eq)]
+pub enum CustomDnsError {
+    #[error(
+        display = "At most {} custom DNS servers may be used, but {} were given",
+        MAX_CUSTOM_DNS_SERVERS,
+        _0
+    )]
+    TooManyServers(usize),
+
+    #[error(
+        display = "Custom DNS servers would be reached outside the tunnel: {:?}",
+        _0
+    )]
+    OutsideTunnel(Vec<IpAddr>),
+}
+
+/// Describes how queries to a custom DNS server are sent while connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsServerReachability {
+    /// Queries are sent through the tunnel.
+    Tunnel,
+    /// The server runs on this device.
+    Loopback,
+    /// Queries are sent to the local network, outside the tunnel.
+    LocalNetwork,
+}
+
+impl CustomDnsOptions {
+    /// Removes duplicate addresses, keeping the first occurrence of each, and checks that no more
+    /// than [`MAX_CUSTOM_DNS_SERVERS`] remain.
+    pub fn validate_addresses(addresses: Vec<IpAddr>) -> Result<Vec<IpAddr>, CustomDnsError> {
+        let mut unique_addresses = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            if !unique_addresses.contains(&address) {
+                unique_addresses.push(address);
+            }
+        }
+        if unique_addresses.len() > MAX_CUSTOM_DNS_SERVERS {
+            return Err(CustomDnsError::TooManyServers(unique_addresses.len()));
+        }
+        Ok(unique_addresses)
+    }
+
+    /// Returns how queries to `address` are sent. Private addresses are on the local network,
+    /// except for `tunnel_gateways`, which are reached through the tunnel.
+    pub fn reachability(address: &IpAddr, tunnel_gateways: &[IpAddr]) -> DnsServerReachability {
+        if tunnel_gateways.contains(address) {
+            return DnsServerReachability::Tunnel;
+        }
+        if address.is_loopback() {
+            return DnsServerReachability::Loopback;
+        }
+        let is_local = match address {
+            IpAddr::V4(address) => address.is_private() || address.is_link_local(),
+            IpAddr::V6(address) => {
+                let first_segment = address.segments()[0];
+                // Link-local (fe80::/10) or unique local (fc00::/7)
+                (first_segment & 0xffc0) == 0xfe80 || (first_segment & 0xfe00) == 0xfc00
+            }
+        };
+        if is_local {
+            DnsServerReachability::LocalNetwork
+        } else {
+            DnsServerReachability::Tunnel
+        }
+    }
+
+    /// Returns the addresses in `addresses` that queries are sent to outside the tunnel.
+    pub fn servers_outside_tunnel(addresses: &[IpAddr], tunnel_gateways: &[IpAddr]) -> Vec<IpAddr> {
+        addresses
+            .iter()
+            .filter(|address| {
+                Self::reachability(address, tunnel_gateways) == DnsServerReachability::LocalNetwork
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Fails if [`Self::reject_outside_tunnel`] is set and any of the addresses would be reached
+    /// outside the tunnel.
+    pub fn check_outside_tunnel(&self, tunnel_gateways: &[IpAddr]) -> Result<(), CustomDnsError> {
+        if !self.reject_outside_tunnel {
+            return Ok(());
+        }
+        let outside_tunnel = Self::servers_outside_tunnel(&self.addresses, tunnel_gateways);
+        if outside_tunnel.is_empty() {
+            Ok(())
+        } else {
+            Err(CustomDnsError::OutsideTunnel(outside_tunnel))
+        }
+    }
+}
+
+/// Maximum number of networks in [`Settings::allowed_networks`].
+pub const MAX_ALLOWED_NETWORKS: usize = 16;
+
+/// Whether the firewall on this platform can allow networks in addition to the private ranges.
+pub const ALLOWED_NETWORKS_SUPPORTED: bool = cfg!(not(any(windows, target_os = "android")));
+
+/// Error for an unusable list of allowed networks.
+#[derive(err_derive::Error, Debug, Clone, PartialEq, Eq)]
+pub enum AllowedNetworksError {
+    #[error(
+        display = "At most {} networks may be allowed, but {} were given",
+        MAX_ALLOWED_NETWORKS,
+        _0
+    )]
+    TooManyNetworks(usize),
+    #[error(display = "{} contains every address and cannot be allowed", _0)]
+    AllowsAllAddresses(IpNetwork),
+    #[error(display = "Allowing additional networks is not supported on this platform")]
+    Unsupported,
+}
+
+/// Fails if `networks` is not empty and [`ALLOWED_NETWORKS_SUPPORTED`] is not set.
+pub fn check_allowed_networks_supported(
+    networks: &[IpNetwork],
+) -> Result<(), AllowedNetworksError> {
+    if ALLOWED_NETWORKS_SUPPORTED || networks.is_empty() {
+        Ok(())
+    } else {
+        Err(AllowedNetworksError::Unsupported)
+    }
+}
+
+/// Normalizes a list of allowed networks and checks that it can be used. Host bits are cleared,
+/// networks contained in other networks are removed and the result is sorted. At most
+/// [`MAX_ALLOWED_NETWORKS`] networks may remain, and none of them may contain every address.
+pub fn validate_allowed_networks(
+    networks: Vec<IpNetwork>,
+) -> Result<Vec<IpNetwork>, AllowedNetworksError> {
+    let mut normalized: Vec<IpNetwork> = Vec::with_capacity(networks.len());
+    for network in networks {
+        if network.prefix() == 0 {
+            return Err(AllowedNetworksError::AllowsAllAddresses(network));
+        }
+        let network = IpNetwork::new(network.network(), network.prefix())
+            .expect("prefix of an existing network must be valid");
+        normalized.push(network);
+    }
+
+    // Shorter prefixes sort first, so every network is compared against all larger ones
+    normalized.sort_by_key(|network| (network.is_ipv6(), network.prefix(), network.ip()));
+    let mut unique: Vec<IpNetwork> = Vec::with_capacity(normalized.len());
+    for network in normalized {
+        let is_covered = unique
+            .iter()
+            .any(|larger| larger.contains(network.network()));
+        if !is_covered {
+            unique.push(network);
+        }
+    }
+    unique.sort_by_key(|network| (network.is_ipv6(), network.ip(), network.prefix()));
+
+    if unique.len() > MAX_ALLOWED_NETWORKS {
+        return Err(AllowedNetworksError::TooManyNetworks(unique.len()));
+    }
+    Ok(unique)
+}
+
+/// Named combination of the settings that control which traffic may bypass the tunnel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityPreset {
+    /// Block all traffic outside the tunnel, including to the local network, and never
+    /// connect to the API directly.
+    Strict,
+    /// The default settings. Traffic is not blocked while disconnected.
+    Standard,
+    /// Block all traffic outside the tunnel, except to the local network.
+    LanFriendly,
+}
+
+/// Values of the settings that make up a [`SecurityPreset`]. Every preset also clears
+/// [`Settings::allowed_networks`]. Only the mode of [`Settings::api_bridge_settings`] is part
+/// of a preset; its location is left as is.
+struct PresetDefinition {
+    block_when_disconnected: bool,
+    allow_lan: bool,
+    api_bridge_mode: ApiBridgeMode,
+}
+
+impl SecurityPreset {
+    pub const ALL: [SecurityPreset; 3] = [
+        SecurityPreset::Strict,
+        SecurityPreset::Standard,
+        SecurityPreset::LanFriendly,
+    ];
+
+    fn definition(self) -> PresetDefinition {
+        match self {
+            SecurityPreset::Strict => PresetDefinition {
+                block_when_disconnected: true,
+                allow_lan: false,
+                api_bridge_mode: ApiBridgeMode::Always,
+            },
+            SecurityPreset::Standard => PresetDefinition {
+                block_when_disconnected: false,
+                allow_lan: false,
+                api_bridge_mode: ApiBridgeMode::Auto,
+            },
+            SecurityPreset::LanFriendly => PresetDefinition {
+                block_when_disconnected: true,
+                allow_lan: true,
+                api_bridge_mode: ApiBridgeMode::Auto,
+            },
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SecurityPreset::Strict => "strict",
+            SecurityPreset::Standard => "standard",
+            SecurityPreset::LanFriendly => "lan-friendly",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|preset| preset.name() == name)
+    }
+
+    /// Returns the preset that matches `settings`, or `None` if the settings have been
+    /// customized.
+    pub fn matching(settings: &Settings) -> Option<Self> {
+        Self::ALL.iter().copied().find(|preset| {
+            let definition = preset.definition();
+            settings.block_when_disconnected == definition.block_when_disconnected
+                && settings.allow_lan == definition.allow_lan
+                && settings.api_bridge_settings.mode == definition.api_bridge_mode
+                && settings.allowed_networks.is_empty()
+        })
+    }
+
+    /// Changes all settings in the preset at once. Returns whether any setting changed.
+    pub fn apply(self, settings: &mut Settings) -> bool {
+        let definition = self.definition();
+        let changed = Self::matching(settings) != Some(self);
+        settings.block_when_disconnected = definition.block_when_disconnected;
+        settings.allow_lan = definition.allow_lan;
+        settings.api_bridge_settings.mode = definition.api_bridge_mode;
+        settings.allowed_networks.clear();
+        changed
+    }
+}
+
+impl fmt::Display for SecurityPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
 impl Default for TunnelOptions {
@@ -321,3 +625,201 @@ impl Default for TunnelOptions {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_custom_dns_deduplication() {
+        let addresses: Vec<IpAddr> = vec![
+            "1.1.1.1".parse().unwrap(),
+            "9.9.9.9".parse().unwrap(),
+            "1.1.1.1".parse().unwrap(),
+        ];
+        assert_eq!(
+            CustomDnsOptions::validate_addresses(addresses),
+            Ok(vec!["1.1.1.1".parse().unwrap(), "9.9.9.9".parse().unwrap()])
+        );
+    }
+
+    #[test]
+    fn test_custom_dns_server_limit() {
+        let addresses: Vec<IpAddr> = (0..MAX_CUSTOM_DNS_SERVERS as u8)
+            .map(|i| IpAddr::from([1, 1, 1, i]))
+            .collect();
+        assert!(CustomDnsOptions::validate_addresses(addresses.clone()).is_ok());
+
+        // Duplicates do not count towards the limit
+        let mut with_duplicate = addresses.clone();
+        with_duplicate.push(addresses[0]);
+        assert!(CustomDnsOptions::validate_addresses(with_duplicate).is_ok());
+
+        let mut too_many = addresses;
+        too_many.push(IpAddr::from([8, 8, 8, 8]));
+        assert_eq!(
+            CustomDnsOptions::validate_addresses(too_many),
+            Err(CustomDnsError::TooManyServers(MAX_CUSTOM_DNS_SERVERS + 1))
+        );
+    }
+
+    #[test]
+    fn test_allowed_networks_normalization() {
+        let networks: Vec<IpNetwork> = vec![
+            "192.0.2.7/24".parse().unwrap(),
+            "100.64.1.0/24".parse().unwrap(),
+            "100.64.0.0/10".parse().unwrap(),
+            "192.0.2.0/24".parse().unwrap(),
+            "2001:db8::1/32".parse().unwrap(),
+        ];
+        assert_eq!(
+            validate_allowed_networks(networks),
+            Ok(vec![
+                "100.64.0.0/10".parse().unwrap(),
+                "192.0.2.0/24".parse().unwrap(),
+                "2001:db8::/32".parse().unwrap(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_allowed_networks_support() {
+        assert_eq!(check_allowed_networks_supported(&[]), Ok(()));
+        let networks = vec!["100.64.0.0/10".parse().unwrap()];
+        assert_eq!(
+            check_allowed_networks_supported(&networks).is_ok(),
+            ALLOWED_NETWORKS_SUPPORTED
+        );
+    }
+
+    #[test]
+    fn test_allowed_networks_limits() {
+        let all_v4: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        assert_eq!(
+            validate_allowed_networks(vec![all_v4]),
+            Err(AllowedNetworksError::AllowsAllAddresses(all_v4))
+        );
+        let all_v6: IpNetwork = "::/0".parse().unwrap();
+        assert_eq!(
+            validate_allowed_networks(vec![all_v6]),
+            Err(AllowedNetworksError::AllowsAllAddresses(all_v6))
+        );
+
+        let networks: Vec<IpNetwork> = (0..MAX_ALLOWED_NETWORKS as u8)
+            .map(|i| IpNetwork::new(IpAddr::from([100, 64, i, 0]), 24).unwrap())
+            .collect();
+        assert!(validate_allowed_networks(networks.clone()).is_ok());
+
+        // Duplicates do not count towards the limit
+        let mut with_duplicate = networks.clone();
+        with_duplicate.push(networks[0]);
+        assert!(validate_allowed_networks(with_duplicate).is_ok());
+
+        let mut too_many = networks;
+        too_many.push("198.51.100.0/24".parse().unwrap());
+        assert_eq!(
+            validate_allowed_networks(too_many),
+            Err(AllowedNetworksError::TooManyNetworks(
+                MAX_ALLOWED_NETWORKS + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn test_custom_dns_reachability() {
+        let gateway: IpAddr = "10.64.0.1".parse().unwrap();
+        let cases = [
+            ("1.1.1.1", DnsServerReachability::Tunnel),
+            ("100.64.0.1", DnsServerReachability::Tunnel),
+            ("2606:4700:4700::1111", DnsServerReachability::Tunnel),
+            ("10.64.0.1", DnsServerReachability::Tunnel),
+            ("127.0.0.53", DnsServerReachability::Loopback),
+            ("::1", DnsServerReachability::Loopback),
+            ("10.0.0.1", DnsServerReachability::LocalNetwork),
+            ("172.16.0.1", DnsServerReachability::LocalNetwork),
+            ("192.168.1.1", DnsServerReachability::LocalNetwork),
+            ("169.254.1.1", DnsServerReachability::LocalNetwork),
+            ("fe80::1", DnsServerReachability::LocalNetwork),
+            ("fd00::1", DnsServerReachability::LocalNetwork),
+        ];
+        for (address, expected) in cases {
+            let address: IpAddr = address.parse().unwrap();
+            assert_eq!(
+                CustomDnsOptions::reachability(&address, &[gateway]),
+                expected,
+                "unexpected reachability for {}",
+                address
+            );
+        }
+
+        assert_eq!(
+            CustomDnsOptions::reachability(&gateway, &[]),
+            DnsServerReachability::LocalNetwork
+        );
+    }
+
+    #[test]
+    fn test_custom_dns_outside_tunnel() {
+        let gateway: IpAddr = "10.64.0.1".parse().unwrap();
+        let local: IpAddr = "192.168.1.1".parse().unwrap();
+        let mut options = CustomDnsOptions {
+            addresses: vec![gateway, local, "1.1.1.1".parse().unwrap()],
+            reject_outside_tunnel: false,
+        };
+
+        assert_eq!(
+            CustomDnsOptions::servers_outside_tunnel(&options.addresses, &[gateway]),
+            vec![local]
+        );
+        assert!(options.check_outside_tunnel(&[gateway]).is_ok());
+
+        options.reject_outside_tunnel = true;
+        assert_eq!(
+            options.check_outside_tunnel(&[gateway]),
+            Err(CustomDnsError::OutsideTunnel(vec![local]))
+        );
+
+        options.addresses.retain(|address| *address != local);
+        assert!(options.check_outside_tunnel(&[gateway]).is_ok());
+    }
+
+    #[test]
+    fn test_security_presets() {
+        let mut settings = Settings::default();
+        assert_eq!(
+            SecurityPreset::matching(&settings),
+            Some(SecurityPreset::Standard)
+        );
+
+        for preset in SecurityPreset::ALL {
+            settings.allowed_networks = vec!["100.64.0.0/10".parse().unwrap()];
+            assert!(preset.apply(&mut settings));
+            assert_eq!(SecurityPreset::matching(&settings), Some(preset));
+            assert!(settings.allowed_networks.is_empty());
+            assert_eq!(
+                settings.api_bridge_settings.mode,
+                preset.definition().api_bridge_mode
+            );
+            assert!(!preset.apply(&mut settings));
+            assert_eq!(SecurityPreset::from_name(preset.name()), Some(preset));
+        }
+        assert_eq!(SecurityPreset::from_name("paranoid"), None);
+    }
+
+    #[test]
+    fn test_changed_setting_makes_preset_custom() {
+        let mut settings = Settings::default();
+        SecurityPreset::LanFriendly.apply(&mut settings);
+        settings.allowed_networks = vec!["100.64.0.0/10".parse().unwrap()];
+        assert_eq!(SecurityPreset::matching(&settings), None);
+
+        SecurityPreset::Standard.apply(&mut settings);
+        settings.api_bridge_settings.mode = ApiBridgeMode::Never;
+        assert_eq!(SecurityPreset::matching(&settings), None);
+
+        SecurityPreset::Strict.apply(&mut settings);
+        settings.block_when_disconnected = false;
+        settings.allow_lan = true;
+        assert_eq!(SecurityPreset::matching(&settings), None);
+    }
+}
@@ -30,6 +30,21 @@ impl WireguardData {
             created: self.created,
         }
     }
+
+    /// Returns whether the key is at least `max_age` old. Keys that were created before the
+    /// creation time was stored are considered to have been created when they were loaded.
+    pub fn needs_rotation(&self, max_age: Duration) -> bool {
+        is_older_than(self.created, max_age, Utc::now())
+    }
+}
+
+/// Returns whether `created` is at least `max_age` before `now`. Times after `now`, for example
+/// because the clock was moved back, are never too old.
+fn is_older_than(created: DateTime<Utc>, max_age: Duration, now: DateTime<Utc>) -> bool {
+    now.signed_duration_since(created)
+        .to_std()
+        .map(|age| age >= max_age)
+        .unwrap_or(false)
 }
 
 #[derive(Debug, Clone)]
@@ -138,6 +153,13 @@ pub struct PublicKey {
     pub created: DateTime<Utc>,
 }
 
+impl PublicKey {
+    /// Returns whether the key is at least `max_age` old.
+    pub fn needs_rotation(&self, max_age: Duration) -> bool {
+        is_older_than(self.created, max_age, Utc::now())
+    }
+}
+
 /// Contains a pair of local link addresses that are paired with a specific wireguard
 /// public/private keypair.
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
@@ -166,3 +188,21 @@ impl fmt::Display for KeygenEvent {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key_age() {
+        let now = Utc::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let created = now - chrono::Duration::days(2);
+
+        assert!(is_older_than(created, day, now));
+        assert!(is_older_than(created, 2 * day, now));
+        assert!(!is_older_than(created, 3 * day, now));
+        // A creation time in the future does not make the key stale
+        assert!(!is_older_than(now + chrono::Duration::days(2), day, now));
+    }
+}
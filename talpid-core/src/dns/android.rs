@@ -1,4 +1,5 @@
 use std::net::IpAddr;
+use talpid_types::net::dns::DnsMechanism;
 
 /// Stub error type for DNS errors on Android.
 #[derive(Debug, err_derive::Error)]
@@ -21,4 +22,8 @@ impl super::DnsMonitorT for DnsMonitor {
     fn reset(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
+
+    fn mechanism(&self) -> Option<DnsMechanism> {
+        Some(DnsMechanism::VpnService)
+    }
 }
@@ -9,6 +9,7 @@ use self::{
 };
 use crate::routing::RouteManagerHandle;
 use std::{env, fmt, net::IpAddr};
+use talpid_types::net::dns::DnsMechanism;
 
 const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
 
@@ -72,6 +73,10 @@ impl super::DnsMonitorT for DnsMonitor {
         }
         Ok(())
     }
+
+    fn mechanism(&self) -> Option<DnsMechanism> {
+        self.inner.as_ref().map(DnsMonitorHolder::mechanism)
+    }
 }
 
 pub enum DnsMonitorHolder {
@@ -95,6 +100,16 @@ impl fmt::Display for DnsMonitorHolder {
 }
 
 impl DnsMonitorHolder {
+    fn mechanism(&self) -> DnsMechanism {
+        use self::DnsMonitorHolder::*;
+        match self {
+            Resolvconf(..) => DnsMechanism::Resolvconf,
+            StaticResolvConf(..) => DnsMechanism::StaticResolvConf,
+            SystemdResolved(..) => DnsMechanism::SystemdResolved,
+            NetworkManager(..) => DnsMechanism::NetworkManager,
+        }
+    }
+
     fn new() -> Result<Self> {
         let dns_module = env::var_os("TALPID_DNS_MODULE");
 
@@ -18,6 +18,7 @@ use system_configuration::{
     dynamic_store::{SCDynamicStore, SCDynamicStoreBuilder, SCDynamicStoreCallBackContext},
     sys::schema_definitions::{kSCPropNetDNSServerAddresses, kSCPropNetInterfaceDeviceName},
 };
+use talpid_types::net::dns::DnsMechanism;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -275,6 +276,10 @@ impl super::DnsMonitorT for DnsMonitor {
         }
         Ok(())
     }
+
+    fn mechanism(&self) -> Option<DnsMechanism> {
+        Some(DnsMechanism::SystemConfiguration)
+    }
 }
 
 impl DnsMonitor {
@@ -1,6 +1,10 @@
 #[cfg(target_os = "linux")]
 use crate::routing::RouteManagerHandle;
-use std::net::IpAddr;
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+use talpid_types::net::dns::{AppliedDnsConfig, DnsMechanism};
 
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
@@ -23,16 +27,36 @@ mod imp;
 
 pub use self::imp::Error;
 
+/// Shared record of the DNS configuration that was most recently applied by a [`DnsMonitor`].
+#[derive(Clone, Default)]
+pub struct AppliedDns {
+    config: Arc<Mutex<Option<AppliedDnsConfig>>>,
+}
+
+impl AppliedDns {
+    /// Returns the applied configuration, or `None` if the system DNS settings are not changed.
+    pub fn get(&self) -> Option<AppliedDnsConfig> {
+        self.config.lock().unwrap().clone()
+    }
+
+    fn set(&self, config: Option<AppliedDnsConfig>) {
+        *self.config.lock().unwrap() = config;
+    }
+}
+
 /// Sets and monitors system DNS settings. Makes sure the desired DNS servers are being used.
 pub struct DnsMonitor {
     inner: imp::DnsMonitor,
+    applied: AppliedDns,
 }
 
 impl DnsMonitor {
-    /// Returns a new `DnsMonitor` that can set and monitor the system DNS.
+    /// Returns a new `DnsMonitor` that can set and monitor the system DNS. Every change it makes
+    /// is recorded in `applied`.
     pub fn new(
         #[cfg(target_os = "linux")] handle: tokio::runtime::Handle,
         #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
+        applied: AppliedDns,
     ) -> Result<Self, Error> {
         Ok(DnsMonitor {
             inner: imp::DnsMonitor::new(
@@ -41,6 +65,7 @@ impl DnsMonitor {
                 #[cfg(target_os = "linux")]
                 route_manager,
             )?,
+            applied,
         })
     }
 
@@ -61,14 +86,23 @@ impl DnsMonitor {
                 .collect::<Vec<String>>()
                 .join(", ")
         );
-        self.inner.set(interface, servers)
+        self.inner.set(interface, servers)?;
+        self.applied
+            .set(self.inner.mechanism().map(|mechanism| AppliedDnsConfig {
+                interface: interface.to_owned(),
+                servers: servers.to_vec(),
+                mechanism,
+            }));
+        Ok(())
     }
 
     /// Reset system DNS settings to what it was before being set by this instance.
     /// This succeeds if the interface does not exist.
     pub fn reset(&mut self) -> Result<(), Error> {
         log::info!("Resetting DNS");
-        self.inner.reset()
+        self.inner.reset()?;
+        self.applied.set(None);
+        Ok(())
     }
 }
 
@@ -87,4 +121,8 @@ trait DnsMonitorT: Sized {
     fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Self::Error>;
 
     fn reset(&mut self) -> Result<(), Self::Error>;
+
+    /// Returns how the servers passed to the last call to `set` were applied, or `None` if they
+    /// were not applied.
+    fn mechanism(&self) -> Option<DnsMechanism>;
 }
@@ -5,7 +5,7 @@ use crate::{
 
 use lazy_static::lazy_static;
 use std::{env, io, net::IpAddr, path::Path};
-use talpid_types::ErrorExt;
+use talpid_types::{net::dns::DnsMechanism, ErrorExt};
 use widestring::WideCString;
 use winapi::shared::ifdef::NET_LUID;
 use winreg::{
@@ -116,6 +116,10 @@ impl super::DnsMonitorT for DnsMonitor {
             Ok(())
         }
     }
+
+    fn mechanism(&self) -> Option<DnsMechanism> {
+        Some(DnsMechanism::WinDns)
+    }
 }
 
 fn ip_to_widestring(ip: &IpAddr) -> WideCString {
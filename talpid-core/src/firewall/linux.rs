@@ -558,6 +558,7 @@ impl<'a> PolicyBatch<'a> {
                 tunnel,
                 allow_lan,
                 allowed_endpoint,
+                ..
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint);
                 self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
@@ -579,6 +580,7 @@ impl<'a> PolicyBatch<'a> {
                 tunnel,
                 allow_lan,
                 dns_servers,
+                ..
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint);
                 self.add_allow_dns_rules(tunnel, &dns_servers, TransportProtocol::Udp)?;
@@ -595,6 +597,7 @@ impl<'a> PolicyBatch<'a> {
             FirewallPolicy::Blocked {
                 allow_lan,
                 allowed_endpoint,
+                ..
             } => {
                 self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
 
@@ -605,7 +608,7 @@ impl<'a> PolicyBatch<'a> {
         };
 
         if allow_lan {
-            self.add_allow_lan_rules();
+            self.add_allow_lan_rules(&policy.allowed_lan_nets());
         }
 
         // Reject any remaining outgoing traffic
@@ -811,11 +814,11 @@ impl<'a> PolicyBatch<'a> {
         }
     }
 
-    fn add_allow_lan_rules(&mut self) {
+    fn add_allow_lan_rules(&mut self, lan_nets: &[IpNetwork]) {
         // Output and forward chains
         for chain in &[&self.out_chain, &self.forward_chain] {
             // LAN -> LAN
-            for net in &*super::ALLOWED_LAN_NETS {
+            for net in lan_nets {
                 let mut out_rule = Rule::new(chain);
                 check_net(&mut out_rule, End::Dst, *net);
                 add_verdict(&mut out_rule, &Verdict::Accept);
@@ -833,7 +836,7 @@ impl<'a> PolicyBatch<'a> {
 
         // Input chain
         // LAN -> LAN
-        for net in &*super::ALLOWED_LAN_NETS {
+        for net in lan_nets {
             let mut in_rule = Rule::new(&self.in_chain);
             check_net(&mut in_rule, End::Src, *net);
             add_verdict(&mut in_rule, &Verdict::Accept);
@@ -1029,3 +1032,59 @@ fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
     }
     rule.add_expr(verdict);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::AllowedEndpoint;
+
+    fn serialize_policy(policy: &FirewallPolicy) -> Vec<u8> {
+        let tables = FirewallTables {
+            main: Table::new(&*TABLE_NAME, ProtoFamily::Inet),
+            mangle_v4: Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
+            mangle_v6: Table::new(&*MANGLE_TABLE_NAME_V6, ProtoFamily::Ipv6),
+        };
+        let batch = PolicyBatch::new(&tables)
+            .finalize(policy)
+            .expect("failed to generate rules");
+        (&batch).into_iter().flatten().copied().collect()
+    }
+
+    fn count_occurrences(haystack: &[u8], needle: &[u8]) -> usize {
+        haystack
+            .windows(needle.len())
+            .filter(|window| *window == needle)
+            .count()
+    }
+
+    fn blocked_policy(allow_lan: bool, allowed_networks: Vec<IpNetwork>) -> FirewallPolicy {
+        FirewallPolicy::Blocked {
+            allow_lan,
+            allowed_networks,
+            allowed_endpoint: AllowedEndpoint {
+                endpoint: Endpoint::new(
+                    Ipv4Addr::new(193, 138, 218, 78),
+                    443,
+                    TransportProtocol::Tcp,
+                ),
+            },
+        }
+    }
+
+    #[test]
+    fn test_allowed_network_rules() {
+        let network: IpNetwork = "198.18.0.0/15".parse().unwrap();
+        let network_bytes = [198, 18, 0, 0];
+
+        // One rule each in the output, forward and input chains
+        let rules = serialize_policy(&blocked_policy(true, vec![network]));
+        assert_eq!(count_occurrences(&rules, &network_bytes), 3);
+
+        let rules = serialize_policy(&blocked_policy(true, vec![]));
+        assert_eq!(count_occurrences(&rules, &network_bytes), 0);
+
+        // Allowed networks are not used when LAN access is blocked
+        let rules = serialize_policy(&blocked_policy(false, vec![network]));
+        assert_eq!(count_occurrences(&rules, &network_bytes), 0);
+    }
+}
@@ -119,6 +119,7 @@ impl Firewall {
                 tunnel,
                 allow_lan,
                 allowed_endpoint,
+                ..
             } => {
                 let mut rules = vec![self.get_allow_relay_rule(*peer_endpoint)?];
                 rules.push(self.get_allowed_endpoint_rule(allowed_endpoint.endpoint)?);
@@ -132,7 +133,7 @@ impl Firewall {
                 }
 
                 if *allow_lan {
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(&policy.allowed_lan_nets())?);
                 }
                 Ok(rules)
             }
@@ -141,6 +142,7 @@ impl Firewall {
                 tunnel,
                 allow_lan,
                 dns_servers,
+                ..
             } => {
                 let mut rules = vec![];
 
@@ -157,7 +159,7 @@ impl Firewall {
                 rules.push(self.get_allow_tunnel_rule(tunnel.interface.as_str())?);
 
                 if *allow_lan {
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(&policy.allowed_lan_nets())?);
                 }
 
                 Ok(rules)
@@ -173,7 +175,7 @@ impl Firewall {
                 if *allow_lan {
                     // Important to block DNS before allow LAN (so DNS does not leak to the LAN)
                     rules.append(&mut self.get_block_dns_rules()?);
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(&policy.allowed_lan_nets())?);
                 }
 
                 Ok(rules)
@@ -338,9 +340,9 @@ impl Firewall {
         Ok(vec![lo0_rule])
     }
 
-    fn get_allow_lan_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
+    fn get_allow_lan_rules(&self, lan_nets: &[IpNetwork]) -> Result<Vec<pfctl::FilterRule>> {
         let mut rules = vec![];
-        for net in &*super::ALLOWED_LAN_NETS {
+        for net in lan_nets {
             let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
             rule_builder.quick(true);
             let allow_out = rule_builder
@@ -1,5 +1,6 @@
+use ipnetwork::IpNetwork;
 #[cfg(unix)]
-use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use ipnetwork::{Ipv4Network, Ipv6Network};
 #[cfg(unix)]
 use lazy_static::lazy_static;
 use std::fmt;
@@ -109,6 +110,8 @@ pub enum FirewallPolicy {
         tunnel: Option<crate::tunnel::TunnelMetadata>,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Networks outside of the private ranges that are treated as LAN when `allow_lan` is set.
+        allowed_networks: Vec<IpNetwork>,
         /// Host that should be reachable while connecting.
         allowed_endpoint: AllowedEndpoint,
         /// A process that is allowed to send packets to the relay.
@@ -124,6 +127,8 @@ pub enum FirewallPolicy {
         tunnel: crate::tunnel::TunnelMetadata,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Networks outside of the private ranges that are treated as LAN when `allow_lan` is set.
+        allowed_networks: Vec<IpNetwork>,
         /// Servers that are allowed to respond to DNS requests.
         #[cfg(not(target_os = "android"))]
         dns_servers: Vec<IpAddr>,
@@ -136,6 +141,8 @@ pub enum FirewallPolicy {
     Blocked {
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Networks outside of the private ranges that are treated as LAN when `allow_lan` is set.
+        allowed_networks: Vec<IpNetwork>,
         /// Host that should be reachable while in the blocked state.
         allowed_endpoint: AllowedEndpoint,
         /// Desination port for DNS traffic redirection. Traffic destined to `127.0.0.1:53` will be
@@ -213,6 +220,39 @@ impl fmt::Display for FirewallPolicy {
     }
 }
 
+impl FirewallPolicy {
+    /// Returns the networks that traffic is allowed to and from outside the tunnel when LAN access
+    /// is allowed. This is empty if LAN access is blocked. Multicast networks are not included.
+    #[cfg(all(unix, not(target_os = "android")))]
+    pub fn allowed_lan_nets(&self) -> Vec<IpNetwork> {
+        let (allow_lan, allowed_networks) = match self {
+            FirewallPolicy::Connecting {
+                allow_lan,
+                allowed_networks,
+                ..
+            }
+            | FirewallPolicy::Connected {
+                allow_lan,
+                allowed_networks,
+                ..
+            }
+            | FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_networks,
+                ..
+            } => (*allow_lan, allowed_networks),
+        };
+        if !allow_lan {
+            return vec![];
+        }
+        ALLOWED_LAN_NETS
+            .iter()
+            .chain(allowed_networks.iter())
+            .cloned()
+            .collect()
+    }
+}
+
 /// Manages network security of the computer/device. Can apply and enforce firewall policies
 /// by manipulating the OS firewall and DNS settings.
 pub struct Firewall {
@@ -264,3 +304,43 @@ impl Firewall {
         self.inner.reset_policy()
     }
 }
+
+#[cfg(all(test, unix, not(target_os = "android")))]
+mod test {
+    use super::*;
+
+    fn blocked_policy(allow_lan: bool, allowed_networks: Vec<IpNetwork>) -> FirewallPolicy {
+        FirewallPolicy::Blocked {
+            allow_lan,
+            allowed_networks,
+            allowed_endpoint: AllowedEndpoint {
+                endpoint: Endpoint::new(
+                    Ipv4Addr::new(193, 138, 218, 78),
+                    443,
+                    talpid_types::net::TransportProtocol::Tcp,
+                ),
+            },
+            #[cfg(target_os = "macos")]
+            dns_redirect_port: 53,
+        }
+    }
+
+    #[test]
+    fn test_allowed_lan_nets() {
+        let extra_net: IpNetwork = "100.64.0.0/10".parse().unwrap();
+
+        let nets = blocked_policy(true, vec![extra_net]).allowed_lan_nets();
+        assert_eq!(nets.len(), ALLOWED_LAN_NETS.len() + 1);
+        assert!(ALLOWED_LAN_NETS.iter().all(|net| nets.contains(net)));
+        assert!(nets.contains(&extra_net));
+
+        assert_eq!(
+            blocked_policy(true, vec![]).allowed_lan_nets(),
+            ALLOWED_LAN_NETS.to_vec()
+        );
+        // Allowed networks are only used if LAN access is allowed
+        assert!(blocked_policy(false, vec![extra_net])
+            .allowed_lan_nets()
+            .is_empty());
+    }
+}
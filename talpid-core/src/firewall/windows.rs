@@ -1,5 +1,6 @@
 use crate::{logging::windows::log_sink, tunnel::TunnelMetadata};
 
+use ipnetwork::IpNetwork;
 use std::{net::IpAddr, path::Path, ptr};
 
 use self::winfw::*;
@@ -101,9 +102,12 @@ impl Firewall {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                allowed_networks,
                 allowed_endpoint,
                 relay_client,
             } => {
+                Self::warn_unsupported_allowed_networks(&allowed_networks);
+
                 let cfg = &WinFwSettings::new(allow_lan);
 
                 self.set_connecting_state(
@@ -118,16 +122,20 @@ impl Firewall {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                allowed_networks,
                 dns_servers,
                 relay_client,
             } => {
+                Self::warn_unsupported_allowed_networks(&allowed_networks);
                 let cfg = &WinFwSettings::new(allow_lan);
                 self.set_connected_state(&peer_endpoint, &cfg, &tunnel, &dns_servers, &relay_client)
             }
             FirewallPolicy::Blocked {
                 allow_lan,
+                allowed_networks,
                 allowed_endpoint,
             } => {
+                Self::warn_unsupported_allowed_networks(&allowed_networks);
                 let cfg = &WinFwSettings::new(allow_lan);
                 self.set_blocked_state(
                     &cfg,
@@ -137,6 +145,17 @@ impl Firewall {
         }
     }
 
+    /// WinFw only knows about the standard private ranges, so additional networks cannot be
+    /// allowed yet.
+    fn warn_unsupported_allowed_networks(allowed_networks: &[IpNetwork]) {
+        if !allowed_networks.is_empty() {
+            log::warn!(
+                "Ignoring allowed networks since they are not supported on Windows: {:?}",
+                allowed_networks
+            );
+        }
+    }
+
     pub fn reset_policy(&mut self) -> Result<(), Error> {
         unsafe { WinFw_Reset().into_result().map_err(Error::ResettingPolicy) }?;
         Ok(())
@@ -262,6 +262,11 @@ impl OpenVpnCommand {
             args.push(OsString::from(mssfix.to_string()));
         }
 
+        if let Some(data_cipher) = self.tunnel_options.data_cipher {
+            args.push(OsString::from("--data-ciphers"));
+            args.push(OsString::from(data_cipher.as_str()));
+        }
+
         if !self.enable_ipv6 {
             args.push(OsString::from("--pull-filter"));
             args.push(OsString::from("ignore"));
@@ -480,7 +485,10 @@ impl Drop for OpenVpnProcHandle {
 mod tests {
     use super::OpenVpnCommand;
     use std::{ffi::OsString, net::Ipv4Addr};
-    use talpid_types::net::{Endpoint, TransportProtocol};
+    use talpid_types::net::{
+        openvpn::{DataCipher, TunnelOptions},
+        Endpoint, TransportProtocol,
+    };
 
     #[test]
     fn passes_one_remote() {
@@ -507,4 +515,59 @@ mod tests {
         assert!(testee_args.contains(&OsString::from("123")));
         assert!(testee_args.contains(&OsString::from("cde")));
     }
+
+    #[test]
+    fn passes_mssfix() {
+        let options = TunnelOptions {
+            mssfix: Some(1400),
+            ..TunnelOptions::default()
+        };
+        let testee_args = OpenVpnCommand::new("")
+            .tunnel_options(&options)
+            .get_arguments();
+        let position = testee_args
+            .iter()
+            .position(|arg| arg == &OsString::from("--mssfix"))
+            .expect("missing --mssfix argument");
+        assert_eq!(testee_args[position + 1], OsString::from("1400"));
+    }
+
+    #[test]
+    fn omits_mssfix_by_default() {
+        let testee_args = OpenVpnCommand::new("").get_arguments();
+        assert!(!testee_args.contains(&OsString::from("--mssfix")));
+    }
+
+    #[test]
+    fn passes_data_cipher() {
+        let options = TunnelOptions {
+            data_cipher: Some(DataCipher::Chacha20Poly1305),
+            ..TunnelOptions::default()
+        };
+        let testee_args = OpenVpnCommand::new("")
+            .tunnel_options(&options)
+            .get_arguments();
+        let position = testee_args
+            .iter()
+            .position(|arg| arg == &OsString::from("--data-ciphers"))
+            .expect("missing --data-ciphers argument");
+        assert_eq!(
+            testee_args[position + 1],
+            OsString::from("CHACHA20-POLY1305")
+        );
+    }
+
+    #[test]
+    fn omits_data_cipher_by_default() {
+        let testee_args = OpenVpnCommand::new("").get_arguments();
+        assert!(!testee_args.contains(&OsString::from("--data-ciphers")));
+    }
+
+    #[test]
+    fn rejects_out_of_range_mssfix() {
+        assert!(!TunnelOptions::is_valid_mssfix(999));
+        assert!(TunnelOptions::is_valid_mssfix(1000));
+        assert!(TunnelOptions::is_valid_mssfix(1450));
+        assert!(!TunnelOptions::is_valid_mssfix(1451));
+    }
 }
@@ -3,7 +3,7 @@ use std::{
     ffi::CString,
     net::{Ipv4Addr, Ipv6Addr},
 };
-use talpid_types::net::{wireguard, GenericTunnelOptions};
+use talpid_types::net::{wireguard, GenericTunnelOptions, TransportProtocol};
 
 /// Config required to set up a single WireGuard tunnel
 pub struct Config {
@@ -17,6 +17,10 @@ pub struct Config {
     pub ipv6_gateway: Option<Ipv6Addr>,
     /// Maximum transmission unit for the tunnel
     pub mtu: u16,
+    /// Interval in seconds between keepalive packets sent to each peer
+    pub persistent_keepalive: Option<u16>,
+    /// Bandwidth limit that is applied to the tunnel interface
+    pub rate_limit: Option<wireguard::RateLimit>,
     /// Firewall mark
     #[cfg(target_os = "linux")]
     pub fwmark: u32,
@@ -30,6 +34,11 @@ pub struct Config {
 
 const DEFAULT_MTU: u16 = 1380;
 
+/// Number of bytes by which the MTU is reduced when traffic is proxied using udp2tcp. The TCP
+/// header, including the timestamp option, replaces the UDP header, and each datagram is prefixed
+/// by its length.
+const UDP2TCP_MTU_OVERHEAD: u16 = 20 + 12 - 8 + 2;
+
 /// Configuration errors
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -74,7 +83,28 @@ impl Config {
         if peers.is_empty() {
             return Err(Error::NoPeersSuppliedError);
         }
-        let mtu = wg_options.mtu.unwrap_or(DEFAULT_MTU);
+        let mut mtu = wg_options.mtu.unwrap_or(DEFAULT_MTU);
+        if peers
+            .iter()
+            .any(|peer| peer.protocol == TransportProtocol::Tcp)
+        {
+            let reduced_mtu = mtu
+                .saturating_sub(UDP2TCP_MTU_OVERHEAD)
+                .max(wireguard::MIN_MTU);
+            log::info!(
+                "Reducing tunnel MTU from {} to {} since udp2tcp is used",
+                mtu,
+                reduced_mtu
+            );
+            mtu = reduced_mtu;
+            if let Some(interval) = wg_options.persistent_keepalive {
+                log::warn!(
+                    "Persistent keepalive is enabled while udp2tcp is used. Keepalive packets are \
+                     sent over the TCP connection every {} seconds, which keeps it busy",
+                    interval
+                );
+            }
+        }
         for peer in &mut peers {
             peer.allowed_ips = peer
                 .allowed_ips
@@ -108,6 +138,8 @@ impl Config {
             ipv4_gateway: connection_config.ipv4_gateway,
             ipv6_gateway,
             mtu,
+            persistent_keepalive: wg_options.persistent_keepalive,
+            rate_limit: wg_options.rate_limit,
             #[cfg(target_os = "linux")]
             fwmark: crate::linux::TUNNEL_FW_MARK,
             #[cfg(target_os = "linux")]
@@ -136,6 +168,12 @@ impl Config {
                 .add("public_key", peer.public_key.as_bytes().as_ref())
                 .add("endpoint", peer.endpoint.to_string().as_str())
                 .add("replace_allowed_ips", "true");
+            if let Some(interval) = self.persistent_keepalive {
+                wg_conf.add(
+                    "persistent_keepalive_interval",
+                    interval.to_string().as_str(),
+                );
+            }
             for addr in &peer.allowed_ips {
                 wg_conf.add("allowed_ip", addr.to_string().as_str());
             }
@@ -194,3 +232,103 @@ impl WgConfigBuffer {
         self.buf
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn config_with_protocol(protocol: TransportProtocol, mtu: Option<u16>) -> Config {
+        config_with_options(
+            protocol,
+            wireguard::TunnelOptions {
+                mtu,
+                ..wireguard::TunnelOptions::default()
+            },
+        )
+    }
+
+    fn config_with_options(
+        protocol: TransportProtocol,
+        options: wireguard::TunnelOptions,
+    ) -> Config {
+        let private_key = wireguard::PrivateKey::new_from_random();
+        let peer = wireguard::PeerConfig {
+            public_key: private_key.public_key(),
+            allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+            endpoint: SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 51820),
+            protocol,
+        };
+        let tunnel = wireguard::TunnelConfig {
+            private_key,
+            addresses: vec![Ipv4Addr::new(10, 64, 0, 2).into()],
+        };
+        let connection_config = wireguard::ConnectionConfig {
+            tunnel: tunnel.clone(),
+            peer: peer.clone(),
+            exit_peer: None,
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: None,
+        };
+        Config::new(
+            tunnel,
+            vec![peer],
+            &connection_config,
+            &options,
+            &GenericTunnelOptions { enable_ipv6: false },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_mtu() {
+        let config = config_with_protocol(TransportProtocol::Udp, None);
+        assert_eq!(config.mtu, DEFAULT_MTU);
+        let config = config_with_protocol(TransportProtocol::Udp, Some(1300));
+        assert_eq!(config.mtu, 1300);
+    }
+
+    #[test]
+    fn test_udp2tcp_mtu_reduction() {
+        let config = config_with_protocol(TransportProtocol::Tcp, None);
+        assert_eq!(config.mtu, DEFAULT_MTU - UDP2TCP_MTU_OVERHEAD);
+        // The MTU is never reduced below the IPv6 minimum.
+        let config = config_with_protocol(TransportProtocol::Tcp, Some(wireguard::MIN_MTU));
+        assert_eq!(config.mtu, wireguard::MIN_MTU);
+    }
+
+    #[test]
+    fn test_persistent_keepalive_in_userspace_config() {
+        let config = config_with_options(
+            TransportProtocol::Udp,
+            wireguard::TunnelOptions {
+                persistent_keepalive: Some(25),
+                ..wireguard::TunnelOptions::default()
+            },
+        );
+        let userspace_config = config.to_userspace_format().into_string().unwrap();
+        assert!(userspace_config.contains("\npersistent_keepalive_interval=25\n"));
+
+        let config = config_with_protocol(TransportProtocol::Udp, None);
+        let userspace_config = config.to_userspace_format().into_string().unwrap();
+        assert!(!userspace_config.contains("persistent_keepalive_interval"));
+    }
+
+    #[test]
+    fn test_persistent_keepalive_range() {
+        assert!(!wireguard::TunnelOptions::is_valid_persistent_keepalive(9));
+        assert!(wireguard::TunnelOptions::is_valid_persistent_keepalive(10));
+        assert!(wireguard::TunnelOptions::is_valid_persistent_keepalive(120));
+        assert!(!wireguard::TunnelOptions::is_valid_persistent_keepalive(
+            121
+        ));
+    }
+
+    #[test]
+    fn test_mtu_range() {
+        assert!(!wireguard::TunnelOptions::is_valid_mtu(1279));
+        assert!(wireguard::TunnelOptions::is_valid_mtu(1280));
+        assert!(wireguard::TunnelOptions::is_valid_mtu(1420));
+        assert!(!wireguard::TunnelOptions::is_valid_mtu(1421));
+    }
+}
@@ -29,6 +29,7 @@ use udp_over_tcp::{TcpOptions, Udp2Tcp};
 pub mod config;
 mod connectivity_check;
 mod logging;
+pub mod rate_limit;
 mod stats;
 mod wireguard_go;
 #[cfg(target_os = "linux")]
@@ -68,6 +69,10 @@ pub enum Error {
     #[error(display = "Connectivity monitor failed")]
     ConnectivityMonitorError(#[error(source)] connectivity_check::Error),
 
+    /// Failed to limit the bandwidth of the tunnel
+    #[error(display = "Failed to limit the tunnel bandwidth")]
+    RateLimitError(#[error(source)] rate_limit::Error),
+
     /// Failed to set up IP interfaces.
     #[cfg(windows)]
     #[error(display = "Failed to set up IP interfaces")]
@@ -93,6 +98,8 @@ pub struct WireguardMonitor {
     >,
     close_msg_receiver: sync_mpsc::Receiver<CloseMsg>,
     pinger_stop_sender: sync_mpsc::Sender<()>,
+    /// Interface whose bandwidth is limited, if any
+    rate_limited_interface: Option<String>,
     _tcp_proxies: Vec<TcpProxy>,
 }
 
@@ -211,6 +218,7 @@ impl WireguardMonitor {
             event_callback,
             close_msg_receiver,
             pinger_stop_sender: pinger_tx,
+            rate_limited_interface: config.rate_limit.map(|_| iface_name.clone()),
             _tcp_proxies: tcp_proxies,
         };
 
@@ -251,6 +259,18 @@ impl WireguardMonitor {
 
             (on_event)(TunnelEvent::InterfaceUp(metadata.clone())).await;
 
+            if let Some(limit) = config.rate_limit {
+                let iface_name = iface_name.clone();
+                tokio::task::spawn_blocking(move || {
+                    rate_limit::platform_shaper().apply(&iface_name, limit)
+                })
+                .await
+                .map_err(rate_limit::Error::ShaperTaskFailed)
+                .and_then(|result| result)
+                .map_err(Error::RateLimitError)
+                .map_err(CloseMsg::SetupError)?;
+            }
+
             // Add non-default routes before establishing the tunnel.
             #[cfg(target_os = "linux")]
             route_manager
@@ -420,6 +440,15 @@ impl WireguardMonitor {
 
         let _ = self.pinger_stop_sender.send(());
 
+        if let Some(interface) = self.rate_limited_interface.take() {
+            // The limits are also removed along with the interface
+            if let Err(error) = rate_limit::platform_shaper().remove(&interface) {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to remove the tunnel bandwidth limit")
+                );
+            }
+        }
         self.stop_tunnel();
 
         self.runtime
@@ -0,0 +1,285 @@
+//! Limits the bandwidth of the tunnel by shaping the traffic of the tunnel interface. Only Linux
+//! is supported, where the limits are enforced using `tc`. Traffic sent through the tunnel is
+//! queued by an HTB qdisc, and traffic received through the tunnel is policed at ingress.
+
+use talpid_types::net::wireguard::RateLimit;
+
+/// Errors that can happen when limiting the bandwidth of the tunnel.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Traffic shaping is not implemented on this platform.
+    #[error(display = "Limiting the tunnel bandwidth is not supported on this platform")]
+    Unsupported,
+
+    /// The limit is too low to be enforced.
+    #[error(display = "Invalid bandwidth limit: {}", _0)]
+    InvalidLimit(RateLimit),
+
+    /// The task that applies the limit panicked or was cancelled.
+    #[error(display = "The traffic shaping task failed")]
+    ShaperTaskFailed(#[error(source)] tokio::task::JoinError),
+
+    /// Failed to start the traffic control tool.
+    #[cfg(target_os = "linux")]
+    #[error(display = "Failed to run tc")]
+    RunTc(#[error(source)] std::io::Error),
+
+    /// The traffic control tool returned an error.
+    #[cfg(target_os = "linux")]
+    #[error(display = "tc {} failed: {}", _0, _1)]
+    TcFailed(String, String),
+}
+
+/// Applies and removes bandwidth limits on a tunnel interface.
+pub(crate) trait TrafficShaper: Send + Sync {
+    /// Limits the bandwidth of `interface`, replacing any limit that is already applied.
+    fn apply(&self, interface: &str, limit: RateLimit) -> Result<(), Error>;
+
+    /// Removes any limit from `interface`.
+    fn remove(&self, interface: &str) -> Result<(), Error>;
+}
+
+/// Returns the traffic shaper for this platform.
+pub(crate) fn platform_shaper() -> Box<dyn TrafficShaper> {
+    #[cfg(target_os = "linux")]
+    {
+        Box::new(linux::TcShaper)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        Box::new(UnsupportedShaper)
+    }
+}
+
+/// Checks that `limit` can be enforced on this platform, so that the limit can be rejected when
+/// it is set rather than when the tunnel is connected.
+pub fn check_supported(limit: RateLimit) -> Result<(), Error> {
+    if !limit.is_valid() {
+        return Err(Error::InvalidLimit(limit));
+    }
+    if cfg!(target_os = "linux") {
+        Ok(())
+    } else {
+        Err(Error::Unsupported)
+    }
+}
+
+/// Traffic shaper for platforms where traffic shaping is not implemented.
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+struct UnsupportedShaper;
+
+impl TrafficShaper for UnsupportedShaper {
+    fn apply(&self, _interface: &str, _limit: RateLimit) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn remove(&self, _interface: &str) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{Error, RateLimit, TrafficShaper};
+
+    /// Handle of the qdisc that shapes outgoing traffic.
+    const ROOT_HANDLE: &str = "1:";
+    /// Class that all outgoing traffic is assigned to.
+    const ROOT_CLASS: &str = "1:1";
+    /// Handle of the ingress qdisc.
+    const INGRESS_HANDLE: &str = "ffff:";
+    /// Smallest burst, in bytes, that is allowed by the ingress policer. This must be larger
+    /// than a packet, or every packet is dropped.
+    const MIN_BURST_BYTES: u64 = 16 * 1024;
+
+    /// Enforces limits using `tc`.
+    pub struct TcShaper;
+
+    impl TrafficShaper for TcShaper {
+        fn apply(&self, interface: &str, limit: RateLimit) -> Result<(), Error> {
+            if !limit.is_valid() {
+                return Err(Error::InvalidLimit(limit));
+            }
+            // Remove any previous limit, so that the qdiscs can be added from scratch
+            let _ = self.remove(interface);
+            for args in apply_commands(interface, limit) {
+                run_tc(&args)?;
+            }
+            log::debug!("Limited the bandwidth of {} to {}", interface, limit);
+            Ok(())
+        }
+
+        fn remove(&self, interface: &str) -> Result<(), Error> {
+            // Both commands are run even if the first qdisc does not exist
+            let results: Vec<_> = remove_commands(interface)
+                .iter()
+                .map(|args| run_tc(args))
+                .collect();
+            results.into_iter().collect()
+        }
+    }
+
+    /// Returns the `tc` arguments that limit the bandwidth of `interface`.
+    pub fn apply_commands(interface: &str, limit: RateLimit) -> Vec<Vec<String>> {
+        let up_rate = format!("{}kbit", limit.up_kbps);
+        let down_rate = format!("{}kbit", limit.down_kbps);
+        let burst = burst_bytes(limit.down_kbps);
+        [
+            format!(
+                "qdisc add dev {} root handle {} htb default 1",
+                interface, ROOT_HANDLE
+            ),
+            format!(
+                "class add dev {} parent {} classid {} htb rate {} ceil {}",
+                interface, ROOT_HANDLE, ROOT_CLASS, up_rate, up_rate
+            ),
+            format!(
+                "qdisc add dev {} handle {} ingress",
+                interface, INGRESS_HANDLE
+            ),
+            format!(
+                "filter add dev {} parent {} protocol all prio 1 matchall action police rate {} \
+                 burst {} drop",
+                interface, INGRESS_HANDLE, down_rate, burst
+            ),
+        ]
+        .iter()
+        .map(|command| split_args(command))
+        .collect()
+    }
+
+    /// Returns the `tc` arguments that remove the limits from `interface`.
+    pub fn remove_commands(interface: &str) -> Vec<Vec<String>> {
+        [
+            format!("qdisc del dev {} root", interface),
+            format!("qdisc del dev {} ingress", interface),
+        ]
+        .iter()
+        .map(|command| split_args(command))
+        .collect()
+    }
+
+    /// Returns the burst that the ingress policer allows, which is 100 ms worth of traffic.
+    fn burst_bytes(rate_kbps: u32) -> u64 {
+        (u64::from(rate_kbps) * 1000 / 8 / 10).max(MIN_BURST_BYTES)
+    }
+
+    /// Splits a command on whitespace. Interface names cannot contain whitespace.
+    fn split_args(command: &str) -> Vec<String> {
+        command.split_whitespace().map(str::to_owned).collect()
+    }
+
+    fn run_tc(args: &[String]) -> Result<(), Error> {
+        let output = duct::cmd("tc", args)
+            .stdout_null()
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .map_err(Error::RunTc)?;
+        if !output.status.success() {
+            return Err(Error::TcFailed(
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        fn joined(commands: Vec<Vec<String>>) -> Vec<String> {
+            commands.into_iter().map(|args| args.join(" ")).collect()
+        }
+
+        #[test]
+        fn test_apply_commands() {
+            let limit = RateLimit {
+                up_kbps: 5000,
+                down_kbps: 20000,
+            };
+            assert_eq!(
+                joined(apply_commands("wg-mullvad", limit)),
+                vec![
+                    "qdisc add dev wg-mullvad root handle 1: htb default 1",
+                    "class add dev wg-mullvad parent 1: classid 1:1 htb rate 5000kbit ceil 5000kbit",
+                    "qdisc add dev wg-mullvad handle ffff: ingress",
+                    "filter add dev wg-mullvad parent ffff: protocol all prio 1 matchall action \
+                     police rate 20000kbit burst 250000 drop",
+                ]
+            );
+        }
+
+        #[test]
+        fn test_remove_commands() {
+            assert_eq!(
+                joined(remove_commands("wg-mullvad")),
+                vec![
+                    "qdisc del dev wg-mullvad root",
+                    "qdisc del dev wg-mullvad ingress",
+                ]
+            );
+        }
+
+        #[test]
+        fn test_burst() {
+            assert_eq!(burst_bytes(20000), 250_000);
+            // Low rates still allow a full packet through
+            assert_eq!(burst_bytes(64), MIN_BURST_BYTES);
+        }
+
+        #[test]
+        fn test_invalid_limit_is_not_applied() {
+            let limit = RateLimit {
+                up_kbps: 0,
+                down_kbps: 20000,
+            };
+            assert!(matches!(
+                TcShaper.apply("wg-mullvad", limit),
+                Err(Error::InvalidLimit(_))
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unsupported_shaper() {
+        let limit = RateLimit {
+            up_kbps: 5000,
+            down_kbps: 20000,
+        };
+        assert!(matches!(
+            UnsupportedShaper.apply("wg-mullvad", limit),
+            Err(Error::Unsupported)
+        ));
+        assert!(UnsupportedShaper.remove("wg-mullvad").is_ok());
+    }
+
+    #[test]
+    fn test_check_supported() {
+        let limit = RateLimit {
+            up_kbps: 5000,
+            down_kbps: 20000,
+        };
+        if cfg!(target_os = "linux") {
+            assert!(check_supported(limit).is_ok());
+        } else {
+            assert!(matches!(check_supported(limit), Err(Error::Unsupported)));
+        }
+
+        let too_low = RateLimit {
+            up_kbps: 5000,
+            down_kbps: 1,
+        };
+        assert!(matches!(
+            check_supported(too_low),
+            Err(Error::InvalidLimit(_))
+        ));
+    }
+}
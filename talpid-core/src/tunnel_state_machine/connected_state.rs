@@ -1,7 +1,7 @@
 use super::{
-    AfterDisconnect, ConnectingState, DisconnectingState, ErrorState, EventConsequence,
-    EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver, TunnelState,
-    TunnelStateTransition, TunnelStateWrapper,
+    connection_metrics::ConnectionAttempt, AfterDisconnect, ConnectingState, DisconnectingState,
+    ErrorState, EventConsequence, EventResult, SharedTunnelStateValues, TunnelCommand,
+    TunnelCommandReceiver, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::{
     firewall::FirewallPolicy,
@@ -13,10 +13,10 @@ use futures::{
     stream::Fuse,
     StreamExt,
 };
-use std::net::IpAddr;
+use std::{net::IpAddr, time::Instant};
 use talpid_types::{
     net::TunnelParameters,
-    tunnel::{ErrorStateCause, FirewallPolicyError},
+    tunnel::{ConnectionAttemptOutcome, ErrorStateCause, FirewallPolicyError},
     BoxedError, ErrorExt,
 };
 
@@ -34,6 +34,8 @@ pub struct ConnectedStateBootstrap {
     pub tunnel_parameters: TunnelParameters,
     pub tunnel_close_event: TunnelCloseEvent,
     pub tunnel_close_tx: oneshot::Sender<()>,
+    /// The connection attempt that is completed by entering the connected state.
+    pub attempt: Option<ConnectionAttempt>,
 }
 
 /// The tunnel is up and working.
@@ -110,6 +112,7 @@ impl ConnectedState {
             peer_endpoint: self.tunnel_parameters.get_next_hop_endpoint(),
             tunnel: self.metadata.clone(),
             allow_lan: shared_values.allow_lan,
+            allowed_networks: shared_values.allowed_networks.clone(),
             #[cfg(not(target_os = "android"))]
             dns_servers: self.get_dns_servers(shared_values),
             #[cfg(windows)]
@@ -210,6 +213,16 @@ impl ConnectedState {
                     }
                 }
             }
+            Some(TunnelCommand::AllowedNetworks(allowed_networks)) => {
+                shared_values.allowed_networks = allowed_networks;
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                 shared_values.allowed_endpoint = endpoint;
                 if let Err(_) = tx.send(()) {
@@ -297,6 +310,18 @@ impl ConnectedState {
         }
     }
 
+    fn finish_attempt(
+        attempt: Option<ConnectionAttempt>,
+        setup_start: Instant,
+        outcome: ConnectionAttemptOutcome,
+        shared_values: &SharedTunnelStateValues,
+    ) {
+        if let Some(mut attempt) = attempt {
+            attempt.set_connected_setup(setup_start.elapsed());
+            attempt.finish(outcome, &shared_values.connection_metrics);
+        }
+    }
+
     fn handle_tunnel_close_event(
         self,
         block_reason: Option<ErrorStateCause>,
@@ -323,12 +348,20 @@ impl TunnelState for ConnectedState {
     #[cfg_attr(target_os = "android", allow(unused_variables))]
     fn enter(
         shared_values: &mut SharedTunnelStateValues,
-        bootstrap: Self::Bootstrap,
+        mut bootstrap: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        let attempt = bootstrap.attempt.take();
         let connected_state = ConnectedState::from(bootstrap);
         let tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
+        let setup_start = Instant::now();
 
         if let Err(error) = connected_state.set_firewall_policy(shared_values) {
+            Self::finish_attempt(
+                attempt,
+                setup_start,
+                ConnectionAttemptOutcome::Failed,
+                shared_values,
+            );
             DisconnectingState::enter(
                 shared_values,
                 (
@@ -339,6 +372,12 @@ impl TunnelState for ConnectedState {
             )
         } else if let Err(error) = connected_state.set_dns(shared_values) {
             log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
+            Self::finish_attempt(
+                attempt,
+                setup_start,
+                ConnectionAttemptOutcome::Failed,
+                shared_values,
+            );
             DisconnectingState::enter(
                 shared_values,
                 (
@@ -348,6 +387,12 @@ impl TunnelState for ConnectedState {
                 ),
             )
         } else {
+            Self::finish_attempt(
+                attempt,
+                setup_start,
+                ConnectionAttemptOutcome::Connected,
+                shared_values,
+            );
             (
                 TunnelStateWrapper::from(connected_state),
                 TunnelStateTransition::Connected(tunnel_endpoint),
@@ -376,7 +421,7 @@ impl TunnelState for ConnectedState {
                 if result.is_err() {
                     log::warn!("Tunnel monitor thread has stopped unexpectedly");
                 }
-                let block_reason = result.unwrap_or(None);
+                let block_reason = result.unwrap_or_default().block_reason;
                 self.handle_tunnel_close_event(block_reason, shared_values)
             }
         }
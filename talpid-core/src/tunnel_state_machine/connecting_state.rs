@@ -1,7 +1,8 @@
 use super::{
-    AfterDisconnect, ConnectedState, ConnectedStateBootstrap, DisconnectingState, ErrorState,
-    EventConsequence, EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver,
-    TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    connection_metrics::ConnectionAttempt, AfterDisconnect, ConnectedState,
+    ConnectedStateBootstrap, DisconnectingState, ErrorState, EventConsequence, EventResult,
+    SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver, TunnelState,
+    TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::{
     firewall::FirewallPolicy,
@@ -22,7 +23,7 @@ use std::{
 };
 use talpid_types::{
     net::TunnelParameters,
-    tunnel::{ErrorStateCause, FirewallPolicyError},
+    tunnel::{ConnectionAttemptOutcome, ErrorStateCause, FirewallPolicyError},
     ErrorExt,
 };
 
@@ -34,7 +35,26 @@ use crate::tunnel::tun_provider;
 
 use super::connected_state::TunnelEventsReceiver;
 
-pub(crate) type TunnelCloseEvent = Fuse<oneshot::Receiver<Option<ErrorStateCause>>>;
+pub(crate) type TunnelCloseEvent = Fuse<oneshot::Receiver<TunnelCloseReason>>;
+
+/// Sent by the tunnel monitor thread when the tunnel has been closed.
+#[derive(Debug, Default)]
+pub(crate) struct TunnelCloseReason {
+    /// Enter the error state for this reason instead of reconnecting.
+    pub block_reason: Option<ErrorStateCause>,
+    /// The tunnel could not be established with the relay, e.g. because the handshake timed
+    /// out. Local failures, such as failing to create the tunnel device, are not included.
+    pub connection_failed: bool,
+}
+
+impl TunnelCloseReason {
+    fn block(block_reason: ErrorStateCause) -> Self {
+        TunnelCloseReason {
+            block_reason: Some(block_reason),
+            connection_failed: false,
+        }
+    }
+}
 
 #[cfg(target_os = "android")]
 const MAX_ATTEMPTS_WITH_SAME_TUN: u32 = 5;
@@ -50,6 +70,7 @@ pub struct ConnectingState {
     tunnel_close_event: TunnelCloseEvent,
     tunnel_close_tx: oneshot::Sender<()>,
     retry_attempt: u32,
+    attempt: Option<ConnectionAttempt>,
 }
 
 impl ConnectingState {
@@ -67,6 +88,7 @@ impl ConnectingState {
             peer_endpoint,
             tunnel: tunnel_metadata.clone(),
             allow_lan: shared_values.allow_lan,
+            allowed_networks: shared_values.allowed_networks.clone(),
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(&shared_values.resource_dir, &params),
@@ -124,7 +146,7 @@ impl ConnectingState {
                 Ok(handle) => handle,
                 Err(error) => {
                     if tunnel_close_event_tx
-                        .send(Some(ErrorStateCause::StartTunnelError))
+                        .send(TunnelCloseReason::block(ErrorStateCause::StartTunnelError))
                         .is_err()
                     {
                         log::warn!(
@@ -139,7 +161,7 @@ impl ConnectingState {
                 }
             };
 
-            let block_reason = match TunnelMonitor::start(
+            let close_reason = match TunnelMonitor::start(
                 runtime,
                 &tunnel_parameters,
                 &log_dir,
@@ -152,7 +174,7 @@ impl ConnectingState {
             ) {
                 Ok(monitor) => {
                     let reason = Self::wait_for_tunnel_monitor(monitor, retry_attempt);
-                    log::debug!("Tunnel monitor exited with block reason: {:?}", reason);
+                    log::debug!("Tunnel monitor exited: {:?}", reason);
                     reason
                 }
                 Err(error) if should_retry(&error, retry_attempt) => {
@@ -162,7 +184,7 @@ impl ConnectingState {
                             "Retrying to connect after failing to start tunnel"
                         )
                     );
-                    None
+                    TunnelCloseReason::default()
                 }
                 Err(error) => {
                     log::error!("{}", error.display_chain_with_msg("Failed to start tunnel"));
@@ -186,17 +208,17 @@ impl ConnectingState {
                         ) => ErrorStateCause::InvalidDnsServers(addresses),
                         _ => ErrorStateCause::StartTunnelError,
                     };
-                    Some(block_reason)
+                    TunnelCloseReason::block(block_reason)
                 }
             };
 
-            if block_reason.is_none() {
+            if close_reason.block_reason.is_none() {
                 if let Some(remaining_time) = MIN_TUNNEL_ALIVE_TIME.checked_sub(start.elapsed()) {
                     thread::sleep(remaining_time);
                 }
             }
 
-            if tunnel_close_event_tx.send(block_reason).is_err() {
+            if tunnel_close_event_tx.send(close_reason).is_err() {
                 log::warn!("Tunnel state machine stopped before receiving tunnel closed event");
             }
 
@@ -210,21 +232,30 @@ impl ConnectingState {
             tunnel_close_event: tunnel_close_event_rx.fuse(),
             tunnel_close_tx,
             retry_attempt,
+            attempt: None,
         }
     }
 
     fn wait_for_tunnel_monitor(
         tunnel_monitor: TunnelMonitor,
         retry_attempt: u32,
-    ) -> Option<ErrorStateCause> {
+    ) -> TunnelCloseReason {
         match tunnel_monitor.wait() {
-            Ok(_) => None,
+            // The tunnel exited by itself before it was established, e.g. because OpenVPN gave
+            // up connecting to the relay.
+            Ok(_) => TunnelCloseReason {
+                block_reason: None,
+                connection_failed: true,
+            },
             Err(error) => match error {
                 tunnel::Error::WireguardTunnelMonitoringError(
                     tunnel::wireguard::Error::TimeoutError,
                 ) => {
                     log::debug!("WireGuard tunnel timed out");
-                    None
+                    TunnelCloseReason {
+                        block_reason: None,
+                        connection_failed: true,
+                    }
                 }
                 error @ tunnel::Error::WireguardTunnelMonitoringError(..)
                     if !should_retry(&error, retry_attempt) =>
@@ -233,14 +264,17 @@ impl ConnectingState {
                         "{}",
                         error.display_chain_with_msg("Tunnel has stopped unexpectedly")
                     );
-                    Some(ErrorStateCause::StartTunnelError)
+                    TunnelCloseReason::block(ErrorStateCause::StartTunnelError)
                 }
                 error => {
                     log::warn!(
                         "{}",
                         error.display_chain_with_msg("Tunnel has stopped unexpectedly")
                     );
-                    None
+                    TunnelCloseReason {
+                        block_reason: None,
+                        connection_failed: is_connection_failure(&error),
+                    }
                 }
             },
         }
@@ -253,6 +287,20 @@ impl ConnectingState {
             tunnel_parameters: self.tunnel_parameters,
             tunnel_close_event: self.tunnel_close_event,
             tunnel_close_tx: self.tunnel_close_tx,
+            attempt: self.attempt.map(|mut attempt| {
+                attempt.tunnel_up();
+                attempt
+            }),
+        }
+    }
+
+    fn finish_attempt(
+        &mut self,
+        outcome: ConnectionAttemptOutcome,
+        shared_values: &SharedTunnelStateValues,
+    ) {
+        if let Some(attempt) = self.attempt.take() {
+            attempt.finish(outcome, &shared_values.connection_metrics);
         }
     }
 
@@ -273,10 +321,11 @@ impl ConnectingState {
     }
 
     fn disconnect(
-        self,
+        mut self,
         shared_values: &mut SharedTunnelStateValues,
         after_disconnect: AfterDisconnect,
     ) -> EventConsequence {
+        self.finish_attempt(ConnectionAttemptOutcome::Failed, shared_values);
         Self::reset_routes(shared_values);
 
         EventConsequence::NewState(DisconnectingState::enter(
@@ -289,6 +338,16 @@ impl ConnectingState {
         ))
     }
 
+    /// Disconnects because the user asked to, which does not count as a failed attempt.
+    fn abort(
+        mut self,
+        shared_values: &mut SharedTunnelStateValues,
+        after_disconnect: AfterDisconnect,
+    ) -> EventConsequence {
+        self.finish_attempt(ConnectionAttemptOutcome::Aborted, shared_values);
+        self.disconnect(shared_values, after_disconnect)
+    }
+
     fn reset_firewall(self, shared_values: &mut SharedTunnelStateValues) -> EventConsequence {
         match Self::set_firewall_policy(
             shared_values,
@@ -327,6 +386,10 @@ impl ConnectingState {
                     return next_state;
                 }
             }
+            Some(TunnelCommand::AllowedNetworks(allowed_networks)) => {
+                shared_values.allowed_networks = allowed_networks;
+                self.reset_firewall(shared_values)
+            }
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                 if shared_values.allowed_endpoint != endpoint {
                     shared_values.allowed_endpoint = endpoint;
@@ -371,10 +434,10 @@ impl ConnectingState {
                 }
             }
             Some(TunnelCommand::Connect) => {
-                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                self.abort(shared_values, AfterDisconnect::Reconnect(0))
             }
             Some(TunnelCommand::Disconnect) | None => {
-                self.disconnect(shared_values, AfterDisconnect::Nothing)
+                self.abort(shared_values, AfterDisconnect::Nothing)
             }
             Some(TunnelCommand::Block(reason)) => {
                 self.disconnect(shared_values, AfterDisconnect::Block(reason))
@@ -449,17 +512,25 @@ impl ConnectingState {
     }
 
     fn handle_tunnel_close_event(
-        self,
-        block_reason: Option<ErrorStateCause>,
+        mut self,
+        close_reason: TunnelCloseReason,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
         use self::EventConsequence::*;
 
-        if let Some(block_reason) = block_reason {
+        self.finish_attempt(ConnectionAttemptOutcome::Failed, shared_values);
+
+        if let Some(block_reason) = close_reason.block_reason {
             Self::reset_routes(shared_values);
             return NewState(ErrorState::enter(shared_values, block_reason));
         }
 
+        if close_reason.connection_failed {
+            shared_values
+                .tunnel_parameters_generator
+                .report_connection_failure();
+        }
+
         log::info!(
             "Tunnel closed. Reconnecting, attempt {}.",
             self.retry_attempt + 1
@@ -472,6 +543,18 @@ impl ConnectingState {
     }
 }
 
+/// Returns whether the tunnel stopped because the relay could not be reached, as opposed to a
+/// failure on this device.
+fn is_connection_failure(error: &tunnel::Error) -> bool {
+    match error {
+        #[cfg(not(target_os = "android"))]
+        tunnel::Error::OpenVpnTunnelMonitoringError(tunnel::openvpn::Error::ChildProcessDied) => {
+            true
+        }
+        _ => false,
+    }
+}
+
 #[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
 fn should_retry(error: &tunnel::Error, retry_attempt: u32) -> bool {
     #[cfg(windows)]
@@ -533,11 +616,20 @@ impl TunnelState for ConnectingState {
         if shared_values.is_offline {
             return ErrorState::enter(shared_values, ErrorStateCause::IsOffline);
         }
-        match shared_values
+
+        let mut attempt = ConnectionAttempt::new(retry_attempt);
+        let parameter_generation_start = Instant::now();
+        let tunnel_parameters = shared_values
             .tunnel_parameters_generator
-            .generate(retry_attempt)
-        {
+            .generate(retry_attempt);
+        attempt.set_parameter_generation(parameter_generation_start.elapsed());
+
+        match tunnel_parameters {
             Err(err) => {
+                attempt.finish(
+                    ConnectionAttemptOutcome::Failed,
+                    &shared_values.connection_metrics,
+                );
                 ErrorState::enter(shared_values, ErrorStateCause::TunnelParameterError(err))
             }
             Ok(tunnel_parameters) => {
@@ -550,12 +642,23 @@ impl TunnelState for ConnectingState {
                         )
                     );
 
+                    attempt.finish(
+                        ConnectionAttemptOutcome::Failed,
+                        &shared_values.connection_metrics,
+                    );
                     return ErrorState::enter(shared_values, ErrorStateCause::SplitTunnelError);
                 }
 
-                if let Err(error) =
-                    Self::set_firewall_policy(shared_values, &tunnel_parameters, &None)
-                {
+                let firewall_start = Instant::now();
+                let firewall_result =
+                    Self::set_firewall_policy(shared_values, &tunnel_parameters, &None);
+                attempt.set_firewall_policy(firewall_start.elapsed());
+
+                if let Err(error) = firewall_result {
+                    attempt.finish(
+                        ConnectionAttemptOutcome::Failed,
+                        &shared_values.connection_metrics,
+                    );
                     ErrorState::enter(
                         shared_values,
                         ErrorStateCause::SetFirewallPolicyError(error),
@@ -575,7 +678,8 @@ impl TunnelState for ConnectingState {
                         }
                     }
 
-                    let connecting_state = Self::start_tunnel(
+                    attempt.start_tunnel();
+                    let mut connecting_state = Self::start_tunnel(
                         shared_values.runtime.clone(),
                         tunnel_parameters,
                         &shared_values.log_dir,
@@ -584,6 +688,7 @@ impl TunnelState for ConnectingState {
                         &mut shared_values.route_manager,
                         retry_attempt,
                     );
+                    connecting_state.attempt = Some(attempt);
                     let params = connecting_state.tunnel_parameters.clone();
                     (
                         TunnelStateWrapper::from(connecting_state),
@@ -615,8 +720,7 @@ impl TunnelState for ConnectingState {
                 if result.is_err() {
                     log::warn!("Tunnel monitor thread has stopped unexpectedly");
                 }
-                let block_reason = result.unwrap_or(None);
-                self.handle_tunnel_close_event(block_reason, shared_values)
+                self.handle_tunnel_close_event(result.unwrap_or_default(), shared_values)
             }
         }
     }
@@ -0,0 +1,141 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use talpid_types::tunnel::{ConnectionAttemptMetrics, ConnectionAttemptOutcome};
+
+/// Number of connection attempts for which metrics are kept.
+pub const MAX_RECORDED_ATTEMPTS: usize = 10;
+
+/// Shared record of the most recent connection attempts made by the tunnel state machine.
+#[derive(Clone, Default)]
+pub struct ConnectionMetrics {
+    attempts: Arc<Mutex<VecDeque<ConnectionAttemptMetrics>>>,
+}
+
+impl ConnectionMetrics {
+    /// Returns the recorded attempts, oldest first.
+    pub fn attempts(&self) -> Vec<ConnectionAttemptMetrics> {
+        self.attempts.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn push(&self, attempt: ConnectionAttemptMetrics) {
+        let mut attempts = self.attempts.lock().unwrap();
+        if attempts.len() >= MAX_RECORDED_ATTEMPTS {
+            attempts.pop_front();
+        }
+        attempts.push_back(attempt);
+    }
+}
+
+/// Measures the phases of a connection attempt that is in progress.
+pub struct ConnectionAttempt {
+    start: Instant,
+    tunnel_start: Option<Instant>,
+    metrics: ConnectionAttemptMetrics,
+}
+
+impl ConnectionAttempt {
+    pub fn new(retry_attempt: u32) -> Self {
+        ConnectionAttempt {
+            start: Instant::now(),
+            tunnel_start: None,
+            metrics: ConnectionAttemptMetrics {
+                retry_attempt,
+                parameter_generation: None,
+                firewall_policy: None,
+                tunnel_setup: None,
+                connected_setup: None,
+                total: Duration::ZERO,
+                outcome: ConnectionAttemptOutcome::Failed,
+            },
+        }
+    }
+
+    pub fn set_parameter_generation(&mut self, duration: Duration) {
+        self.metrics.parameter_generation = Some(duration);
+    }
+
+    pub fn set_firewall_policy(&mut self, duration: Duration) {
+        self.metrics.firewall_policy = Some(duration);
+    }
+
+    pub fn set_connected_setup(&mut self, duration: Duration) {
+        self.metrics.connected_setup = Some(duration);
+    }
+
+    /// Marks the point at which the tunnel is started.
+    pub fn start_tunnel(&mut self) {
+        self.tunnel_start = Some(Instant::now());
+    }
+
+    /// Marks the point at which the tunnel reported being up.
+    pub fn tunnel_up(&mut self) {
+        self.metrics.tunnel_setup = self.tunnel_start.map(|start| start.elapsed());
+    }
+
+    /// Completes the attempt and adds it to `metrics`.
+    pub fn finish(mut self, outcome: ConnectionAttemptOutcome, metrics: &ConnectionMetrics) {
+        self.metrics.total = self.start.elapsed();
+        self.metrics.outcome = outcome;
+        log::debug!("Connection attempt metrics: {:?}", self.metrics);
+        metrics.push(self.metrics);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer() {
+        let metrics = ConnectionMetrics::default();
+        assert!(metrics.attempts().is_empty());
+
+        for retry_attempt in 0..(MAX_RECORDED_ATTEMPTS as u32 + 3) {
+            ConnectionAttempt::new(retry_attempt)
+                .finish(ConnectionAttemptOutcome::Failed, &metrics);
+        }
+
+        let attempts = metrics.attempts();
+        assert_eq!(attempts.len(), MAX_RECORDED_ATTEMPTS);
+        assert_eq!(attempts.first().unwrap().retry_attempt, 3);
+        assert_eq!(
+            attempts.last().unwrap().retry_attempt,
+            MAX_RECORDED_ATTEMPTS as u32 + 2
+        );
+    }
+
+    #[test]
+    fn test_attempt_phases() {
+        let metrics = ConnectionMetrics::default();
+
+        let mut attempt = ConnectionAttempt::new(1);
+        attempt.set_parameter_generation(Duration::from_millis(5));
+        attempt.set_firewall_policy(Duration::from_millis(10));
+        attempt.tunnel_up();
+        attempt.finish(ConnectionAttemptOutcome::Aborted, &metrics);
+
+        let mut attempt = ConnectionAttempt::new(2);
+        attempt.start_tunnel();
+        attempt.tunnel_up();
+        attempt.set_connected_setup(Duration::from_millis(20));
+        attempt.finish(ConnectionAttemptOutcome::Connected, &metrics);
+
+        let attempts = metrics.attempts();
+        assert_eq!(
+            attempts[0].parameter_generation,
+            Some(Duration::from_millis(5))
+        );
+        assert_eq!(attempts[0].firewall_policy, Some(Duration::from_millis(10)));
+        // The tunnel was never started, so there is no setup time.
+        assert_eq!(attempts[0].tunnel_setup, None);
+        assert_eq!(attempts[0].outcome, ConnectionAttemptOutcome::Aborted);
+
+        assert!(attempts[1].tunnel_setup.is_some());
+        assert_eq!(attempts[1].connected_setup, Some(Duration::from_millis(20)));
+        assert_eq!(attempts[1].outcome, ConnectionAttemptOutcome::Connected);
+        assert!(attempts[1].total >= attempts[1].tunnel_setup.unwrap());
+    }
+}
@@ -23,6 +23,7 @@ impl DisconnectedState {
         let result = if shared_values.block_when_disconnected {
             let policy = FirewallPolicy::Blocked {
                 allow_lan: shared_values.allow_lan,
+                allowed_networks: shared_values.allowed_networks.clone(),
                 allowed_endpoint: shared_values.allowed_endpoint.clone(),
                 #[cfg(target_os = "macos")]
                 dns_redirect_port: shared_values.filtering_resolver.listening_port(),
@@ -149,6 +150,13 @@ impl TunnelState for DisconnectedState {
                 }
                 SameState(self.into())
             }
+            Some(TunnelCommand::AllowedNetworks(allowed_networks)) => {
+                if shared_values.allowed_networks != allowed_networks {
+                    shared_values.allowed_networks = allowed_networks;
+                    Self::set_firewall_policy(shared_values, true);
+                }
+                SameState(self.into())
+            }
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                 if shared_values.allowed_endpoint != endpoint {
                     shared_values.allowed_endpoint = endpoint;
@@ -1,7 +1,8 @@
 use super::{
-    connecting_state::TunnelCloseEvent, ConnectingState, DisconnectedState, ErrorState,
-    EventConsequence, EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver,
-    TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    connecting_state::{TunnelCloseEvent, TunnelCloseReason},
+    ConnectingState, DisconnectedState, ErrorState, EventConsequence, EventResult,
+    SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver, TunnelState,
+    TunnelStateTransition, TunnelStateWrapper,
 };
 use futures::{channel::oneshot, future::FusedFuture, StreamExt};
 use talpid_types::tunnel::{ActionAfterDisconnect, ErrorStateCause};
@@ -27,6 +28,10 @@ impl DisconnectingState {
                     let _ = shared_values.set_allow_lan(allow_lan);
                     AfterDisconnect::Nothing
                 }
+                Some(TunnelCommand::AllowedNetworks(allowed_networks)) => {
+                    shared_values.allowed_networks = allowed_networks;
+                    AfterDisconnect::Nothing
+                }
                 Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                     shared_values.allowed_endpoint = endpoint;
                     if let Err(_) = tx.send(()) {
@@ -65,6 +70,10 @@ impl DisconnectingState {
                     let _ = shared_values.set_allow_lan(allow_lan);
                     AfterDisconnect::Block(reason)
                 }
+                Some(TunnelCommand::AllowedNetworks(allowed_networks)) => {
+                    shared_values.allowed_networks = allowed_networks;
+                    AfterDisconnect::Block(reason)
+                }
                 Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                     shared_values.allowed_endpoint = endpoint;
                     if let Err(_) = tx.send(()) {
@@ -108,6 +117,10 @@ impl DisconnectingState {
                     let _ = shared_values.set_allow_lan(allow_lan);
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                Some(TunnelCommand::AllowedNetworks(allowed_networks)) => {
+                    shared_values.allowed_networks = allowed_networks;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                     shared_values.allowed_endpoint = endpoint;
                     if let Err(_) = tx.send(()) {
@@ -198,12 +211,12 @@ impl TunnelState for DisconnectingState {
 
         let result = if self.tunnel_close_event.is_terminated() {
             if commands.is_done() {
-                EventResult::Close(Ok(None))
+                EventResult::Close(Ok(TunnelCloseReason::default()))
             } else {
                 if let Ok(command) = commands.get_mut().try_next() {
                     EventResult::Command(command)
                 } else {
-                    EventResult::Close(Ok(None))
+                    EventResult::Close(Ok(TunnelCloseReason::default()))
                 }
             }
         } else {
@@ -218,7 +231,7 @@ impl TunnelState for DisconnectingState {
         match result {
             EventResult::Command(command) => self.handle_commands(command, shared_values),
             EventResult::Close(result) => {
-                let block_reason = result.unwrap_or(None);
+                let block_reason = result.unwrap_or_default().block_reason;
                 NewState(self.after_disconnect(block_reason, shared_values))
             }
             _ => unreachable!("unexpected event result"),
@@ -22,6 +22,7 @@ impl ErrorState {
     ) -> Result<(), FirewallPolicyError> {
         let policy = FirewallPolicy::Blocked {
             allow_lan: shared_values.allow_lan,
+            allowed_networks: shared_values.allowed_networks.clone(),
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
             #[cfg(target_os = "macos")]
             dns_redirect_port: shared_values.filtering_resolver.listening_port(),
@@ -148,6 +149,11 @@ impl TunnelState for ErrorState {
                     SameState(self.into())
                 }
             }
+            Some(TunnelCommand::AllowedNetworks(allowed_networks)) => {
+                shared_values.allowed_networks = allowed_networks;
+                let _ = Self::set_firewall_policy(shared_values);
+                SameState(self.into())
+            }
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                 if shared_values.allowed_endpoint != endpoint {
                     shared_values.allowed_endpoint = endpoint;
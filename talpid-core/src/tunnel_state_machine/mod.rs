@@ -1,12 +1,14 @@
 mod connected_state;
 mod connecting_state;
+mod connection_metrics;
 mod disconnected_state;
 mod disconnecting_state;
 mod error_state;
 
+pub use self::connection_metrics::{ConnectionMetrics, MAX_RECORDED_ATTEMPTS};
 use self::{
     connected_state::{ConnectedState, ConnectedStateBootstrap},
-    connecting_state::ConnectingState,
+    connecting_state::{ConnectingState, TunnelCloseReason},
     disconnected_state::DisconnectedState,
     disconnecting_state::{AfterDisconnect, DisconnectingState},
     error_state::ErrorState,
@@ -14,7 +16,7 @@ use self::{
 #[cfg(windows)]
 use crate::split_tunnel;
 use crate::{
-    dns::DnsMonitor,
+    dns::{AppliedDns, DnsMonitor},
     firewall::{Firewall, FirewallArguments, InitialFirewallState},
     mpsc::Sender,
     offline,
@@ -28,6 +30,7 @@ use futures::{
     channel::{mpsc, oneshot},
     stream, StreamExt,
 };
+use ipnetwork::IpNetwork;
 #[cfg(target_os = "android")]
 use std::os::unix::io::RawFd;
 use std::{
@@ -89,6 +92,8 @@ pub enum Error {
 pub struct InitialTunnelState {
     /// Whether to allow LAN traffic when not in the (non-blocking) disconnected state.
     pub allow_lan: bool,
+    /// Networks outside of the private ranges that are treated as LAN when `allow_lan` is set.
+    pub allowed_networks: Vec<IpNetwork>,
     /// Block traffic unless connected to the VPN.
     pub block_when_disconnected: bool,
     /// DNS servers to use. If `None`, the tunnel gateway is used.
@@ -111,6 +116,8 @@ pub async fn spawn(
     resource_dir: PathBuf,
     state_change_listener: impl Sender<TunnelStateTransition> + Send + 'static,
     offline_state_listener: mpsc::UnboundedSender<bool>,
+    connection_metrics: ConnectionMetrics,
+    applied_dns: AppliedDns,
     #[cfg(target_os = "windows")] volume_update_rx: mpsc::UnboundedReceiver<()>,
     #[cfg(target_os = "macos")] exclusion_gid: u32,
     #[cfg(target_os = "android")] android_context: AndroidContext,
@@ -134,6 +141,8 @@ pub async fn spawn(
         initial_settings,
         weak_command_tx,
         offline_state_listener,
+        connection_metrics,
+        applied_dns,
         tunnel_parameters_generator,
         tun_provider,
         log_dir,
@@ -162,6 +171,8 @@ pub async fn spawn(
 pub enum TunnelCommand {
     /// Enable or disable LAN access in the firewall.
     AllowLan(bool),
+    /// Set the networks that are treated as LAN in addition to the private ranges.
+    AllowedNetworks(Vec<IpNetwork>),
     /// Endpoint that should never be blocked.
     /// If an error occurs, the sender is dropped.
     AllowEndpoint(AllowedEndpoint, oneshot::Sender<()>),
@@ -193,7 +204,7 @@ type TunnelCommandReceiver = stream::Fuse<mpsc::UnboundedReceiver<TunnelCommand>
 enum EventResult {
     Command(Option<TunnelCommand>),
     Event(Option<(TunnelEvent, oneshot::Sender<()>)>),
-    Close(Result<Option<ErrorStateCause>, oneshot::Canceled>),
+    Close(Result<TunnelCloseReason, oneshot::Canceled>),
 }
 
 /// Asynchronous handling of the tunnel state machine.
@@ -213,6 +224,8 @@ impl TunnelStateMachine {
         settings: InitialTunnelState,
         command_tx: std::sync::Weak<mpsc::UnboundedSender<TunnelCommand>>,
         offline_state_tx: mpsc::UnboundedSender<bool>,
+        connection_metrics: ConnectionMetrics,
+        applied_dns: AppliedDns,
         tunnel_parameters_generator: impl TunnelParametersGenerator,
         tun_provider: TunProvider,
         log_dir: Option<PathBuf>,
@@ -252,6 +265,7 @@ impl TunnelStateMachine {
             route_manager
                 .handle()
                 .map_err(Error::InitRouteManagerError)?,
+            applied_dns,
         )
         .map_err(Error::InitDnsMonitorError)?;
 
@@ -295,11 +309,13 @@ impl TunnelStateMachine {
             route_manager,
             _offline_monitor: offline_monitor,
             allow_lan: settings.allow_lan,
+            allowed_networks: settings.allowed_networks,
             block_when_disconnected: settings.block_when_disconnected,
             is_offline,
             dns_servers: settings.dns_servers,
             allowed_endpoint: settings.allowed_endpoint,
             tunnel_parameters_generator: Box::new(tunnel_parameters_generator),
+            connection_metrics,
             tun_provider: Arc::new(Mutex::new(tun_provider)),
             log_dir,
             resource_dir,
@@ -364,6 +380,11 @@ pub trait TunnelParametersGenerator: Send + 'static {
         &mut self,
         retry_attempt: u32,
     ) -> Result<TunnelParameters, ParameterGenerationError>;
+
+    /// Called when a tunnel could not be established with the relay in the parameters that were
+    /// generated last, e.g. because the handshake timed out. It is not called when the attempt
+    /// failed for reasons that do not depend on the relay, such as being offline.
+    fn report_connection_failure(&mut self) {}
 }
 
 /// Values that are common to all tunnel states.
@@ -380,6 +401,8 @@ struct SharedTunnelStateValues {
     _offline_monitor: offline::MonitorHandle,
     /// Should LAN access be allowed outside the tunnel.
     allow_lan: bool,
+    /// Networks that are treated as LAN in addition to the private ranges.
+    allowed_networks: Vec<IpNetwork>,
     /// Should network access be allowed when in the disconnected state.
     block_when_disconnected: bool,
     /// True when the computer is known to be offline.
@@ -390,6 +413,8 @@ struct SharedTunnelStateValues {
     allowed_endpoint: AllowedEndpoint,
     /// The generator of new `TunnelParameter`s
     tunnel_parameters_generator: Box<dyn TunnelParametersGenerator>,
+    /// Metrics for the most recent connection attempts.
+    connection_metrics: ConnectionMetrics,
     /// The provider of tunnel devices.
     tun_provider: Arc<Mutex<TunProvider>>,
     /// Directory to store tunnel log file.
@@ -0,0 +1,43 @@
+//! DNS configuration applied by the tunnel state machine.
+
+use std::{fmt, net::IpAddr};
+
+/// How DNS servers are applied to the system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsMechanism {
+    SystemdResolved,
+    NetworkManager,
+    Resolvconf,
+    /// `/etc/resolv.conf` is overwritten.
+    StaticResolvConf,
+    /// The dynamic store of the System Configuration framework, as used by `scutil`.
+    SystemConfiguration,
+    /// The interface settings and DNS cache policy set by WinDns.
+    WinDns,
+    /// The DNS servers of the VPN service.
+    VpnService,
+}
+
+impl fmt::Display for DnsMechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DnsMechanism::SystemdResolved => "systemd-resolved",
+            DnsMechanism::NetworkManager => "NetworkManager",
+            DnsMechanism::Resolvconf => "resolvconf",
+            DnsMechanism::StaticResolvConf => "/etc/resolv.conf",
+            DnsMechanism::SystemConfiguration => "scutil",
+            DnsMechanism::WinDns => "WinDns",
+            DnsMechanism::VpnService => "VPN service",
+        };
+        f.write_str(name)
+    }
+}
+
+/// DNS servers that were applied to the system, and how they were applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedDnsConfig {
+    /// The interface that the servers were set on.
+    pub interface: String,
+    pub servers: Vec<IpAddr>,
+    pub mechanism: DnsMechanism,
+}
@@ -9,6 +9,7 @@ use std::{
     str::FromStr,
 };
 
+pub mod dns;
 pub mod openvpn;
 pub mod proxy;
 pub mod wireguard;
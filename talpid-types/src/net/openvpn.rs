@@ -3,7 +3,7 @@ use crate::net::{
     Endpoint, GenericTunnelOptions, TransportProtocol,
 };
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::{fmt, net::SocketAddr};
 
 /// Information needed by `OpenVpnMonitor` to establish a tunnel connection.
 /// See [`crate::net::TunnelParameters`].
@@ -33,15 +33,57 @@ impl ConnectionConfig {
     }
 }
 
+/// Smallest `mssfix` value that is accepted.
+pub const MIN_MSSFIX: u16 = 1000;
+/// Largest `mssfix` value that is accepted.
+pub const MAX_MSSFIX: u16 = 1450;
+
 /// `TunnelOptions` contains options for an OpenVPN tunnel that should be applied
 /// irrespective of the relay parameters - i.e. have nothing to do with the particular
 /// OpenVPN server, but do affect the connection.
 /// Stored in [`TunnelParameters`].
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct TunnelOptions {
     /// Optional argument for openvpn to try and limit TCP packet size,
     /// as discussed [here](https://openvpn.net/archive/openvpn-users/2003-11/msg00154.html)
     pub mssfix: Option<u16>,
+    /// Data channel cipher to require. The server decides when this is unset.
+    pub data_cipher: Option<DataCipher>,
+}
+
+impl TunnelOptions {
+    /// Returns whether `mssfix` is within the range of values that may be passed to OpenVPN.
+    pub fn is_valid_mssfix(mssfix: u16) -> bool {
+        (MIN_MSSFIX..=MAX_MSSFIX).contains(&mssfix)
+    }
+}
+
+/// Data channel ciphers that may be passed to OpenVPN. Arbitrary OpenVPN options are never
+/// accepted, so this is an allowlist rather than a free-form string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataCipher {
+    Aes256Gcm,
+    Aes128Gcm,
+    Chacha20Poly1305,
+}
+
+impl DataCipher {
+    /// Returns the name of the cipher as understood by OpenVPN.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataCipher::Aes256Gcm => "AES-256-GCM",
+            DataCipher::Aes128Gcm => "AES-128-GCM",
+            DataCipher::Chacha20Poly1305 => "CHACHA20-POLY1305",
+        }
+    }
+}
+
+impl fmt::Display for DataCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 /// Proxy server options to be used by `OpenVpnMonitor` when starting a tunnel.
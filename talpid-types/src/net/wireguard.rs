@@ -71,6 +71,16 @@ pub struct TunnelConfig {
     pub addresses: Vec<IpAddr>,
 }
 
+/// Smallest tunnel MTU that is accepted. This is the minimum MTU required by IPv6.
+pub const MIN_MTU: u16 = 1280;
+/// Largest tunnel MTU that is accepted.
+pub const MAX_MTU: u16 = 1420;
+
+/// Shortest persistent keepalive interval, in seconds, that is accepted.
+pub const MIN_PERSISTENT_KEEPALIVE: u16 = 10;
+/// Longest persistent keepalive interval, in seconds, that is accepted.
+pub const MAX_PERSISTENT_KEEPALIVE: u16 = 120;
+
 /// Options in [`TunnelParameters`] that apply to any WireGuard connection.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[cfg_attr(target_os = "android", derive(IntoJava))]
@@ -85,6 +95,18 @@ pub struct TunnelOptions {
         jnix(map = "|maybe_mtu| maybe_mtu.map(|mtu| mtu as i32)")
     )]
     pub mtu: Option<u16>,
+    /// Interval in seconds between keepalive packets sent to each peer, or `None` to only send
+    /// packets when there is traffic
+    #[serde(default)]
+    #[cfg_attr(
+        target_os = "android",
+        jnix(map = "|maybe_interval| maybe_interval.map(|interval| interval as i32)")
+    )]
+    pub persistent_keepalive: Option<u16>,
+    /// Limits the bandwidth of the tunnel, or `None` to not limit it
+    #[serde(default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub rate_limit: Option<RateLimit>,
     /// Temporary switch for wireguard-nt
     #[cfg(windows)]
     #[serde(default = "default_wgnt_setting")]
@@ -97,10 +119,54 @@ fn default_wgnt_setting() -> bool {
     true
 }
 
+/// Smallest bandwidth limit, in kilobits per second, that is accepted.
+pub const MIN_RATE_LIMIT_KBPS: u32 = 64;
+
+/// Bandwidth limit of a tunnel, in kilobits per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Limit of the traffic sent through the tunnel
+    pub up_kbps: u32,
+    /// Limit of the traffic received through the tunnel
+    pub down_kbps: u32,
+}
+
+impl RateLimit {
+    /// Returns whether both limits are large enough to be enforced.
+    pub fn is_valid(&self) -> bool {
+        self.up_kbps >= MIN_RATE_LIMIT_KBPS && self.down_kbps >= MIN_RATE_LIMIT_KBPS
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "up {} kbit/s, down {} kbit/s",
+            self.up_kbps, self.down_kbps
+        )
+    }
+}
+
+impl TunnelOptions {
+    /// Returns whether `mtu` is within the range of values that may be used for the tunnel.
+    pub fn is_valid_mtu(mtu: u16) -> bool {
+        (MIN_MTU..=MAX_MTU).contains(&mtu)
+    }
+
+    /// Returns whether `interval` is within the range of persistent keepalive intervals that may
+    /// be used for the tunnel.
+    pub fn is_valid_persistent_keepalive(interval: u16) -> bool {
+        (MIN_PERSISTENT_KEEPALIVE..=MAX_PERSISTENT_KEEPALIVE).contains(&interval)
+    }
+}
+
 impl Default for TunnelOptions {
     fn default() -> Self {
         Self {
             mtu: None,
+            persistent_keepalive: None,
+            rate_limit: None,
             #[cfg(windows)]
             use_wireguard_nt: default_wgnt_setting(),
         }
@@ -2,9 +2,9 @@ use crate::net::TunnelEndpoint;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::fmt;
 #[cfg(target_os = "android")]
 use std::net::IpAddr;
+use std::{fmt, time::Duration};
 
 /// Event emitted from the states in `talpid_core::tunnel_state_machine` when the tunnel state
 /// machine enters a new state.
@@ -213,3 +213,80 @@ impl fmt::Display for ErrorStateCause {
         write!(f, "{}", description)
     }
 }
+
+/// File in the cache directory that lists the most recent connection attempts, so that they can
+/// be included in problem reports.
+pub const CONNECTION_METRICS_FILENAME: &str = "connection-attempts.txt";
+
+/// How long each phase of a connection attempt took. A phase that was never reached is `None`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionAttemptMetrics {
+    /// Number of consecutive attempts that preceded this one.
+    pub retry_attempt: u32,
+    /// Time spent generating tunnel parameters, which includes selecting a relay.
+    pub parameter_generation: Option<Duration>,
+    /// Time spent applying the firewall policy for the connecting state.
+    pub firewall_policy: Option<Duration>,
+    /// Time from starting the tunnel until it reported being up. This includes setting up any
+    /// proxy or obfuscation, the handshake, and connectivity checks done by the tunnel.
+    pub tunnel_setup: Option<Duration>,
+    /// Time spent applying the firewall policy and DNS settings for the connected state.
+    pub connected_setup: Option<Duration>,
+    /// Total duration of the attempt.
+    pub total: Duration,
+    pub outcome: ConnectionAttemptOutcome,
+}
+
+/// How a connection attempt ended.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionAttemptOutcome {
+    Connected,
+    Failed,
+    /// The user disconnected or reconnected before the attempt finished.
+    Aborted,
+}
+
+impl fmt::Display for ConnectionAttemptMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn phase(duration: &Option<Duration>) -> String {
+            match duration {
+                Some(duration) => format!("{} ms", duration.as_millis()),
+                None => "-".to_owned(),
+            }
+        }
+        write!(
+            f,
+            "Attempt {} ({}): total {} ms, relay selection {}, firewall {}, tunnel setup {}, \
+            connected setup {}",
+            self.retry_attempt + 1,
+            self.outcome,
+            self.total.as_millis(),
+            phase(&self.parameter_generation),
+            phase(&self.firewall_policy),
+            phase(&self.tunnel_setup),
+            phase(&self.connected_setup),
+        )
+    }
+}
+
+/// Formats `attempts` with one line per attempt, in the order given.
+pub fn format_connection_attempts(attempts: &[ConnectionAttemptMetrics]) -> String {
+    let mut formatted = String::new();
+    for attempt in attempts {
+        formatted.push_str(&attempt.to_string());
+        formatted.push('\n');
+    }
+    formatted
+}
+
+impl fmt::Display for ConnectionAttemptOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match self {
+            ConnectionAttemptOutcome::Connected => "connected",
+            ConnectionAttemptOutcome::Failed => "failed",
+            ConnectionAttemptOutcome::Aborted => "aborted",
+        };
+        f.write_str(outcome)
+    }
+}